@@ -0,0 +1,29 @@
+/// Dipsw values of the kernel.
+///
+/// Each bit is identified by its index (e.g. [`DipswFlags::DISABLED_KASLR`]).
+#[repr(C)]
+#[derive(Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct DipswFlags(#[cfg_attr(feature = "serde", serde(with = "serde_bytes"))] [u8; 32]);
+
+impl DipswFlags {
+    pub const DISABLED_KASLR: usize = 12;
+
+    /// # Panics
+    /// If `n` is not less than 256.
+    pub fn get(&self, n: usize) -> bool {
+        (self.0[n / 8] & (1 << (n % 8))) != 0
+    }
+
+    /// # Panics
+    /// If `n` is not less than 256.
+    pub fn set(&mut self, n: usize, v: bool) {
+        let b = &mut self.0[n / 8];
+
+        if v {
+            *b |= 1 << (n % 8);
+        } else {
+            *b &= !(1 << (n % 8));
+        }
+    }
+}
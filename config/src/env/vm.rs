@@ -8,15 +8,16 @@ pub struct Vm {
     ///
     /// This used for diagnostic only.
     pub hypervisor: [u8; 128],
-    /// Address of [VmmMemory].
-    pub vmm: usize,
-    /// Address of [ConsoleMemory].
-    pub console: usize,
+    /// Virtual devices. Set [DeviceInfo::ty] to [DeviceType::None] to mark the end of the list.
+    pub devices: [DeviceInfo; 32],
     /// Page size on the host.
     pub host_page_size: NonZero<usize>,
     /// Memory map. Set [PhysMap::ty] to [MapType::None](super::MapType::None) to mark the end of
     /// the list.
     pub memory_map: [PhysMap; 64],
+    /// Virtual address where the whole RAM is mapped (AKA direct map). The physical address `p` can
+    /// be accessed at `dmap + p`.
+    pub dmap: usize,
 }
 
 impl Vm {
@@ -29,13 +30,68 @@ impl Vm {
 
         &self.hypervisor[..len]
     }
+
+    /// Returns address of the first device with type `ty`.
+    pub fn device(&self, ty: DeviceType) -> Option<usize> {
+        self.devices
+            .iter()
+            .take_while(|d| d.ty != DeviceType::None)
+            .find(|d| d.ty == ty)
+            .map(|d| d.addr)
+    }
+}
+
+/// Virtual device on the VM.
+#[repr(C)]
+pub struct DeviceInfo {
+    pub ty: DeviceType,
+    pub addr: usize,
+}
+
+/// Type of [DeviceInfo].
+#[repr(u32)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DeviceType {
+    None = 0,
+    /// Device with [VmmMemory] layout.
+    Vmm = 1,
+    /// Device with [ConsoleMemory] layout.
+    Console = 2,
+    /// Device with [DebuggerMemory] layout.
+    Debugger = 3,
+    /// Device with [TimerMemory] layout.
+    Timer = 4,
+    /// Device with [StorageMemory] layout.
+    Storage = 5,
 }
 
 /// Layout of a memory for Memory-mapped I/O to communicate with VMM.
+///
+/// The kernel can start a secondary CPU by:
+///
+/// 1. Write [`Self::cpu_page_table`], [`Self::cpu_entry`], [`Self::cpu_stack`] and
+///    [`Self::cpu_arg`].
+/// 2. Write the identifier of the CPU to start to [`Self::cpu_start`].
+///
+/// The sequence of operations is per-cpu. The new CPU will start in the same mode as the main CPU
+/// with `cpu_arg` as the first argument of `cpu_entry`. The stack pointer will be `cpu_stack`
+/// as-is so it need to point to the top of the stack.
+///
+/// The kernel can return a range of physical memory to the host by writing [`Self::ram_addr`] then
+/// the length of the range to [`Self::ram_release`]. Both values must be multiply by
+/// [`Vm::host_page_size`] and VM page size. The range will be filled with zeroes when the kernel
+/// touch it again.
 #[cfg(feature = "virt")]
 #[repr(C)]
 pub struct VmmMemory {
     pub shutdown: KernelExit,
+    pub cpu_page_table: usize,
+    pub cpu_entry: usize,
+    pub cpu_stack: usize,
+    pub cpu_arg: usize,
+    pub cpu_start: usize,
+    pub ram_addr: usize,
+    pub ram_release: usize,
 }
 
 /// Exit status of the kernel.
@@ -59,12 +115,165 @@ pub enum KernelExit {
 /// The consequence of this is [`Self::msg_addr`] may point to an incomplete UTF-8 byte sequence.
 /// That means you should buffer the message until [`Self::commit`] has been written before validating
 /// if it is valid UTF-8.
+///
+/// The kernel can read the input from the host by:
+///
+/// 1. Write the size of the buffer to [`Self::input_len`].
+/// 2. Write the address of the buffer to [`Self::input_addr`].
+/// 3. Read [`Self::input_read`] to get the number of bytes that was written to the buffer.
+///
+/// The buffer must not cross a page boundary. Zero will be returned on step 3 if no input is
+/// available.
 #[cfg(feature = "virt")]
 #[repr(C)]
 pub struct ConsoleMemory {
     pub msg_len: NonZero<usize>,
     pub msg_addr: usize,
     pub commit: ConsoleType,
+    pub input_len: NonZero<usize>,
+    pub input_addr: usize,
+    pub input_read: usize,
+}
+
+/// Layout of a memory for Memory-mapped I/O to report a trap to the debugger.
+///
+/// The kernel can report a trap by:
+///
+/// 1. Write [`Self::trap`] then [`Self::frame_len`].
+/// 2. Write the address of the trap frame to [`Self::frame_addr`].
+/// 3. Read [`Self::handled`]. Zero means no debugger is attached and the kernel must handle the
+///    trap by itself.
+///
+/// The sequence of operations is per-cpu. The write on step 2 will not return until the debugger
+/// resume the CPU, which may modify the trap frame. The trap frame must be a `TrapFrame` of the kernel for the current architecture
+/// and it must not cross a page boundary.
+#[cfg(feature = "virt")]
+#[repr(C)]
+pub struct DebuggerMemory {
+    pub trap: usize,
+    pub frame_len: NonZero<usize>,
+    pub frame_addr: usize,
+    pub handled: usize,
+}
+
+/// Layout of a memory for Memory-mapped I/O to access the clock and timer.
+///
+/// Reading [`Self::monotonic`] returns the number of nanoseconds since the VM was started, which
+/// never go backward. Reading [`Self::realtime`] returns the number of nanoseconds since Unix epoch
+/// according to the host.
+///
+/// The kernel can arm a deadline by:
+///
+/// 1. Write the identifier of the CPU to receive the deadline to [`Self::deadline_cpu`].
+/// 2. Write the interval for a periodic deadline to [`Self::deadline_period`] or zero for a
+///    one-shot deadline.
+/// 3. Write the interrupt vector to raise on the CPU when the deadline expired to
+///    [`Self::deadline_vector`] or zero to not raise any interrupt.
+/// 4. Write the value of [`Self::monotonic`] when the deadline should expire to
+///    [`Self::deadline`]. Zero will cancel the deadline of the CPU instead.
+///
+/// The sequence of operations is per-cpu. Each CPU can have only one deadline so arming a new one
+/// will replace the previous one. Reading [`Self::expired`] returns the number of times the deadline
+/// of the current CPU has been expired since the last read. A periodic deadline will not raise the
+/// next interrupt until [`Self::expired`] has been read.
+#[cfg(feature = "virt")]
+#[repr(C)]
+pub struct TimerMemory {
+    pub monotonic: usize,
+    pub realtime: usize,
+    pub deadline_cpu: usize,
+    pub deadline_period: usize,
+    pub deadline_vector: usize,
+    pub deadline: usize,
+    pub expired: usize,
+}
+
+/// Layout of a memory for Memory-mapped I/O to access the firmware partitions.
+///
+/// Each file or directory is referred by a handle returned from [`StorageCmd::Open`]. The kernel
+/// can execute a command by:
+///
+/// 1. Write the operands required by the command to [`Self::handle`], [`Self::offset`],
+///    [`Self::mode`], [`Self::buf_len`] and [`Self::buf_addr`].
+/// 2. Write the command to [`Self::command`].
+/// 3. Read [`Self::status`] then [`Self::result`] if the status is [`StorageStatus::Ok`].
+///
+/// The sequence of operations is per-cpu but the handles are shared with all CPUs. The buffer must
+/// not cross a page boundary.
+#[cfg(feature = "virt")]
+#[repr(C)]
+pub struct StorageMemory {
+    pub handle: usize,
+    pub offset: usize,
+    pub mode: StorageMode,
+    pub buf_len: usize,
+    pub buf_addr: usize,
+    pub command: StorageCmd,
+    pub status: StorageStatus,
+    pub result: usize,
+}
+
+/// Information about a file or directory from [`StorageCmd::Stat`].
+#[cfg(feature = "virt")]
+#[repr(C)]
+pub struct StorageStat {
+    /// Size of the file in bytes or number of entries in the directory.
+    pub len: u64,
+    pub dir: bool,
+}
+
+/// Command of [`StorageMemory`].
+#[cfg(feature = "virt")]
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, num_enum::IntoPrimitive, num_enum::TryFromPrimitive)]
+pub enum StorageCmd {
+    /// Open a UTF-8 path in the buffer with [`StorageMemory::mode`]. The path must be in the form
+    /// of `PART/PATH` (e.g. `md0/system/common/lib/libkernel.sprx`) or `PART` to open the root of
+    /// the partition. The result is a handle.
+    Open,
+    /// Write a [`StorageStat`] of the handle to the buffer.
+    Stat,
+    /// Read the file at the offset into the buffer. The result is the number of bytes read, which
+    /// is zero at the end of file.
+    Read,
+    /// Write the buffer to the file at the offset. The handle must be opened with
+    /// [`StorageMode::ReadWrite`]. The result is the number of bytes written.
+    Write,
+    /// Write the name of the entry at the offset of the directory to the buffer. The result is the
+    /// length of the name, which is zero at the end of directory.
+    ReadDir,
+    /// Close the handle.
+    Close,
+}
+
+/// Access mode for [`StorageCmd::Open`].
+#[cfg(feature = "virt")]
+#[repr(u8)]
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, num_enum::IntoPrimitive, num_enum::TryFromPrimitive,
+)]
+pub enum StorageMode {
+    Read,
+    /// Only files on a writable partition can be opened with this mode.
+    ReadWrite,
+}
+
+/// Status of the last [`StorageCmd`].
+#[cfg(feature = "virt")]
+#[repr(u8)]
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, num_enum::IntoPrimitive, num_enum::TryFromPrimitive,
+)]
+pub enum StorageStatus {
+    Ok,
+    NotFound,
+    InvalidHandle,
+    InvalidArgument,
+    IsDirectory,
+    NotDirectory,
+    BufferTooSmall,
+    ReadOnly,
+    IoError,
 }
 
 /// Type of console message.
@@ -75,4 +284,6 @@ pub enum ConsoleType {
     Info,
     Warn,
     Error,
+    /// Statistics report from the kernel. The VMM keep the latest one so it can be queried later.
+    Stats,
 }
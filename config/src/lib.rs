@@ -1,5 +1,6 @@
 #![no_std]
 
+pub use self::dipsw::*;
 pub use self::env::*;
 pub use self::idps::*;
 pub use self::qa::*;
@@ -7,6 +8,7 @@ pub use self::qa::*;
 use core::iter::{FusedIterator, Peekable};
 use core::num::NonZero;
 
+mod dipsw;
 mod env;
 mod idps;
 mod qa;
@@ -22,6 +24,13 @@ pub struct KernelMap {
     ///
     /// This include everything that need to be lived forever (e.g. stack for main CPU).
     pub kern_vsize: NonZero<usize>,
+    /// Virtual address of the stack for the CPU #1.
+    ///
+    /// The stack for the CPU `n` is at `ap_stacks + (n - 1) * (ap_stack_len + PAGE_SIZE)` since
+    /// each stack has an unmapped guard page below it.
+    pub ap_stacks: usize,
+    /// Size of the stack for each secondary CPU.
+    pub ap_stack_len: usize,
 }
 
 /// Contains information about the boot environment.
@@ -40,6 +49,7 @@ pub struct Config {
     pub idps: ConsoleId,
     pub qa: bool,
     pub qa_flags: QaFlags,
+    pub dipsw: DipswFlags,
     #[cfg_attr(feature = "serde", serde(with = "serde_bytes"))]
     env_vars: [u8; 132096], // See init_dynamic_kenv() on the Orbis for this number.
 }
@@ -106,6 +116,17 @@ impl Config {
     pub fn clear_env(&mut self) {
         self.env_vars.fill(Self::ENV_END);
     }
+
+    /// Returns `true` if the kernel will honor [`DipswFlags::DISABLED_KASLR`].
+    ///
+    /// The kernel only check the dipsw on a devkit that allowed to disable ASLR (see
+    /// `sceSblRcMgrIsAllowDisablingAslr` and `sceKernelCheckDipsw` on the Orbis).
+    pub fn is_kaslr_disabled(&self) -> bool {
+        self.qa
+            && self.qa_flags.internal_dev()
+            && self.idps.product == ProductId::DEVKIT
+            && self.dipsw.get(DipswFlags::DISABLED_KASLR)
+    }
 }
 
 impl Default for Config {
@@ -115,6 +136,7 @@ impl Default for Config {
             idps: ConsoleId::default(),
             qa: false,
             qa_flags: QaFlags::default(),
+            dipsw: DipswFlags::default(),
             env_vars: [Self::ENV_END; 132096], // Orbis fill this with NULs.
         };
 
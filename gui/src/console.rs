@@ -0,0 +1,133 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+use crate::util::channel::Sender;
+use std::io::{Read, Write};
+use std::sync::mpsc::SyncSender;
+use std::sync::{Arc, Mutex};
+
+/// Read `src` until EOF or error and send the data to `dst`.
+pub fn forward_input(mut src: impl Read, dst: &Sender<Vec<u8>>) {
+    let mut buf = [0; 1024];
+
+    loop {
+        let len = match src.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(v) => v,
+        };
+
+        dst.send(buf[..len].to_vec());
+    }
+}
+
+/// Broadcast the kernel console output to the attached clients.
+///
+/// Each client has a dedicated thread to write the output so a slow client will not block the
+/// caller. A client will be detached when its write fails or it has too many pending messages.
+#[derive(Default, Clone)]
+pub struct ConsoleOutput(Arc<Mutex<Vec<OutputClient>>>);
+
+impl ConsoleOutput {
+    /// Maximum number of messages that was not written to a client.
+    const PENDING: usize = 1000;
+
+    pub fn attach(&self, mut client: impl Write + Send + 'static) {
+        let (tx, rx) = std::sync::mpsc::sync_channel::<Arc<[u8]>>(Self::PENDING);
+
+        std::thread::spawn(move || {
+            for data in rx {
+                if client.write_all(&data).is_err() {
+                    break;
+                }
+            }
+        });
+
+        self.0.lock().unwrap().push(tx);
+    }
+
+    pub fn write(&self, msg: &str) {
+        let data = Arc::<[u8]>::from(msg.as_bytes());
+
+        self.0
+            .lock()
+            .unwrap()
+            .retain(|c| c.try_send(data.clone()).is_ok());
+    }
+}
+
+/// Sender side of the thread that write the output to a client.
+type OutputClient = SyncSender<Arc<[u8]>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::num::NonZero;
+    use std::sync::mpsc::Sender as StdSender;
+
+    #[test]
+    fn input() {
+        let (tx, mut rx) = crate::util::channel::new(NonZero::new(16).unwrap());
+        let data = vec![0x41u8; 1500];
+
+        forward_input(data.as_slice(), &tx);
+
+        let mut recv = Vec::new();
+
+        while recv.len() < data.len() {
+            recv.extend(futures::executor::block_on(rx.recv()));
+        }
+
+        assert_eq!(recv, data);
+    }
+
+    #[test]
+    fn output() {
+        let out = ConsoleOutput::default();
+        let (tx, rx) = std::sync::mpsc::channel();
+
+        out.attach(Client(tx.clone()));
+        out.attach(Client(tx));
+        out.write("abc");
+
+        assert_eq!(rx.recv().unwrap(), b"abc");
+        assert_eq!(rx.recv().unwrap(), b"abc");
+
+        // Detach the client that was closed.
+        out.attach(Closed);
+        out.write("d");
+
+        assert_eq!(rx.recv().unwrap(), b"d");
+        assert_eq!(rx.recv().unwrap(), b"d");
+
+        while out.0.lock().unwrap().len() != 2 {
+            std::thread::yield_now();
+            out.write("");
+        }
+    }
+
+    struct Client(StdSender<Vec<u8>>);
+
+    impl Write for Client {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0
+                .send(buf.to_vec())
+                .map_err(|_| std::io::ErrorKind::BrokenPipe)?;
+
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct Closed;
+
+    impl Write for Closed {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+}
@@ -1,12 +1,13 @@
 use std::path::PathBuf;
 
 /// Manages disk partition to be mounted by the kernel.
+#[derive(Clone)]
 pub struct Part {
     root: PathBuf,
 }
 
 impl Part {
-    pub(super) fn new(root: PathBuf) -> Self {
+    pub(crate) fn new(root: PathBuf) -> Self {
         Self { root }
     }
 
@@ -27,6 +27,11 @@ impl Prof {
 
         self.root.join(id)
     }
+
+    /// Returns path to the VM snapshot of the profile.
+    pub fn snapshot(&self, id: Uuid) -> PathBuf {
+        self.data(id).join("snapshot.bin")
+    }
 }
 
 /// Implementation of [`Iterator`] to enumerate profile directories.
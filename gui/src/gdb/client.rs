@@ -1,4 +1,12 @@
-use super::{GdbDispatcher, GdbError, GdbHandler, GdbSession};
+use super::state::{PACKET_SIZE, SessionState};
+use super::{BreakpointType, GdbDispatcher, GdbError, GdbHandler, GdbSession, StopReason};
+use gdbstub::arch::{Arch, RegId, Registers};
+use gdbstub::target::ext::breakpoints::WatchKind;
+use gdbstub::target::ext::section_offsets::Offsets;
+use std::num::NonZero;
+
+const EPERM: u8 = 1;
+const EINVAL: u8 = 22;
 
 /// Implementation of [`GdbDispatcher`] to dispatch requests from GDB client.
 pub struct ClientDispatcher<'a, H> {
@@ -10,15 +18,395 @@ impl<'a, H> ClientDispatcher<'a, H> {
     pub fn new(session: &'a mut GdbSession, handler: &'a mut H) -> Self {
         Self { session, handler }
     }
+}
+
+impl<'a, H: GdbHandler> ClientDispatcher<'a, H> {
+    /// Returns `false` if the command does not have a response.
+    fn exec(state: &mut SessionState, h: &mut H, data: &[u8], res: &mut Vec<u8>) -> bool {
+        // Strip thread suffix.
+        // https://lldb.llvm.org/resources/lldbgdbremote.html#qthreadsuffixsupported
+        let (data, suffix) = match state.thread_suffix_supported() {
+            true => match data
+                .windows(8)
+                .position(|w| w == b";thread:")
+                .map(|i| (&data[..i], &data[(i + 8)..]))
+            {
+                Some((d, t)) => (d, parse_tid(t.strip_suffix(b";").unwrap_or(t))),
+                None => (data, None),
+            },
+            false => (data, None),
+        };
+
+        if data == b"?" {
+            match h.stop_reason() {
+                Some((tid, r)) => write_stop(state, h, tid, r, res),
+                None => {
+                    state.set_waiting_stop(true);
+                    return false;
+                }
+            }
+        } else if data == b"QStartNoAckMode" {
+            state.parse_start_no_ack_mode(res);
+        } else if let Some(data) = data.strip_prefix(b"qSupported") {
+            // It is unclear if qSupported can sent from GDB without additional payload.
+            state.parse_supported(data, res);
+
+            if H::Arch::target_description_xml().is_some() {
+                res.extend_from_slice(b";qXfer:features:read+");
+            }
+
+            if h.exec_file().is_some() {
+                res.extend_from_slice(b";qXfer:exec-file:read+");
+            }
+        } else if data == b"QThreadSuffixSupported" {
+            // https://lldb.llvm.org/resources/lldbgdbremote.html#qthreadsuffixsupported
+            state.parse_thread_suffix_supported(res);
+        } else if data == b"QListThreadsInStopReply" {
+            state.parse_list_threads_in_stop_reply(res);
+        } else if data == b"qHostInfo" {
+            // https://lldb.llvm.org/resources/lldbgdbremote.html#qhostinfo
+            write_host_info(res);
+        } else if data == b"qProcessInfo" {
+            // https://lldb.llvm.org/resources/lldbgdbremote.html#qprocessinfo
+            res.extend_from_slice(b"pid:1;");
+            write_host_info(res);
+        } else if data == b"qAttached" {
+            res.push(b'1');
+        } else if data == b"qC" {
+            if let Some(tid) = current_thread(state, h, None) {
+                res.extend_from_slice(format!("QC{tid:x}").as_bytes());
+            }
+        } else if data == b"qfThreadInfo" {
+            let threads = h.active_threads();
+
+            res.push(b'm');
+
+            for (i, tid) in threads.into_iter().enumerate() {
+                if i != 0 {
+                    res.push(b',');
+                }
+
+                res.extend_from_slice(format!("{tid:x}").as_bytes());
+            }
+        } else if data == b"qsThreadInfo" {
+            res.push(b'l');
+        } else if let Some(v) = data.strip_prefix(b"qXfer:features:read:") {
+            if let Err(e) = Self::read_features(v, res) {
+                write_error(res, e);
+            }
+        } else if let Some(v) = data.strip_prefix(b"qXfer:exec-file:read:") {
+            if let Err(e) = Self::read_exec_file(h, v, res) {
+                write_error(res, e);
+            }
+        } else if data == b"qOffsets" {
+            match h.section_offsets() {
+                Some(Offsets::Sections { text, data, bss }) => {
+                    let bss = bss.unwrap_or(data);
+
+                    res.extend_from_slice(
+                        format!("Text={text:x};Data={data:x};Bss={bss:x}").as_bytes(),
+                    );
+                }
+                Some(Offsets::Segments { text_seg, data_seg }) => {
+                    res.extend_from_slice(format!("TextSeg={text_seg:x}").as_bytes());
+
+                    if let Some(v) = data_seg {
+                        res.extend_from_slice(format!(";DataSeg={v:x}").as_bytes());
+                    }
+                }
+                None => {}
+            }
+        } else if let Some(v) = data.strip_prefix(b"H") {
+            // We don't support different thread for each operation so we use the same thread.
+            match v.split_first().and_then(|(_, t)| parse_tid(t)) {
+                Some(v) => {
+                    state.set_thread(v);
+                    res.extend_from_slice(b"OK");
+                }
+                None => write_error(res, EINVAL),
+            }
+        } else if let Some(v) = data.strip_prefix(b"T") {
+            let tid = parse_tid(v).flatten();
+
+            match tid.filter(|v| h.active_threads().contains(v)) {
+                Some(_) => res.extend_from_slice(b"OK"),
+                None => write_error(res, EINVAL),
+            }
+        } else if data == b"g" {
+            let r = current_thread(state, h, suffix)
+                .ok_or(EPERM)
+                .and_then(|tid| h.read_registers(tid))
+                .map(|regs| regs.gdb_serialize(|b| write_reg_byte(res, b)));
+
+            if let Err(e) = r {
+                write_error(res, e);
+            }
+        } else if let Some(v) = data.strip_prefix(b"G") {
+            let r = Self::write_registers(state, h, suffix, v);
+
+            write_result(res, r);
+        } else if let Some(v) = data.strip_prefix(b"p") {
+            let r = Self::read_register(state, h, suffix, v, res);
+
+            if let Err(e) = r {
+                write_error(res, e);
+            }
+        } else if let Some(v) = data.strip_prefix(b"P") {
+            let r = Self::write_register(state, h, suffix, v);
+
+            write_result(res, r);
+        } else if let Some(v) = data.strip_prefix(b"m") {
+            let r = Self::read_memory(state, h, suffix, v, res);
+
+            if let Err(e) = r {
+                write_error(res, e);
+            }
+        } else if let Some(v) = data.strip_prefix(b"M") {
+            let r = parse_mem_write(v, |v| hex::decode(v).ok())
+                .and_then(|(addr, data)| Self::write_memory(state, h, suffix, addr, &data));
+
+            write_result(res, r);
+        } else if let Some(v) = data.strip_prefix(b"X") {
+            let r = parse_mem_write(v, |v| Some(unescape(v)))
+                .and_then(|(addr, data)| Self::write_memory(state, h, suffix, addr, &data));
+
+            write_result(res, r);
+        } else if let Some(v) = data.strip_prefix(b"Z") {
+            match parse_breakpoint(v).map(|(t, a, k)| h.insert_breakpoint(t, a, k)) {
+                Some(Ok(true)) => res.extend_from_slice(b"OK"),
+                Some(Ok(false)) => {} // Not supported.
+                Some(Err(e)) => write_error(res, e),
+                None => write_error(res, EINVAL),
+            }
+        } else if let Some(v) = data.strip_prefix(b"z") {
+            match parse_breakpoint(v).map(|(t, a, k)| h.remove_breakpoint(t, a, k)) {
+                Some(Ok(true)) => res.extend_from_slice(b"OK"),
+                Some(Ok(false)) => {}
+                Some(Err(e)) => write_error(res, e),
+                None => write_error(res, EINVAL),
+            }
+        } else if let Some(v) = data.strip_prefix(b"qRcmd,") {
+            let r = hex::decode(v)
+                .ok()
+                .and_then(|v| String::from_utf8(v).ok())
+                .ok_or(EINVAL)
+                .and_then(|cmd| h.monitor(&cmd));
+
+            match r {
+                Ok(v) if v.is_empty() => res.extend_from_slice(b"OK"),
+                Ok(v) => res.extend_from_slice(hex::encode(v).as_bytes()),
+                Err(e) => write_error(res, e),
+            }
+        } else if data == b"vCont?" {
+            res.extend_from_slice(b"vCont;c;C;s;S");
+        } else if let Some(v) = data.strip_prefix(b"vCont;") {
+            match Self::parse_vcont(state, h, v).map(|step| h.resume(&step)) {
+                Some(Ok(_)) => {
+                    state.set_waiting_stop(true);
+                    return false;
+                }
+                Some(Err(e)) => write_error(res, e),
+                None => write_error(res, EINVAL),
+            }
+        } else if data.first().is_some_and(|&b| b == b'c' || b == b'C') {
+            // We don't support resume at the specified address and signal.
+            match h.resume(&[]) {
+                Ok(_) => {
+                    state.set_waiting_stop(true);
+                    return false;
+                }
+                Err(e) => write_error(res, e),
+            }
+        } else if data.first().is_some_and(|&b| b == b's' || b == b'S') {
+            let r = current_thread(state, h, None)
+                .ok_or(EPERM)
+                .and_then(|tid| h.resume(&[tid]));
+
+            match r {
+                Ok(_) => {
+                    state.set_waiting_stop(true);
+                    return false;
+                }
+                Err(e) => write_error(res, e),
+            }
+        } else if data.first() == Some(&b'D') {
+            write_result(res, h.resume(&[]));
+        } else {
+            // Empty response means the packet is not supported.
+        }
+
+        true
+    }
+
+    fn read_features(req: &[u8], res: &mut Vec<u8>) -> Result<(), u8> {
+        let (annex, range) = split_once(req, b':').ok_or(EINVAL)?;
+
+        if annex != b"target.xml" {
+            return Err(EINVAL);
+        }
+
+        let xml = H::Arch::target_description_xml().ok_or(EINVAL)?;
+
+        write_xfer(xml.as_bytes(), range, res)
+    }
+
+    fn read_exec_file(h: &mut H, req: &[u8], res: &mut Vec<u8>) -> Result<(), u8> {
+        // The annex is a process ID, which we only have one.
+        let (_, range) = split_once(req, b':').ok_or(EINVAL)?;
+        let path = h.exec_file().ok_or(EINVAL)?.to_string_lossy();
+
+        write_xfer(path.as_bytes(), range, res)
+    }
+
+    fn write_registers(
+        state: &SessionState,
+        h: &mut H,
+        suffix: Option<Option<NonZero<usize>>>,
+        data: &[u8],
+    ) -> Result<(), u8> {
+        let tid = current_thread(state, h, suffix).ok_or(EPERM)?;
+        let data = hex::decode(data).map_err(|_| EINVAL)?;
+        let mut regs = <H::Arch as Arch>::Registers::default();
+
+        regs.gdb_deserialize(&data).map_err(|_| EINVAL)?;
+
+        h.write_registers(tid, &regs)
+    }
+
+    fn read_register(
+        state: &SessionState,
+        h: &mut H,
+        suffix: Option<Option<NonZero<usize>>>,
+        req: &[u8],
+        res: &mut Vec<u8>,
+    ) -> Result<(), u8> {
+        let reg = parse_hex(req).ok_or(EINVAL)?;
+        let (off, len) = register_range::<H::Arch>(reg).ok_or(EINVAL)?;
+        let tid = current_thread(state, h, suffix).ok_or(EPERM)?;
+        let regs = h.read_registers(tid)?;
+        let mut data = Vec::new();
+
+        regs.gdb_serialize(|b| data.push(b));
+
+        for &b in data.get(off..(off + len)).ok_or(EINVAL)? {
+            write_reg_byte(res, b);
+        }
+
+        Ok(())
+    }
+
+    fn write_register(
+        state: &SessionState,
+        h: &mut H,
+        suffix: Option<Option<NonZero<usize>>>,
+        req: &[u8],
+    ) -> Result<(), u8> {
+        // Parse request.
+        let (reg, val) = split_once(req, b'=').ok_or(EINVAL)?;
+        let reg = parse_hex(reg).ok_or(EINVAL)?;
+        let val = hex::decode(val).map_err(|_| EINVAL)?;
+        let (off, len) = register_range::<H::Arch>(reg).ok_or(EINVAL)?;
+
+        if val.len() != len {
+            return Err(EINVAL);
+        }
+
+        // Replace the register then write all registers back. We can't do this if some registers
+        // are not available since we don't know their values.
+        let tid = current_thread(state, h, suffix).ok_or(EPERM)?;
+        let mut regs = h.read_registers(tid)?;
+        let mut data = Vec::new();
+
+        regs.gdb_serialize(|b| data.push(b));
+
+        let mut data = data
+            .into_iter()
+            .collect::<Option<Vec<u8>>>()
+            .ok_or(EINVAL)?;
+
+        data.get_mut(off..(off + len))
+            .ok_or(EINVAL)?
+            .copy_from_slice(&val);
+        regs.gdb_deserialize(&data).map_err(|_| EINVAL)?;
+
+        h.write_registers(tid, &regs)
+    }
+
+    fn read_memory(
+        state: &SessionState,
+        h: &mut H,
+        suffix: Option<Option<NonZero<usize>>>,
+        req: &[u8],
+        res: &mut Vec<u8>,
+    ) -> Result<(), u8> {
+        // Parse request.
+        let (addr, len) = split_once(req, b',').ok_or(EINVAL)?;
+        let addr = parse_hex(addr).ok_or(EINVAL)?;
+        let len = parse_hex(len)
+            .and_then(|v| usize::try_from(v).ok())
+            .ok_or(EINVAL)?;
+
+        // The response is hex-encoded so each byte take 2 bytes.
+        if len > PACKET_SIZE / 2 {
+            return Err(EINVAL);
+        }
+
+        // Read memory.
+        let tid = current_thread(state, h, suffix).ok_or(EPERM)?;
+        let mut buf = vec![0; len];
+
+        if len != 0 {
+            h.read_memory(tid, addr, &mut buf)?;
+        }
+
+        res.extend_from_slice(hex::encode(buf).as_bytes());
+
+        Ok(())
+    }
 
-    fn get_checksum(data: &[u8]) -> u8 {
-        let mut r = 0u8;
+    fn write_memory(
+        state: &SessionState,
+        h: &mut H,
+        suffix: Option<Option<NonZero<usize>>>,
+        addr: u64,
+        data: &[u8],
+    ) -> Result<(), u8> {
+        let tid = current_thread(state, h, suffix).ok_or(EPERM)?;
 
-        for &b in data {
-            r = r.wrapping_add(b);
+        if data.is_empty() {
+            return Ok(());
         }
 
-        r
+        h.write_memory(tid, addr, data)
+    }
+
+    /// Returns threads to single-step.
+    fn parse_vcont(state: &SessionState, h: &mut H, req: &[u8]) -> Option<Vec<NonZero<usize>>> {
+        let mut step = Vec::new();
+
+        for action in req.split(|&b| b == b';') {
+            let (action, tid) = match split_once(action, b':') {
+                Some((a, t)) => (a, parse_tid(t)?),
+                None => (action, None),
+            };
+
+            match action.first()? {
+                b'c' | b'C' => {}
+                b's' | b'S' => {
+                    let tid = match tid {
+                        Some(v) => v,
+                        None => current_thread(state, h, None)?,
+                    };
+
+                    if !step.contains(&tid) {
+                        step.push(tid);
+                    }
+                }
+                _ => return None,
+            }
+        }
+
+        Some(step)
     }
 }
 
@@ -27,6 +415,7 @@ impl<'a, H: GdbHandler> GdbDispatcher for ClientDispatcher<'a, H> {
         // Check if GDB packet.
         let req = &mut self.session.req;
         let res = &mut self.session.res;
+        let last = &mut self.session.last;
         let state = &mut self.session.state;
 
         match req.first().copied() {
@@ -41,6 +430,24 @@ impl<'a, H: GdbHandler> GdbDispatcher for ClientDispatcher<'a, H> {
                 req.drain(..1);
                 return Ok(res.drain(0..0).into());
             }
+            Some(b'-') => {
+                // The client will not send NAK in no-ack mode but just in case.
+                if state.no_ack() != Some(true) {
+                    res.extend_from_slice(last);
+                }
+
+                req.drain(..1);
+                return Ok(res.drain(..).into());
+            }
+            Some(0x03) => {
+                // The stop reply will be sent by GdbSession::stop() once the target stopped.
+                if state.waiting_stop() {
+                    self.handler.interrupt();
+                }
+
+                req.drain(..1);
+                return Ok(res.drain(0..0).into());
+            }
             Some(v) => return Err(GdbError::UnknownPacketPrefix(v)),
             None => return Ok(None),
         }
@@ -75,7 +482,7 @@ impl<'a, H: GdbHandler> GdbDispatcher for ClientDispatcher<'a, H> {
 
         // Calculate expected checksum.
         let data = &cmd[1..(cmd.len() - 3)];
-        let expect = Self::get_checksum(data);
+        let expect = get_checksum(data);
 
         if checksum != expect {
             match state.no_ack() {
@@ -95,31 +502,745 @@ impl<'a, H: GdbHandler> GdbDispatcher for ClientDispatcher<'a, H> {
             None => res.push(b'+'),
         }
 
+        // Execute command.
+        let start = res.len();
+
         res.push(b'$');
 
-        // Execute command.
-        let off = res.len();
+        if Self::exec(state, self.handler, data, res) {
+            end_packet(res, start + 1);
+            save_reply(state, last, &res[start..]);
+        } else {
+            res.truncate(start);
+        }
 
-        if data == b"QStartNoAckMode" {
-            state.parse_start_no_ack_mode(res);
-        } else if let Some(data) = data.strip_prefix(b"qSupported") {
-            // It is unclear if qSupported can sent from GDB without additional payload.
-            state.parse_supported(data, res);
-        } else if data == b"QThreadSuffixSupported" {
-            // https://lldb.llvm.org/resources/lldbgdbremote.html#qthreadsuffixsupported
-            state.parse_thread_suffix_supported(res);
+        Ok(res.drain(..).into())
+    }
+}
+
+/// Write a stop reply to `res` without packet framing.
+pub fn write_stop(
+    state: &mut SessionState,
+    h: &mut impl GdbHandler,
+    tid: NonZero<usize>,
+    reason: StopReason,
+    res: &mut Vec<u8>,
+) {
+    let signal = match reason {
+        StopReason::Interrupt => 2, // SIGINT
+        StopReason::Abort => 6,     // SIGABRT
+        _ => 5,                     // SIGTRAP
+    };
+
+    res.extend_from_slice(format!("T{signal:02x}thread:{tid:x};").as_bytes());
+
+    match reason {
+        StopReason::Trap | StopReason::Abort | StopReason::Interrupt => {}
+        StopReason::SwBreak => {
+            if state.swbreak() {
+                res.extend_from_slice(b"swbreak:;");
+            }
+        }
+        StopReason::HwBreak => {
+            if state.hwbreak() {
+                res.extend_from_slice(b"hwbreak:;");
+            }
+        }
+        StopReason::Watch(kind, addr) => {
+            let name = match kind {
+                WatchKind::Write => "watch",
+                WatchKind::Read => "rwatch",
+                WatchKind::ReadWrite => "awatch",
+            };
+
+            res.extend_from_slice(format!("{name}:{addr:x};").as_bytes());
+        }
+    }
+
+    if state.list_threads_in_stop_reply() {
+        let threads = h
+            .active_threads()
+            .into_iter()
+            .map(|v| format!("{v:x}"))
+            .collect::<Vec<_>>();
+
+        res.extend_from_slice(format!("threads:{};", threads.join(",")).as_bytes());
+    }
+
+    // Operations on the other thread is not allowed until the client select it.
+    state.set_thread(None);
+}
+
+/// Append checksum of the packet started at `off` to `res`.
+pub fn end_packet(res: &mut Vec<u8>, off: usize) {
+    let mut checksum = [0u8; 2];
+
+    hex::encode_to_slice([get_checksum(&res[off..])], &mut checksum).unwrap();
+
+    res.push(b'#');
+    res.extend_from_slice(&checksum);
+}
+
+/// Keep `packet` to resend when the client request a retransmission.
+pub fn save_reply(state: &SessionState, last: &mut Vec<u8>, packet: &[u8]) {
+    if state.no_ack() == Some(true) {
+        return;
+    }
+
+    last.clear();
+    last.extend_from_slice(packet);
+}
+
+fn get_checksum(data: &[u8]) -> u8 {
+    let mut r = 0u8;
+
+    for &b in data {
+        r = r.wrapping_add(b);
+    }
+
+    r
+}
+
+/// Returns the thread to operate on.
+///
+/// `suffix` is the thread from LLDB thread suffix.
+fn current_thread(
+    state: &SessionState,
+    h: &mut impl GdbHandler,
+    suffix: Option<Option<NonZero<usize>>>,
+) -> Option<NonZero<usize>> {
+    if let Some(v) = suffix.flatten().or(state.thread()) {
+        return Some(v);
+    }
+
+    match h.stop_reason() {
+        Some((v, _)) => Some(v),
+        None => h.active_threads().first().copied(),
+    }
+}
+
+#[cfg(not(any(target_arch = "aarch64", target_arch = "x86_64")))]
+compile_error!("the debugger does not support the target architecture");
+
+fn write_host_info(res: &mut Vec<u8>) {
+    #[cfg(target_arch = "aarch64")]
+    let triple: &[u8] = b"aarch64-unknown-none";
+    #[cfg(target_arch = "x86_64")]
+    let triple: &[u8] = b"x86_64-unknown-none";
+
+    res.extend_from_slice(b"triple:");
+    res.extend_from_slice(hex::encode(triple).as_bytes());
+    res.extend_from_slice(b";ptrsize:8;endian:little;");
+}
+
+/// Write the part of `data` requested by `range` as a response of `qXfer` packet.
+fn write_xfer(data: &[u8], range: &[u8], res: &mut Vec<u8>) -> Result<(), u8> {
+    let (off, len) = split_once(range, b',').ok_or(EINVAL)?;
+    let off = parse_hex(off).ok_or(EINVAL)?;
+    let len = parse_hex(len).ok_or(EINVAL)?;
+
+    // Each byte can be escaped into 2 bytes and the response also has a prefix.
+    let len = usize::try_from(len)
+        .unwrap_or(usize::MAX)
+        .min((PACKET_SIZE - 1) / 2);
+    let off = usize::try_from(off).map_or(data.len(), |v| v.min(data.len()));
+    let end = off.saturating_add(len).min(data.len());
+
+    res.push(if end == data.len() { b'l' } else { b'm' });
+
+    escape(&data[off..end], res);
+
+    Ok(())
+}
+
+fn write_result(res: &mut Vec<u8>, r: Result<(), u8>) {
+    match r {
+        Ok(_) => res.extend_from_slice(b"OK"),
+        Err(e) => write_error(res, e),
+    }
+}
+
+fn write_error(res: &mut Vec<u8>, e: u8) {
+    res.extend_from_slice(format!("E{e:02x}").as_bytes());
+}
+
+fn write_reg_byte(res: &mut Vec<u8>, b: Option<u8>) {
+    match b {
+        Some(v) => res.extend_from_slice(format!("{v:02x}").as_bytes()),
+        None => res.extend_from_slice(b"xx"),
+    }
+}
+
+/// Returns offset and size of register `reg` in the data serialized by
+/// [`Registers::gdb_serialize()`].
+fn register_range<A: Arch>(reg: u64) -> Option<(usize, usize)> {
+    let reg = usize::try_from(reg).ok()?;
+    let mut off = 0;
+
+    for id in 0..reg {
+        off += A::RegId::from_raw_id(id)?.1?.get();
+    }
+
+    Some((off, A::RegId::from_raw_id(reg)?.1?.get()))
+}
+
+/// Returns [`None`] for `-1` (all threads) and `0` (any thread).
+fn parse_tid(v: &[u8]) -> Option<Option<NonZero<usize>>> {
+    if v == b"-1" {
+        return Some(None);
+    }
+
+    let v = usize::try_from(parse_hex(v)?).ok()?;
+
+    Some(NonZero::new(v))
+}
+
+fn parse_hex(v: &[u8]) -> Option<u64> {
+    let v = std::str::from_utf8(v).ok()?;
+
+    u64::from_str_radix(v, 16).ok()
+}
+
+fn parse_mem_write(
+    req: &[u8],
+    decode: impl FnOnce(&[u8]) -> Option<Vec<u8>>,
+) -> Result<(u64, Vec<u8>), u8> {
+    let (range, data) = split_once(req, b':').ok_or(EINVAL)?;
+    let (addr, len) = split_once(range, b',').ok_or(EINVAL)?;
+    let addr = parse_hex(addr).ok_or(EINVAL)?;
+    let len = parse_hex(len).ok_or(EINVAL)?;
+
+    if len > u64::try_from(PACKET_SIZE).unwrap() {
+        return Err(EINVAL);
+    }
+
+    let data = decode(data).ok_or(EINVAL)?;
+
+    if u64::try_from(data.len()).unwrap() != len {
+        return Err(EINVAL);
+    }
+
+    Ok((addr, data))
+}
+
+fn parse_breakpoint(req: &[u8]) -> Option<(BreakpointType, u64, usize)> {
+    // Strip conditions and commands.
+    let req = split_once(req, b';').map_or(req, |v| v.0);
+    let mut iter = req.split(|&b| b == b',');
+    let ty = match iter.next()? {
+        b"0" => BreakpointType::Sw,
+        b"1" => BreakpointType::Hw,
+        b"2" => BreakpointType::Watch(WatchKind::Write),
+        b"3" => BreakpointType::Watch(WatchKind::Read),
+        b"4" => BreakpointType::Watch(WatchKind::ReadWrite),
+        _ => return None,
+    };
+    let addr = parse_hex(iter.next()?)?;
+    let kind = usize::try_from(parse_hex(iter.next()?)?).ok()?;
+
+    if iter.next().is_some() {
+        return None;
+    }
+
+    Some((ty, addr, kind))
+}
+
+fn split_once(v: &[u8], sep: u8) -> Option<(&[u8], &[u8])> {
+    let i = v.iter().position(|&b| b == sep)?;
+
+    Some((&v[..i], &v[(i + 1)..]))
+}
+
+/// Decode binary data from the client.
+fn unescape(v: &[u8]) -> Vec<u8> {
+    let mut r = Vec::with_capacity(v.len());
+    let mut iter = v.iter();
+
+    while let Some(&b) = iter.next() {
+        match b {
+            b'}' => match iter.next() {
+                Some(&b) => r.push(b ^ 0x20),
+                None => break,
+            },
+            b => r.push(b),
+        }
+    }
+
+    r
+}
+
+/// Encode binary data to send to the client.
+fn escape(v: &[u8], res: &mut Vec<u8>) {
+    for &b in v {
+        match b {
+            b'#' | b'$' | b'}' | b'*' => {
+                res.push(b'}');
+                res.push(b ^ 0x20);
+            }
+            b => res.push(b),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::num::NonZeroUsize;
+    use std::path::Path;
+
+    #[test]
+    fn gdb() {
+        let mut s = GdbSession::default();
+        let mut h = TestHandler {
+            stop: Some((NonZero::new(1).unwrap(), StopReason::SwBreak)),
+            ..Default::default()
+        };
+
+        run(
+            &mut s,
+            &mut h,
+            &[
+                (
+                    "+$qSupported:multiprocess+;swbreak+;hwbreak+;qRelocInsn+;fork-events+;vfork-events+;exec-events+;vContSupported+;QThreadEvents+;no-resumed+;memory-tagging+#??",
+                    "+$PacketSize=4000;QStartNoAckMode+;vContSupported+;swbreak+;hwbreak+;qXfer:features:read+;qXfer:exec-file:read+#??",
+                ),
+                ("+", ""),
+                ("$vMustReplyEmpty#??", "+$#00"),
+                ("-", "$#00"),
+                ("+$QStartNoAckMode#??", "+$OK#9a"),
+                ("-", "$OK#9a"),
+                ("+", ""),
+                ("-", ""),
+                ("$Hg0#??", "$OK#9a"),
+                (
+                    "$qXfer:features:read:target.xml:0,ffb#??",
+                    "$l<target>}]</target>#??",
+                ),
+                ("$qXfer:features:read:target.xml:0,4#??", "$m<tar#??"),
+                ("$qXfer:exec-file:read::0,ffb#??", "$l/boot/kernel#??"),
+                ("$qXfer:exec-file:read::6,3#??", "$mker#??"),
+                (
+                    "$qXfer:exec-file:read::0,ffffffffffffffff#??",
+                    "$l/boot/kernel#??",
+                ),
+                ("$qOffsets#??", "$TextSeg=ffffffff80000000#??"),
+                ("$qRcmd,6563686f206869#??", "$6869#??"),
+                ("$qRcmd,6563686f#??", "$OK#9a"),
+                ("$qfThreadInfo#??", "$m1,2#??"),
+                ("$qsThreadInfo#??", "$l#6c"),
+                ("$?#??", "$T05thread:1;swbreak:;#??"),
+                ("$qC#??", "$QC1#??"),
+                ("$qAttached#??", "$1#31"),
+                ("$g#??", "$001000000000000000200000#??"),
+                ("$p1#??", "$00200000#??"),
+                ("$p2#??", "$E16#??"),
+                ("$P0=0020000000000000#??", "$OK#9a"),
+                ("$Hg2#??", "$OK#9a"),
+                ("$g#??", "$003000000000000000000000#??"),
+                ("$Hg1#??", "$OK#9a"),
+                ("$g#??", "$002000000000000000200000#??"),
+                ("$m1000,4#??", "$00010203#??"),
+                ("$M1001,2:aabb#??", "$OK#9a"),
+                ("$X1003,2:}]A#??", "$OK#9a"),
+                ("$m1000,6#??", "$00aabb7d4105#??"),
+                ("$m0,4#??", "$E0e#??"),
+                ("$m0,ffffffffffffffff#??", "$E16#??"),
+                ("$m1000,2001#??", "$E16#??"),
+                ("$M1000,4001:aa#??", "$E16#??"),
+                ("$Z0,1000,1#??", "$OK#9a"),
+                ("$Z3,1000,4#??", "$#00"),
+                ("$z0,1000,1#??", "$OK#9a"),
+                ("$vCont?#??", "$vCont;c;C;s;S#??"),
+                ("$vCont;s:1;c#??", ""),
+            ],
+        );
+
+        assert!(h.breakpoints.is_empty());
+        assert_eq!(h.resumed, Some(vec![NonZero::new(1).unwrap()]));
+
+        // Stop after single-step.
+        h.stop = Some((NonZero::new(1).unwrap(), StopReason::Trap));
+
+        let res = s.stop(&mut h).unwrap();
+
+        assert_eq!(
+            String::from_utf8_lossy(res.as_ref()),
+            checksum("$T05thread:1;#??")
+        );
+
+        drop(res);
+
+        // Watchpoint.
+        run(
+            &mut s,
+            &mut h,
+            &[("$Z2,1004,4#??", "$OK#9a"), ("$c#??", "")],
+        );
+
+        h.stop = Some((
+            NonZero::new(2).unwrap(),
+            StopReason::Watch(WatchKind::Write, 0x1004),
+        ));
+
+        let res = s.stop(&mut h).unwrap();
+
+        assert_eq!(
+            String::from_utf8_lossy(res.as_ref()),
+            checksum("$T05thread:2;watch:1004;#??")
+        );
+
+        drop(res);
+
+        assert!(s.stop(&mut h).is_none());
+
+        run(&mut s, &mut h, &[("$?#??", "$T05thread:2;watch:1004;#??")]);
+
+        // Abort.
+        run(&mut s, &mut h, &[("$c#??", "")]);
+
+        h.stop = Some((NonZero::new(1).unwrap(), StopReason::Abort));
+
+        let res = s.stop(&mut h).unwrap();
+
+        assert_eq!(
+            String::from_utf8_lossy(res.as_ref()),
+            checksum("$T06thread:1;#??")
+        );
+
+        drop(res);
+
+        // Interrupt.
+        run(&mut s, &mut h, &[("$c#??", ""), ("\x03", "")]);
+
+        let res = s.stop(&mut h).unwrap();
+
+        assert_eq!(
+            String::from_utf8_lossy(res.as_ref()),
+            checksum("$T02thread:1;#??")
+        );
+
+        drop(res);
+
+        // The target already stopped.
+        h.stop = Some((NonZero::new(2).unwrap(), StopReason::Trap));
+
+        run(&mut s, &mut h, &[("\x03", "")]);
+
+        assert!(s.stop(&mut h).is_none());
+        assert_eq!(h.stop, Some((NonZero::new(2).unwrap(), StopReason::Trap)));
+    }
+
+    #[test]
+    fn lldb() {
+        let mut s = GdbSession::default();
+        let mut h = TestHandler::default();
+        let (xml, triple) = if cfg!(target_arch = "aarch64") {
+            ("arm", "aarch64-unknown-none")
         } else {
-            todo!("{}", String::from_utf8_lossy(data));
+            ("i386", "x86_64-unknown-none")
+        };
+        let host = format!("triple:{};ptrsize:8;endian:little;", hex::encode(triple));
+        let supported = format!(
+            "$PacketSize=4000;QStartNoAckMode+;vContSupported+;xmlRegisters={xml};qXfer:features:read+;qXfer:exec-file:read+#??"
+        );
+
+        run(
+            &mut s,
+            &mut h,
+            &[
+                ("+", ""),
+                ("$QStartNoAckMode#??", "+$OK#9a"),
+                ("+", ""),
+                (
+                    "$qSupported:xmlRegisters=i386,arm,mips#??",
+                    &checksum(&supported),
+                ),
+                ("$QThreadSuffixSupported#??", "$OK#9a"),
+                ("$QListThreadsInStopReply#??", "$OK#9a"),
+                ("$qHostInfo#??", &checksum(&format!("${host}#??"))),
+                ("$qProcessInfo#??", &checksum(&format!("$pid:1;{host}#??"))),
+                ("$vCont?#??", "$vCont;c;C;s;S#??"),
+                ("$x1000,4#??", "$#00"),
+                ("$?#??", ""),
+            ],
+        );
+
+        // The target stopped after the client has been connected.
+        h.stop = Some((NonZero::new(2).unwrap(), StopReason::HwBreak));
+
+        let res = s.stop(&mut h).unwrap();
+
+        assert_eq!(
+            String::from_utf8_lossy(res.as_ref()),
+            checksum("$T05thread:2;threads:1,2;#??")
+        );
+
+        drop(res);
+
+        run(
+            &mut s,
+            &mut h,
+            &[
+                ("$g;thread:2;#??", "$003000000000000000000000#??"),
+                ("$p1;thread:1;#??", "$00200000#??"),
+                ("$P1=00400000;thread:1;#??", "$OK#9a"),
+                ("$g;thread:1;#??", "$001000000000000000400000#??"),
+                ("$g#??", "$003000000000000000000000#??"),
+                ("$vCont;s#??", ""),
+            ],
+        );
+
+        assert_eq!(h.resumed, Some(vec![NonZero::new(2).unwrap()]));
+
+        // Interrupt.
+        run(&mut s, &mut h, &[("\x03", "")]);
+
+        let res = s.stop(&mut h).unwrap();
+
+        assert_eq!(
+            String::from_utf8_lossy(res.as_ref()),
+            checksum("$T02thread:1;threads:1,2;#??")
+        );
+    }
+
+    #[test]
+    fn retransmission() {
+        let mut s = GdbSession::default();
+        let mut h = TestHandler::default();
+
+        run(
+            &mut s,
+            &mut h,
+            &[("$qC#00", "-"), ("$qC#zz", "-"), ("$qC#??", "+$QC1#??")],
+        );
+    }
+
+    #[test]
+    fn partial() {
+        let mut s = GdbSession::default();
+        let mut h = TestHandler::default();
+        let req = checksum("$qfThreadInfo#??");
+        let (a, b) = req.as_bytes().split_at(5);
+
+        assert_eq!(pump(&mut s, &mut h, a), "");
+        assert_eq!(pump(&mut s, &mut h, b), checksum("+$m1,2#??"));
+    }
+
+    fn run(s: &mut GdbSession, h: &mut TestHandler, transcript: &[(&str, &str)]) {
+        for &(req, res) in transcript {
+            let req = checksum(req);
+
+            assert_eq!(pump(s, h, req.as_bytes()), checksum(res), "{req}");
         }
+    }
+
+    fn pump(s: &mut GdbSession, h: &mut TestHandler, req: &[u8]) -> String {
+        let mut dis = s.dispatch_client(req, h);
+        let mut res = Vec::new();
 
-        // Push checksum.
-        let mut checksum = [0u8; 2];
+        while let Some(v) = dis.pump().unwrap() {
+            res.extend_from_slice(v.as_ref());
+        }
 
-        hex::encode_to_slice([Self::get_checksum(&res[off..])], &mut checksum).unwrap();
+        String::from_utf8(res).unwrap()
+    }
 
-        res.push(b'#');
-        res.extend_from_slice(&checksum);
+    /// Replace `#??` with the checksum of the packet.
+    fn checksum(v: &str) -> String {
+        let Some(v) = v.strip_suffix("#??") else {
+            return v.to_owned();
+        };
 
-        Ok(res.drain(..).into())
+        let i = v.find('$').unwrap();
+        let sum = get_checksum(&v.as_bytes()[(i + 1)..]);
+
+        format!("{v}#{sum:02x}")
+    }
+
+    struct TestArch;
+
+    impl Arch for TestArch {
+        type Usize = u64;
+        type Registers = TestRegs;
+        type BreakpointKind = usize;
+        type RegId = TestRegId;
+
+        fn target_description_xml() -> Option<&'static str> {
+            Some("<target>}</target>")
+        }
+    }
+
+    #[derive(Default, Debug, Clone, PartialEq)]
+    struct TestRegs {
+        pc: u64,
+        sp: u32,
+    }
+
+    impl Registers for TestRegs {
+        type ProgramCounter = u64;
+
+        fn pc(&self) -> Self::ProgramCounter {
+            self.pc
+        }
+
+        fn gdb_serialize(&self, mut write_byte: impl FnMut(Option<u8>)) {
+            for b in self
+                .pc
+                .to_le_bytes()
+                .into_iter()
+                .chain(self.sp.to_le_bytes())
+            {
+                write_byte(Some(b));
+            }
+        }
+
+        fn gdb_deserialize(&mut self, bytes: &[u8]) -> Result<(), ()> {
+            if bytes.len() != 12 {
+                return Err(());
+            }
+
+            self.pc = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+            self.sp = u32::from_le_bytes(bytes[8..].try_into().unwrap());
+
+            Ok(())
+        }
+    }
+
+    #[derive(Debug)]
+    struct TestRegId;
+
+    impl RegId for TestRegId {
+        fn from_raw_id(id: usize) -> Option<(Self, Option<NonZeroUsize>)> {
+            match id {
+                0 => Some((Self, NonZeroUsize::new(8))),
+                1 => Some((Self, NonZeroUsize::new(4))),
+                _ => None,
+            }
+        }
+    }
+
+    struct TestHandler {
+        stop: Option<(NonZero<usize>, StopReason)>,
+        regs: [TestRegs; 2],
+        mem: [u8; 16],
+        breakpoints: Vec<(BreakpointType, u64)>,
+        resumed: Option<Vec<NonZero<usize>>>,
+    }
+
+    impl Default for TestHandler {
+        fn default() -> Self {
+            Self {
+                stop: None,
+                regs: [
+                    TestRegs {
+                        pc: 0x1000,
+                        sp: 0x2000,
+                    },
+                    TestRegs { pc: 0x3000, sp: 0 },
+                ],
+                mem: std::array::from_fn(|i| i as u8),
+                breakpoints: Vec::new(),
+                resumed: None,
+            }
+        }
+    }
+
+    impl TestHandler {
+        fn mem(&mut self, addr: u64, len: usize) -> Result<&mut [u8], u8> {
+            let off = addr.checked_sub(0x1000).ok_or(14)? as usize;
+
+            self.mem.get_mut(off..(off + len)).ok_or(14)
+        }
+    }
+
+    impl GdbHandler for TestHandler {
+        type Arch = TestArch;
+
+        fn stop_reason(&mut self) -> Option<(NonZero<usize>, StopReason)> {
+            self.stop
+        }
+
+        fn active_threads(&mut self) -> Vec<NonZero<usize>> {
+            vec![NonZero::new(1).unwrap(), NonZero::new(2).unwrap()]
+        }
+
+        fn exec_file(&mut self) -> Option<&Path> {
+            Some(Path::new("/boot/kernel"))
+        }
+
+        fn section_offsets(&mut self) -> Option<Offsets<u64>> {
+            Some(Offsets::Segments {
+                text_seg: 0xffffffff80000000,
+                data_seg: None,
+            })
+        }
+
+        fn read_registers(&mut self, tid: NonZero<usize>) -> Result<TestRegs, u8> {
+            Ok(self.regs[tid.get() - 1].clone())
+        }
+
+        fn write_registers(&mut self, tid: NonZero<usize>, regs: &TestRegs) -> Result<(), u8> {
+            self.regs[tid.get() - 1] = regs.clone();
+            Ok(())
+        }
+
+        fn read_memory(&mut self, _: NonZero<usize>, addr: u64, buf: &mut [u8]) -> Result<(), u8> {
+            buf.copy_from_slice(self.mem(addr, buf.len())?);
+            Ok(())
+        }
+
+        fn write_memory(&mut self, _: NonZero<usize>, addr: u64, data: &[u8]) -> Result<(), u8> {
+            self.mem(addr, data.len())?.copy_from_slice(data);
+            Ok(())
+        }
+
+        fn insert_breakpoint(
+            &mut self,
+            ty: BreakpointType,
+            addr: u64,
+            _: usize,
+        ) -> Result<bool, u8> {
+            if ty == BreakpointType::Watch(WatchKind::Read) {
+                return Ok(false);
+            }
+
+            self.breakpoints.push((ty, addr));
+
+            Ok(true)
+        }
+
+        fn remove_breakpoint(
+            &mut self,
+            ty: BreakpointType,
+            addr: u64,
+            _: usize,
+        ) -> Result<bool, u8> {
+            let i = self
+                .breakpoints
+                .iter()
+                .position(|&v| v == (ty, addr))
+                .ok_or(2)?;
+
+            self.breakpoints.remove(i);
+
+            Ok(true)
+        }
+
+        fn monitor(&mut self, cmd: &str) -> Result<String, u8> {
+            match cmd.strip_prefix("echo") {
+                Some(v) => Ok(v.trim_start().to_owned()),
+                None => Err(EINVAL),
+            }
+        }
+
+        fn resume(&mut self, step: &[NonZero<usize>]) -> Result<(), u8> {
+            self.stop = None;
+            self.resumed = Some(step.to_vec());
+
+            Ok(())
+        }
+        fn interrupt(&mut self) {
+            self.stop = Some((NonZero::new(1).unwrap(), StopReason::Interrupt));
+        }
     }
 }
@@ -1,2 +1,84 @@
+use gdbstub::arch::Arch;
+use gdbstub::target::ext::breakpoints::WatchKind;
+use gdbstub::target::ext::section_offsets::Offsets;
+use std::num::NonZero;
+use std::path::Path;
+
 /// Provides methods to handle debug events.
-pub trait GdbHandler {}
+///
+/// All methods that return [`Err`] use the value as an error number to report to the client.
+pub trait GdbHandler {
+    type Arch: Arch<Usize = u64>;
+
+    /// Returns the thread that currently stopped with its reason or [`None`] if the target is
+    /// running.
+    fn stop_reason(&mut self) -> Option<(NonZero<usize>, StopReason)>;
+
+    fn active_threads(&mut self) -> Vec<NonZero<usize>>;
+
+    /// Returns path of the file on the host that the target was loaded from.
+    fn exec_file(&mut self) -> Option<&Path>;
+
+    /// Returns the offsets to relocate the symbols from [`GdbHandler::exec_file()`].
+    fn section_offsets(&mut self) -> Option<Offsets<u64>>;
+
+    fn read_registers(
+        &mut self,
+        tid: NonZero<usize>,
+    ) -> Result<<Self::Arch as Arch>::Registers, u8>;
+
+    fn write_registers(
+        &mut self,
+        tid: NonZero<usize>,
+        regs: &<Self::Arch as Arch>::Registers,
+    ) -> Result<(), u8>;
+
+    fn read_memory(&mut self, tid: NonZero<usize>, addr: u64, buf: &mut [u8]) -> Result<(), u8>;
+
+    fn write_memory(&mut self, tid: NonZero<usize>, addr: u64, data: &[u8]) -> Result<(), u8>;
+
+    /// Returns `false` if the breakpoint is not supported.
+    fn insert_breakpoint(&mut self, ty: BreakpointType, addr: u64, kind: usize)
+    -> Result<bool, u8>;
+
+    /// Returns `false` if the breakpoint is not supported.
+    fn remove_breakpoint(&mut self, ty: BreakpointType, addr: u64, kind: usize)
+    -> Result<bool, u8>;
+
+    /// Execute a `monitor` command from the client and returns its output.
+    fn monitor(&mut self, cmd: &str) -> Result<String, u8>;
+
+    /// Resume the target with single-step enabled on the threads in `step`.
+    ///
+    /// [`GdbSession::stop()`](super::GdbSession::stop()) must be called when the target is stopped
+    /// again.
+    fn resume(&mut self, step: &[NonZero<usize>]) -> Result<(), u8>;
+
+    /// Stop the running target with [`StopReason::Interrupt`].
+    ///
+    /// [`GdbSession::stop()`](super::GdbSession::stop()) must be called when the target is stopped.
+    fn interrupt(&mut self);
+}
+
+/// Reason for the target to stop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The thread stopped without specific reason (e.g. single-step).
+    Trap,
+    SwBreak,
+    HwBreak,
+    /// Contains the address that was accessed.
+    Watch(WatchKind, u64),
+    /// The target was aborted (e.g. kernel panic).
+    Abort,
+    /// The client requested the target to stop.
+    Interrupt,
+}
+
+/// Type of breakpoint requested by `Z` and `z` packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakpointType {
+    Sw,
+    Hw,
+    Watch(WatchKind),
+}
@@ -16,6 +16,7 @@ mod state;
 pub struct GdbSession {
     req: Vec<u8>,
     res: Vec<u8>,
+    last: Vec<u8>,
     state: SessionState,
 }
 
@@ -30,6 +31,31 @@ impl GdbSession {
 
         ClientDispatcher::new(self, h)
     }
+
+    /// Notify the session that the target has been stopped.
+    ///
+    /// Returns a stop reply to send to the client if it is waiting for it.
+    pub fn stop<H: GdbHandler>(&mut self, h: &mut H) -> Option<impl AsRef<[u8]> + '_> {
+        let state = &mut self.state;
+        let res = &mut self.res;
+        let last = &mut self.last;
+
+        if !state.waiting_stop() {
+            return None;
+        }
+
+        let (tid, reason) = h.stop_reason()?;
+        let start = res.len();
+
+        state.set_waiting_stop(false);
+        res.push(b'$');
+
+        self::client::write_stop(state, h, tid, reason, res);
+        self::client::end_packet(res, start + 1);
+        self::client::save_reply(state, last, &res[start..]);
+
+        Some(res.drain(..))
+    }
 }
 
 /// Provides method to dispatch debug operations.
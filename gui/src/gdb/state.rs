@@ -1,8 +1,18 @@
+use std::num::NonZero;
+
+/// Maximum size of a packet that we accept and send. This does not include the framing.
+pub const PACKET_SIZE: usize = 0x4000;
+
 /// Contains states for a GDB remote session.
 #[derive(Default)]
 pub struct SessionState {
     no_ack: Option<bool>,
     thread_suffix_supported: bool,
+    list_threads_in_stop_reply: bool,
+    swbreak: bool,
+    hwbreak: bool,
+    thread: Option<NonZero<usize>>,
+    waiting_stop: bool,
 }
 
 impl SessionState {
@@ -10,6 +20,40 @@ impl SessionState {
         self.no_ack
     }
 
+    pub fn thread_suffix_supported(&self) -> bool {
+        self.thread_suffix_supported
+    }
+
+    pub fn list_threads_in_stop_reply(&self) -> bool {
+        self.list_threads_in_stop_reply
+    }
+
+    pub fn swbreak(&self) -> bool {
+        self.swbreak
+    }
+
+    pub fn hwbreak(&self) -> bool {
+        self.hwbreak
+    }
+
+    /// Returns the thread selected by `Hg` packet.
+    pub fn thread(&self) -> Option<NonZero<usize>> {
+        self.thread
+    }
+
+    pub fn set_thread(&mut self, v: Option<NonZero<usize>>) {
+        self.thread = v;
+    }
+
+    /// Returns `true` if the client is waiting for a stop reply.
+    pub fn waiting_stop(&self) -> bool {
+        self.waiting_stop
+    }
+
+    pub fn set_waiting_stop(&mut self, v: bool) {
+        self.waiting_stop = v;
+    }
+
     pub fn parse_start_no_ack_mode(&mut self, res: &mut Vec<u8>) {
         self.no_ack = Some(false);
 
@@ -22,7 +66,9 @@ impl SessionState {
 
     pub fn parse_supported(&mut self, req: &[u8], res: &mut Vec<u8>) {
         // Push features that we always supported.
-        res.extend_from_slice(b"QStartNoAckMode+");
+        res.extend_from_slice(
+            format!("PacketSize={PACKET_SIZE:x};QStartNoAckMode+;vContSupported+").as_bytes(),
+        );
 
         // Parse GDB features.
         let req = match req.strip_prefix(b":") {
@@ -51,15 +97,19 @@ impl SessionState {
             } else if let Some(v) = feat.strip_prefix(b"swbreak") {
                 if v == b"+" {
                     if cfg!(target_arch = "aarch64") || cfg!(target_arch = "x86_64") {
+                        self.swbreak = true;
                         res.extend_from_slice(b";swbreak+");
                     } else {
                         todo!()
                     }
                 }
-            } else if let Some(_) = feat.strip_prefix(b"hwbreak") {
-                // TODO: Implement this.
+            } else if let Some(v) = feat.strip_prefix(b"hwbreak") {
+                if v == b"+" {
+                    self.hwbreak = true;
+                    res.extend_from_slice(b";hwbreak+");
+                }
             } else {
-                todo!("{}", String::from_utf8_lossy(feat));
+                // The client will assume we don't support the features that we did not reply.
             }
         }
     }
@@ -69,4 +119,11 @@ impl SessionState {
 
         res.extend_from_slice(b"OK");
     }
+
+    pub fn parse_list_threads_in_stop_reply(&mut self, res: &mut Vec<u8>) {
+        // https://lldb.llvm.org/resources/lldbgdbremote.html#qlistthreadsinstopreply
+        self.list_threads_in_stop_reply = true;
+
+        res.extend_from_slice(b"OK");
+    }
 }
@@ -1,6 +1,6 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
-use super::Console;
-use crate::hw::{DeviceContext, MmioError, read_ptr, read_u8, read_usize};
+use super::{Console, Pending};
+use crate::hw::{DeviceContext, MmioError, read_ptr, read_u8, read_usize, write_usize};
 use crate::util::channel::Sender;
 use config::{ConsoleMemory, ConsoleType};
 use hv::{Cpu, CpuExit, CpuIo, Hypervisor};
@@ -12,24 +12,52 @@ use thiserror::Error;
 /// Implementation of [`DeviceContext`].
 pub struct Context<'a, H> {
     dev: &'a Console,
+    cpu: usize,
     hv: &'a H,
     logs: &'a Sender<(ConsoleType, String)>,
     msg_len: Option<NonZero<usize>>,
     msg: Vec<u8>,
+    input_len: Option<NonZero<usize>>,
+    input_read: usize,
 }
 
 impl<'a, H> Context<'a, H> {
-    pub fn new(dev: &'a Console, hv: &'a H, logs: &'a Sender<(ConsoleType, String)>) -> Self {
+    pub fn new(
+        dev: &'a Console,
+        cpu: usize,
+        hv: &'a H,
+        logs: &'a Sender<(ConsoleType, String)>,
+        pending: Pending,
+    ) -> Self {
         Self {
             dev,
+            cpu,
             hv,
             logs,
-            msg_len: None,
-            msg: Vec::new(),
+            msg_len: pending.msg_len,
+            msg: pending.msg,
+            input_len: None,
+            input_read: 0,
         }
     }
 }
 
+impl<H> Drop for Context<'_, H> {
+    fn drop(&mut self) {
+        // Keep incomplete message on the device so it can be included in a snapshot.
+        if self.msg_len.is_none() && self.msg.is_empty() {
+            return;
+        }
+
+        let pending = Pending {
+            msg_len: self.msg_len.take(),
+            msg: std::mem::take(&mut self.msg),
+        };
+
+        self.dev.pending.lock().unwrap().insert(self.cpu, pending);
+    }
+}
+
 impl<H: Hypervisor, C: Cpu> DeviceContext<C> for Context<'_, H> {
     fn mmio(
         &mut self,
@@ -72,6 +100,27 @@ impl<H: Hypervisor, C: Cpu> DeviceContext<C> for Context<'_, H> {
 
             self.logs.send((ty, msg.to_owned()));
             self.msg.clear();
+        } else if off == offset_of!(ConsoleMemory, input_len) {
+            self.input_len = read_usize(exit)
+                .map_err(|e| ExecError::ReadFailed(off, e))
+                .and_then(|v| NonZero::new(v).ok_or(ExecError::InvalidLen))
+                .map(Some)?;
+        } else if off == offset_of!(ConsoleMemory, input_addr) {
+            let len = self.input_len.take().ok_or(ExecError::InvalidSequence)?;
+            let mut mem =
+                read_ptr(exit, len, self.hv).map_err(|e| ExecError::ReadFailed(off, e))?;
+            let buf = unsafe { std::slice::from_raw_parts_mut(mem.as_mut_ptr(), mem.len().get()) };
+            let mut input = self.dev.input.lock().unwrap();
+            let len = buf.len().min(input.len());
+
+            for (dst, src) in buf.iter_mut().zip(input.drain(..len)) {
+                *dst = src;
+            }
+
+            self.input_read = len;
+        } else if off == offset_of!(ConsoleMemory, input_read) {
+            write_usize(exit, std::mem::take(&mut self.input_read))
+                .map_err(|e| ExecError::WriteFailed(off, e))?;
         } else {
             return Err(Box::new(ExecError::UnknownField(off)));
         }
@@ -89,6 +138,9 @@ enum ExecError {
     #[error("couldn't read data for offset {0:#x}")]
     ReadFailed(usize, #[source] MmioError),
 
+    #[error("couldn't write data for offset {0:#x}")]
+    WriteFailed(usize, #[source] MmioError),
+
     #[error("invalid message length")]
     InvalidLen,
 
@@ -1,10 +1,13 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 use self::context::Context;
-use super::{Device, DeviceContext};
-use crate::util::channel::Sender;
-use config::{ConsoleMemory, ConsoleType};
+use super::{ContextArgs, Device, DeviceContext};
+use config::{ConsoleMemory, DeviceType};
 use hv::Hypervisor;
+use std::collections::{BTreeMap, VecDeque};
+use std::error::Error;
 use std::num::NonZero;
+use std::sync::Mutex;
+use thiserror::Error;
 
 mod context;
 
@@ -12,32 +15,50 @@ mod context;
 pub struct Console {
     addr: usize,
     len: NonZero<usize>,
+    pending: Mutex<BTreeMap<usize, Pending>>,
+    input: Mutex<VecDeque<u8>>,
 }
 
 impl Console {
+    /// Maximum number of bytes from the host that was not read by the kernel.
+    const INPUT_CAPACITY: usize = 0x10000;
+
     pub fn new(addr: usize, block_size: NonZero<usize>) -> Self {
         let len = size_of::<ConsoleMemory>()
             .checked_next_multiple_of(block_size.get())
             .and_then(NonZero::new)
             .unwrap();
 
-        Self { addr, len }
+        Self {
+            addr,
+            len,
+            pending: Mutex::default(),
+            input: Mutex::default(),
+        }
     }
 
-    pub fn create_context<'a, H: Hypervisor>(
-        &'a self,
-        hv: &'a H,
-        logs: &'a Sender<(ConsoleType, String)>,
-    ) -> Box<dyn DeviceContext<H::Cpu<'a>> + 'a> {
-        Box::new(Context::new(self, hv, logs))
+    /// Queue `data` to be read by the kernel and returns the number of bytes that was queued.
+    ///
+    /// The returned value will be less than the length of `data` if the input buffer is full.
+    pub fn push_input(&self, data: &[u8]) -> usize {
+        let mut input = self.input.lock().unwrap();
+        let len = data.len().min(Self::INPUT_CAPACITY - input.len());
+
+        input.extend(&data[..len]);
+
+        len
     }
 }
 
-impl Device for Console {
+impl<H: Hypervisor> Device<H> for Console {
     fn name(&self) -> &str {
         "Virtual Console"
     }
 
+    fn ty(&self) -> DeviceType {
+        DeviceType::Console
+    }
+
     fn addr(&self) -> usize {
         self.addr
     }
@@ -45,4 +66,159 @@ impl Device for Console {
     fn len(&self) -> NonZero<usize> {
         self.len
     }
+
+    fn create_context<'a>(
+        &'a self,
+        args: &ContextArgs<'a, H>,
+    ) -> Box<dyn DeviceContext<H::Cpu<'a>> + 'a> {
+        let pending = self
+            .pending
+            .lock()
+            .unwrap()
+            .remove(&args.cpu)
+            .unwrap_or_default();
+
+        Box::new(Context::new(self, args.cpu, args.hv, args.logs, pending))
+    }
+
+    fn save(&self) -> Vec<u8> {
+        let pending = self.pending.lock().unwrap();
+        let input = self.input.lock().unwrap();
+        let mut data = Vec::new();
+
+        // Input that was not read by the kernel.
+        data.extend_from_slice(&u64::try_from(input.len()).unwrap().to_le_bytes());
+        data.extend(input.iter());
+
+        // Incomplete messages.
+        for (&cpu, p) in pending.iter() {
+            let len = p.msg_len.map(|v| v.get()).unwrap_or(0);
+
+            data.extend_from_slice(&u64::try_from(cpu).unwrap().to_le_bytes());
+            data.extend_from_slice(&u64::try_from(len).unwrap().to_le_bytes());
+            data.extend_from_slice(&u64::try_from(p.msg.len()).unwrap().to_le_bytes());
+            data.extend_from_slice(&p.msg);
+        }
+
+        data
+    }
+
+    fn restore(&self, mut data: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let len = read_usize(&mut data)?;
+
+        if len > Self::INPUT_CAPACITY {
+            return Err(Box::new(RestoreError::InvalidData));
+        }
+
+        let (input, rest) = data
+            .split_at_checked(len)
+            .ok_or(RestoreError::InvalidData)?;
+        let mut pending = BTreeMap::new();
+
+        data = rest;
+
+        while !data.is_empty() {
+            let cpu = read_usize(&mut data)?;
+            let msg_len = NonZero::new(read_usize(&mut data)?);
+            let len = read_usize(&mut data)?;
+            let (msg, rest) = data
+                .split_at_checked(len)
+                .ok_or(RestoreError::InvalidData)?;
+
+            pending.insert(
+                cpu,
+                Pending {
+                    msg_len,
+                    msg: msg.to_vec(),
+                },
+            );
+
+            data = rest;
+        }
+
+        *self.pending.lock().unwrap() = pending;
+        *self.input.lock().unwrap() = input.iter().copied().collect();
+
+        Ok(())
+    }
+}
+
+fn read_usize(data: &mut &[u8]) -> Result<usize, RestoreError> {
+    let (v, rest) = data.split_first_chunk().ok_or(RestoreError::InvalidData)?;
+
+    *data = rest;
+
+    usize::try_from(u64::from_le_bytes(*v)).map_err(|_| RestoreError::InvalidData)
+}
+
+/// Incomplete message from a CPU that was stopped.
+#[derive(Default)]
+struct Pending {
+    msg_len: Option<NonZero<usize>>,
+    msg: Vec<u8>,
+}
+
+/// Represents an error when [`Console::restore()`] fails.
+#[derive(Debug, Error)]
+enum RestoreError {
+    #[error("invalid data")]
+    InvalidData,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn input() {
+        let con = Console::new(0, NonZero::new(0x4000).unwrap());
+        let data = vec![0u8; Console::INPUT_CAPACITY - 1];
+
+        assert_eq!(con.push_input(&data), data.len());
+        assert_eq!(con.push_input(b"ab"), 1);
+        assert_eq!(con.push_input(b"c"), 0);
+
+        con.input.lock().unwrap().pop_front();
+
+        assert_eq!(con.push_input(b"d"), 1);
+    }
+
+    #[test]
+    fn snapshot() {
+        let con = Console::new(0, NonZero::new(0x4000).unwrap());
+
+        con.push_input(b"abc");
+        con.pending.lock().unwrap().insert(
+            1,
+            Pending {
+                msg_len: NonZero::new(5),
+                msg: b"he".to_vec(),
+            },
+        );
+
+        let restored = Console::new(0, NonZero::new(0x4000).unwrap());
+
+        round_trip(&new_hv(), &con, &restored);
+
+        let input = restored.input.lock().unwrap();
+        let pending = restored.pending.lock().unwrap();
+        let p = pending.get(&1).unwrap();
+
+        assert!(input.iter().eq(b"abc"));
+        assert_eq!(p.msg_len, NonZero::new(5));
+        assert_eq!(p.msg, b"he");
+    }
+
+    fn round_trip<H: Hypervisor>(_: &H, src: &Console, dst: &Console) {
+        let data = <Console as Device<H>>::save(src);
+
+        <Console as Device<H>>::restore(dst, &data).unwrap();
+    }
+
+    fn new_hv() -> impl Hypervisor {
+        let ram = NonZero::new(0x100000).unwrap();
+        let page = NonZero::new(0x4000).unwrap();
+
+        hv::new_emulator(1, ram, page, false).unwrap()
+    }
 }
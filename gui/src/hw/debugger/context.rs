@@ -0,0 +1,105 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+use super::{Debugger, GuestTrap};
+use crate::hw::{DeviceContext, MmioError, lock_vaddr, read_usize, write_usize};
+use config::DebuggerMemory;
+use hv::{Cpu, CpuExit, CpuIo, Hypervisor};
+use std::error::Error;
+use std::mem::offset_of;
+use std::num::NonZero;
+use thiserror::Error;
+
+/// Implementation of [`DeviceContext`].
+pub struct Context<'a, H> {
+    dev: &'a Debugger,
+    cpu: usize,
+    hv: &'a H,
+    debug: bool,
+    trap: Option<usize>,
+    frame_len: Option<NonZero<usize>>,
+    handled: Option<bool>,
+}
+
+impl<'a, H> Context<'a, H> {
+    pub fn new(dev: &'a Debugger, cpu: usize, hv: &'a H, debug: bool) -> Self {
+        Self {
+            dev,
+            cpu,
+            hv,
+            debug,
+            trap: None,
+            frame_len: None,
+            handled: None,
+        }
+    }
+}
+
+impl<H: Hypervisor, C: Cpu> DeviceContext<C> for Context<'_, H> {
+    fn mmio(
+        &mut self,
+        exit: &mut <C::Exit<'_> as CpuExit>::Io,
+    ) -> Result<Option<bool>, Box<dyn Error + Send + Sync>> {
+        // Check field.
+        let off = exit.addr() - self.dev.addr;
+
+        if off == offset_of!(DebuggerMemory, trap) {
+            self.trap = Some(read_usize(exit).map_err(|e| ExecError::ReadFailed(off, e))?);
+        } else if off == offset_of!(DebuggerMemory, frame_len) {
+            self.frame_len = read_usize(exit)
+                .map_err(|e| ExecError::ReadFailed(off, e))
+                .and_then(|v| NonZero::new(v).ok_or(ExecError::InvalidLen))
+                .map(Some)?;
+        } else if off == offset_of!(DebuggerMemory, frame_addr) {
+            let num = self.trap.take().ok_or(ExecError::InvalidSequence)?;
+            let len = self.frame_len.take().ok_or(ExecError::InvalidSequence)?;
+
+            // Let the kernel handle the trap if no debugger is attached.
+            self.handled = Some(self.debug);
+
+            if !self.debug {
+                return Ok(None);
+            }
+
+            // Read the frame.
+            let addr = read_usize(exit).map_err(|e| ExecError::ReadFailed(off, e))?;
+            let data = lock_vaddr(exit.cpu(), addr, len, self.hv)
+                .map_err(|e| ExecError::ReadFailed(off, e))?;
+            let frame = unsafe { std::slice::from_raw_parts(data.as_ptr(), data.len().get()) };
+
+            // The CPU thread will pick this up before entering the VM again.
+            let trap = GuestTrap {
+                num,
+                addr,
+                frame: frame.to_vec(),
+            };
+
+            self.dev.traps.lock().unwrap().insert(self.cpu, trap);
+        } else if off == offset_of!(DebuggerMemory, handled) {
+            let v = self.handled.take().ok_or(ExecError::InvalidSequence)?;
+
+            write_usize(exit, v.into()).map_err(|e| ExecError::WriteFailed(off, e))?;
+        } else {
+            return Err(Box::new(ExecError::UnknownField(off)));
+        }
+
+        Ok(None)
+    }
+}
+
+/// Represents an error when [`Context::mmio()`] fails.
+#[derive(Debug, Error)]
+enum ExecError {
+    #[error("unknown field at offset {0:#x}")]
+    UnknownField(usize),
+
+    #[error("couldn't read data for offset {0:#x}")]
+    ReadFailed(usize, #[source] MmioError),
+
+    #[error("couldn't write data for offset {0:#x}")]
+    WriteFailed(usize, #[source] MmioError),
+
+    #[error("invalid frame length")]
+    InvalidLen,
+
+    #[error("invalid operation sequence")]
+    InvalidSequence,
+}
@@ -0,0 +1,74 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+use self::context::Context;
+use super::{ContextArgs, Device, DeviceContext};
+use config::{DebuggerMemory, DeviceType};
+use hv::Hypervisor;
+use std::collections::BTreeMap;
+use std::num::NonZero;
+use std::sync::Mutex;
+
+mod context;
+
+/// Virtual device for the kernel to report a trap to the debugger.
+///
+/// This allows debugging the kernel on the hypervisor that cannot reliably report debug exits.
+pub struct Debugger {
+    addr: usize,
+    len: NonZero<usize>,
+    traps: Mutex<BTreeMap<usize, GuestTrap>>,
+}
+
+impl Debugger {
+    pub fn new(addr: usize, block_size: NonZero<usize>) -> Self {
+        let len = size_of::<DebuggerMemory>()
+            .checked_next_multiple_of(block_size.get())
+            .and_then(NonZero::new)
+            .unwrap();
+
+        Self {
+            addr,
+            len,
+            traps: Mutex::default(),
+        }
+    }
+
+    /// Returns the trap that was reported by `cpu` and has not been handled.
+    pub fn take_trap(&self, cpu: usize) -> Option<GuestTrap> {
+        self.traps.lock().unwrap().remove(&cpu)
+    }
+}
+
+impl<H: Hypervisor> Device<H> for Debugger {
+    fn name(&self) -> &str {
+        "Debugger"
+    }
+
+    fn ty(&self) -> DeviceType {
+        DeviceType::Debugger
+    }
+
+    fn addr(&self) -> usize {
+        self.addr
+    }
+
+    fn len(&self) -> NonZero<usize> {
+        self.len
+    }
+
+    fn create_context<'a>(
+        &'a self,
+        args: &ContextArgs<'a, H>,
+    ) -> Box<dyn DeviceContext<H::Cpu<'a>> + 'a> {
+        Box::new(Context::new(self, args.cpu, args.hv, args.debug))
+    }
+}
+
+/// Trap reported by the kernel.
+#[derive(Debug, Clone)]
+pub struct GuestTrap {
+    pub num: usize,
+    /// Virtual address of `TrapFrame` on the kernel.
+    pub addr: usize,
+    /// Raw content of `TrapFrame` on the kernel.
+    pub frame: Vec<u8>,
+}
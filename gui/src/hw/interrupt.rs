@@ -0,0 +1,142 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+use hv::{Cpu, CpuKicker};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Condvar, Mutex};
+
+/// Routes interrupts raised by virtual devices to the target vCPU.
+///
+/// [`Interrupts::raise()`] can be called from any thread. The interrupt will be injected by the
+/// thread that run the target vCPU before it enter the VM again.
+#[derive(Default)]
+pub struct Interrupts {
+    cpus: Mutex<BTreeMap<usize, CpuInterrupts>>,
+    wakeup: Condvar,
+}
+
+impl Interrupts {
+    /// Queue `vector` for `cpu` and force it to exit the VM if it is running.
+    pub fn raise(&self, cpu: usize, vector: u8) {
+        let mut cpus = self.cpus.lock().unwrap();
+        let s = cpus.entry(cpu).or_default();
+
+        s.pending.push_back(vector);
+
+        if let Some(k) = &s.kicker {
+            k.kick();
+        }
+
+        self.wakeup.notify_all();
+    }
+
+    /// Force all vCPUs that are running to exit the VM and wake up the halted ones.
+    pub fn kick_all(&self) {
+        for s in self.cpus.lock().unwrap().values_mut() {
+            if let Some(k) = &s.kicker {
+                k.kick();
+                s.kicked = true;
+            }
+        }
+
+        self.wakeup.notify_all();
+    }
+
+    /// Block the calling thread until `cpu` has a pending interrupt or [`Interrupts::kick_all()`]
+    /// is called.
+    ///
+    /// This must be called on the thread that run `cpu` when it was halted.
+    pub fn wait(&self, cpu: usize) {
+        let mut cpus = self.cpus.lock().unwrap();
+
+        loop {
+            let s = cpus.entry(cpu).or_default();
+
+            if !s.pending.is_empty() || std::mem::take(&mut s.kicked) {
+                break;
+            }
+
+            cpus = self.wakeup.wait(cpus).unwrap();
+        }
+    }
+
+    /// Register `kicker` for `cpu` until the returned [`Attached`] is dropped.
+    pub fn attach(&self, cpu: usize, kicker: impl CpuKicker) -> Attached<'_> {
+        let mut cpus = self.cpus.lock().unwrap();
+        let s = cpus.entry(cpu).or_default();
+
+        s.kicker = Some(Box::new(kicker));
+        s.window = false;
+
+        // Make sure the interrupts that was raised before this get delivered.
+        if !s.pending.is_empty() {
+            s.kicker.as_ref().unwrap().kick();
+        }
+
+        Attached { intrs: self, cpu }
+    }
+
+    /// Inject a pending interrupt into `cpu` if it can accept one or request an interrupt window
+    /// otherwise.
+    ///
+    /// This must be called on the thread that run `cpu` before enter the VM.
+    pub fn deliver<C: Cpu>(&self, cpu: &mut C) -> Result<(), C::InterruptErr> {
+        let mut cpus = self.cpus.lock().unwrap();
+        let s = match cpus.get_mut(&cpu.id()) {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+
+        if s.pending.is_empty() {
+            if s.window {
+                cpu.set_interrupt_window(false);
+                s.window = false;
+            }
+
+            return Ok(());
+        }
+
+        // Inject as many as the CPU can accept.
+        while cpu.interruptible() {
+            let v = match s.pending.pop_front() {
+                Some(v) => v,
+                None => break,
+            };
+
+            cpu.inject(v)?;
+        }
+
+        // Ask the CPU to exit when it can accept the next interrupt.
+        let window = !s.pending.is_empty();
+
+        if window != s.window {
+            cpu.set_interrupt_window(window);
+            s.window = window;
+        }
+
+        Ok(())
+    }
+}
+
+/// RAII struct to unregister a [`CpuKicker`] from [`Interrupts`].
+pub struct Attached<'a> {
+    intrs: &'a Interrupts,
+    cpu: usize,
+}
+
+impl Drop for Attached<'_> {
+    fn drop(&mut self) {
+        let mut cpus = self.intrs.cpus.lock().unwrap();
+
+        if let Some(s) = cpus.get_mut(&self.cpu) {
+            s.kicker = None;
+        }
+    }
+}
+
+/// Interrupt states of a vCPU.
+#[derive(Default)]
+struct CpuInterrupts {
+    pending: VecDeque<u8>,
+    kicker: Option<Box<dyn CpuKicker>>,
+    window: bool,
+    kicked: bool,
+}
@@ -1,7 +1,15 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 pub use self::console::*;
+pub use self::debugger::*;
+pub use self::interrupt::*;
+pub use self::storage::*;
+pub use self::timer::*;
 pub use self::vmm::*;
 
+use crate::data::Part;
+use crate::util::channel::Sender;
+use crate::vmm::RamPolicy;
+use config::{ConsoleType, DeviceType};
 use hv::{Cpu, CpuExit, CpuIo, Hypervisor, IoBuf, LockedMem};
 use std::collections::BTreeMap;
 use std::error::Error;
@@ -10,20 +18,34 @@ use std::sync::Arc;
 use thiserror::Error;
 
 mod console;
+mod debugger;
+mod interrupt;
+mod storage;
+mod timer;
 mod vmm;
 
-pub fn setup_devices(start_addr: usize, block_size: NonZero<usize>) -> DeviceTree {
+pub fn setup_devices<H: Hypervisor>(
+    start_addr: usize,
+    block_size: NonZero<usize>,
+    part: &Part,
+    writable: bool,
+) -> DeviceTree<H> {
+    let interrupts = Arc::new(Interrupts::default());
     let mut b = MapBuilder {
         map: BTreeMap::new(),
         next: start_addr,
     };
 
-    let vmm = b.push(|addr| Vmm::new(addr, block_size));
+    b.push(|addr| Vmm::new(addr, block_size));
     let console = b.push(|addr| Console::new(addr, block_size));
+    let debugger = b.push(|addr| Debugger::new(addr, block_size));
+    b.push(|addr| Timer::new(addr, block_size, interrupts.clone()));
+    b.push(|addr| Storage::new(addr, block_size, part.clone(), writable));
 
     DeviceTree {
-        vmm,
         console,
+        debugger,
+        interrupts,
         map: b.map,
     }
 }
@@ -36,6 +58,17 @@ fn read_u8(exit: &mut impl CpuIo) -> Result<u8, MmioError> {
     }
 }
 
+fn write_u8(exit: &mut impl CpuIo, v: u8) -> Result<(), MmioError> {
+    match exit.buffer() {
+        IoBuf::Read([b]) => {
+            *b = v;
+            Ok(())
+        }
+        IoBuf::Read(_) => Err(MmioError::InvalidData),
+        _ => Err(MmioError::InvalidOperation),
+    }
+}
+
 fn read_usize(exit: &mut impl CpuIo) -> Result<usize, MmioError> {
     // Get data.
     let IoBuf::Write(data) = exit.buffer() else {
@@ -48,6 +81,24 @@ fn read_usize(exit: &mut impl CpuIo) -> Result<usize, MmioError> {
         .map_err(|_| MmioError::InvalidData)
 }
 
+fn write_usize(exit: &mut impl CpuIo, v: usize) -> Result<(), MmioError> {
+    // Get buffer.
+    let IoBuf::Read(buf) = exit.buffer() else {
+        return Err(MmioError::InvalidOperation);
+    };
+
+    // Write data.
+    let v = v.to_ne_bytes();
+
+    if buf.len() != v.len() {
+        return Err(MmioError::InvalidData);
+    }
+
+    buf.copy_from_slice(&v);
+
+    Ok(())
+}
+
 fn read_ptr<'a, H: Hypervisor>(
     exit: &mut impl CpuIo,
     len: NonZero<usize>,
@@ -64,12 +115,19 @@ fn read_ptr<'a, H: Hypervisor>(
         .map(usize::from_ne_bytes)
         .map_err(|_| MmioError::InvalidData)?;
 
-    let paddr = exit
-        .cpu()
+    lock_vaddr(exit.cpu(), vaddr, len, hv)
+}
+
+fn lock_vaddr<'a, H: Hypervisor>(
+    cpu: &impl Cpu,
+    vaddr: usize,
+    len: NonZero<usize>,
+    hv: &'a H,
+) -> Result<LockedMem<'a>, MmioError> {
+    let paddr = cpu
         .translate(vaddr)
         .map_err(|e| MmioError::TranslateVaddrFailed(vaddr, Box::new(e)))?;
 
-    // Get data.
     hv.ram()
         .lock(paddr, len)
         .ok_or(MmioError::InvalidAddr { vaddr, paddr })
@@ -78,37 +136,77 @@ fn read_ptr<'a, H: Hypervisor>(
 /// Contains all virtual devices (except RAM) for the VM.
 ///
 /// All devices guarantee to not overlapped.
-pub struct DeviceTree {
-    vmm: Arc<Vmm>,
+pub struct DeviceTree<H> {
     console: Arc<Console>,
-    map: BTreeMap<usize, Arc<dyn Device>>,
+    debugger: Arc<Debugger>,
+    interrupts: Arc<Interrupts>,
+    map: BTreeMap<usize, Arc<dyn Device<H>>>,
 }
 
-impl DeviceTree {
-    pub fn vmm(&self) -> &Vmm {
-        self.vmm.as_ref()
-    }
-
+impl<H> DeviceTree<H> {
     pub fn console(&self) -> &Console {
         self.console.as_ref()
     }
 
+    pub fn debugger(&self) -> &Debugger {
+        self.debugger.as_ref()
+    }
+
+    pub fn interrupts(&self) -> &Interrupts {
+        self.interrupts.as_ref()
+    }
+}
+
+impl<H: Hypervisor> DeviceTree<H> {
     /// Returns iterator ordered by physical address.
-    pub fn all(&self) -> impl Iterator<Item = (usize, &dyn Device)> + '_ {
+    pub fn all(&self) -> impl Iterator<Item = (usize, &dyn Device<H>)> + '_ {
         self.map.iter().map(|(addr, dev)| (*addr, dev.as_ref()))
     }
 }
 
 /// Virtual device that has a physical address in the virtual machine.
-pub trait Device: Send + Sync {
+pub trait Device<H: Hypervisor>: Send + Sync {
     /// Display name of this device.
     fn name(&self) -> &str;
 
+    /// Type of this device to report to the kernel.
+    fn ty(&self) -> DeviceType;
+
     /// Physical address in the virtual machine.
     fn addr(&self) -> usize;
 
     /// Total size of device memory, in bytes.
     fn len(&self) -> NonZero<usize>;
+
+    /// Create a context for a CPU to execute operations on this device.
+    fn create_context<'a>(
+        &'a self,
+        args: &ContextArgs<'a, H>,
+    ) -> Box<dyn DeviceContext<H::Cpu<'a>> + 'a>;
+
+    /// Serialize the current state of this device for a snapshot.
+    ///
+    /// This will be called only when all CPUs are stopped.
+    fn save(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Restore the state produced by [`Device::save()`].
+    fn restore(&self, data: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let _ = data;
+        Ok(())
+    }
+}
+
+/// Contains everything a device may need to create a [`DeviceContext`].
+pub struct ContextArgs<'a, H> {
+    pub cpu: usize,
+    pub hv: &'a H,
+    pub ram: &'a RamPolicy,
+    pub logs: &'a Sender<(ConsoleType, String)>,
+    pub start: &'a Sender<StartCpu>,
+    /// `true` if a debugger is attached to the CPU.
+    pub debug: bool,
 }
 
 /// Context for a CPU to execute operations on a virtual device.
@@ -133,13 +231,13 @@ pub trait DeviceContext<C: Cpu> {
 }
 
 /// Struct to build a map of virtual device.
-struct MapBuilder {
-    map: BTreeMap<usize, Arc<dyn Device>>,
+struct MapBuilder<H> {
+    map: BTreeMap<usize, Arc<dyn Device<H>>>,
     next: usize,
 }
 
-impl MapBuilder {
-    fn push<T: Device + 'static>(&mut self, f: impl FnOnce(usize) -> T) -> Arc<T> {
+impl<H: Hypervisor> MapBuilder<H> {
+    fn push<T: Device<H> + 'static>(&mut self, f: impl FnOnce(usize) -> T) -> Arc<T> {
         let d = Arc::new(f(self.next));
 
         assert!(self.map.insert(self.next, d.clone()).is_none());
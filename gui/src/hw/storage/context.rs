@@ -0,0 +1,176 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+use super::Storage;
+use crate::hw::{DeviceContext, MmioError, lock_vaddr, read_u8, read_usize, write_u8, write_usize};
+use config::{StorageCmd, StorageMemory, StorageMode, StorageStatus};
+use hv::{Cpu, CpuExit, CpuIo, Hypervisor, LockedMem};
+use std::error::Error;
+use std::mem::offset_of;
+use std::num::NonZero;
+use thiserror::Error;
+
+/// Implementation of [`DeviceContext`].
+pub struct Context<'a, H> {
+    dev: &'a Storage,
+    hv: &'a H,
+    handle: usize,
+    offset: usize,
+    mode: StorageMode,
+    buf_len: usize,
+    buf_addr: usize,
+    status: StorageStatus,
+    result: usize,
+}
+
+impl<'a, H: Hypervisor> Context<'a, H> {
+    pub fn new(dev: &'a Storage, hv: &'a H) -> Self {
+        Self {
+            dev,
+            hv,
+            handle: 0,
+            offset: 0,
+            mode: StorageMode::Read,
+            buf_len: 0,
+            buf_addr: 0,
+            status: StorageStatus::Ok,
+            result: 0,
+        }
+    }
+
+    fn exec(&self, cmd: StorageCmd, buf: Option<LockedMem>) -> Result<usize, StorageStatus> {
+        // Get buffer. The VM is blocked on this MMIO so no one else can access this memory.
+        let mut buf = buf;
+        let data = buf
+            .as_mut()
+            .map(|m| unsafe { std::slice::from_raw_parts_mut(m.as_mut_ptr(), m.len().get()) });
+
+        match cmd {
+            StorageCmd::Open => {
+                let path = std::str::from_utf8(data.unwrap())
+                    .map_err(|_| StorageStatus::InvalidArgument)?;
+
+                self.dev.open(path, self.mode)
+            }
+            StorageCmd::Stat => {
+                let stat = self.dev.handle(self.handle)?.stat()?;
+
+                match buf.unwrap().put(0, stat) {
+                    Ok(None) => Ok(0),
+                    Ok(Some(_)) => Err(StorageStatus::BufferTooSmall),
+                    Err(_) => Err(StorageStatus::InvalidArgument),
+                }
+            }
+            StorageCmd::Read => {
+                let h = self.dev.handle(self.handle)?;
+
+                h.read(self.offset, data.unwrap())
+            }
+            StorageCmd::Write => {
+                let h = self.dev.handle(self.handle)?;
+
+                h.write(self.offset, data.unwrap())
+            }
+            StorageCmd::ReadDir => {
+                let h = self.dev.handle(self.handle)?;
+                let name = match h.read_dir(self.offset)? {
+                    Some(v) => v.as_bytes(),
+                    None => return Ok(0),
+                };
+
+                // Copy the name.
+                let data = data.unwrap();
+                let dst = data
+                    .get_mut(..name.len())
+                    .ok_or(StorageStatus::BufferTooSmall)?;
+
+                dst.copy_from_slice(name);
+
+                Ok(name.len())
+            }
+            StorageCmd::Close => self.dev.close(self.handle).map(|_| 0),
+        }
+    }
+}
+
+impl<H: Hypervisor, C: Cpu> DeviceContext<C> for Context<'_, H> {
+    fn mmio(
+        &mut self,
+        exit: &mut <C::Exit<'_> as CpuExit>::Io,
+    ) -> Result<Option<bool>, Box<dyn Error + Send + Sync>> {
+        // Check field.
+        let off = exit.addr() - self.dev.addr;
+
+        if off == offset_of!(StorageMemory, handle) {
+            self.handle = read_usize(exit).map_err(|e| ExecError::ReadFailed(off, e))?;
+        } else if off == offset_of!(StorageMemory, offset) {
+            self.offset = read_usize(exit).map_err(|e| ExecError::ReadFailed(off, e))?;
+        } else if off == offset_of!(StorageMemory, mode) {
+            let v = read_u8(exit).map_err(|e| ExecError::ReadFailed(off, e))?;
+
+            self.mode = StorageMode::try_from(v).map_err(|_| ExecError::UnknownMode(v))?;
+        } else if off == offset_of!(StorageMemory, buf_len) {
+            self.buf_len = read_usize(exit).map_err(|e| ExecError::ReadFailed(off, e))?;
+        } else if off == offset_of!(StorageMemory, buf_addr) {
+            self.buf_addr = read_usize(exit).map_err(|e| ExecError::ReadFailed(off, e))?;
+        } else if off == offset_of!(StorageMemory, command) {
+            let v = read_u8(exit).map_err(|e| ExecError::ReadFailed(off, e))?;
+            let cmd = StorageCmd::try_from(v).map_err(|_| ExecError::UnknownCommand(v))?;
+
+            // Lock the buffer.
+            let buf = match cmd {
+                StorageCmd::Close => None,
+                _ => {
+                    let len = NonZero::new(self.buf_len).ok_or(ExecError::InvalidLen)?;
+                    let mem = lock_vaddr(exit.cpu(), self.buf_addr, len, self.hv)
+                        .map_err(ExecError::LockBufferFailed)?;
+
+                    Some(mem)
+                }
+            };
+
+            // Execute.
+            match self.exec(cmd, buf) {
+                Ok(v) => {
+                    self.status = StorageStatus::Ok;
+                    self.result = v;
+                }
+                Err(e) => {
+                    self.status = e;
+                    self.result = 0;
+                }
+            }
+        } else if off == offset_of!(StorageMemory, status) {
+            write_u8(exit, self.status.into()).map_err(|e| ExecError::WriteFailed(off, e))?;
+        } else if off == offset_of!(StorageMemory, result) {
+            write_usize(exit, self.result).map_err(|e| ExecError::WriteFailed(off, e))?;
+        } else {
+            return Err(Box::new(ExecError::UnknownField(off)));
+        }
+
+        Ok(None)
+    }
+}
+
+/// Represents an error when [`Context::mmio()`] fails.
+#[derive(Debug, Error)]
+enum ExecError {
+    #[error("unknown field at offset {0:#x}")]
+    UnknownField(usize),
+
+    #[error("couldn't read data for offset {0:#x}")]
+    ReadFailed(usize, #[source] MmioError),
+
+    #[error("couldn't write data for offset {0:#x}")]
+    WriteFailed(usize, #[source] MmioError),
+
+    #[error("unknown command {0}")]
+    UnknownCommand(u8),
+
+    #[error("unknown mode {0}")]
+    UnknownMode(u8),
+
+    #[error("invalid buffer length")]
+    InvalidLen,
+
+    #[error("couldn't lock the buffer")]
+    LockBufferFailed(#[source] MmioError),
+}
@@ -0,0 +1,469 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+use self::context::Context;
+use super::{ContextArgs, Device, DeviceContext};
+use crate::data::Part;
+use config::{DeviceType, StorageMemory, StorageMode, StorageStat, StorageStatus};
+use hv::Hypervisor;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::num::NonZero;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+
+mod context;
+
+/// Virtual storage to access the firmware partitions.
+///
+/// Each partition is served from [`Part::data()`]. All files are read-only unless `writable` is
+/// `true`.
+pub struct Storage {
+    addr: usize,
+    len: NonZero<usize>,
+    part: Part,
+    writable: bool,
+    handles: Mutex<Handles>,
+}
+
+impl Storage {
+    pub fn new(addr: usize, block_size: NonZero<usize>, part: Part, writable: bool) -> Self {
+        let len = size_of::<StorageMemory>()
+            .checked_next_multiple_of(block_size.get())
+            .and_then(NonZero::new)
+            .unwrap();
+
+        Self {
+            addr,
+            len,
+            part,
+            writable,
+            handles: Mutex::new(Handles {
+                list: BTreeMap::new(),
+                next: 1,
+            }),
+        }
+    }
+
+    fn open(&self, path: &str, mode: StorageMode) -> Result<usize, StorageStatus> {
+        let h = Arc::new(self.open_handle(path, mode)?);
+        let mut handles = self.handles.lock().unwrap();
+        let id = handles.next;
+
+        handles.list.insert(id, h);
+        handles.next += 1;
+
+        Ok(id)
+    }
+
+    fn open_handle(&self, path: &str, mode: StorageMode) -> Result<Handle, StorageStatus> {
+        let writable = mode == StorageMode::ReadWrite;
+
+        if writable && !self.writable {
+            return Err(StorageStatus::ReadOnly);
+        }
+
+        // Only allow a relative path without any special component so it can't escape from the
+        // partition. The colon is rejected to prevent a drive letter or a stream name on Windows.
+        let mut coms = Path::new(path).components().map(|c| match c {
+            Component::Normal(v) => v.to_str().filter(|v| !v.contains(':')),
+            _ => None,
+        });
+
+        // Get partition.
+        let name = match coms.next() {
+            Some(Some(v)) if !v.contains('.') => v,
+            _ => return Err(StorageStatus::InvalidArgument),
+        };
+
+        let mut host = self.part.data(name);
+
+        if !host.is_dir() {
+            return Err(StorageStatus::NotFound);
+        }
+
+        // Get path on the host.
+        for com in coms {
+            host.push(com.ok_or(StorageStatus::InvalidArgument)?);
+        }
+
+        // Open.
+        let meta = std::fs::metadata(&host).map_err(io_status)?;
+        let data = if meta.is_dir() {
+            if writable {
+                return Err(StorageStatus::IsDirectory);
+            }
+
+            HandleData::Dir(list_dir(host)?)
+        } else {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(writable)
+                .open(host)
+                .map_err(io_status)?;
+
+            HandleData::File(Mutex::new(file))
+        };
+
+        Ok(Handle {
+            path: path.to_owned(),
+            mode,
+            data,
+        })
+    }
+
+    fn handle(&self, id: usize) -> Result<Arc<Handle>, StorageStatus> {
+        self.handles
+            .lock()
+            .unwrap()
+            .list
+            .get(&id)
+            .cloned()
+            .ok_or(StorageStatus::InvalidHandle)
+    }
+
+    fn close(&self, id: usize) -> Result<(), StorageStatus> {
+        self.handles
+            .lock()
+            .unwrap()
+            .list
+            .remove(&id)
+            .map(|_| ())
+            .ok_or(StorageStatus::InvalidHandle)
+    }
+}
+
+impl<H: Hypervisor> Device<H> for Storage {
+    fn name(&self) -> &str {
+        "Storage"
+    }
+
+    fn ty(&self) -> DeviceType {
+        DeviceType::Storage
+    }
+
+    fn addr(&self) -> usize {
+        self.addr
+    }
+
+    fn len(&self) -> NonZero<usize> {
+        self.len
+    }
+
+    fn create_context<'a>(
+        &'a self,
+        args: &ContextArgs<'a, H>,
+    ) -> Box<dyn DeviceContext<H::Cpu<'a>> + 'a> {
+        Box::new(Context::new(self, args.hv))
+    }
+
+    fn save(&self) -> Vec<u8> {
+        let handles = self.handles.lock().unwrap();
+        let mut data = Vec::new();
+
+        data.extend_from_slice(&u64::try_from(handles.next).unwrap().to_le_bytes());
+
+        for (&id, h) in &handles.list {
+            let path = h.path.as_bytes();
+
+            data.extend_from_slice(&u64::try_from(id).unwrap().to_le_bytes());
+            data.push(h.mode.into());
+            data.extend_from_slice(&u64::try_from(path.len()).unwrap().to_le_bytes());
+            data.extend_from_slice(path);
+        }
+
+        data
+    }
+
+    fn restore(&self, mut data: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // Re-open all handles from the snapshot.
+        let next = read_usize(&mut data)?;
+        let mut list = BTreeMap::new();
+
+        while !data.is_empty() {
+            let id = read_usize(&mut data)?;
+            let (&mode, rest) = data.split_first().ok_or(RestoreError::InvalidData)?;
+            let mode = StorageMode::try_from(mode).map_err(|_| RestoreError::InvalidData)?;
+
+            data = rest;
+
+            let len = read_usize(&mut data)?;
+            let (path, rest) = data
+                .split_at_checked(len)
+                .ok_or(RestoreError::InvalidData)?;
+            let path = std::str::from_utf8(path).map_err(|_| RestoreError::InvalidData)?;
+            let h = self
+                .open_handle(path, mode)
+                .map_err(|e| RestoreError::OpenFailed(path.to_owned(), e))?;
+
+            list.insert(id, Arc::new(h));
+            data = rest;
+        }
+
+        *self.handles.lock().unwrap() = Handles { list, next };
+
+        Ok(())
+    }
+}
+
+fn list_dir(path: PathBuf) -> Result<Vec<String>, StorageStatus> {
+    let mut names = Vec::new();
+
+    for e in std::fs::read_dir(path).map_err(io_status)? {
+        let e = e.map_err(io_status)?;
+        let name = e
+            .file_name()
+            .into_string()
+            .map_err(|_| StorageStatus::IoError)?;
+
+        names.push(name);
+    }
+
+    // Sort the entries so the offset is stable.
+    names.sort_unstable();
+
+    Ok(names)
+}
+
+fn io_status(e: std::io::Error) -> StorageStatus {
+    match e.kind() {
+        ErrorKind::NotFound => StorageStatus::NotFound,
+        ErrorKind::NotADirectory => StorageStatus::NotDirectory,
+        ErrorKind::ReadOnlyFilesystem | ErrorKind::PermissionDenied => StorageStatus::ReadOnly,
+        _ => StorageStatus::IoError,
+    }
+}
+
+fn read_usize(data: &mut &[u8]) -> Result<usize, RestoreError> {
+    let (v, rest) = data.split_first_chunk().ok_or(RestoreError::InvalidData)?;
+
+    *data = rest;
+
+    u64::from_le_bytes(*v)
+        .try_into()
+        .map_err(|_| RestoreError::InvalidData)
+}
+
+/// All opened handles.
+struct Handles {
+    list: BTreeMap<usize, Arc<Handle>>,
+    next: usize,
+}
+
+/// Opened file or directory.
+struct Handle {
+    path: String,
+    mode: StorageMode,
+    data: HandleData,
+}
+
+impl Handle {
+    fn stat(&self) -> Result<StorageStat, StorageStatus> {
+        let (len, dir) = match &self.data {
+            HandleData::File(f) => {
+                let meta = f.lock().unwrap().metadata().map_err(io_status)?;
+
+                (meta.len(), false)
+            }
+            HandleData::Dir(v) => (v.len().try_into().unwrap(), true),
+        };
+
+        Ok(StorageStat { len, dir })
+    }
+
+    fn read(&self, off: usize, buf: &mut [u8]) -> Result<usize, StorageStatus> {
+        let mut file = match &self.data {
+            HandleData::File(v) => v.lock().unwrap(),
+            HandleData::Dir(_) => return Err(StorageStatus::IsDirectory),
+        };
+
+        file.seek(SeekFrom::Start(off.try_into().unwrap()))
+            .map_err(io_status)?;
+
+        // Fill the buffer as much as possible.
+        let mut n = 0;
+
+        while n < buf.len() {
+            match file.read(&mut buf[n..]) {
+                Ok(0) => break,
+                Ok(v) => n += v,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(io_status(e)),
+            }
+        }
+
+        Ok(n)
+    }
+
+    fn write(&self, off: usize, data: &[u8]) -> Result<usize, StorageStatus> {
+        if self.mode != StorageMode::ReadWrite {
+            return Err(StorageStatus::ReadOnly);
+        }
+
+        let mut file = match &self.data {
+            HandleData::File(v) => v.lock().unwrap(),
+            HandleData::Dir(_) => return Err(StorageStatus::IsDirectory),
+        };
+
+        file.seek(SeekFrom::Start(off.try_into().unwrap()))
+            .map_err(io_status)?;
+        file.write_all(data).map_err(io_status)?;
+
+        Ok(data.len())
+    }
+
+    fn read_dir(&self, off: usize) -> Result<Option<&str>, StorageStatus> {
+        match &self.data {
+            HandleData::File(_) => Err(StorageStatus::NotDirectory),
+            HandleData::Dir(v) => Ok(v.get(off).map(|v| v.as_str())),
+        }
+    }
+}
+
+/// Data of [`Handle`].
+enum HandleData {
+    File(Mutex<File>),
+    Dir(Vec<String>),
+}
+
+/// Represents an error when [`Storage::restore()`] fails.
+#[derive(Debug, Error)]
+enum RestoreError {
+    #[error("invalid data")]
+    InvalidData,
+
+    #[error("couldn't open {0} ({1:?})")]
+    OpenFailed(String, StorageStatus),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn open_path() {
+        let root = TempDir::new("open-path");
+        let storage = root.storage(false);
+
+        assert!(storage.open_handle("md0", StorageMode::Read).is_ok());
+        assert!(
+            storage
+                .open_handle("md0/dir/file", StorageMode::Read)
+                .is_ok()
+        );
+        assert!(
+            storage
+                .open_handle("md0/dir/./file", StorageMode::Read)
+                .is_ok()
+        );
+
+        for path in [
+            "",
+            "/md0",
+            "./md0",
+            "md0.obp",
+            "md0/../md0/dir",
+            "md0/dir/..",
+            "md0/dir:file",
+        ] {
+            assert_eq!(
+                storage.open_handle(path, StorageMode::Read).err(),
+                Some(StorageStatus::InvalidArgument),
+                "{path}"
+            );
+        }
+
+        assert_eq!(
+            storage.open_handle("md1", StorageMode::Read).err(),
+            Some(StorageStatus::NotFound)
+        );
+    }
+
+    #[test]
+    fn open_mode() {
+        let root = TempDir::new("open-mode");
+        let file = root.0.join("md0").join("dir").join("file");
+
+        // Read-only device.
+        let storage = root.storage(false);
+        let h = storage
+            .open_handle("md0/dir/file", StorageMode::Read)
+            .unwrap();
+
+        assert_eq!(
+            storage
+                .open_handle("md0/dir/file", StorageMode::ReadWrite)
+                .err(),
+            Some(StorageStatus::ReadOnly)
+        );
+        assert_eq!(h.write(0, b"abc").err(), Some(StorageStatus::ReadOnly));
+
+        // Writable device.
+        let storage = root.storage(true);
+        let h = storage
+            .open_handle("md0/dir/file", StorageMode::ReadWrite)
+            .unwrap();
+        let mut buf = [0; 8];
+
+        assert_eq!(h.write(0, b"abc").unwrap(), 3);
+        assert_eq!(h.read(0, &mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"abc");
+        assert_eq!(
+            storage.open_handle("md0/dir", StorageMode::ReadWrite).err(),
+            Some(StorageStatus::IsDirectory)
+        );
+
+        // A read-only file must still be readable on a writable device.
+        let mut perm = std::fs::metadata(&file).unwrap().permissions();
+
+        perm.set_readonly(true);
+        std::fs::set_permissions(&file, perm).unwrap();
+
+        let h = storage
+            .open_handle("md0/dir/file", StorageMode::Read)
+            .unwrap();
+
+        assert_eq!(h.read(0, &mut buf).unwrap(), 3);
+    }
+
+    /// Temporary directory with `md0/dir/file` partition.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let root = std::env::temp_dir().join(format!(
+                "obliteration-storage-{}-{name}",
+                std::process::id()
+            ));
+            let dir = root.join("md0").join("dir");
+
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("file"), b"").unwrap();
+
+            Self(root)
+        }
+
+        fn storage(&self, writable: bool) -> Storage {
+            let part = Part::new(self.0.clone());
+
+            Storage::new(0, NonZero::new(0x1000).unwrap(), part, writable)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let file = self.0.join("md0").join("dir").join("file");
+
+            if let Ok(meta) = std::fs::metadata(&file) {
+                let mut perm = meta.permissions();
+
+                #[allow(clippy::permissions_set_readonly_false)]
+                perm.set_readonly(false);
+                std::fs::set_permissions(&file, perm).ok();
+            }
+
+            std::fs::remove_dir_all(&self.0).ok();
+        }
+    }
+}
@@ -0,0 +1,100 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+use super::{Deadline, Timer};
+use crate::hw::{DeviceContext, MmioError, read_usize, write_usize};
+use config::TimerMemory;
+use hv::{Cpu, CpuExit, CpuIo};
+use std::error::Error;
+use std::mem::offset_of;
+use std::num::NonZero;
+use thiserror::Error;
+
+/// Implementation of [`DeviceContext`].
+pub struct Context<'a> {
+    dev: &'a Timer,
+    cpu: usize,
+    target: Option<usize>,
+    period: Option<u64>,
+    vector: Option<u8>,
+}
+
+impl<'a> Context<'a> {
+    pub fn new(dev: &'a Timer, cpu: usize) -> Self {
+        Self {
+            dev,
+            cpu,
+            target: None,
+            period: None,
+            vector: None,
+        }
+    }
+}
+
+impl<C: Cpu> DeviceContext<C> for Context<'_> {
+    fn mmio(
+        &mut self,
+        exit: &mut <C::Exit<'_> as CpuExit>::Io,
+    ) -> Result<Option<bool>, Box<dyn Error + Send + Sync>> {
+        // Check field.
+        let off = exit.addr() - self.dev.addr;
+
+        if off == offset_of!(TimerMemory, monotonic) {
+            let v = self.dev.monotonic().try_into().unwrap();
+
+            write_usize(exit, v).map_err(|e| ExecError::WriteFailed(off, e))?;
+        } else if off == offset_of!(TimerMemory, realtime) {
+            let v = self.dev.realtime().try_into().unwrap();
+
+            write_usize(exit, v).map_err(|e| ExecError::WriteFailed(off, e))?;
+        } else if off == offset_of!(TimerMemory, deadline_cpu) {
+            self.target = Some(read_usize(exit).map_err(|e| ExecError::ReadFailed(off, e))?);
+        } else if off == offset_of!(TimerMemory, deadline_period) {
+            let v = read_usize(exit).map_err(|e| ExecError::ReadFailed(off, e))?;
+
+            self.period = Some(v.try_into().unwrap());
+        } else if off == offset_of!(TimerMemory, deadline_vector) {
+            let v = read_usize(exit).map_err(|e| ExecError::ReadFailed(off, e))?;
+
+            self.vector = Some(v.try_into().map_err(|_| ExecError::InvalidVector(v))?);
+        } else if off == offset_of!(TimerMemory, deadline) {
+            let cpu = self.target.take().ok_or(ExecError::InvalidSequence)?;
+            let period = self.period.take().ok_or(ExecError::InvalidSequence)?;
+            let vector = self.vector.take().ok_or(ExecError::InvalidSequence)?;
+            let at = read_usize(exit).map_err(|e| ExecError::ReadFailed(off, e))?;
+            let deadline = (at != 0).then(|| Deadline {
+                at: at.try_into().unwrap(),
+                period: NonZero::new(period),
+                vector: NonZero::new(vector),
+                raised: false,
+            });
+
+            self.dev.set_deadline(cpu, deadline);
+        } else if off == offset_of!(TimerMemory, expired) {
+            let n = self.dev.take_expired(self.cpu);
+
+            write_usize(exit, n).map_err(|e| ExecError::WriteFailed(off, e))?;
+        } else {
+            return Err(Box::new(ExecError::UnknownField(off)));
+        }
+
+        Ok(None)
+    }
+}
+
+/// Represents an error when [`Context::mmio()`] fails.
+#[derive(Debug, Error)]
+enum ExecError {
+    #[error("unknown field at offset {0:#x}")]
+    UnknownField(usize),
+
+    #[error("couldn't read data for offset {0:#x}")]
+    ReadFailed(usize, #[source] MmioError),
+
+    #[error("couldn't write data for offset {0:#x}")]
+    WriteFailed(usize, #[source] MmioError),
+
+    #[error("{0:#x} is not a valid interrupt vector")]
+    InvalidVector(usize),
+
+    #[error("invalid operation sequence")]
+    InvalidSequence,
+}
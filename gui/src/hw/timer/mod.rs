@@ -0,0 +1,289 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+use self::context::Context;
+use super::{ContextArgs, Device, DeviceContext, Interrupts};
+use config::{DeviceType, TimerMemory};
+use hv::Hypervisor;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::num::NonZero;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime};
+use thiserror::Error;
+
+mod context;
+
+/// Virtual clock and timer for the VM.
+pub struct Timer {
+    addr: usize,
+    len: NonZero<usize>,
+    shared: Arc<Shared>,
+    notifier: Option<JoinHandle<()>>,
+}
+
+impl Timer {
+    pub fn new(addr: usize, block_size: NonZero<usize>, interrupts: Arc<Interrupts>) -> Self {
+        let len = size_of::<TimerMemory>()
+            .checked_next_multiple_of(block_size.get())
+            .and_then(NonZero::new)
+            .unwrap();
+        let shared = Arc::new(Shared {
+            start: Mutex::new(Instant::now()),
+            deadlines: Mutex::new(Deadlines {
+                list: BTreeMap::new(),
+                shutdown: false,
+            }),
+            cv: Condvar::new(),
+            interrupts,
+        });
+
+        // Spawn a thread to raise the interrupts.
+        let notifier = {
+            let shared = shared.clone();
+
+            std::thread::spawn(move || shared.notify())
+        };
+
+        Self {
+            addr,
+            len,
+            shared,
+            notifier: Some(notifier),
+        }
+    }
+
+    /// Returns the number of nanoseconds since the VM was started.
+    pub fn monotonic(&self) -> u64 {
+        self.shared.monotonic()
+    }
+
+    /// Returns the number of nanoseconds since Unix epoch.
+    pub fn realtime(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|v| v.as_nanos().try_into().unwrap())
+            .unwrap_or(0)
+    }
+
+    fn set_deadline(&self, cpu: usize, deadline: Option<Deadline>) {
+        let mut deadlines = self.shared.deadlines.lock().unwrap();
+
+        match deadline {
+            Some(v) => deadlines.list.insert(cpu, v),
+            None => deadlines.list.remove(&cpu),
+        };
+
+        self.shared.cv.notify_one();
+    }
+
+    /// Returns the number of times the deadline of `cpu` has been expired since the last call.
+    fn take_expired(&self, cpu: usize) -> usize {
+        let now = self.monotonic();
+        let mut deadlines = self.shared.deadlines.lock().unwrap();
+        let d = match deadlines.list.get_mut(&cpu) {
+            Some(v) => v,
+            None => return 0,
+        };
+
+        if d.at > now {
+            return 0;
+        }
+
+        // Re-arm periodic deadline.
+        let period = match d.period {
+            Some(v) => v.get(),
+            None => {
+                deadlines.list.remove(&cpu);
+                return 1;
+            }
+        };
+
+        let n = (now - d.at) / period + 1;
+
+        // Drop the deadline if the next one is too far to represent. Both values are controlled by
+        // the guest.
+        match n.checked_mul(period).and_then(|v| d.at.checked_add(v)) {
+            Some(v) => {
+                d.at = v;
+                d.raised = false;
+            }
+            None => {
+                deadlines.list.remove(&cpu);
+            }
+        }
+
+        self.shared.cv.notify_one();
+
+        n.try_into().unwrap()
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.shared.deadlines.lock().unwrap().shutdown = true;
+        self.shared.cv.notify_one();
+        self.notifier.take().unwrap().join().unwrap();
+    }
+}
+
+impl<H: Hypervisor> Device<H> for Timer {
+    fn name(&self) -> &str {
+        "Timer"
+    }
+
+    fn ty(&self) -> DeviceType {
+        DeviceType::Timer
+    }
+
+    fn addr(&self) -> usize {
+        self.addr
+    }
+
+    fn len(&self) -> NonZero<usize> {
+        self.len
+    }
+
+    fn create_context<'a>(
+        &'a self,
+        args: &ContextArgs<'a, H>,
+    ) -> Box<dyn DeviceContext<H::Cpu<'a>> + 'a> {
+        Box::new(Context::new(self, args.cpu))
+    }
+
+    fn save(&self) -> Vec<u8> {
+        let deadlines = self.shared.deadlines.lock().unwrap();
+        let mut data = Vec::new();
+
+        data.extend_from_slice(&self.monotonic().to_le_bytes());
+
+        for (&cpu, d) in deadlines.list.iter() {
+            let period = d.period.map(|v| v.get()).unwrap_or(0);
+            let vector = d.vector.map(|v| v.get()).unwrap_or(0);
+
+            data.extend_from_slice(&u64::try_from(cpu).unwrap().to_le_bytes());
+            data.extend_from_slice(&d.at.to_le_bytes());
+            data.extend_from_slice(&period.to_le_bytes());
+            data.push(vector);
+        }
+
+        data
+    }
+
+    fn restore(&self, mut data: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // Continue the monotonic clock from the snapshot.
+        let elapsed = Duration::from_nanos(read_u64(&mut data)?);
+        let start = Instant::now()
+            .checked_sub(elapsed)
+            .ok_or(RestoreError::InvalidClock)?;
+        let mut deadlines = BTreeMap::new();
+
+        while !data.is_empty() {
+            let cpu =
+                usize::try_from(read_u64(&mut data)?).map_err(|_| RestoreError::InvalidData)?;
+            let at = read_u64(&mut data)?;
+            let period = NonZero::new(read_u64(&mut data)?);
+            let (&vector, rest) = data.split_first().ok_or(RestoreError::InvalidData)?;
+
+            data = rest;
+
+            deadlines.insert(
+                cpu,
+                Deadline {
+                    at,
+                    period,
+                    vector: NonZero::new(vector),
+                    raised: false,
+                },
+            );
+        }
+
+        *self.shared.start.lock().unwrap() = start;
+        self.shared.deadlines.lock().unwrap().list = deadlines;
+        self.shared.cv.notify_one();
+
+        Ok(())
+    }
+}
+
+fn read_u64(data: &mut &[u8]) -> Result<u64, RestoreError> {
+    let (v, rest) = data.split_first_chunk().ok_or(RestoreError::InvalidData)?;
+
+    *data = rest;
+
+    Ok(u64::from_le_bytes(*v))
+}
+
+/// States of [`Timer`] that shared with the notifier thread.
+struct Shared {
+    start: Mutex<Instant>,
+    deadlines: Mutex<Deadlines>,
+    cv: Condvar,
+    interrupts: Arc<Interrupts>,
+}
+
+impl Shared {
+    fn monotonic(&self) -> u64 {
+        let start = self.start.lock().unwrap();
+
+        start.elapsed().as_nanos().try_into().unwrap()
+    }
+
+    /// Raise the interrupt for each expired deadline until [`Timer`] is dropped.
+    fn notify(&self) {
+        let mut deadlines = self.deadlines.lock().unwrap();
+
+        while !deadlines.shutdown {
+            // Raise the interrupts for expired deadlines.
+            let now = self.monotonic();
+            let mut next = None;
+
+            for (&cpu, d) in &mut deadlines.list {
+                let vector = match d.vector {
+                    Some(v) if !d.raised => v,
+                    _ => continue,
+                };
+
+                if d.at <= now {
+                    self.interrupts.raise(cpu, vector.get());
+                    d.raised = true;
+                } else {
+                    next = Some(next.map_or(d.at, |v: u64| v.min(d.at)));
+                }
+            }
+
+            // Wait until the next deadline or the deadlines get changed.
+            deadlines = match next {
+                Some(v) => {
+                    let d = Duration::from_nanos(v - now);
+
+                    self.cv.wait_timeout(deadlines, d).unwrap().0
+                }
+                None => self.cv.wait(deadlines).unwrap(),
+            };
+        }
+    }
+}
+
+/// Deadlines of all CPUs.
+struct Deadlines {
+    list: BTreeMap<usize, Deadline>,
+    shutdown: bool,
+}
+
+/// Deadline of a CPU.
+struct Deadline {
+    at: u64,
+    period: Option<NonZero<u64>>,
+    vector: Option<NonZero<u8>>,
+    raised: bool,
+}
+
+/// Represents an error when [`Timer::restore()`] fails.
+#[derive(Debug, Error)]
+enum RestoreError {
+    #[error("invalid data")]
+    InvalidData,
+
+    #[error("the clock in the snapshot is too far from the current time")]
+    InvalidClock,
+}
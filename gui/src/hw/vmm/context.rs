@@ -1,20 +1,46 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
-use super::Vmm;
-use crate::hw::{DeviceContext, MmioError, read_u8};
+use super::{StartCpu, Vmm};
+use crate::hw::{DeviceContext, MmioError, read_u8, read_usize};
+use crate::util::channel::Sender;
+use crate::vmm::RamPolicy;
 use config::{KernelExit, VmmMemory};
-use hv::{Cpu, CpuExit, CpuIo};
+use hv::{Cpu, CpuExit, CpuIo, Ram, RamError};
 use std::error::Error;
 use std::mem::offset_of;
+use std::num::NonZero;
 use thiserror::Error;
 
 /// Implementation of [`DeviceContext`].
 pub struct Context<'a> {
     dev: &'a Vmm,
+    start: &'a Sender<StartCpu>,
+    ram: &'a Ram,
+    policy: &'a RamPolicy,
+    page_table: Option<usize>,
+    entry: Option<usize>,
+    stack: Option<usize>,
+    arg: Option<usize>,
+    ram_addr: Option<usize>,
 }
 
 impl<'a> Context<'a> {
-    pub fn new(dev: &'a Vmm) -> Self {
-        Self { dev }
+    pub fn new(
+        dev: &'a Vmm,
+        start: &'a Sender<StartCpu>,
+        ram: &'a Ram,
+        policy: &'a RamPolicy,
+    ) -> Self {
+        Self {
+            dev,
+            start,
+            ram,
+            policy,
+            page_table: None,
+            entry: None,
+            stack: None,
+            arg: None,
+            ram_addr: None,
+        }
     }
 }
 
@@ -33,6 +59,47 @@ impl<C: Cpu> DeviceContext<C> for Context<'_> {
                 .map_err(|_| Box::new(ExecError::InvalidExit(exit)))?;
 
             Ok(Some(exit == KernelExit::Success))
+        } else if off == offset_of!(VmmMemory, cpu_page_table) {
+            self.page_table = Some(read_usize(exit).map_err(|e| ExecError::ReadFailed(off, e))?);
+            Ok(None)
+        } else if off == offset_of!(VmmMemory, cpu_entry) {
+            self.entry = Some(read_usize(exit).map_err(|e| ExecError::ReadFailed(off, e))?);
+            Ok(None)
+        } else if off == offset_of!(VmmMemory, cpu_stack) {
+            self.stack = Some(read_usize(exit).map_err(|e| ExecError::ReadFailed(off, e))?);
+            Ok(None)
+        } else if off == offset_of!(VmmMemory, cpu_arg) {
+            self.arg = Some(read_usize(exit).map_err(|e| ExecError::ReadFailed(off, e))?);
+            Ok(None)
+        } else if off == offset_of!(VmmMemory, cpu_start) {
+            let id = read_usize(exit).map_err(|e| ExecError::ReadFailed(off, e))?;
+            let req = StartCpu {
+                id,
+                page_table: self.page_table.take().ok_or(ExecError::InvalidSequence)?,
+                entry: self.entry.take().ok_or(ExecError::InvalidSequence)?,
+                stack: self.stack.take().ok_or(ExecError::InvalidSequence)?,
+                arg: self.arg.take().ok_or(ExecError::InvalidSequence)?,
+            };
+
+            self.start.send(req);
+
+            Ok(None)
+        } else if off == offset_of!(VmmMemory, ram_addr) {
+            self.ram_addr = Some(read_usize(exit).map_err(|e| ExecError::ReadFailed(off, e))?);
+            Ok(None)
+        } else if off == offset_of!(VmmMemory, ram_release) {
+            let len = read_usize(exit).map_err(|e| ExecError::ReadFailed(off, e))?;
+            let addr = self.ram_addr.take().ok_or(ExecError::InvalidSequence)?;
+            let block_size = self.ram.block_size().get();
+            let len = NonZero::new(len)
+                .filter(|v| addr % block_size == 0 && v.get() % block_size == 0)
+                .ok_or(ExecError::InvalidRamRange(addr, len))?;
+
+            self.policy
+                .release(self.ram, addr, len)
+                .map_err(|e| ExecError::ReleaseRam(addr, len, e))?;
+
+            Ok(None)
         } else {
             Err(Box::new(ExecError::UnknownField(off)))
         }
@@ -50,4 +117,13 @@ enum ExecError {
 
     #[error("{0:#} is not a valid exit status")]
     InvalidExit(u8),
+
+    #[error("invalid operation sequence")]
+    InvalidSequence,
+
+    #[error("{0:#x}:{1:#x} is not a valid RAM range")]
+    InvalidRamRange(usize, usize),
+
+    #[error("couldn't release RAM at {0:#x}:{1:#x}")]
+    ReleaseRam(usize, NonZero<usize>, #[source] RamError),
 }
@@ -1,8 +1,8 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 use self::context::Context;
-use super::{Device, DeviceContext};
-use config::VmmMemory;
-use hv::Cpu;
+use super::{ContextArgs, Device, DeviceContext};
+use config::{DeviceType, VmmMemory};
+use hv::Hypervisor;
 use std::num::NonZero;
 
 mod context;
@@ -22,17 +22,17 @@ impl Vmm {
 
         Self { addr, len }
     }
-
-    pub fn create_context<'a, C: Cpu>(&'a self) -> Box<dyn DeviceContext<C> + 'a> {
-        Box::new(Context::new(self))
-    }
 }
 
-impl Device for Vmm {
+impl<H: Hypervisor> Device<H> for Vmm {
     fn name(&self) -> &str {
         "VMM"
     }
 
+    fn ty(&self) -> DeviceType {
+        DeviceType::Vmm
+    }
+
     fn addr(&self) -> usize {
         self.addr
     }
@@ -40,4 +40,20 @@ impl Device for Vmm {
     fn len(&self) -> NonZero<usize> {
         self.len
     }
+
+    fn create_context<'a>(
+        &'a self,
+        args: &ContextArgs<'a, H>,
+    ) -> Box<dyn DeviceContext<H::Cpu<'a>> + 'a> {
+        Box::new(Context::new(self, args.start, args.hv.ram(), args.ram))
+    }
+}
+
+/// Request from the kernel to start a secondary CPU.
+pub struct StartCpu {
+    pub id: usize,
+    pub page_table: usize,
+    pub entry: usize,
+    pub stack: usize,
+    pub arg: usize,
 }
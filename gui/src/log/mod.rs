@@ -35,7 +35,7 @@ impl LogWriter {
         let msg = msg.as_bytes();
 
         match ty {
-            ConsoleType::Info => stdout().write_all(msg).unwrap(),
+            ConsoleType::Info | ConsoleType::Stats => stdout().write_all(msg).unwrap(),
             ConsoleType::Warn | ConsoleType::Error => stderr().write_all(msg).unwrap(),
         }
 
@@ -1,5 +1,6 @@
 #![windows_subsystem = "windows"]
 
+use self::console::{ConsoleOutput, forward_input};
 use self::data::{DataError, DataMgr};
 use self::gdb::{GdbDispatcher, GdbError, GdbSession};
 use self::graphics::{GraphicsBuilder, GraphicsError};
@@ -12,25 +13,32 @@ use self::ui::{
     ProductList, ProfileModel, ResolutionModel, RuntimeExt, SettingsWindow, WaitForDebugger, error,
     spawn_handler,
 };
-use self::vmm::{CpuError, Vmm, VmmError, VmmEvent};
+use self::util::channel::Receiver;
+use self::vmm::{CpuError, Snapshot, SnapshotError, Vmm, VmmError, VmmEvent};
 use async_net::{TcpListener, TcpStream};
-use clap::{Parser, ValueEnum};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use config::ConsoleType;
 use erdp::ErrorDisplay;
 use futures::{
-    AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, FutureExt, TryStreamExt, select_biased,
+    AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, FutureExt, TryStreamExt,
+    future::FusedFuture, select_biased,
 };
 use hv::Hypervisor;
 use slint::{ComponentHandle, SharedString, ToSharedString};
 use std::cell::{Cell, RefMut};
 use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::num::NonZero;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
 use std::rc::Rc;
 use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error;
+use uuid::Uuid;
 use winit::dpi::PhysicalSize;
 use winit::window::Window;
 
+mod console;
 mod data;
 mod gdb;
 mod graphics;
@@ -55,7 +63,248 @@ fn main() -> ExitCode {
     }
 
     // Run.
-    self::ui::run::<MainProgram>(args)
+    match &args.command {
+        Some(ProgramCommand::Run(v)) => run_headless(&args, v),
+        None => self::ui::run::<MainProgram>(args),
+    }
+}
+
+/// Boot the kernel without any window or graphics engine.
+fn run_headless(args: &ProgramArgs, run: &RunArgs) -> ExitCode {
+    match futures::executor::block_on(headless(args, run)) {
+        Ok(HeadlessExit::Success) => ExitCode::SUCCESS,
+        Ok(HeadlessExit::Panic) => ExitCode::from(2),
+        Ok(HeadlessExit::Timeout) => ExitCode::from(3),
+        Err(e) => {
+            eprintln!("{}.", e.display());
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn headless(args: &ProgramArgs, run: &RunArgs) -> Result<HeadlessExit, ProgramError> {
+    increase_fd_limit()?;
+
+    // Load profile.
+    let data = DataMgr::new(&run.data_root)
+        .map_err(|e| ProgramError::DataManager(run.data_root.clone(), e))?;
+    let id = run.profile.parse::<Uuid>().ok();
+    let mut profile = None;
+
+    for l in data.profiles().list().map_err(ProgramError::ListProfile)? {
+        let l = l.map_err(ProgramError::ListProfile)?;
+        let p = Profile::load(&l).map_err(ProgramError::LoadProfile)?;
+
+        if id.is_some_and(|v| v == p.id()) || p.name == run.profile {
+            profile = Some(p);
+            break;
+        }
+    }
+
+    let mut profile = profile.ok_or_else(|| ProgramError::ProfileNotFound(run.profile.clone()))?;
+
+    if let Some(v) = args.kaslr_seed {
+        profile.kaslr_seed = Some(v);
+    }
+
+    // Get kernel.
+    let kernel = match &args.kernel {
+        Some(v) => v.clone(),
+        None => {
+            let exe = std::env::current_exe()
+                .and_then(std::fs::canonicalize)
+                .map_err(ProgramError::GetExePath)?;
+
+            default_kernel(&exe)
+        }
+    };
+
+    // Start timer. We use a dedicated thread here since we don't have any async runtime.
+    let timeout = run.timeout.map(|v| {
+        let (tx, rx) = futures::channel::oneshot::channel();
+
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_secs(v));
+            tx.send(()).ok();
+        });
+
+        rx
+    });
+
+    let timeout = async move {
+        match timeout {
+            Some(v) => v.await.unwrap(),
+            None => std::future::pending().await,
+        }
+    }
+    .boxed()
+    .fuse();
+
+    // Forward stdin to the kernel console.
+    let (tx, mut input) = self::util::channel::new(const { NonZero::new(16).unwrap() });
+
+    std::thread::spawn(move || forward_input(std::io::stdin(), &tx));
+
+    // Start VMM.
+    let logs = data.logs();
+    let mut logs =
+        LogWriter::new(logs).map_err(|e| ProgramError::CreateKernelLog(logs.into(), e))?;
+    let shutdown = Arc::default();
+    let part = data.partitions();
+
+    // Load snapshot.
+    let snapshot = match args.restore_snapshot {
+        true => {
+            let path = data.profiles().snapshot(profile.id());
+
+            Some(Snapshot::load(&path).map_err(ProgramError::LoadSnapshot)?)
+        }
+        false => None,
+    };
+
+    #[cfg(target_arch = "x86_64")]
+    if args.emulator {
+        let vmm = match Vmm::new_emulated(&profile, &kernel, part, snapshot, false, &shutdown) {
+            Ok(v) => v,
+            Err(e) => return Err(ProgramError::StartVmm(kernel, e)),
+        };
+
+        return run_headless_vmm(vmm, &mut logs, &mut input, timeout).await;
+    }
+
+    let vmm = match Vmm::new(&profile, &kernel, part, snapshot, false, &shutdown) {
+        Ok(v) => v,
+        Err(e) => return Err(ProgramError::StartVmm(kernel, e)),
+    };
+
+    run_headless_vmm(vmm, &mut logs, &mut input, timeout).await
+}
+
+async fn run_headless_vmm<H: Hypervisor>(
+    mut vmm: Vmm<H>,
+    logs: &mut LogWriter,
+    input: &mut Receiver<Vec<u8>>,
+    mut timeout: impl FusedFuture<Output = ()> + Unpin,
+) -> Result<HeadlessExit, ProgramError> {
+    loop {
+        let ev = select_biased! {
+            _ = timeout => return Ok(HeadlessExit::Timeout),
+            v = vmm.recv().fuse() => v,
+            v = input.recv().fuse() => {
+                send_input(&vmm, &v, logs);
+                continue;
+            }
+        };
+
+        match ev {
+            VmmEvent::Exit(id, r) => match r.map_err(ProgramError::CpuThread)? {
+                true if id == 0 => return Ok(HeadlessExit::Success),
+                true => {}
+                false => return Ok(HeadlessExit::Panic),
+            },
+            VmmEvent::Breakpoint(id) => return Err(ProgramError::UnexpectedBreakpoint(id)),
+            VmmEvent::Log(t, m) => logs.write(t, m),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn increase_fd_limit() -> Result<(), ProgramError> {
+    use libc::{RLIMIT_NOFILE, getrlimit, setrlimit};
+    use std::io::Error;
+    use std::mem::MaybeUninit;
+
+    // Get current value.
+    let mut val = MaybeUninit::uninit();
+
+    if unsafe { getrlimit(RLIMIT_NOFILE, val.as_mut_ptr()) } < 0 {
+        return Err(ProgramError::GetFdLimit(Error::last_os_error()));
+    }
+
+    // Check if we need to increase the limit.
+    let mut val = unsafe { val.assume_init() };
+
+    if val.rlim_cur < val.rlim_max {
+        val.rlim_cur = val.rlim_max;
+
+        if unsafe { setrlimit(RLIMIT_NOFILE, &val) } < 0 {
+            return Err(ProgramError::SetFdLimit(Error::last_os_error()));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn increase_fd_limit() -> Result<(), ProgramError> {
+    Ok(())
+}
+
+/// Spawn a thread to accept console clients for the kernel on `addr`.
+///
+/// The input from all clients will be sent to the returned [`Receiver`] and the kernel console
+/// output written to the returned [`ConsoleOutput`] will be sent to all clients.
+fn start_console_server(
+    addr: SocketAddr,
+) -> Result<(Receiver<Vec<u8>>, ConsoleOutput), ProgramError> {
+    let server =
+        std::net::TcpListener::bind(addr).map_err(|e| ProgramError::StartConsoleServer(addr, e))?;
+    let (tx, rx) = self::util::channel::new(const { NonZero::new(16).unwrap() });
+    let output = ConsoleOutput::default();
+    let clients = output.clone();
+
+    std::thread::spawn(move || {
+        for client in server.incoming() {
+            let Ok(input) = client else {
+                continue;
+            };
+
+            if let Ok(v) = input.try_clone() {
+                clients.attach(v);
+            }
+
+            let tx = tx.clone();
+
+            std::thread::spawn(move || forward_input(input, &tx));
+        }
+    });
+
+    Ok((rx, output))
+}
+
+/// Send `data` to the kernel console and report the bytes that the console cannot accept.
+fn send_input<H: Hypervisor>(vmm: &Vmm<H>, data: &[u8], logs: &mut LogWriter) {
+    let n = vmm.send_input(data);
+
+    if n != data.len() {
+        let msg = format!(
+            "Kernel console input is full, {} bytes was dropped.\n",
+            data.len() - n
+        );
+
+        logs.write(ConsoleType::Warn, msg);
+    }
+}
+
+fn default_kernel(exe: &Path) -> PathBuf {
+    // Get kernel directory.
+    let mut path = exe.parent().unwrap().to_owned();
+
+    if cfg!(target_os = "windows") {
+        path.push("share");
+    } else {
+        path.pop();
+
+        if cfg!(target_os = "macos") {
+            path.push("Resources");
+        } else {
+            path.push("share");
+        }
+    }
+
+    // Append kernel.
+    path.push("obkrnl");
+    path
 }
 
 /// Implementation of [`App`] for main program.
@@ -626,30 +875,7 @@ impl App for MainProgram {
 
     async fn run(self) -> Result<(), Self::Err> {
         // Increase number of file descriptor to maximum allowed.
-        #[cfg(unix)]
-        unsafe {
-            use libc::{RLIMIT_NOFILE, getrlimit, setrlimit};
-            use std::io::Error;
-            use std::mem::MaybeUninit;
-
-            // Get current value.
-            let mut val = MaybeUninit::uninit();
-
-            if getrlimit(RLIMIT_NOFILE, val.as_mut_ptr()) < 0 {
-                return Err(ProgramError::GetFdLimit(Error::last_os_error()));
-            }
-
-            // Check if we need to increase the limit.
-            let mut val = val.assume_init();
-
-            if val.rlim_cur < val.rlim_max {
-                val.rlim_cur = val.rlim_max;
-
-                if setrlimit(RLIMIT_NOFILE, &val) < 0 {
-                    return Err(ProgramError::SetFdLimit(Error::last_os_error()));
-                }
-            }
-        }
+        increase_fd_limit()?;
 
         // Run setup wizard. This will simply return the data manager if the user already has
         // required settings.
@@ -672,26 +898,12 @@ impl App for MainProgram {
 
         // Initialize graphics engine.
         let graphics = graphics::builder(&settings).map_err(ProgramError::InitGraphics)?;
-        let kernel = self.args.kernel.as_ref().cloned().unwrap_or_else(|| {
-            // Get kernel directory.
-            let mut path = self.exe.parent().unwrap().to_owned();
-
-            if cfg!(target_os = "windows") {
-                path.push("share");
-            } else {
-                path.pop();
-
-                if cfg!(target_os = "macos") {
-                    path.push("Resources");
-                } else {
-                    path.push("share");
-                }
-            }
-
-            // Append kernel.
-            path.push("obkrnl");
-            path
-        });
+        let kernel = self
+            .args
+            .kernel
+            .as_ref()
+            .cloned()
+            .unwrap_or_else(|| default_kernel(&self.exe));
 
         // Load profiles.
         let mut profiles = Vec::new();
@@ -720,7 +932,7 @@ impl App for MainProgram {
         }
 
         // Get profile to use.
-        let (graphics, profile, debug) = if let Some(v) = self.args.debug {
+        let (graphics, mut profile, debug) = if let Some(v) = self.args.debug {
             // TODO: Select last used profile.
             (graphics, profiles.pop().unwrap(), Some(v))
         } else {
@@ -739,6 +951,10 @@ impl App for MainProgram {
             }
         };
 
+        if let Some(v) = self.args.kaslr_seed {
+            profile.kaslr_seed = Some(v);
+        }
+
         // Wait for debugger.
         let mut gdb_read: Box<dyn AsyncRead + Unpin>;
         let mut gdb_write: Box<dyn AsyncWrite + Unpin>;
@@ -779,30 +995,102 @@ impl App for MainProgram {
             .build(&profile, attrs, &shutdown)
             .map_err(ProgramError::BuildGraphicsEngine)?;
         let mut gdb = GdbSession::default();
-        let mut gdb_buf = [0; 1024];
+
+        // Start console server. The sender will be dropped when the user does not request it so the
+        // receiver will never produce any value.
+        let (mut input, output) = match self.args.console {
+            Some(addr) => start_console_server(addr)?,
+            None => (
+                self::util::channel::new(const { NonZero::new(1).unwrap() }).1,
+                ConsoleOutput::default(),
+            ),
+        };
+
+        // Load snapshot.
+        let path = data.profiles().snapshot(profile.id());
+        let snapshot = match self.args.restore_snapshot {
+            true => Some(Snapshot::load(&path).map_err(ProgramError::LoadSnapshot)?),
+            false => None,
+        };
 
         // Start VMM.
-        let mut vmm = match Vmm::new(&profile, &kernel, &shutdown) {
+        let debug = debug.is_some();
+        let part = data.partitions();
+
+        #[cfg(target_arch = "x86_64")]
+        if self.args.emulator {
+            let mut vmm =
+                match Vmm::new_emulated(&profile, &kernel, part, snapshot, debug, &shutdown) {
+                    Ok(v) => v,
+                    Err(e) => return Err(ProgramError::StartVmm(kernel, e)),
+                };
+
+            vmm.set_snapshot_path(path);
+
+            return run_vmm(
+                vmm,
+                &mut gdb,
+                &mut gdb_read,
+                &mut gdb_write,
+                &mut logs,
+                &mut input,
+                &output,
+            )
+            .await;
+        }
+
+        let mut vmm = match Vmm::new(&profile, &kernel, part, snapshot, debug, &shutdown) {
             Ok(v) => v,
             Err(e) => return Err(ProgramError::StartVmm(kernel, e)),
         };
 
-        loop {
-            // Wait for event.
-            let r = select_biased! {
-                v = gdb_read.read(&mut gdb_buf).fuse() => {
-                    dispatch_gdb(v, &mut gdb, &gdb_buf, &mut vmm, &mut gdb_write).await?
-                }
-                v = vmm.recv().fuse() => dispatch_vmm(v, &mut logs).await?,
-            };
+        vmm.set_snapshot_path(path);
+
+        run_vmm(
+            vmm,
+            &mut gdb,
+            &mut gdb_read,
+            &mut gdb_write,
+            &mut logs,
+            &mut input,
+            &output,
+        )
+        .await
+    }
+}
 
-            if !r {
-                break;
+async fn run_vmm<H: Hypervisor>(
+    mut vmm: Vmm<H>,
+    gdb: &mut GdbSession,
+    gdb_read: &mut (dyn AsyncRead + Unpin),
+    gdb_write: &mut (dyn AsyncWrite + Unpin),
+    logs: &mut LogWriter,
+    input: &mut Receiver<Vec<u8>>,
+    output: &ConsoleOutput,
+) -> Result<(), ProgramError> {
+    let mut gdb_buf = [0; 1024];
+
+    loop {
+        // Wait for event.
+        let r = select_biased! {
+            v = gdb_read.read(&mut gdb_buf).fuse() => {
+                dispatch_gdb(v, gdb, &gdb_buf, &mut vmm, gdb_write).await?
             }
-        }
+            v = vmm.recv().fuse() => {
+                dispatch_vmm(v, gdb, &mut vmm, gdb_write, logs, output).await?
+            }
+            v = input.recv().fuse() => {
+                send_input(&vmm, &v, logs);
+                true
+            }
+        };
 
-        Ok(())
+        if !r {
+            break;
+        }
     }
+
+    Ok(())
 }
 
 async fn dispatch_gdb<H: Hypervisor>(
@@ -835,7 +1123,14 @@ async fn dispatch_gdb<H: Hypervisor>(
     Ok(true)
 }
 
-async fn dispatch_vmm(ev: VmmEvent, logs: &mut LogWriter) -> Result<bool, ProgramError> {
+async fn dispatch_vmm<H: Hypervisor>(
+    ev: VmmEvent,
+    gdb: &mut GdbSession,
+    vmm: &mut Vmm<H>,
+    con: &mut (dyn AsyncWrite + Unpin),
+    logs: &mut LogWriter,
+    output: &ConsoleOutput,
+) -> Result<bool, ProgramError> {
     match ev {
         VmmEvent::Exit(id, r) => {
             if !r.map_err(ProgramError::CpuThread)? {
@@ -844,7 +1139,17 @@ async fn dispatch_vmm(ev: VmmEvent, logs: &mut LogWriter) -> Result<bool, Progra
                 return Ok(false);
             }
         }
-        VmmEvent::Log(t, m) => logs.write(t, m),
+        VmmEvent::Breakpoint(_) => {
+            if let Some(res) = gdb.stop(vmm) {
+                con.write_all(res.as_ref())
+                    .await
+                    .map_err(ProgramError::WriteDebuggerSocket)?;
+            }
+        }
+        VmmEvent::Log(t, m) => {
+            output.write(&m);
+            logs.write(t, m);
+        }
     }
 
     Ok(true)
@@ -854,6 +1159,9 @@ async fn dispatch_vmm(ev: VmmEvent, logs: &mut LogWriter) -> Result<bool, Progra
 #[derive(Parser)]
 #[command(about = None)]
 struct ProgramArgs {
+    #[command(subcommand)]
+    command: Option<ProgramCommand>,
+
     #[arg(long, value_enum, hide = true)]
     mode: Option<ProgramMode>,
 
@@ -861,13 +1169,61 @@ struct ProgramArgs {
     #[arg(long, value_name = "ADDR")]
     debug: Option<SocketAddr>,
 
+    /// Serve the kernel console on the specified address.
+    #[arg(long, value_name = "ADDR")]
+    console: Option<SocketAddr>,
+
     /// Use the kernel image at the specified path instead of the default one.
-    #[arg(long, value_name = "PATH")]
+    #[arg(long, value_name = "PATH", global = true)]
     kernel: Option<PathBuf>,
 
+    /// Use the specified seed to randomize the kernel base instead of the one from the profile.
+    #[arg(long, value_name = "SEED", global = true)]
+    kaslr_seed: Option<u64>,
+
     /// Ignore saved settings and use default values instead.
     #[arg(long)]
     use_default_settings: bool,
+
+    /// Use software-emulated CPU instead of the hypervisor provided by the OS.
+    #[cfg(target_arch = "x86_64")]
+    #[arg(long, global = true)]
+    emulator: bool,
+
+    /// Resume the VM from the snapshot of the selected profile instead of booting the kernel.
+    #[arg(long, global = true)]
+    restore_snapshot: bool,
+}
+
+/// Sub-command of our program.
+#[derive(Subcommand)]
+enum ProgramCommand {
+    /// Boot the kernel without any window then exit with 0 when the kernel shutdown normally, 2
+    /// when the kernel panic or 3 when timed out.
+    Run(RunArgs),
+}
+
+/// Arguments for [`ProgramCommand::Run`].
+#[derive(Args)]
+struct RunArgs {
+    /// Path to the data root.
+    #[arg(long, value_name = "PATH")]
+    data_root: PathBuf,
+
+    /// UUID or name of the profile to use.
+    #[arg(long, value_name = "PROFILE")]
+    profile: String,
+
+    /// Stop the VM after the specified number of seconds.
+    #[arg(long, value_name = "SECS")]
+    timeout: Option<u64>,
+}
+
+/// Result of [`headless()`].
+enum HeadlessExit {
+    Success,
+    Panic,
+    Timeout,
 }
 
 /// Contains objects returned from [`MainProgram::run_launcher()`].
@@ -913,12 +1269,18 @@ enum ProgramError {
     #[error("couldn't load settings from {0}")]
     LoadSettings(PathBuf, #[source] SettingsError),
 
+    #[error("couldn't create data manager on {0}")]
+    DataManager(PathBuf, #[source] DataError),
+
     #[error("couldn't list available profiles")]
     ListProfile(#[source] DataError),
 
     #[error("couldn't load profile")]
     LoadProfile(#[source] self::profile::LoadError),
 
+    #[error("couldn't find profile '{0}'")]
+    ProfileNotFound(String),
+
     #[error("couldn't create {0}")]
     CreateDirectory(PathBuf, #[source] std::io::Error),
 
@@ -943,6 +1305,9 @@ enum ProgramError {
     #[error("couldn't disable Nagle algorithm on debugger connection")]
     DisableDebuggerNagle(#[source] std::io::Error),
 
+    #[error("couldn't start console server on {0}")]
+    StartConsoleServer(SocketAddr, #[source] std::io::Error),
+
     #[error("couldn't create main window")]
     CreateMainWindow(#[source] slint::PlatformError),
 
@@ -961,6 +1326,9 @@ enum ProgramError {
     #[error("couldn't build graphics engine")]
     BuildGraphicsEngine(#[source] GraphicsError),
 
+    #[error("couldn't load VM snapshot")]
+    LoadSnapshot(#[source] SnapshotError),
+
     #[error("couldn't start VMM for {0}")]
     StartVmm(PathBuf, #[source] VmmError),
 
@@ -970,6 +1338,9 @@ enum ProgramError {
     #[error("vCPU #{0} panicked, see {1} for more information")]
     CpuPanic(usize, PathBuf),
 
+    #[error("vCPU #{0} stopped for a debugger while no debugger is attached")]
+    UnexpectedBreakpoint(usize),
+
     #[error("couldn't read debugger connection")]
     ReadDebuggerSocket(#[source] std::io::Error),
 
@@ -26,7 +26,15 @@ pub struct Profile {
     pub display_device: ByteBuf,
     pub display_resolution: DisplayResolution,
     pub cpu_model: CpuModel,
+    /// Size of the guest RAM. This must be multiply by the RAM block size of the VM.
+    pub ram_size: NonZero<usize>,
+    /// Maximum size of the host memory the guest RAM can use. [`None`] means no limit.
+    pub ram_limit: Option<NonZero<usize>>,
     pub debug_addr: SocketAddr,
+    /// Seed to randomize the kernel base. [`None`] means a new seed for each boot.
+    pub kaslr_seed: Option<u64>,
+    /// Allow the kernel to write the firmware partitions.
+    pub writable_partitions: bool,
     pub kernel_config: Box<Config>,
     created: SystemTime,
 }
@@ -39,7 +47,11 @@ impl Profile {
             display_device: ByteBuf::new(),
             display_resolution: DisplayResolution::Hd,
             cpu_model: CpuModel::Pro,
+            ram_size: NonZero::new(1024 * 1024 * 1024 * 8).unwrap(),
+            ram_limit: None,
             debug_addr: SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 1234)),
+            kaslr_seed: None,
+            writable_partitions: false,
             kernel_config: Box::default(),
             created: SystemTime::now(),
         };
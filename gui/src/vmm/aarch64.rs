@@ -1,22 +1,33 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 use super::cpu::GdbError;
-use super::{MainCpuError, RamMap, Vmm};
+use super::cpu::debug::PageTableResult;
+use super::{MainCpuError, RamMap, SecondaryCpuError, Vmm};
+use crate::hw::StartCpu;
 use gdbstub::target::TargetResult;
 use gdbstub::target::ext::base::BaseOps;
 use gdbstub::target::ext::breakpoints::{
-    Breakpoints, BreakpointsOps, SwBreakpoint, SwBreakpointOps,
+    Breakpoints, BreakpointsOps, HwBreakpoint, HwBreakpointOps, HwWatchpoint, HwWatchpointOps,
+    SwBreakpoint, SwBreakpointOps, WatchKind,
 };
-use hv::{Cpu, CpuCommit, CpuStates, Hypervisor, Pstate, Sctlr, Tcr};
+use gdbstub::target::ext::section_offsets::SectionOffsetsOps;
+use hv::{Cpu, CpuCommit, CpuStates, Hypervisor, Pstate, Ram, Sctlr, Tcr};
+use std::error::Error;
 use std::num::NonZero;
 
 pub type GdbRegs = gdbstub_arch::aarch64::reg::AArch64CoreRegs;
 
 pub const BREAKPOINT_SIZE: NonZero<usize> = NonZero::new(4).unwrap();
 pub const RELOCATE_TYPE: usize = 1027;
+pub const RELOCATE_ABS: usize = 257; // R_AARCH64_ABS64
+pub const RELOCATE_GLOB_DAT: usize = 1025; // R_AARCH64_GLOB_DAT
+pub const GLOB_DAT_ADDEND: bool = true; // R_AARCH64_GLOB_DAT is S + A.
 pub const MEMORY_ATTRS: [u8; 8] = [0, 0b11111111, 0, 0, 0, 0, 0, 0];
 pub const MEMORY_DEV_NG_NR_NE: u8 = 0; // MEMORY_ATTRS[0]
 pub const MEMORY_NORMAL: u8 = 1; // MEMORY_ATTRS[1]
 
+/// Number of breakpoints and watchpoints to use. The architecture guarantee at least 2 of each.
+const HW_BREAKPOINTS: usize = 2;
+
 pub fn setup_main_cpu<H: Hypervisor>(
     hv: &H,
     cpu: &mut H::Cpu<'_>,
@@ -27,6 +38,124 @@ pub fn setup_main_cpu<H: Hypervisor>(
         .states()
         .map_err(|e| MainCpuError::GetCpuStatesFailed(Box::new(e)))?;
 
+    setup_el1(hv, &mut states, map.page_table);
+
+    // Set entry point, its argument and stack pointer.
+    states.set_x0(map.map_vaddr);
+    states.set_x1(map.env_vaddr);
+    states.set_x2(map.conf_vaddr);
+    states.set_sp_el1(map.stack_vaddr.checked_add(map.stack_len.get()).unwrap()); // Top-down.
+    states.set_pc(entry);
+
+    states
+        .commit()
+        .map_err(|e| MainCpuError::CommitCpuStatesFailed(Box::new(e)))
+}
+
+pub fn setup_secondary_cpu<H: Hypervisor>(
+    hv: &H,
+    cpu: &mut H::Cpu<'_>,
+    req: &StartCpu,
+) -> Result<(), SecondaryCpuError> {
+    let mut states = cpu
+        .states()
+        .map_err(|e| SecondaryCpuError::GetCpuStatesFailed(req.id, Box::new(e)))?;
+
+    setup_el1(hv, &mut states, req.page_table);
+
+    // Set entry point, its argument and stack pointer.
+    states.set_x0(req.arg);
+    states.set_sp_el1(req.stack);
+    states.set_pc(req.entry);
+
+    states
+        .commit()
+        .map_err(|e| SecondaryCpuError::CommitCpuStatesFailed(req.id, Box::new(e)))
+}
+
+/// Returns [`None`] if the stack pointer cannot be retrieved.
+pub fn stack_pointer(cpu: &mut impl Cpu) -> Option<usize> {
+    cpu.states().ok()?.get_sp_el1().ok()
+}
+
+/// Returns the address of the last page fault or [`None`] if it cannot be retrieved.
+pub fn fault_addr(cpu: &mut impl Cpu) -> Option<usize> {
+    cpu.states().ok()?.get_far_el1().ok()
+}
+
+/// Returns [`None`] if the page table is not available on this architecture.
+pub fn page_table(cpu: &mut impl Cpu) -> Option<PageTableResult> {
+    // TTBR0_EL1 has the same value as TTBR1_EL1 so this also used for the lower VA.
+    let r = match cpu.states() {
+        Ok(mut v) => v
+            .get_ttbr1_el1()
+            .map_err(|e| Box::new(e) as Box<dyn Error + Send + Sync>),
+        Err(e) => Err(Box::new(e) as Box<dyn Error + Send + Sync>),
+    };
+
+    Some(r)
+}
+
+/// Walk the page table at `root` to translate `vaddr`.
+///
+/// Each entry that was visited will be passed to `f` with its name, physical address and value.
+/// Returns [`None`] if `vaddr` is not mapped.
+pub fn page_walk(
+    ram: &Ram,
+    root: usize,
+    vaddr: usize,
+    mut f: impl FnMut(&str, usize, u64),
+) -> Option<usize> {
+    // We only use 16K granule with 48-bit VA, which is the same as setup_el1().
+    const ADDR_MASK: u64 = 0x0000FFFFFFFFC000;
+    const LEVELS: [(&str, u32, usize); 4] = [
+        ("L0", 47, 0x1),
+        ("L1", 36, 0x7FF),
+        ("L2", 25, 0x7FF),
+        ("L3", 14, 0x7FF),
+    ];
+    let mut table = root & ADDR_MASK as usize;
+
+    for (level, (name, shift, index)) in LEVELS.into_iter().enumerate() {
+        let addr = table + ((vaddr >> shift) & index) * 8;
+        let entry = ram.lock(addr, const { NonZero::new(8).unwrap() })?;
+        let entry = unsafe { entry.as_ptr().cast::<u64>().read_unaligned() };
+
+        f(name, addr, entry);
+
+        // Check if valid.
+        if entry & 1 == 0 {
+            return None;
+        }
+
+        // Check if the entry is a page descriptor or block descriptor. Block descriptor on 16K
+        // granule is only available on level 2 without FEAT_LPA2.
+        let base = (entry & ADDR_MASK) as usize;
+        let mask = (1usize << shift) - 1;
+
+        if level == LEVELS.len() - 1 {
+            return (entry & 0b10 != 0).then(|| base | (vaddr & mask));
+        } else if entry & 0b10 == 0 {
+            return (level == 2).then(|| (base & !mask) | (vaddr & mask));
+        }
+
+        table = base;
+    }
+
+    unreachable!()
+}
+
+/// Replace the registers in `regs` with the one saved in `TrapFrame` of the kernel.
+pub fn apply_trap_frame(_: &mut GdbRegs, _: &[u8]) {
+    // TODO: TrapFrame on AArch64 does not contains anything yet.
+}
+
+/// Write the registers in `regs` to `TrapFrame` of the kernel.
+pub fn store_trap_frame(_: &GdbRegs, _: &mut [u8]) {
+    // TODO: TrapFrame on AArch64 does not contains anything yet.
+}
+
+fn setup_el1<H: Hypervisor>(hv: &H, states: &mut impl CpuStates, page_table: usize) {
     // Set PSTATE.
     states.set_pstate(
         Pstate::new()
@@ -73,19 +202,8 @@ pub fn setup_main_cpu<H: Hypervisor>(
 
     // Set page table. We need both lower and higher VA here because the virtual devices mapped with
     // identity mapping.
-    states.set_ttbr0_el1(map.page_table);
-    states.set_ttbr1_el1(map.page_table);
-
-    // Set entry point, its argument and stack pointer.
-    states.set_x0(map.map_vaddr);
-    states.set_x1(map.env_vaddr);
-    states.set_x2(map.conf_vaddr);
-    states.set_sp_el1(map.stack_vaddr.checked_add(map.stack_len.get()).unwrap()); // Top-down.
-    states.set_pc(entry);
-
-    states
-        .commit()
-        .map_err(|e| MainCpuError::CommitCpuStatesFailed(Box::new(e)))
+    states.set_ttbr0_el1(page_table);
+    states.set_ttbr1_el1(page_table);
 }
 
 impl<H: Hypervisor> gdbstub::target::Target for Vmm<H> {
@@ -99,12 +217,24 @@ impl<H: Hypervisor> gdbstub::target::Target for Vmm<H> {
     fn support_breakpoints(&mut self) -> Option<BreakpointsOps<'_, Self>> {
         Some(self)
     }
+
+    fn support_section_offsets(&mut self) -> Option<SectionOffsetsOps<'_, Self>> {
+        Some(self)
+    }
 }
 
 impl<H: Hypervisor> Breakpoints for Vmm<H> {
     fn support_sw_breakpoint(&mut self) -> Option<SwBreakpointOps<'_, Self>> {
         Some(self)
     }
+
+    fn support_hw_breakpoint(&mut self) -> Option<HwBreakpointOps<'_, Self>> {
+        Some(self)
+    }
+
+    fn support_hw_watchpoint(&mut self) -> Option<HwWatchpointOps<'_, Self>> {
+        Some(self)
+    }
 }
 
 impl<H: Hypervisor> SwBreakpoint for Vmm<H> {
@@ -116,3 +246,97 @@ impl<H: Hypervisor> SwBreakpoint for Vmm<H> {
         todo!()
     }
 }
+
+impl<H: Hypervisor> HwBreakpoint for Vmm<H> {
+    fn add_hw_breakpoint(&mut self, addr: u64, _kind: usize) -> TargetResult<bool, Self> {
+        let d = &mut self.hw_debug;
+        let Some(i) = d.bcr[..HW_BREAKPOINTS].iter().position(|&v| v & 1 == 0) else {
+            return Ok(false);
+        };
+
+        // Enable for EL1 and EL0 on all bytes.
+        d.bvr[i] = addr.try_into().unwrap();
+        d.bcr[i] = 1 | 0b11 << 1 | 0b1111 << 5;
+
+        Ok(true)
+    }
+
+    fn remove_hw_breakpoint(&mut self, addr: u64, _kind: usize) -> TargetResult<bool, Self> {
+        let d = &mut self.hw_debug;
+        let Some(i) = (0..HW_BREAKPOINTS).find(|&i| d.bcr[i] & 1 != 0 && d.bvr[i] as u64 == addr)
+        else {
+            return Ok(false);
+        };
+
+        d.bvr[i] = 0;
+        d.bcr[i] = 0;
+
+        Ok(true)
+    }
+}
+
+impl<H: Hypervisor> HwWatchpoint for Vmm<H> {
+    fn add_hw_watchpoint(
+        &mut self,
+        addr: u64,
+        len: u64,
+        kind: WatchKind,
+    ) -> TargetResult<bool, Self> {
+        let d = &mut self.hw_debug;
+        let Some(wcr) = wcr(addr, len, kind) else {
+            return Ok(false);
+        };
+
+        let Some(i) = d.wcr[..HW_BREAKPOINTS].iter().position(|&v| v & 1 == 0) else {
+            return Ok(false);
+        };
+
+        d.wvr[i] = (addr & !7).try_into().unwrap();
+        d.wcr[i] = wcr;
+
+        Ok(true)
+    }
+
+    fn remove_hw_watchpoint(
+        &mut self,
+        addr: u64,
+        len: u64,
+        kind: WatchKind,
+    ) -> TargetResult<bool, Self> {
+        let d = &mut self.hw_debug;
+        let Some(wcr) = wcr(addr, len, kind) else {
+            return Ok(false);
+        };
+
+        let Some(i) =
+            (0..HW_BREAKPOINTS).find(|&i| d.wcr[i] == wcr && d.wvr[i] as u64 == addr & !7)
+        else {
+            return Ok(false);
+        };
+
+        d.wvr[i] = 0;
+        d.wcr[i] = 0;
+
+        Ok(true)
+    }
+}
+
+/// Returns `DBGWCR<n>_EL1` for the watchpoint or [`None`] if the range is not supported.
+fn wcr(addr: u64, len: u64, kind: WatchKind) -> Option<u64> {
+    // BAS can only select bytes within a doubleword.
+    let off = addr & 7;
+
+    if len == 0 || off + len > 8 {
+        return None;
+    }
+
+    let lsc = match kind {
+        WatchKind::Read => 0b01,
+        WatchKind::Write => 0b10,
+        WatchKind::ReadWrite => 0b11,
+    };
+
+    let bas = ((1 << len) - 1) << off;
+
+    Some(1 | 0b11 << 1 | lsc << 3 | bas << 5)
+}
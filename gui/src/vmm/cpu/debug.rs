@@ -1,5 +1,8 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 use crate::vmm::arch::GdbRegs;
+use crate::vmm::pause::CpuSnapshot;
+use hv::HwDebug;
+use std::error::Error;
 use std::sync::mpsc::{Receiver, Sender};
 
 pub fn channel() -> (Debuggee, Debugger) {
@@ -41,7 +44,14 @@ impl Debuggee {
         })
     }
 
-    pub fn translate_address(&mut self, addr: usize) -> Option<usize> {
+    pub fn set_regs(&mut self, v: GdbRegs) -> Option<()> {
+        self.sender.send(DebugReq::SetRegs(v)).ok()?;
+        self.locked = true;
+
+        Some(())
+    }
+
+    pub fn translate_address(&mut self, addr: usize) -> Option<TranslateResult> {
         self.sender.send(DebugReq::TranslateAddress(addr)).ok()?;
 
         self.locked = true;
@@ -52,6 +62,34 @@ impl Debuggee {
         })
     }
 
+    /// The inner [`None`] means the page table is not available on this architecture.
+    pub fn get_page_table(&mut self) -> Option<Option<PageTableResult>> {
+        self.sender.send(DebugReq::GetPageTable).ok()?;
+
+        self.locked = true;
+
+        self.receiver.recv().ok().map(|v| match v {
+            DebugRes::PageTable(v) => v,
+            _ => panic!("unexpected response when getting page table {v:?}"),
+        })
+    }
+
+    pub fn snapshot(&mut self) -> Option<CpuSnapshot> {
+        self.sender.send(DebugReq::Snapshot).ok()?;
+
+        self.locked = true;
+
+        self.receiver.recv().ok().map(|v| match v {
+            DebugRes::Snapshot(v) => v,
+            _ => panic!("unexpected response when taking a snapshot {v:?}"),
+        })
+    }
+
+    pub fn set_debug(&mut self, v: HwDebug) {
+        self.sender.send(DebugReq::SetDebug(v)).ok();
+        self.locked = true;
+    }
+
     pub fn lock(&mut self) {
         self.sender.send(DebugReq::Lock).ok();
         self.locked = true;
@@ -80,18 +118,30 @@ impl Debugger {
     }
 }
 
+/// Result of [`hv::Cpu::translate()`] from a debuggee.
+pub type TranslateResult = Result<usize, Box<dyn Error + Send + Sync>>;
+
+/// Result of reading the page table root from a debuggee.
+pub type PageTableResult = Result<usize, Box<dyn Error + Send + Sync>>;
+
 /// Debug request from a debugger to a debuggee.
 #[derive(Debug)]
 pub enum DebugReq {
     GetRegs,
+    SetRegs(GdbRegs),
     Lock,
     Release,
     TranslateAddress(usize),
+    GetPageTable,
+    SetDebug(HwDebug),
+    Snapshot,
 }
 
 /// Debug response from a debuggee to a debugger.
 #[derive(Debug)]
 pub enum DebugRes {
     Regs(GdbRegs),
-    TranslatedAddress(usize),
+    TranslatedAddress(TranslateResult),
+    PageTable(Option<PageTableResult>),
+    Snapshot(CpuSnapshot),
 }
@@ -1,6 +1,7 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
-use crate::hw::DeviceContext;
-use hv::Cpu;
+use crate::hw::{ContextArgs, DeviceContext};
+use gdbstub::common::Signal;
+use hv::{Cpu, Hypervisor};
 use std::collections::BTreeMap;
 use std::num::NonZero;
 use thiserror::Error;
@@ -15,14 +16,14 @@ pub struct Device<'a, C: Cpu> {
 }
 
 impl<'a, C: Cpu> Device<'a, C> {
-    pub fn insert<T: crate::hw::Device>(
+    pub fn insert<H: Hypervisor<Cpu<'a> = C>>(
         tree: &mut BTreeMap<usize, Self>,
-        dev: &'a T,
-        f: impl FnOnce(&'a T) -> Box<dyn DeviceContext<C> + 'a>,
+        addr: usize,
+        dev: &'a dyn crate::hw::Device<H>,
+        args: &ContextArgs<'a, H>,
     ) {
-        let addr = dev.addr();
         let dev = Self {
-            context: f(dev),
+            context: dev.create_context(args),
             end: dev.len().checked_add(addr).unwrap(),
             name: dev.name(),
         };
@@ -39,4 +40,10 @@ pub enum GdbError {
 
     #[error("CPU not found")]
     CpuNotFound,
+
+    #[error("resuming with signal {0} is not supported")]
+    ResumeWithSignal(Signal),
+
+    #[error("no CPU is stopped")]
+    NotStopped,
 }
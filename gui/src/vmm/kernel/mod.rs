@@ -97,6 +97,29 @@ impl Kernel {
             Ok(self.file.by_ref().take(hdr.p_filesz.try_into().unwrap()))
         }
     }
+
+    /// Returns 64-bit FNV-1a hash of the whole image.
+    pub fn hash(&mut self) -> Result<u64, Error> {
+        let mut hash = 0xcbf29ce484222325u64;
+        let mut buf = vec![0; 0x10000];
+
+        self.file.seek(SeekFrom::Start(0))?;
+
+        loop {
+            let n = match self.file.read(&mut buf) {
+                Ok(0) => break,
+                Ok(v) => v,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+
+            for &b in &buf[..n] {
+                hash = (hash ^ u64::from(b)).wrapping_mul(0x100000001b3);
+            }
+        }
+
+        Ok(hash)
+    }
 }
 
 #[cfg(target_arch = "x86_64")]
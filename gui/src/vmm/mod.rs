@@ -1,36 +1,48 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
-use self::arch::{BREAKPOINT_SIZE, GdbRegs, RELOCATE_TYPE};
+use self::arch::{
+    BREAKPOINT_SIZE, GLOB_DAT_ADDEND, GdbRegs, RELOCATE_ABS, RELOCATE_GLOB_DAT, RELOCATE_TYPE,
+};
+use self::cpu::GdbError;
 use self::kernel::{
     Kernel, NoteError, PT_DYNAMIC, PT_GNU_EH_FRAME, PT_GNU_RELRO, PT_GNU_STACK, PT_LOAD, PT_NOTE,
     PT_PHDR, ProgramHeader,
 };
-use crate::gdb::GdbHandler;
-use crate::hw::{Device, DeviceTree, setup_devices};
+use self::pause::Pause;
+pub use self::ram::{RamCommit, RamPolicy, RamPolicyError};
+pub use self::snapshot::{Snapshot, SnapshotError};
+use crate::data::Part;
+use crate::gdb::{BreakpointType, GdbHandler, StopReason};
+use crate::hw::{ContextArgs, DeviceTree, GuestTrap, StartCpu, setup_devices};
 use crate::profile::{CpuModel, Profile};
 use crate::util::channel::{Receiver, Sender};
-use config::{BootEnv, ConsoleType, KernelMap, MapType, PhysMap, Vm};
+use config::{BootEnv, ConsoleType, DeviceInfo, DeviceType, KernelMap, MapType, PhysMap, Vm};
 use futures::{FutureExt, select_biased};
 use gdbstub::common::{Signal, Tid};
 use gdbstub::target::ext::base::multithread::{
-    MultiThreadBase, MultiThreadResume, MultiThreadResumeOps,
+    MultiThreadBase, MultiThreadResume, MultiThreadResumeOps, MultiThreadSingleStep,
+    MultiThreadSingleStepOps,
 };
+use gdbstub::target::ext::breakpoints::{HwBreakpoint, HwWatchpoint, SwBreakpoint, WatchKind};
+use gdbstub::target::ext::section_offsets::{Offsets, SectionOffsets};
 use gdbstub::target::{TargetError, TargetResult};
 use hv::{
-    AllocInfo, CpuDebug, CpuExit, CpuIo, CpuRun, CpuStates, DebugEvent, HvError, Hypervisor,
-    RamBuilder, RamBuilderError, RamError,
+    AllocInfo, Cpu as _, CpuCommit, CpuDebug, CpuExit, CpuIo, CpuRun, CpuStates, DebugEvent,
+    HvError, HwDebug, Hypervisor, HypervisorExt, RamBuilder, RamBuilderError, RamError,
 };
 use kernel::{KernelError, ProgramHeaderError};
 use rustc_hash::FxHashMap;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 use std::error::Error;
+use std::hash::{BuildHasher, RandomState};
 use std::io::Write;
 use std::mem::zeroed;
 use std::num::NonZero;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
 use std::task::Poll;
 use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime};
 use thiserror::Error;
 
 #[cfg_attr(target_arch = "aarch64", path = "aarch64.rs")]
@@ -38,27 +50,126 @@ use thiserror::Error;
 mod arch;
 mod cpu;
 mod kernel;
+mod monitor;
+mod pause;
+mod ram;
+mod snapshot;
+
+/// Base address of the kernel when KASLR is disabled.
+const KERNEL_BASE: usize = 0xffffffff82200000;
+/// Start address of the window to randomize the kernel base.
+const KASLR_START: usize = 0xffffffff80000000;
+/// End address of the window to randomize the kernel base.
+const KASLR_END: usize = 0xffffffffc0000000;
+/// Alignment of the kernel base when KASLR is enabled.
+const KASLR_ALIGN: usize = 0x200000;
+/// Size of virtual address to reserve right after the kernel when randomizing the kernel base.
+///
+/// This must be large enough for everything we map right after the kernel (e.g. [`KernelMap`],
+/// [`BootEnv`], kernel config and the stack of each CPU) so it does not go beyond [`KASLR_END`].
+const KASLR_RESERVED: usize = 0x4000000;
+/// Virtual address to map the whole RAM for the kernel.
+const DMAP_BASE: usize = 0xfffffe0000000000;
+/// Smallest page size that the guest can map.
+const TRANSLATE_GRANULE: usize = 0x1000;
 
 /// Manage a virtual machine that run the kernel.
 pub struct Vmm<H> {
     hv: Arc<H>,
-    devices: Arc<DeviceTree>,
+    devices: Arc<DeviceTree<H>>,
+    ram: Arc<RamPolicy>,
+    kernel: PathBuf,
+    kern_hash: u64,
+    kern_vaddr: usize,
+    guards: Arc<[(usize, NonZero<usize>)]>,
+    snapshot: Option<PathBuf>,
     cpus: FxHashMap<usize, Cpu>,
-    next: usize,
-    breakpoint: Arc<Mutex<()>>,
+    started: BTreeSet<usize>,
+    suspend: Arc<AtomicBool>,
+    stopped: Option<(usize, Option<CpuStop>)>,
+    stopping: bool,
+    parked: BTreeSet<usize>,
+    stops: Receiver<(usize, Option<CpuStop>)>,
+    stop_sender: Sender<(usize, Option<CpuStop>)>,
     sw_breakpoints: HashMap<u64, [u8; BREAKPOINT_SIZE.get()]>,
+    hw_debug: HwDebug,
+    stepping: BTreeSet<usize>,
     logs: Receiver<(ConsoleType, String)>,
+    log_sender: Sender<(ConsoleType, String)>,
+    last_error: Option<String>,
+    last_stats: Option<String>,
+    recent_logs: VecDeque<(ConsoleType, String)>,
+    starts: Receiver<StartCpu>,
+    start_sender: Sender<StartCpu>,
+    pause: Arc<Pause>,
     shutdown: Arc<AtomicBool>,
 }
 
 impl Vmm<()> {
+    /// Start the kernel on a new VM or resume the VM from `snapshot` if specified.
+    ///
+    /// If `debug` is `true` the kernel will not be started until the debugger resume it.
     pub fn new(
         profile: &Profile,
         kernel: &Path,
+        part: &Part,
+        snapshot: Option<Snapshot>,
+        debug: bool,
+        shutdown: &Arc<AtomicBool>,
+    ) -> Result<Vmm<impl Hypervisor>, VmmError> {
+        Self::setup(
+            profile,
+            kernel,
+            part,
+            snapshot,
+            debug,
+            shutdown,
+            hv::new,
+            RamCommit::Host,
+            Self::hypervisor_name,
+        )
+    }
+
+    /// Same as [`Vmm::new()`] but use a software-emulated CPU instead of the hypervisor provided by
+    /// the OS.
+    #[cfg(target_arch = "x86_64")]
+    pub fn new_emulated(
+        profile: &Profile,
+        kernel: &Path,
+        part: &Part,
+        snapshot: Option<Snapshot>,
+        debug: bool,
         shutdown: &Arc<AtomicBool>,
     ) -> Result<Vmm<impl Hypervisor>, VmmError> {
+        Self::setup(
+            profile,
+            kernel,
+            part,
+            snapshot,
+            debug,
+            shutdown,
+            hv::new_emulator,
+            RamCommit::Exit,
+            |mut w| {
+                w.write(b"Emulator (x86-64)").unwrap();
+            },
+        )
+    }
+
+    fn setup<H: HypervisorExt>(
+        profile: &Profile,
+        kernel: &Path,
+        part: &Part,
+        snapshot: Option<Snapshot>,
+        debug: bool,
+        shutdown: &Arc<AtomicBool>,
+        hv: impl FnOnce(usize, NonZero<usize>, NonZero<usize>, bool) -> Result<H, HvError>,
+        commit: RamCommit,
+        name: impl FnOnce(&mut [u8]),
+    ) -> Result<Vmm<H>, VmmError> {
         // Get program header enumerator.
         let mut img = Kernel::open(kernel).map_err(|e| VmmError::OpenKernel(e))?;
+        let kern_hash = img.hash().map_err(VmmError::HashKernel)?;
         let hdrs = img
             .program_headers()
             .map_err(|e| VmmError::EnumerateProgramHeaders(e))?;
@@ -169,9 +280,21 @@ impl Vmm<()> {
         let kern_len = NonZero::new(kern_len).ok_or(VmmError::ZeroLengthLoadSegment)?;
 
         // Setup hypervisor.
-        let ram_size = NonZero::new(1024 * 1024 * 1024 * 8).unwrap();
-        let mut hv =
-            hv::new(8, ram_size, vm_page_size, false).map_err(VmmError::SetupHypervisor)?;
+        let ram_size = profile.ram_size;
+        let cpus = profile.kernel_config.max_cpu.get();
+        let mut hv = match hv(cpus, ram_size, vm_page_size, false) {
+            Ok(v) => v,
+            Err(HvError::InvalidRamSize) => return Err(VmmError::InvalidRamSize(ram_size)),
+            Err(e) => return Err(VmmError::SetupHypervisor(e)),
+        };
+
+        // Check RAM limit.
+        let block_size = hv.ram().block_size();
+        let ram_limit = profile.ram_limit.unwrap_or(ram_size);
+
+        if ram_limit.get() % block_size != 0 {
+            return Err(VmmError::InvalidRamLimit(ram_limit, block_size));
+        }
 
         match profile.cpu_model {
             CpuModel::Host => (), // hv::new() already set to host by default.
@@ -179,7 +302,7 @@ impl Vmm<()> {
             CpuModel::Pro => (), // On non-x86 the kernel always assume Pro.
             #[cfg(target_arch = "x86_64")]
             CpuModel::Pro => {
-                use hv::{FeatLeaf, HypervisorExt};
+                use hv::FeatLeaf;
 
                 hv.set_cpuid(FeatLeaf {
                     id: 1,
@@ -193,13 +316,33 @@ impl Vmm<()> {
             CpuModel::ProWithHost => todo!(),
         }
 
-        let devices = Arc::new(setup_devices(ram_size.get(), hv.ram().block_size()));
+        let devices = Arc::new(setup_devices(
+            ram_size.get(),
+            hv.ram().block_size(),
+            part,
+            profile.writable_partitions,
+        ));
+
+        // Skip kernel loading if we are resuming from a snapshot. We still need to parse the kernel
+        // to make sure the snapshot was taken with a compatible one.
+        if let Some(snapshot) = snapshot {
+            if snapshot.kern_hash != kern_hash {
+                return Err(VmmError::IncompatibleSnapshot);
+            }
+
+            if snapshot.vm_page_size != vm_page_size || snapshot.ram_size != ram_size {
+                return Err(VmmError::IncompatibleSnapshot);
+            }
+
+            return Self::restore(
+                hv, devices, ram_limit, commit, kernel, snapshot, debug, shutdown,
+            );
+        }
 
         // Reserve the beginning of the memory for kernel use. On BIOS this area is used as an entry
         // point of the other CPU since it start in real-mode. In our case we don't actually need
         // this but the memory map in the kernel expect to have this area.
         let host_page_size = hv.ram().host_page_size();
-        let block_size = hv.ram().block_size();
         let boot_len = 0xA0000usize
             .next_multiple_of(block_size.get())
             .try_into()
@@ -214,8 +357,18 @@ impl Vmm<()> {
         )
         .map_err(VmmError::AllocBootMem)?;
 
-        // TODO: Implement ASLR.
-        let mut vaddr = 0xffffffff82200000;
+        // Get kernel base.
+        let (mut vaddr, seed) = if profile.kernel_config.is_kaslr_disabled() {
+            (KERNEL_BASE, None)
+        } else {
+            let seed = profile
+                .kaslr_seed
+                .unwrap_or_else(|| RandomState::new().hash_one(SystemTime::now()));
+            let base = Self::kaslr_base(seed, kern_len, vm_page_size)?;
+
+            (base, Some(seed))
+        };
+
         let kern_vaddr = vaddr;
         let (kern_paddr, mut kern) = ram
             .alloc(
@@ -251,6 +404,15 @@ impl Vmm<()> {
             .and_then(move |v| v.checked_next_multiple_of(vm_page_size.get()))
             .unwrap();
 
+        // Allocate guard pages between the kernel and boot structures.
+        let mut guards = Vec::new();
+
+        ram.alloc_guard(vaddr, vm_page_size)
+            .map_err(VmmError::AllocGuard)?;
+        guards.push((vaddr, vm_page_size));
+
+        vaddr += vm_page_size.get();
+
         // Allocate kernel map.
         let len = size_of::<KernelMap>().try_into().unwrap();
         let map_vaddr = vaddr;
@@ -307,7 +469,15 @@ impl Vmm<()> {
             .and_then(move |v| v.checked_next_multiple_of(vm_page_size.get()))
             .unwrap();
 
-        // TODO: Allocate guard pages.
+        // Allocate guard pages between boot structures and the stack. This will catch the stack
+        // overflow.
+        ram.alloc_guard(vaddr, vm_page_size)
+            .map_err(VmmError::AllocGuard)?;
+        guards.push((vaddr, vm_page_size));
+
+        vaddr += vm_page_size.get();
+
+        // Allocate stack.
         let stack_len = (1024usize * 1024 * 1)
             .next_multiple_of(block_size.get())
             .try_into()
@@ -327,74 +497,71 @@ impl Vmm<()> {
             .and_then(move |v| v.checked_next_multiple_of(vm_page_size.get()))
             .unwrap();
 
-        // Get hypervisor name.
-        let mut hypervisor = [0; 128];
-        let mut w = hypervisor.as_mut_slice();
-
-        #[cfg(unix)]
-        unsafe {
-            use std::ffi::CStr;
+        // Allocate guard pages after the stack.
+        ram.alloc_guard(vaddr, vm_page_size)
+            .map_err(VmmError::AllocGuard)?;
+        guards.push((vaddr, vm_page_size));
 
-            // Use write to treat buffer full as non-error.
-            let hv: &[u8] = if cfg!(target_os = "linux") {
-                b"KVM"
-            } else if cfg!(target_os = "macos") {
-                b"Hypervisor Framework"
-            } else {
-                todo!()
-            };
+        vaddr += vm_page_size.get();
 
-            w.write(hv).unwrap();
-            w.write(b" (").unwrap();
+        // Allocate stacks for the secondary CPUs. The guard page after each stack will be the guard
+        // page below the next stack.
+        let ap_stacks = vaddr;
 
-            // Write OS name.
-            let mut uname = zeroed();
+        for _ in 1..cpus {
+            ram.alloc(
+                Some(vaddr),
+                stack_len,
+                #[cfg(target_arch = "aarch64")]
+                self::arch::MEMORY_NORMAL,
+            )
+            .map_err(VmmError::AllocStack)?;
 
-            if libc::uname(&mut uname) < 0 {
-                w.write(b"Unknown").unwrap();
-            } else {
-                let m = CStr::from_ptr(uname.machine.as_ptr());
-                let r = CStr::from_ptr(uname.release.as_ptr());
+            vaddr += stack_len.get();
 
-                w.write(m.to_bytes()).unwrap();
-                w.write(b" ").unwrap();
-                w.write(r.to_bytes()).unwrap();
-            }
+            ram.alloc_guard(vaddr, vm_page_size)
+                .map_err(VmmError::AllocGuard)?;
+            guards.push((vaddr, vm_page_size));
 
-            w.write(b")").unwrap();
+            vaddr += vm_page_size.get();
         }
 
-        #[cfg(windows)]
-        unsafe {
-            use windows_sys::Win32::System::SystemInformation::{GetVersionExW, OSVERSIONINFOW};
+        if vaddr - kern_vaddr > kern_len.get() + KASLR_RESERVED {
+            return Err(VmmError::KernelTooLarge);
+        }
 
-            let mut v = zeroed::<OSVERSIONINFOW>();
+        // Get hypervisor name.
+        let mut hypervisor = [0; 128];
 
-            v.dwOSVersionInfoSize = size_of_val(&v).try_into().unwrap();
-            w.write(b"WHP (").unwrap();
+        name(hypervisor.as_mut_slice());
 
-            if GetVersionExW(&mut v) != 0 {
-                // The buffer should never full here.
-                write!(
-                    w,
-                    "x86-64 {}.{}.{}",
-                    v.dwMajorVersion, v.dwMinorVersion, v.dwBuildNumber
-                )
-                .unwrap();
-            } else {
-                w.write(b"Unknown").unwrap();
-            }
+        // Build page table. This need to be done before writing the boot environment so the memory
+        // for the page tables will be included in the reserved area.
+        ram.map_ram(
+            DMAP_BASE,
+            #[cfg(target_arch = "aarch64")]
+            self::arch::MEMORY_NORMAL,
+        );
 
-            w.write(b")").unwrap();
-        }
+        let page_table = ram
+            .build_page_table(devices.all().map(|(addr, dev)| AllocInfo {
+                paddr: addr,
+                vaddr: addr,
+                len: dev.len(),
+                #[cfg(target_arch = "aarch64")]
+                attr: self::arch::MEMORY_DEV_NG_NR_NE,
+            }))
+            .map_err(VmmError::BuildPageTable)?;
 
         // Write boot environment.
         let reserved_end = ram.next_addr();
         let mut mem = env;
         let mut env = Vm {
             hypervisor,
-            vmm: devices.vmm().addr(),
-            console: devices.console().addr(),
+            devices: std::array::from_fn(|_| DeviceInfo {
+                ty: DeviceType::None,
+                addr: 0,
+            }),
             host_page_size,
             memory_map: std::array::from_fn(|_| PhysMap {
                 base: 0,
@@ -402,6 +569,7 @@ impl Vmm<()> {
                 ty: MapType::None,
                 attrs: 0,
             }),
+            dmap: DMAP_BASE,
         };
 
         env.memory_map[0].base = 0;
@@ -416,27 +584,23 @@ impl Vmm<()> {
         env.memory_map[2].len = (ram_size.get() - reserved_end).try_into().unwrap();
         env.memory_map[2].ty = MapType::Ram;
 
+        for (i, (addr, dev)) in devices.all().enumerate() {
+            env.devices[i].ty = dev.ty();
+            env.devices[i].addr = addr;
+        }
+
         assert!(mem.put(0, BootEnv::Vm(env)).unwrap().is_none());
 
         drop(mem);
 
-        // Build page table.
-        let page_table = ram
-            .build_page_table(devices.all().map(|(addr, dev)| AllocInfo {
-                paddr: addr,
-                vaddr: addr,
-                len: dev.len(),
-                #[cfg(target_arch = "aarch64")]
-                attr: self::arch::MEMORY_DEV_NG_NR_NE,
-            }))
-            .map_err(VmmError::BuildPageTable)?;
-
         assert!(
             map.put(
                 0,
                 KernelMap {
                     kern_vaddr,
                     kern_vsize: (vaddr - kern_vaddr).try_into().unwrap(),
+                    ap_stacks,
+                    ap_stack_len: stack_len.get(),
                 }
             )
             .unwrap()
@@ -461,19 +625,36 @@ impl Vmm<()> {
         Self::relocate_kernel(&mut hv, &map, dynamic)?;
 
         // Setup main CPU arguments.
+        let ram = RamPolicy::new(hv.ram(), ram_limit, commit).map_err(VmmError::SetupRamPolicy)?;
+        let ram = Arc::new(ram);
+        let guards = Arc::<[_]>::from(guards);
         let hv = Arc::new(hv);
-        let breakpoint = Arc::new(Mutex::default());
-        let (tx, logs) = crate::util::channel::new(const { NonZero::new(100).unwrap() });
+        let suspend = Arc::new(AtomicBool::new(false));
+        let pause = Arc::new(Pause::default());
+        let (log_sender, logs) = crate::util::channel::new(const { NonZero::new(100).unwrap() });
+        let (start_sender, starts) = crate::util::channel::new(const { NonZero::new(8).unwrap() });
+        let (stop_sender, stops) = crate::util::channel::new(const { NonZero::new(8).unwrap() });
+        let msg = match seed {
+            Some(v) => format!("Kernel mapped at {kern_vaddr:#x} with KASLR seed {v:#x}.\n"),
+            None => format!("Kernel mapped at {kern_vaddr:#x} with KASLR disabled.\n"),
+        };
+
+        log_sender.send((ConsoleType::Info, msg));
+
         let args = CpuArgs {
             hv: hv.clone(),
             devices: devices.clone(),
-            breakpoint: breakpoint.clone(),
-            logs: tx,
+            ram: ram.clone(),
+            guards: guards.clone(),
+            suspend: suspend.clone(),
+            logs: log_sender.clone(),
+            start: start_sender.clone(),
+            stop: stop_sender.clone(),
+            pause: pause.clone(),
             shutdown: shutdown.clone(),
         };
 
         // Setup debug channel.
-        let debug = false;
         let (debug, debugger) = if debug {
             Some(self::cpu::debug::channel()).unzip()
         } else {
@@ -494,6 +675,12 @@ impl Vmm<()> {
         Ok(Vmm {
             hv,
             devices,
+            ram,
+            kernel: std::path::absolute(kernel).unwrap_or_else(|_| kernel.into()),
+            kern_hash,
+            kern_vaddr,
+            guards,
+            snapshot: None,
             cpus: FxHashMap::from_iter([(
                 0,
                 Cpu {
@@ -502,30 +689,248 @@ impl Vmm<()> {
                     debug,
                 },
             )]),
-            next: 1,
-            breakpoint,
+            started: BTreeSet::from([0]),
+            suspend,
+            stopped: None,
+            stopping: false,
+            parked: BTreeSet::new(),
+            stops,
+            stop_sender,
             sw_breakpoints: HashMap::new(),
+            hw_debug: HwDebug::default(),
+            stepping: BTreeSet::new(),
             logs,
+            log_sender,
+            last_error: None,
+            last_stats: None,
+            recent_logs: VecDeque::new(),
+            starts,
+            start_sender,
+            pause,
             shutdown: shutdown.clone(),
         })
     }
 
+    fn restore<H: Hypervisor>(
+        hv: H,
+        devices: Arc<DeviceTree<H>>,
+        ram_limit: NonZero<usize>,
+        commit: RamCommit,
+        kernel: &Path,
+        snapshot: Snapshot,
+        debug: bool,
+        shutdown: &Arc<AtomicBool>,
+    ) -> Result<Vmm<H>, VmmError> {
+        // Restore RAM.
+        for (addr, data) in snapshot.ram {
+            let len = NonZero::new(data.len()).ok_or(VmmError::IncompatibleSnapshot)?;
+            let mut mem = hv
+                .ram()
+                .alloc(addr, len)
+                .map_err(|e| VmmError::RestoreRam(addr, e))?;
+
+            unsafe { mem.as_mut_slice().copy_from_slice(&data) };
+        }
+
+        // Restore devices.
+        for (addr, data) in snapshot.devices {
+            let dev = devices
+                .all()
+                .find_map(|(a, d)| (a == addr).then_some(d))
+                .ok_or(VmmError::UnknownSnapshotDevice(addr))?;
+
+            dev.restore(&data)
+                .map_err(|e| VmmError::RestoreDevice(dev.name().to_owned(), e))?;
+        }
+
+        if !snapshot.cpus.iter().any(|(id, _)| *id == 0) {
+            return Err(VmmError::NoMainCpuInSnapshot);
+        }
+
+        let ram = RamPolicy::new(hv.ram(), ram_limit, commit).map_err(VmmError::SetupRamPolicy)?;
+
+        // Spawn threads to drive the CPUs. We need to construct the VMM first so the spawned
+        // threads will be stopped if we fail in the middle.
+        let (log_sender, logs) = crate::util::channel::new(const { NonZero::new(100).unwrap() });
+        let (start_sender, starts) = crate::util::channel::new(const { NonZero::new(8).unwrap() });
+        let (stop_sender, stops) = crate::util::channel::new(const { NonZero::new(8).unwrap() });
+        let mut vmm = Vmm {
+            ram: Arc::new(ram),
+            hv: Arc::new(hv),
+            devices,
+            kernel: std::path::absolute(kernel).unwrap_or_else(|_| kernel.into()),
+            kern_hash: snapshot.kern_hash,
+            kern_vaddr: snapshot.kern_vaddr,
+            guards: snapshot.guards.into(),
+            snapshot: None,
+            cpus: FxHashMap::default(),
+            started: BTreeSet::new(),
+            suspend: Arc::default(),
+            stopped: None,
+            stopping: false,
+            parked: BTreeSet::new(),
+            stops,
+            stop_sender,
+            sw_breakpoints: HashMap::new(),
+            hw_debug: HwDebug::default(),
+            stepping: BTreeSet::new(),
+            logs,
+            log_sender,
+            last_error: None,
+            last_stats: None,
+            recent_logs: VecDeque::new(),
+            starts,
+            start_sender,
+            pause: Arc::default(),
+            shutdown: shutdown.clone(),
+        };
+
+        for (id, data) in snapshot.cpus {
+            // Setup debug channel. All CPUs will be parked until the debugger resume it the same as
+            // the main CPU on a fresh boot.
+            let (debug, debugger) = if debug {
+                Some(self::cpu::debug::channel()).unzip()
+            } else {
+                None.unzip()
+            };
+
+            let args = vmm.cpu_args();
+            let (tx, exiting) = futures::channel::oneshot::channel();
+            let thread = std::thread::Builder::new()
+                .spawn(move || {
+                    let r = Vmm::resume_cpu(args, debugger, id, data);
+                    tx.send(()).unwrap();
+                    r
+                })
+                .map_err(|e| VmmError::SpawnCpu(id, e))?;
+
+            vmm.cpus.insert(
+                id,
+                Cpu {
+                    thread,
+                    exiting,
+                    debug,
+                },
+            );
+
+            vmm.started.insert(id);
+        }
+
+        Ok(vmm)
+    }
+
+    /// Write name of the native hypervisor to `w`.
+    fn hypervisor_name(mut w: &mut [u8]) {
+        #[cfg(unix)]
+        unsafe {
+            use std::ffi::CStr;
+
+            // Use write to treat buffer full as non-error.
+            let hv: &[u8] = if cfg!(target_os = "linux") {
+                b"KVM"
+            } else if cfg!(target_os = "macos") {
+                b"Hypervisor Framework"
+            } else {
+                todo!()
+            };
+
+            w.write(hv).unwrap();
+            w.write(b" (").unwrap();
+
+            // Write OS name.
+            let mut uname = zeroed();
+
+            if libc::uname(&mut uname) < 0 {
+                w.write(b"Unknown").unwrap();
+            } else {
+                let m = CStr::from_ptr(uname.machine.as_ptr());
+                let r = CStr::from_ptr(uname.release.as_ptr());
+
+                w.write(m.to_bytes()).unwrap();
+                w.write(b" ").unwrap();
+                w.write(r.to_bytes()).unwrap();
+            }
+
+            w.write(b")").unwrap();
+        }
+
+        #[cfg(windows)]
+        unsafe {
+            use windows_sys::Win32::System::SystemInformation::{GetVersionExW, OSVERSIONINFOW};
+
+            let mut v = zeroed::<OSVERSIONINFOW>();
+
+            v.dwOSVersionInfoSize = size_of_val(&v).try_into().unwrap();
+            w.write(b"WHP (").unwrap();
+
+            if GetVersionExW(&mut v) != 0 {
+                // The buffer should never full here.
+                write!(
+                    w,
+                    "x86-64 {}.{}.{}",
+                    v.dwMajorVersion, v.dwMinorVersion, v.dwBuildNumber
+                )
+                .unwrap();
+            } else {
+                w.write(b"Unknown").unwrap();
+            }
+
+            w.write(b")").unwrap();
+        }
+    }
+
+    /// Returns a random kernel base within the KASLR window.
+    fn kaslr_base(
+        seed: u64,
+        kern_len: NonZero<usize>,
+        vm_page_size: NonZero<usize>,
+    ) -> Result<usize, VmmError> {
+        // Get number of possible base. We also need to leave some space for the data we put right
+        // after the kernel.
+        let align = KASLR_ALIGN.max(vm_page_size.get());
+        let len = kern_len
+            .get()
+            .checked_add(KASLR_RESERVED)
+            .and_then(|v| v.checked_next_multiple_of(align))
+            .filter(|&v| v <= KASLR_END - KASLR_START)
+            .ok_or(VmmError::KernelTooLarge)?;
+        let count = (KASLR_END - KASLR_START - len) / align + 1;
+
+        // Use SplitMix64 to make a small difference on the seed produce a completely different base.
+        let mut v = seed.wrapping_add(0x9e3779b97f4a7c15);
+
+        v = (v ^ (v >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        v = (v ^ (v >> 27)).wrapping_mul(0x94d049bb133111eb);
+        v ^= v >> 31;
+
+        Ok(KASLR_START + (v % u64::try_from(count).unwrap()) as usize * align)
+    }
+
     fn relocate_kernel<H: Hypervisor>(
         hv: &mut H,
         map: &RamMap,
         dynamic: ProgramHeader,
     ) -> Result<(), VmmError> {
-        // Check if PT_DYNAMIC valid.
-        let p_vaddr = dynamic.p_vaddr;
-        let p_memsz = dynamic.p_memsz;
+        let mut kern = hv.ram().lock(map.kern_paddr, map.kern_len).unwrap();
+        let kern = unsafe { kern.as_mut_slice() };
+
+        Self::relocate(kern, map.kern_vaddr, dynamic.p_vaddr, dynamic.p_memsz)
+    }
 
+    /// Apply relocations on `kern` that will be loaded at `base` according to `PT_DYNAMIC` at
+    /// `p_vaddr`.
+    fn relocate(
+        kern: &mut [u8],
+        base: usize,
+        p_vaddr: usize,
+        p_memsz: usize,
+    ) -> Result<(), VmmError> {
+        // Check if PT_DYNAMIC valid.
         if p_memsz % 16 != 0 {
             return Err(VmmError::InvalidDynamicLinking);
         }
 
         // Get PT_DYNAMIC.
-        let mut kern = hv.ram().lock(map.kern_paddr, map.kern_len).unwrap();
-        let kern = unsafe { kern.as_mut_slice() };
         let dynamic = p_vaddr
             .checked_add(p_memsz)
             .and_then(|end| kern.get(p_vaddr..end))
@@ -534,19 +939,42 @@ impl Vmm<()> {
         // Parse PT_DYNAMIC.
         let mut rela = None;
         let mut relasz = None;
+        let mut relr = None;
+        let mut relrsz = None;
+        let mut symtab = None;
+        let mut relaent = 24;
+        let mut relrent = 8;
+        let mut syment = 24;
 
         for entry in dynamic.chunks_exact(16) {
             let tag = usize::from_ne_bytes(entry[..8].try_into().unwrap());
             let val = usize::from_ne_bytes(entry[8..].try_into().unwrap());
 
             match tag {
-                0 => break,              // DT_NULL
-                7 => rela = Some(val),   // DT_RELA
-                8 => relasz = Some(val), // DT_RELASZ
+                0 => break,               // DT_NULL
+                6 => symtab = Some(val),  // DT_SYMTAB
+                7 => rela = Some(val),    // DT_RELA
+                8 => relasz = Some(val),  // DT_RELASZ
+                9 => relaent = val,       // DT_RELAENT
+                11 => syment = val,       // DT_SYMENT
+                35 => relrsz = Some(val), // DT_RELRSZ
+                36 => relr = Some(val),   // DT_RELR
+                37 => relrent = val,      // DT_RELRENT
                 _ => {}
             }
         }
 
+        if relaent != 24 || relrent != 8 || syment < 24 {
+            return Err(VmmError::InvalidDynamicLinking);
+        }
+
+        // Apply DT_RELR first since it does not depend on anything.
+        match (relr, relrsz) {
+            (None, None) => {}
+            (Some(relr), Some(relrsz)) => Self::apply_relr(kern, base, relr, relrsz)?,
+            _ => return Err(VmmError::InvalidDynamicLinking),
+        }
+
         // Check DT_RELA and DT_RELASZ.
         let (relocs, len) = match (rela, relasz) {
             (None, None) => return Ok(()),
@@ -567,68 +995,430 @@ impl Vmm<()> {
             let r_offset = usize::from_ne_bytes(data[..8].try_into().unwrap());
             let r_info = usize::from_ne_bytes(data[8..16].try_into().unwrap());
             let r_addend = isize::from_ne_bytes(data[16..].try_into().unwrap());
-
-            match r_info & 0xffffffff {
+            let ty = r_info & 0xffffffff;
+            let val = match ty {
                 // R_<ARCH>_NONE
-                0 => break,
+                0 => continue,
                 // R_<ARCH>_RELATIVE
-                RELOCATE_TYPE => {
-                    let dst = r_offset
-                        .checked_add(8)
-                        .and_then(|end| kern.get_mut(r_offset..end))
-                        .ok_or(VmmError::InvalidDynamicLinking)?;
-                    let val = map.kern_vaddr.wrapping_add_signed(r_addend);
-
-                    dst.copy_from_slice(&val.to_ne_bytes());
+                RELOCATE_TYPE => base.wrapping_add_signed(r_addend),
+                // R_X86_64_64, R_X86_64_GLOB_DAT, R_AARCH64_ABS64 and R_AARCH64_GLOB_DAT.
+                RELOCATE_ABS | RELOCATE_GLOB_DAT => {
+                    let symtab = symtab.ok_or(VmmError::InvalidDynamicLinking)?;
+                    let sym = Self::resolve_symbol(kern, base, symtab, syment, r_info >> 32)?;
+
+                    if ty == RELOCATE_GLOB_DAT && !GLOB_DAT_ADDEND {
+                        sym
+                    } else {
+                        sym.wrapping_add_signed(r_addend)
+                    }
                 }
-                _ => (),
-            }
+                v => return Err(VmmError::UnknownRelocation(r_offset, v)),
+            };
+
+            let dst = r_offset
+                .checked_add(8)
+                .and_then(|end| kern.get_mut(r_offset..end))
+                .ok_or(VmmError::InvalidDynamicLinking)?;
+
+            dst.copy_from_slice(&val.to_ne_bytes());
         }
 
         Ok(())
     }
-}
 
-impl<H> Vmm<H> {
-    pub async fn recv(&mut self) -> VmmEvent {
-        // Prepare futures to poll.
-        let exit = std::future::poll_fn(|cx| {
-            for (&id, cpu) in &mut self.cpus {
-                // The sender side will never close without sending the value.
-                if cpu.exiting.poll_unpin(cx).is_ready() {
-                    let c = self.cpus.remove(&id).unwrap();
-                    let r = c.thread.join().unwrap();
-
-                    return Poll::Ready((id, r));
-                }
+    /// Apply packed relative relocations from `DT_RELR`.
+    fn apply_relr(kern: &mut [u8], base: usize, relr: usize, len: usize) -> Result<(), VmmError> {
+        let relocs = relr
+            .checked_add(len)
+            .filter(|_| len % 8 == 0)
+            .and_then(|end| kern.get(relr..end))
+            .ok_or(VmmError::InvalidDynamicLinking)?
+            .chunks_exact(8)
+            .map(|v| usize::from_ne_bytes(v.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        let mut relocate = |off: usize| -> Result<(), VmmError> {
+            let dst = off
+                .checked_add(8)
+                .and_then(|end| kern.get_mut(off..end))
+                .ok_or(VmmError::InvalidDynamicLinking)?;
+            let val = usize::from_ne_bytes(dst[..].try_into().unwrap()).wrapping_add(base);
+
+            dst.copy_from_slice(&val.to_ne_bytes());
+
+            Ok(())
+        };
+
+        // An even entry is an address to relocate while an odd entry is a bitmap of the next 63
+        // words after the last relocated address.
+        let mut next = None;
+
+        for entry in relocs {
+            if entry & 1 == 0 {
+                relocate(entry)?;
+                next = Some(entry + 8);
+                continue;
             }
 
-            Poll::Pending
-        });
+            let mut addr = next.ok_or(VmmError::InvalidDynamicLinking)?;
+            let mut bits = entry >> 1;
+
+            while bits != 0 {
+                if bits & 1 != 0 {
+                    relocate(addr)?;
+                }
+
+                bits >>= 1;
+                addr += 8;
+            }
 
-        // Poll.
-        select_biased! {
-            v = self.logs.recv().fuse() => VmmEvent::Log(v.0, v.1),
-            v = exit.fuse() => VmmEvent::Exit(v.0, v.1)
+            next = Some(next.unwrap() + 63 * 8);
         }
+
+        Ok(())
     }
 
-    pub fn lock(&mut self) {
-        for cpu in self.cpus.values_mut() {
-            cpu.debug.as_mut().unwrap().lock();
+    /// Returns the address of dynamic symbol `index` with the kernel loaded at `base`.
+    fn resolve_symbol(
+        kern: &[u8],
+        base: usize,
+        symtab: usize,
+        syment: usize,
+        index: usize,
+    ) -> Result<usize, VmmError> {
+        let sym = index
+            .checked_mul(syment)
+            .and_then(|v| v.checked_add(symtab))
+            .and_then(|off| kern.get(off..off.checked_add(24)?))
+            .ok_or(VmmError::InvalidDynamicLinking)?;
+        let st_info = sym[4];
+        let st_shndx = u16::from_ne_bytes(sym[6..8].try_into().unwrap());
+        let st_value = usize::from_ne_bytes(sym[8..16].try_into().unwrap());
+
+        match st_shndx {
+            // SHN_UNDEF. The kernel does not link against anything so only weak symbol is allowed.
+            0 if st_info >> 4 == 2 => Ok(0),
+            0 => Err(VmmError::UndefinedSymbol(index)),
+            // SHN_ABS.
+            0xfff1 => Ok(st_value),
+            _ => Ok(base.wrapping_add(st_value)),
         }
     }
+}
 
-    pub fn release(&mut self) {
+impl<H> Vmm<H> {
+    /// Stop all vCPUs and wait for its thread to exit.
+    fn stop(&mut self) {
+        // Force the running vCPUs to exit the VM so they see the shutdown signal. Otherwise a vCPU
+        // that is waiting for an interrupt (e.g. HLT) will never return.
+        self.shutdown.store(true, Ordering::Relaxed);
+        self.devices.interrupts().kick_all();
+
+        for (_, cpu) in self.cpus.drain() {
+            // We need to drop the debug channel first so it will unblock the CPU thread if it is
+            // waiting for a request.
+            drop(cpu.debug);
+            drop(cpu.thread.join().unwrap());
+        }
+    }
+
+    pub fn lock(&mut self) {
+        for cpu in self.cpus.values_mut() {
+            cpu.debug.as_mut().unwrap().lock();
+        }
+    }
+
+    pub fn release(&mut self) {
         for cpu in self.cpus.values_mut() {
             cpu.debug.as_mut().unwrap().release();
         }
     }
+
+    fn cpu_args(&self) -> CpuArgs<H> {
+        CpuArgs {
+            hv: self.hv.clone(),
+            devices: self.devices.clone(),
+            ram: self.ram.clone(),
+            guards: self.guards.clone(),
+            suspend: self.suspend.clone(),
+            logs: self.log_sender.clone(),
+            start: self.start_sender.clone(),
+            stop: self.stop_sender.clone(),
+            pause: self.pause.clone(),
+            shutdown: self.shutdown.clone(),
+        }
+    }
 }
 
 impl<H: Hypervisor> Vmm<H> {
     const GDB_ENOENT: u8 = 2;
+    const GDB_EIO: u8 = 5;
     const GDB_EFAULT: u8 = 14;
+    const GDB_EBUSY: u8 = 16;
+    const GDB_EINVAL: u8 = 22;
+    const LOG_HISTORY: usize = 1000;
+
+    /// Wait for an event from any vCPU.
+    ///
+    /// This also start the secondary CPUs requested by the kernel. [`GdbSession::stop()`] must be
+    /// called when [`VmmEvent::Breakpoint`] is returned.
+    ///
+    /// [`GdbSession::stop()`]: crate::gdb::GdbSession::stop()
+    pub async fn recv(&mut self) -> VmmEvent {
+        loop {
+            // Report the stop once all debuggable CPUs are parked.
+            if self.stopping
+                && self
+                    .cpus
+                    .iter()
+                    .all(|(id, c)| c.debug.is_none() || self.parked.contains(id))
+            {
+                self.stopping = false;
+
+                return VmmEvent::Breakpoint(self.stopped.as_ref().unwrap().0);
+            }
+
+            // Prepare futures to poll.
+            let exit = std::future::poll_fn(|cx| {
+                for (&id, cpu) in &mut self.cpus {
+                    // The sender side will never close without sending the value.
+                    if cpu.exiting.poll_unpin(cx).is_ready() {
+                        let c = self.cpus.remove(&id).unwrap();
+                        let r = c.thread.join().unwrap();
+
+                        return Poll::Ready((id, r));
+                    }
+                }
+
+                Poll::Pending
+            });
+
+            // Poll.
+            let req = select_biased! {
+                v = self.logs.recv().fuse() => {
+                    match v.0 {
+                        ConsoleType::Error => self.last_error = Some(v.1.clone()),
+                        ConsoleType::Stats => self.last_stats = Some(v.1.clone()),
+                        _ => (),
+                    }
+
+                    if self.recent_logs.len() == Self::LOG_HISTORY {
+                        self.recent_logs.pop_front();
+                    }
+
+                    self.recent_logs.push_back(v.clone());
+
+                    return VmmEvent::Log(v.0, v.1);
+                }
+                v = self.stops.recv().fuse() => {
+                    self.parked.insert(v.0);
+
+                    // Stop the other CPUs when the first one stopped. The reason of the other CPUs
+                    // that stopped at the same time will be discarded.
+                    if !self.suspend.swap(true, Ordering::Relaxed) {
+                        self.stopped = Some(v);
+                        self.stopping = true;
+                        self.devices.interrupts().kick_all();
+                    }
+
+                    continue;
+                }
+                v = exit.fuse() => {
+                    self.parked.remove(&v.0);
+
+                    return VmmEvent::Exit(v.0, v.1);
+                }
+                v = self.starts.recv().fuse() => v,
+            };
+
+            // Start the requested CPU.
+            let id = req.id;
+
+            if let Err(e) = self.start_cpu(req) {
+                return VmmEvent::Exit(id, Err(e));
+            }
+        }
+    }
+
+    /// Send `data` to the kernel console and returns the number of bytes that was accepted.
+    pub fn send_input(&self, data: &[u8]) -> usize {
+        self.devices.console().push_input(data)
+    }
+
+    /// Returns the thread that reported the stop to the debugger. If `tid` is not [`None`] it
+    /// can be any thread that currently parked for the debugger.
+    fn stopped_thread(&self, tid: Option<NonZero<usize>>) -> Result<NonZero<usize>, u8> {
+        let (id, _) = self.stopped.as_ref().ok_or(Self::GDB_EBUSY)?;
+
+        if self.stopping {
+            return Err(Self::GDB_EBUSY);
+        }
+
+        match tid {
+            Some(v) if !self.parked.contains(&(v.get() - 1)) => Err(Self::GDB_EBUSY),
+            Some(v) => Ok(v),
+            None => Ok(NonZero::new(id + 1).unwrap()),
+        }
+    }
+
+    /// Resume all CPUs that parked for the debugger with the current hardware breakpoints.
+    fn resume_parked(&mut self, step: impl Fn(usize) -> bool) -> Result<(), u8> {
+        if self.stopped.take().is_none() {
+            return Err(Self::GDB_EBUSY);
+        }
+
+        // Clear the flag first so the CPUs will not park again.
+        self.stopping = false;
+        self.suspend.store(false, Ordering::Relaxed);
+
+        for id in std::mem::take(&mut self.parked) {
+            let debug = match self.cpus.get_mut(&id).and_then(|c| c.debug.as_mut()) {
+                Some(v) => v,
+                None => continue, // The CPU thread just stopped.
+            };
+            let mut v = self.hw_debug.clone();
+
+            v.step = step(id);
+
+            debug.set_debug(v);
+            debug.release();
+        }
+
+        Ok(())
+    }
+
+    /// Translate `vaddr` with the CPU of `tid`.
+    fn translate_vaddr(&mut self, vaddr: usize, tid: Tid) -> TargetResult<usize, Self> {
+        let cpu = self
+            .cpus
+            .get_mut(&(tid.get() - 1))
+            .ok_or(TargetError::Errno(Self::GDB_ENOENT))?;
+
+        cpu.debug
+            .as_mut()
+            .unwrap()
+            .translate_address(vaddr)
+            .ok_or(TargetError::Errno(Self::GDB_ENOENT))?
+            .map_err(|_| TargetError::Errno(Self::GDB_EFAULT))
+    }
+
+    fn gdb_errno<E>(e: TargetError<E>) -> u8 {
+        match e {
+            TargetError::Errno(v) => v,
+            _ => Self::GDB_EIO,
+        }
+    }
+
+    fn start_cpu(&mut self, req: StartCpu) -> Result<(), CpuError> {
+        let id = req.id;
+
+        if self.cpus.contains_key(&id) {
+            return Err(CpuError::AlreadyRunning);
+        }
+
+        // Enable debugging on the new CPU if the main CPU has it.
+        let args = self.cpu_args();
+        let debug = self.cpus.get(&0).is_some_and(|c| c.debug.is_some());
+        let (debug, debugger) = if debug {
+            Some(self::cpu::debug::channel()).unzip()
+        } else {
+            None.unzip()
+        };
+
+        // Spawn thread to drive the CPU.
+        let (tx, exiting) = futures::channel::oneshot::channel();
+        let thread = std::thread::Builder::new()
+            .spawn(move || {
+                let r = Vmm::secondary_cpu(args, debugger, req);
+                tx.send(()).unwrap();
+                r
+            })
+            .map_err(CpuError::Spawn)?;
+
+        self.cpus.insert(
+            id,
+            Cpu {
+                thread,
+                exiting,
+                debug,
+            },
+        );
+
+        self.started.insert(id);
+
+        Ok(())
+    }
+
+    /// Set the path for `monitor snapshot` without a path.
+    pub fn set_snapshot_path(&mut self, v: impl Into<PathBuf>) {
+        self.snapshot = Some(v.into());
+    }
+
+    /// Pause the VM and write its states to `path`.
+    ///
+    /// The vCPUs that are running will be paused until the snapshot is written. A vCPU that is
+    /// stopped for the debugger will be included with the states that the debugger currently
+    /// see.
+    pub fn save(&mut self, path: &Path) -> Result<(), SaveError> {
+        // Pause the running vCPUs. A vCPU is paused before it enter the VM so any in-progress
+        // memory-mapped I/O was already completed.
+        let running = self
+            .started
+            .iter()
+            .copied()
+            .filter(|id| !self.parked.contains(id))
+            .collect::<Vec<_>>();
+        let pause = self.pause.request();
+
+        self.devices.interrupts().kick_all();
+
+        let mut paused = pause.collect(&running, Duration::from_secs(5));
+        let mut cpus = Vec::with_capacity(self.started.len());
+
+        for &id in &self.started {
+            let data = if self.parked.contains(&id) {
+                let debug = self.cpus.get_mut(&id).and_then(|c| c.debug.as_mut());
+
+                debug
+                    .and_then(|d| d.snapshot())
+                    .ok_or(SaveError::CpuExited(id))?
+            } else if let Some(v) = paused.remove(&id) {
+                v
+            } else if self.cpus.get(&id).is_some_and(|c| !c.thread.is_finished()) {
+                return Err(SaveError::PauseCpu(id));
+            } else {
+                return Err(SaveError::CpuExited(id));
+            };
+
+            cpus.push((id, data.map_err(|e| SaveError::SnapshotCpu(id, e))?));
+        }
+
+        // Copy RAM.
+        let ram = self.hv.ram();
+        let mut mem = Vec::new();
+
+        for (addr, len) in ram.allocated() {
+            let m = ram.lock(addr, len).ok_or(SaveError::LockRam(addr))?;
+            let data = unsafe { std::slice::from_raw_parts(m.as_ptr(), m.len().get()) };
+
+            mem.push((addr, data.to_vec()));
+        }
+
+        // Write.
+        let snapshot = Snapshot {
+            vm_page_size: ram.vm_page_size(),
+            ram_size: ram.len(),
+            kern_hash: self.kern_hash,
+            kern_vaddr: self.kern_vaddr,
+            guards: self.guards.to_vec(),
+            ram: mem,
+            cpus,
+            devices: self.devices.all().map(|(a, d)| (a, d.save())).collect(),
+        };
+
+        drop(pause);
+
+        snapshot.save(path).map_err(SaveError::Write)
+    }
 
     fn main_cpu(
         args: CpuArgs<H>,
@@ -658,31 +1448,116 @@ impl<H: Hypervisor> Vmm<H> {
         Self::run_cpu(&args, debug, cpu)
     }
 
+    fn secondary_cpu(
+        args: CpuArgs<H>,
+        debug: Option<self::cpu::debug::Debugger>,
+        req: StartCpu,
+    ) -> Result<bool, CpuError> {
+        // Create CPU.
+        let hv = args.hv.as_ref();
+        let mut cpu = match hv.create_cpu(req.id) {
+            Ok(v) => v,
+            Err(e) => return Err(CpuError::Create(Box::new(e))),
+        };
+
+        if let Err(e) = self::arch::setup_secondary_cpu(hv, &mut cpu, &req) {
+            return Err(CpuError::Setup(Box::new(e)));
+        }
+
+        // Wait for debugger.
+        if let Some(debug) = &debug {
+            if let Some(v) = Self::handle_breakpoint(&args, debug, &mut cpu, None)? {
+                return Ok(v);
+            }
+        }
+
+        // Run.
+        Self::run_cpu(&args, debug, cpu)
+    }
+
+    fn resume_cpu(
+        args: CpuArgs<H>,
+        debug: Option<self::cpu::debug::Debugger>,
+        id: usize,
+        data: Vec<u8>,
+    ) -> Result<bool, CpuError> {
+        // Create CPU.
+        let hv = args.hv.as_ref();
+        let mut cpu = match hv.create_cpu(id) {
+            Ok(v) => v,
+            Err(e) => return Err(CpuError::Create(Box::new(e))),
+        };
+
+        if let Err(e) = cpu.restore(&data) {
+            return Err(CpuError::Restore(Box::new(e)));
+        }
+
+        // Wait for debugger.
+        if let Some(debug) = &debug {
+            if let Some(v) = Self::handle_breakpoint(&args, debug, &mut cpu, None)? {
+                return Ok(v);
+            }
+        }
+
+        // Run.
+        Self::run_cpu(&args, debug, cpu)
+    }
+
     fn run_cpu<'c>(
         args: &'c CpuArgs<H>,
         debug: Option<self::cpu::debug::Debugger>,
         mut cpu: H::Cpu<'c>,
     ) -> Result<bool, CpuError> {
         // Build device contexts for this CPU.
-        let hv = args.hv.as_ref();
         let t = &args.devices;
-        let logs = &args.logs;
+        let cx = ContextArgs {
+            cpu: cpu.id(),
+            hv: args.hv.as_ref(),
+            ram: &args.ram,
+            logs: &args.logs,
+            start: &args.start,
+            debug: debug.is_some(),
+        };
         let mut devices = BTreeMap::<usize, self::cpu::Device<'c, H::Cpu<'c>>>::new();
 
-        self::cpu::Device::insert(&mut devices, t.console(), |d| d.create_context(hv, logs));
-        self::cpu::Device::insert(&mut devices, t.vmm(), |d| d.create_context());
+        for (addr, dev) in t.all() {
+            self::cpu::Device::insert(&mut devices, addr, dev, &cx);
+        }
+
+        // Allow the devices to interrupt this CPU.
+        let _intr = t.interrupts().attach(cpu.id(), cpu.kicker());
 
         // Dispatch CPU events until shutdown.
-        loop {
+        let e = loop {
             // Check for shutdown signal.
             if args.shutdown.load(Ordering::Relaxed) {
                 return Ok(true);
             }
 
+            // Check if the VMM want to take a snapshot.
+            if args.pause.is_requested() {
+                args.pause.wait(&mut cpu);
+                continue;
+            }
+
+            // Park for the debugger if the other CPU was stopped.
+            if let (true, Some(debug)) = (args.suspend.load(Ordering::Relaxed), &debug) {
+                if let Some(v) = Self::handle_breakpoint(args, debug, &mut cpu, None)? {
+                    return Ok(v);
+                }
+
+                continue;
+            }
+
+            // Inject pending interrupt.
+            t.interrupts()
+                .deliver(&mut cpu)
+                .map_err(|e| CpuError::Interrupt(Box::new(e)))?;
+
             // Run the vCPU.
             let mut exit = match cpu.run() {
                 Ok(v) => v,
-                Err(e) => return Err(CpuError::Run(Box::new(e))),
+                Err(e) => break CpuError::Run(Box::new(e)),
             };
 
             // Execute VM exited event.
@@ -696,9 +1571,28 @@ impl<H: Hypervisor> Vmm<H> {
 
             // Handle exit.
             if let Some(v) = Self::handle_exit(args, debug.as_ref(), &mut devices, exit)? {
+                // Let the debugger inspect the CPU before we tear down the VM.
+                if let (false, Some(debug)) = (v, &debug) {
+                    Self::handle_breakpoint(args, debug, &mut cpu, Some(CpuStop::Panic))?;
+                }
+
+                // The kernel may panic on the page fault caused by a guard page.
+                if let Some(e) = (!v).then(|| Self::check_stack(args, &mut cpu)).flatten() {
+                    return Err(e);
+                }
+
                 return Ok(v);
             }
 
+            // Check if the kernel reported a trap. Without a debugger the kernel will handle it.
+            if let (Some(debug), Some(trap)) = (&debug, t.debugger().take_trap(cpu.id())) {
+                if let Some(v) =
+                    Self::handle_breakpoint(args, debug, &mut cpu, Some(CpuStop::Trap(trap)))?
+                {
+                    return Ok(v);
+                }
+            }
+
             // Execute post exit event.
             for d in devices.values_mut() {
                 match d.context.post(&mut cpu) {
@@ -707,7 +1601,10 @@ impl<H: Hypervisor> Vmm<H> {
                     Err(e) => return Err(CpuError::DevicePostExitHandler(d.name.to_owned(), e)),
                 }
             }
-        }
+        };
+
+        // The vCPU may fail to run because of the guard page.
+        Err(Self::check_stack(args, &mut cpu).unwrap_or(e))
     }
 
     fn handle_exit<'c, C: hv::Cpu>(
@@ -716,16 +1613,31 @@ impl<H: Hypervisor> Vmm<H> {
         devices: &mut BTreeMap<usize, self::cpu::Device<'c, C>>,
         exit: C::Exit<'_>,
     ) -> Result<Option<bool>, CpuError> {
-        // Check if HLT.
-        #[cfg(target_arch = "x86_64")]
-        let exit = match exit.into_hlt() {
+        // Check if interrupted. The pending interrupts will be delivered before enter the VM again.
+        let exit = match exit.into_interrupt() {
             Ok(_) => return Ok(None),
             Err(v) => v,
         };
 
+        // Check if HLT. Wait until the CPU has something to do instead of enter the VM again,
+        // which will exit immediately.
+        #[cfg(target_arch = "x86_64")]
+        let exit = {
+            let mut exit = exit;
+            let cpu = exit.cpu().id();
+
+            match exit.into_hlt() {
+                Ok(_) => {
+                    args.devices.interrupts().wait(cpu);
+                    return Ok(None);
+                }
+                Err(v) => v,
+            }
+        };
+
         // Check if I/O.
         let exit = match exit.into_io() {
-            Ok(io) => return Self::handle_io(devices, io),
+            Ok(io) => return Self::handle_io(args, devices, io),
             Err(v) => v,
         };
 
@@ -734,20 +1646,52 @@ impl<H: Hypervisor> Vmm<H> {
             Ok(mut debug) => {
                 let reason = debug.reason();
 
-                if let Some(debugger) = debugger {
-                    Self::handle_breakpoint(args, debugger, debug.cpu(), Some(reason))
-                } else {
-                    todo!()
+                // The hypervisor report a debug exit only when the debugger enabled it.
+                match debugger {
+                    Some(v) => {
+                        let stop = CpuStop::Debug(reason);
+
+                        Self::handle_breakpoint(args, v, debug.cpu(), Some(stop))
+                    }
+                    None => Err(CpuError::UnexpectedDebug(debug.cpu().id(), reason)),
                 }
             }
-            Err(_) => todo!(),
+            Err(mut exit) => match Self::check_stack(args, exit.cpu()) {
+                Some(e) => Err(e),
+                None => Err(CpuError::UnknownExit(exit.cpu().id())),
+            },
         }
     }
 
+    /// Returns [`CpuError::StackOverflow`] if the stack pointer of `cpu` is pointed to the end of
+    /// a guard page or the last page fault was on a guard page.
+    fn check_stack(args: &CpuArgs<H>, cpu: &mut impl hv::Cpu) -> Option<CpuError> {
+        // The next push will be on the byte right before the stack pointer. Nothing is mapped on
+        // the guard pages so any page fault on it is a stack overflow.
+        let sp = self::arch::stack_pointer(cpu).map(|v| v.wrapping_sub(1));
+        let fault = self::arch::fault_addr(cpu);
+
+        [sp, fault]
+            .into_iter()
+            .flatten()
+            .any(|v| is_guarded(&args.guards, v))
+            .then(|| CpuError::StackOverflow(cpu.id()))
+    }
+
     fn handle_io<C: hv::Cpu>(
+        args: &CpuArgs<H>,
         devices: &mut BTreeMap<usize, self::cpu::Device<'_, C>>,
         mut io: <C::Exit<'_> as CpuExit>::Io,
     ) -> Result<Option<bool>, CpuError> {
+        // Check if the RAM that has not been allocated.
+        if args
+            .ram
+            .handle(args.hv.ram(), &mut io)
+            .map_err(CpuError::Ram)?
+        {
+            return Ok(None);
+        }
+
         // Get target device.
         let addr = io.addr();
         let dev = match devices
@@ -770,12 +1714,10 @@ impl<H: Hypervisor> Vmm<H> {
         args: &CpuArgs<H>,
         debug: &self::cpu::debug::Debugger,
         cpu: &mut impl hv::Cpu,
-        stop: Option<DebugEvent>,
+        mut stop: Option<CpuStop>,
     ) -> Result<Option<bool>, CpuError> {
-        // Notify GUI. We need to allow only one CPU to enter the debugger dispatch loop.
-        let lock = args.breakpoint.lock().unwrap();
-
-        todo!();
+        // Notify GUI.
+        args.stop.send((cpu.id(), stop.clone()));
 
         // Wait for command from debugger thread.
         loop {
@@ -792,27 +1734,126 @@ impl<H: Hypervisor> Vmm<H> {
                         Err(e) => return Err(CpuError::GetStates(Box::new(e))),
                     };
 
-                    debug.send(self::cpu::debug::DebugRes::Regs(Self::get_debug_regs(
-                        &mut states,
-                    )?));
+                    let mut regs = Self::get_debug_regs(&mut states)?;
+
+                    // Show the interrupted program instead of the trap handler.
+                    if let Some(CpuStop::Trap(trap)) = &stop {
+                        self::arch::apply_trap_frame(&mut regs, &trap.frame);
+                    }
+
+                    debug.send(self::cpu::debug::DebugRes::Regs(regs));
+                }
+                self::cpu::debug::DebugReq::SetRegs(regs) => {
+                    // Write to the interrupted program instead of the trap handler. The kernel will
+                    // restore it from the trap frame when the CPU resume.
+                    if let Some(CpuStop::Trap(trap)) = &mut stop {
+                        self::arch::store_trap_frame(&regs, &mut trap.frame);
+                        Self::write_trap_frame(args, cpu, trap)?;
+                        continue;
+                    }
+
+                    let mut states = match cpu.states() {
+                        Ok(v) => v,
+                        Err(e) => return Err(CpuError::GetStates(Box::new(e))),
+                    };
+
+                    Self::set_debug_regs(&mut states, regs)?;
+
+                    if let Err(e) = states.commit() {
+                        return Err(CpuError::CommitStates(Box::new(e)));
+                    }
+                }
+                self::cpu::debug::DebugReq::TranslateAddress(addr) => {
+                    // The address may not be mapped so report the error to the debugger instead.
+                    let r = cpu
+                        .translate(addr)
+                        .map_err(|e| Box::new(e) as Box<dyn Error + Send + Sync>);
+
+                    debug.send(self::cpu::debug::DebugRes::TranslatedAddress(r));
+                }
+                self::cpu::debug::DebugReq::GetPageTable => {
+                    debug.send(self::cpu::debug::DebugRes::PageTable(
+                        self::arch::page_table(cpu),
+                    ));
+                }
+                self::cpu::debug::DebugReq::SetDebug(v) => {
+                    if let Err(e) = cpu.set_debug(&v) {
+                        return Err(CpuError::SetDebug(Box::new(e)));
+                    }
+                }
+                self::cpu::debug::DebugReq::Snapshot => {
+                    let data = cpu
+                        .snapshot()
+                        .map_err(|e| Box::new(e) as Box<dyn Error + Send + Sync>);
+
+                    debug.send(self::cpu::debug::DebugRes::Snapshot(data));
                 }
-                self::cpu::debug::DebugReq::TranslateAddress(addr) => match cpu.translate(addr) {
-                    Ok(v) => debug.send(self::cpu::debug::DebugRes::TranslatedAddress(v)),
-                    Err(e) => return Err(CpuError::TranslateAddr(addr, Box::new(e))),
-                },
                 self::cpu::debug::DebugReq::Lock => {} // We already in a locked loop.
                 self::cpu::debug::DebugReq::Release => break,
             }
         }
 
-        drop(lock);
-
         Ok(None)
     }
 
+    fn write_trap_frame(
+        args: &CpuArgs<H>,
+        cpu: &impl hv::Cpu,
+        trap: &GuestTrap,
+    ) -> Result<(), CpuError> {
+        // The kernel guarantee the frame does not cross a page boundary.
+        let len = NonZero::new(trap.frame.len()).unwrap();
+        let paddr = cpu
+            .translate(trap.addr)
+            .map_err(|e| CpuError::TranslateTrapFrame(trap.addr, Box::new(e)))?;
+        let mut mem = args
+            .hv
+            .ram()
+            .lock(paddr, len)
+            .ok_or(CpuError::LockTrapFrame(paddr))?;
+
+        unsafe { std::slice::from_raw_parts_mut(mem.as_mut_ptr(), len.get()) }
+            .copy_from_slice(&trap.frame);
+
+        Ok(())
+    }
+
     #[cfg(target_arch = "aarch64")]
-    fn get_debug_regs(_: &mut impl CpuStates) -> Result<GdbRegs, CpuError> {
-        todo!()
+    fn get_debug_regs<C: CpuStates>(states: &mut C) -> Result<GdbRegs, CpuError> {
+        let error = |n: &'static str, e: C::Err| CpuError::ReadReg(n, Box::new(e));
+        let mut x = [0; 31];
+        let mut v = [0; 32];
+
+        for (n, r) in x.iter_mut().enumerate() {
+            *r = states
+                .get_x(n)
+                .map(|v| v.try_into().unwrap())
+                .map_err(|e| error("x", e))?;
+        }
+
+        for (n, r) in v.iter_mut().enumerate() {
+            *r = states.get_v(n).map_err(|e| error("v", e))?;
+        }
+
+        Ok(GdbRegs {
+            x,
+            sp: states
+                .get_sp_el1()
+                .map(|v| v.try_into().unwrap())
+                .map_err(|e| error("sp", e))?,
+            pc: states
+                .get_pc()
+                .map(|v| v.try_into().unwrap())
+                .map_err(|e| error("pc", e))?,
+            // CPSR on GDB only has the lower 32 bits of PSTATE.
+            cpsr: states
+                .get_pstate()
+                .map(|v| v.into_bits() as u32)
+                .map_err(|e| error("pstate", e))?,
+            v,
+            fpsr: states.get_fpsr().map_err(|e| error("fpsr", e))?,
+            fpcr: states.get_fpcr().map_err(|e| error("fpcr", e))?,
+        })
     }
 
     #[cfg(target_arch = "x86_64")]
@@ -901,33 +1942,306 @@ impl<H: Hypervisor> Vmm<H> {
     }
 
     #[cfg(target_arch = "aarch64")]
-    fn set_debug_regs(_: &mut impl CpuStates, _: GdbRegs) -> Result<(), CpuError> {
-        todo!()
+    fn set_debug_regs(states: &mut impl CpuStates, regs: GdbRegs) -> Result<(), CpuError> {
+        for (n, v) in regs.x.into_iter().enumerate() {
+            states.set_x(n, v.try_into().unwrap());
+        }
+
+        states.set_sp_el1(regs.sp.try_into().unwrap());
+        states.set_pc(regs.pc.try_into().unwrap());
+        states.set_pstate(hv::Pstate::from_bits(regs.cpsr.into()));
+
+        for (n, v) in regs.v.into_iter().enumerate() {
+            states.set_v(n, v);
+        }
+
+        states.set_fpsr(regs.fpsr);
+        states.set_fpcr(regs.fpcr);
+
+        Ok(())
     }
 
     #[cfg(target_arch = "x86_64")]
-    fn set_debug_regs(_: &mut impl CpuStates, _: GdbRegs) -> Result<(), CpuError> {
-        todo!()
+    fn set_debug_regs(states: &mut impl CpuStates, regs: GdbRegs) -> Result<(), CpuError> {
+        let [
+            rax,
+            rbx,
+            rcx,
+            rdx,
+            rsi,
+            rdi,
+            rbp,
+            rsp,
+            r8,
+            r9,
+            r10,
+            r11,
+            r12,
+            r13,
+            r14,
+            r15,
+        ] = regs.regs.map(|v| usize::try_from(v).unwrap());
+
+        states.set_rax(rax);
+        states.set_rbx(rbx);
+        states.set_rcx(rcx);
+        states.set_rdx(rdx);
+        states.set_rsi(rsi);
+        states.set_rdi(rdi);
+        states.set_rbp(rbp);
+        states.set_rsp(rsp);
+        states.set_r8(r8);
+        states.set_r9(r9);
+        states.set_r10(r10);
+        states.set_r11(r11);
+        states.set_r12(r12);
+        states.set_r13(r13);
+        states.set_r14(r14);
+        states.set_r15(r15);
+        states.set_rip(regs.rip.try_into().unwrap());
+        states.set_rflags(u64::from(regs.eflags).into());
+
+        // Segment selectors are 16-bit on the hardware.
+        let seg = regs.segments;
+
+        states.set_cs_selector(seg.cs as u16);
+        states.set_ss_selector(seg.ss as u16);
+        states.set_ds_selector(seg.ds as u16);
+        states.set_es_selector(seg.es as u16);
+        states.set_fs_selector(seg.fs as u16);
+        states.set_gs_selector(seg.gs as u16);
+
+        let [st0, st1, st2, st3, st4, st5, st6, st7] = regs.st;
+
+        states.set_st0(st0);
+        states.set_st1(st1);
+        states.set_st2(st2);
+        states.set_st3(st3);
+        states.set_st4(st4);
+        states.set_st5(st5);
+        states.set_st6(st6);
+        states.set_st7(st7);
+
+        let fpu = regs.fpu;
+
+        states.set_fcw(fpu.fctrl);
+        states.set_fsw(fpu.fstat);
+        states.set_ftwx(fpu.ftag);
+        states.set_fiseg(fpu.fiseg);
+        states.set_fioff(fpu.fioff);
+        states.set_foseg(fpu.foseg);
+        states.set_fooff(fpu.fooff);
+        states.set_fop(fpu.fop);
+
+        let [
+            xmm0,
+            xmm1,
+            xmm2,
+            xmm3,
+            xmm4,
+            xmm5,
+            xmm6,
+            xmm7,
+            xmm8,
+            xmm9,
+            xmm10,
+            xmm11,
+            xmm12,
+            xmm13,
+            xmm14,
+            xmm15,
+        ] = regs.xmm;
+
+        states.set_xmm0(xmm0);
+        states.set_xmm1(xmm1);
+        states.set_xmm2(xmm2);
+        states.set_xmm3(xmm3);
+        states.set_xmm4(xmm4);
+        states.set_xmm5(xmm5);
+        states.set_xmm6(xmm6);
+        states.set_xmm7(xmm7);
+        states.set_xmm8(xmm8);
+        states.set_xmm9(xmm9);
+        states.set_xmm10(xmm10);
+        states.set_xmm11(xmm11);
+        states.set_xmm12(xmm12);
+        states.set_xmm13(xmm13);
+        states.set_xmm14(xmm14);
+        states.set_xmm15(xmm15);
+        states.set_mxcsr(regs.mxcsr);
+
+        Ok(())
     }
 }
 
 impl<H> Drop for Vmm<H> {
     fn drop(&mut self) {
-        // Set shutdown flag before dropping the other fields so their background thread can stop
+        // Stop all CPU before dropping the other fields so their background thread can stop
         // before they try to join with it.
-        self.shutdown.store(true, Ordering::Relaxed);
+        self.stop();
+    }
+}
 
-        // Wait for all CPU to stop.
-        for (_, cpu) in self.cpus.drain() {
-            // We need to drop the debug channel first so it will unblock the CPU thread if it is
-            // waiting for a request.
-            drop(cpu.debug);
-            drop(cpu.thread.join().unwrap());
+impl<H: Hypervisor> GdbHandler for Vmm<H> {
+    type Arch = <Self as gdbstub::target::Target>::Arch;
+
+    fn stop_reason(&mut self) -> Option<(NonZero<usize>, StopReason)> {
+        // The other CPUs are not parked yet.
+        if self.stopping {
+            return None;
         }
+
+        let (id, ev) = self.stopped.as_ref()?;
+        let reason = match ev {
+            Some(CpuStop::Debug(ev)) => match *ev {
+                DebugEvent::SwBreak => StopReason::SwBreak,
+                DebugEvent::HwBreak => StopReason::HwBreak,
+                DebugEvent::ReadWatch(v) => StopReason::Watch(WatchKind::Read, v as u64),
+                DebugEvent::WriteWatch(v) => StopReason::Watch(WatchKind::Write, v as u64),
+                DebugEvent::AccessWatch(v) => StopReason::Watch(WatchKind::ReadWrite, v as u64),
+                DebugEvent::Step | DebugEvent::Guest(_) => StopReason::Trap,
+            },
+            Some(CpuStop::Trap(_)) | None => StopReason::Trap,
+            Some(CpuStop::Panic) => StopReason::Abort,
+            Some(CpuStop::Interrupt) => StopReason::Interrupt,
+        };
+
+        Some((NonZero::new(id + 1).unwrap(), reason))
+    }
+
+    fn active_threads(&mut self) -> Vec<NonZero<usize>> {
+        let mut threads = Vec::with_capacity(self.cpus.len());
+
+        self.list_active_threads(&mut |v| threads.push(v)).unwrap();
+        threads.sort_unstable();
+        threads
+    }
+
+    fn exec_file(&mut self) -> Option<&Path> {
+        Some(&self.kernel)
+    }
+
+    fn section_offsets(&mut self) -> Option<Offsets<u64>> {
+        SectionOffsets::get_section_offsets(self).ok()
+    }
+
+    fn read_registers(&mut self, tid: NonZero<usize>) -> Result<GdbRegs, u8> {
+        let tid = self.stopped_thread(Some(tid))?;
+        let mut regs = GdbRegs::default();
+
+        MultiThreadBase::read_registers(self, &mut regs, tid).map_err(Self::gdb_errno)?;
+
+        Ok(regs)
+    }
+
+    fn write_registers(&mut self, tid: NonZero<usize>, regs: &GdbRegs) -> Result<(), u8> {
+        let tid = self.stopped_thread(Some(tid))?;
+
+        MultiThreadBase::write_registers(self, regs, tid).map_err(Self::gdb_errno)
+    }
+
+    fn read_memory(&mut self, tid: NonZero<usize>, addr: u64, buf: &mut [u8]) -> Result<(), u8> {
+        // Translate with the page table of the thread so user addresses resolve correctly.
+        let tid = self.stopped_thread(Some(tid))?;
+
+        self.read_addrs(addr, buf, tid)
+            .map(|_| ())
+            .map_err(Self::gdb_errno)
+    }
+
+    fn write_memory(&mut self, tid: NonZero<usize>, addr: u64, data: &[u8]) -> Result<(), u8> {
+        let tid = self.stopped_thread(Some(tid))?;
+
+        self.write_addrs(addr, data, tid).map_err(Self::gdb_errno)
+    }
+
+    fn insert_breakpoint(
+        &mut self,
+        ty: BreakpointType,
+        addr: u64,
+        kind: usize,
+    ) -> Result<bool, u8> {
+        let r = match ty {
+            BreakpointType::Sw => {
+                // Software breakpoint use the main CPU to translate the address.
+                self.stopped_thread(NonZero::new(1))?;
+                self.add_sw_breakpoint(addr, kind)
+            }
+            BreakpointType::Hw => self.add_hw_breakpoint(addr, kind),
+            BreakpointType::Watch(v) => {
+                let len = kind.try_into().map_err(|_| Self::GDB_EINVAL)?;
+
+                self.add_hw_watchpoint(addr, len, v)
+            }
+        };
+
+        r.map_err(Self::gdb_errno)
+    }
+
+    fn remove_breakpoint(
+        &mut self,
+        ty: BreakpointType,
+        addr: u64,
+        kind: usize,
+    ) -> Result<bool, u8> {
+        let r = match ty {
+            BreakpointType::Sw => {
+                self.stopped_thread(NonZero::new(1))?;
+                self.remove_sw_breakpoint(addr, kind)
+            }
+            BreakpointType::Hw => self.remove_hw_breakpoint(addr, kind),
+            BreakpointType::Watch(v) => {
+                let len = kind.try_into().map_err(|_| Self::GDB_EINVAL)?;
+
+                self.remove_hw_watchpoint(addr, len, v)
+            }
+        };
+
+        r.map_err(Self::gdb_errno)
+    }
+
+    fn monitor(&mut self, cmd: &str) -> Result<String, u8> {
+        Ok(self.exec_monitor(cmd))
+    }
+
+    fn resume(&mut self, step: &[NonZero<usize>]) -> Result<(), u8> {
+        self.resume_parked(|id| step.iter().any(|t| t.get() == id + 1))
+    }
+
+    fn interrupt(&mut self) {
+        // Report the stop on the first CPU that accept the debugger.
+        let id = match self
+            .cpus
+            .iter()
+            .filter(|(_, c)| c.debug.is_some())
+            .map(|(&id, _)| id)
+            .min()
+        {
+            Some(v) => v,
+            None => return,
+        };
+
+        // Do nothing if the CPUs already stopping.
+        if self.suspend.swap(true, Ordering::Relaxed) {
+            return;
+        }
+
+        // Park all CPUs. The stop will be reported by recv() once all of them are parked.
+        self.stopped = Some((id, Some(CpuStop::Interrupt)));
+        self.stopping = true;
+        self.devices.interrupts().kick_all();
     }
 }
 
-impl<H: Hypervisor> GdbHandler for Vmm<H> {}
+impl<H: Hypervisor> SectionOffsets for Vmm<H> {
+    fn get_section_offsets(&mut self) -> Result<Offsets<u64>, Self::Error> {
+        // The kernel was linked at zero so its base is the slide.
+        Ok(Offsets::Segments {
+            text_seg: self.kern_vaddr.try_into().unwrap(),
+            data_seg: None,
+        })
+    }
+}
 
 impl<H: Hypervisor> MultiThreadBase for Vmm<H> {
     fn read_registers(&mut self, regs: &mut GdbRegs, tid: Tid) -> TargetResult<(), Self> {
@@ -947,7 +2261,18 @@ impl<H: Hypervisor> MultiThreadBase for Vmm<H> {
     }
 
     fn write_registers(&mut self, regs: &GdbRegs, tid: Tid) -> TargetResult<(), Self> {
-        todo!()
+        let cpu = self
+            .cpus
+            .get_mut(&(tid.get() - 1))
+            .ok_or(TargetError::Errno(Self::GDB_ENOENT))?;
+
+        cpu.debug
+            .as_mut()
+            .unwrap()
+            .set_regs(regs.clone())
+            .ok_or(TargetError::Errno(Self::GDB_ENOENT))?; // The CPU thread just stopped.
+
+        Ok(())
     }
 
     fn read_addrs(
@@ -956,44 +2281,71 @@ impl<H: Hypervisor> MultiThreadBase for Vmm<H> {
         data: &mut [u8],
         tid: Tid,
     ) -> TargetResult<usize, Self> {
-        let Some(len) = NonZero::new(data.len()) else {
-            return Ok(0);
-        };
+        let vaddr = usize::try_from(start_addr).unwrap();
+        let mut off = 0;
 
-        // Translate virtual address to physical address.
-        let cpu = self
-            .cpus
-            .get_mut(&(tid.get() - 1))
-            .ok_or(TargetError::Errno(Self::GDB_ENOENT))?;
+        // The contiguous virtual pages may not be contiguous on the physical memory so we need to
+        // translate each page separately.
+        while off < data.len() {
+            let (addr, len) = next_page(vaddr, off, data.len());
 
-        let addr = cpu
-            .debug
-            .as_mut()
-            .unwrap()
-            .translate_address(start_addr.try_into().unwrap())
-            .ok_or(TargetError::Errno(Self::GDB_ENOENT))?;
+            // Return what we have read if some pages is not accessible.
+            let addr = match self.translate_vaddr(addr, tid) {
+                Ok(v) => v,
+                Err(e) if off == 0 => return Err(e),
+                Err(_) => break,
+            };
 
-        // Get data.
-        let src = self
-            .hv
-            .ram()
-            .lock(addr, len)
-            .ok_or(TargetError::Errno(Self::GDB_EFAULT))?;
+            let src = match self.hv.ram().lock(addr, len) {
+                Some(v) => v,
+                None if off == 0 => return Err(TargetError::Errno(Self::GDB_EFAULT)),
+                None => break,
+            };
 
-        data.copy_from_slice(unsafe { std::slice::from_raw_parts(src.as_ptr(), src.len().get()) });
+            data[off..(off + len.get())]
+                .copy_from_slice(unsafe { std::slice::from_raw_parts(src.as_ptr(), len.get()) });
 
-        Ok(len.get())
+            off += len.get();
+        }
+
+        Ok(off)
     }
 
     fn write_addrs(&mut self, start_addr: u64, data: &[u8], tid: Tid) -> TargetResult<(), Self> {
-        todo!()
+        let vaddr = usize::try_from(start_addr).unwrap();
+        let mut off = 0;
+
+        // Translate all pages first so we don't do a partial write.
+        let mut pages = Vec::new();
+
+        while off < data.len() {
+            let (addr, len) = next_page(vaddr, off, data.len());
+
+            pages.push((off, self.translate_vaddr(addr, tid)?, len));
+
+            off += len.get();
+        }
+
+        // Set data.
+        for (off, addr, len) in pages {
+            let mut dst = self
+                .hv
+                .ram()
+                .lock(addr, len)
+                .ok_or(TargetError::Errno(Self::GDB_EFAULT))?;
+
+            unsafe { std::slice::from_raw_parts_mut(dst.as_mut_ptr(), len.get()) }
+                .copy_from_slice(&data[off..(off + len.get())]);
+        }
+
+        Ok(())
     }
 
     fn list_active_threads(
         &mut self,
         thread_is_active: &mut dyn FnMut(Tid),
     ) -> Result<(), Self::Error> {
-        for id in (0..self.cpus.len()).map(|v| NonZero::new(v + 1).unwrap()) {
+        for id in self.cpus.keys().map(|&v| NonZero::new(v + 1).unwrap()) {
             thread_is_active(id);
         }
 
@@ -1008,12 +2360,16 @@ impl<H: Hypervisor> MultiThreadBase for Vmm<H> {
 
 impl<H: Hypervisor> MultiThreadResume for Vmm<H> {
     fn resume(&mut self) -> Result<(), Self::Error> {
-        self.release();
+        // Apply hardware breakpoints and single-step to all CPUs.
+        let stepping = std::mem::take(&mut self.stepping);
 
-        Ok(())
+        self.resume_parked(|id| stepping.contains(&id))
+            .map_err(|_| GdbError::NotStopped)
     }
 
     fn clear_resume_actions(&mut self) -> Result<(), Self::Error> {
+        self.stepping.clear();
+
         Ok(())
     }
 
@@ -1023,11 +2379,48 @@ impl<H: Hypervisor> MultiThreadResume for Vmm<H> {
         signal: Option<Signal>,
     ) -> Result<(), Self::Error> {
         if let Some(signal) = signal {
-            todo!("set_resume_action_continue with signal {signal:?}");
+            return Err(GdbError::ResumeWithSignal(signal));
         }
 
         Ok(())
     }
+
+    #[inline(always)]
+    fn support_single_step(&mut self) -> Option<MultiThreadSingleStepOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl<H: Hypervisor> MultiThreadSingleStep for Vmm<H> {
+    fn set_resume_action_step(
+        &mut self,
+        tid: Tid,
+        signal: Option<Signal>,
+    ) -> Result<(), Self::Error> {
+        if let Some(signal) = signal {
+            return Err(GdbError::ResumeWithSignal(signal));
+        }
+
+        self.stepping.insert(tid.get() - 1);
+
+        Ok(())
+    }
+}
+
+/// Returns the address at `off` from `vaddr` and the number of bytes up to the next page boundary
+/// or `len`, whichever come first.
+fn next_page(vaddr: usize, off: usize, len: usize) -> (usize, NonZero<usize>) {
+    let addr = vaddr.wrapping_add(off);
+    let n = (TRANSLATE_GRANULE - addr % TRANSLATE_GRANULE).min(len - off);
+
+    (addr, NonZero::new(n).unwrap())
+}
+
+/// Returns `true` if `addr` is within one of `guards`.
+fn is_guarded(guards: &[(usize, NonZero<usize>)], addr: usize) -> bool {
+    guards
+        .iter()
+        .any(|&(start, len)| addr >= start && addr - start < len.get())
 }
 
 /// Contains objects to control a CPU from outside.
@@ -1040,12 +2433,28 @@ struct Cpu {
 /// Encapsulates arguments for a function to run a CPU.
 struct CpuArgs<H> {
     hv: Arc<H>,
-    devices: Arc<DeviceTree>,
-    breakpoint: Arc<Mutex<()>>,
+    devices: Arc<DeviceTree<H>>,
+    ram: Arc<RamPolicy>,
+    guards: Arc<[(usize, NonZero<usize>)]>,
+    suspend: Arc<AtomicBool>,
     logs: Sender<(ConsoleType, String)>,
+    start: Sender<StartCpu>,
+    stop: Sender<(usize, Option<CpuStop>)>,
+    pause: Arc<Pause>,
     shutdown: Arc<AtomicBool>,
 }
 
+/// Reason for a vCPU to enter the debugger.
+#[derive(Clone)]
+enum CpuStop {
+    Debug(DebugEvent),
+    /// The kernel reported a trap via [`Debugger`](crate::hw::Debugger).
+    Trap(GuestTrap),
+    Panic,
+    /// The debugger requested the CPUs to stop.
+    Interrupt,
+}
+
 /// Finalized layout of the RAM before execute the kernel entry point.
 pub struct RamMap {
     page_table: usize,
@@ -1062,6 +2471,8 @@ pub struct RamMap {
 /// Event from VMM.
 pub enum VmmEvent {
     Exit(usize, Result<bool, CpuError>),
+    /// The vCPU has been stopped and waiting for the debugger.
+    Breakpoint(usize),
     Log(ConsoleType, String),
 }
 
@@ -1071,6 +2482,9 @@ pub enum VmmError {
     #[error("couldn't open the kernel")]
     OpenKernel(#[source] KernelError),
 
+    #[error("couldn't compute the hash of the kernel")]
+    HashKernel(#[source] std::io::Error),
+
     #[error("couldn't start enumerating program headers")]
     EnumerateProgramHeaders(#[source] std::io::Error),
 
@@ -1131,6 +2545,15 @@ pub enum VmmError {
     #[error("the kernel has PT_LOAD with zero length")]
     ZeroLengthLoadSegment,
 
+    #[error("{0} is not a valid RAM size")]
+    InvalidRamSize(NonZero<usize>),
+
+    #[error("RAM limit {0} is not multiply by {1}")]
+    InvalidRamLimit(NonZero<usize>, NonZero<usize>),
+
+    #[error("couldn't setup RAM policy")]
+    SetupRamPolicy(#[source] RamPolicyError),
+
     #[error("couldn't setup a hypervisor")]
     SetupHypervisor(#[source] HvError),
 
@@ -1141,6 +2564,9 @@ pub enum VmmError {
     #[error("couldn't allocate RAM for boot memory")]
     AllocBootMem(#[source] RamError),
 
+    #[error("the kernel is too large for KASLR window")]
+    KernelTooLarge,
+
     #[error("couldn't allocate RAM for the kernel")]
     AllocKernel(#[source] RamError),
 
@@ -1162,6 +2588,9 @@ pub enum VmmError {
     #[error("couldn't allocate RAM for kernel config")]
     AllocKernelConfig(#[source] RamError),
 
+    #[error("couldn't allocate guard pages")]
+    AllocGuard(#[source] RamError),
+
     #[error("couldn't allocate RAM for stack")]
     AllocStack(#[source] RamError),
 
@@ -1171,45 +2600,121 @@ pub enum VmmError {
     #[error("the kernel has invalid PT_DYNAMIC")]
     InvalidDynamicLinking,
 
+    #[error("unknown relocation type {1} at {0:#x}")]
+    UnknownRelocation(usize, usize),
+
+    #[error("the kernel has undefined symbol #{0}")]
+    UndefinedSymbol(usize),
+
     #[error("couldn't spawn the main CPU")]
     SpawnMainCpu(#[source] std::io::Error),
+
+    #[error("the snapshot was not created with the same kernel")]
+    IncompatibleSnapshot,
+
+    #[error("couldn't restore RAM at {0:#x}")]
+    RestoreRam(usize, #[source] RamError),
+
+    #[error("no device at {0:#x} to restore")]
+    UnknownSnapshotDevice(usize),
+
+    #[error("couldn't restore {0}")]
+    RestoreDevice(String, #[source] Box<dyn Error + Send + Sync>),
+
+    #[error("the snapshot does not have the main CPU")]
+    NoMainCpuInSnapshot,
+
+    #[error("couldn't spawn CPU #{0}")]
+    SpawnCpu(usize, #[source] std::io::Error),
+}
+
+/// Represents an error when [`Vmm::save()`] fails.
+#[derive(Debug, Error)]
+pub enum SaveError {
+    #[error("vCPU #{0} was not paused in time")]
+    PauseCpu(usize),
+
+    #[error("vCPU #{0} has been exited")]
+    CpuExited(usize),
+
+    #[error("couldn't get states of vCPU #{0}")]
+    SnapshotCpu(usize, #[source] Box<dyn Error + Send + Sync>),
+
+    #[error("couldn't lock RAM at {0:#x}")]
+    LockRam(usize),
+
+    #[error("couldn't write the snapshot")]
+    Write(#[source] SnapshotError),
 }
 
 /// Represents an error when a vCPU fails.
 #[derive(Debug, Error)]
 pub enum CpuError {
+    #[error("the vCPU is already running")]
+    AlreadyRunning,
+
+    #[error("couldn't spawn a thread for vCPU")]
+    Spawn(#[source] std::io::Error),
+
     #[error("couldn't create vCPU")]
     Create(#[source] Box<dyn Error + Send + Sync>),
 
     #[error("couldn't setup vCPU")]
     Setup(#[source] Box<dyn Error + Send + Sync>),
 
+    #[error("couldn't restore vCPU states")]
+    Restore(#[source] Box<dyn Error + Send + Sync>),
+
     #[error("couldn't run vCPU")]
     Run(#[source] Box<dyn Error + Send + Sync>),
 
+    #[error("couldn't inject an interrupt")]
+    Interrupt(#[source] Box<dyn Error + Send + Sync>),
+
     #[error("couldn't execute a VM exited event on a {0}")]
     DeviceExitHandler(String, #[source] Box<dyn Error + Send + Sync>),
 
     #[error("the vCPU attempt to execute a memory-mapped I/O on a non-mapped address {0:#x}")]
     MmioAddr(usize),
 
+    #[error("kernel stack overflow on CPU {0}")]
+    StackOverflow(usize),
+
+    #[error("unexpected debug exit ({1:?}) on CPU {0} without a debugger attached")]
+    UnexpectedDebug(usize, DebugEvent),
+
+    #[error("CPU {0} exited from the VM with an unknown reason")]
+    UnknownExit(usize),
+
+    #[error("couldn't translate the address of trap frame {0:#x}")]
+    TranslateTrapFrame(usize, #[source] Box<dyn Error + Send + Sync>),
+
+    #[error("couldn't lock the trap frame at {0:#x}")]
+    LockTrapFrame(usize),
+
+    #[error("couldn't handle RAM access")]
+    Ram(#[source] RamPolicyError),
+
     #[error("couldn't execute a memory-mapped I/O on a {0}")]
     Mmio(String, #[source] Box<dyn Error + Send + Sync>),
 
     #[error("couldn't get vCPU states")]
     GetStates(#[source] Box<dyn Error + Send + Sync>),
 
+    #[error("couldn't commit vCPU states")]
+    CommitStates(#[source] Box<dyn Error + Send + Sync>),
+
     #[error("couldn't read {0} register")]
     ReadReg(&'static str, #[source] Box<dyn Error + Send + Sync>),
 
-    #[error("couldn't translate address {0:#x}")]
-    TranslateAddr(usize, #[source] Box<dyn Error + Send + Sync>),
+    #[error("couldn't set debug registers")]
+    SetDebug(#[source] Box<dyn Error + Send + Sync>),
 
     #[error("couldn't execute a post VM exit on a {0}")]
     DevicePostExitHandler(String, #[source] Box<dyn Error + Send + Sync>),
 }
 
-/// Represents an error when [`main_cpu()`] fails to reach event loop.
+/// Represents an error when the main CPU fails to reach event loop.
 #[derive(Debug, Error)]
 enum MainCpuError {
     #[error("couldn't get vCPU states")]
@@ -1218,3 +2723,190 @@ enum MainCpuError {
     #[error("couldn't commit vCPU states")]
     CommitCpuStatesFailed(#[source] Box<dyn Error + Send + Sync>),
 }
+
+/// Represents an error when a secondary CPU fails to reach event loop.
+#[derive(Debug, Error)]
+enum SecondaryCpuError {
+    #[error("couldn't get states of CPU #{0}")]
+    GetCpuStatesFailed(usize, #[source] Box<dyn Error + Send + Sync>),
+
+    #[error("couldn't commit states of CPU #{0}")]
+    CommitCpuStatesFailed(usize, #[source] Box<dyn Error + Send + Sync>),
+
+    #[cfg(target_arch = "x86_64")]
+    #[error("invalid page table address {1:#x} for CPU #{0}")]
+    InvalidPageTable(usize, usize),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relocate() {
+        let mut kern = vec![0u8; 0x1000];
+        let base = 0xffffffff82200000usize;
+        let write = |kern: &mut [u8], off: usize, v: usize| {
+            kern[off..(off + 8)].copy_from_slice(&v.to_ne_bytes())
+        };
+
+        // PT_DYNAMIC.
+        let dynamic = [
+            (6, 0x400),  // DT_SYMTAB
+            (11, 24),    // DT_SYMENT
+            (7, 0x300),  // DT_RELA
+            (8, 24 * 4), // DT_RELASZ
+            (9, 24),     // DT_RELAENT
+            (36, 0x200), // DT_RELR
+            (35, 16),    // DT_RELRSZ
+            (37, 8),     // DT_RELRENT
+            (0, 0),      // DT_NULL
+        ];
+
+        for (i, (tag, val)) in dynamic.into_iter().enumerate() {
+            write(&mut kern, 0x100 + i * 16, tag);
+            write(&mut kern, 0x100 + i * 16 + 8, val);
+        }
+
+        // DT_RELR with an address followed by a bitmap for the next 1st and 3rd words.
+        write(&mut kern, 0x200, 0x800);
+        write(&mut kern, 0x208, (0b101 << 1) | 1);
+
+        for (i, v) in [0x10, 0x20, 0x30, 0x40].into_iter().enumerate() {
+            write(&mut kern, 0x800 + i * 8, v);
+        }
+
+        // DT_RELA. R_<ARCH>_NONE must not stop the remaining relocations.
+        let rela = [
+            (0x900, 0, 0),
+            (0x908, RELOCATE_TYPE, 0x50),
+            (0x910, (1 << 32) | RELOCATE_ABS, 8),
+            (0x918, (1 << 32) | RELOCATE_GLOB_DAT, 8),
+        ];
+
+        for (i, (off, info, addend)) in rela.into_iter().enumerate() {
+            write(&mut kern, 0x300 + i * 24, off);
+            write(&mut kern, 0x300 + i * 24 + 8, info);
+            write(&mut kern, 0x300 + i * 24 + 16, addend);
+        }
+
+        // Symbol #1 is a global function defined in the first section.
+        kern[0x418 + 4] = (1 << 4) | 2;
+        kern[(0x418 + 6)..(0x418 + 8)].copy_from_slice(&1u16.to_ne_bytes());
+        write(&mut kern, 0x418 + 8, 0x600);
+
+        // Apply.
+        Vmm::relocate(&mut kern, base, 0x100, dynamic.len() * 16).unwrap();
+
+        let read = |off: usize| usize::from_ne_bytes(kern[off..(off + 8)].try_into().unwrap());
+        let sym = base + 0x600;
+
+        assert_eq!(read(0x800), base + 0x10);
+        assert_eq!(read(0x808), base + 0x20);
+        assert_eq!(read(0x810), 0x30);
+        assert_eq!(read(0x818), base + 0x40);
+        assert_eq!(read(0x900), 0);
+        assert_eq!(read(0x908), base + 0x50);
+        assert_eq!(read(0x910), sym + 8);
+        assert_eq!(read(0x918), if GLOB_DAT_ADDEND { sym + 8 } else { sym });
+    }
+
+    #[test]
+    fn pages() {
+        let page = TRANSLATE_GRANULE;
+        let split = |vaddr: usize, len: usize| {
+            let mut off = 0;
+            let mut pages = Vec::new();
+
+            while off < len {
+                let (addr, n) = next_page(vaddr, off, len);
+
+                pages.push((addr, n.get()));
+                off += n.get();
+            }
+
+            pages
+        };
+
+        assert_eq!(split(page, 16), [(page, 16)]);
+        assert_eq!(split(page, page), [(page, page)]);
+        assert_eq!(
+            split(page * 2 - 8, page + 16),
+            [(page * 2 - 8, 8), (page * 2, page), (page * 3, 8)]
+        );
+    }
+
+    #[test]
+    fn guarded() {
+        let page = NonZero::new(0x4000).unwrap();
+        let stack = 0x100000;
+        let guards = [(0x10000, page), (0x10000 + page.get() + stack, page)];
+
+        // Overflow from the top of the second stack.
+        assert!(is_guarded(
+            &guards,
+            0x10000 + page.get() + stack + page.get() - 1
+        ));
+        assert!(is_guarded(&guards, 0x10000 + page.get() + stack));
+        assert!(!is_guarded(&guards, 0x10000 + page.get() + stack - 1));
+        assert!(!is_guarded(&guards, 0x10000 + page.get()));
+        assert!(!is_guarded(&guards, 0xffff));
+        assert!(!is_guarded(&guards, usize::MAX));
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn restore_debug() {
+        let ram_size = NonZero::new(0x1000000).unwrap();
+        let page_size = NonZero::new(0x4000).unwrap();
+        let new = || hv::new_emulator(1, ram_size, page_size, false).unwrap();
+
+        // Take the states of a fresh CPU.
+        let cpu = new().create_cpu(0).unwrap().snapshot().unwrap();
+
+        // Restore.
+        let hv = new();
+        let block = hv.ram().block_size();
+        let part = Part::new(std::env::temp_dir());
+        let devices = Arc::new(setup_devices(ram_size.get(), block, &part, false));
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let snapshot = Snapshot {
+            vm_page_size: page_size,
+            ram_size,
+            kern_hash: 0,
+            kern_vaddr: KERNEL_BASE,
+            guards: Vec::new(),
+            ram: vec![(0, vec![0xcc; block.get()])],
+            cpus: vec![(0, cpu)],
+            devices: Vec::new(),
+        };
+
+        let mut vmm = Vmm::<()>::restore(
+            hv,
+            devices,
+            ram_size,
+            RamCommit::Exit,
+            Path::new("kernel"),
+            snapshot,
+            true,
+            &shutdown,
+        )
+        .unwrap();
+
+        // The CPU must be parked for the debugger before it execute anything.
+        assert!(matches!(
+            futures::executor::block_on(vmm.recv()),
+            VmmEvent::Breakpoint(0)
+        ));
+
+        assert!(vmm.cpus[&0].debug.is_some());
+
+        vmm.lock();
+
+        // Check RAM.
+        let mem = vmm.hv.ram().lock(0, block).unwrap();
+        let mem = unsafe { std::slice::from_raw_parts(mem.as_ptr(), mem.len().get()) };
+
+        assert!(mem.iter().all(|&b| b == 0xcc));
+    }
+}
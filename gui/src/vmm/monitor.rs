@@ -0,0 +1,304 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+use super::{CpuStop, Vmm};
+use erdp::ErrorDisplay;
+use hv::{DebugEvent, Hypervisor, Ram};
+use std::path::PathBuf;
+
+impl<H: Hypervisor> Vmm<H> {
+    /// Execute a `monitor` command from the debugger and returns its output.
+    pub(super) fn exec_monitor(&mut self, cmd: &str) -> String {
+        let mut args = cmd.split_whitespace();
+        let cmd = match args.next() {
+            Some(v) => v,
+            None => return String::new(),
+        };
+
+        let args = args.collect::<Vec<_>>();
+
+        match cmd {
+            "cpus" => self.monitor_cpus(),
+            "devices" => self.monitor_devices(),
+            "help" => Self::monitor_help(),
+            "log" => self.monitor_log(&args),
+            "pagewalk" => self.monitor_pagewalk(&args),
+            "panic" => self.monitor_panic(),
+            "ram" => self.monitor_ram(),
+            "snapshot" => self.monitor_snapshot(&args),
+            "translate" => self.monitor_translate(&args),
+            "vmstat" => self.monitor_vmstat(),
+            v => format!("Unknown command '{v}'. Use 'monitor help' to list available commands.\n"),
+        }
+    }
+
+    fn monitor_help() -> String {
+        let mut out = String::new();
+
+        out.push_str("cpus              List all vCPUs and its state.\n");
+        out.push_str("devices           List all virtual devices.\n");
+        out.push_str("log [N]           Show N recent kernel logs (default to 10).\n");
+        out.push_str("pagewalk VADDR    Walk the page table of the stopped vCPU for VADDR.\n");
+        out.push_str("panic             Show the last error from the kernel.\n");
+        out.push_str("ram               List allocated RAM.\n");
+        out.push_str("snapshot [PATH]   Save the VM to PATH (default to the profile snapshot).\n");
+        out.push_str("translate VADDR   Translate VADDR with the stopped vCPU.\n");
+        out.push_str("vmstat            Show the last memory statistics from the kernel.\n");
+
+        out
+    }
+
+    fn monitor_cpus(&mut self) -> String {
+        let mut ids = self.cpus.keys().copied().collect::<Vec<_>>();
+        let mut out = String::new();
+
+        ids.sort_unstable();
+
+        for id in ids {
+            let cpu = &self.cpus[&id];
+            let state = match &self.stopped {
+                Some((v, stop)) if *v == id => match stop {
+                    Some(CpuStop::Debug(DebugEvent::SwBreak)) => "stopped (software breakpoint)",
+                    Some(CpuStop::Debug(DebugEvent::HwBreak)) => "stopped (hardware breakpoint)",
+                    Some(CpuStop::Debug(DebugEvent::Step)) => "stopped (single-step)",
+                    Some(CpuStop::Debug(DebugEvent::Guest(_))) => "stopped (guest exception)",
+                    Some(CpuStop::Debug(_)) => "stopped (watchpoint)",
+                    Some(CpuStop::Trap(_)) => "stopped (kernel trap)",
+                    Some(CpuStop::Panic) => "stopped (kernel panic)",
+                    Some(CpuStop::Interrupt) => "stopped (interrupted)",
+                    None => "stopped",
+                },
+                _ if cpu.thread.is_finished() => "exited",
+                _ => "running",
+            };
+
+            out.push_str(&format!("CPU {id}: {state}\n"));
+        }
+
+        out
+    }
+
+    fn monitor_devices(&mut self) -> String {
+        let mut out = String::new();
+
+        for (addr, dev) in self.devices.all() {
+            let end = addr + dev.len().get();
+
+            out.push_str(&format!("{addr:#x}-{end:#x} {}\n", dev.name()));
+        }
+
+        out
+    }
+
+    fn monitor_log(&mut self, args: &[&str]) -> String {
+        let n = match args {
+            [] => 10,
+            [v] => match v.parse::<usize>() {
+                Ok(v) => v,
+                Err(_) => return format!("'{v}' is not a valid number.\n"),
+            },
+            _ => return "Usage: log [N]\n".into(),
+        };
+
+        let skip = self.recent_logs.len().saturating_sub(n);
+        let mut out = String::new();
+
+        for (_, msg) in self.recent_logs.iter().skip(skip) {
+            out.push_str(msg);
+
+            if !msg.ends_with('\n') {
+                out.push('\n');
+            }
+        }
+
+        out
+    }
+
+    fn monitor_pagewalk(&mut self, args: &[&str]) -> String {
+        let vaddr = match args {
+            [v] => match parse_addr(v) {
+                Some(v) => v,
+                None => return format!("'{v}' is not a valid address.\n"),
+            },
+            _ => return "Usage: pagewalk VADDR\n".into(),
+        };
+
+        // Get page table of the stopped CPU.
+        let id = match self.stopped_thread(None) {
+            Ok(v) => v.get() - 1,
+            Err(_) => return "The target is running.\n".into(),
+        };
+
+        let debug = match self.cpus.get_mut(&id).and_then(|c| c.debug.as_mut()) {
+            Some(v) => v,
+            None => return format!("CPU {id} is not debuggable.\n"),
+        };
+
+        let root = match debug.get_page_table() {
+            Some(Some(Ok(v))) => v,
+            Some(Some(Err(e))) => return format!("Couldn't get page table of CPU {id}: {e}.\n"),
+            Some(None) => return "Page table is not available on this architecture.\n".into(),
+            None => return format!("CPU {id} has been exited.\n"),
+        };
+
+        page_walk(self.hv.ram(), root, vaddr)
+    }
+
+    fn monitor_panic(&mut self) -> String {
+        match &self.last_error {
+            Some(v) => format!("{}\n", v.trim_end()),
+            None => "The kernel has not reported any error.\n".into(),
+        }
+    }
+
+    fn monitor_ram(&mut self) -> String {
+        let mut out = String::new();
+
+        for (addr, len) in self.hv.ram().allocated() {
+            let end = addr + len.get();
+
+            out.push_str(&format!("{addr:#x}-{end:#x} ({} bytes)\n", len.get()));
+        }
+
+        out.push_str(&format!(
+            "Committed {} of {} bytes.\n",
+            self.ram.committed(),
+            self.ram.limit()
+        ));
+
+        out
+    }
+
+    fn monitor_snapshot(&mut self, args: &[&str]) -> String {
+        let path = match args {
+            [] => match &self.snapshot {
+                Some(v) => v.clone(),
+                None => return "No default snapshot path for this VM.\n".into(),
+            },
+            [v] => PathBuf::from(v),
+            _ => return "Usage: snapshot [PATH]\n".into(),
+        };
+
+        match self.save(&path) {
+            Ok(_) => format!("Snapshot saved to {}.\n", path.display()),
+            Err(e) => format!(
+                "Couldn't save snapshot to {}: {}.\n",
+                path.display(),
+                e.display()
+            ),
+        }
+    }
+
+    fn monitor_translate(&mut self, args: &[&str]) -> String {
+        let vaddr = match args {
+            [v] => match parse_addr(v) {
+                Some(v) => v,
+                None => return format!("'{v}' is not a valid address.\n"),
+            },
+            _ => return "Usage: translate VADDR\n".into(),
+        };
+
+        // Translate with the stopped CPU.
+        let id = match self.stopped_thread(None) {
+            Ok(v) => v.get() - 1,
+            Err(_) => return "The target is running.\n".into(),
+        };
+
+        let debug = match self.cpus.get_mut(&id).and_then(|c| c.debug.as_mut()) {
+            Some(v) => v,
+            None => return format!("CPU {id} is not debuggable.\n"),
+        };
+
+        match debug.translate_address(vaddr) {
+            Some(Ok(v)) => format!("{vaddr:#x} -> {v:#x}\n"),
+            Some(Err(e)) => format!("Couldn't translate {vaddr:#x}: {e}.\n"),
+            None => format!("CPU {id} has been exited.\n"),
+        }
+    }
+
+    fn monitor_vmstat(&mut self) -> String {
+        match &self.last_stats {
+            Some(v) => format!("{}\n", v.trim_end()),
+            None => "The kernel has not reported any statistics.\n".into(),
+        }
+    }
+}
+
+/// Walk the page table at `root` for `vaddr` and returns the visited entries.
+fn page_walk(ram: &Ram, root: usize, vaddr: usize) -> String {
+    let mut out = format!("Page table: {root:#x}\n");
+    let paddr = super::arch::page_walk(ram, root, vaddr, |name, addr, entry| {
+        out.push_str(&format!("{name:<5} at {addr:#x}: {entry:#018x}\n"));
+    });
+
+    match paddr {
+        Some(v) => out.push_str(&format!("{vaddr:#x} -> {v:#x}\n")),
+        None => out.push_str(&format!("{vaddr:#x} is not mapped.\n")),
+    }
+
+    out
+}
+
+/// Parse address in either hexadecimal (with `0x` prefix) or decimal.
+fn parse_addr(v: &str) -> Option<usize> {
+    match v.strip_prefix("0x").or_else(|| v.strip_prefix("0X")) {
+        Some(v) => usize::from_str_radix(v, 16).ok(),
+        None => v.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::num::NonZero;
+
+    #[test]
+    fn addr() {
+        assert_eq!(parse_addr("0x1000"), Some(0x1000));
+        assert_eq!(parse_addr("0XFfFf"), Some(0xffff));
+        assert_eq!(parse_addr("4096"), Some(4096));
+        assert_eq!(parse_addr("0x"), None);
+        assert_eq!(parse_addr("1000h"), None);
+        assert_eq!(parse_addr("-1"), None);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn pagewalk() {
+        use hv::Hypervisor;
+
+        let hv = hv::new_emulator(
+            1,
+            NonZero::new(0x100000).unwrap(),
+            NonZero::new(0x4000).unwrap(),
+            false,
+        )
+        .unwrap();
+        let ram = hv.ram();
+        let mut mem = ram.alloc(0, ram.block_size()).unwrap();
+        let data = unsafe { mem.as_mut_slice() };
+        let mut write = |off: usize, v: u64| data[off..(off + 8)].copy_from_slice(&v.to_le_bytes());
+
+        // Map 0x40200000 to 0x200000 with a 2MB page.
+        write(0x0000, 0x1003);
+        write(0x1008, 0x2003);
+        write(0x2008, 0x200083);
+
+        drop(mem);
+
+        assert_eq!(
+            page_walk(ram, 0, 0x40201234),
+            "Page table: 0x0\n\
+            PML4E at 0x0: 0x0000000000001003\n\
+            PDPTE at 0x1008: 0x0000000000002003\n\
+            PDE   at 0x2008: 0x0000000000200083\n\
+            0x40201234 -> 0x201234\n"
+        );
+
+        assert_eq!(
+            page_walk(ram, 0, 0x80000000),
+            "Page table: 0x0\n\
+            PML4E at 0x0: 0x0000000000001003\n\
+            PDPTE at 0x1010: 0x0000000000000000\n\
+            0x80000000 is not mapped.\n"
+        );
+    }
+}
@@ -0,0 +1,104 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Pause the running vCPUs at the point where its states are consistent.
+///
+/// Each vCPU thread check [`Pause::is_requested()`] before enter the VM and call [`Pause::wait()`]
+/// if it return `true`.
+#[derive(Default)]
+pub struct Pause {
+    requested: AtomicBool,
+    state: Mutex<PauseState>,
+    cv: Condvar,
+}
+
+impl Pause {
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::Relaxed)
+    }
+
+    /// Request all vCPUs to pause. The request is active until the returned [`Paused`] is
+    /// dropped.
+    pub fn request(&self) -> Paused<'_> {
+        let mut s = self.state.lock().unwrap();
+
+        s.requested = true;
+        s.cpus.clear();
+
+        self.requested.store(true, Ordering::Relaxed);
+
+        Paused(self)
+    }
+
+    /// Take a snapshot of `cpu` then block until the request is dropped.
+    pub fn wait(&self, cpu: &mut impl hv::Cpu) {
+        let mut s = self.state.lock().unwrap();
+
+        if !s.requested {
+            return;
+        }
+
+        let data = cpu
+            .snapshot()
+            .map_err(|e| Box::new(e) as Box<dyn Error + Send + Sync>);
+
+        s.cpus.insert(cpu.id(), data);
+        self.cv.notify_all();
+
+        while s.requested {
+            s = self.cv.wait(s).unwrap();
+        }
+    }
+}
+
+/// RAII struct to resume the vCPUs paused by [`Pause`].
+pub struct Paused<'a>(&'a Pause);
+
+impl Paused<'_> {
+    /// Wait until all vCPUs in `cpus` are paused or `timeout` elapsed then returns the snapshot of
+    /// the paused vCPUs.
+    pub fn collect(&self, cpus: &[usize], timeout: Duration) -> BTreeMap<usize, CpuSnapshot> {
+        let end = Instant::now() + timeout;
+        let mut s = self.0.state.lock().unwrap();
+
+        loop {
+            if cpus.iter().all(|id| s.cpus.contains_key(id)) {
+                break;
+            }
+
+            let now = Instant::now();
+
+            if now >= end {
+                break;
+            }
+
+            s = self.0.cv.wait_timeout(s, end - now).unwrap().0;
+        }
+
+        std::mem::take(&mut s.cpus)
+    }
+}
+
+impl Drop for Paused<'_> {
+    fn drop(&mut self) {
+        let mut s = self.0.state.lock().unwrap();
+
+        s.requested = false;
+
+        self.0.requested.store(false, Ordering::Relaxed);
+        self.0.cv.notify_all();
+    }
+}
+
+/// Result of [`hv::Cpu::snapshot()`] from a paused vCPU.
+pub type CpuSnapshot = Result<Vec<u8>, Box<dyn Error + Send + Sync>>;
+
+#[derive(Default)]
+struct PauseState {
+    requested: bool,
+    cpus: BTreeMap<usize, CpuSnapshot>,
+}
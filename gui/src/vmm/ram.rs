@@ -0,0 +1,272 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+use hv::{CpuIo, IoBuf, Ram, RamError};
+use std::num::NonZero;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use thiserror::Error;
+
+/// Policy to commit the RAM only when the VM touch it.
+///
+/// With [`RamCommit::Exit`] the hypervisor will report an access to the RAM that has not been
+/// allocated as a memory-mapped I/O. We allocate the touched blocks and complete the I/O with it.
+/// With [`RamCommit::Host`] the whole RAM is allocated up front and the host commit the pages when
+/// the VM touch it. The VM can return the memory with [`RamPolicy::release()`].
+pub struct RamPolicy {
+    limit: NonZero<usize>,
+    committed: AtomicUsize,
+    commit: RamCommit,
+}
+
+impl RamPolicy {
+    /// `limit` is a maximum size of the RAM that can be committed, including the memory that
+    /// already allocated on `ram`. It can't be less than the size of `ram` with
+    /// [`RamCommit::Host`] since we don't know which pages the host has committed.
+    pub fn new(
+        ram: &Ram,
+        limit: NonZero<usize>,
+        commit: RamCommit,
+    ) -> Result<Self, RamPolicyError> {
+        // Allocate the blocks that was not allocated.
+        if commit == RamCommit::Host {
+            if limit < ram.len() {
+                return Err(RamPolicyError::LimitNotSupported(limit));
+            }
+
+            let mut next = 0;
+            let alloc = |addr: usize, end: usize| match NonZero::new(end - addr) {
+                Some(len) => ram
+                    .alloc(addr, len)
+                    .map(|_| ())
+                    .map_err(|e| RamPolicyError::Alloc(addr, e)),
+                None => Ok(()),
+            };
+
+            for (addr, len) in ram.allocated() {
+                alloc(next, addr)?;
+                next = addr + len.get();
+            }
+
+            alloc(next, ram.len().get())?;
+        }
+
+        let committed = ram.allocated().into_iter().map(|(_, len)| len.get()).sum();
+
+        Ok(Self {
+            limit,
+            committed: AtomicUsize::new(committed),
+            commit,
+        })
+    }
+
+    pub fn limit(&self) -> NonZero<usize> {
+        self.limit
+    }
+
+    /// Returns the size of the RAM that has been committed, in bytes. This is always the size of
+    /// the whole RAM with [`RamCommit::Host`].
+    pub fn committed(&self) -> usize {
+        self.committed.load(Ordering::Relaxed)
+    }
+
+    /// Returns `false` if the I/O is not targeted the RAM.
+    pub fn handle(&self, ram: &Ram, io: &mut impl CpuIo) -> Result<bool, RamPolicyError> {
+        // Check if RAM.
+        let addr = io.addr();
+
+        if addr >= ram.len().get() {
+            return Ok(false);
+        }
+
+        // Allocate the blocks that was not allocated. Some of it might already allocated by the
+        // other vCPU.
+        let buf = io.buffer();
+        let len = match &buf {
+            IoBuf::Write(v) => v.len(),
+            IoBuf::Read(v) => v.len(),
+        };
+
+        let len = NonZero::new(len).unwrap();
+        let end = addr
+            .checked_add(len.get())
+            .filter(|&v| v <= ram.len().get())
+            .ok_or(RamPolicyError::InvalidAccess(addr))?;
+        let block_size = ram.block_size();
+        let mut next = addr - addr % block_size;
+
+        while next < end {
+            self.alloc(ram, next, block_size)?;
+            next += block_size.get();
+        }
+
+        // Complete the I/O. The RAM may be released by the other vCPU between the allocation and
+        // here.
+        let mut mem = ram
+            .lock(addr, len)
+            .ok_or(RamPolicyError::InvalidAccess(addr))?;
+
+        match buf {
+            IoBuf::Write(v) => unsafe { mem.as_mut_ptr().copy_from(v.as_ptr(), v.len()) },
+            IoBuf::Read(v) => unsafe { mem.as_ptr().copy_to(v.as_mut_ptr(), v.len()) },
+        }
+
+        Ok(true)
+    }
+
+    /// # Panics
+    /// If `addr` or `len` is not multiply by block size.
+    pub fn release(&self, ram: &Ram, addr: usize, len: NonZero<usize>) -> Result<(), RamError> {
+        if self.commit == RamCommit::Host {
+            return ram.discard(addr, len);
+        }
+
+        // Get the size that will be decommitted.
+        let end = addr.checked_add(len.get()).ok_or(RamError::InvalidAddr)?;
+        let released = ram
+            .allocated()
+            .into_iter()
+            .map(|(start, len)| (start.max(addr), (start + len.get()).min(end)))
+            .filter_map(|(start, end)| end.checked_sub(start))
+            .sum();
+
+        ram.dealloc(addr, len)?;
+
+        self.committed.fetch_sub(released, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    fn alloc(&self, ram: &Ram, addr: usize, len: NonZero<usize>) -> Result<(), RamPolicyError> {
+        if ram.lock(addr, len).is_some() {
+            return Ok(());
+        }
+
+        // Reserve the quota.
+        let limit = self.limit.get();
+
+        self.committed
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| {
+                v.checked_add(len.get()).filter(move |&v| v <= limit)
+            })
+            .map_err(|_| RamPolicyError::LimitExceeded(self.limit))?;
+
+        // Allocate.
+        let e = match ram.alloc(addr, len) {
+            Ok(_) => return Ok(()),
+            Err(RamError::AlreadyAllocated) => None,
+            Err(e) => Some(e),
+        };
+
+        self.committed.fetch_sub(len.get(), Ordering::Relaxed);
+
+        match e {
+            Some(e) => Err(RamPolicyError::Alloc(addr, e)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// How the RAM get committed when the VM touch it.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum RamCommit {
+    /// The hypervisor report an access to the RAM that has not been allocated as a memory-mapped
+    /// I/O.
+    Exit,
+    /// The host commit the pages on the first access.
+    Host,
+}
+
+/// Represents an error when [`RamPolicy::new()`] or [`RamPolicy::handle()`] fails.
+#[derive(Debug, Error)]
+pub enum RamPolicyError {
+    #[error("invalid RAM access at {0:#x}")]
+    InvalidAccess(usize),
+
+    #[error("committed RAM has reached the limit of {0} bytes")]
+    LimitExceeded(NonZero<usize>),
+
+    #[error("RAM limit of {0} bytes is not supported by the hypervisor")]
+    LimitNotSupported(NonZero<usize>),
+
+    #[error("couldn't allocate RAM at {0:#x}")]
+    Alloc(usize, #[source] RamError),
+}
+
+#[cfg(all(test, target_arch = "x86_64"))]
+mod tests {
+    use super::*;
+    use hv::Hypervisor;
+
+    #[test]
+    fn exit() {
+        let hv = new_hv();
+        let ram = hv.ram();
+        let block = ram.block_size();
+
+        ram.alloc(0, block).unwrap();
+
+        let limit = NonZero::new(block.get() * 3).unwrap();
+        let policy = RamPolicy::new(ram, limit, RamCommit::Exit).unwrap();
+
+        assert_eq!(policy.committed(), block.get());
+
+        // Allocate up to the limit.
+        policy.alloc(ram, block.get(), block).unwrap();
+        policy.alloc(ram, block.get(), block).unwrap();
+        policy.alloc(ram, block.get() * 2, block).unwrap();
+
+        assert_eq!(policy.committed(), limit.get());
+        assert!(matches!(
+            policy.alloc(ram, block.get() * 3, block),
+            Err(RamPolicyError::LimitExceeded(v)) if v == limit
+        ));
+
+        // Release a range that is partially allocated.
+        let len = NonZero::new(block.get() * 3).unwrap();
+
+        policy.release(ram, block.get() * 2, len).unwrap();
+
+        assert_eq!(policy.committed(), block.get() * 2);
+        assert_eq!(
+            ram.allocated(),
+            [(0, NonZero::new(block.get() * 2).unwrap())]
+        );
+
+        policy.alloc(ram, block.get() * 3, block).unwrap();
+    }
+
+    #[test]
+    fn host() {
+        let hv = new_hv();
+        let ram = hv.ram();
+        let block = ram.block_size();
+
+        ram.alloc(block.get(), block).unwrap().fill(0xFF);
+
+        let limit = NonZero::new(ram.len().get() - block.get()).unwrap();
+
+        assert!(matches!(
+            RamPolicy::new(ram, limit, RamCommit::Host),
+            Err(RamPolicyError::LimitNotSupported(v)) if v == limit
+        ));
+
+        let policy = RamPolicy::new(ram, ram.len(), RamCommit::Host).unwrap();
+
+        assert_eq!(policy.committed(), ram.len().get());
+        assert_eq!(ram.allocated(), [(0, ram.len())]);
+
+        // The released range must stay allocated with zeroes.
+        policy.release(ram, block.get(), block).unwrap();
+
+        let mem = ram.lock(block.get(), block).unwrap();
+        let data = unsafe { std::slice::from_raw_parts(mem.as_ptr(), block.get()) };
+
+        assert!(data.iter().all(|&v| v == 0));
+        assert_eq!(policy.committed(), ram.len().get());
+    }
+
+    fn new_hv() -> impl Hypervisor {
+        let ram = NonZero::new(0x100000).unwrap();
+        let page = NonZero::new(0x4000).unwrap();
+
+        hv::new_emulator(1, ram, page, false).unwrap()
+    }
+}
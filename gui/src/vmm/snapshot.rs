@@ -0,0 +1,265 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::num::NonZero;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+const MAGIC: [u8; 8] = *b"OBVMSNAP";
+const VERSION: u32 = 4;
+
+/// Contains states of a paused VM.
+///
+/// All integers in the file are little-endian. The file has the following layout:
+///
+/// - Magic and version.
+/// - VM page size, RAM size, kernel hash and kernel base.
+/// - Guard ranges (address and length).
+/// - Allocated RAM ranges (address, length and data).
+/// - vCPU states (identifier and hypervisor-specific data).
+/// - Device states (address and device-specific data).
+pub struct Snapshot {
+    pub vm_page_size: NonZero<usize>,
+    pub ram_size: NonZero<usize>,
+    pub kern_hash: u64,
+    pub kern_vaddr: usize,
+    pub guards: Vec<(usize, NonZero<usize>)>,
+    pub ram: Vec<(usize, Vec<u8>)>,
+    pub cpus: Vec<(usize, Vec<u8>)>,
+    pub devices: Vec<(usize, Vec<u8>)>,
+}
+
+impl Snapshot {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SnapshotError> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|e| SnapshotError::Open(path.into(), e))?;
+        let mut r = Reader(BufReader::new(file));
+
+        Self::read(&mut r).map_err(|e| match e {
+            ReadError::Io(e) => SnapshotError::Read(path.into(), e),
+            ReadError::NotSnapshot => SnapshotError::NotSnapshot(path.into()),
+            ReadError::UnsupportedVersion(v) => SnapshotError::UnsupportedVersion(v),
+            ReadError::InvalidData => SnapshotError::Corrupted(path.into()),
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        let path = path.as_ref();
+        let file = File::create(path).map_err(|e| SnapshotError::Create(path.into(), e))?;
+        let mut w = BufWriter::new(file);
+
+        self.write(&mut w)
+            .and_then(|_| w.flush())
+            .map_err(|e| SnapshotError::Write(path.into(), e))
+    }
+
+    fn read<R: Read>(r: &mut Reader<R>) -> Result<Self, ReadError> {
+        // Check header.
+        let mut magic = [0; 8];
+
+        r.0.read_exact(&mut magic)?;
+
+        if magic != MAGIC {
+            return Err(ReadError::NotSnapshot);
+        }
+
+        match r.u32()? {
+            VERSION => (),
+            v => return Err(ReadError::UnsupportedVersion(v)),
+        }
+
+        // Read VM configurations.
+        let vm_page_size = NonZero::new(r.usize()?).ok_or(ReadError::InvalidData)?;
+        let ram_size = NonZero::new(r.usize()?).ok_or(ReadError::InvalidData)?;
+        let kern_hash = r.u64()?;
+        let kern_vaddr = r.usize()?;
+        let mut guards = Vec::new();
+
+        for _ in 0..r.usize()? {
+            let addr = r.usize()?;
+            let len = NonZero::new(r.usize()?).ok_or(ReadError::InvalidData)?;
+
+            guards.push((addr, len));
+        }
+
+        // Read sections.
+        let ram = r.entries()?;
+        let cpus = r.entries()?;
+        let devices = r.entries()?;
+
+        Ok(Self {
+            vm_page_size,
+            ram_size,
+            kern_hash,
+            kern_vaddr,
+            guards,
+            ram,
+            cpus,
+            devices,
+        })
+    }
+
+    fn write(&self, w: &mut impl Write) -> std::io::Result<()> {
+        let int = |v: usize| u64::try_from(v).unwrap().to_le_bytes();
+
+        w.write_all(&MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        w.write_all(&int(self.vm_page_size.get()))?;
+        w.write_all(&int(self.ram_size.get()))?;
+        w.write_all(&self.kern_hash.to_le_bytes())?;
+        w.write_all(&int(self.kern_vaddr))?;
+        w.write_all(&int(self.guards.len()))?;
+
+        for &(addr, len) in &self.guards {
+            w.write_all(&int(addr))?;
+            w.write_all(&int(len.get()))?;
+        }
+
+        for section in [&self.ram, &self.cpus, &self.devices] {
+            w.write_all(&int(section.len()))?;
+
+            for (key, data) in section {
+                w.write_all(&int(*key))?;
+                w.write_all(&int(data.len()))?;
+                w.write_all(data)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Helper to read integers from a snapshot.
+struct Reader<R>(R);
+
+impl<R: Read> Reader<R> {
+    fn u32(&mut self) -> Result<u32, ReadError> {
+        let mut buf = [0; 4];
+
+        self.0.read_exact(&mut buf)?;
+
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn u64(&mut self) -> Result<u64, ReadError> {
+        let mut buf = [0; 8];
+
+        self.0.read_exact(&mut buf)?;
+
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn usize(&mut self) -> Result<usize, ReadError> {
+        usize::try_from(self.u64()?).map_err(|_| ReadError::InvalidData)
+    }
+
+    fn entries(&mut self) -> Result<Vec<(usize, Vec<u8>)>, ReadError> {
+        let n = self.usize()?;
+        let mut entries = Vec::new();
+
+        for _ in 0..n {
+            let key = self.usize()?;
+            let len = self.usize()?;
+            let mut data = Vec::new();
+
+            // Don't trust the length until we actually have the data.
+            (&mut self.0)
+                .take(len.try_into().unwrap())
+                .read_to_end(&mut data)?;
+
+            if data.len() != len {
+                return Err(ReadError::InvalidData);
+            }
+
+            entries.push((key, data));
+        }
+
+        Ok(entries)
+    }
+}
+
+/// Represents an error when [`Snapshot::read()`] fails.
+enum ReadError {
+    Io(std::io::Error),
+    NotSnapshot,
+    UnsupportedVersion(u32),
+    InvalidData,
+}
+
+impl From<std::io::Error> for ReadError {
+    fn from(value: std::io::Error) -> Self {
+        match value.kind() {
+            std::io::ErrorKind::UnexpectedEof => Self::InvalidData,
+            _ => Self::Io(value),
+        }
+    }
+}
+
+/// Represents an error when [`Snapshot`] fails to load or save.
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("couldn't open {0}")]
+    Open(PathBuf, #[source] std::io::Error),
+
+    #[error("couldn't read {0}")]
+    Read(PathBuf, #[source] std::io::Error),
+
+    #[error("{0} is not a VM snapshot")]
+    NotSnapshot(PathBuf),
+
+    #[error("snapshot version {0} is not supported")]
+    UnsupportedVersion(u32),
+
+    #[error("{0} is corrupted")]
+    Corrupted(PathBuf),
+
+    #[error("couldn't create {0}")]
+    Create(PathBuf, #[source] std::io::Error),
+
+    #[error("couldn't write {0}")]
+    Write(PathBuf, #[source] std::io::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let snapshot = Snapshot {
+            vm_page_size: NonZero::new(0x4000).unwrap(),
+            ram_size: NonZero::new(0x1000000).unwrap(),
+            kern_hash: 0x0123456789abcdef,
+            kern_vaddr: 0xffffffff82200000,
+            guards: vec![(0x10000, NonZero::new(0x4000).unwrap())],
+            ram: vec![(0, vec![1; 0x4000]), (0x8000, vec![2; 0x4000])],
+            cpus: vec![(0, vec![3; 16]), (1, Vec::new())],
+            devices: vec![(0x1000000, vec![4; 8])],
+        };
+        let mut data = Vec::new();
+
+        snapshot.write(&mut data).unwrap();
+
+        let r = match Snapshot::read(&mut Reader(data.as_slice())) {
+            Ok(v) => v,
+            Err(_) => panic!("couldn't read the snapshot"),
+        };
+
+        assert_eq!(r.vm_page_size, snapshot.vm_page_size);
+        assert_eq!(r.ram_size, snapshot.ram_size);
+        assert_eq!(r.kern_hash, snapshot.kern_hash);
+        assert_eq!(r.kern_vaddr, snapshot.kern_vaddr);
+        assert_eq!(r.guards, snapshot.guards);
+        assert_eq!(r.ram, snapshot.ram);
+        assert_eq!(r.cpus, snapshot.cpus);
+        assert_eq!(r.devices, snapshot.devices);
+
+        // Truncated.
+        for len in [0, 8, 12, data.len() - 1] {
+            assert!(matches!(
+                Snapshot::read(&mut Reader(&data[..len])),
+                Err(ReadError::InvalidData)
+            ));
+        }
+    }
+}
@@ -1,13 +1,18 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 use super::cpu::GdbError;
-use super::{MainCpuError, RamMap, Vmm};
+use super::cpu::debug::PageTableResult;
+use super::{MainCpuError, RamMap, SecondaryCpuError, Vmm};
+use crate::hw::StartCpu;
 use gdbstub::target::ext::base::BaseOps;
 use gdbstub::target::ext::breakpoints::{
-    Breakpoints, BreakpointsOps, SwBreakpoint, SwBreakpointOps,
+    Breakpoints, BreakpointsOps, HwBreakpoint, HwBreakpointOps, HwWatchpoint, HwWatchpointOps,
+    SwBreakpoint, SwBreakpointOps, WatchKind,
 };
+use gdbstub::target::ext::section_offsets::SectionOffsetsOps;
 use gdbstub::target::{TargetError, TargetResult};
 use gdbstub_arch::x86::X86_64_SSE;
-use hv::{Cpu, CpuCommit, CpuStates, Hypervisor};
+use hv::{Cpu, CpuCommit, CpuStates, HwDebug, Hypervisor, Ram};
+use std::error::Error;
 use std::num::NonZero;
 use x86_64::{Efer, Rflags};
 
@@ -15,6 +20,30 @@ pub type GdbRegs = gdbstub_arch::x86::reg::X86_64CoreRegs;
 
 pub const BREAKPOINT_SIZE: NonZero<usize> = NonZero::new(1).unwrap();
 pub const RELOCATE_TYPE: usize = 8;
+pub const RELOCATE_ABS: usize = 1; // R_X86_64_64
+pub const RELOCATE_GLOB_DAT: usize = 6; // R_X86_64_GLOB_DAT
+pub const GLOB_DAT_ADDEND: bool = false; // R_X86_64_GLOB_DAT is S.
+
+/// Index in [`GdbRegs::regs`] and offset in `TrapFrame` of the kernel for each general purpose
+/// register.
+const TRAP_FRAME_GPRS: [(usize, usize); 16] = [
+    (5, 0x00),  // rdi
+    (4, 0x08),  // rsi
+    (3, 0x10),  // rdx
+    (2, 0x18),  // rcx
+    (8, 0x20),  // r8
+    (9, 0x28),  // r9
+    (0, 0x30),  // rax
+    (1, 0x38),  // rbx
+    (6, 0x40),  // rbp
+    (10, 0x48), // r10
+    (11, 0x50), // r11
+    (12, 0x58), // r12
+    (13, 0x60), // r13
+    (14, 0x68), // r14
+    (15, 0x70), // r15
+    (7, 0xb0),  // rsp
+];
 
 pub fn setup_main_cpu<H: Hypervisor>(
     _: &H,
@@ -22,14 +51,188 @@ pub fn setup_main_cpu<H: Hypervisor>(
     entry: usize,
     map: RamMap,
 ) -> Result<(), MainCpuError> {
-    // Set CR3 to page-map level-4 table.
     let mut states = cpu
         .states()
         .map_err(|e| MainCpuError::GetCpuStatesFailed(Box::new(e)))?;
 
     assert_eq!(map.page_table & 0xFFF0000000000FFF, 0);
 
-    states.set_cr3(map.page_table);
+    setup_long_mode(&mut states, map.page_table);
+
+    // Set entry point, its argument and stack pointer.
+    states.set_rdi(map.map_vaddr);
+    states.set_rsi(map.env_vaddr);
+    states.set_rdx(map.conf_vaddr);
+    states.set_rsp(map.stack_vaddr.checked_add(map.stack_len.get()).unwrap()); // Top-down.
+    states.set_rip(entry);
+    states.set_rflags(Rflags::new().with_reserved(true).with_id(true));
+
+    states
+        .commit()
+        .map_err(|e| MainCpuError::CommitCpuStatesFailed(Box::new(e)))
+}
+
+pub fn setup_secondary_cpu<H: Hypervisor>(
+    _: &H,
+    cpu: &mut H::Cpu<'_>,
+    req: &StartCpu,
+) -> Result<(), SecondaryCpuError> {
+    let mut states = cpu
+        .states()
+        .map_err(|e| SecondaryCpuError::GetCpuStatesFailed(req.id, Box::new(e)))?;
+
+    // The page table is provided by the kernel so we can't assume it is valid.
+    if req.page_table & 0xFFF0000000000FFF != 0 {
+        return Err(SecondaryCpuError::InvalidPageTable(req.id, req.page_table));
+    }
+
+    setup_long_mode(&mut states, req.page_table);
+
+    // Set entry point, its argument and stack pointer.
+    states.set_rdi(req.arg);
+    states.set_rsp(req.stack);
+    states.set_rip(req.entry);
+    states.set_rflags(Rflags::new().with_reserved(true).with_id(true));
+
+    states
+        .commit()
+        .map_err(|e| SecondaryCpuError::CommitCpuStatesFailed(req.id, Box::new(e)))
+}
+
+/// Returns [`None`] if the stack pointer cannot be retrieved.
+pub fn stack_pointer(cpu: &mut impl Cpu) -> Option<usize> {
+    cpu.states().ok()?.get_rsp().ok()
+}
+
+/// Returns the address of the last page fault or [`None`] if it cannot be retrieved.
+pub fn fault_addr(cpu: &mut impl Cpu) -> Option<usize> {
+    cpu.states().ok()?.get_cr2().ok()
+}
+
+/// Returns [`None`] if the page table is not available on this architecture.
+pub fn page_table(cpu: &mut impl Cpu) -> Option<PageTableResult> {
+    let r = match cpu.states() {
+        Ok(mut v) => v
+            .get_cr3()
+            .map_err(|e| Box::new(e) as Box<dyn Error + Send + Sync>),
+        Err(e) => Err(Box::new(e) as Box<dyn Error + Send + Sync>),
+    };
+
+    Some(r)
+}
+
+/// Walk the page table at `root` to translate `vaddr`.
+///
+/// Each entry that was visited will be passed to `f` with its name, physical address and value.
+/// Returns [`None`] if `vaddr` is not mapped.
+pub fn page_walk(
+    ram: &Ram,
+    root: usize,
+    vaddr: usize,
+    mut f: impl FnMut(&str, usize, u64),
+) -> Option<usize> {
+    const ADDR_MASK: u64 = 0x000FFFFFFFFFF000;
+    const LEVELS: [(&str, u32); 4] = [("PML4E", 39), ("PDPTE", 30), ("PDE", 21), ("PTE", 12)];
+    let mut table = root & ADDR_MASK as usize;
+
+    for (level, (name, shift)) in LEVELS.into_iter().enumerate() {
+        let addr = table + ((vaddr >> shift) & 0x1FF) * 8;
+        let entry = ram.lock(addr, const { NonZero::new(8).unwrap() })?;
+        let entry = unsafe { entry.as_ptr().cast::<u64>().read_unaligned() };
+
+        f(name, addr, entry);
+
+        // Check if present.
+        if entry & 1 == 0 {
+            return None;
+        }
+
+        // Check if the entry map a page (PS bit on PDPTE and PDE).
+        let base = (entry & ADDR_MASK) as usize;
+        let mask = (1usize << shift) - 1;
+
+        if level == LEVELS.len() - 1 || (level != 0 && entry & 0x80 != 0) {
+            return Some((base & !mask) | (vaddr & mask));
+        }
+
+        table = base;
+    }
+
+    unreachable!()
+}
+
+/// Replace the registers in `regs` with the one saved in `TrapFrame` of the kernel.
+pub fn apply_trap_frame(regs: &mut GdbRegs, frame: &[u8]) {
+    let load = |off: usize, len: usize| {
+        frame.get(off..(off + len)).map(|v| {
+            let mut b = [0; 8];
+            b[..len].copy_from_slice(v);
+            u64::from_le_bytes(b)
+        })
+    };
+
+    for (i, off) in TRAP_FRAME_GPRS {
+        if let Some(v) = load(off, 8) {
+            regs.regs[i] = v;
+        }
+    }
+
+    if let Some(v) = load(0x98, 8) {
+        regs.rip = v;
+    }
+
+    if let Some(v) = load(0xa0, 8) {
+        regs.segments.cs = v as u32;
+    }
+
+    if let Some(v) = load(0xa8, 8) {
+        regs.eflags = v as u32;
+    }
+
+    if let Some(v) = load(0xb8, 8) {
+        regs.segments.ss = v as u32;
+    }
+
+    // The segment registers is available only when TF_HASSEGS is set.
+    if load(0x88, 4).is_some_and(|v| v & 1 != 0) {
+        let segs = [
+            (&mut regs.segments.fs, 0x7c),
+            (&mut regs.segments.gs, 0x7e),
+            (&mut regs.segments.es, 0x8c),
+            (&mut regs.segments.ds, 0x8e),
+        ];
+
+        for (r, off) in segs {
+            if let Some(v) = load(off, 2) {
+                *r = v as u32;
+            }
+        }
+    }
+}
+
+/// Write the registers in `regs` to `TrapFrame` of the kernel.
+///
+/// Only the registers that the kernel restores when returning from the trap are written.
+pub fn store_trap_frame(regs: &GdbRegs, frame: &mut [u8]) {
+    let mut store = |off: usize, v: u64| {
+        if let Some(d) = frame.get_mut(off..(off + 8)) {
+            d.copy_from_slice(&v.to_le_bytes());
+        }
+    };
+
+    for (i, off) in TRAP_FRAME_GPRS {
+        store(off, regs.regs[i]);
+    }
+
+    store(0x98, regs.rip);
+    store(0xa0, regs.segments.cs.into());
+    store(0xa8, regs.eflags.into());
+    store(0xb8, regs.segments.ss.into());
+}
+
+fn setup_long_mode(states: &mut impl CpuStates, page_table: usize) {
+    // Set CR3 to page-map level-4 table.
+    states.set_cr3(page_table);
 
     // Set CR4.
     let mut cr4 = 0;
@@ -59,18 +262,61 @@ pub fn setup_main_cpu<H: Hypervisor>(
     states.set_fs(true);
     states.set_gs(true);
     states.set_ss(true);
+}
 
-    // Set entry point, its argument and stack pointer.
-    states.set_rdi(map.map_vaddr);
-    states.set_rsi(map.env_vaddr);
-    states.set_rdx(map.conf_vaddr);
-    states.set_rsp(map.stack_vaddr.checked_add(map.stack_len.get()).unwrap()); // Top-down.
-    states.set_rip(entry);
-    states.set_rflags(Rflags::new().with_reserved(true).with_id(true));
+/// Enable a free DRn. Returns `false` if all of DR0-DR3 are in use or the range is not supported.
+fn set_dr(debug: &mut HwDebug, addr: u64, rw: usize, len: u64) -> bool {
+    let Some(len) = dr_len(addr, len) else {
+        return false;
+    };
 
-    states
-        .commit()
-        .map_err(|e| MainCpuError::CommitCpuStatesFailed(Box::new(e)))
+    let Some(i) = (0..4).find(|i| debug.dr7 & (0b11 << (i * 2)) == 0) else {
+        return false;
+    };
+
+    debug.addrs[i] = addr.try_into().unwrap();
+    debug.dr7 |= 0b10 << (i * 2); // Gn.
+    debug.dr7 |= ((len << 2) | rw) << (16 + i * 4);
+
+    true
+}
+
+/// Disable DRn that was enabled by [`set_dr()`] with the same arguments.
+fn clear_dr(debug: &mut HwDebug, addr: u64, rw: usize, len: u64) -> bool {
+    let Some(len) = dr_len(addr, len) else {
+        return false;
+    };
+
+    for i in 0..4 {
+        let enabled = debug.dr7 & (0b11 << (i * 2)) != 0;
+        let cond = (debug.dr7 >> (16 + i * 4)) & 0b1111;
+
+        if enabled && debug.addrs[i] as u64 == addr && cond == (len << 2) | rw {
+            debug.addrs[i] = 0;
+            debug.dr7 &= !(0b11 << (i * 2) | 0b1111 << (16 + i * 4));
+            return true;
+        }
+    }
+
+    false
+}
+
+/// Returns LENn for DR7.
+fn dr_len(addr: u64, len: u64) -> Option<usize> {
+    let v = match len {
+        1 => 0b00,
+        2 => 0b01,
+        4 => 0b11,
+        8 => 0b10,
+        _ => return None,
+    };
+
+    // The address must be aligned to the length.
+    if addr % len != 0 {
+        return None;
+    }
+
+    Some(v)
 }
 
 impl<H: Hypervisor> gdbstub::target::Target for Vmm<H> {
@@ -84,12 +330,67 @@ impl<H: Hypervisor> gdbstub::target::Target for Vmm<H> {
     fn support_breakpoints(&mut self) -> Option<BreakpointsOps<'_, Self>> {
         Some(self)
     }
+
+    fn support_section_offsets(&mut self) -> Option<SectionOffsetsOps<'_, Self>> {
+        Some(self)
+    }
 }
 
 impl<H: Hypervisor> Breakpoints for Vmm<H> {
     fn support_sw_breakpoint(&mut self) -> Option<SwBreakpointOps<'_, Self>> {
         Some(self)
     }
+
+    fn support_hw_breakpoint(&mut self) -> Option<HwBreakpointOps<'_, Self>> {
+        Some(self)
+    }
+
+    fn support_hw_watchpoint(&mut self) -> Option<HwWatchpointOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl<H: Hypervisor> HwBreakpoint for Vmm<H> {
+    fn add_hw_breakpoint(&mut self, addr: u64, _kind: usize) -> TargetResult<bool, Self> {
+        Ok(set_dr(&mut self.hw_debug, addr, 0b00, 1))
+    }
+
+    fn remove_hw_breakpoint(&mut self, addr: u64, _kind: usize) -> TargetResult<bool, Self> {
+        Ok(clear_dr(&mut self.hw_debug, addr, 0b00, 1))
+    }
+}
+
+impl<H: Hypervisor> HwWatchpoint for Vmm<H> {
+    fn add_hw_watchpoint(
+        &mut self,
+        addr: u64,
+        len: u64,
+        kind: WatchKind,
+    ) -> TargetResult<bool, Self> {
+        // x86-64 does not support read-only watchpoint.
+        let rw = match kind {
+            WatchKind::Write => 0b01,
+            WatchKind::Read => return Ok(false),
+            WatchKind::ReadWrite => 0b11,
+        };
+
+        Ok(set_dr(&mut self.hw_debug, addr, rw, len))
+    }
+
+    fn remove_hw_watchpoint(
+        &mut self,
+        addr: u64,
+        len: u64,
+        kind: WatchKind,
+    ) -> TargetResult<bool, Self> {
+        let rw = match kind {
+            WatchKind::Write => 0b01,
+            WatchKind::Read => return Ok(false),
+            WatchKind::ReadWrite => 0b11,
+        };
+
+        Ok(clear_dr(&mut self.hw_debug, addr, rw, len))
+    }
 }
 
 impl<H: Hypervisor> SwBreakpoint for Vmm<H> {
@@ -106,7 +407,8 @@ impl<H: Hypervisor> SwBreakpoint for Vmm<H> {
             .as_mut()
             .unwrap()
             .translate_address(addr.try_into().unwrap())
-            .ok_or(TargetError::Fatal(GdbError::MainCpuExited))?;
+            .ok_or(TargetError::Fatal(GdbError::MainCpuExited))?
+            .map_err(|_| TargetError::Errno(Self::GDB_EFAULT))?;
 
         // Get data.
         let mut src = self
@@ -136,7 +438,8 @@ impl<H: Hypervisor> SwBreakpoint for Vmm<H> {
             .as_mut()
             .unwrap()
             .translate_address(addr.try_into().unwrap())
-            .ok_or(TargetError::Fatal(GdbError::MainCpuExited))?;
+            .ok_or(TargetError::Fatal(GdbError::MainCpuExited))?
+            .map_err(|_| TargetError::Errno(Self::GDB_EFAULT))?;
 
         // Get data.
         let mut src = self
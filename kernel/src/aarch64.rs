@@ -9,12 +9,45 @@ pub unsafe fn setup_main_cpu(cpu: CpuInfo) -> Arc<ArchConfig> {
     todo!()
 }
 
+pub unsafe fn setup_secondary_cpu(tables: &CpuTables) {
+    todo!()
+}
+
+pub fn page_table() -> usize {
+    todo!()
+}
+
+pub fn halt() {
+    todo!()
+}
+
+pub fn disable_interrupts() -> bool {
+    todo!()
+}
+
+pub fn restore_interrupts(enabled: bool) {
+    todo!()
+}
+
+pub fn enable_interrupts() {
+    todo!()
+}
+
 /// Contains information for CPU on current machine.
 pub struct CpuInfo {
     pub cpu_vendor: String,
     pub cpu_id: u32, // TODO: Figure out how to remove this.
 }
 
+/// Per-CPU tables that need to be allocated before the CPU context is activated.
+pub struct CpuTables {}
+
+impl CpuTables {
+    pub fn alloc() -> Self {
+        todo!()
+    }
+}
+
 /// Contains architecture-specific configurations obtained from [`setup_main_cpu()`].
 pub struct ArchConfig {
     pub secondary_start: &'static [u8],
@@ -3,7 +3,7 @@ pub use self::param1::*;
 
 use alloc::boxed::Box;
 use alloc::sync::Arc;
-use config::{ConsoleId, ProductId, QaFlags};
+use config::{ConsoleId, DipswFlags, ProductId, QaFlags};
 use core::num::NonZero;
 use macros::elf_note;
 
@@ -21,6 +21,7 @@ pub struct Config {
     idps: &'static ConsoleId,
     qa: bool,
     qa_flags: &'static QaFlags,
+    dipsw: &'static DipswFlags,
     env_vars: Box<[(&'static str, &'static str)]>, // kenvp
 }
 
@@ -34,6 +35,7 @@ impl Config {
             idps: &src.idps,
             qa: src.qa,
             qa_flags: &src.qa_flags,
+            dipsw: &src.dipsw,
             env_vars,
         })
     }
@@ -102,7 +104,7 @@ impl Config {
     /// | Version | Offset |
     /// |---------|--------|
     /// |PS4 11.00|0x654D70|
-    pub fn dipsw(&self, _: Dipsw) -> bool {
+    pub fn dipsw(&self, id: Dipsw) -> bool {
         if !self.is_testkit() {
             if !self.is_devkit() {
                 return false;
@@ -111,7 +113,7 @@ impl Config {
             todo!()
         }
 
-        todo!()
+        self.dipsw.get(id as usize)
     }
 
     /// See `init_dynamic_kenv` on the Orbis for a reference.
@@ -3,6 +3,7 @@ use crate::arch::ArchConfig;
 use crate::proc::Thread;
 use core::marker::PhantomPinned;
 use core::pin::Pin;
+use core::sync::atomic::AtomicBool;
 
 /// Extended [Base] for AArch64.
 #[repr(C)]
@@ -35,7 +36,22 @@ impl Context {
         todo!()
     }
 
+    pub unsafe fn switch(from: *mut Pcb, done: &AtomicBool, to: *const Pcb) {
+        todo!()
+    }
+
+    #[cfg_attr(test, allow(dead_code))]
     pub unsafe fn load_volatile_usize<const O: usize>() -> usize {
         todo!()
     }
 }
+
+/// Saved states of a thread that is not running.
+#[derive(Default)]
+pub struct Pcb {}
+
+impl Pcb {
+    pub fn new(stack: &mut [u8], entry: extern "C" fn(usize) -> !, arg: usize) -> Self {
+        todo!()
+    }
+}
@@ -1,4 +1,6 @@
-use super::{PinnedContext, config, pin_cpu};
+#[cfg(not(test))]
+use super::config;
+use super::{PinnedContext, pin_cpu};
 use alloc::vec::Vec;
 use core::ops::Deref;
 
@@ -14,7 +16,10 @@ pub struct CpuLocal<T>(Vec<T>);
 
 impl<T> CpuLocal<T> {
     pub fn new(mut f: impl FnMut(usize) -> T) -> Self {
+        #[cfg(not(test))]
         let len = config().max_cpu().get();
+        #[cfg(test)]
+        let len = 1;
         let mut vec = Vec::with_capacity(len);
 
         for i in 0..len {
@@ -32,6 +37,15 @@ impl<T> CpuLocal<T> {
 
         CpuLock { val, pin }
     }
+
+    /// Returns the values of all CPUs. The values may be modified by the other CPUs while
+    /// iterating so `T` need to implement [Sync].
+    pub fn iter(&self) -> impl Iterator<Item = &T>
+    where
+        T: Sync,
+    {
+        self.0.iter()
+    }
 }
 
 unsafe impl<T: Send> Send for CpuLocal<T> {}
@@ -13,7 +13,7 @@ use core::marker::PhantomData;
 use core::mem::offset_of;
 use core::pin::pin;
 use core::ptr::null;
-use core::sync::atomic::Ordering;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 mod arc;
 #[cfg_attr(target_arch = "aarch64", path = "aarch64.rs")]
@@ -86,6 +86,7 @@ pub fn arch() -> BorrowedArc<ArchConfig> {
 
 /// # Interrupt safety
 /// This function is interrupt safe.
+#[cfg(not(test))]
 pub fn current_thread() -> BorrowedArc<Thread> {
     // It does not matter if we are on a different CPU after we load the Context::thread because it
     // is going to be the same one since it represent the current thread.
@@ -94,6 +95,31 @@ pub fn current_thread() -> BorrowedArc<Thread> {
     }
 }
 
+/// The tests does not have a CPU context so each test thread will have its own [`Thread`] instead.
+#[cfg(test)]
+pub fn current_thread() -> BorrowedArc<Thread> {
+    use crate::proc::{Pid, Proc, ProcAbi};
+    use crate::trap::TrapFrame;
+
+    extern crate std;
+
+    struct Abi;
+
+    impl ProcAbi for Abi {
+        fn syscall_handler(&self, _: &Thread, _: &mut TrapFrame) {}
+    }
+
+    std::thread_local! {
+        static TD: Arc<Thread> = {
+            let proc = Proc::new_bare(Pid::KERNEL, Arc::new(Abi));
+
+            Arc::new(Thread::new_bare(Arc::new(proc)))
+        };
+    }
+
+    TD.with(|td| unsafe { BorrowedArc::from_non_null(Arc::as_ptr(td)) })
+}
+
 pub const fn current_thread_offset() -> usize {
     offset_of!(Base, thread)
 }
@@ -128,6 +154,24 @@ pub fn pin_cpu() -> PinnedContext {
     }
 }
 
+/// Set `td` as the current thread then switch the current CPU to the states in `to`. The states of
+/// the current thread will be saved to `from` and `done` will be cleared once the current CPU no
+/// longer use its stack.
+///
+/// This function returns when the other CPU switch back to `from`.
+///
+/// See `cpu_switch` on the PS4 for a reference.
+///
+/// # Safety
+/// - Interrupts must be disabled and the current thread must be pinned.
+/// - `from` and `done` must belong to the current thread.
+/// - `to` must belong to `td`, which must not running on any CPU.
+/// - The reference to `td` will be owned by the CPU context.
+pub unsafe fn switch(td: *const Thread, from: *mut Pcb, done: &AtomicBool, to: *const Pcb) {
+    unsafe { Context::store_ptr::<{ current_thread_offset() }, _>(td) };
+    unsafe { Context::switch(from, done, to) };
+}
+
 /// Implementation of `pcpu` structure.
 ///
 /// Access to this structure must be done by **atomic reading or writing its field directly**. It is
@@ -175,9 +219,16 @@ impl PinnedContext {
     /// # Safety
     /// Anything that derive from the returned value will invalid when this [`PinnedContext`]
     /// dropped.
+    #[cfg(not(test))]
     pub unsafe fn cpu(&self) -> usize {
         unsafe { Context::load_volatile_usize::<{ offset_of!(Base, cpu) }>() }
     }
+
+    /// The tests does not have a CPU context so all test threads are on the same CPU.
+    #[cfg(test)]
+    pub unsafe fn cpu(&self) -> usize {
+        0
+    }
 }
 
 impl Drop for PinnedContext {
@@ -186,6 +237,9 @@ impl Drop for PinnedContext {
         // https://github.com/rust-lang/rust/issues/130655#issuecomment-2365189317 for the explanation.
         unsafe { self.td.active_pins().fetch_sub(1, Ordering::Release) };
 
-        // TODO: Implement td_owepreempt.
+        // Switch to the other thread if the current thread was preempted while it was pinned.
+        if self.td.can_sleep() {
+            crate::sched::preempt();
+        }
     }
 }
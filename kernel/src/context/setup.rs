@@ -19,4 +19,10 @@ impl ContextSetup {
     pub fn set_uma(&mut self, v: Arc<Uma>) {
         unsafe { Context::store_ptr::<{ offset_of!(Base, uma) }, _>(Arc::into_raw(v)) };
     }
+
+    /// Set stack for trap handler on the current CPU.
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_trap_rsp(&mut self, v: usize) {
+        unsafe { Context::store_ptr::<{ super::current_trap_rsp_offset() }, _>(v as *const u8) };
+    }
 }
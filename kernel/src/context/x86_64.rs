@@ -1,9 +1,10 @@
 use super::Base;
 use crate::arch::{ArchConfig, wrmsr};
-use core::arch::asm;
+use core::arch::{asm, global_asm};
 use core::marker::PhantomPinned;
 use core::mem::offset_of;
 use core::pin::Pin;
+use core::sync::atomic::AtomicBool;
 
 pub const fn current_trap_rsp_offset() -> usize {
     offset_of!(Context, trap_rsp)
@@ -85,6 +86,21 @@ impl Context {
         v
     }
 
+    /// # Safety
+    /// See [`super::switch()`].
+    pub unsafe fn switch(from: *mut Pcb, done: &AtomicBool, to: *const Pcb) {
+        // Use the kernel stack of the next thread for the trap handler.
+        let rsp0 = unsafe { (*to).rsp0 };
+
+        if rsp0 != 0 {
+            // TODO: Update rsp0 on the TSS once we support interrupts from user-mode.
+            unsafe { Self::store_ptr::<{ current_trap_rsp_offset() }, _>(rsp0 as *const u8) };
+        }
+
+        unsafe { cpu_switch(from, to, done) };
+    }
+
+    #[cfg_attr(test, allow(dead_code))]
     pub unsafe fn load_volatile_usize<const O: usize>() -> usize {
         let mut v;
 
@@ -100,3 +116,70 @@ impl Context {
         v
     }
 }
+
+/// Saved states of a thread that is not running.
+///
+/// See `pcb` on the PS4 for a reference.
+#[repr(C)]
+#[derive(Default)]
+pub struct Pcb {
+    rsp: usize,  // pcb_rsp
+    rsp0: usize, // Top of the kernel stack or zero to keep the current one.
+}
+
+impl Pcb {
+    /// Setup `stack` so the thread will call `entry` with `arg` once it has been switched to.
+    ///
+    /// See `cpu_fork` and `cpu_set_fork_handler` on the PS4 for a reference.
+    pub fn new(stack: &mut [u8], entry: extern "C" fn(usize) -> !, arg: usize) -> Self {
+        // The frame must have the same layout as the one pushed by cpu_switch.
+        let top = stack.as_mut_ptr_range().end.map_addr(|v| v & !0xF);
+        let frame: [usize; 7] = [
+            0,                                     // r15
+            0,                                     // r14
+            arg,                                   // r13
+            entry as *const () as usize,           // r12
+            0,                                     // rbx
+            0,                                     // rbp
+            fork_trampoline as *const () as usize, // Return address.
+        ];
+        let rsp = unsafe { top.sub(size_of_val(&frame)) };
+
+        unsafe { rsp.cast::<[usize; 7]>().write_unaligned(frame) };
+
+        Self {
+            rsp: rsp as usize,
+            rsp0: top as usize,
+        }
+    }
+}
+
+unsafe extern "C" {
+    fn cpu_switch(from: *mut Pcb, to: *const Pcb, done: *const AtomicBool);
+    fn fork_trampoline() -> !;
+}
+
+// See cpu_switch on the PS4 for a reference.
+global_asm!(
+    "cpu_switch:",
+    "push rbp",
+    "push rbx",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    "mov [rdi+{rsp}], rsp",
+    "mov byte ptr [rdx], 0", // The stack of the previous thread is no longer used after this.
+    "mov rsp, [rsi+{rsp}]",
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop rbx",
+    "pop rbp",
+    "ret",
+    rsp = const offset_of!(Pcb, rsp)
+);
+
+// See fork_trampoline on the PS4 for a reference.
+global_asm!("fork_trampoline:", "mov rdi, r13", "call r12", "ud2");
@@ -1,10 +1,118 @@
-use crate::proc::ProcAbi;
+use crate::proc::{ProcAbi, Thread};
+use crate::syscall::{SysErr, SysIn, SysOut, Syscalls};
+use crate::trap::TrapFrame;
 
 /// Implementation of [`ProcAbi`] for PS4 processes.
-pub struct Ps4Abi;
+///
+/// See `self_orbis_sysvec` on the PS4 for a reference.
+pub struct Ps4Abi {
+    syscalls: Syscalls, // sv_table
+}
+
+impl Ps4Abi {
+    pub fn new(syscalls: Syscalls) -> Self {
+        Self { syscalls }
+    }
+
+    /// See `cpu_fetch_syscall_args` on the PS4 for a reference.
+    #[cfg(target_arch = "x86_64")]
+    fn fetch_args(frame: &TrapFrame) -> Result<SysIn, SysErr> {
+        use crate::syscall::SysArg;
+
+        // The fourth argument is in RCX slot. See syscall_entry64 for more details.
+        let regs = [
+            frame.rdi, frame.rsi, frame.rdx, frame.rcx, frame.r8, frame.r9,
+        ];
+        let (id, regs) = match frame.rax {
+            0 | 198 => (regs[0], &regs[1..]), // SYS_syscall and SYS___syscall.
+            v => (v, &regs[..]),
+        };
+
+        // Get arguments from registers.
+        let mut args = [SysArg::new(0); 6];
+
+        for (i, &v) in regs.iter().enumerate() {
+            args[i] = SysArg::new(v);
+        }
+
+        // The remaining argument is on the user stack (after the return address). We don't read it
+        // here since the syscall may not use it. See SysIn::arg() for more details.
+        let stack = (regs.len() != args.len()).then(|| frame.rsp.wrapping_add(8));
+
+        Ok(SysIn {
+            id: id.try_into().unwrap_or(u32::MAX),
+            offset: frame.rip,
+            args,
+            stack,
+        })
+    }
+
+    /// See arm64 version of `cpu_fetch_syscall_args` on FreeBSD for a reference.
+    #[cfg(target_arch = "aarch64")]
+    fn fetch_args(frame: &TrapFrame) -> Result<SysIn, SysErr> {
+        use crate::syscall::SysArg;
+
+        let regs = &frame.x[..8];
+        let (id, regs) = match frame.x[8] {
+            0 | 198 => (regs[0], &regs[1..]), // SYS_syscall and SYS___syscall.
+            v => (v, regs),
+        };
+
+        // All arguments are in the registers.
+        let mut args = [SysArg::new(0); 6];
+
+        for (i, &v) in regs.iter().take(args.len()).enumerate() {
+            args[i] = SysArg::new(v);
+        }
+
+        Ok(SysIn {
+            id: id.try_into().unwrap_or(u32::MAX),
+            offset: frame.elr,
+            args,
+            stack: None,
+        })
+    }
+
+    /// See `cpu_set_syscall_retval` on the PS4 for a reference.
+    #[cfg(target_arch = "x86_64")]
+    fn set_retval(frame: &mut TrapFrame, r: Result<SysOut, SysErr>) {
+        // Report the error through carry flag (PSL_C).
+        match r {
+            Ok(v) => {
+                frame.rax = v.rax;
+                frame.rdx = v.rdx;
+                frame.rflags &= !1;
+            }
+            Err(e) => {
+                frame.rax = e.errno().get().try_into().unwrap();
+                frame.rflags |= 1;
+            }
+        }
+    }
+
+    /// See arm64 version of `cpu_set_syscall_retval` on FreeBSD for a reference.
+    #[cfg(target_arch = "aarch64")]
+    fn set_retval(frame: &mut TrapFrame, r: Result<SysOut, SysErr>) {
+        // Report the error through carry flag (PSR_C).
+        match r {
+            Ok(v) => {
+                frame.x[0] = v.rax;
+                frame.x[1] = v.rdx;
+                frame.spsr &= !0x20000000;
+            }
+            Err(e) => {
+                frame.x[0] = e.errno().get().try_into().unwrap();
+                frame.spsr |= 0x20000000;
+            }
+        }
+    }
+}
 
 impl ProcAbi for Ps4Abi {
-    fn syscall_handler(&self) {
-        todo!()
+    /// See `amd64_syscall` on the PS4 for a reference.
+    fn syscall_handler(&self, td: &Thread, frame: &mut TrapFrame) {
+        let r = Self::fetch_args(frame).and_then(|i| self.syscalls.exec(td, &i));
+
+        Self::set_retval(frame, r);
     }
 }
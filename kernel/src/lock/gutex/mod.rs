@@ -1,6 +1,6 @@
 pub use self::guard::*;
 
-use super::MTX_UNOWNED;
+use super::{MTX_CONTESTED, MTX_UNOWNED, acquire, release, thread_waiter};
 use crate::context::{BorrowedArc, current_thread};
 use alloc::rc::Rc;
 use alloc::sync::Arc;
@@ -55,6 +55,11 @@ pub struct Gutex<T> {
 }
 
 impl<T> Gutex<T> {
+    /// Returns the group of this [`Gutex`], which can be used to spawn more members.
+    pub fn group(&self) -> &Arc<GutexGroup> {
+        &self.group
+    }
+
     /// Locks this [`Gutex`] with read-only access.
     ///
     /// Multiple read-only accesses can be taken out at the same time.
@@ -143,33 +148,17 @@ impl GutexGroup {
         let td = current_thread();
         let id = BorrowedArc::as_ptr(&td) as usize;
 
-        loop {
-            let owning = match self.owning.compare_exchange(
-                MTX_UNOWNED,
-                id,
-                Ordering::Acquire,
-                Ordering::Relaxed,
-            ) {
-                Ok(_) => break,
-                Err(v) => v,
-            };
-
-            if owning == id {
-                break;
-            }
-
-            self.wait();
+        // Only the current thread can set the owner to itself so we don't need to worry about the
+        // race condition here.
+        if self.owning.load(Ordering::Relaxed) & !MTX_CONTESTED != id {
+            acquire(&self.owning, id, td.waiter(), thread_waiter);
+            td.set_active_mutexes(td.active_mutexes() + 1);
         }
 
         // SAFETY: This is safe because the current thread acquire the lock successfully by the
         // above compare_exchange().
         unsafe { GroupGuard::new(self) }
     }
-
-    #[inline(never)]
-    fn wait(&self) {
-        todo!()
-    }
 }
 
 unsafe impl Send for GutexGroup {}
@@ -195,11 +184,13 @@ impl<'a> GroupGuard<'a> {
         }
     }
 
-    #[inline(never)]
     fn release(&mut self) {
-        self.group.owning.store(MTX_UNOWNED, Ordering::Release);
+        let td = current_thread();
+        let id = BorrowedArc::as_ptr(&td) as usize;
+
+        td.set_active_mutexes(td.active_mutexes() - 1);
 
-        todo!("wakeup waiting thread");
+        release(&self.group.owning, id, td.waiter());
     }
 }
 
@@ -218,3 +209,68 @@ impl Drop for GroupGuard<'_> {
         self.release();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern crate std;
+
+    #[test]
+    fn recursive_read() {
+        let group = GutexGroup::new();
+        let v1 = group.clone().spawn(1);
+        let v2 = group.spawn(2);
+        let r1 = v1.read();
+        let r2 = v2.read();
+        let r3 = v1.read();
+
+        assert_eq!(*r1 + *r2 + *r3, 4);
+        assert_eq!(current_thread().active_mutexes(), 1);
+
+        drop(r1);
+        drop(r3);
+
+        *v1.write() = 3;
+
+        assert_eq!(*r2, 2);
+
+        drop(r2);
+
+        assert_eq!(*v1.read(), 3);
+        assert_eq!(current_thread().active_mutexes(), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn write_while_read() {
+        let v = GutexGroup::new().spawn(0);
+        let _r = v.read();
+
+        v.write();
+    }
+
+    #[test]
+    fn contention() {
+        let group = GutexGroup::new();
+        let v1 = group.clone().spawn(0usize);
+        let v2 = group.spawn(0usize);
+
+        std::thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        let mut a = v1.write();
+                        let mut b = v2.write();
+
+                        *a += 1;
+                        *b = *a;
+                    }
+                });
+            }
+        });
+
+        assert_eq!(*v1.read(), 8000);
+        assert_eq!(*v2.read(), 8000);
+    }
+}
@@ -1,7 +1,219 @@
 pub use self::gutex::*;
 pub use self::mutex::*;
+pub use self::spin::*;
+
+use crate::proc::Thread;
+use crate::sched::{Turnstiles, Waiter};
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 mod gutex;
 mod mutex;
+mod spin;
+
+/// Acquire `lock` for `id` or block `td` on a turnstile until the lock has been released.
+///
+/// `owner` is used to get [`Waiter`] for the owner of the lock from its identifier.
+fn acquire(lock: &AtomicUsize, id: usize, td: &Waiter, owner: fn(usize) -> *const Waiter) {
+    if lock
+        .compare_exchange(MTX_UNOWNED, id, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        acquire_sleep(lock, id, td, owner);
+    }
+}
+
+/// See `_mtx_lock_sleep` on the PS4 for a reference.
+#[inline(never)]
+fn acquire_sleep(lock: &AtomicUsize, id: usize, td: &Waiter, owner: fn(usize) -> *const Waiter) {
+    let addr = lock as *const AtomicUsize as usize;
+
+    while lock
+        .compare_exchange(MTX_UNOWNED, id, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        TURNSTILES.wait(addr, td, || {
+            let mut v = lock.load(Ordering::Relaxed);
+
+            // Mark the lock as contested so the owner will need to take the slow path, which need
+            // to acquire the chain lock.
+            loop {
+                if v == MTX_UNOWNED {
+                    return None;
+                } else if v & MTX_CONTESTED != 0 {
+                    break;
+                }
+
+                match lock.compare_exchange_weak(
+                    v,
+                    v | MTX_CONTESTED,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => break,
+                    Err(e) => v = e,
+                }
+            }
+
+            Some(owner(v & !MTX_CONTESTED))
+        });
+    }
+}
+
+/// Release `lock` that was acquired with [`acquire()`] and wakeup all waiters.
+fn release(lock: &AtomicUsize, id: usize, td: &Waiter) {
+    if lock
+        .compare_exchange(id, MTX_UNOWNED, Ordering::Release, Ordering::Relaxed)
+        .is_err()
+    {
+        release_sleep(lock, td);
+    }
+}
+
+/// See `_mtx_unlock_sleep` on the PS4 for a reference.
+#[inline(never)]
+fn release_sleep(lock: &AtomicUsize, td: &Waiter) {
+    let addr = lock as *const AtomicUsize as usize;
+
+    TURNSTILES.broadcast(addr, td, || lock.store(MTX_UNOWNED, Ordering::Release));
+}
 
+/// Returns [`Waiter`] of the [`Thread`] identified by `id`.
+fn thread_waiter(id: usize) -> *const Waiter {
+    // SAFETY: The owner can't release the lock while we are holding the chain lock so it can't be
+    // gone.
+    unsafe { (*(id as *const Thread)).waiter() }
+}
+
+const MTX_CONTESTED: usize = 2;
 const MTX_UNOWNED: usize = 4;
+
+/// See `turnstile_chains` on the PS4 for a reference.
+static TURNSTILES: Turnstiles = Turnstiles::new();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::UnsafeCell;
+    use core::hint::spin_loop;
+
+    extern crate std;
+
+    #[test]
+    fn contention() {
+        struct Counter(UnsafeCell<usize>);
+
+        impl Counter {
+            fn get(&self) -> *mut usize {
+                self.0.get()
+            }
+        }
+
+        unsafe impl Sync for Counter {}
+
+        let lock = AtomicUsize::new(MTX_UNOWNED);
+        let counter = Counter(UnsafeCell::new(0));
+        let owner = |id| id as *const Waiter;
+
+        std::thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    let td = Waiter::new(120);
+                    let id = &td as *const Waiter as usize;
+
+                    for _ in 0..1000 {
+                        acquire(&lock, id, &td, owner);
+                        unsafe { *counter.get() += 1 };
+                        release(&lock, id, &td);
+                    }
+                });
+            }
+        });
+
+        assert_eq!(lock.load(Ordering::Relaxed), MTX_UNOWNED);
+        assert_eq!(unsafe { *counter.get() }, 8000);
+    }
+
+    #[test]
+    fn priority_propagation() {
+        let lock = AtomicUsize::new(MTX_UNOWNED);
+        let low = Waiter::new(200);
+        let high = Waiter::new(50);
+        let low_id = &low as *const Waiter as usize;
+        let high_id = &high as *const Waiter as usize;
+        let owner = |id| id as *const Waiter;
+
+        acquire(&lock, low_id, &low, owner);
+
+        std::thread::scope(|s| {
+            let t = s.spawn(|| {
+                acquire(&lock, high_id, &high, owner);
+                release(&lock, high_id, &high);
+            });
+
+            // Wait until the high priority thread lend its priority to us.
+            while low.priority() != 50 {
+                spin_loop();
+            }
+
+            release(&lock, low_id, &low);
+
+            assert_eq!(low.priority(), 200);
+
+            t.join().unwrap();
+        });
+
+        assert_eq!(lock.load(Ordering::Relaxed), MTX_UNOWNED);
+    }
+
+    #[test]
+    fn transitive_priority_propagation() {
+        let lock1 = AtomicUsize::new(MTX_UNOWNED);
+        let lock2 = AtomicUsize::new(MTX_UNOWNED);
+        let low = Waiter::new(200);
+        let mid = Waiter::new(100);
+        let high = Waiter::new(50);
+        let low_id = &low as *const Waiter as usize;
+        let mid_id = &mid as *const Waiter as usize;
+        let high_id = &high as *const Waiter as usize;
+        let owner = |id| id as *const Waiter;
+
+        acquire(&lock1, low_id, &low, owner);
+
+        std::thread::scope(|s| {
+            // The middle thread own lock2 and waiting for lock1.
+            let t1 = s.spawn(|| {
+                acquire(&lock2, mid_id, &mid, owner);
+                acquire(&lock1, mid_id, &mid, owner);
+                release(&lock1, mid_id, &mid);
+                release(&lock2, mid_id, &mid);
+            });
+
+            while low.priority() != 100 {
+                spin_loop();
+            }
+
+            // The high priority thread waiting for lock2 must lend its priority to us too.
+            let t2 = s.spawn(|| {
+                acquire(&lock2, high_id, &high, owner);
+                release(&lock2, high_id, &high);
+            });
+
+            while low.priority() != 50 {
+                spin_loop();
+            }
+
+            assert_eq!(mid.priority(), 50);
+
+            release(&lock1, low_id, &low);
+
+            assert_eq!(low.priority(), 200);
+
+            t1.join().unwrap();
+            t2.join().unwrap();
+        });
+
+        assert_eq!(mid.priority(), 100);
+        assert_eq!(lock1.load(Ordering::Relaxed), MTX_UNOWNED);
+        assert_eq!(lock2.load(Ordering::Relaxed), MTX_UNOWNED);
+    }
+}
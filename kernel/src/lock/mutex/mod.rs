@@ -1,10 +1,10 @@
-use super::MTX_UNOWNED;
+use super::{MTX_UNOWNED, acquire, release, thread_waiter};
 use crate::context::{BorrowedArc, current_thread};
 use alloc::rc::Rc;
 use core::cell::UnsafeCell;
 use core::marker::PhantomData;
 use core::ops::{Deref, DerefMut};
-use core::sync::atomic::{AtomicUsize, Ordering};
+use core::sync::atomic::AtomicUsize;
 
 /// Implementation of `mtx` structure.
 pub struct Mutex<T> {
@@ -33,18 +33,9 @@ impl<T> Mutex<T> {
         }
 
         // Take ownership.
-        if self
-            .owning
-            .compare_exchange(
-                MTX_UNOWNED,
-                BorrowedArc::as_ptr(&td) as usize,
-                Ordering::Acquire,
-                Ordering::Relaxed,
-            )
-            .is_err()
-        {
-            todo!()
-        }
+        let id = BorrowedArc::as_ptr(&td) as usize;
+
+        acquire(&self.owning, id, td.waiter(), thread_waiter);
 
         td.set_active_mutexes(td.active_mutexes() + 1);
 
@@ -65,17 +56,7 @@ impl<T> Mutex<T> {
         td.set_active_mutexes(td.active_mutexes() - 1);
 
         // TODO: There is a check for (m->lock_object).lo_data == 0 on the PS4.
-        if lock
-            .compare_exchange(
-                BorrowedArc::as_ptr(&td) as usize,
-                MTX_UNOWNED,
-                Ordering::Release,
-                Ordering::Relaxed,
-            )
-            .is_err()
-        {
-            todo!()
-        }
+        release(lock, BorrowedArc::as_ptr(&td) as usize, td.waiter());
     }
 }
 
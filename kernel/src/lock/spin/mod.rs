@@ -0,0 +1,84 @@
+#[cfg(not(test))]
+use crate::context::{PinnedContext, pin_cpu};
+use alloc::rc::Rc;
+use core::cell::UnsafeCell;
+use core::hint::spin_loop;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Lock that busy-waiting until it is available.
+///
+/// Unlike `mtx_lock_spin` on the PS4 this does not disable interrupts so it must not be used from
+/// interrupt handler. The current thread will be pinned to its CPU while holding the lock so it
+/// will never be preempted by the other thread that spinning on the same lock.
+pub struct Spinlock<T> {
+    locked: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+impl<T> Spinlock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn lock(&self) -> SpinlockGuard<'_, T> {
+        #[cfg(not(test))]
+        let pin = pin_cpu();
+
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            // Wait without write to the cache line.
+            while self.locked.load(Ordering::Relaxed) {
+                spin_loop();
+            }
+        }
+
+        SpinlockGuard {
+            lock: self,
+            #[cfg(not(test))]
+            pin,
+            phantom: PhantomData,
+        }
+    }
+}
+
+unsafe impl<T: Send> Send for Spinlock<T> {}
+unsafe impl<T: Send> Sync for Spinlock<T> {}
+
+/// An RAII implementation of a "scoped lock" of a [`Spinlock`].
+///
+/// This struct must not implement [`Send`].
+pub struct SpinlockGuard<'a, T> {
+    lock: &'a Spinlock<T>,
+    #[cfg(not(test))]
+    #[allow(dead_code)] // Unpin after the lock has been released.
+    pin: PinnedContext,
+    phantom: PhantomData<Rc<()>>, // For !Send and !Sync.
+}
+
+impl<T> Drop for SpinlockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+    }
+}
+
+impl<T> Deref for SpinlockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for SpinlockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
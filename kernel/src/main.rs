@@ -1,22 +1,29 @@
 #![no_std]
 #![cfg_attr(not(test), no_main)]
 
+use self::arch::{ArchConfig, CpuTables};
 use self::config::{Config, Dipsw, PAGE_MASK, PAGE_SHIFT, PAGE_SIZE, Param1};
-use self::context::{ContextSetup, arch, config};
+use self::context::{ContextSetup, arch, config, current_thread, uma};
 use self::dmem::Dmem;
 use self::imgact::Ps4Abi;
 use self::malloc::KernelHeap;
-use self::proc::{Fork, Proc, ProcAbi, ProcMgr, Thread};
-use self::sched::sleep;
+use self::proc::{Fork, Pid, Proc, ProcAbi, ProcMgr, Thread};
+use self::sched::{sched_add, sched_idletd, sched_setup, sleep};
+use self::storage::Partition;
+use self::syscall::Syscalls;
+use self::time::TimeMgr;
+use self::trap::TrapFrame;
 use self::uma::Uma;
 use self::vm::Vm;
-use ::config::{BootEnv, MapType};
+use self::vmstat::start_vmstatd;
+use ::config::{BootEnv, MapType, StorageMode};
+use alloc::boxed::Box;
 use alloc::string::String;
 use alloc::sync::Arc;
 use core::cmp::min;
 use core::fmt::Write;
 use humansize::{DECIMAL, SizeFormatter};
-use krt::{boot_env, info, warn};
+use krt::{boot_env, info, start_cpu, warn};
 
 #[cfg_attr(target_arch = "aarch64", path = "aarch64.rs")]
 #[cfg_attr(target_arch = "x86_64", path = "x86_64.rs")]
@@ -24,6 +31,7 @@ mod arch;
 mod config;
 mod context;
 mod dmem;
+mod errno;
 mod event;
 mod imgact;
 mod imgfmt;
@@ -32,10 +40,14 @@ mod malloc;
 mod proc;
 mod sched;
 mod signal;
+mod storage;
 mod subsystem;
+mod syscall;
+mod time;
 mod trap;
 mod uma;
 mod vm;
+mod vmstat;
 
 extern crate alloc;
 
@@ -73,7 +85,7 @@ fn main(map: &'static ::config::KernelMap, config: &'static ::config::Config) ->
     let arch = unsafe { self::arch::setup_main_cpu(cpu) };
 
     // Setup proc0 to represent the kernel.
-    let proc0 = Proc::new_bare(Arc::new(Proc0Abi));
+    let proc0 = Proc::new_bare(Pid::KERNEL, Arc::new(Proc0Abi));
 
     // Setup thread0 to represent this thread.
     let proc0 = Arc::new(proc0);
@@ -96,11 +108,11 @@ fn main(map: &'static ::config::KernelMap, config: &'static ::config::Config) ->
 
 fn setup(
     setup: &mut ContextSetup,
-    map: &'static ::config::KernelMap,
+    kmap: &'static ::config::KernelMap,
     param1: Arc<Param1>,
 ) -> SetupResult {
     // Initialize physical memory.
-    let mut mi = load_memory_map(u64::try_from(map.kern_vsize.get()).unwrap());
+    let mut mi = load_memory_map(u64::try_from(kmap.kern_vsize.get()).unwrap());
     let mut map = String::with_capacity(0x2000);
 
     fn format_map(tab: &[u64], last: usize, buf: &mut String) {
@@ -273,11 +285,18 @@ fn setup(
     // then loop the list to execute all of it. We manually execute those functions instead for
     // readability. This also allow us to pass data from one function to another function. See
     // mi_startup function on the Orbis for a reference.
+    let mut sys = Syscalls::new();
     let pmgr = ProcMgr::new();
 
     setup.set_uma(init_vm(phys_avail, &dmem)); // 161 on PS4 11.00.
 
-    SetupResult { pmgr }
+    TimeMgr::new(&mut sys);
+
+    SetupResult {
+        kmap,
+        pmgr,
+        ps4: Arc::new(Ps4Abi::new(sys)),
+    }
 }
 
 fn run(sr: SetupResult) -> ! {
@@ -286,11 +305,94 @@ fn run(sr: SetupResult) -> ! {
 
     unsafe { KERNEL_HEAP.activate_stage2() };
 
+    // Start the scheduler on this CPU. We need a dedicated idle thread here since this thread will
+    // become the swapper.
+    let proc0 = current_thread().proc().clone();
+
+    sched_setup(Arc::new(Thread::new(proc0, sched_idletd)));
+
+    // Start secondary CPUs.
+    start_aps(sr.kmap);
+    start_vmstatd();
+
     // Run remaining sysinit vector.
-    create_init(&sr); // 659 on PS4 11.00.
+    let init = create_init(&sr); // 659 on PS4 11.00.
+
+    kick_init(init);
     swapper(&sr); // 1119 on PS4 11.00.
 }
 
+/// See `start_all_aps` on the Orbis for a reference.
+fn start_aps(kmap: &'static ::config::KernelMap) {
+    let config = config();
+    let td = current_thread();
+
+    for cpu in 1..config.max_cpu().get() {
+        // The stack was mapped by the VMM with a guard page below it. The secondary CPU cannot
+        // allocate anything before its context has been activated so we need to allocate
+        // everything it need here.
+        let stack = (cpu - 1) * (kmap.ap_stack_len + PAGE_SIZE.get());
+        let stack = kmap.ap_stacks + stack + kmap.ap_stack_len;
+        let args = Box::new(ApArgs {
+            config: config.into_owned(),
+            arch: arch().into_owned(),
+            uma: uma().unwrap().into_owned(),
+            cpu,
+            td: Arc::new(Thread::new_bare(td.proc().clone())),
+            tables: CpuTables::alloc(),
+        });
+
+        unsafe {
+            start_cpu(
+                cpu,
+                self::arch::page_table(),
+                ap_main,
+                (stack & !0xF) as *mut u8, // Top-down.
+                Box::into_raw(args) as usize,
+            )
+        };
+    }
+}
+
+/// Entry point of the secondary CPUs.
+///
+/// See `init_secondary` on the Orbis for a reference.
+unsafe extern "C" fn ap_main(args: usize) -> ! {
+    // We can't drop the arguments here since it require the CPU context.
+    let args = args as *mut ApArgs;
+    let a = unsafe { &*args };
+
+    unsafe { self::arch::setup_secondary_cpu(&a.tables) };
+
+    unsafe {
+        self::context::run_with_context(
+            a.config.clone(),
+            a.arch.clone(),
+            a.cpu,
+            a.td.clone(),
+            move |s| ap_setup(s, *Box::from_raw(args)),
+            ap_run,
+        )
+    };
+}
+
+fn ap_setup(setup: &mut ContextSetup, args: ApArgs) -> usize {
+    #[cfg(target_arch = "x86_64")]
+    setup.set_trap_rsp(args.tables.trap_rsp());
+
+    setup.set_uma(args.uma.clone());
+
+    args.cpu
+}
+
+fn ap_run(cpu: usize) -> ! {
+    info!("CPU {cpu} started.");
+
+    // This thread will become the idle thread of this CPU.
+    sched_setup(current_thread().into_owned());
+    sched_idletd()
+}
+
 /// See `getmemsize` on the Orbis for a reference.
 ///
 /// # Reference offsets
@@ -503,7 +605,9 @@ fn load_pmap(paddr_free: u64) -> u64 {
     let config = config();
 
     if config.is_allow_disabling_aslr() && config.dipsw(Dipsw::DisabledKaslr) {
-        todo!()
+        // The VMM already mapped the kernel at the fixed address (see
+        // config::Config::is_kaslr_disabled) and we don't randomize anything else here yet so
+        // there is nothing to do.
     } else {
         // TODO: There are a lot of unknown variables here so we skip implementing this until we
         // run into the code that using them.
@@ -532,15 +636,51 @@ fn init_vm(phys_avail: [u64; 61], dmem: &Dmem) -> Arc<Uma> {
 /// | Version | Offset |
 /// |---------|--------|
 /// |PS4 11.00|0x2BEF30|
-fn create_init(sr: &SetupResult) {
-    let abi = Arc::new(Ps4Abi);
+fn create_init(sr: &SetupResult) -> Arc<Thread> {
+    let abi = sr.ps4.clone();
     let flags = Fork::CopyFd | Fork::CreateProcess;
 
     info!("Creating init process.");
 
-    sr.pmgr.fork(abi, flags).unwrap();
+    let proc = sr.pmgr.fork(abi, flags).unwrap();
+
+    // TODO: Set the name of the process to "init".
+    Arc::new(Thread::new(proc, start_init))
+}
+
+/// See `kick_init` function on the Orbis for a reference.
+fn kick_init(td: Arc<Thread>) {
+    sched_add(td);
+}
+
+/// Entry point of the init process.
+///
+/// See `start_init` function on the Orbis for a reference.
+fn start_init() -> ! {
+    let td = current_thread();
+
+    info!("Init process started with PID {}.", td.proc().id());
+
+    // Mount the root file system. We don't have VFS yet so the system partition is served directly
+    // by the VMM.
+    let root = match Partition::mount(ROOT_PART) {
+        Ok(v) => v,
+        Err(e) => panic!("couldn't mount root from {ROOT_PART}: {e}"),
+    };
+
+    info!("Root file system mounted from {}.", root.name());
+
+    // TODO: Execute the image once we have an image activator for the executable. FreeBSD panic the
+    // same as below if none of it can be executed.
+    for path in INIT_PATH.split(':') {
+        if root.open(path, StorageMode::Read).is_ok() {
+            warn!("exec {path}: image activator is not implemented");
+        }
+    }
+
+    warn!("init: not found in path {INIT_PATH}");
 
-    todo!()
+    panic!("no init");
 }
 
 /// See `scheduler` function on the Orbis for a reference.
@@ -551,18 +691,27 @@ fn create_init(sr: &SetupResult) {
 /// |PS4 11.00|0x437E00|
 fn swapper(sr: &SetupResult) -> ! {
     // TODO: Subscribe to "system_suspend_phase2_pre_sync" and "system_resume_phase2" event.
+    let wchan = Arc::as_ptr(&sr.pmgr) as usize;
+
     loop {
         // TODO: Implement a call to vm_page_count_min().
         let procs = sr.pmgr.list();
 
         if procs.len() == 0 {
             // TODO: The PS4 check for some value for non-zero but it seems like that value always
-            // zero.
-            sleep();
+            // zero. The PS4 also use proc0 as a wait channel with a timeout.
+            drop(procs);
+            sleep(wchan);
             continue;
         }
 
-        todo!();
+        // Look for the process to swap in. We never swap out any process so all of them are always
+        // in the memory, which mean there is nothing to do here the same as the PS4 when all
+        // processes are in the memory.
+        drop(procs);
+
+        // Back to sleep until the other thread request a rescan.
+        sleep(wchan);
     }
 }
 
@@ -573,14 +722,26 @@ struct Proc0Abi;
 
 impl ProcAbi for Proc0Abi {
     /// See `null_fetch_syscall_args` on the PS4 for a reference.
-    fn syscall_handler(&self) {
+    fn syscall_handler(&self, _: &Thread, _: &mut TrapFrame) {
         unimplemented!()
     }
 }
 
+/// Arguments for [`ap_main()`].
+struct ApArgs {
+    config: Arc<Config>,
+    arch: Arc<ArchConfig>,
+    uma: Arc<Uma>,
+    cpu: usize,
+    td: Arc<Thread>,
+    tables: CpuTables,
+}
+
 /// Result of [`setup()`].
 struct SetupResult {
+    kmap: &'static ::config::KernelMap,
     pmgr: Arc<ProcMgr>,
+    ps4: Arc<Ps4Abi>,
 }
 
 /// Contains memory information populated from memory map.
@@ -604,6 +765,13 @@ struct BootInfo {
     page_tables: u64,
 }
 
+/// Name of the partition to mount as the root file system.
+const ROOT_PART: &str = "md0";
+
+/// Paths to search for the init program, separated by colon. This is the default value of
+/// `init_path` on FreeBSD.
+const INIT_PATH: &str = "/sbin/init:/sbin/oinit:/sbin/init.bak:/rescue/init";
+
 // SAFETY: PRIMITIVE_HEAP is a mutable static so it valid for reads and writes. This will be safe as
 // long as no one access PRIMITIVE_HEAP.
 #[allow(dead_code)]
@@ -1,4 +1,5 @@
-use self::vm::VmHeap;
+pub use self::vm::*;
+
 use crate::context::current_thread;
 use crate::lock::Mutex;
 use alloc::boxed::Box;
@@ -61,6 +62,14 @@ impl KernelHeap {
         // moving the value from Stage::One to Stage::Two.
         unsafe { stage.write(Stage::Two(vm, primitive)) };
     }
+
+    /// Returns [`None`] if stage 2 has not been activated yet.
+    pub fn vm(&self) -> Option<&VmHeap> {
+        match unsafe { &*self.stage.get() } {
+            Stage::One(_) => None,
+            Stage::Two(vm, _) => Some(vm),
+        }
+    }
 }
 
 unsafe impl GlobalAlloc for KernelHeap {
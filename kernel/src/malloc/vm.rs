@@ -1,18 +1,19 @@
 use crate::config::PAGE_SIZE;
 use crate::context::{CpuLocal, current_thread, uma};
-use crate::uma::{Alloc, UmaFlags, UmaZone};
+use crate::uma::{Alloc, Uma, UmaFlags, UmaZone};
 use alloc::string::ToString;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::alloc::Layout;
-use core::cell::RefCell;
 use core::num::NonZero;
+use core::sync::atomic::{AtomicU64, Ordering};
 
 /// Kernel heap that allocate a memory from a virtual memory management system. This struct is a
 /// merge of `malloc_type` and `malloc_type_internal` structure.
 pub struct VmHeap {
+    uma: Arc<Uma>,
     zones: [Vec<Arc<UmaZone>>; (usize::BITS - 1) as usize], // kmemsize + kmemzones
-    stats: CpuLocal<RefCell<Stats>>,                        // mti_stats
+    stats: CpuLocal<Stats>,                                 // mti_stats
 }
 
 impl VmHeap {
@@ -50,7 +51,7 @@ impl VmHeap {
                 }
 
                 // Create zone.
-                let zone = Arc::new(uma.into_owned().create_zone(
+                let zone = Arc::new(uma.create_zone(
                     size.to_string(),
                     size,
                     Some(align - 1),
@@ -67,8 +68,9 @@ impl VmHeap {
         });
 
         Self {
+            uma: uma.into_owned(),
             zones,
-            stats: CpuLocal::new(|_| RefCell::default()),
+            stats: CpuLocal::new(|_| Stats::default()),
         }
     }
 
@@ -93,38 +95,36 @@ impl VmHeap {
 
         // Determine how to allocate.
         let lock = td.disable_vm_heap();
-        let size = layout.size();
-        let mem = if size <= PAGE_SIZE.get() {
-            // Get zone to allocate from.
-            let align = layout.align().trailing_zeros() as usize;
-            let size = if (size & Self::KMEM_ZMASK) != 0 {
-                // TODO: Refactor this for readability.
-                (size + Self::KMEM_ZBASE) & !Self::KMEM_ZMASK
-            } else {
-                size
-            };
-
-            // Allocate a memory from UMA zone.
-            let zone = &self.zones[align][size >> Self::KMEM_ZSHIFT];
-            let mem = zone.alloc(Alloc::Wait | Alloc::Zero);
-
-            // Update stats.
-            let stats = self.stats.lock();
-            let mut stats = stats.borrow_mut();
-            let size = if mem.is_null() { 0 } else { zone.size().get() };
-
-            if size != 0 {
-                stats.alloc_bytes = stats
-                    .alloc_bytes
-                    .checked_add(size.try_into().unwrap())
-                    .unwrap();
-                stats.alloc_count += 1;
+        let mem = match self.zone(layout) {
+            Some(zone) => {
+                // Allocate a memory from UMA zone.
+                let mem = zone.alloc(Alloc::Wait | Alloc::Zero);
+
+                // Update stats. The zones with the same size but different alignment use the same
+                // bit on mts_size.
+                if !mem.is_null() {
+                    let stats = self.stats.lock();
+                    let size = zone.size();
+
+                    stats.add_alloc(size.get());
+                    stats.size.fetch_or(
+                        1 << (size.trailing_zeros() as usize - Self::KMEM_ZSHIFT),
+                        Ordering::Relaxed,
+                    );
+                }
+
+                mem
             }
+            None => {
+                let size = Self::large_size(layout);
+                let mem = self.uma.large_alloc(size, Alloc::Wait | Alloc::Zero);
 
-            // TODO: How to update mts_size here since our zone table also indexed by alignment?
-            mem
-        } else {
-            todo!()
+                if !mem.is_null() {
+                    self.stats.lock().add_alloc(size.get());
+                }
+
+                mem
+            }
         };
 
         drop(lock);
@@ -132,17 +132,139 @@ impl VmHeap {
         mem
     }
 
+    /// See `free` on the Orbis for a reference.
+    ///
     /// # Safety
     /// `ptr` must be obtained with [`Self::alloc()`] and `layout` must be the same one that was
     /// passed to that method.
-    pub unsafe fn dealloc(&self, _: *mut u8, _: Layout) {
-        todo!()
+    pub unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let td = current_thread();
+        let lock = td.disable_vm_heap();
+        let size = match self.zone(layout) {
+            Some(zone) => {
+                unsafe { zone.free(ptr) };
+                zone.size()
+            }
+            None => {
+                let size = Self::large_size(layout);
+
+                unsafe { self.uma.large_free(ptr, size) };
+
+                size
+            }
+        };
+
+        // Update stats.
+        let stats = self.stats.lock();
+
+        stats
+            .free_bytes
+            .fetch_add(size.get().try_into().unwrap(), Ordering::Relaxed);
+        stats.free_count.fetch_add(1, Ordering::Relaxed);
+
+        drop(stats);
+        drop(lock);
+    }
+
+    /// Returns the statistics of this heap by summing up the values from all CPUs.
+    ///
+    /// See `sysctl_kern_malloc_stats` on the Orbis for a reference.
+    pub fn stats(&self) -> HeapStats {
+        let mut r = HeapStats::default();
+
+        for s in self.stats.iter() {
+            r.alloc_bytes += s.alloc_bytes.load(Ordering::Relaxed);
+            r.alloc_count += s.alloc_count.load(Ordering::Relaxed);
+            r.free_bytes += s.free_bytes.load(Ordering::Relaxed);
+            r.free_count += s.free_count.load(Ordering::Relaxed);
+            r.size |= s.size.load(Ordering::Relaxed);
+        }
+
+        r
+    }
+
+    /// Returns all zones used by this heap with its alignment.
+    pub fn zones(&self) -> impl Iterator<Item = (usize, &UmaZone)> {
+        self.zones.iter().enumerate().flat_map(|(align, zones)| {
+            let mut last = None;
+
+            zones.iter().filter_map(move |z| {
+                if last.is_some_and(|v| Arc::ptr_eq(v, z)) {
+                    return None;
+                }
+
+                last = Some(z);
+
+                Some((1 << align, z.as_ref()))
+            })
+        })
+    }
+
+    /// Returns [`None`] if `layout` need to allocate directly from the VM.
+    fn zone(&self, layout: Layout) -> Option<&UmaZone> {
+        let size = layout.size();
+
+        if size > PAGE_SIZE.get() || layout.align() > PAGE_SIZE.get() {
+            return None;
+        }
+
+        // Get zone to allocate from.
+        let align = layout.align().trailing_zeros() as usize;
+        let size = if (size & Self::KMEM_ZMASK) != 0 {
+            // TODO: Refactor this for readability.
+            (size + Self::KMEM_ZBASE) & !Self::KMEM_ZMASK
+        } else {
+            size
+        };
+
+        Some(&self.zones[align][size >> Self::KMEM_ZSHIFT])
+    }
+
+    /// The pages from [`Uma::large_alloc()`] are naturally aligned to its size so we need to
+    /// allocate a larger pages for a larger alignment.
+    fn large_size(layout: Layout) -> NonZero<usize> {
+        NonZero::new(layout.size().max(layout.align())).unwrap()
+    }
+}
+
+/// Statistics of [`VmHeap`] from all CPUs.
+#[derive(Default)]
+pub struct HeapStats {
+    pub alloc_bytes: u64, // mts_memalloced
+    pub alloc_count: u64, // mts_numallocs
+    pub free_bytes: u64,  // mts_memfreed
+    pub free_count: u64,  // mts_numfrees
+    pub size: u64,        // mts_size
+}
+
+impl HeapStats {
+    /// Returns the number of bytes that currently in use.
+    pub fn used_bytes(&self) -> u64 {
+        self.alloc_bytes.saturating_sub(self.free_bytes)
+    }
+
+    /// Returns the number of allocations that currently in use.
+    pub fn used_count(&self) -> u64 {
+        self.alloc_count.saturating_sub(self.free_count)
     }
 }
 
 /// Implementation of `malloc_type_stats` structure.
+///
+/// The fields are atomic so [`VmHeap::stats()`] can read it from the other CPUs.
 #[derive(Default)]
 struct Stats {
-    alloc_bytes: u64, // mts_memalloced
-    alloc_count: u64, // mts_numallocs
+    alloc_bytes: AtomicU64, // mts_memalloced
+    alloc_count: AtomicU64, // mts_numallocs
+    free_bytes: AtomicU64,  // mts_memfreed
+    free_count: AtomicU64,  // mts_numfrees
+    size: AtomicU64,        // mts_size
+}
+
+impl Stats {
+    fn add_alloc(&self, size: usize) {
+        self.alloc_bytes
+            .fetch_add(size.try_into().unwrap(), Ordering::Relaxed);
+        self.alloc_count.fetch_add(1, Ordering::Relaxed);
+    }
 }
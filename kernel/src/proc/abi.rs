@@ -1,4 +1,8 @@
+use super::Thread;
+use crate::trap::TrapFrame;
+
 /// Implementation of `sysentvec` structure.
 pub trait ProcAbi: Send + Sync {
-    fn syscall_handler(&self);
+    /// Invoked when `td` execute a syscall.
+    fn syscall_handler(&self, td: &Thread, frame: &mut TrapFrame);
 }
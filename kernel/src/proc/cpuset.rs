@@ -0,0 +1,38 @@
+/// Maximum number of CPUs supported by the kernel.
+pub const MAXCPU: usize = 64;
+
+/// Implementation of `cpuset` structure.
+pub struct CpuSet {
+    mask: CpuMask, // cs_mask
+}
+
+impl CpuSet {
+    pub fn new(mask: CpuMask) -> Self {
+        Self { mask }
+    }
+
+    pub fn mask(&self) -> &CpuMask {
+        &self.mask
+    }
+}
+
+/// Implementation of `cpuset_t`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct CpuMask {
+    pub bits: [u64; MAXCPU / 64],
+}
+
+impl CpuMask {
+    /// Returns a mask of all CPUs.
+    pub const fn all() -> Self {
+        Self {
+            bits: [u64::MAX; _],
+        }
+    }
+
+    /// See `CPU_ISSET` on the PS4 for a reference.
+    pub fn has(&self, cpu: usize) -> bool {
+        self.bits[cpu / 64] & (1 << (cpu % 64)) != 0
+    }
+}
@@ -1,4 +1,5 @@
 pub use self::abi::*;
+pub use self::cpuset::*;
 pub use self::pid::*;
 pub use self::process::*;
 pub use self::thread::*;
@@ -9,19 +10,21 @@ use crate::signal::Signal;
 use crate::subsystem::Subsystem;
 use alloc::sync::{Arc, Weak};
 use core::error::Error;
+use core::ffi::c_int;
 use core::fmt::{Display, Formatter};
 use hashbrown::HashMap;
 use macros::bitflag;
 
 mod abi;
 mod cell;
+mod cpuset;
 mod pid;
 mod process;
 mod thread;
 
 /// Manage all processes in the system.
 pub struct ProcMgr {
-    procs: Mutex<HashMap<Pid, Weak<Proc>>>, // allproc + pidhashtbl + zombproc
+    procs: Mutex<ProcList>,
     events: Arc<EventSet<ProcEvents>>,
 }
 
@@ -30,13 +33,16 @@ impl ProcMgr {
         let events = Arc::default();
 
         Arc::new(Self {
-            procs: Mutex::new(HashMap::new()),
+            procs: Mutex::new(ProcList {
+                list: HashMap::new(),
+                last: 0,
+            }),
             events,
         })
     }
 
     pub fn list(&self) -> MappedMutex<'_, impl ExactSizeIterator<Item = &Weak<Proc>>> {
-        MutexGuard::map(self.procs.lock(), |procs| procs.values())
+        MutexGuard::map(self.procs.lock(), |procs| procs.list.values())
     }
 
     /// We imply `RFSTOPPED` to make [`ProcMgr`] not depend on the scheduler.
@@ -61,7 +67,43 @@ impl ProcMgr {
         }
 
         // Create process.
-        Ok(Proc::new(abi, &self.events))
+        let mut procs = self.procs.lock();
+        let id = procs.find_pid();
+        let proc = Proc::new(id, abi, &self.events);
+
+        procs.list.insert(id, Arc::downgrade(&proc));
+
+        Ok(proc)
+    }
+}
+
+/// Contains all processes in the system.
+struct ProcList {
+    list: HashMap<Pid, Weak<Proc>>, // allproc + pidhashtbl + zombproc
+    last: c_int,                    // lastpid
+}
+
+impl ProcList {
+    /// See `fork_findpid` on the PS4 for a reference.
+    fn find_pid(&mut self) -> Pid {
+        let mut id = self.last + 1;
+
+        loop {
+            if id >= PID_MAX {
+                id = 100;
+            }
+
+            // The process that was already destroyed may still be in the list.
+            if self.list.get(&id).is_none_or(|p| p.strong_count() == 0) {
+                break;
+            }
+
+            id += 1;
+        }
+
+        self.last = id;
+
+        Pid::new(id).unwrap()
     }
 }
 
@@ -117,3 +159,6 @@ impl Display for ForkError {
         }
     }
 }
+
+/// Maximum value of [`Pid`] (exclusive).
+const PID_MAX: c_int = 99999;
@@ -1,5 +1,6 @@
 use core::borrow::Borrow;
 use core::ffi::c_int;
+use core::fmt::{Display, Formatter};
 
 /// Unique identifier of a process.
 #[repr(transparent)]
@@ -16,6 +17,12 @@ impl Pid {
     }
 }
 
+impl Display for Pid {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
 impl Borrow<c_int> for Pid {
     fn borrow(&self) -> &c_int {
         &self.0
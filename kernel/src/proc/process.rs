@@ -1,10 +1,11 @@
-use super::{ProcAbi, ProcEvents};
+use super::{Pid, ProcAbi, ProcEvents};
 use crate::event::EventSet;
 use alloc::sync::Arc;
 use core::sync::atomic::{AtomicUsize, Ordering};
 
 /// Implementation of `proc` structure.
 pub struct Proc {
+    id: Pid,               // p_pid
     abi: Arc<dyn ProcAbi>, // p_sysent
     pager: AtomicUsize,
 }
@@ -16,8 +17,9 @@ impl Proc {
     /// | Version | Offset              |
     /// |---------|---------------------|
     /// |PS4 11.00|0x375970 and 0x3755D0|
-    pub fn new(abi: Arc<dyn ProcAbi>, events: &Arc<EventSet<ProcEvents>>) -> Arc<Self> {
+    pub fn new(id: Pid, abi: Arc<dyn ProcAbi>, events: &Arc<EventSet<ProcEvents>>) -> Arc<Self> {
         let mut proc = Self {
+            id,
             abi,
             pager: AtomicUsize::new(0),
         };
@@ -39,7 +41,7 @@ impl Proc {
 
         drop(et);
 
-        todo!()
+        proc
     }
 
     /// This function does not do anything except initialize the struct memory. It is the caller
@@ -48,13 +50,18 @@ impl Proc {
     ///
     /// # Context safety
     /// This function does not require a CPU context.
-    pub fn new_bare(abi: Arc<dyn ProcAbi>) -> Self {
+    pub fn new_bare(id: Pid, abi: Arc<dyn ProcAbi>) -> Self {
         Self {
+            id,
             abi,
             pager: AtomicUsize::new(0),
         }
     }
 
+    pub fn id(&self) -> Pid {
+        self.id
+    }
+
     pub fn abi(&self) -> &Arc<dyn ProcAbi> {
         &self.abi
     }
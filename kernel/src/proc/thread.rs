@@ -1,9 +1,10 @@
 use super::Proc;
 use super::cell::{PrivateCell, get, set};
-use crate::lock::{Gutex, GutexGroup, GutexWrite};
+use crate::sched::{PUSER, TdSched, Waiter};
 use alloc::sync::Arc;
 use core::cell::Cell;
 use core::marker::PhantomData;
+use core::mem::offset_of;
 use core::sync::atomic::{AtomicU8, Ordering};
 
 /// Implementation of `thread` structure.
@@ -21,7 +22,8 @@ pub struct Thread {
     active_pins: AtomicU8,                   // td_critnest
     active_interrupts: AtomicU8,             // td_intr_nesting_level
     active_mutexes: PrivateCell<Cell<u16>>,  // td_locks
-    sleeping: Gutex<usize>,                  // td_wchan
+    waiter: Waiter,                          // td_wchan + td_blocked + td_priority
+    sched: TdSched,                          // td_sched
     profiling_ticks: PrivateCell<Cell<u32>>, // td_pticks
     active_heap_guard: PrivateCell<Cell<usize>>,
 }
@@ -31,24 +33,47 @@ impl Thread {
     /// responsibility to configure the thread after this so it have a proper states and trigger
     /// necessary events.
     ///
+    /// The thread will be treated as it is currently running on a CPU so it is intended to
+    /// represent the code that is already running.
+    ///
     /// # Context safety
     /// This function does not require a CPU context.
     pub fn new_bare(proc: Arc<Proc>) -> Self {
+        Self::new_with_sched(proc, TdSched::running())
+    }
+
+    /// Create a thread that will start at `entry` on its own stack. The thread will not run until
+    /// it is added to the scheduler with [`crate::sched::sched_add()`].
+    ///
+    /// See `thread_alloc` on the PS4 for a reference.
+    pub fn new(proc: Arc<Proc>, entry: fn() -> !) -> Self {
+        Self::new_with_sched(proc, TdSched::inactive(entry))
+    }
+
+    fn new_with_sched(proc: Arc<Proc>, sched: TdSched) -> Self {
         // td_critnest on the PS4 started with 1 but this does not work in our case because we use
         // RAII to increase and decrease it.
-        let gg = GutexGroup::new();
-
         Self {
             proc,
             active_pins: AtomicU8::new(0),
             active_interrupts: AtomicU8::new(0),
             active_mutexes: PrivateCell::default(),
-            sleeping: gg.spawn(0),
+            waiter: Waiter::new(PUSER),
+            sched,
             profiling_ticks: PrivateCell::default(),
             active_heap_guard: PrivateCell::default(),
         }
     }
 
+    /// # Safety
+    /// `w` must be the [`Waiter`] of a [`Thread`].
+    #[cfg_attr(test, allow(dead_code))]
+    pub unsafe fn from_waiter(w: &Waiter) -> &Self {
+        let w = w as *const Waiter;
+
+        unsafe { &*w.byte_sub(offset_of!(Self, waiter)).cast() }
+    }
+
     pub fn can_sleep(&self) -> bool {
         // Both of the values here can only modified by this thread so no race condition here.
         let active_pins = self.active_pins.load(Ordering::Relaxed);
@@ -90,9 +115,13 @@ impl Thread {
         set!(self, active_mutexes, v)
     }
 
-    /// Sleeping address. Zero if this thread is not in a sleep queue.
-    pub fn sleeping_mut(&self) -> GutexWrite<'_, usize> {
-        self.sleeping.write()
+    /// States to block this thread on a lock or a wait channel.
+    pub fn waiter(&self) -> &Waiter {
+        &self.waiter
+    }
+
+    pub fn sched(&self) -> &TdSched {
+        &self.sched
     }
 
     /// # Panics
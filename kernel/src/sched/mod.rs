@@ -1,3 +1,44 @@
+pub use self::rtprio::*;
+pub use self::runq::*;
 pub use self::sleep::*;
+pub use self::sleepq::*;
+pub use self::thread::*;
+pub use self::turnstile::*;
+pub use self::ule::*;
+pub use self::waiter::*;
 
+mod rtprio;
+mod runq;
 mod sleep;
+mod sleepq;
+mod thread;
+mod turnstile;
+mod ule;
+mod waiter;
+
+/// Highest priority of the real-time threads.
+pub const PRI_MIN_REALTIME: u8 = 48;
+
+/// Highest priority of the time sharing threads.
+pub const PRI_MIN_TIMESHARE: u8 = 120;
+
+/// Lowest priority of the time sharing threads.
+pub const PRI_MAX_TIMESHARE: u8 = PRI_MIN_IDLE - 1;
+
+/// Highest priority of the idle threads.
+pub const PRI_MIN_IDLE: u8 = 224;
+
+/// Priority of the user threads.
+pub const PUSER: u8 = PRI_MIN_TIMESHARE;
+
+/// Real-time scheduling class.
+pub const PRI_REALTIME: u8 = 2;
+
+/// Time sharing scheduling class.
+pub const PRI_TIMESHARE: u8 = 3;
+
+/// Idle scheduling class.
+pub const PRI_IDLE: u8 = 4;
+
+/// Bit of the scheduling class to indicate a FIFO thread.
+pub const PRI_FIFO_BIT: u8 = 8;
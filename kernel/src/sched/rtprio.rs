@@ -0,0 +1,69 @@
+use super::{
+    PRI_FIFO_BIT, PRI_IDLE, PRI_MAX_TIMESHARE, PRI_MIN_IDLE, PRI_MIN_REALTIME, PRI_MIN_TIMESHARE,
+    PRI_REALTIME, PRI_TIMESHARE,
+};
+use crate::errno::{EINVAL, Errno};
+use crate::proc::Thread;
+use core::num::NonZero;
+use thiserror::Error;
+
+/// Implementation of `rtprio` structure.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct RtPrio {
+    pub ty: u16,   // type
+    pub prio: u16, // prio
+}
+
+impl RtPrio {
+    pub const REALTIME: u16 = PRI_REALTIME as u16;
+    pub const NORMAL: u16 = PRI_TIMESHARE as u16;
+    pub const IDLE: u16 = PRI_IDLE as u16;
+
+    /// Lowest priority for [`Self::REALTIME`] and [`Self::IDLE`].
+    pub const MAX: u16 = 31;
+}
+
+/// Set the scheduling class and priority of `td` from `rtp`.
+///
+/// See `rtp_to_pri` on the PS4 for a reference.
+pub fn rtp_to_pri(rtp: &RtPrio, td: &Thread) -> Result<(), RtPrioError> {
+    let (min, max) = match rtp.ty & !u16::from(PRI_FIFO_BIT) {
+        RtPrio::REALTIME => (PRI_MIN_REALTIME, RtPrio::MAX),
+        RtPrio::NORMAL => (
+            PRI_MIN_TIMESHARE,
+            (PRI_MAX_TIMESHARE - PRI_MIN_TIMESHARE).into(),
+        ),
+        RtPrio::IDLE => (PRI_MIN_IDLE, RtPrio::MAX),
+        _ => return Err(RtPrioError::InvalidType),
+    };
+
+    if rtp.prio > max {
+        return Err(RtPrioError::InvalidPriority);
+    }
+
+    // The PS4 set td_user_pri here and only change td_priority in some cases. We don't have
+    // td_user_pri so we always change the priority.
+    td.sched().set_class(rtp.ty.try_into().unwrap());
+    td.waiter().set_base(min + u8::try_from(rtp.prio).unwrap());
+
+    Ok(())
+}
+
+/// Represents an error when [`rtp_to_pri()`] fails.
+#[derive(Debug, Error)]
+pub enum RtPrioError {
+    #[error("invalid type")]
+    InvalidType,
+
+    #[error("invalid priority")]
+    InvalidPriority,
+}
+
+impl Errno for RtPrioError {
+    fn errno(&self) -> NonZero<i32> {
+        match self {
+            Self::InvalidType | Self::InvalidPriority => EINVAL,
+        }
+    }
+}
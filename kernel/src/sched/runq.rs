@@ -0,0 +1,163 @@
+use crate::proc::Thread;
+use alloc::sync::Arc;
+use core::ptr::null;
+
+/// Implementation of `runq` structure.
+///
+/// The threads are linked through [`super::TdSched`] so adding or removing a thread never allocate
+/// any memory.
+pub struct Runq {
+    status: u64,             // rq_status
+    queues: [Queue; RQ_NQS], // rq_queues
+}
+
+impl Runq {
+    pub const fn new() -> Self {
+        Self {
+            status: 0,
+            queues: [const {
+                Queue {
+                    head: null(),
+                    tail: null(),
+                }
+            }; RQ_NQS],
+        }
+    }
+
+    /// Add `td` to the tail of the queue for its current priority.
+    ///
+    /// See `runq_add` on the PS4 for a reference.
+    pub fn add(&mut self, td: Arc<Thread>) {
+        let pri = usize::from(td.waiter().priority()) / RQ_PPQ;
+        let q = &mut self.queues[pri];
+        let td = Arc::into_raw(td);
+
+        unsafe { *(*td).sched().next() = null() };
+
+        match unsafe { q.tail.as_ref() } {
+            Some(t) => unsafe { *t.sched().next() = td },
+            None => q.head = td,
+        }
+
+        q.tail = td;
+        self.status |= 1 << pri;
+    }
+
+    /// Remove the thread with the highest priority.
+    ///
+    /// See `runq_choose` on the PS4 for a reference.
+    pub fn choose(&mut self) -> Option<Arc<Thread>> {
+        self.steal(|_| true)
+    }
+
+    /// Remove the thread with the highest priority that `f` returns `true`.
+    ///
+    /// See `runq_steal` on the PS4 for a reference.
+    pub fn steal(&mut self, f: impl Fn(&Thread) -> bool) -> Option<Arc<Thread>> {
+        let mut status = self.status;
+
+        while status != 0 {
+            let pri = status.trailing_zeros() as usize;
+
+            if let Some(td) = self.remove(pri, &f) {
+                return Some(td);
+            }
+
+            status &= !(1 << pri);
+        }
+
+        None
+    }
+
+    fn remove(&mut self, pri: usize, f: impl Fn(&Thread) -> bool) -> Option<Arc<Thread>> {
+        let q = &mut self.queues[pri];
+        let mut prev: *const Thread = null();
+        let mut td = q.head;
+
+        while let Some(t) = unsafe { td.as_ref() } {
+            let next = unsafe { *t.sched().next() };
+
+            if !f(t) {
+                prev = td;
+                td = next;
+                continue;
+            }
+
+            // Unlink.
+            match unsafe { prev.as_ref() } {
+                Some(p) => unsafe { *p.sched().next() = next },
+                None => q.head = next,
+            }
+
+            if q.tail == td {
+                q.tail = prev;
+            }
+
+            if q.head.is_null() {
+                self.status &= !(1 << pri);
+            }
+
+            return Some(unsafe { Arc::from_raw(td) });
+        }
+
+        None
+    }
+}
+
+unsafe impl Send for Runq {}
+
+/// List of threads that have the same priority.
+struct Queue {
+    head: *const Thread,
+    tail: *const Thread,
+}
+
+/// Number of queues in [`Runq`].
+const RQ_NQS: usize = 64;
+
+/// Number of priorities per queue.
+const RQ_PPQ: usize = 4;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proc::{Pid, Proc, ProcAbi};
+    use crate::trap::TrapFrame;
+
+    #[test]
+    fn order() {
+        struct Abi;
+
+        impl ProcAbi for Abi {
+            fn syscall_handler(&self, _: &Thread, _: &mut TrapFrame) {}
+        }
+
+        let proc = Arc::new(Proc::new_bare(Pid::KERNEL, Arc::new(Abi)));
+        let spawn = |pri| {
+            let td = Arc::new(Thread::new_bare(proc.clone()));
+
+            td.waiter().set_base(pri);
+            td
+        };
+
+        // Lower value is higher priority and the same priority is first-in first-out.
+        let t1 = spawn(120);
+        let t2 = spawn(50);
+        let t3 = spawn(121);
+        let t4 = spawn(200);
+        let mut rq = Runq::new();
+
+        for td in [&t1, &t2, &t3, &t4] {
+            rq.add(td.clone());
+        }
+
+        assert!(Arc::ptr_eq(&rq.choose().unwrap(), &t2));
+        assert!(Arc::ptr_eq(
+            &rq.steal(|td| !core::ptr::eq(td, &*t1)).unwrap(),
+            &t3
+        ));
+        assert!(Arc::ptr_eq(&rq.choose().unwrap(), &t1));
+        assert!(Arc::ptr_eq(&rq.choose().unwrap(), &t4));
+        assert!(rq.choose().is_none());
+    }
+}
@@ -1,14 +1,57 @@
+use super::SleepQueues;
 use crate::context::current_thread;
+use crate::proc::Thread;
 
+/// Block the current thread until [`wakeup()`] is called on `wchan`.
+///
+/// Use [`sleep_until()`] instead if the thread is waiting for a condition that the other thread
+/// can satisfy before this function is called.
+///
 /// See `_sleep` on the PS4 for a reference.
-pub fn sleep() {
-    // Remove current thread from sleep queue.
+pub fn sleep(wchan: usize) {
     let td = current_thread();
-    let addr = td.sleeping_mut();
 
-    if *addr != 0 {
-        todo!()
+    check_sleep(&td);
+
+    SLEEPQ.sleep(wchan, td.waiter(), || {});
+}
+
+/// Block the current thread on `wchan` until `ready` returns `true`.
+///
+/// `ready` will be called with the sleep queue locked so the thread that satisfy the condition
+/// before calling [`wakeup()`] on `wchan` will never be missed. This has the same purpose as the
+/// interlock of `msleep` on the PS4. `ready` must not block or acquire any sleeping locks.
+pub fn sleep_until(wchan: usize, mut ready: impl FnMut() -> bool) {
+    let td = current_thread();
+
+    check_sleep(&td);
+
+    while SLEEPQ.sleep_unless(wchan, td.waiter(), &mut ready) {}
+}
+
+fn check_sleep(td: &Thread) {
+    if !td.can_sleep() {
+        panic!("sleeping in a non-sleeping context is not supported");
     }
 
-    todo!()
+    // The other threads that waiting for the mutex will be blocked until we are woken.
+    assert_eq!(td.active_mutexes(), 0, "sleeping while holding a mutex");
+}
+
+/// Wakeup all threads that sleeping on `wchan`.
+///
+/// See `wakeup` on the PS4 for a reference.
+pub fn wakeup(wchan: usize) {
+    SLEEPQ.wakeup(wchan);
 }
+
+/// Wakeup the highest priority thread that sleeping on `wchan`.
+///
+/// See `wakeup_one` on the PS4 for a reference.
+#[allow(dead_code)] // TODO: Remove this once someone use it.
+pub fn wakeup_one(wchan: usize) {
+    SLEEPQ.wakeup_one(wchan);
+}
+
+/// See `sleepq_chains` on the PS4 for a reference.
+static SLEEPQ: SleepQueues = SleepQueues::new();
@@ -0,0 +1,200 @@
+use super::{Chains, Waiter};
+
+/// Queues of the threads that waiting for an event.
+///
+/// See `subr_sleepqueue.c` on the PS4 for a reference.
+pub struct SleepQueues(Chains);
+
+impl SleepQueues {
+    pub const fn new() -> Self {
+        Self(Chains::new())
+    }
+
+    /// Block `td` until [`SleepQueues::wakeup()`] or [`SleepQueues::wakeup_one()`] is called on
+    /// `wchan`.
+    ///
+    /// `release` will be called after `td` has been added to the queue, which can be used to
+    /// release the lock that protect the condition without missing a wakeup.
+    ///
+    /// See `sleepq_add` and `sleepq_wait` on the PS4 for a reference.
+    pub fn sleep(&self, wchan: usize, td: &Waiter, release: impl FnOnce()) {
+        let mut chain = self.0.lock(wchan);
+
+        // SAFETY: td will not go anywhere until it has been woken.
+        unsafe { chain.push(wchan, core::ptr::null(), td) };
+
+        release();
+        drop(chain);
+
+        td.block();
+    }
+
+    /// Block `td` on `wchan` if `ready` returns `false`. Returns `false` without blocking if `ready`
+    /// returns `true`.
+    ///
+    /// `ready` will be called with the chain lock held.
+    pub fn sleep_unless(&self, wchan: usize, td: &Waiter, ready: impl FnOnce() -> bool) -> bool {
+        let mut chain = self.0.lock(wchan);
+
+        if ready() {
+            return false;
+        }
+
+        // SAFETY: td will not go anywhere until it has been woken.
+        unsafe { chain.push(wchan, core::ptr::null(), td) };
+
+        drop(chain);
+
+        td.block();
+
+        true
+    }
+
+    /// Returns `true` if there are at least one thread has been woken.
+    ///
+    /// See `sleepq_broadcast` on the PS4 for a reference.
+    pub fn wakeup(&self, wchan: usize) -> bool {
+        let mut chain = self.0.lock(wchan);
+        let woken = chain.remove_all(wchan);
+
+        drop(chain);
+
+        !woken.is_empty()
+    }
+
+    /// Wakeup the thread with the highest priority. Returns `false` if there is no threads waiting
+    /// on `wchan`.
+    ///
+    /// See `sleepq_signal` on the PS4 for a reference.
+    pub fn wakeup_one(&self, wchan: usize) -> bool {
+        let mut chain = self.0.lock(wchan);
+        let woken = chain.remove_one(wchan);
+
+        drop(chain);
+
+        !woken.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    extern crate std;
+
+    #[test]
+    fn wakeup() {
+        let queues = SleepQueues::new();
+        let lock = AtomicBool::new(false);
+        let ready = AtomicBool::new(false);
+        let sleeping = AtomicUsize::new(0);
+        let woken = AtomicUsize::new(0);
+        let wchan = &ready as *const AtomicBool as usize;
+        let spin_lock = || {
+            while lock.swap(true, Ordering::Acquire) {
+                core::hint::spin_loop();
+            }
+        };
+
+        std::thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    let td = Waiter::new(120);
+
+                    spin_lock();
+
+                    while !ready.load(Ordering::Relaxed) {
+                        sleeping.fetch_add(1, Ordering::Relaxed);
+                        queues.sleep(wchan, &td, || lock.store(false, Ordering::Release));
+                        spin_lock();
+                    }
+
+                    lock.store(false, Ordering::Release);
+                    woken.fetch_add(1, Ordering::Relaxed);
+                });
+            }
+
+            // Wait until all threads is sleeping then wake them up.
+            while sleeping.load(Ordering::Relaxed) != 8 {
+                std::thread::yield_now();
+            }
+
+            spin_lock();
+            ready.store(true, Ordering::Relaxed);
+            lock.store(false, Ordering::Release);
+
+            assert!(queues.wakeup(wchan));
+        });
+
+        assert_eq!(woken.load(Ordering::Relaxed), 8);
+        assert!(!queues.wakeup(wchan));
+    }
+
+    #[test]
+    fn sleep_unless() {
+        let queues = SleepQueues::new();
+        let ready = AtomicBool::new(false);
+        let wchan = &ready as *const AtomicBool as usize;
+
+        for _ in 0..100 {
+            ready.store(false, Ordering::Relaxed);
+
+            std::thread::scope(|s| {
+                s.spawn(|| {
+                    let td = Waiter::new(120);
+
+                    while queues.sleep_unless(wchan, &td, || ready.load(Ordering::Relaxed)) {}
+                });
+
+                // The wakeup may happen before the thread start sleeping, which must not be lost.
+                ready.store(true, Ordering::Relaxed);
+                queues.wakeup(wchan);
+            });
+        }
+
+        assert!(!queues.wakeup(wchan));
+    }
+
+    #[test]
+    fn wakeup_one() {
+        let queues = SleepQueues::new();
+        let low = Waiter::new(200);
+        let high = Waiter::new(50);
+        let order = std::sync::Mutex::new(std::vec::Vec::new());
+        let sleeping = AtomicUsize::new(0);
+        let wchan = &queues as *const SleepQueues as usize;
+
+        std::thread::scope(|s| {
+            for td in [&low, &high] {
+                let order = &order;
+                let sleeping = &sleeping;
+                let queues = &queues;
+
+                s.spawn(move || {
+                    queues.sleep(wchan, td, || {
+                        sleeping.fetch_add(1, Ordering::Relaxed);
+                    });
+
+                    order.lock().unwrap().push(td.priority());
+                });
+            }
+
+            while sleeping.load(Ordering::Relaxed) != 2 {
+                std::thread::yield_now();
+            }
+
+            // Wake up one at a time.
+            assert!(queues.wakeup_one(wchan));
+
+            while order.lock().unwrap().len() != 1 {
+                std::thread::yield_now();
+            }
+
+            assert!(queues.wakeup_one(wchan));
+        });
+
+        assert!(!queues.wakeup_one(wchan));
+        assert_eq!(*order.lock().unwrap(), [50, 200]);
+    }
+}
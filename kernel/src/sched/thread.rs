@@ -0,0 +1,144 @@
+use super::PRI_TIMESHARE;
+use crate::context::Pcb;
+use crate::lock::{Spinlock, SpinlockGuard};
+use crate::proc::{CpuMask, CpuSet, Thread};
+use alloc::boxed::Box;
+use alloc::vec;
+use core::cell::UnsafeCell;
+use core::ptr::null;
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+
+/// Implementation of `td_sched` structure.
+///
+/// This also contains the fields of `thread` structure that are owned by the scheduler.
+pub struct TdSched {
+    state: Spinlock<TdState>,        // td_state + td_lock
+    class: AtomicU8,                 // td_pri_class
+    cpuset: CpuSet,                  // td_cpuset
+    cpu: AtomicUsize,                // ts_cpu
+    slice: AtomicUsize,              // ts_slice
+    owepreempt: AtomicBool,          // td_owepreempt
+    oncpu: AtomicBool,               // td_oncpu
+    pcb: UnsafeCell<Pcb>,            // td_pcb
+    next: UnsafeCell<*const Thread>, // td_runq
+    #[allow(dead_code)] // pcb is pointed to this stack.
+    kstack: Option<Box<[u8]>>, // td_kstack
+}
+
+impl TdSched {
+    /// Create the states for a thread that is currently running on a CPU.
+    ///
+    /// # Context safety
+    /// This function does not require a CPU context.
+    pub fn running() -> Self {
+        Self::new(TdState::Running, Pcb::default(), None)
+    }
+
+    /// Create the states for a new thread that will start at `entry` once it has been scheduled.
+    ///
+    /// See `vm_thread_new` and `cpu_set_fork_handler` on the PS4 for a reference.
+    pub fn inactive(entry: fn() -> !) -> Self {
+        let mut stack = vec![0u8; KSTACK_SIZE].into_boxed_slice();
+        let pcb = Pcb::new(&mut stack, super::fork_exit, entry as *const () as usize);
+
+        Self::new(TdState::Inactive, pcb, Some(stack))
+    }
+
+    fn new(state: TdState, pcb: Pcb, kstack: Option<Box<[u8]>>) -> Self {
+        let oncpu = state == TdState::Running;
+
+        Self {
+            state: Spinlock::new(state),
+            class: AtomicU8::new(PRI_TIMESHARE),
+            cpuset: CpuSet::new(CpuMask::all()),
+            cpu: AtomicUsize::new(0),
+            slice: AtomicUsize::new(SCHED_SLICE),
+            owepreempt: AtomicBool::new(false),
+            oncpu: AtomicBool::new(oncpu),
+            pcb: UnsafeCell::new(pcb),
+            next: UnsafeCell::new(null()),
+            kstack,
+        }
+    }
+
+    pub(super) fn state(&self) -> SpinlockGuard<'_, TdState> {
+        self.state.lock()
+    }
+
+    /// Returns the scheduling class of this thread (e.g. [`PRI_TIMESHARE`]).
+    pub(super) fn class(&self) -> u8 {
+        self.class.load(Ordering::Relaxed)
+    }
+
+    pub(super) fn set_class(&self, v: u8) {
+        self.class.store(v, Ordering::Relaxed);
+    }
+
+    pub fn cpuset(&self) -> &CpuSet {
+        &self.cpuset
+    }
+
+    /// Returns the CPU that this thread was last run on or queued to.
+    pub(super) fn cpu(&self) -> usize {
+        self.cpu.load(Ordering::Relaxed)
+    }
+
+    pub(super) fn set_cpu(&self, v: usize) {
+        self.cpu.store(v, Ordering::Relaxed);
+    }
+
+    /// Consume `ticks` from the time slice. Returns `true` if the time slice has been used up, in
+    /// which case it will be refilled.
+    pub(super) fn consume_slice(&self, ticks: usize) -> bool {
+        let left = self.slice.load(Ordering::Relaxed).saturating_sub(ticks);
+
+        if left == 0 {
+            self.slice.store(SCHED_SLICE, Ordering::Relaxed);
+            true
+        } else {
+            self.slice.store(left, Ordering::Relaxed);
+            false
+        }
+    }
+
+    pub(super) fn owepreempt(&self) -> &AtomicBool {
+        &self.owepreempt
+    }
+
+    /// `true` while the CPU is still using the stack of this thread.
+    pub(super) fn oncpu(&self) -> &AtomicBool {
+        &self.oncpu
+    }
+
+    pub(super) fn pcb(&self) -> *mut Pcb {
+        self.pcb.get()
+    }
+
+    /// # Safety
+    /// The caller must hold the lock of the run queue that contains this thread.
+    pub(super) unsafe fn next(&self) -> *mut *const Thread {
+        self.next.get()
+    }
+}
+
+unsafe impl Send for TdSched {}
+unsafe impl Sync for TdSched {}
+
+/// Implementation of `td_state`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(super) enum TdState {
+    /// The thread has not been added to the scheduler yet (`TDS_INACTIVE`).
+    Inactive,
+    /// The thread is blocked on a wait channel (`TDS_INHIBITED`).
+    Inhibited,
+    /// The thread is on a run queue (`TDS_RUNQ`).
+    Runnable,
+    /// The thread is running on a CPU (`TDS_RUNNING`).
+    Running,
+}
+
+/// Number of ticks a thread can run before it will be preempted.
+pub(super) const SCHED_SLICE: usize = 10;
+
+/// Size of the kernel stack for each thread.
+const KSTACK_SIZE: usize = 1024 * 64;
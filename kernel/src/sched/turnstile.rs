@@ -0,0 +1,104 @@
+use super::{Chains, Waiter};
+
+/// Queues of the threads that blocked on a lock.
+///
+/// The owner of the lock will get the priority of the highest priority waiter until it release the
+/// lock.
+///
+/// See `subr_turnstile.c` on the PS4 for a reference.
+pub struct Turnstiles(Chains);
+
+impl Turnstiles {
+    pub const fn new() -> Self {
+        Self(Chains::new())
+    }
+
+    /// Block `td` until the lock identified by `lock` has been released.
+    ///
+    /// `owner` will be called with the chain lock held and must return the current owner of the
+    /// lock or [`None`] if it is already released, in which case this method return immediately.
+    /// The owner must not be able to release the lock without calling [`Turnstiles::broadcast()`]
+    /// after `owner` returned.
+    ///
+    /// See `turnstile_wait` and `propagate_priority` on the PS4 for a reference.
+    pub fn wait(&self, lock: usize, td: &Waiter, owner: impl FnOnce() -> Option<*const Waiter>) {
+        let mut chain = self.0.lock(lock);
+        let mut owner = match owner() {
+            Some(v) => v,
+            None => return,
+        };
+
+        // SAFETY: td will not go anywhere until it has been woken.
+        unsafe { chain.push(lock, owner, td) };
+        unsafe { (*owner).contest(Chains::index(lock)) };
+
+        // Lend our priority to the owner and all threads that the owner is waiting for. The owner
+        // can't release the lock (and go away) while we are holding the chain of the lock it owns.
+        // The same for the lock that the owner is waiting for.
+        let pri = td.priority();
+        let mut current = Chains::index(lock);
+
+        loop {
+            let o = unsafe { &*owner };
+
+            if !o.lend(pri) {
+                break;
+            }
+
+            // Check if the owner is blocked on a lock.
+            let wchan = o.wchan();
+
+            if wchan == 0 {
+                break;
+            }
+
+            // Switch to the chain of that lock. We never hold two chain locks at the same time to
+            // prevent a deadlock with the other walk so the owner may be woken in the middle. Once
+            // woken it may be gone so we can only compare its address until we found it again.
+            let next = Chains::index(wchan);
+
+            if next != current {
+                drop(chain);
+
+                chain = self.0.lock(wchan);
+                current = next;
+
+                if !chain.contains(wchan, owner) {
+                    break;
+                }
+            }
+
+            // The owner may be blocked on a sleep queue.
+            let next = o.owner();
+
+            if next.is_null() {
+                break;
+            }
+
+            owner = next;
+        }
+
+        drop(chain);
+
+        td.block();
+    }
+
+    /// Wakeup all threads blocked on `lock`. `release` will be called with the chain lock held
+    /// to mark the lock as released.
+    ///
+    /// `owner` must be the waiter of the current thread, which own the lock.
+    ///
+    /// See `turnstile_broadcast` and `turnstile_unpend` on the PS4 for a reference.
+    pub fn broadcast(&self, lock: usize, owner: &Waiter, release: impl FnOnce()) {
+        let mut chain = self.0.lock(lock);
+        let woken = chain.remove_all(lock);
+
+        release();
+        drop(chain);
+
+        // Take back the priority that was lent by the waiters of this lock.
+        if !woken.is_empty() {
+            owner.unlend(|| self.0.lent_priority(owner));
+        }
+    }
+}
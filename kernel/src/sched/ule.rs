@@ -0,0 +1,393 @@
+use super::{PRI_FIFO_BIT, RtPrio, Runq, TdState, rtp_to_pri};
+use crate::arch::{disable_interrupts, enable_interrupts, halt, restore_interrupts};
+use crate::context::{BorrowedArc, PinnedContext, config, current_thread, pin_cpu};
+use crate::lock::Spinlock;
+use crate::proc::{MAXCPU, Thread};
+use alloc::sync::Arc;
+use core::hint::spin_loop;
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+
+/// Start scheduling the threads on the current CPU. `idle` will be run when there are no other
+/// threads to run on this CPU, which can be the current thread.
+///
+/// This must be called before any thread on this CPU can block.
+///
+/// See `sched_setup` and `sched_idletd` on the PS4 for a reference.
+pub fn sched_setup(idle: Arc<Thread>) {
+    let pin = pin_cpu();
+    let cpu = unsafe { pin.cpu() };
+    let tdq = &TDQ[cpu];
+    let rtp = RtPrio {
+        ty: RtPrio::IDLE,
+        prio: RtPrio::MAX,
+    };
+
+    assert!(config().max_cpu().get() <= MAXCPU);
+
+    rtp_to_pri(&rtp, &idle).unwrap();
+    idle.sched().set_cpu(cpu);
+    tdq.idle
+        .store(Arc::into_raw(idle).cast_mut(), Ordering::Release);
+
+    // Start the periodic timer for preemption.
+    crate::time::start_clock(cpu);
+
+    drop(pin);
+    enable_interrupts();
+}
+
+/// Add `td` that was created with [`Thread::new()`] to a run queue.
+///
+/// See `sched_add` on the PS4 for a reference.
+pub fn sched_add(td: Arc<Thread>) {
+    let mut st = td.sched().state();
+
+    assert!(*st == TdState::Inactive);
+
+    *st = TdState::Runnable;
+    drop(st);
+
+    tdq_add(td);
+}
+
+/// Switch to the other thread if the current thread has been preempted.
+///
+/// This must be called only when the current thread can sleep.
+///
+/// See `critical_exit` on the PS4 for a reference.
+pub fn preempt() {
+    let td = current_thread();
+
+    if !td.sched().owepreempt().load(Ordering::Relaxed) {
+        return;
+    }
+
+    mi_switch(&pin_cpu());
+}
+
+/// Invoked by the timer interrupt on each CPU.
+///
+/// See `hardclock_cpu` and `sched_clock` on the PS4 for a reference.
+pub fn sched_clock() {
+    let ticks = crate::time::ack_clock();
+    let td = current_thread();
+    let pin = pin_cpu();
+    let cpu = unsafe { pin.cpu() };
+
+    // The actual switch will be done when the interrupt handler returns.
+    if TDQ[cpu].clock(&td, ticks) {
+        td.sched().owepreempt().store(true, Ordering::Relaxed);
+    }
+}
+
+/// Entry point of the idle thread.
+///
+/// See `sched_idletd` on the PS4 for a reference.
+pub fn sched_idletd() -> ! {
+    loop {
+        let pin = pin_cpu();
+        let cpu = unsafe { pin.cpu() };
+
+        if TDQ[cpu].load() != 0 || tdq_idled(cpu) {
+            mi_switch(&pin);
+            continue;
+        }
+
+        drop(pin);
+
+        // TODO: Send IPI when a thread is added to the other CPU so we don't need to wait for the
+        // next tick.
+        halt();
+    }
+}
+
+/// Switch to the other thread until `woken` has been set by [`unblock()`].
+///
+/// `td` must be the current thread.
+///
+/// See `sleepq_switch` on the PS4 for a reference.
+#[cfg_attr(test, allow(dead_code))]
+pub(super) fn block(td: &Thread, woken: &AtomicBool) {
+    let pin = pin_cpu();
+    let mut st = td.sched().state();
+
+    if woken.load(Ordering::Relaxed) {
+        return;
+    }
+
+    *st = TdState::Inhibited;
+    drop(st);
+
+    mi_switch(&pin);
+}
+
+/// Set `woken` then put `td` that was blocked by [`block()`] back to a run queue.
+///
+/// The caller must not access `td` after this.
+///
+/// See `setrunnable` on the PS4 for a reference.
+#[cfg_attr(test, allow(dead_code))]
+pub(super) fn unblock(td: &Thread, woken: &AtomicBool) {
+    let mut st = td.sched().state();
+
+    woken.store(true, Ordering::Relaxed);
+
+    if *st != TdState::Inhibited {
+        return;
+    }
+
+    *st = TdState::Runnable;
+    drop(st);
+
+    // SAFETY: The reference that was owned by the CPU context has been left to us by mi_switch.
+    tdq_add(unsafe { Arc::from_raw(td) });
+}
+
+/// Entry point of a thread that was created with [`Thread::new()`].
+///
+/// See `fork_exit` on the PS4 for a reference.
+pub(super) extern "C" fn fork_exit(entry: usize) -> ! {
+    let entry: fn() -> ! = unsafe { core::mem::transmute(entry) };
+
+    // The previous thread has disabled interrupts before switched to us.
+    enable_interrupts();
+
+    entry();
+}
+
+/// Switch the current CPU to the thread with the highest priority. The current thread will be put
+/// back to a run queue if it is still runnable.
+///
+/// See `mi_switch` and `sched_switch` on the PS4 for a reference.
+fn mi_switch(pin: &PinnedContext) {
+    let td = current_thread();
+    let from = BorrowedArc::as_ptr(&td);
+    let cpu = unsafe { pin.cpu() };
+    let tdq = &TDQ[cpu];
+    let idle = tdq.idle();
+    let intr = disable_interrupts();
+
+    td.sched().owepreempt().store(false, Ordering::Relaxed);
+
+    if !core::ptr::eq(from, idle) {
+        let mut st = td.sched().state();
+
+        if *st == TdState::Running {
+            *st = TdState::Runnable;
+            drop(st);
+
+            // SAFETY: The reference that was owned by the CPU context will be transferred to the
+            // run queue.
+            tdq_add(unsafe { Arc::from_raw(from) });
+        }
+    }
+
+    // Pick the next thread. If the current thread has been blocked its reference that was owned
+    // by the CPU context will be left to unblock().
+    let next = tdq.choose().map_or(idle, Arc::into_raw);
+    let to = unsafe { &*next };
+
+    *to.sched().state() = TdState::Running;
+
+    if !core::ptr::eq(next, from) {
+        // The next thread may still switching out on the other CPU.
+        while to.sched().oncpu().load(Ordering::Acquire) {
+            spin_loop();
+        }
+
+        to.sched().oncpu().store(true, Ordering::Relaxed);
+        to.sched().set_cpu(cpu);
+
+        unsafe {
+            crate::context::switch(next, td.sched().pcb(), td.sched().oncpu(), to.sched().pcb())
+        };
+    }
+
+    restore_interrupts(intr);
+}
+
+/// See `tdq_add` on the PS4 for a reference.
+fn tdq_add(td: Arc<Thread>) {
+    let cpu = sched_pickcpu(&td);
+
+    td.sched().set_cpu(cpu);
+
+    TDQ[cpu].add(td);
+}
+
+/// Returns the CPU with the lowest load that `td` can run on.
+///
+/// See `sched_pickcpu` on the PS4 for a reference.
+fn sched_pickcpu(td: &Thread) -> usize {
+    let mask = td.sched().cpuset().mask();
+    let last = td.sched().cpu();
+
+    // Prefer the last CPU when it has the same load to keep the cache hot.
+    (0..config().max_cpu().get())
+        .filter(|&cpu| mask.has(cpu) && !TDQ[cpu].idle().is_null())
+        .min_by_key(|&cpu| (TDQ[cpu].load(), cpu != last))
+        .expect("no CPU available for the thread")
+}
+
+/// Move a thread from the busiest CPU to `cpu`. Returns `false` if there are no threads to move.
+///
+/// See `tdq_idled` on the PS4 for a reference.
+fn tdq_idled(cpu: usize) -> bool {
+    let src = (0..config().max_cpu().get())
+        .filter(|&c| c != cpu)
+        .max_by_key(|&c| TDQ[c].load());
+    let td = match src.and_then(|c| TDQ[c].steal(cpu)) {
+        Some(v) => v,
+        None => return false,
+    };
+
+    td.sched().set_cpu(cpu);
+
+    TDQ[cpu].add(td);
+
+    true
+}
+
+/// Implementation of `tdq` structure.
+struct Tdq {
+    runq: Spinlock<Runq>,    // tdq_lock + tdq_realtime + tdq_timeshare + tdq_idle
+    load: AtomicUsize,       // tdq_load
+    idle: AtomicPtr<Thread>, // pc_idlethread
+}
+
+impl Tdq {
+    const fn new() -> Self {
+        Self {
+            runq: Spinlock::new(Runq::new()),
+            load: AtomicUsize::new(0),
+            idle: AtomicPtr::new(null_mut()),
+        }
+    }
+
+    /// Returns the number of threads on the run queue.
+    fn load(&self) -> usize {
+        self.load.load(Ordering::Relaxed)
+    }
+
+    /// Returns null if the scheduler has not been started on this CPU.
+    fn idle(&self) -> *const Thread {
+        self.idle.load(Ordering::Acquire)
+    }
+
+    /// Consume `ticks` from the time slice of `td`, which is running on this CPU. Returns `true` if
+    /// `td` should be preempted by the other thread on this queue.
+    ///
+    /// See `sched_clock` on the PS4 for a reference.
+    fn clock(&self, td: &Thread, ticks: usize) -> bool {
+        let expired = if core::ptr::eq(td, self.idle()) {
+            true
+        } else if td.sched().class() & PRI_FIFO_BIT != 0 {
+            // FIFO threads are not time sliced.
+            false
+        } else {
+            td.sched().consume_slice(ticks)
+        };
+
+        expired && self.load() != 0
+    }
+
+    fn add(&self, td: Arc<Thread>) {
+        let mut rq = self.runq.lock();
+
+        rq.add(td);
+
+        self.load.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn choose(&self) -> Option<Arc<Thread>> {
+        let mut rq = self.runq.lock();
+        let td = rq.choose()?;
+
+        self.load.fetch_sub(1, Ordering::Relaxed);
+
+        Some(td)
+    }
+
+    /// Remove a thread that can run on `cpu`.
+    fn steal(&self, cpu: usize) -> Option<Arc<Thread>> {
+        let mut rq = self.runq.lock();
+        let td = rq.steal(|td| td.sched().cpuset().mask().has(cpu))?;
+
+        self.load.fetch_sub(1, Ordering::Relaxed);
+
+        Some(td)
+    }
+}
+
+/// See `tdq_cpu` on the PS4 for a reference.
+static TDQ: [Tdq; MAXCPU] = [const { Tdq::new() }; MAXCPU];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proc::{Pid, Proc, ProcAbi};
+    use crate::sched::thread::SCHED_SLICE;
+    use crate::sched::{PRI_FIFO_BIT, PRI_REALTIME};
+    use crate::trap::TrapFrame;
+
+    #[test]
+    fn preemption() {
+        struct Abi;
+
+        impl ProcAbi for Abi {
+            fn syscall_handler(&self, _: &Thread, _: &mut TrapFrame) {}
+        }
+
+        let proc = Arc::new(Proc::new_bare(Pid::KERNEL, Arc::new(Abi)));
+        let idle = Arc::new(Thread::new_bare(proc.clone()));
+        let t1 = Arc::new(Thread::new_bare(proc.clone()));
+        let t2 = Arc::new(Thread::new_bare(proc.clone()));
+        let tdq = Tdq::new();
+
+        tdq.idle
+            .store(Arc::as_ptr(&idle).cast_mut(), Ordering::Release);
+
+        // The idle thread should be preempted as soon as there is a thread to run.
+        assert!(!tdq.clock(&idle, 1));
+
+        tdq.add(t1.clone());
+
+        assert!(tdq.clock(&idle, 1));
+
+        // A thread without competitors is never preempted.
+        let mut running = tdq.choose().unwrap();
+
+        assert!(Arc::ptr_eq(&running, &t1));
+
+        for _ in 0..(SCHED_SLICE * 2) {
+            assert!(!tdq.clock(&running, 1));
+        }
+
+        // Both threads should take turns once the time slice has been used up.
+        tdq.add(t2.clone());
+
+        for i in 0..4 {
+            for _ in 1..SCHED_SLICE {
+                assert!(!tdq.clock(&running, 1));
+            }
+
+            assert!(tdq.clock(&running, 1));
+
+            // This is what mi_switch() does.
+            tdq.add(running);
+            running = tdq.choose().unwrap();
+
+            assert!(Arc::ptr_eq(&running, if i % 2 == 0 { &t2 } else { &t1 }));
+        }
+
+        // FIFO threads are not time sliced.
+        running.sched().set_class(PRI_REALTIME | PRI_FIFO_BIT);
+
+        for _ in 0..(SCHED_SLICE * 2) {
+            assert!(!tdq.clock(&running, 1));
+        }
+
+        assert_eq!(tdq.load(), 1);
+    }
+}
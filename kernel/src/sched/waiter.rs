@@ -0,0 +1,357 @@
+use crate::lock::{Spinlock, SpinlockGuard};
+#[cfg(not(test))]
+use crate::proc::Thread;
+use core::cell::UnsafeCell;
+#[cfg(test)]
+use core::hint::spin_loop;
+use core::ptr::{null, null_mut};
+use core::sync::atomic::{
+    AtomicBool, AtomicPtr, AtomicU8, AtomicU32, AtomicU64, AtomicUsize, Ordering,
+};
+
+/// Contains the states for a thread to block on [`super::Turnstiles`] or
+/// [`super::SleepQueues`].
+///
+/// The lower value of priority mean higher priority the same as the PS4.
+pub struct Waiter {
+    base: AtomicU8,                  // td_base_pri
+    lent: AtomicU32,                 // td_priority if it was lent on the lowest 8 bits
+    wchan: AtomicUsize,              // td_wchan
+    owner: AtomicPtr<Waiter>,        // td_blocked
+    next: UnsafeCell<*const Waiter>, // Protected by the chain lock.
+    woken: AtomicBool,
+    contested: [AtomicU64; TABLE_SIZE / 64], // td_contested
+}
+
+impl Waiter {
+    pub const fn new(pri: u8) -> Self {
+        Self {
+            base: AtomicU8::new(pri),
+            lent: AtomicU32::new(u8::MAX as u32),
+            wchan: AtomicUsize::new(0),
+            owner: AtomicPtr::new(null_mut()),
+            next: UnsafeCell::new(null()),
+            woken: AtomicBool::new(false),
+            contested: [const { AtomicU64::new(0) }; TABLE_SIZE / 64],
+        }
+    }
+
+    /// Returns the effective priority, which include the priority lent by the waiters.
+    pub fn priority(&self) -> u8 {
+        let base = self.base.load(Ordering::Relaxed);
+        let lent = self.lent.load(Ordering::Relaxed) as u8;
+
+        base.min(lent)
+    }
+
+    /// Set the priority of this waiter without the lent one.
+    pub(super) fn set_base(&self, pri: u8) {
+        self.base.store(pri, Ordering::Relaxed);
+    }
+
+    /// Returns `false` if the priority of this waiter is already equal or higher than `pri`.
+    pub(super) fn lend(&self, pri: u8) -> bool {
+        // Always bump the counter on the upper bits even if the priority does not changed so
+        // Self::unlend() will know it need to recompute.
+        let base = self.base.load(Ordering::Relaxed);
+        let old = self
+            .lent
+            .fetch_update(Ordering::Release, Ordering::Relaxed, |v| {
+                let lent = pri.min(v as u8);
+
+                Some((v & !0xFF).wrapping_add(0x100) | u32::from(lent))
+            })
+            .unwrap();
+
+        pri < base.min(old as u8)
+    }
+
+    /// Set the lent priority to the value returned from `f` ([`u8::MAX`] to remove it).
+    ///
+    /// `f` will be called again if [`Self::lend()`] was called in the middle.
+    pub(super) fn unlend(&self, mut f: impl FnMut() -> u8) {
+        let mut cur = self.lent.load(Ordering::Acquire);
+
+        loop {
+            let new = (cur & !0xFF) | u32::from(f());
+
+            match self
+                .lent
+                .compare_exchange(cur, new, Ordering::Relaxed, Ordering::Acquire)
+            {
+                Ok(_) => break,
+                Err(v) => cur = v,
+            }
+        }
+    }
+
+    /// Mark the chain at `index` as containing a waiter that blocked on a lock owned by this waiter.
+    ///
+    /// This must be called with the chain lock held.
+    pub(super) fn contest(&self, index: usize) {
+        self.contested[index / 64].fetch_or(1 << (index % 64), Ordering::Relaxed);
+    }
+
+    /// Returns the wait channel this waiter is blocked on or zero if it is not blocked.
+    pub(super) fn wchan(&self) -> usize {
+        self.wchan.load(Ordering::Acquire)
+    }
+
+    pub(super) fn owner(&self) -> *const Self {
+        self.owner.load(Ordering::Acquire)
+    }
+
+    /// Switch to the other thread until this waiter has been removed from the queue.
+    ///
+    /// This must be called by the thread that own this waiter after the chain lock has been
+    /// released. The tests does not have the scheduler so it will be busy-waiting instead.
+    pub(super) fn block(&self) {
+        #[cfg(not(test))]
+        super::block(unsafe { Thread::from_waiter(self) }, &self.woken);
+
+        #[cfg(test)]
+        while !self.woken.load(Ordering::Acquire) {
+            spin_loop();
+        }
+    }
+
+    /// Resume the thread that was blocked by [`Self::block()`].
+    ///
+    /// The waiter can't be accessed after this.
+    fn wake(&self) {
+        #[cfg(not(test))]
+        super::unblock(unsafe { Thread::from_waiter(self) }, &self.woken);
+
+        #[cfg(test)]
+        self.woken.store(true, Ordering::Release);
+    }
+}
+
+unsafe impl Send for Waiter {}
+unsafe impl Sync for Waiter {}
+
+/// Hash table of [`Waiter`] keyed by the wait channel.
+///
+/// See `turnstile_chains` and `sleepq_chains` on the PS4 for a reference.
+pub(super) struct Chains([Spinlock<Chain>; TABLE_SIZE]);
+
+impl Chains {
+    pub const fn new() -> Self {
+        Self([const { Spinlock::new(Chain { head: null() }) }; TABLE_SIZE])
+    }
+
+    /// See `TC_LOOKUP` and `SC_LOOKUP` on the PS4 for a reference.
+    pub fn lock(&self, wchan: usize) -> SpinlockGuard<'_, Chain> {
+        self.0[Self::index(wchan)].lock()
+    }
+
+    /// Returns index of the chain for `wchan`. The same index mean the same chain lock.
+    pub fn index(wchan: usize) -> usize {
+        (wchan >> 8) % TABLE_SIZE
+    }
+
+    /// Returns the highest priority of the waiters that blocked on a lock owned by `owner`.
+    ///
+    /// Only the chains that was marked with [`Waiter::contest()`] will be looked. The mark will be
+    /// removed from the chain that no longer contains the waiters for `owner`.
+    pub fn lent_priority(&self, owner: &Waiter) -> u8 {
+        let mut pri = u8::MAX;
+
+        for (i, bits) in owner.contested.iter().enumerate() {
+            let mut set = bits.load(Ordering::Relaxed);
+
+            while set != 0 {
+                let bit = set.trailing_zeros();
+                let c = self.0[i * 64 + bit as usize].lock();
+                let mut td = c.head;
+                let mut found = false;
+
+                set &= set - 1;
+
+                while !td.is_null() {
+                    let w = unsafe { &*td };
+
+                    if core::ptr::eq(w.owner(), owner) {
+                        pri = pri.min(w.priority());
+                        found = true;
+                    }
+
+                    td = unsafe { *w.next.get() };
+                }
+
+                // We need to do this while holding the chain lock so it does not race with the new
+                // waiter.
+                if !found {
+                    bits.fetch_and(!(1 << bit), Ordering::Relaxed);
+                }
+            }
+        }
+
+        pri
+    }
+}
+
+/// List of [`Waiter`] that has the same hash.
+pub(super) struct Chain {
+    head: *const Waiter,
+}
+
+impl Chain {
+    /// `owner` is the waiter that own the lock if `wchan` is a lock.
+    ///
+    /// # Safety
+    /// `td` must not already in any chain and it must outlive this chain or until it is removed.
+    pub unsafe fn push(&mut self, wchan: usize, owner: *const Waiter, td: &Waiter) {
+        td.wchan.store(wchan, Ordering::Relaxed);
+        td.owner.store(owner.cast_mut(), Ordering::Release);
+        td.woken.store(false, Ordering::Relaxed);
+
+        unsafe { *td.next.get() = self.head };
+
+        self.head = td;
+    }
+
+    /// Returns `true` if `td` is blocked on `wchan`.
+    ///
+    /// `td` does not need to be a valid pointer since it will be used only for comparison.
+    pub fn contains(&self, wchan: usize, td: *const Waiter) -> bool {
+        let mut w = self.head;
+
+        while let Some(v) = unsafe { w.as_ref() } {
+            if core::ptr::eq(w, td) {
+                return v.wchan.load(Ordering::Relaxed) == wchan;
+            }
+
+            w = unsafe { *v.next.get() };
+        }
+
+        false
+    }
+
+    /// Remove all waiters that blocked on `wchan`.
+    pub fn remove_all(&mut self, wchan: usize) -> Woken {
+        let mut woken = Woken(null());
+        let mut prev: *mut *const Waiter = &mut self.head;
+
+        while let Some(w) = unsafe { (*prev).as_ref() } {
+            let next = w.next.get();
+
+            if w.wchan.load(Ordering::Relaxed) != wchan {
+                prev = next;
+                continue;
+            }
+
+            unsafe { *prev = *next };
+            unsafe { woken.push(w) };
+        }
+
+        woken
+    }
+
+    /// Remove a waiter that has the highest priority from `wchan`. The oldest one will be removed
+    /// if there are multiple waiters with the same priority.
+    pub fn remove_one(&mut self, wchan: usize) -> Woken {
+        let mut found: Option<(*mut *const Waiter, u8)> = None;
+        let mut prev: *mut *const Waiter = &mut self.head;
+
+        // The newest waiter is on the head so we need to use <= to get the oldest one.
+        while let Some(w) = unsafe { (*prev).as_ref() } {
+            if w.wchan.load(Ordering::Relaxed) == wchan {
+                let pri = w.priority();
+
+                if found.is_none_or(|(_, p)| pri <= p) {
+                    found = Some((prev, pri));
+                }
+            }
+
+            prev = w.next.get();
+        }
+
+        // Remove.
+        let mut woken = Woken(null());
+
+        if let Some((prev, _)) = found {
+            let w = unsafe { &**prev };
+
+            unsafe { *prev = *w.next.get() };
+            unsafe { woken.push(w) };
+        }
+
+        woken
+    }
+}
+
+unsafe impl Send for Chain {}
+
+/// List of [`Waiter`] that was removed from [`Chain`].
+///
+/// All waiters will be resumed when this is dropped so it should be dropped after the chain lock
+/// has been released.
+pub(super) struct Woken(*const Waiter);
+
+impl Woken {
+    unsafe fn push(&mut self, td: &Waiter) {
+        td.wchan.store(0, Ordering::Relaxed);
+        td.owner.store(null_mut(), Ordering::Relaxed);
+
+        unsafe { *td.next.get() = self.0 };
+
+        self.0 = td;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_null()
+    }
+}
+
+impl Drop for Woken {
+    fn drop(&mut self) {
+        let mut td = self.0;
+
+        while let Some(w) = unsafe { td.as_ref() } {
+            // We can't access the waiter after it has been woken.
+            td = unsafe { *w.next.get() };
+
+            w.wake();
+        }
+    }
+}
+
+/// See `TC_TABLESIZE` and `SC_TABLESIZE` on the PS4 for a reference.
+const TABLE_SIZE: usize = 128;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern crate std;
+
+    #[test]
+    fn unlend_race() {
+        let owner = Waiter::new(200);
+        let mut calls = 0;
+
+        owner.lend(100);
+
+        // Lend the priority from the other thread after we computed the new one.
+        std::thread::scope(|s| {
+            owner.unlend(|| {
+                calls += 1;
+
+                if calls == 1 {
+                    s.spawn(|| assert!(owner.lend(50))).join().unwrap();
+                    u8::MAX
+                } else {
+                    50
+                }
+            });
+        });
+
+        assert_eq!(calls, 2);
+        assert_eq!(owner.priority(), 50);
+
+        owner.unlend(|| u8::MAX);
+
+        assert_eq!(owner.priority(), 200);
+    }
+}
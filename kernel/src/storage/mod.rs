@@ -0,0 +1,257 @@
+use crate::config::PAGE_SIZE;
+use alloc::format;
+use alloc::string::String;
+use config::{BootEnv, StorageCmd, StorageMode, StorageStat, StorageStatus};
+use core::cmp::min;
+use core::ptr::null_mut;
+use krt::boot_env;
+use thiserror::Error;
+
+mod vm;
+
+/// Firmware partition that served by the VMM.
+pub struct Partition {
+    name: String,
+}
+
+impl Partition {
+    /// Mount the partition `name` (e.g. `md0`).
+    pub fn mount(name: impl Into<String>) -> Result<Self, StorageError> {
+        let name = name.into();
+        let root = StorageFile::open(&name, StorageMode::Read)?;
+
+        if !root.stat()?.dir {
+            return Err(StorageError::Failed(StorageStatus::NotDirectory));
+        }
+
+        Ok(Self { name })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Open `path` relative to the root of this partition.
+    pub fn open(&self, path: &str, mode: StorageMode) -> Result<StorageFile, StorageError> {
+        StorageFile::open(&self.path(path), mode)
+    }
+
+    fn path(&self, path: &str) -> String {
+        match path.trim_start_matches('/') {
+            "" => self.name.clone(),
+            v => format!("{}/{}", self.name, v),
+        }
+    }
+}
+
+/// Opened file or directory on a [`Partition`].
+pub struct StorageFile {
+    handle: usize,
+}
+
+impl StorageFile {
+    fn open(path: &str, mode: StorageMode) -> Result<Self, StorageError> {
+        // The buffer can't cross a page boundary so we need to copy the path to an aligned buffer.
+        let path = path.as_bytes();
+        let mut buf = Buffer([0; 1024]);
+
+        if path.is_empty() {
+            return Err(StorageError::InvalidName);
+        }
+
+        let buf = buf
+            .0
+            .get_mut(..path.len())
+            .ok_or(StorageError::NameTooLong)?;
+
+        buf.copy_from_slice(path);
+
+        let handle = exec(StorageCmd::Open, 0, 0, mode, buf.as_mut_ptr(), buf.len())?;
+
+        Ok(Self { handle })
+    }
+
+    pub fn stat(&self) -> Result<StorageStat, StorageError> {
+        let mut buf = Buffer([0; 1024]);
+        let len = size_of::<StorageStat>();
+
+        exec(
+            StorageCmd::Stat,
+            self.handle,
+            0,
+            StorageMode::Read,
+            buf.0.as_mut_ptr(),
+            len,
+        )?;
+
+        Ok(unsafe { buf.0.as_ptr().cast::<StorageStat>().read() })
+    }
+
+    /// Read the file at `off` into `buf`. Returns the number of bytes read, which is less than the
+    /// size of `buf` only at the end of file.
+    #[allow(dead_code)] // TODO: Remove this once someone use it.
+    pub fn read(&self, off: usize, buf: &mut [u8]) -> Result<usize, StorageError> {
+        let mut n = 0;
+
+        for (i, len) in split_pages(buf.as_ptr() as usize, buf.len()) {
+            let buf = buf[i..].as_mut_ptr();
+            let r = exec(
+                StorageCmd::Read,
+                self.handle,
+                off + i,
+                StorageMode::Read,
+                buf,
+                len,
+            )?;
+
+            n += r;
+
+            if r != len {
+                break;
+            }
+        }
+
+        Ok(n)
+    }
+
+    /// Write `data` to the file at `off`. The file must be opened with [`StorageMode::ReadWrite`].
+    #[allow(dead_code)] // TODO: Remove this once someone use it.
+    pub fn write(&self, off: usize, data: &[u8]) -> Result<usize, StorageError> {
+        let mut n = 0;
+
+        for (i, len) in split_pages(data.as_ptr() as usize, data.len()) {
+            // The VMM never write to the buffer for this command.
+            let buf = data[i..].as_ptr().cast_mut();
+
+            n += exec(
+                StorageCmd::Write,
+                self.handle,
+                off + i,
+                StorageMode::Read,
+                buf,
+                len,
+            )?;
+        }
+
+        Ok(n)
+    }
+
+    /// Returns the name of the entry at `off` of the directory or [`None`] at the end of directory.
+    #[allow(dead_code)] // TODO: Remove this once someone use it.
+    pub fn read_dir(&self, off: usize) -> Result<Option<String>, StorageError> {
+        let mut buf = Buffer([0; 1024]);
+        let len = exec(
+            StorageCmd::ReadDir,
+            self.handle,
+            off,
+            StorageMode::Read,
+            buf.0.as_mut_ptr(),
+            buf.0.len(),
+        )?;
+
+        if len == 0 {
+            return Ok(None);
+        }
+
+        let name = core::str::from_utf8(&buf.0[..len]).map_err(|_| StorageError::InvalidName)?;
+
+        Ok(Some(name.into()))
+    }
+}
+
+impl Drop for StorageFile {
+    fn drop(&mut self) {
+        // There is nothing we can do if the VMM refused to close the handle.
+        exec(
+            StorageCmd::Close,
+            self.handle,
+            0,
+            StorageMode::Read,
+            null_mut(),
+            0,
+        )
+        .ok();
+    }
+}
+
+/// Buffer that never cross a page boundary.
+#[repr(C, align(1024))]
+struct Buffer([u8; 1024]);
+
+fn exec(
+    cmd: StorageCmd,
+    handle: usize,
+    off: usize,
+    mode: StorageMode,
+    buf: *mut u8,
+    len: usize,
+) -> Result<usize, StorageError> {
+    match boot_env() {
+        BootEnv::Vm(env) => self::vm::exec(env, cmd, handle, off, mode, buf, len),
+    }
+}
+
+/// Split `len` bytes at `addr` at each page boundary. Each item is an offset from `addr` and the
+/// length.
+fn split_pages(addr: usize, len: usize) -> impl Iterator<Item = (usize, usize)> {
+    let mut off = 0;
+
+    core::iter::from_fn(move || {
+        if off == len {
+            return None;
+        }
+
+        let page = PAGE_SIZE.get() - ((addr + off) % PAGE_SIZE.get());
+        let n = min(page, len - off);
+        let r = (off, n);
+
+        off += n;
+
+        Some(r)
+    })
+}
+
+/// Represents an error when an operation on [`Partition`] or [`StorageFile`] fails.
+#[derive(Debug, Error)]
+pub enum StorageError {
+    #[error("no storage device")]
+    NoDevice,
+
+    #[error("name too long")]
+    NameTooLong,
+
+    #[error("invalid name")]
+    InvalidName,
+
+    #[error("storage device returned {0:?}")]
+    Failed(StorageStatus),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn split() {
+        let page = PAGE_SIZE.get();
+
+        assert_eq!(split_pages(page, 0).count(), 0);
+        assert_eq!(split_pages(page, 16).collect::<Vec<_>>(), [(0, 16)]);
+        assert_eq!(split_pages(page, page).collect::<Vec<_>>(), [(0, page)]);
+        assert_eq!(
+            split_pages(page - 8, page + 16).collect::<Vec<_>>(),
+            [(0, 8), (8, page), (page + 8, 8)]
+        );
+    }
+
+    #[test]
+    fn path() {
+        let part = Partition { name: "md0".into() };
+
+        assert_eq!(part.path(""), "md0");
+        assert_eq!(part.path("/"), "md0");
+        assert_eq!(part.path("/sbin/init"), "md0/sbin/init");
+        assert_eq!(part.path("sbin/init"), "md0/sbin/init");
+    }
+}
@@ -0,0 +1,38 @@
+use super::StorageError;
+use crate::context::pin_cpu;
+use config::{DeviceType, StorageCmd, StorageMemory, StorageMode, StorageStatus, Vm};
+use core::ptr::{read_volatile, write_volatile};
+
+pub fn exec(
+    vm: &Vm,
+    cmd: StorageCmd,
+    handle: usize,
+    off: usize,
+    mode: StorageMode,
+    buf: *mut u8,
+    len: usize,
+) -> Result<usize, StorageError> {
+    let s = vm
+        .device(DeviceType::Storage)
+        .ok_or(StorageError::NoDevice)? as *mut StorageMemory;
+
+    // The sequence of operations must be on the same CPU.
+    let pin = pin_cpu();
+
+    unsafe { write_volatile(&raw mut (*s).handle, handle) };
+    unsafe { write_volatile(&raw mut (*s).offset, off) };
+    unsafe { write_volatile(&raw mut (*s).mode, mode) };
+    unsafe { write_volatile(&raw mut (*s).buf_len, len) };
+    unsafe { write_volatile(&raw mut (*s).buf_addr, buf as usize) };
+    unsafe { write_volatile(&raw mut (*s).command, cmd) };
+
+    let status = unsafe { read_volatile(&raw const (*s).status) };
+    let result = unsafe { read_volatile(&raw const (*s).result) };
+
+    drop(pin);
+
+    match status {
+        StorageStatus::Ok => Ok(result),
+        v => Err(StorageError::Failed(v)),
+    }
+}
@@ -0,0 +1,64 @@
+use crate::errno::{Errno, strerror};
+use alloc::boxed::Box;
+use core::error::Error;
+use core::fmt::{Display, Formatter};
+use core::num::NonZero;
+
+/// Error of each syscall.
+#[derive(Debug)]
+pub enum SysErr {
+    Raw(NonZero<i32>),
+    Object(Box<dyn Errno>),
+}
+
+impl SysErr {
+    pub fn errno(&self) -> NonZero<i32> {
+        match self {
+            Self::Raw(v) => *v,
+            Self::Object(v) => v.errno(),
+        }
+    }
+}
+
+impl<T: Errno + 'static> From<T> for SysErr {
+    fn from(value: T) -> Self {
+        Self::Object(Box::new(value))
+    }
+}
+
+impl Error for SysErr {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Raw(_) => None,
+            Self::Object(e) => e.source(),
+        }
+    }
+}
+
+impl Display for SysErr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Raw(v) => match strerror(*v) {
+                Some(v) => f.write_str(v),
+                None => write!(f, "unknown error {v}"),
+            },
+            Self::Object(e) => Display::fmt(&e, f),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errno::EINVAL;
+    use alloc::string::ToString;
+
+    #[test]
+    fn display() {
+        assert_eq!(SysErr::Raw(EINVAL).to_string(), "invalid argument");
+        assert_eq!(
+            SysErr::Raw(NonZero::new(1000).unwrap()).to_string(),
+            "unknown error 1000"
+        );
+    }
+}
@@ -0,0 +1,149 @@
+use super::SysErr;
+use crate::context::uma;
+use crate::errno::EFAULT;
+use core::fmt::{Formatter, LowerHex};
+use core::num::{NonZero, TryFromIntError};
+use core::ptr::NonNull;
+
+/// Input of the syscall.
+pub struct SysIn {
+    pub id: u32,
+    pub offset: usize,
+    /// The last argument will be zero if it is on the user stack. Use [`SysIn::arg()`] if the
+    /// syscall need it.
+    pub args: [SysArg; 6],
+    /// Address of the last argument on the user stack.
+    pub stack: Option<usize>,
+}
+
+impl SysIn {
+    /// Returns the argument at `n`, which will be read from the user stack if it was not passed in
+    /// the registers.
+    ///
+    /// We only read the user stack here since the syscall with fewer arguments should not fail
+    /// because of an unmapped stack slot.
+    pub fn arg(&self, n: usize) -> Result<SysArg, SysErr> {
+        if n != self.args.len() - 1 {
+            return Ok(self.args[n]);
+        }
+
+        match self.stack {
+            Some(v) => {
+                let ptr = SysArg::new(v).to_ptr::<usize>()?;
+
+                Ok(SysArg::new(ptr.map(|v| unsafe { v.read() }).unwrap_or(0)))
+            }
+            None => Ok(self.args[n]),
+        }
+    }
+}
+
+/// An argument of the syscall.
+#[repr(transparent)]
+#[derive(Clone, Copy)]
+pub struct SysArg(usize);
+
+impl SysArg {
+    pub fn new(v: usize) -> Self {
+        Self(v)
+    }
+
+    /// Returns [`None`] if the argument is null or [`EFAULT`] if the memory is not inside the user
+    /// space, not aligned for `T` or not mapped as writable user memory.
+    ///
+    /// The returned pointer can only be accessed from the thread that invoke the syscall.
+    pub fn to_ptr<T>(self) -> Result<Option<NonNull<T>>, SysErr> {
+        let ptr = match NonNull::new(self.0 as *mut T) {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+
+        let end = self.0.checked_add(size_of::<T>());
+
+        if !ptr.is_aligned() || end.is_none_or(|v| v > MAX_USER_ADDR) {
+            return Err(SysErr::Raw(EFAULT));
+        }
+
+        // TODO: Handle page fault instead once we have a page fault handler for the user memory.
+        // The memory can still be unmapped by the other thread after this check.
+        let uma = uma().unwrap();
+
+        if !uma.vm().is_user_writable(self.0, size_of::<T>()) {
+            return Err(SysErr::Raw(EFAULT));
+        }
+
+        Ok(Some(ptr))
+    }
+}
+
+impl<T> From<SysArg> for *const T {
+    fn from(v: SysArg) -> Self {
+        v.0 as _
+    }
+}
+
+impl<T> From<SysArg> for *mut T {
+    fn from(v: SysArg) -> Self {
+        v.0 as _
+    }
+}
+
+impl From<SysArg> for i64 {
+    fn from(v: SysArg) -> Self {
+        v.0 as _
+    }
+}
+
+impl From<SysArg> for u64 {
+    fn from(v: SysArg) -> Self {
+        v.0 as _
+    }
+}
+
+impl From<SysArg> for usize {
+    fn from(v: SysArg) -> Self {
+        v.0
+    }
+}
+
+impl TryFrom<SysArg> for i32 {
+    type Error = TryFromIntError;
+
+    fn try_from(v: SysArg) -> Result<Self, Self::Error> {
+        TryInto::<u32>::try_into(v.0).map(|v| v as i32)
+    }
+}
+
+impl TryFrom<SysArg> for Option<NonZero<i32>> {
+    type Error = TryFromIntError;
+
+    fn try_from(v: SysArg) -> Result<Self, Self::Error> {
+        let v = TryInto::<i32>::try_into(v)?;
+        Ok(NonZero::new(v))
+    }
+}
+
+impl TryFrom<SysArg> for u32 {
+    type Error = TryFromIntError;
+
+    fn try_from(v: SysArg) -> Result<Self, Self::Error> {
+        v.0.try_into()
+    }
+}
+
+impl TryFrom<SysArg> for u8 {
+    type Error = TryFromIntError;
+
+    fn try_from(v: SysArg) -> Result<Self, Self::Error> {
+        v.0.try_into()
+    }
+}
+
+impl LowerHex for SysArg {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        LowerHex::fmt(&self.0, f)
+    }
+}
+
+/// Equivalent to `VM_MAXUSER_ADDRESS` on the PS4.
+const MAX_USER_ADDR: usize = 0x800000000000;
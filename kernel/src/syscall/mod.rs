@@ -0,0 +1,72 @@
+pub use self::error::*;
+pub use self::input::*;
+pub use self::output::*;
+
+use crate::errno::ENOSYS;
+use crate::proc::Thread;
+use crate::subsystem::Subsystem;
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use krt::warn;
+
+mod error;
+mod input;
+mod output;
+
+/// Implementation of `sysent` table.
+///
+/// Each [`Subsystem`] register its own handlers with [`Syscalls::register()`].
+pub struct Syscalls([Option<Handler>; 680]);
+
+impl Syscalls {
+    pub const fn new() -> Self {
+        Self([const { None }; 680])
+    }
+
+    /// # Panics
+    /// If `id` is not a valid number or the syscall with identifier `id` is already registered.
+    pub fn register<S: Subsystem>(
+        &mut self,
+        id: u32,
+        s: &Arc<S>,
+        handler: fn(&Arc<S>, &Thread, &SysIn) -> Result<SysOut, SysErr>,
+    ) {
+        let id = usize::try_from(id).unwrap();
+        let s = s.clone();
+
+        assert!(
+            self.0[id]
+                .replace(Box::new(move |td, i| handler(&s, td, i)))
+                .is_none()
+        );
+    }
+
+    /// Unknown syscall will return [`ENOSYS`] instead of sending `SIGSYS` to the process.
+    ///
+    /// See `nosys` on the PS4 for a reference.
+    pub fn exec(&self, td: &Thread, i: &SysIn) -> Result<SysOut, SysErr> {
+        let id = usize::try_from(i.id).unwrap();
+        let handler = match self.0.get(id) {
+            Some(Some(v)) => v,
+            _ => {
+                warn!(
+                    "Unknown syscall {} at {:#x} with args = [{:#x}, {:#x}, {:#x}, {:#x}, {:#x}, {:#x}].",
+                    i.id,
+                    i.offset,
+                    i.args[0],
+                    i.args[1],
+                    i.args[2],
+                    i.args[3],
+                    i.args[4],
+                    i.arg(5).unwrap_or(SysArg::new(0))
+                );
+
+                return Err(SysErr::Raw(ENOSYS));
+            }
+        };
+
+        handler(td, i)
+    }
+}
+
+type Handler = Box<dyn Fn(&Thread, &SysIn) -> Result<SysOut, SysErr> + Send + Sync>;
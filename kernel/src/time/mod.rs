@@ -0,0 +1,259 @@
+use crate::errno::{EFAULT, EINVAL, ENOSYS, Errno};
+use crate::proc::Thread;
+use crate::subsystem::Subsystem;
+use crate::syscall::{SysErr, SysIn, SysOut, Syscalls};
+use crate::trap::TIMER_VECTOR;
+use alloc::sync::Arc;
+use config::BootEnv;
+use core::num::NonZero;
+use krt::boot_env;
+use thiserror::Error;
+
+mod vm;
+
+/// Number of clock ticks per second.
+pub const HZ: u64 = 100;
+
+/// Subsystem to provide time-related syscalls.
+pub struct TimeMgr {}
+
+impl TimeMgr {
+    pub fn new(sys: &mut Syscalls) -> Arc<Self> {
+        let mgr = Arc::new(Self {});
+
+        sys.register(116, &mgr, Self::sys_gettimeofday);
+        sys.register(232, &mgr, Self::sys_clock_gettime);
+        sys.register(234, &mgr, Self::sys_clock_getres);
+
+        mgr
+    }
+
+    /// See `sys_gettimeofday` on the PS4 for a reference.
+    fn sys_gettimeofday(self: &Arc<Self>, _: &Thread, i: &SysIn) -> Result<SysOut, SysErr> {
+        let tv = i.args[0].to_ptr::<TimeVal>()?;
+        let tz = i.args[1].to_ptr::<TimeZone>()?;
+
+        if let Some(tv) = tv {
+            unsafe { tv.write(gettimeofday()) };
+        }
+
+        if let Some(tz) = tz {
+            // We don't support tz_minuteswest and tz_dsttime so it always zero.
+            unsafe { tz.write(TimeZone::default()) };
+        }
+
+        Ok(SysOut::from(0))
+    }
+
+    /// See `sys_clock_gettime` on the PS4 for a reference.
+    fn sys_clock_gettime(self: &Arc<Self>, _: &Thread, i: &SysIn) -> Result<SysOut, SysErr> {
+        let id = i.args[0].try_into().map_err(|_| SysErr::Raw(EINVAL))?;
+        let tp = i.args[1].to_ptr::<TimeSpec>()?.ok_or(SysErr::Raw(EFAULT))?;
+        let ts = clock_gettime(ClockId(id))?;
+
+        unsafe { tp.write(ts) };
+
+        Ok(SysOut::from(0))
+    }
+
+    /// See `sys_clock_getres` on the PS4 for a reference.
+    fn sys_clock_getres(self: &Arc<Self>, _: &Thread, i: &SysIn) -> Result<SysOut, SysErr> {
+        let id = i.args[0].try_into().map_err(|_| SysErr::Raw(EINVAL))?;
+        let tp = i.args[1].to_ptr::<TimeSpec>()?;
+        let ts = clock_getres(ClockId(id))?;
+
+        if let Some(tp) = tp {
+            unsafe { tp.write(ts) };
+        }
+
+        Ok(SysOut::from(0))
+    }
+}
+
+impl Subsystem for TimeMgr {}
+
+/// See `kern_clock_gettime` on the PS4 for a reference.
+pub fn clock_gettime(id: ClockId) -> Result<TimeSpec, ClockError> {
+    let ts = match id {
+        ClockId::REALTIME | ClockId::REALTIME_PRECISE | ClockId::REALTIME_FAST => nanotime(),
+        ClockId::MONOTONIC
+        | ClockId::MONOTONIC_PRECISE
+        | ClockId::MONOTONIC_FAST
+        | ClockId::UPTIME
+        | ClockId::UPTIME_PRECISE
+        | ClockId::UPTIME_FAST => nanouptime(),
+        ClockId::SECOND => TimeSpec {
+            sec: nanotime().sec,
+            nsec: 0,
+        },
+        // We don't keep track of the CPU time used by each thread yet so the clocks that based on
+        // it are not supported. The same for the clocks that synchronized with the network.
+        ClockId::VIRTUAL
+        | ClockId::PROF
+        | ClockId::THREAD_CPUTIME_ID
+        | ClockId::PROC_TIME
+        | ClockId::EXT_NETWORK
+        | ClockId::EXT_DEBUG_NETWORK
+        | ClockId::EXT_AD_NETWORK
+        | ClockId::EXT_RAW_NETWORK
+        | ClockId(..0) => return Err(ClockError::UnsupportedClock(id)),
+        _ => return Err(ClockError::InvalidClock),
+    };
+
+    Ok(ts)
+}
+
+/// See `kern_clock_getres` on the PS4 for a reference.
+pub fn clock_getres(id: ClockId) -> Result<TimeSpec, ClockError> {
+    // Our clock source has nanosecond resolution.
+    let ts = match id {
+        ClockId::REALTIME
+        | ClockId::REALTIME_PRECISE
+        | ClockId::REALTIME_FAST
+        | ClockId::MONOTONIC
+        | ClockId::MONOTONIC_PRECISE
+        | ClockId::MONOTONIC_FAST
+        | ClockId::UPTIME
+        | ClockId::UPTIME_PRECISE
+        | ClockId::UPTIME_FAST => TimeSpec { sec: 0, nsec: 1 },
+        ClockId::SECOND => TimeSpec { sec: 1, nsec: 0 },
+        ClockId::VIRTUAL | ClockId::PROF | ClockId::THREAD_CPUTIME_ID | ClockId(..0) => {
+            return Err(ClockError::UnsupportedClock(id));
+        }
+        _ => return Err(ClockError::InvalidClock),
+    };
+
+    Ok(ts)
+}
+
+/// See `microtime` on the PS4 for a reference.
+pub fn gettimeofday() -> TimeVal {
+    let ts = nanotime();
+
+    TimeVal {
+        sec: ts.sec,
+        usec: ts.nsec / 1000,
+    }
+}
+
+/// Returns the time since Unix epoch.
+///
+/// See `nanotime` on the PS4 for a reference.
+pub fn nanotime() -> TimeSpec {
+    let v = match boot_env() {
+        BootEnv::Vm(vm) => self::vm::realtime(vm),
+    };
+
+    TimeSpec::from_nanos(v)
+}
+
+/// Returns the time since the machine was started.
+///
+/// See `nanouptime` on the PS4 for a reference.
+pub fn nanouptime() -> TimeSpec {
+    let v = match boot_env() {
+        BootEnv::Vm(vm) => self::vm::monotonic(vm),
+    };
+
+    TimeSpec::from_nanos(v)
+}
+
+/// Start a periodic interrupt on `cpu` at the rate of [`HZ`]. The interrupt will be delivered to
+/// [`crate::sched::sched_clock()`].
+///
+/// This must be called on `cpu`. See `cpu_initclocks_bsp` and `cpu_initclocks_ap` on the PS4 for
+/// a reference.
+pub fn start_clock(cpu: usize) {
+    let period = 1000000000 / HZ;
+
+    match boot_env() {
+        BootEnv::Vm(vm) => self::vm::start_clock(vm, cpu, period, TIMER_VECTOR),
+    }
+}
+
+/// Acknowledge the clock interrupt on the current CPU. Returns the number of ticks since the last
+/// call.
+///
+/// The next interrupt will not be delivered until this has been called.
+pub fn ack_clock() -> usize {
+    match boot_env() {
+        BootEnv::Vm(vm) => self::vm::expired(vm),
+    }
+}
+
+/// Identifier of a clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockId(pub i32);
+
+impl ClockId {
+    pub const REALTIME: Self = Self(0);
+    pub const VIRTUAL: Self = Self(1);
+    pub const PROF: Self = Self(2);
+    pub const MONOTONIC: Self = Self(4);
+    pub const UPTIME: Self = Self(5);
+    pub const UPTIME_PRECISE: Self = Self(7);
+    pub const UPTIME_FAST: Self = Self(8);
+    pub const REALTIME_PRECISE: Self = Self(9);
+    pub const REALTIME_FAST: Self = Self(10);
+    pub const MONOTONIC_PRECISE: Self = Self(11);
+    pub const MONOTONIC_FAST: Self = Self(12);
+    pub const SECOND: Self = Self(13);
+    pub const THREAD_CPUTIME_ID: Self = Self(14);
+    pub const PROC_TIME: Self = Self(15);
+    pub const EXT_NETWORK: Self = Self(16);
+    pub const EXT_DEBUG_NETWORK: Self = Self(17);
+    pub const EXT_AD_NETWORK: Self = Self(18);
+    pub const EXT_RAW_NETWORK: Self = Self(19);
+}
+
+/// Implementation of `timespec` structure.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeSpec {
+    pub sec: i64,  // tv_sec
+    pub nsec: i64, // tv_nsec
+}
+
+impl TimeSpec {
+    fn from_nanos(v: u64) -> Self {
+        Self {
+            sec: (v / 1000000000).try_into().unwrap(),
+            nsec: (v % 1000000000).try_into().unwrap(),
+        }
+    }
+}
+
+/// Implementation of `timeval` structure.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeVal {
+    pub sec: i64,  // tv_sec
+    pub usec: i64, // tv_usec
+}
+
+/// Implementation of `timezone` structure.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TimeZone {
+    pub minuteswest: i32, // tz_minuteswest
+    pub dsttime: i32,     // tz_dsttime
+}
+
+/// Represents an error when [`clock_gettime()`] or [`clock_getres()`] fails.
+#[derive(Debug, Error)]
+pub enum ClockError {
+    #[error("invalid clock")]
+    InvalidClock,
+
+    #[error("clock {0:?} is not supported")]
+    UnsupportedClock(ClockId),
+}
+
+impl Errno for ClockError {
+    fn errno(&self) -> NonZero<i32> {
+        match self {
+            Self::InvalidClock => EINVAL,
+            Self::UnsupportedClock(_) => ENOSYS,
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use config::{DeviceType, TimerMemory, Vm};
+use core::ptr::{read_volatile, write_volatile};
+
+pub fn monotonic(vm: &Vm) -> u64 {
+    let t = vm.device(DeviceType::Timer).unwrap() as *const TimerMemory;
+
+    unsafe { read_volatile(&raw const (*t).monotonic) as u64 }
+}
+
+pub fn realtime(vm: &Vm) -> u64 {
+    let t = vm.device(DeviceType::Timer).unwrap() as *const TimerMemory;
+
+    unsafe { read_volatile(&raw const (*t).realtime) as u64 }
+}
+
+pub fn start_clock(vm: &Vm, cpu: usize, period: u64, vector: u8) {
+    let t = vm.device(DeviceType::Timer).unwrap() as *mut TimerMemory;
+
+    unsafe { write_volatile(&raw mut (*t).deadline_cpu, cpu) };
+    unsafe { write_volatile(&raw mut (*t).deadline_period, period.try_into().unwrap()) };
+    unsafe { write_volatile(&raw mut (*t).deadline_vector, vector.into()) };
+    unsafe {
+        write_volatile(
+            &raw mut (*t).deadline,
+            (monotonic(vm) + period).try_into().unwrap(),
+        )
+    };
+}
+
+pub fn expired(vm: &Vm) -> usize {
+    let t = vm.device(DeviceType::Timer).unwrap() as *const TimerMemory;
+
+    unsafe { read_volatile(&raw const (*t).expired) }
+}
@@ -5,6 +5,16 @@ pub extern "C" fn interrupt_handler(_: &mut TrapFrame) {
     todo!()
 }
 
+/// Interrupt vector of the clock interrupt.
+pub const TIMER_VECTOR: u8 = 0; // TODO: Use the actual interrupt ID once we support AArch64.
+
 /// Contains states of the interupted program.
 #[repr(C)]
-pub struct TrapFrame {}
+pub struct TrapFrame {
+    pub sp: usize,      // tf_sp
+    pub lr: usize,      // tf_lr
+    pub elr: usize,     // tf_elr
+    pub spsr: u32,      // tf_spsr
+    pub esr: u32,       // tf_esr
+    pub x: [usize; 30], // tf_x
+}
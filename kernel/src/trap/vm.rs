@@ -1,9 +1,21 @@
 use super::TrapFrame;
-use config::Vm;
+use config::{DebuggerMemory, DeviceType, Vm};
+use core::num::NonZero;
+use core::ptr::{read_volatile, write_volatile};
 
+/// Returns `false` if no debugger is attached to handle the trap.
+///
 /// # Interupt safety
 /// This function can be called from interupt handler.
-pub fn interrupt_handler(_: &Vm, _: &mut TrapFrame) {
-    // TODO: Implement a virtual device with GDB stub.
-    todo!()
+pub fn interrupt_handler(vm: &Vm, trap: usize, frame: &mut TrapFrame) -> bool {
+    // Report to the debugger. The write to frame_addr will not return until the debugger resume
+    // the CPU.
+    let dbg = vm.device(DeviceType::Debugger).unwrap() as *mut DebuggerMemory;
+    let len = NonZero::new(size_of::<TrapFrame>()).unwrap();
+    let frame = frame as *mut TrapFrame as usize;
+
+    unsafe { write_volatile(&raw mut (*dbg).trap, trap) };
+    unsafe { write_volatile(&raw mut (*dbg).frame_len, len) };
+    unsafe { write_volatile(&raw mut (*dbg).frame_addr, frame) };
+    unsafe { read_volatile(&raw const (*dbg).handled) != 0 }
 }
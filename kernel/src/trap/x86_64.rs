@@ -14,12 +14,29 @@ pub extern "C" fn interrupt_handler(frame: &mut TrapFrame) {
     unsafe { td.active_interrupts().fetch_add(1, Ordering::Relaxed) };
 
     match frame.num {
-        TrapNo::Breakpoint => match boot_env() {
-            BootEnv::Vm(vm) => super::vm::interrupt_handler(vm, frame),
-        },
+        TrapNo::Debug | TrapNo::Breakpoint => {
+            let handled = match boot_env() {
+                BootEnv::Vm(vm) => super::vm::interrupt_handler(vm, frame.num as usize, frame),
+            };
+
+            if !handled {
+                trap_fatal(frame);
+            }
+        }
+        TrapNo::Timer => crate::sched::sched_clock(),
     }
 
     unsafe { td.active_interrupts().fetch_sub(1, Ordering::Relaxed) };
+
+    // Switch to the other thread if the current thread was preempted by the interrupt.
+    if td.can_sleep() {
+        crate::sched::preempt();
+    }
+}
+
+/// See `trap_fatal` function on the PS4 for a reference.
+fn trap_fatal(frame: &TrapFrame) -> ! {
+    panic!("fatal trap {} at {:#x}", frame.num as u32, frame.rip);
 }
 
 /// Main entry point for `syscall` instruction.
@@ -27,7 +44,7 @@ pub extern "C" fn interrupt_handler(frame: &mut TrapFrame) {
 /// This will be called by an inline assembly.
 ///
 /// See `amd64_syscall` function on the PS4 for a reference.
-pub extern "C" fn syscall_handler() {
+pub extern "C" fn syscall_handler(frame: &mut TrapFrame) {
     // TODO: Implement pc_cnt.v_syscall increment.
     let td = current_thread();
     let p = td.proc();
@@ -35,9 +52,9 @@ pub extern "C" fn syscall_handler() {
     td.set_profiling_ticks(0);
 
     // We merge sv_fetch_syscall_args and the code to invoke each syscall handler together.
-    p.abi().syscall_handler();
+    p.abi().syscall_handler(&td, frame);
 
-    todo!()
+    // TODO: Implement syscallret.
 }
 
 /// Predefined interrupt vector number.
@@ -45,28 +62,46 @@ pub extern "C" fn syscall_handler() {
 #[repr(u32)]
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum TrapNo {
+    Debug = 1,      // T_TRCTRAP
     Breakpoint = 3, // T_BPTFLT
+    Timer = 240,    // APIC_TIMER_INT
 }
 
+/// Interrupt vector of the clock interrupt.
+pub const TIMER_VECTOR: u8 = TrapNo::Timer as u8;
+
+/// Set in [`TrapFrame::flags`] when the segment registers was saved.
+pub const TF_HASSEGS: u32 = 0x1;
+
 /// Contains states of the interupted program.
 #[repr(C)]
 pub struct TrapFrame {
-    pub rdi: usize,  // tf_rdi
-    pub rsi: usize,  // tf_rsi
-    pub rdx: usize,  // tf_rdx
-    pub rcx: usize,  // tf_rcx
-    pub r8: usize,   // tf_r8
-    pub r9: usize,   // tf_r9
-    pub rax: usize,  // tf_rax
-    pub rbx: usize,  // tf_rbx
-    pub rbp: usize,  // tf_rbp
-    pub r10: usize,  // tf_r10
-    pub r11: usize,  // tf_r11
-    pub r12: usize,  // tf_r12
-    pub r13: usize,  // tf_r13
-    pub r14: usize,  // tf_r14
-    pub r15: usize,  // tf_r15
-    pub num: TrapNo, // tf_trapno
-    pub fs: u16,     // tf_fs
-    pub gs: u16,     // tf_gs
+    pub rdi: usize,    // tf_rdi
+    pub rsi: usize,    // tf_rsi
+    pub rdx: usize,    // tf_rdx
+    pub rcx: usize,    // tf_rcx
+    pub r8: usize,     // tf_r8
+    pub r9: usize,     // tf_r9
+    pub rax: usize,    // tf_rax
+    pub rbx: usize,    // tf_rbx
+    pub rbp: usize,    // tf_rbp
+    pub r10: usize,    // tf_r10
+    pub r11: usize,    // tf_r11
+    pub r12: usize,    // tf_r12
+    pub r13: usize,    // tf_r13
+    pub r14: usize,    // tf_r14
+    pub r15: usize,    // tf_r15
+    pub num: TrapNo,   // tf_trapno
+    pub fs: u16,       // tf_fs
+    pub gs: u16,       // tf_gs
+    pub addr: usize,   // tf_addr
+    pub flags: u32,    // tf_flags
+    pub es: u16,       // tf_es
+    pub ds: u16,       // tf_ds
+    pub err: usize,    // tf_err
+    pub rip: usize,    // tf_rip
+    pub cs: usize,     // tf_cs
+    pub rflags: usize, // tf_rflags
+    pub rsp: usize,    // tf_rsp
+    pub ss: usize,     // tf_ss
 }
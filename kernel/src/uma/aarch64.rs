@@ -1,6 +1,24 @@
 use super::Alloc;
-use crate::vm::Vm;
+use crate::config::PAGE_SIZE;
+use crate::vm::{Vm, VmAlloc};
+use core::ptr::null_mut;
 
-pub fn small_alloc(vm: &Vm, flags: Alloc) {
-    todo!()
+pub fn small_alloc(vm: &Vm, _: usize, flags: Alloc) -> *mut u8 {
+    let req = match flags.has_any(Alloc::NoWait) {
+        true => VmAlloc::Interrupt,
+        false => VmAlloc::System,
+    };
+
+    let page = match vm.alloc_page(None, req.into()) {
+        Some(v) => v,
+        None => return null_mut(),
+    };
+
+    let mem = vm.phys_to_dmap(page.addr());
+
+    if flags.has_any(Alloc::Zero) {
+        unsafe { mem.write_bytes(0, PAGE_SIZE.get()) };
+    }
+
+    mem
 }
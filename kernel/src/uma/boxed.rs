@@ -1,13 +1,43 @@
-use core::ops::Deref;
+use super::UmaZone;
+use core::ops::{Deref, DerefMut};
 
 /// Encapsulates an object allocated from a UMA zone.
-pub struct UmaBox<T: ?Sized>(*mut T);
+///
+/// The object will be freed with [`UmaZone::free_item()`] when dropped, which bypass the per-CPU
+/// caches. That means this type should be used only for the object allocated with
+/// [`UmaZone::alloc_item()`].
+pub struct UmaBox<T: ?Sized> {
+    ptr: *mut T,
+    zone: *const UmaZone,
+}
+
+impl<T: ?Sized> UmaBox<T> {
+    /// # Safety
+    /// `ptr` must be a valid object allocated from `zone` and `zone` must outlive the returned
+    /// [`UmaBox`].
+    pub unsafe fn new(zone: &UmaZone, ptr: *mut T) -> Self {
+        Self { ptr, zone }
+    }
+}
+
+impl<T: ?Sized> Drop for UmaBox<T> {
+    fn drop(&mut self) {
+        unsafe { self.ptr.drop_in_place() };
+        unsafe { (*self.zone).free_item(self.ptr.cast()) };
+    }
+}
 
 impl<T: ?Sized> Deref for UmaBox<T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
-        unsafe { &*self.0 }
+        unsafe { &*self.ptr }
+    }
+}
+
+impl<T: ?Sized> DerefMut for UmaBox<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.ptr }
     }
 }
 
@@ -11,5 +11,43 @@ impl<I: ?Sized> UmaBucket<I> {
     }
 }
 
+impl UmaBucket<[BucketItem]> {
+    /// Construct an empty bucket at `mem` that can hold `entries` items.
+    ///
+    /// # Safety
+    /// `mem` must be valid for a bucket with `entries` items.
+    pub unsafe fn init(mem: *mut u8, entries: usize) -> *mut Self {
+        let b = core::ptr::slice_from_raw_parts_mut(mem, entries) as *mut Self;
+
+        unsafe { (&raw mut (*b).len).write(0) };
+
+        b
+    }
+
+    /// Returns the maximum number of items this bucket can hold.
+    pub fn entries(&self) -> usize {
+        self.items.len() // ub_entries
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == self.entries()
+    }
+
+    /// The bucket must not full.
+    pub fn push(&mut self, item: *mut u8) {
+        self.items[self.len] = BucketItem(item);
+        self.len += 1;
+    }
+
+    pub fn pop(&mut self) -> Option<*mut u8> {
+        self.len = self.len.checked_sub(1)?;
+
+        Some(self.items[self.len].0)
+    }
+}
+
 /// Each item in the [`UmaBucket::items`].
-pub struct BucketItem {}
+#[repr(transparent)]
+pub struct BucketItem(*mut u8);
+
+unsafe impl Send for BucketItem {}
@@ -1,24 +1,32 @@
 use super::arch::small_alloc;
-use super::slab::{Free, RcFree, Slab};
-use super::{Alloc, Uma, UmaFlags, UmaZone};
+use super::slab::{Free, RcFree, Slab, SlabList};
+use super::{Alloc, Uma, UmaFlags};
 use crate::config::{PAGE_MASK, PAGE_SHIFT, PAGE_SIZE};
-use crate::vm::Vm;
-use alloc::sync::Arc;
+use crate::vm::{Vm, VmAlloc};
+use alloc::vec;
+use alloc::vec::Vec;
 use core::alloc::Layout;
 use core::cmp::{max, min};
 use core::num::NonZero;
+use core::ptr::null_mut;
 
 /// Implementation of `uma_keg` structure.
 pub struct UmaKeg {
-    vm: Arc<Vm>,
-    size: NonZero<usize>,  // uk_size
-    ipers: usize,          // uk_ipers
-    alloc: fn(&Vm, Alloc), // uk_allocf
-    max_pages: u32,        // uk_maxpages
-    pages: u32,            // uk_pages
-    free: u32,             // uk_free
-    recurse: u32,          // uk_recurse
-    flags: UmaFlags,       // uk_flags
+    size: NonZero<usize>,                    // uk_size
+    rsize: usize,                            // uk_rsize
+    ppera: usize,                            // uk_ppera
+    ipers: usize,                            // uk_ipers
+    pgoff: usize,                            // uk_pgoff
+    alloc: fn(&Vm, usize, Alloc) -> *mut u8, // uk_allocf
+    hash: Vec<*mut Slab<[Free]>>,            // uk_hash
+    part_slabs: SlabList,                    // uk_part_slab
+    free_slabs: SlabList,                    // uk_free_slab
+    full_slabs: SlabList,                    // uk_full_slab
+    max_pages: u32,                          // uk_maxpages
+    pages: u32,                              // uk_pages
+    free: u32,                               // uk_free
+    recurse: u32,                            // uk_recurse
+    flags: UmaFlags,                         // uk_flags
 }
 
 impl UmaKeg {
@@ -31,18 +39,10 @@ impl UmaKeg {
     /// | Version | Offset |
     /// |---------|--------|
     /// |PS4 11.00|0x13CF40|
-    pub(super) fn new(
-        vm: Arc<Vm>,
-        size: NonZero<usize>,
-        align: usize,
-        mut flags: UmaFlags,
-    ) -> Self {
+    pub(super) fn new(size: NonZero<usize>, align: usize, mut flags: UmaFlags) -> Self {
+        // Allocating the buckets for the zones that used by the VM must not recurse into the VM.
         if flags.has_any(UmaFlags::Vm) {
-            todo!()
-        }
-
-        if flags.has_any(UmaFlags::ZInit) {
-            todo!()
+            flags |= UmaFlags::CacheOnly;
         }
 
         if flags.has_any(UmaFlags::Malloc | UmaFlags::RefCnt) {
@@ -63,8 +63,11 @@ impl UmaKeg {
         let free_item = hdr.size() - off;
         let available = PAGE_SIZE.get() - hdr.size();
 
-        // Get uk_ppera and uk_ipers.
-        let (ppera, ipers) = if flags.has_any(UmaFlags::CacheSpread) {
+        // Get uk_ppera, uk_ipers and uk_rsize.
+        let (ppera, ipers, rsize) = if flags.has_any(UmaFlags::CacheSpread) {
+            // The items will span across multiple pages so the header can't be on the page.
+            flags |= UmaFlags::Offpage | UmaFlags::VToSlab;
+
             // Round size.
             let rsize = if (size.get() & align) == 0 {
                 size.get()
@@ -88,11 +91,10 @@ impl UmaKeg {
             // Get uk_ipers.
             let ipers = (ppera * PAGE_SIZE.get() + (rsize - size.get())) / rsize;
 
-            (ppera, ipers)
+            (ppera, ipers, rsize)
         } else {
             // TODO: Not sure why we need space at least for 2 free item?
             if (size.get() + free_item) > available {
-                // TODO: Set uk_ppera and uk_rsize.
                 if !flags.has_any(UmaFlags::Internal) {
                     flags |= UmaFlags::Offpage;
 
@@ -108,7 +110,7 @@ impl UmaKeg {
                     ppera += 1;
                 }
 
-                (ppera, 1)
+                (ppera, 1, size.get())
             } else {
                 // Get uk_rsize.
                 let rsize = max(size, Uma::SMALLEST_UNIT);
@@ -120,17 +122,24 @@ impl UmaKeg {
                 };
 
                 // Get uk_ipers.
-                let ipers = available / (rsize + free_item);
+                let mut ipers = available / (rsize + free_item);
 
+                // Move the slab header out of the page if it can reduce the wasted space.
+                //
                 // TODO: Verify if this valid for PAGE_SIZE < 0x4000.
                 if !flags.has_any(UmaFlags::Internal | UmaFlags::CacheOnly)
                     && (available % (rsize + free_item)) >= Uma::MAX_WASTE.get()
                     && (PAGE_SIZE.get() / rsize) > ipers
                 {
-                    todo!()
+                    ipers = PAGE_SIZE.get() / rsize;
+                    flags |= UmaFlags::Offpage;
+
+                    if !flags.has_any(UmaFlags::VToSlab) {
+                        flags |= UmaFlags::Hash;
+                    }
                 }
 
-                (1, ipers)
+                (1, ipers, rsize)
             }
         };
 
@@ -150,11 +159,10 @@ impl UmaKeg {
             Self::page_alloc
         };
 
-        if flags.has_any(UmaFlags::MtxClass) {
-            todo!()
-        }
+        // UMA_ZONE_MTXCLASS only give uk_lock a different name for WITNESS, which we don't have.
 
-        if !flags.has_any(UmaFlags::Offpage) {
+        // Get uk_pgoff.
+        let pgoff = if !flags.has_any(UmaFlags::Offpage) {
             let space = ppera * PAGE_SIZE.get();
             let pgoff = (space - hdr.size()) - ipers * free_item;
 
@@ -162,19 +170,36 @@ impl UmaKeg {
             if space < pgoff + hdr.size() + ipers * free_item {
                 panic!("UMA slab won't fit");
             }
-        }
 
-        if flags.has_any(UmaFlags::Hash) {
-            todo!()
-        }
+            pgoff
+        } else {
+            0
+        };
+
+        // Our us_freelist can hold only 256 items.
+        assert!(ipers <= 256);
+
+        // We also use uk_hash to lookup the slab for UMA_ZONE_VTOSLAB since we don't have
+        // vm_page_array.
+        let hash = if flags.has_any(UmaFlags::Offpage | UmaFlags::Hash) {
+            vec![Slab::null(); UMA_HASH_SIZE_INIT]
+        } else {
+            Vec::new()
+        };
 
         // TODO: Add uk_zones.
         // TODO: Add uma_kegs.
         Self {
-            vm,
             size,
+            rsize,
+            ppera,
             ipers,
+            pgoff,
             alloc,
+            hash,
+            part_slabs: SlabList::new(),
+            free_slabs: SlabList::new(),
+            full_slabs: SlabList::new(),
             max_pages: 0,
             pages: 0,
             free: 0,
@@ -191,6 +216,16 @@ impl UmaKeg {
         self.ipers
     }
 
+    /// Returns the number of pages allocated for the slabs.
+    pub fn pages(&self) -> u32 {
+        self.pages
+    }
+
+    /// Returns the number of free items in the slabs.
+    pub fn free(&self) -> u32 {
+        self.free
+    }
+
     pub fn recurse(&self) -> u32 {
         self.recurse
     }
@@ -199,31 +234,125 @@ impl UmaKeg {
         self.flags
     }
 
+    /// Returns `true` if this keg has reached the limit from [`Self::set_max_items()`].
+    pub fn is_full(&self) -> bool {
+        self.max_pages != 0 && self.max_pages <= self.pages
+    }
+
+    /// Limit the number of pages for the slabs to hold at least `items` items. Returns the actual
+    /// number of items that can be allocated.
+    pub fn set_max_items(&mut self, items: usize) -> usize {
+        let slabs = items.div_ceil(self.ipers);
+
+        self.max_pages = (slabs * self.ppera).try_into().unwrap();
+
+        slabs * self.ipers
+    }
+
+    /// Returns a slab that has some free items, which will be on the partial list.
+    ///
     /// See `keg_fetch_slab` on the Orbis for a reference.
     ///
     /// # Reference offsets
     /// | Version | Offset |
     /// |---------|--------|
     /// |PS4 11.00|0x141E20|
-    pub fn fetch_slab(&mut self, _: &UmaZone, flags: Alloc) -> Option<()> {
-        while self.free == 0 {
+    pub fn fetch_slab(&mut self, vm: &Vm, mut flags: Alloc) -> Option<*mut Slab<[Free]>> {
+        loop {
+            if let Some(v) = self.available_slab() {
+                return Some(v);
+            }
+
+            // M_NOVM means don't ask at all!
             if flags.has_any(Alloc::NoVm) {
                 return None;
             }
 
-            #[allow(clippy::while_immutable_condition)] // TODO: Remove this.
-            while self.max_pages != 0 && self.max_pages <= self.pages {
-                todo!()
+            // We can't sleep here since the keg is locked so the zone will wait for us.
+            if self.is_full() {
+                return None;
             }
 
             self.recurse += 1;
-            self.alloc_slab(flags);
+            let ok = self.alloc_slab(vm, flags);
             self.recurse -= 1;
 
-            todo!()
+            if !ok {
+                flags |= Alloc::NoVm;
+            }
+        }
+    }
+
+    /// Remove a free item from `slab` that was returned from [`Self::fetch_slab()`].
+    ///
+    /// See `slab_alloc_item` on the Orbis for a reference.
+    ///
+    /// # Safety
+    /// `slab` must be on the partial list of this keg.
+    pub unsafe fn alloc_item(&mut self, slab: *mut Slab<[Free]>) -> *mut u8 {
+        let s = unsafe { &mut *slab };
+        let i = s.alloc();
+
+        self.free -= 1;
+
+        // Move this slab to the full list.
+        if s.free_count() == 0 {
+            unsafe { self.part_slabs.remove(slab) };
+            unsafe { self.full_slabs.insert(slab) };
+        }
+
+        unsafe { s.data().add(i * self.rsize) }
+    }
+
+    /// Put `item` back to its slab.
+    ///
+    /// See `zone_free_item` on the Orbis for a reference.
+    ///
+    /// # Safety
+    /// `item` must be allocated from this keg and it must not be freed yet.
+    pub unsafe fn free_item(&mut self, item: *mut u8) {
+        let slab = unsafe { self.slab_of(item) };
+        let s = unsafe { &mut *slab };
+        let full = s.free_count() == 0;
+
+        s.free((item as usize - s.data() as usize) / self.rsize);
+
+        self.free += 1;
+
+        // Do we need to move to the other list?
+        let free = s.free_count() == self.ipers;
+
+        if full {
+            unsafe { self.full_slabs.remove(slab) };
+        } else if free {
+            unsafe { self.part_slabs.remove(slab) };
+        } else {
+            return;
+        }
+
+        match free {
+            true => unsafe { self.free_slabs.insert(slab) },
+            false => unsafe { self.part_slabs.insert(slab) },
+        }
+    }
+
+    /// Returns a slab from the partial list or move the one from the free list.
+    fn available_slab(&mut self) -> Option<*mut Slab<[Free]>> {
+        if self.free == 0 {
+            return None;
+        }
+
+        // Prefer slabs that are partially used over those that are totally free.
+        if let Some(v) = self.part_slabs.first() {
+            return Some(v);
         }
 
-        todo!()
+        let slab = self.free_slabs.first().unwrap();
+
+        unsafe { self.free_slabs.remove(slab) };
+        unsafe { self.part_slabs.insert(slab) };
+
+        Some(slab)
     }
 
     /// See `keg_alloc_slab` on the Orbis for a reference.
@@ -232,19 +361,122 @@ impl UmaKeg {
     /// | Version | Offset |
     /// |---------|--------|
     /// |PS4 11.00|0x13FBA0|
-    fn alloc_slab(&self, flags: Alloc) {
-        if self.flags.has_any(UmaFlags::Offpage) {
-            todo!()
+    fn alloc_slab(&mut self, vm: &Vm, flags: Alloc) -> bool {
+        // TODO: Allocate the header from uk_slabzone.
+        let hdr = if self.flags.has_any(UmaFlags::Offpage) {
+            let hdr = unsafe { alloc::alloc::alloc(self.offpage_layout()) };
+
+            if hdr.is_null() {
+                return false;
+            }
+
+            hdr
         } else {
-            let flags = if self.flags.has_any(UmaFlags::Malloc) {
-                flags & !Alloc::Zero
-            } else {
-                flags | Alloc::Zero
-            };
+            null_mut()
+        };
+
+        // Allocate pages. The items of UMA_ZONE_ZINIT must be zeroed when the slab is created.
+        let flags = if self.flags.has_any(UmaFlags::Malloc) && !self.flags.has_any(UmaFlags::ZInit)
+        {
+            flags & !Alloc::Zero
+        } else {
+            flags | Alloc::Zero
+        };
+
+        let mem = (self.alloc)(vm, self.ppera, flags);
+
+        if mem.is_null() {
+            if !hdr.is_null() {
+                unsafe { alloc::alloc::dealloc(hdr, self.offpage_layout()) };
+            }
+
+            return false;
+        }
+
+        unsafe { self.init_slab(mem, hdr) };
 
-            (self.alloc)(&self.vm, flags);
-            todo!()
+        true
+    }
+
+    /// Setup a slab for `mem` and put it on the free list. `hdr` is a memory for the slab header
+    /// if [`UmaFlags::Offpage`] is set.
+    ///
+    /// # Safety
+    /// `mem` must be valid for `uk_ppera` pages and aligned to page size. `hdr` must be valid for
+    /// [`Self::offpage_layout()`] if [`UmaFlags::Offpage`] is set.
+    unsafe fn init_slab(&mut self, mem: *mut u8, hdr: *mut u8) {
+        let hdr = match self.flags.has_any(UmaFlags::Offpage) {
+            true => hdr,
+            false => unsafe { mem.add(self.pgoff) },
+        };
+
+        let slab = unsafe { Slab::init(hdr, mem, self.ipers) };
+
+        if !self.hash.is_empty() {
+            let h = &mut self.hash[Self::hash_index(mem)];
+
+            unsafe { (*slab).set_hash(*h) };
+
+            *h = slab;
+        }
+
+        unsafe { self.free_slabs.insert(slab) };
+
+        self.pages += u32::try_from(self.ppera).unwrap();
+        self.free += u32::try_from(self.ipers).unwrap();
+    }
+
+    /// Returns the slab that contains `item`.
+    ///
+    /// # Safety
+    /// `item` must be allocated from this keg.
+    unsafe fn slab_of(&self, item: *mut u8) -> *mut Slab<[Free]> {
+        let mem = item.map_addr(|v| v & !PAGE_MASK.get());
+
+        if self.hash.is_empty() {
+            let hdr = unsafe { mem.add(self.pgoff) };
+
+            return core::ptr::slice_from_raw_parts_mut(hdr, self.ipers) as *mut Slab<[Free]>;
+        }
+
+        // The slab for UMA_ZONE_CACHESPREAD may have the items on the other pages so we need to
+        // walk back to the first page of the slab.
+        let pages = if self.ipers > 1 { self.ppera } else { 1 };
+
+        (0..pages)
+            .find_map(|i| self.find_slab(mem.wrapping_sub(i * PAGE_SIZE.get()), item))
+            .expect("the item does not belong to this keg")
+    }
+
+    /// Lookup the slab for `mem` from uk_hash that contains `item`.
+    fn find_slab(&self, mem: *mut u8, item: *mut u8) -> Option<*mut Slab<[Free]>> {
+        let mut slab = self.hash[Self::hash_index(mem)];
+
+        while let Some(s) = unsafe { slab.as_ref() } {
+            if s.data() == mem && (item as usize) < mem as usize + self.ppera * PAGE_SIZE.get() {
+                return Some(slab);
+            }
+
+            slab = s.hash();
         }
+
+        None
+    }
+
+    /// Returns a layout of the slab header for [`UmaFlags::Offpage`].
+    fn offpage_layout(&self) -> Layout {
+        let free = Layout::array::<Free>(self.ipers).unwrap();
+
+        Layout::new::<Slab<()>>()
+            .extend(free)
+            .unwrap()
+            .0
+            .pad_to_align()
+    }
+
+    /// See `UMA_HASH` on the Orbis for a reference.
+    fn hash_index(mem: *mut u8) -> usize {
+        (mem as usize >> PAGE_SHIFT) & (UMA_HASH_SIZE_INIT - 1)
     }
 
     /// See `page_alloc` on the Orbis for a reference.
@@ -253,7 +485,158 @@ impl UmaKeg {
     /// | Version | Offset |
     /// |---------|--------|
     /// |PS4 11.00|0x1402F0|
-    fn page_alloc(_: &Vm, _: Alloc) {
-        todo!()
+    fn page_alloc(vm: &Vm, pages: usize, flags: Alloc) -> *mut u8 {
+        // We don't have kmem_map so we use contiguous pages on the direct map instead.
+        let req = match flags.has_any(Alloc::NoWait) {
+            true => VmAlloc::Interrupt,
+            false => VmAlloc::System,
+        };
+
+        let page = match vm.alloc_contig(NonZero::new(pages).unwrap(), req.into()) {
+            Some(v) => v,
+            None => return null_mut(),
+        };
+
+        let mem = vm.phys_to_dmap(page.addr());
+
+        if flags.has_any(Alloc::Zero) {
+            unsafe { mem.write_bytes(0, pages * PAGE_SIZE.get()) };
+        }
+
+        mem
+    }
+}
+
+unsafe impl Send for UmaKeg {}
+
+/// `UMA_HASH_SIZE_INIT`.
+const UMA_HASH_SIZE_INIT: usize = 32;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::alloc::{alloc, dealloc};
+
+    #[test]
+    fn alloc_free() {
+        let sizes = [16, 24, 64, 100, 256, 1000, 1024, 2048, PAGE_SIZE.get()];
+
+        for size in sizes {
+            for align in [7, 15, 63] {
+                let size = NonZero::new(size).unwrap();
+                let mut keg = UmaKeg::new(size, align, UmaFlags::Malloc.into());
+                let page = Layout::from_size_align(PAGE_SIZE.get(), PAGE_SIZE.get()).unwrap();
+                let mut pages = Vec::new();
+                let mut items = Vec::new();
+
+                // Allocate items across multiple slabs.
+                while items.len() < keg.ipers * 3 {
+                    let slab = match keg.available_slab() {
+                        Some(v) => v,
+                        None => {
+                            let mem = unsafe { alloc(page) };
+                            let hdr = match keg.flags.has_any(UmaFlags::Offpage) {
+                                true => unsafe { alloc(keg.offpage_layout()) },
+                                false => null_mut(),
+                            };
+
+                            unsafe { keg.init_slab(mem, hdr) };
+                            pages.push((mem, hdr));
+                            continue;
+                        }
+                    };
+
+                    let item = unsafe { keg.alloc_item(slab) };
+
+                    assert_eq!(item as usize & align, 0);
+                    assert!(!items.contains(&item));
+
+                    unsafe { item.write_bytes(items.len() as u8, size.get()) };
+                    items.push(item);
+                }
+
+                assert_eq!(keg.free, 0);
+
+                // Check if the items are overlapped then free it in a different order.
+                for (i, &item) in items.iter().enumerate() {
+                    let data = unsafe { core::slice::from_raw_parts(item, size.get()) };
+
+                    assert!(data.iter().all(|&b| b == i as u8));
+                }
+
+                for i in (0..items.len())
+                    .step_by(2)
+                    .chain((1..items.len()).step_by(2))
+                {
+                    unsafe { keg.free_item(items[i]) };
+                }
+
+                assert_eq!(keg.free as usize, keg.ipers * pages.len());
+                assert!(keg.part_slabs.first().is_none());
+                assert!(keg.full_slabs.first().is_none());
+
+                // Allocate again without new pages.
+                for _ in 0..items.len() {
+                    let slab = keg.available_slab().unwrap();
+
+                    unsafe { keg.alloc_item(slab) };
+                }
+
+                for (mem, hdr) in pages {
+                    unsafe { dealloc(mem, page) };
+
+                    if !hdr.is_null() {
+                        unsafe { dealloc(hdr, keg.offpage_layout()) };
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn cache_spread() {
+        let size = NonZero::new(128).unwrap();
+        let mut keg = UmaKeg::new(size, 63, UmaFlags::CacheSpread.into());
+
+        assert!(keg.ppera > 1);
+        assert!(keg.flags.has_any(UmaFlags::Offpage));
+
+        // Setup a slab.
+        let layout = Layout::from_size_align(keg.ppera * PAGE_SIZE.get(), PAGE_SIZE.get()).unwrap();
+        let mem = unsafe { alloc(layout) };
+        let hdr = unsafe { alloc(keg.offpage_layout()) };
+
+        unsafe { keg.init_slab(mem, hdr) };
+
+        // Allocate all items, which will be on the other pages.
+        let slab = keg.available_slab().unwrap();
+        let mut items = Vec::new();
+
+        for _ in 0..keg.ipers {
+            let item = unsafe { keg.alloc_item(slab) };
+
+            assert!(item >= mem && item < mem.wrapping_add(layout.size()));
+
+            items.push(item);
+        }
+
+        assert!(keg.available_slab().is_none());
+        assert!(
+            items
+                .iter()
+                .any(|&v| v as usize - mem as usize >= PAGE_SIZE.get())
+        );
+
+        // Free all items.
+        for item in items.into_iter().rev() {
+            assert_eq!(unsafe { keg.slab_of(item) }, slab);
+            unsafe { keg.free_item(item) };
+        }
+
+        assert_eq!(keg.free as usize, keg.ipers);
+        assert!(keg.part_slabs.first().is_none());
+
+        unsafe { dealloc(mem, layout) };
+        unsafe { dealloc(hdr, keg.offpage_layout()) };
     }
 }
@@ -3,13 +3,14 @@ pub use self::zone::*;
 
 use self::bucket::{BucketItem, UmaBucket};
 use crate::config::PAGE_SIZE;
-use crate::vm::Vm;
+use crate::vm::{Vm, VmAlloc};
 use alloc::format;
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::alloc::Layout;
 use core::num::NonZero;
+use core::ptr::null_mut;
 use core::sync::atomic::AtomicBool;
 use macros::bitflag;
 
@@ -70,6 +71,7 @@ impl Uma {
                 Arc::default(),
                 format!("{size} Bucket"),
                 None,
+                None,
                 layout.size().try_into().unwrap(),
                 Some(layout.align() - 1),
                 UmaFlags::Bucket | UmaFlags::Internal,
@@ -89,6 +91,15 @@ impl Uma {
         })
     }
 
+    pub fn vm(&self) -> &Arc<Vm> {
+        &self.vm
+    }
+
+    /// Returns the zones to allocate the buckets for the per-CPU caches.
+    pub fn bucket_zones(&self) -> &[UmaZone] {
+        &self.bucket_zones
+    }
+
     /// See `uma_zcreate` on the Orbis for a reference.
     ///
     /// # Reference offsets
@@ -111,11 +122,72 @@ impl Uma {
             self.bucket_zones.clone(),
             name,
             None,
+            None,
             size,
             align,
             flags,
         )
     }
+
+    /// Create a zone that share the keg with `master`.
+    ///
+    /// See `uma_zsecond_create` on the Orbis for a reference.
+    #[allow(dead_code)] // TODO: Remove this once someone use it.
+    pub fn create_secondary_zone(&self, name: impl Into<String>, master: &UmaZone) -> UmaZone {
+        UmaZone::new(
+            self.vm.clone(),
+            self.bucket_enable.clone(),
+            self.bucket_keys.clone(),
+            self.bucket_zones.clone(),
+            name,
+            None,
+            Some(master),
+            master.size(),
+            None,
+            UmaFlags::Secondary,
+        )
+    }
+
+    /// Allocate contiguous pages that large enough for `size` bytes. Returns null on failure.
+    ///
+    /// See `uma_large_malloc` on the Orbis for a reference.
+    pub fn large_alloc(&self, size: NonZero<usize>, flags: Alloc) -> *mut u8 {
+        // We don't have kmem_map so we use contiguous pages on the direct map instead, which
+        // naturally aligned to its size.
+        let pages = size.get().div_ceil(PAGE_SIZE.get());
+        let req = match flags.has_any(Alloc::NoWait) {
+            true => VmAlloc::Interrupt,
+            false => VmAlloc::System,
+        };
+
+        let page = match self
+            .vm
+            .alloc_contig(NonZero::new(pages).unwrap(), req.into())
+        {
+            Some(v) => v,
+            None => return null_mut(),
+        };
+
+        let mem = self.vm.phys_to_dmap(page.addr());
+
+        if flags.has_any(Alloc::Zero) {
+            unsafe { mem.write_bytes(0, PAGE_SIZE.get() << page.order()) };
+        }
+
+        mem
+    }
+
+    /// See `uma_large_free` on the Orbis for a reference.
+    ///
+    /// # Safety
+    /// `mem` must be allocated with [`Self::large_alloc()`] with the same `size`.
+    pub unsafe fn large_free(&self, mem: *mut u8, size: NonZero<usize>) {
+        let pages = size.get().div_ceil(PAGE_SIZE.get());
+        let order = pages.next_power_of_two().trailing_zeros();
+
+        self.vm
+            .free_contig(self.vm.dmap_to_phys(mem), order.try_into().unwrap());
+    }
 }
 
 /// Flags for [`Uma::create_zone()`].
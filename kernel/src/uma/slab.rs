@@ -3,13 +3,145 @@
 /// We use slightly different mechanism here but has the same memory layout.
 #[repr(C)]
 pub struct Slab<I: ?Sized> {
-    free: I, // us_freelist
+    next: *mut Slab<[Free]>, // us_link
+    prev: *mut Slab<[Free]>, // us_link
+    hash: *mut Slab<[Free]>, // us_hlink
+    data: *mut u8,           // us_data
+    free_count: usize,       // us_freecount
+    first_free: usize,       // us_firstfree
+    free: I,                 // us_freelist
+}
+
+impl Slab<[Free]> {
+    /// Returns a null pointer to [`Slab`].
+    pub const fn null() -> *mut Self {
+        core::ptr::slice_from_raw_parts_mut(core::ptr::null_mut::<u8>(), 0) as *mut Self
+    }
+
+    /// Construct a slab header at `hdr` for `ipers` items at `data`.
+    ///
+    /// # Safety
+    /// `hdr` must be valid for a slab header with `ipers` items and it must not overlap with the
+    /// items.
+    pub unsafe fn init(hdr: *mut u8, data: *mut u8, ipers: usize) -> *mut Self {
+        let slab = core::ptr::slice_from_raw_parts_mut(hdr, ipers) as *mut Self;
+
+        unsafe {
+            (&raw mut (*slab).next).write(Self::null());
+            (&raw mut (*slab).prev).write(Self::null());
+            (&raw mut (*slab).hash).write(Self::null());
+            (&raw mut (*slab).data).write(data);
+            (&raw mut (*slab).free_count).write(ipers);
+            (&raw mut (*slab).first_free).write(0);
+
+            for i in 0..ipers {
+                (&raw mut (*slab).free[i]).write(Free {
+                    item: (i + 1) as u8,
+                });
+            }
+        }
+
+        slab
+    }
+
+    pub fn data(&self) -> *mut u8 {
+        self.data
+    }
+
+    pub fn free_count(&self) -> usize {
+        self.free_count
+    }
+
+    /// Returns the next slab on the same hash chain.
+    pub fn hash(&self) -> *mut Self {
+        self.hash
+    }
+
+    pub fn set_hash(&mut self, v: *mut Self) {
+        self.hash = v;
+    }
+
+    /// Remove the first free item and return its index. The slab must have some free items.
+    pub fn alloc(&mut self) -> usize {
+        let i = self.first_free;
+
+        self.first_free = self.free[i].item.into();
+        self.free_count -= 1;
+
+        i
+    }
+
+    /// Put the item at index `i` back to the free list.
+    pub fn free(&mut self, i: usize) {
+        self.free[i].item = self.first_free as u8;
+        self.first_free = i;
+        self.free_count += 1;
+    }
 }
 
 /// Item in the slab to represents `uma_slab` structure.
 #[repr(C)]
-pub struct Free {}
+pub struct Free {
+    item: u8, // us_item
+}
 
 /// Item in the slab to represents `uma_slab_refcnt` structure.
 #[repr(C)]
 pub struct RcFree {}
+
+/// Implementation of `slabhead`.
+///
+/// The slabs are linked through [`Slab`] so adding or removing a slab never allocate any memory.
+pub struct SlabList {
+    head: *mut Slab<[Free]>, // lh_first
+}
+
+impl SlabList {
+    pub const fn new() -> Self {
+        Self { head: Slab::null() }
+    }
+
+    pub fn first(&self) -> Option<*mut Slab<[Free]>> {
+        match self.head.is_null() {
+            true => None,
+            false => Some(self.head),
+        }
+    }
+
+    /// See `LIST_INSERT_HEAD` on the Orbis for a reference.
+    ///
+    /// # Safety
+    /// `slab` must be valid and it must not on any list.
+    pub unsafe fn insert(&mut self, slab: *mut Slab<[Free]>) {
+        unsafe {
+            (*slab).next = self.head;
+            (*slab).prev = Slab::null();
+
+            if let Some(h) = self.head.as_mut() {
+                h.prev = slab;
+            }
+        }
+
+        self.head = slab;
+    }
+
+    /// See `LIST_REMOVE` on the Orbis for a reference.
+    ///
+    /// # Safety
+    /// `slab` must be on this list.
+    pub unsafe fn remove(&mut self, slab: *mut Slab<[Free]>) {
+        let s = unsafe { &mut *slab };
+
+        match unsafe { s.prev.as_mut() } {
+            Some(p) => p.next = s.next,
+            None => self.head = s.next,
+        }
+
+        if let Some(n) = unsafe { s.next.as_mut() } {
+            n.prev = s.prev;
+        }
+
+        s.next = Slab::null();
+        s.prev = Slab::null();
+    }
+}
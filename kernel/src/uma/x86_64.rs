@@ -1,5 +1,7 @@
 use super::Alloc;
+use crate::config::PAGE_SIZE;
 use crate::vm::Vm;
+use core::ptr::null_mut;
 
 /// See `uma_small_alloc` on the Orbis for a reference.
 ///
@@ -7,15 +9,27 @@ use crate::vm::Vm;
 /// | Version | Offset |
 /// |---------|--------|
 /// |PS4 11.00|0x22FD70|
-pub fn small_alloc(vm: &Vm, flags: Alloc) {
+pub fn small_alloc(vm: &Vm, _: usize, flags: Alloc) -> *mut u8 {
     // TODO: There are an increment on an unknown variable on the Orbis.
-    vm.alloc_page(
+    let page = match vm.alloc_page(
         None,
         // TODO: Refactor this for readability.
-        ((((u32::from(flags) & 0x100) >> 2) - (u32::from((u32::from(flags) & 0x401) == 1)) + 0x22)
+        (((u32::from(flags) & 0x100) >> 2)
+            .wrapping_sub(u32::from((u32::from(flags) & 0x401) == 1))
+            .wrapping_add(0x22)
             | 0x100)
             .into(),
-    );
+    ) {
+        Some(v) => v,
+        None => return null_mut(),
+    };
 
-    todo!()
+    // TODO: Skip zeroing if the page already zeroed.
+    let mem = vm.phys_to_dmap(page.addr());
+
+    if flags.has_any(Alloc::Zero) {
+        unsafe { mem.write_bytes(0, PAGE_SIZE.get()) };
+    }
+
+    mem
 }
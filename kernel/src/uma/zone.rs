@@ -1,9 +1,12 @@
 use super::bucket::{BucketItem, UmaBucket};
 use super::keg::UmaKeg;
+use super::slab::{Free, Slab};
 use super::{Alloc, Uma, UmaBox, UmaFlags};
 use crate::context::{CpuLocal, current_thread};
 use crate::lock::{Gutex, GutexGroup, GutexWrite};
+use crate::sched::{sleep_until, wakeup};
 use crate::vm::Vm;
+use crate::vmstat::vmstat_poll;
 use alloc::collections::VecDeque;
 use alloc::collections::linked_list::LinkedList;
 use alloc::string::String;
@@ -17,21 +20,30 @@ use core::ptr::null_mut;
 use core::sync::atomic::{AtomicBool, Ordering};
 
 /// Implementation of `uma_zone` structure.
+///
+/// The buckets are allocated from [`Uma`] bucket zones so `bucket_zones` must be the last field to
+/// make sure it outlive the buckets owned by this zone.
+#[allow(clippy::type_complexity)]
 pub struct UmaZone {
+    vm: Arc<Vm>,
     bucket_enable: Arc<AtomicBool>,
     bucket_keys: Arc<Vec<usize>>,
-    bucket_zones: Arc<Vec<UmaZone>>,
+    name: String, // uz_name
     ty: ZoneType,
-    size: NonZero<usize>,                                           // uz_size
-    kegs: Gutex<LinkedList<UmaKeg>>,                                // uz_kegs + uz_klink
-    slab: fn(&Self, Option<&mut UmaKeg>, Alloc) -> Option<()>,      // uz_slab
-    caches: CpuLocal<RefCell<UmaCache>>,                            // uz_cpu
-    full_buckets: Gutex<VecDeque<UmaBox<UmaBucket<[BucketItem]>>>>, // uz_full_bucket
-    free_buckets: Gutex<VecDeque<UmaBox<UmaBucket<[BucketItem]>>>>, // uz_free_bucket
-    alloc_count: Gutex<u64>,                                        // uz_allocs
-    free_count: Gutex<u64>,                                         // uz_frees
-    count: Gutex<usize>,                                            // uz_count
-    flags: UmaFlags,                                                // uz_flags
+    size: NonZero<usize>,                                             // uz_size
+    kegs: Arc<Gutex<LinkedList<UmaKeg>>>,                             // uz_kegs + uz_klink
+    keg_full: Arc<AtomicBool>,                                        // UMA_ZFLAG_FULL
+    slab: fn(&Self, &mut UmaKeg, Alloc) -> Option<*mut Slab<[Free]>>, // uz_slab
+    caches: CpuLocal<RefCell<UmaCache>>,                              // uz_cpu
+    full_buckets: Gutex<VecDeque<UmaBox<UmaBucket<[BucketItem]>>>>,   // uz_full_bucket
+    free_buckets: Gutex<VecDeque<UmaBox<UmaBucket<[BucketItem]>>>>,   // uz_free_bucket
+    alloc_count: Gutex<u64>,                                          // uz_allocs
+    free_count: Gutex<u64>,                                           // uz_frees
+    cache_hits: Gutex<u64>,
+    cache_misses: Gutex<u64>,
+    count: Gutex<usize>, // uz_count
+    flags: UmaFlags,     // uz_flags
+    bucket_zones: Arc<Vec<UmaZone>>,
 }
 
 impl UmaZone {
@@ -51,26 +63,41 @@ impl UmaZone {
         bucket_zones: Arc<Vec<UmaZone>>,
         name: impl Into<String>,
         keg: Option<UmaKeg>,
+        master: Option<&UmaZone>,
         size: NonZero<usize>,
         align: Option<usize>,
         flags: impl Into<UmaFlags>,
     ) -> Self {
         let name = name.into();
         let flags = flags.into();
-        let (keg, mut flags) = if flags.has_any(UmaFlags::Secondary) {
-            todo!()
+        let (gg, kegs, keg_full, mut flags) = if flags.has_any(UmaFlags::Secondary) {
+            // The secondary zone use the keg of the master zone, including its lock.
+            let master = master.expect("secondary zone requires a master zone");
+
+            (
+                master.kegs.group().clone(),
+                master.kegs.clone(),
+                master.keg_full.clone(),
+                UmaFlags::Secondary.into(),
+            )
         } else {
             // We use a different approach here to make it idiomatic to Rust. On Orbis it will
             // construct a keg here if it is passed from the caller. If not it will allocate a new
             // keg from masterzone_k.
             let keg = match keg {
                 Some(v) => v,
-                None => UmaKeg::new(vm, size, align.unwrap_or(Self::ALIGN_CACHE), flags),
+                None => UmaKeg::new(size, align.unwrap_or(Self::ALIGN_CACHE), flags),
             };
 
-            (keg, UmaFlags::zeroed())
+            let gg = GutexGroup::new();
+            let kegs = Arc::new(gg.clone().spawn(LinkedList::from([keg])));
+
+            (gg, kegs, Arc::default(), UmaFlags::zeroed())
         };
 
+        let list = kegs.read();
+        let keg = list.front().unwrap();
+
         // Get type and uz_count.
         let mut ty = ZoneType::Other;
         let mut count = 0;
@@ -108,7 +135,6 @@ impl UmaZone {
         }
 
         // Construct uma_zone.
-        let gg = GutexGroup::new();
         let inherit = UmaFlags::Offpage
             | UmaFlags::Malloc
             | UmaFlags::Hash
@@ -120,28 +146,50 @@ impl UmaZone {
 
         flags |= keg.flags() & inherit;
 
+        let size = keg.size();
+
+        drop(list);
+
         Self {
+            vm,
             bucket_enable,
             bucket_keys,
-            bucket_zones,
+            name,
             ty,
-            size: keg.size(),
-            kegs: gg.clone().spawn(LinkedList::from([keg])),
+            size,
+            kegs,
+            keg_full,
             slab: Self::fetch_slab,
             caches: CpuLocal::new(|_| RefCell::default()),
             full_buckets: gg.clone().spawn_default(),
             free_buckets: gg.clone().spawn_default(),
             alloc_count: gg.clone().spawn_default(),
             free_count: gg.clone().spawn_default(),
+            cache_hits: gg.clone().spawn_default(),
+            cache_misses: gg.clone().spawn_default(),
             count: gg.spawn(count),
             flags,
+            bucket_zones,
         }
     }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     pub fn size(&self) -> NonZero<usize> {
         self.size
     }
 
+    /// Limit the number of items that can be allocated from this zone. Returns the actual limit,
+    /// which is rounded up to fill the last slab.
+    ///
+    /// See `uma_zone_set_max` on the Orbis for a reference.
+    #[allow(dead_code)] // TODO: Remove this once someone use it.
+    pub fn set_max(&self, items: usize) -> usize {
+        self.kegs.write().front_mut().unwrap().set_max_items(items)
+    }
+
     /// See `uma_zalloc_arg` on the Orbis for a reference.
     ///
     /// # Reference offsets
@@ -158,17 +206,23 @@ impl UmaZone {
             }
         }
 
-        loop {
+        let mem = loop {
             // Try allocate from per-CPU cache first so we don't need to acquire a mutex lock.
             let caches = self.caches.lock();
-            let mem = Self::alloc_from_cache(caches.borrow_mut().deref_mut());
+            let mut cache = caches.borrow_mut();
+            let mem = Self::alloc_from_cache(&mut cache);
 
             if !mem.is_null() {
-                return mem;
+                cache.hits += 1;
+                break mem;
             }
 
+            drop(cache);
+
             drop(caches); // Exit from non-sleeping context before acquire the mutex.
 
+            vmstat_poll();
+
             // Cache not found, allocate from the zone. We need to re-check the cache again because
             // we may on a different CPU since we drop the CPU pinning on the above.
             let mut frees = self.free_buckets.write();
@@ -178,17 +232,21 @@ impl UmaZone {
             let mem = Self::alloc_from_cache(&mut cache);
 
             if !mem.is_null() {
-                return mem;
+                cache.hits += 1;
+                break mem;
             }
 
-            // TODO: What actually we are doing here?
-            *self.alloc_count.write() += core::mem::take(&mut cache.allocs);
-            *self.free_count.write() += core::mem::take(&mut cache.frees);
+            // Since we have locked the zone we may as well send back our stats too.
+            self.flush_stats(&mut cache);
+
+            *self.cache_misses.write() += 1;
 
+            // Our old one is now a free bucket.
             if let Some(b) = cache.alloc.take() {
                 frees.push_front(b);
             }
 
+            // Check the full list for a new alloc bucket.
             if let Some(b) = self.full_buckets.write().pop_front() {
                 cache.alloc = Some(b);
 
@@ -197,28 +255,16 @@ impl UmaZone {
 
                 assert!(!m.is_null());
 
-                return m;
+                break m;
             }
 
             drop(cache);
             drop(caches);
 
-            // TODO: What is this?
-            if matches!(
-                self.ty,
-                ZoneType::MbufPacket
-                    | ZoneType::MbufJumboPage
-                    | ZoneType::Mbuf
-                    | ZoneType::MbufCluster
-            ) {
-                if flags.has_any(Alloc::Wait) {
-                    todo!()
-                }
-
-                todo!()
-            }
+            // The Orbis has a special path for the mbuf zones here but we don't have mbuf yet so
+            // we fill the bucket the same way as the other zones.
 
-            // TODO: What is this?
+            // Bump up our uz_count so we get here less.
             if !matches!(
                 self.ty,
                 ZoneType::MbufCluster
@@ -231,16 +277,123 @@ impl UmaZone {
                 *count += 1;
             }
 
-            if self.alloc_bucket(frees, count, flags) {
+            // Now lets just fill a bucket and put it on the full list. If that works we'll restart
+            // the allocation from the beginning. If not we return an actual item.
+            if !self.alloc_bucket(frees, count, flags) {
                 return self.alloc_item(flags);
             }
+        };
+
+        if flags.has_any(Alloc::Zero) {
+            unsafe { mem.write_bytes(0, self.size.get()) };
         }
+
+        mem
+    }
+
+    /// See `uma_zfree_arg` on the Orbis for a reference.
+    ///
+    /// # Safety
+    /// `item` must be allocated from this zone with [`Self::alloc()`] and it must not be freed yet.
+    pub unsafe fn free(&self, item: *mut u8) {
+        // Return the item to the keg directly if some threads are waiting for it. The race here is
+        // acceptable since the waiting thread will be woken by the next free.
+        if self.keg_full.load(Ordering::Relaxed) {
+            unsafe { self.free_item(item) };
+            return;
+        }
+
+        loop {
+            // Try free to per-CPU cache first so we don't need to acquire a mutex lock.
+            let caches = self.caches.lock();
+
+            if Self::free_to_cache(caches.borrow_mut().deref_mut(), item) {
+                return;
+            }
+
+            drop(caches); // Exit from non-sleeping context before acquire the mutex.
+
+            // Re-check the cache again since we may on a different CPU.
+            let mut frees = self.free_buckets.write();
+            let count = self.count.read();
+            let caches = self.caches.lock();
+            let mut cache = caches.borrow_mut();
+
+            if Self::free_to_cache(&mut cache, item) {
+                return;
+            }
+
+            // Since we have locked the zone we may as well send back our stats too.
+            self.flush_stats(&mut cache);
+
+            // Our old one is now a full bucket.
+            if let Some(b) = cache.free.take() {
+                self.full_buckets.write().push_front(b);
+            }
+
+            // Check the free list for a new free bucket.
+            if let Some(b) = frees.pop_front() {
+                cache.free = Some(b);
+
+                assert!(Self::free_to_cache(&mut cache, item));
+
+                return;
+            }
+
+            drop(cache);
+            drop(caches);
+
+            // Allocate a new free bucket then restart from the beginning.
+            let mut flags = Alloc::NoWait.into();
+
+            if self.flags.has_any(UmaFlags::CacheOnly) {
+                flags |= Alloc::NoVm;
+            }
+
+            match self.new_bucket(*count, flags) {
+                Some(b) => frees.push_front(b),
+                None => break,
+            }
+        }
+
+        // If nothing else caught this, we'll just do an internal free.
+        unsafe { self.free_item(item) };
+    }
+
+    /// Returns the statistics of this zone.
+    ///
+    /// The counters on the per-CPU caches are not included until the cache is refilled from the
+    /// zone.
+    pub fn stats(&self) -> ZoneStats {
+        let kegs = self.kegs.read();
+        let allocs = self.alloc_count.read();
+        let frees = self.free_count.read();
+        let hits = self.cache_hits.read();
+        let misses = self.cache_misses.read();
+
+        ZoneStats {
+            size: self.size,
+            allocs: *allocs,
+            frees: *frees,
+            free: kegs.iter().map(|k| u64::from(k.free())).sum(),
+            pages: kegs.iter().map(|k| u64::from(k.pages())).sum(),
+            cache_hits: *hits,
+            cache_misses: *misses,
+        }
+    }
+
+    /// The zone must be locked.
+    fn flush_stats(&self, c: &mut UmaCache) {
+        *self.alloc_count.write() += core::mem::take(&mut c.allocs);
+        *self.free_count.write() += core::mem::take(&mut c.frees);
+        *self.cache_hits.write() += core::mem::take(&mut c.hits);
     }
 
     fn alloc_from_cache(c: &mut UmaCache) -> *mut u8 {
         while let Some(b) = &mut c.alloc {
-            if b.len() != 0 {
-                todo!()
+            if let Some(v) = b.pop() {
+                c.allocs += 1;
+                return v;
             }
 
             if c.free.as_ref().is_some_and(|b| b.len() != 0) {
@@ -254,6 +407,25 @@ impl UmaZone {
         null_mut()
     }
 
+    fn free_to_cache(c: &mut UmaCache, item: *mut u8) -> bool {
+        while let Some(b) = &mut c.free {
+            if !b.is_full() {
+                b.push(item);
+                c.frees += 1;
+                return true;
+            }
+
+            if c.alloc.as_ref().is_some_and(|a| a.len() < b.len()) {
+                core::mem::swap(&mut c.alloc, &mut c.free);
+                continue;
+            }
+
+            break;
+        }
+
+        false
+    }
+
     /// See `zone_alloc_bucket` on the Orbis for a reference.
     ///
     /// # Reference offsets
@@ -262,50 +434,138 @@ impl UmaZone {
     /// |PS4 11.00|0x13EBA0|
     fn alloc_bucket(
         &self,
-        frees: GutexWrite<VecDeque<UmaBox<UmaBucket<[BucketItem]>>>>,
+        mut frees: GutexWrite<VecDeque<UmaBox<UmaBucket<[BucketItem]>>>>,
         count: GutexWrite<usize>,
         flags: Alloc,
     ) -> bool {
-        match frees.front() {
-            Some(_) => todo!(),
+        let mut bucket = match frees.pop_front() {
+            Some(v) => v,
             None => {
-                if self.bucket_enable.load(Ordering::Relaxed) {
-                    // Get allocation flags.
-                    let mut flags = flags & !Alloc::Zero;
+                // Get allocation flags.
+                let mut flags = flags & !Alloc::Zero;
 
-                    if self.flags.has_any(UmaFlags::CacheOnly) {
-                        flags |= Alloc::NoVm;
-                    }
+                if self.flags.has_any(UmaFlags::CacheOnly) {
+                    flags |= Alloc::NoVm;
+                }
 
-                    // Alloc a bucket.
-                    let i = (*count + 15) >> Uma::BUCKET_SHIFT;
-                    let k = self.bucket_keys[i];
+                match self.new_bucket(*count, flags) {
+                    Some(v) => v,
+                    None => return false,
+                }
+            }
+        };
 
-                    self.bucket_zones[k].alloc_item(flags);
+        // Try to keep the buckets totally full.
+        let max = min(bucket.entries(), *count);
+        let mut kegs = self.kegs.write();
+        let keg = kegs.front_mut().unwrap();
+        let mut flags = flags;
 
-                    todo!()
-                }
+        while bucket.len() < max {
+            let slab = match (self.slab)(self, keg, flags) {
+                Some(v) => v,
+                None => break,
+            };
+
+            while unsafe { (*slab).free_count() } != 0 && bucket.len() < max {
+                bucket.push(unsafe { keg.alloc_item(slab) });
             }
+
+            // Don't block on the next fill.
+            flags |= Alloc::NoWait;
+        }
+
+        drop(kegs);
+
+        if bucket.len() == 0 {
+            return false;
         }
 
+        self.full_buckets.write().push_front(bucket);
+
         true
     }
 
+    /// See `bucket_alloc` on the Orbis for a reference.
+    fn new_bucket(&self, count: usize, flags: Alloc) -> Option<UmaBox<UmaBucket<[BucketItem]>>> {
+        if !self.bucket_enable.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        let i = (count + 15) >> Uma::BUCKET_SHIFT;
+        let k = self.bucket_keys[i];
+        let zone = &self.bucket_zones[k];
+        let mem = zone.alloc_item(flags);
+
+        if mem.is_null() {
+            return None;
+        }
+
+        let bucket = unsafe { UmaBucket::init(mem, Uma::BUCKET_SIZES[k]) };
+
+        Some(unsafe { UmaBox::new(zone, bucket) })
+    }
+
+    /// Allocate an item directly from the slab, which bypass the per-CPU caches.
+    ///
     /// See `zone_alloc_item` on the Orbis for a reference.
     ///
     /// # Reference offsets
     /// | Version | Offset |
     /// |---------|--------|
     /// |PS4 11.00|0x13DD50|
-    fn alloc_item(&self, flags: Alloc) -> *mut u8 {
-        // Get a slab.
-        let slab = (self.slab)(self, None, flags);
+    pub fn alloc_item(&self, flags: Alloc) -> *mut u8 {
+        let item = loop {
+            // Get a slab.
+            let mut kegs = self.kegs.write();
+            let keg = kegs.front_mut().unwrap();
+
+            if let Some(v) = (self.slab)(self, keg, flags) {
+                let item = unsafe { keg.alloc_item(v) };
+
+                *self.alloc_count.write() += 1;
+
+                break item;
+            }
+
+            // Wait for the other threads to free some items if the keg has reached its limit.
+            if flags.has_any(Alloc::NoWait) || !keg.is_full() {
+                return null_mut();
+            }
 
-        if slab.is_some() {
-            todo!()
+            self.keg_full.store(true, Ordering::Relaxed);
+
+            drop(kegs);
+
+            sleep_until(Arc::as_ptr(&self.keg_full) as usize, || {
+                !self.keg_full.load(Ordering::Relaxed)
+            });
+        };
+
+        if flags.has_any(Alloc::Zero) {
+            unsafe { item.write_bytes(0, self.size.get()) };
         }
 
-        todo!()
+        item
+    }
+
+    /// Free an item directly to its slab, which bypass the per-CPU caches.
+    ///
+    /// See `zone_free_item` on the Orbis for a reference.
+    ///
+    /// # Safety
+    /// `item` must be allocated from this zone and it must not be freed yet.
+    pub unsafe fn free_item(&self, item: *mut u8) {
+        let mut kegs = self.kegs.write();
+
+        unsafe { kegs.front_mut().unwrap().free_item(item) };
+
+        *self.free_count.write() += 1;
+
+        if self.keg_full.swap(false, Ordering::Relaxed) {
+            drop(kegs);
+            wakeup(Arc::as_ptr(&self.keg_full) as usize);
+        }
     }
 
     /// See `zone_fetch_slab` on the Orbis for a reference.
@@ -314,23 +574,34 @@ impl UmaZone {
     /// | Version | Offset |
     /// |---------|--------|
     /// |PS4 11.00|0x141DB0|
-    fn fetch_slab(&self, keg: Option<&mut UmaKeg>, flags: Alloc) -> Option<()> {
-        let mut kegs = self.kegs.write();
-        let keg = keg.unwrap_or(kegs.front_mut().unwrap());
+    fn fetch_slab(&self, keg: &mut UmaKeg, flags: Alloc) -> Option<*mut Slab<[Free]>> {
+        if keg.flags().has_any(UmaFlags::Bucket) && keg.recurse() != 0 {
+            return None;
+        }
 
-        if !keg.flags().has_any(UmaFlags::Bucket) || keg.recurse() == 0 {
-            loop {
-                if let Some(v) = keg.fetch_slab(self, flags) {
-                    return Some(v);
-                }
+        // The Orbis retry with M_WAITOK since keg_fetch_slab will sleep in VM_WAIT until some
+        // pages are freed. We don't have VM_WAIT yet so retrying here will spin while holding the
+        // zone lock. The keg already retried with M_NOVM so let the caller handle the failure
+        // instead.
+        keg.fetch_slab(&self.vm, flags)
+    }
+}
 
-                if flags.has_any(Alloc::NoWait | Alloc::NoVm) {
-                    break;
-                }
-            }
-        }
+/// Statistics of [`UmaZone`].
+pub struct ZoneStats {
+    pub size: NonZero<usize>, // uz_size
+    pub allocs: u64,          // uz_allocs
+    pub frees: u64,           // uz_frees
+    pub free: u64,            // uk_free
+    pub pages: u64,           // uk_pages
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+}
 
-        None
+impl ZoneStats {
+    /// Returns the number of items that currently in use.
+    pub fn used(&self) -> u64 {
+        self.allocs.saturating_sub(self.frees)
     }
 }
 
@@ -357,4 +628,116 @@ struct UmaCache {
     free: Option<UmaBox<UmaBucket<[BucketItem]>>>,  // uc_freebucket
     allocs: u64,                                    // uc_allocs
     frees: u64,                                     // uc_frees
+    hits: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PAGE_SIZE;
+    use crate::uma::Uma;
+
+    extern crate std;
+
+    #[test]
+    fn alloc_free() {
+        let uma = Uma::new(Vm::new_test(1024));
+        let sizes = [16, 24, 64, 100, 256, 1000, 4096, PAGE_SIZE.get()];
+
+        for size in sizes {
+            for flags in [UmaFlags::zeroed(), UmaFlags::Malloc.into()] {
+                let size = NonZero::new(size).unwrap();
+                let zone = uma.create_zone("test", size, None, flags);
+                let mut items = Vec::new();
+
+                // Allocate enough items to refill the per-CPU cache multiple times.
+                for i in 0..(Uma::BUCKET_MAX * 2) {
+                    let item = zone.alloc(Alloc::Wait | Alloc::Zero);
+
+                    assert!(!item.is_null());
+                    assert_eq!(item as usize & UmaZone::ALIGN_CACHE, 0);
+                    assert!(!items.contains(&item));
+
+                    let data = unsafe { core::slice::from_raw_parts_mut(item, size.get()) };
+
+                    assert!(data.iter().all(|&b| b == 0));
+
+                    data.fill(i as u8);
+                    items.push(item);
+                }
+
+                // Check if the items are overlapped.
+                for (i, &item) in items.iter().enumerate() {
+                    let data = unsafe { core::slice::from_raw_parts(item, size.get()) };
+
+                    assert!(data.iter().all(|&b| b == i as u8));
+                }
+
+                // Free all items then allocate it again without new pages.
+                let pages = zone.stats().pages;
+
+                for &item in &items {
+                    unsafe { zone.free(item) };
+                }
+
+                for _ in 0..items.len() {
+                    assert!(!zone.alloc(Alloc::Wait.into()).is_null());
+                }
+
+                assert_eq!(zone.stats().pages, pages);
+            }
+        }
+    }
+
+    #[test]
+    fn secondary() {
+        let uma = Uma::new(Vm::new_test(64));
+        let master = uma.create_zone("master", NonZero::new(128).unwrap(), None, 0);
+        let zone = uma.create_secondary_zone("secondary", &master);
+
+        assert_eq!(zone.size(), master.size());
+
+        // Both zones must allocate from the same keg.
+        let item = zone.alloc_item(Alloc::Wait.into());
+
+        assert!(!item.is_null());
+        assert_eq!(master.stats().free, zone.stats().free);
+
+        unsafe { master.free_item(item) };
+
+        assert_eq!(master.alloc_item(Alloc::Wait.into()), item);
+    }
+
+    #[test]
+    fn max() {
+        let uma = Uma::new(Vm::new_test(64));
+        let zone = uma.create_zone("test", NonZero::new(1000).unwrap(), None, 0);
+        let max = zone.set_max(1);
+        let mut items = Vec::new();
+
+        assert!(max >= 1);
+
+        for _ in 0..max {
+            let item = zone.alloc_item(Alloc::Wait.into());
+
+            assert!(!item.is_null());
+
+            items.push(item);
+        }
+
+        assert!(zone.alloc_item(Alloc::NoWait.into()).is_null());
+
+        // Allocation with M_WAITOK must wait until the other thread free some items.
+        let item = items.pop().unwrap() as usize;
+
+        std::thread::scope(|s| {
+            let t = s.spawn(|| zone.alloc_item(Alloc::Wait.into()) as usize);
+
+            std::thread::yield_now();
+
+            unsafe { zone.free_item(item as *mut u8) };
+
+            assert_eq!(t.join().unwrap(), item);
+        });
+    }
 }
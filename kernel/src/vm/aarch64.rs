@@ -0,0 +1,48 @@
+use super::Vm;
+use core::arch::asm;
+
+/// Size of the smallest page the CPU supports. We only support 16K granule.
+pub const HW_PAGE_SIZE: usize = 0x4000;
+
+/// Returns `true` if the page at `addr` is mapped as writable user memory in the current address
+/// space.
+pub fn user_writable(vm: &Vm, addr: usize) -> bool {
+    let mut table: usize;
+
+    unsafe {
+        asm!("mrs {v}, ttbr0_el1", v = out(reg) table, options(nomem, preserves_flags, nostack))
+    };
+
+    // Walk level 0 to 3 with 16K granule. All levels must allow the access.
+    for (level, shift) in [47, 36, 25, 14].into_iter().enumerate() {
+        let index = (addr >> shift) & 0x7FF;
+        let base = vm.phys_to_dmap((table & ADDR_MASK).try_into().unwrap());
+        let entry = unsafe { base.cast::<usize>().add(index).read_volatile() };
+
+        if entry & DESC_VALID == 0 {
+            return false;
+        }
+
+        // Check if block or page descriptor.
+        if level == 3 || entry & DESC_TABLE == 0 {
+            // AP[1] allow EL0 and AP[2] make it read-only.
+            return entry & AP_EL0 != 0 && entry & AP_RO == 0;
+        }
+
+        if entry & (APTABLE_NO_EL0 | APTABLE_RO) != 0 {
+            return false;
+        }
+
+        table = entry;
+    }
+
+    unreachable!()
+}
+
+const DESC_VALID: usize = 1 << 0;
+const DESC_TABLE: usize = 1 << 1;
+const AP_EL0: usize = 1 << 6;
+const AP_RO: usize = 1 << 7;
+const APTABLE_NO_EL0: usize = 1 << 61;
+const APTABLE_RO: usize = 1 << 62;
+const ADDR_MASK: usize = 0x0000FFFFFFFFC000;
@@ -1,21 +1,26 @@
 pub use self::object::*;
 pub use self::page::*;
+pub use self::stats::*;
 
 use self::phys::PhysAllocator;
-use self::stats::VmStats;
 use crate::config::PAGE_SIZE;
 use crate::context::{config, current_thread};
 use crate::dmem::Dmem;
-use crate::lock::GutexGroup;
+use crate::lock::{Gutex, GutexGroup};
 use crate::proc::Proc;
 use alloc::sync::{Arc, Weak};
-use core::cmp::max;
+use config::BootEnv;
+use core::cmp::{max, min};
 use core::fmt::Debug;
+use core::num::NonZero;
 use core::sync::atomic::{AtomicUsize, Ordering};
-use krt::info;
+use krt::{boot_env, info};
 use macros::bitflag;
 use thiserror::Error;
 
+#[cfg_attr(target_arch = "aarch64", path = "aarch64.rs")]
+#[cfg_attr(target_arch = "x86_64", path = "x86_64.rs")]
+mod arch;
 mod object;
 mod page;
 mod phys;
@@ -23,7 +28,8 @@ mod stats;
 
 /// Implementation of Virtual Memory system.
 pub struct Vm {
-    phys: PhysAllocator,
+    dmap: usize,
+    phys: Gutex<PhysAllocator>, // vm_page_queue_free_mtx
     stats: [VmStats; 2],
     pagers: [Weak<Proc>; 2],         // pageproc
     pages_deficit: [AtomicUsize; 2], // vm_pageout_deficit
@@ -37,7 +43,11 @@ impl Vm {
     /// |---------|--------|
     /// |PS4 11.00|0x029200|
     pub fn new(phys_avail: [u64; 61], dmem: &Dmem) -> Result<Arc<Self>, VmError> {
-        let phys = PhysAllocator::new(&phys_avail);
+        let dmap = match boot_env() {
+            BootEnv::Vm(v) => v.dmap,
+        };
+
+        let mut phys = PhysAllocator::new(&phys_avail, dmap);
 
         // Get initial v_page_count and v_free_count.
         let page_size = u64::try_from(PAGE_SIZE.get()).unwrap();
@@ -56,17 +66,24 @@ impl Vm {
                 break;
             }
 
+            // Populate free pages. The pages within the game memory will be managed by DMEM.
+            if addr < unk {
+                phys.add_pages(0, addr, min(end, unk));
+            }
+
+            if dmem.game_end() < end {
+                phys.add_pages(0, max(addr, dmem.game_end()), end);
+            }
+
             while addr < end {
                 if blocked.is_some() {
                     todo!();
                 }
 
                 if addr < unk || dmem.game_end() <= addr {
-                    // TODO: Update vm_phys_segs.
                     page_count[0] += 1;
                     free_count[0] += 1;
                 } else {
-                    // TODO: Update vm_phys_segs.
                     page_count[1] += 1;
                 }
 
@@ -90,12 +107,14 @@ impl Vm {
         let gg = GutexGroup::new();
         let stats = [
             VmStats {
+                page_count: page_count[0],
                 free_reserved: pageout_page_count + 100 + 10,
                 cache_count: gg.clone().spawn_default(),
                 free_count: gg.clone().spawn(free_count[0]),
                 interrupt_free_min: gg.clone().spawn(2),
             },
             VmStats {
+                page_count: page_count[1],
                 free_reserved: pageout_page_count,
                 cache_count: gg.clone().spawn_default(),
                 free_count: gg.clone().spawn(free_count[1]),
@@ -106,7 +125,8 @@ impl Vm {
         // Spawn page daemons. The Orbis do this in a separated sysinit but we do it here instead to
         // keep it in the VM subsystem.
         let mut vm = Self {
-            phys,
+            dmap,
+            phys: gg.spawn(phys),
             stats,
             pagers: Default::default(),
             pages_deficit: [AtomicUsize::new(0), AtomicUsize::new(0)],
@@ -117,6 +137,12 @@ impl Vm {
         Ok(Arc::new(vm))
     }
 
+    /// Returns the statistics for each VM. Index 0 is the system memory and index 1 is the game
+    /// memory.
+    pub fn stats(&self) -> &[VmStats; 2] {
+        &self.stats
+    }
+
     /// See `vm_page_alloc` on the Orbis for a reference.
     ///
     /// # Reference offsets
@@ -124,14 +150,91 @@ impl Vm {
     /// |---------|--------|
     /// |PS4 11.00|0x02B030|
     pub fn alloc_page(&self, obj: Option<VmObject>, flags: VmAlloc) -> Option<VmPage> {
+        self.alloc_pages(obj, 0, flags)
+    }
+
+    /// Allocate `npages` pages that are physically contiguous. The actual number of pages will be
+    /// rounded up to a power of two, which is available from [`VmPage::order()`].
+    ///
+    /// See `vm_page_alloc_contig` on the Orbis for a reference.
+    pub fn alloc_contig(&self, npages: NonZero<usize>, flags: VmAlloc) -> Option<VmPage> {
+        let order = npages.get().next_power_of_two().trailing_zeros();
+
+        self.alloc_pages(None, order.try_into().unwrap(), flags)
+    }
+
+    /// Free the pages that was allocated with [`Vm::alloc_page()`] or [`Vm::alloc_contig()`].
+    /// `addr` and `order` must be the value of [`VmPage::addr()`] and [`VmPage::order()`].
+    ///
+    /// See `vm_page_free` on the Orbis for a reference.
+    pub fn free_contig(&self, addr: u64, order: usize) {
+        // Return the memory to the host before the pages can be allocated again. The Orbis does
+        // not do this since it run on a real hardware. The tests does not have the VMM.
+        #[cfg(not(test))]
+        unsafe {
+            let len = NonZero::new(PAGE_SIZE.get() << order).unwrap();
+
+            krt::release_ram(addr.try_into().unwrap(), len)
+        };
+
+        // TODO: Use the VM and the pool of the page once we support VmObject.
+        let mut phys = self.phys.write();
+        let mut free_count = self.stats[0].free_count.write();
+
+        phys.free_pages(0, 1, addr, order);
+
+        *free_count += 1 << order;
+    }
+
+    /// Returns a pointer to the physical address `addr` in the direct map.
+    ///
+    /// See `PHYS_TO_DMAP` on the Orbis for a reference.
+    pub fn phys_to_dmap(&self, addr: u64) -> *mut u8 {
+        self.dmap.wrapping_add(addr.try_into().unwrap()) as *mut u8
+    }
+
+    /// Returns a physical address of `ptr` that was returned from [`Vm::phys_to_dmap()`].
+    ///
+    /// See `DMAP_TO_PHYS` on the Orbis for a reference.
+    pub fn dmap_to_phys(&self, ptr: *const u8) -> u64 {
+        (ptr as usize - self.dmap).try_into().unwrap()
+    }
+
+    /// Returns `true` if `len` bytes at `addr` are mapped as writable user memory in the current
+    /// address space.
+    ///
+    /// We don't have a page fault handler for the user memory yet so this is used to validate the
+    /// pointers from the user instead.
+    pub fn is_user_writable(&self, addr: usize, len: usize) -> bool {
+        let end = match addr.checked_add(len) {
+            Some(v) => v,
+            None => return false,
+        };
+
+        let mut page = addr & !(self::arch::HW_PAGE_SIZE - 1);
+
+        while page < end {
+            if !self::arch::user_writable(self, page) {
+                return false;
+            }
+
+            page += self::arch::HW_PAGE_SIZE;
+        }
+
+        true
+    }
+
+    fn alloc_pages(&self, obj: Option<VmObject>, order: usize, flags: VmAlloc) -> Option<VmPage> {
         let vm = obj.as_ref().map_or(0, |v| v.vm());
         let td = current_thread();
+        let npages = 1 << order;
         let stats = &self.stats[vm];
+        let mut phys = self.phys.write();
         let cache_count = stats.cache_count.read();
         let free_count = stats.free_count.read();
         let available = *free_count + *cache_count;
 
-        if available <= stats.free_reserved {
+        if available < stats.free_reserved + npages {
             let p = td.proc();
             let mut flags = if Arc::as_ptr(p) == self.pagers[p.pager()].as_ptr() {
                 VmAlloc::System.into()
@@ -151,6 +254,7 @@ impl Vm {
 
                     drop(free_count);
                     drop(cache_count);
+                    drop(phys);
 
                     self.pages_deficit[vm].fetch_add(deficit.into(), Ordering::Relaxed);
                     self.wake_pager(vm);
@@ -162,6 +266,9 @@ impl Vm {
             }
         }
 
+        drop(free_count);
+        drop(cache_count);
+
         // Allocate VmPage.
         let page = match &obj {
             Some(_) => todo!(),
@@ -170,17 +277,20 @@ impl Vm {
                     return None;
                 }
 
-                self.phys.alloc_page(vm, obj.is_none().into(), 0)
+                phys.alloc_page(vm, obj.is_none().into(), order)
             }
         };
 
-        // TODO: The Orbis assume page is never null here.
-        let page = page.unwrap();
+        // The Orbis assume page is never null here but it can be null for contiguous pages when the
+        // memory was fragmented.
+        let page = page?;
 
         match page.flags().has_any(PageFlags::Cached) {
             true => todo!(),
-            false => todo!(),
+            false => *stats.free_count.write() -= npages,
         }
+
+        Some(page)
     }
 
     /// See `kick_pagedaemons` on the Orbis for a reference.
@@ -205,6 +315,55 @@ impl Vm {
     }
 }
 
+#[cfg(test)]
+impl Vm {
+    /// Create a [`Vm`] with `pages` pages of the physical memory on the heap.
+    ///
+    /// The tests does not have the direct map so the memory will be leaked.
+    pub fn new_test(pages: usize) -> Arc<Self> {
+        // Use the heap as the physical memory at the second page.
+        let layout = core::alloc::Layout::from_size_align(pages * PAGE_SIZE.get(), PAGE_SIZE.get());
+        let mem = unsafe { alloc::alloc::alloc(layout.unwrap()) };
+        let dmap = mem as usize - PAGE_SIZE.get();
+        let start = u64::try_from(PAGE_SIZE.get()).unwrap();
+        let end = start * (u64::try_from(pages).unwrap() + 1);
+        let mut phys_avail = [0; 61];
+
+        phys_avail[0] = start;
+        phys_avail[1] = end;
+
+        let mut phys = PhysAllocator::new(&phys_avail, dmap);
+
+        phys.add_pages(0, start, end);
+
+        let gg = GutexGroup::new();
+        let stats = [
+            VmStats {
+                page_count: pages,
+                free_reserved: 0,
+                cache_count: gg.clone().spawn_default(),
+                free_count: gg.clone().spawn(pages),
+                interrupt_free_min: gg.clone().spawn(0),
+            },
+            VmStats {
+                page_count: 0,
+                free_reserved: 0,
+                cache_count: gg.clone().spawn_default(),
+                free_count: gg.clone().spawn_default(),
+                interrupt_free_min: gg.clone().spawn(0),
+            },
+        ];
+
+        Arc::new(Self {
+            dmap,
+            phys: gg.spawn(phys),
+            stats,
+            pagers: Default::default(),
+            pages_deficit: [AtomicUsize::new(0), AtomicUsize::new(0)],
+        })
+    }
+}
+
 /// Flags for [`Vm::alloc_page()`].
 #[bitflag(u32)]
 pub enum VmAlloc {
@@ -2,10 +2,30 @@ use macros::bitflag;
 
 /// Implementation of `vm_page` structure.
 pub struct VmPage {
+    addr: u64,        // phys_addr
+    order: usize,     // order
     flags: PageFlags, // flags
 }
 
 impl VmPage {
+    pub(super) fn new(addr: u64, order: usize) -> Self {
+        Self {
+            addr,
+            order,
+            flags: PageFlags::zeroed(),
+        }
+    }
+
+    /// Returns the physical address of this page.
+    pub fn addr(&self) -> u64 {
+        self.addr
+    }
+
+    /// Returns `n` where `2^n` is the number of contiguous pages starting at this page.
+    pub fn order(&self) -> usize {
+        self.order
+    }
+
     pub fn flags(&self) -> PageFlags {
         self.flags
     }
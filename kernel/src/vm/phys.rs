@@ -1,22 +1,27 @@
 use super::VmPage;
-use alloc::collections::vec_deque::VecDeque;
-use alloc::sync::Arc;
+use crate::config::PAGE_SHIFT;
+use core::cmp::min;
 
 /// Provides methods to allocate physical memory.
+///
+/// The free pages are linked through the direct map so adding or removing the pages never allocate
+/// any memory.
 pub struct PhysAllocator {
-    nfree: usize, // vm_nfreelists
-    #[allow(clippy::type_complexity)] // TODO: Remove this.
-    lookup_lists: [Arc<[[[VecDeque<VmPage>; 13]; 3]; 2]>; 2], // vm_phys_lookup_lists
+    dmap: usize,
+    nfree: usize,                                                     // vm_nfreelists
+    free_queues: [[[[FreeList; VM_NFREEORDER]; VM_NFREEPOOL]; 2]; 2], // vm_phys_free_queues
 }
 
 impl PhysAllocator {
+    /// `dmap` is the virtual address where the physical memory is mapped.
+    ///
     /// See `vm_phys_init` on the Orbis for a reference.
     ///
     /// # Reference offsets
     /// | Version | Offset |
     /// |---------|--------|
     /// |PS4 11.00|0x15F410|
-    pub fn new(phys_avail: &[u64; 61]) -> Self {
+    pub fn new(phys_avail: &[u64; 61], dmap: usize) -> Self {
         let mut nfree = 0;
 
         for i in (0..).step_by(2) {
@@ -28,34 +33,42 @@ impl PhysAllocator {
                 break;
             }
 
-            // TODO: What is 16777216?
-            if addr < 16777216 {
-                let unk = end < 0x1000001;
-
-                if !unk {
-                    // TODO: Invoke vm_phys_create_seg.
-                }
-
-                // TODO: Invoke vm_phys_create_seg.
+            // We don't need vm_phys_segs since the free pages are linked through the direct map.
+            if addr < ISADMA_END {
                 nfree = 1;
-            } else {
-                // TODO: Invoke vm_phys_create_seg.
             }
         }
 
-        // Populate vm_phys_free_queues. Do not use Clone to construct the array here since it will
-        // refer to the same object.
-        let free_queues = [
-            Arc::<[[[VecDeque<VmPage>; 13]; 3]; 2]>::default(),
-            Arc::<[[[VecDeque<VmPage>; 13]; 3]; 2]>::default(),
-        ];
-
-        // Populate vm_phys_lookup_lists.
-        let lookup_lists = [free_queues[0].clone(), free_queues[1].clone()];
-
         Self {
+            dmap,
             nfree,
-            lookup_lists,
+            free_queues: [[[[FreeList::new(); VM_NFREEORDER]; VM_NFREEPOOL]; 2]; 2],
+        }
+    }
+
+    /// Add the pages in the range `start..end` to the free queues of `vm`. Both `start` and `end`
+    /// must be multiply by page size and `start` must not be zero.
+    ///
+    /// See `vm_phys_add_page` on the Orbis for a reference.
+    pub fn add_pages(&mut self, vm: usize, mut start: u64, end: u64) {
+        assert_ne!(start, 0);
+
+        while start < end {
+            // The pages below 16MB have its own free list.
+            let (flind, limit) = if start < ISADMA_END {
+                (1, min(end, ISADMA_END))
+            } else {
+                (0, end)
+            };
+
+            // Get the largest block that naturally aligned.
+            let pages = (limit - start) >> PAGE_SHIFT;
+            let align = (start >> PAGE_SHIFT).trailing_zeros() as usize;
+            let order = min(min(align, pages.ilog2() as usize), VM_NFREEORDER - 1);
+
+            self.free_queues[flind][vm][VM_FREEPOOL_DEFAULT][order].push(self.dmap, start);
+
+            start += 1 << (order + PAGE_SHIFT);
         }
     }
 
@@ -65,27 +78,27 @@ impl PhysAllocator {
     /// | Version | Offset |
     /// |---------|--------|
     /// |PS4 11.00|0x160520|
-    pub fn alloc_page(&self, vm: usize, pool: usize, order: usize) -> Option<VmPage> {
+    pub fn alloc_page(&mut self, vm: usize, pool: usize, order: usize) -> Option<VmPage> {
         // TODO: There is an increasement on unknown variable here.
-        let mut i = 0;
-
-        loop {
-            let l = &self.lookup_lists[i];
-
-            if let Some(v) = self.alloc_freelist(&l[vm], pool, order) {
+        for flind in 0..=self.nfree {
+            if let Some(v) = self.alloc_freelist(flind, vm, pool, order) {
                 return Some(v);
             }
-
-            i += 1;
-
-            if i >= (self.nfree + 1) {
-                break;
-            }
         }
 
         None
     }
 
+    /// Put `2^order` pages starting at `addr` back to the free queues.
+    ///
+    /// See `vm_phys_free_pages` on the Orbis for a reference.
+    pub fn free_pages(&mut self, vm: usize, pool: usize, addr: u64, order: usize) {
+        let flind = if addr < ISADMA_END { 1 } else { 0 };
+
+        // TODO: Coalesce with the buddy.
+        self.free_queues[flind][vm][pool][order].push(self.dmap, addr);
+    }
+
     /// See `vm_phys_alloc_freelist_pages` on the Orbis for a reference.
     ///
     /// # Reference offsets
@@ -93,57 +106,165 @@ impl PhysAllocator {
     /// |---------|--------|
     /// |PS4 11.00|0x1605D0|
     fn alloc_freelist(
-        &self,
-        list: &[[VecDeque<VmPage>; 13]; 3],
+        &mut self,
+        flind: usize,
+        vm: usize,
         pool: usize,
         order: usize,
     ) -> Option<VmPage> {
-        if order >= 13 {
+        if order >= VM_NFREEORDER {
             return None;
         }
 
-        let mut i = 0;
-
-        loop {
-            match list[pool][order + i].front() {
-                Some(v) => v,
-                None => match (order + i) < 12 {
-                    true => {
-                        i += 1;
-                        continue;
-                    }
-                    false => break,
-                },
-            };
+        let dmap = self.dmap;
+        let queues = &mut self.free_queues[flind][vm];
 
-            todo!()
+        for oind in order..VM_NFREEORDER {
+            if let Some(addr) = queues[pool][oind].pop(dmap) {
+                Self::split_pages(dmap, &mut queues[pool], addr, oind, order);
+                return Some(VmPage::new(addr, order));
+            }
         }
 
-        let mut next = 11;
+        // The given pool was empty. Find the largest contiguous pages in any pool then transfer it
+        // to the given pool.
+        for oind in (order..VM_NFREEORDER).rev() {
+            for pind in 0..VM_NFREEPOOL {
+                if let Some(addr) = queues[pind][oind].pop(dmap) {
+                    Self::split_pages(dmap, &mut queues[pool], addr, oind, order);
+                    return Some(VmPage::new(addr, order));
+                }
+            }
+        }
 
-        loop {
-            let mut found = None;
+        None
+    }
 
-            for f in list {
-                found = f[next + 1].front();
+    /// Put the upper halves of `2^oind` pages starting at `addr` back to `fl` until it has only
+    /// `2^order` pages left.
+    ///
+    /// See `vm_phys_split_pages` on the Orbis for a reference.
+    fn split_pages(
+        dmap: usize,
+        fl: &mut [FreeList; VM_NFREEORDER],
+        addr: u64,
+        mut oind: usize,
+        order: usize,
+    ) {
+        while oind > order {
+            oind -= 1;
+            fl[oind].push(dmap, addr + (1 << (oind + PAGE_SHIFT)));
+        }
+    }
+}
 
-                if found.is_some() {
-                    break;
-                }
-            }
+/// Implementation of `vm_freelist` structure.
+///
+/// Each free block contains the physical address of the next block at the beginning. Physical
+/// address zero is used to mark the end of the list.
+#[derive(Clone, Copy)]
+struct FreeList {
+    head: u64, // pl
+}
 
-            match found {
-                Some(_) => todo!(),
-                None => {
-                    if next < order || next == 0 {
-                        break;
-                    }
+impl FreeList {
+    const fn new() -> Self {
+        Self { head: 0 }
+    }
 
-                    next -= 1;
-                }
-            }
+    fn push(&mut self, dmap: usize, addr: u64) {
+        let next = dmap.wrapping_add(addr.try_into().unwrap()) as *mut u64;
+
+        unsafe { next.write(self.head) };
+
+        self.head = addr;
+    }
+
+    fn pop(&mut self, dmap: usize) -> Option<u64> {
+        let addr = self.head;
+
+        if addr == 0 {
+            return None;
         }
 
-        None
+        let next = dmap.wrapping_add(addr.try_into().unwrap()) as *const u64;
+
+        self.head = unsafe { next.read() };
+
+        Some(addr)
+    }
+}
+
+/// End of the physical address for `VM_FREELIST_ISADMA`.
+const ISADMA_END: u64 = 16777216;
+
+/// `VM_NFREEORDER`.
+const VM_NFREEORDER: usize = 13;
+
+/// `VM_NFREEPOOL`.
+const VM_NFREEPOOL: usize = 3;
+
+/// `VM_FREEPOOL_DEFAULT`.
+const VM_FREEPOOL_DEFAULT: usize = 0;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PAGE_SIZE;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn alloc_free() {
+        // Use a buffer as the physical memory at the second page.
+        let page = u64::try_from(PAGE_SIZE.get()).unwrap();
+        let end = page * 65;
+        let mut mem = vec![0u64; (end - page) as usize / 8];
+        let dmap = mem.as_mut_ptr() as usize - PAGE_SIZE.get();
+        let mut phys_avail = [0; 61];
+
+        phys_avail[0] = page;
+        phys_avail[1] = end;
+
+        let mut phys = PhysAllocator::new(&phys_avail, dmap);
+
+        phys.add_pages(0, page, end);
+
+        // Allocate contiguous pages.
+        let block = phys.alloc_page(0, 1, 3).unwrap();
+
+        assert_eq!(block.addr() % (page * 8), 0);
+        assert_eq!(block.order(), 3);
+
+        // Allocate all remaining pages.
+        let mut pages = Vec::new();
+
+        while let Some(v) = phys.alloc_page(0, 1, 0) {
+            let addr = v.addr();
+
+            assert!(addr >= page && addr < end);
+            assert!(addr < block.addr() || addr >= block.addr() + page * 8);
+            assert!(!pages.contains(&addr));
+
+            pages.push(addr);
+        }
+
+        assert_eq!(pages.len(), 64 - 8);
+        assert!(phys.alloc_page(1, 1, 0).is_none());
+
+        // Free all pages then allocate it again.
+        for &addr in &pages {
+            phys.free_pages(0, 1, addr, 0);
+        }
+
+        phys.free_pages(0, 1, block.addr(), 3);
+
+        assert!(phys.alloc_page(0, 1, 3).is_some());
+
+        for _ in 0..pages.len() {
+            assert!(phys.alloc_page(0, 0, 0).is_some());
+        }
+
+        assert!(phys.alloc_page(0, 1, 0).is_none());
     }
 }
@@ -4,6 +4,7 @@ use crate::lock::Gutex;
 ///
 /// This is a subset of `vmmeter` structure.
 pub struct VmStats {
+    pub page_count: usize,                // v_page_count
     pub free_reserved: usize,             // v_free_reserved
     pub cache_count: Gutex<usize>,        // v_cache_count
     pub free_count: Gutex<usize>,         // v_free_count
@@ -0,0 +1,39 @@
+use super::Vm;
+use core::arch::asm;
+
+/// Size of the smallest page the CPU supports.
+pub const HW_PAGE_SIZE: usize = 0x1000;
+
+/// Returns `true` if the page at `addr` is mapped as writable user memory in the current address
+/// space.
+pub fn user_writable(vm: &Vm, addr: usize) -> bool {
+    let mut table: usize;
+
+    unsafe { asm!("mov {v}, cr3", v = out(reg) table, options(nomem, preserves_flags, nostack)) };
+
+    // Walk PML4, PDPT, PD and PT. All levels must allow the access.
+    for shift in [39, 30, 21, 12] {
+        let index = (addr >> shift) & 0x1FF;
+        let base = vm.phys_to_dmap((table & ADDR_MASK).try_into().unwrap());
+        let entry = unsafe { base.cast::<usize>().add(index).read_volatile() };
+
+        if entry & (PTE_P | PTE_RW | PTE_US) != (PTE_P | PTE_RW | PTE_US) {
+            return false;
+        }
+
+        // Check if 1GB or 2MB page.
+        if shift != 12 && entry & PTE_PS != 0 {
+            break;
+        }
+
+        table = entry;
+    }
+
+    true
+}
+
+const PTE_P: usize = 0x001;
+const PTE_RW: usize = 0x002;
+const PTE_US: usize = 0x004;
+const PTE_PS: usize = 0x080;
+const ADDR_MASK: usize = 0x000FFFFFFFFFF000;
@@ -0,0 +1,193 @@
+use crate::KERNEL_HEAP;
+use crate::context::{config, current_thread, uma};
+use crate::proc::Thread;
+use crate::sched::{sched_add, sleep_until, wakeup};
+use crate::time::nanouptime;
+use crate::uma::UmaZone;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use krt::{stats, warn};
+
+/// Start a thread to report the memory statistics to the host periodically.
+///
+/// The interval in seconds can be changed with `vm.stats_interval` environment variable. Zero will
+/// disable the report. The report will be skipped if the kernel does not allocate any memory within
+/// the interval since there is nothing new to report.
+pub fn start_vmstatd() {
+    let secs: usize = match config().env("vm.stats_interval") {
+        Some(v) => match v.parse() {
+            Ok(v) => v,
+            Err(_) => {
+                warn!("Invalid vm.stats_interval '{v}', using {DEFAULT_INTERVAL} seconds.");
+                DEFAULT_INTERVAL
+            }
+        },
+        None => DEFAULT_INTERVAL,
+    };
+
+    if secs == 0 {
+        return;
+    }
+
+    let secs = i64::try_from(secs).unwrap_or(i64::MAX);
+
+    NEXT.store(nanouptime().sec.saturating_add(secs), Ordering::Relaxed);
+    INTERVAL.store(secs, Ordering::Relaxed);
+
+    sched_add(Arc::new(Thread::new(
+        current_thread().proc().clone(),
+        vmstatd,
+    )));
+}
+
+/// Wakeup the reporting thread if the interval has been elapsed.
+///
+/// This is invoked by the slow path of the allocators so the report will be produced only when the
+/// kernel is allocating. This must be called only when the current thread can sleep.
+pub fn vmstat_poll() {
+    let interval = INTERVAL.load(Ordering::Relaxed);
+
+    if interval == 0 {
+        return;
+    }
+
+    // Only one thread can win the deadline.
+    let now = nanouptime().sec;
+    let next = NEXT.load(Ordering::Relaxed);
+
+    if now < next
+        || NEXT
+            .compare_exchange(
+                next,
+                now.saturating_add(interval),
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            )
+            .is_err()
+    {
+        return;
+    }
+
+    REPORT.store(true, Ordering::Relaxed);
+    wakeup(&raw const REPORT as usize);
+}
+
+/// Entry point of the reporting thread.
+fn vmstatd() -> ! {
+    loop {
+        sleep_until(&raw const REPORT as usize, || {
+            REPORT.swap(false, Ordering::Relaxed)
+        });
+
+        report();
+    }
+}
+
+/// See `vmstat -s`, `vmstat -m` and `vmstat -z` for a reference.
+fn report() {
+    let uma = uma().unwrap();
+    let vm = uma.vm();
+    let heap = KERNEL_HEAP.vm().unwrap();
+    let up = nanouptime();
+    let mut out = String::with_capacity(0x4000);
+
+    writeln!(
+        out,
+        "Memory statistics at {}.{:03}s uptime.",
+        up.sec,
+        up.nsec / 1000000
+    )
+    .unwrap();
+
+    // Pages.
+    writeln!(
+        out,
+        "\n{:<3} {:>10} {:>10} {:>10}",
+        "VM", "PAGES", "FREE", "CACHE"
+    )
+    .unwrap();
+
+    for (i, s) in vm.stats().iter().enumerate() {
+        let free = *s.free_count.read();
+        let cache = *s.cache_count.read();
+
+        writeln!(out, "{i:<3} {:>10} {free:>10} {cache:>10}", s.page_count).unwrap();
+    }
+
+    // Heap.
+    let s = heap.stats();
+
+    writeln!(
+        out,
+        "\n{:<6} {:>10} {:>12} {:>10}  SIZES",
+        "TYPE", "INUSE", "MEMUSE", "REQUESTS"
+    )
+    .unwrap();
+
+    write!(
+        out,
+        "{:<6} {:>10} {:>12} {:>10} ",
+        "kernel",
+        s.used_count(),
+        s.used_bytes(),
+        s.alloc_count
+    )
+    .unwrap();
+
+    for i in (0..u64::BITS).filter(|&i| s.size & (1 << i) != 0) {
+        write!(out, " {}", 16usize << i).unwrap();
+    }
+
+    out.push('\n');
+
+    // Zones.
+    writeln!(
+        out,
+        "\n{:<12} {:>6} {:>6} {:>10} {:>10} {:>10} {:>10} {:>10} {:>8}",
+        "ZONE", "ALIGN", "SIZE", "USED", "FREE", "REQ", "HIT", "MISS", "PAGES"
+    )
+    .unwrap();
+
+    let buckets = uma.bucket_zones().iter().map(|z| (None, z));
+    let heap = heap.zones().map(|(a, z)| (Some(a), z));
+
+    for (align, zone) in buckets.chain(heap) {
+        write_zone(&mut out, align, zone);
+    }
+
+    stats(out.trim_end());
+}
+
+fn write_zone(out: &mut String, align: Option<usize>, zone: &UmaZone) {
+    let s = zone.stats();
+
+    // Skip the zones that never used.
+    if s.allocs == 0 && s.pages == 0 {
+        return;
+    }
+
+    let align = align.map_or_else(|| "-".into(), |v| v.to_string());
+
+    writeln!(
+        out,
+        "{:<12} {align:>6} {:>6} {:>10} {:>10} {:>10} {:>10} {:>10} {:>8}",
+        zone.name(),
+        s.size,
+        s.used(),
+        s.free,
+        s.allocs,
+        s.cache_hits,
+        s.cache_misses,
+        s.pages
+    )
+    .unwrap();
+}
+
+/// Default value of `vm.stats_interval`.
+const DEFAULT_INTERVAL: usize = 60;
+
+static INTERVAL: AtomicI64 = AtomicI64::new(0);
+static NEXT: AtomicI64 = AtomicI64::new(0);
+static REPORT: AtomicBool = AtomicBool::new(false);
@@ -1,5 +1,5 @@
 use crate::context::{current_trap_rsp_offset, current_user_rsp_offset};
-use crate::trap::{interrupt_handler, syscall_handler};
+use crate::trap::{TF_HASSEGS, TrapFrame, TrapNo, interrupt_handler, syscall_handler};
 use alloc::boxed::Box;
 use alloc::string::String;
 use alloc::sync::Arc;
@@ -8,7 +8,7 @@ use alloc::vec::Vec;
 use bitfield_struct::bitfield;
 use core::arch::{asm, global_asm};
 use core::fmt::Write;
-use core::mem::{transmute, zeroed};
+use core::mem::{offset_of, transmute, zeroed};
 use x86_64::{
     Dpl, Efer, Gdtr, Rflags, SegmentDescriptor, SegmentSelector, Star, Tss64, TssDescriptor,
 };
@@ -62,69 +62,7 @@ pub fn identify_cpu() -> CpuInfo {
 /// # Safety
 /// This function can be called only once and must be called by main CPU entry point.
 pub unsafe fn setup_main_cpu(cpu: CpuInfo) -> Arc<ArchConfig> {
-    // Setup GDT.
-    let mut gdt = vec![
-        // Null descriptor.
-        SegmentDescriptor::new(),
-        // 32-bit GS for user.
-        SegmentDescriptor::new(),
-        // 32-bit FS for user.
-        SegmentDescriptor::new(),
-        // CS for kernel.
-        SegmentDescriptor::new()
-            .with_ty(0b1000) // This required somehow although the docs said it is ignored.
-            .with_s(true) // Same here.
-            .with_p(true)
-            .with_l(true), // 64-bit mode.
-        // DS for kernel.
-        SegmentDescriptor::new()
-            .with_ty(0b0010) // This required somehow although the docs said it is ignored.
-            .with_s(true) // Same here.
-            .with_p(true),
-        // 32-bit CS for user.
-        SegmentDescriptor::new(),
-        // DS for user.
-        SegmentDescriptor::new(),
-        // 64-bit CS for user.
-        SegmentDescriptor::new(),
-    ];
-
-    // Setup Task State Segment (TSS).
-    let trap_rsp = Box::new([0u8; 1024 * 128]);
-    let trap_rsp = Box::leak(trap_rsp);
-    let tss = unsafe { push_tss(&mut gdt, trap_rsp) };
-
-    // Switch GDT from bootloader GDT to our own.
-    let limit = (size_of::<SegmentDescriptor>() * gdt.len() - 1)
-        .try_into()
-        .unwrap();
-
-    gdt.shrink_to_fit();
-
-    unsafe {
-        set_gdtr(
-            &Gdtr {
-                limit,
-                addr: gdt.leak().as_ptr(),
-            },
-            GDT_KERNEL_CS,
-            GDT_KERNEL_DS,
-        )
-    };
-
-    // Set Task Register (TR).
-    unsafe {
-        asm!(
-            "ltr {v:x}",
-            v = in(reg) tss.into_bits(),
-            options(preserves_flags, nostack)
-        )
-    };
-
     // See idt0 on the PS4 for a reference.
-    const IDT_LEN: usize = 256;
-    static mut IDT: [GateDescriptor; IDT_LEN] = unsafe { zeroed() };
-
     let set_idt = |n: usize, f: unsafe extern "C" fn() -> !, ty, dpl, ist| {
         let f = f as usize;
         let d = GateDescriptor::new()
@@ -139,71 +77,74 @@ pub unsafe fn setup_main_cpu(cpu: CpuInfo) -> Arc<ArchConfig> {
         unsafe { IDT[n] = d };
     };
 
+    set_idt(1, Xdbg, 0b1110, Dpl::Ring0, 0);
     set_idt(3, Xbpt, 0b1110, Dpl::Ring3, 0);
+    set_idt(240, Xtimerint, 0b1110, Dpl::Ring0, 0);
 
-    // Set IDT.
-    let limit = (size_of::<GateDescriptor>() * IDT_LEN - 1)
-        .try_into()
-        .unwrap();
-    let addr = (&raw const IDT).cast();
-    let idtr = Idtr { limit, addr };
+    // Setup GDT, TSS and the other states. The IDT is shared with the secondary CPUs.
+    let tables = CpuTables::alloc();
 
-    unsafe {
-        asm!(
-            "lidt qword ptr [{v}]",
-            v = in(reg) &idtr,
-            options(preserves_flags, nostack)
-        )
-    };
+    unsafe { tables.load() };
 
-    // Set CS and SS for syscall and sysret instruction.
-    let star = Star::new()
-        .with_syscall_sel(GDT_KERNEL_CS)
-        .with_sysret_sel(GDT_USER_CS32)
-        .into_bits()
+    // TODO: Find a better way.
+    let len = unsafe { secondary_end.as_ptr().offset_from(secondary_start.as_ptr()) }
         .try_into()
         .unwrap();
 
-    unsafe { wrmsr(0xC0000081, star) };
+    Arc::new(ArchConfig {
+        cpu,
+        trap_rsp: tables.trap_rsp,
+        secondary_start: unsafe { core::slice::from_raw_parts(secondary_start.as_ptr(), len) },
+    })
+}
 
-    // Set entry point for syscall instruction.
-    unsafe { wrmsr(0xC0000082, syscall_entry64 as usize) };
-    unsafe { wrmsr(0xC0000083, syscall_entry32 as usize) };
+/// See `init_secondary` on the Orbis for a reference.
+///
+/// # Safety
+/// This function can be called only once per secondary CPU and must be called by its entry point
+/// before the CPU context is activated.
+pub unsafe fn setup_secondary_cpu(tables: &CpuTables) {
+    unsafe { tables.load() };
+}
 
-    // Set SFMASK for syscall.
-    let mask = Rflags::new()
-        .with_cf(true)
-        .with_tf(true)
-        .with_if(true) // https://wiki.osdev.org/SWAPGS#Complications,_Part_2
-        .with_df(true)
-        .with_nt(true)
-        .into_bits()
-        .try_into()
-        .unwrap();
+/// Returns physical address of the page table on the current CPU.
+pub fn page_table() -> usize {
+    let v: usize;
 
-    unsafe { wrmsr(0xC0000084, mask) };
+    unsafe { asm!("mov {v}, cr3", v = out(reg) v, options(nomem, preserves_flags, nostack)) };
 
-    // Switch EFER from bootloader to our own.
-    let efer = Efer::new()
-        .with_sce(true) // Enable syscall and sysret instruction.
-        .with_lme(true) // Long Mode Enable.
-        .with_lma(true) // Long Mode Active.
-        .into_bits()
-        .try_into()
-        .unwrap();
+    v & 0x000FFFFFFFFFF000
+}
 
-    unsafe { wrmsr(0xC0000080, efer) };
+/// Enable interrupts then stop the current CPU until the next interrupt.
+pub fn halt() {
+    // STI will delay the interrupt until HLT has been executed so we don't miss any interrupt.
+    unsafe { asm!("sti", "hlt", options(nomem, nostack)) };
+}
 
-    // TODO: Find a better way.
-    let len = unsafe { secondary_end.as_ptr().offset_from(secondary_start.as_ptr()) }
-        .try_into()
-        .unwrap();
+/// Disable interrupts on the current CPU. Returns `true` if interrupts was enabled.
+///
+/// See `intr_disable` on the PS4 for a reference.
+pub fn disable_interrupts() -> bool {
+    let flags: u64;
 
-    Arc::new(ArchConfig {
-        cpu,
-        trap_rsp: trap_rsp.as_mut_ptr() as usize,
-        secondary_start: unsafe { core::slice::from_raw_parts(secondary_start.as_ptr(), len) },
-    })
+    unsafe { asm!("pushfq", "pop {v}", "cli", v = out(reg) flags, options(nomem)) };
+
+    Rflags::from_bits(flags).r#if()
+}
+
+/// Enable interrupts on the current CPU if `enabled` is `true`.
+///
+/// See `intr_restore` on the PS4 for a reference.
+pub fn restore_interrupts(enabled: bool) {
+    if enabled {
+        enable_interrupts();
+    }
+}
+
+/// See `enable_intr` on the PS4 for a reference.
+pub fn enable_interrupts() {
+    unsafe { asm!("sti", options(nomem, nostack)) };
 }
 
 pub unsafe fn wrmsr(reg: u32, val: usize) {
@@ -249,12 +190,155 @@ unsafe fn push_tss<const L: usize>(
     SegmentSelector::new().with_si(si.try_into().unwrap())
 }
 
+/// Per-CPU tables that need to be allocated before the CPU context is activated.
+///
+/// The secondary CPU cannot use the heap before its context has been activated so another CPU need
+/// to allocate this for it.
+pub struct CpuTables {
+    gdt: &'static [SegmentDescriptor],
+    tss: SegmentSelector,
+    trap_rsp: usize,
+}
+
+impl CpuTables {
+    pub fn alloc() -> Self {
+        let mut gdt = vec![
+            // Null descriptor.
+            SegmentDescriptor::new(),
+            // 32-bit GS for user.
+            SegmentDescriptor::new(),
+            // 32-bit FS for user.
+            SegmentDescriptor::new(),
+            // CS for kernel.
+            SegmentDescriptor::new()
+                .with_ty(0b1000) // This required somehow although the docs said it is ignored.
+                .with_s(true) // Same here.
+                .with_p(true)
+                .with_l(true), // 64-bit mode.
+            // DS for kernel.
+            SegmentDescriptor::new()
+                .with_ty(0b0010) // This required somehow although the docs said it is ignored.
+                .with_s(true) // Same here.
+                .with_p(true),
+            // 32-bit CS for user.
+            SegmentDescriptor::new(),
+            // DS for user.
+            SegmentDescriptor::new(),
+            // 64-bit CS for user.
+            SegmentDescriptor::new(),
+        ];
+
+        // Setup Task State Segment (TSS).
+        let trap_rsp = Box::new([0u8; 1024 * 128]);
+        let trap_rsp = Box::leak(trap_rsp);
+        let tss = unsafe { push_tss(&mut gdt, trap_rsp) };
+
+        gdt.shrink_to_fit();
+
+        Self {
+            gdt: gdt.leak(),
+            tss,
+            trap_rsp: trap_rsp.as_mut_ptr() as usize,
+        }
+    }
+
+    pub fn trap_rsp(&self) -> usize {
+        self.trap_rsp
+    }
+
+    /// # Safety
+    /// This can be called only once per CPU.
+    unsafe fn load(&self) {
+        // Switch GDT from bootloader GDT to our own.
+        let limit = (size_of_val(self.gdt) - 1).try_into().unwrap();
+
+        unsafe {
+            set_gdtr(
+                &Gdtr {
+                    limit,
+                    addr: self.gdt.as_ptr(),
+                },
+                GDT_KERNEL_CS,
+                GDT_KERNEL_DS,
+            )
+        };
+
+        // Set Task Register (TR).
+        unsafe {
+            asm!(
+                "ltr {v:x}",
+                v = in(reg) self.tss.into_bits(),
+                options(preserves_flags, nostack)
+            )
+        };
+
+        // Set IDT.
+        let limit = (size_of::<GateDescriptor>() * IDT_LEN - 1)
+            .try_into()
+            .unwrap();
+        let addr = (&raw const IDT).cast();
+        let idtr = Idtr { limit, addr };
+
+        unsafe {
+            asm!(
+                "lidt qword ptr [{v}]",
+                v = in(reg) &idtr,
+                options(preserves_flags, nostack)
+            )
+        };
+
+        // Set CS and SS for syscall and sysret instruction.
+        let star = Star::new()
+            .with_syscall_sel(GDT_KERNEL_CS)
+            .with_sysret_sel(GDT_USER_CS32)
+            .into_bits()
+            .try_into()
+            .unwrap();
+
+        unsafe { wrmsr(0xC0000081, star) };
+
+        // Set entry point for syscall instruction.
+        unsafe { wrmsr(0xC0000082, syscall_entry64 as usize) };
+        unsafe { wrmsr(0xC0000083, syscall_entry32 as usize) };
+
+        // Set SFMASK for syscall.
+        let mask = Rflags::new()
+            .with_cf(true)
+            .with_tf(true)
+            .with_if(true) // https://wiki.osdev.org/SWAPGS#Complications,_Part_2
+            .with_df(true)
+            .with_nt(true)
+            .into_bits()
+            .try_into()
+            .unwrap();
+
+        unsafe { wrmsr(0xC0000084, mask) };
+
+        // Switch EFER from bootloader to our own.
+        let efer = Efer::new()
+            .with_sce(true) // Enable syscall and sysret instruction.
+            .with_lme(true) // Long Mode Enable.
+            .with_lma(true) // Long Mode Active.
+            .into_bits()
+            .try_into()
+            .unwrap();
+
+        unsafe { wrmsr(0xC0000080, efer) };
+    }
+}
+
+// See idt0 on the PS4 for a reference.
+const IDT_LEN: usize = 256;
+static mut IDT: [GateDescriptor; IDT_LEN] = unsafe { zeroed() };
+
 unsafe extern "C" {
     safe static secondary_start: [u8; 0];
     safe static secondary_end: [u8; 0];
 
     fn set_gdtr(v: &Gdtr, code: SegmentSelector, data: SegmentSelector);
+    fn Xdbg() -> !;
     fn Xbpt() -> !;
+    fn Xtimerint() -> !;
     fn syscall_entry64() -> !;
     fn syscall_entry32() -> !;
 }
@@ -274,13 +358,107 @@ global_asm!(
     "retfq" // Set CS then return.
 );
 
+// See Xdbg on the PS4 for a reference.
+global_asm!(
+    "Xdbg:",
+    "sub rsp, {rip}",
+    "mov dword ptr [rsp+{num}], {dbg}",
+    "jmp alltraps",
+    rip = const offset_of!(TrapFrame, rip),
+    num = const offset_of!(TrapFrame, num),
+    dbg = const TrapNo::Debug as u32
+);
+
 // See Xbpt on the PS4 for a reference.
 global_asm!(
-    "Xbpt:", // TODO: Check if coming from user-space.
-    "sub rsp, 0x80", // TODO: Use const from Rust 1.82.
-    "mov dword ptr [rsp+0x78], 3", // TODO: Use const from Rust 1.82.
+    "Xbpt:",
+    "sub rsp, {rip}",
+    "mov dword ptr [rsp+{num}], {bpt}",
+    "jmp alltraps",
+    rip = const offset_of!(TrapFrame, rip),
+    num = const offset_of!(TrapFrame, num),
+    bpt = const TrapNo::Breakpoint as u32
+);
+
+// See Xtimerint on the PS4 for a reference.
+global_asm!(
+    "Xtimerint:",
+    "sub rsp, {rip}",
+    "mov dword ptr [rsp+{num}], {timer}",
+    "jmp alltraps",
+    rip = const offset_of!(TrapFrame, rip),
+    num = const offset_of!(TrapFrame, num),
+    timer = const TrapNo::Timer as u32
+);
+
+// See alltraps on the PS4 for a reference.
+global_asm!(
+    "alltraps:", // TODO: Check if coming from user-space.
+    "mov qword ptr [rsp+{addr}], 0",
+    "mov qword ptr [rsp+{err}], 0",
+    "mov word ptr [rsp+{fs}], fs",
+    "mov word ptr [rsp+{gs}], gs",
+    "mov word ptr [rsp+{es}], es",
+    "mov word ptr [rsp+{ds}], ds",
+    "mov dword ptr [rsp+{flags}], {hassegs}",
+    "mov [rsp+{rdi}], rdi",
+    "mov [rsp+{rsi}], rsi",
+    "mov [rsp+{rdx}], rdx",
+    "mov [rsp+{rcx}], rcx",
+    "mov [rsp+{r8}], r8",
+    "mov [rsp+{r9}], r9",
+    "mov [rsp+{rax}], rax",
+    "mov [rsp+{rbx}], rbx",
+    "mov [rsp+{rbp}], rbp",
+    "mov [rsp+{r10}], r10",
+    "mov [rsp+{r11}], r11",
+    "mov [rsp+{r12}], r12",
+    "mov [rsp+{r13}], r13",
+    "mov [rsp+{r14}], r14",
+    "mov [rsp+{r15}], r15",
     "mov rdi, rsp",
     "call {f}",
+    "mov rdi, [rsp+{rdi}]",
+    "mov rsi, [rsp+{rsi}]",
+    "mov rdx, [rsp+{rdx}]",
+    "mov rcx, [rsp+{rcx}]",
+    "mov r8, [rsp+{r8}]",
+    "mov r9, [rsp+{r9}]",
+    "mov rax, [rsp+{rax}]",
+    "mov rbx, [rsp+{rbx}]",
+    "mov rbp, [rsp+{rbp}]",
+    "mov r10, [rsp+{r10}]",
+    "mov r11, [rsp+{r11}]",
+    "mov r12, [rsp+{r12}]",
+    "mov r13, [rsp+{r13}]",
+    "mov r14, [rsp+{r14}]",
+    "mov r15, [rsp+{r15}]",
+    "add rsp, {rip}",
+    "iretq",
+    rdi = const offset_of!(TrapFrame, rdi),
+    rsi = const offset_of!(TrapFrame, rsi),
+    rdx = const offset_of!(TrapFrame, rdx),
+    rcx = const offset_of!(TrapFrame, rcx),
+    r8 = const offset_of!(TrapFrame, r8),
+    r9 = const offset_of!(TrapFrame, r9),
+    rax = const offset_of!(TrapFrame, rax),
+    rbx = const offset_of!(TrapFrame, rbx),
+    rbp = const offset_of!(TrapFrame, rbp),
+    r10 = const offset_of!(TrapFrame, r10),
+    r11 = const offset_of!(TrapFrame, r11),
+    r12 = const offset_of!(TrapFrame, r12),
+    r13 = const offset_of!(TrapFrame, r13),
+    r14 = const offset_of!(TrapFrame, r14),
+    r15 = const offset_of!(TrapFrame, r15),
+    addr = const offset_of!(TrapFrame, addr),
+    err = const offset_of!(TrapFrame, err),
+    fs = const offset_of!(TrapFrame, fs),
+    gs = const offset_of!(TrapFrame, gs),
+    es = const offset_of!(TrapFrame, es),
+    ds = const offset_of!(TrapFrame, ds),
+    flags = const offset_of!(TrapFrame, flags),
+    hassegs = const TF_HASSEGS,
+    rip = const offset_of!(TrapFrame, rip),
     f = sym interrupt_handler
 );
 
@@ -290,10 +468,67 @@ global_asm!(
     "swapgs",
     "mov gs:[{user_rsp}], rsp", // Save user RSP.
     "mov rsp, gs:[{trap_rsp}]",
+    "sub rsp, {frame}",
+    "mov [rsp+{rdi}], rdi",
+    "mov [rsp+{rsi}], rsi",
+    "mov [rsp+{rdx}], rdx",
+    "mov [rsp+{rcx}], r10", // The fourth argument is in R10 since RCX was used by syscall.
+    "mov [rsp+{r8}], r8",
+    "mov [rsp+{r9}], r9",
+    "mov [rsp+{rax}], rax",
+    "mov [rsp+{rbx}], rbx",
+    "mov [rsp+{rbp}], rbp",
+    "mov [rsp+{r10}], r10",
+    "mov [rsp+{r12}], r12",
+    "mov [rsp+{r13}], r13",
+    "mov [rsp+{r14}], r14",
+    "mov [rsp+{r15}], r15",
+    "mov [rsp+{rip}], rcx",
+    "mov [rsp+{rflags}], r11",
+    "mov r11, gs:[{user_rsp}]",
+    "mov [rsp+{rsp}], r11",
+    "mov qword ptr [rsp+{err}], 2", // Size of syscall instruction.
+    "mov rdi, rsp",
     "call {handler}",
-    "ud2",
+    "mov rdi, [rsp+{rdi}]",
+    "mov rsi, [rsp+{rsi}]",
+    "mov rdx, [rsp+{rdx}]",
+    "mov r8, [rsp+{r8}]",
+    "mov r9, [rsp+{r9}]",
+    "mov rax, [rsp+{rax}]",
+    "mov rbx, [rsp+{rbx}]",
+    "mov rbp, [rsp+{rbp}]",
+    "mov r10, [rsp+{r10}]",
+    "mov r12, [rsp+{r12}]",
+    "mov r13, [rsp+{r13}]",
+    "mov r14, [rsp+{r14}]",
+    "mov r15, [rsp+{r15}]",
+    "mov rcx, [rsp+{rip}]",
+    "mov r11, [rsp+{rflags}]",
+    "mov rsp, [rsp+{rsp}]",
+    "swapgs",
+    "sysretq",
     user_rsp = const current_user_rsp_offset(),
     trap_rsp = const current_trap_rsp_offset(),
+    frame = const size_of::<TrapFrame>(),
+    rdi = const offset_of!(TrapFrame, rdi),
+    rsi = const offset_of!(TrapFrame, rsi),
+    rdx = const offset_of!(TrapFrame, rdx),
+    rcx = const offset_of!(TrapFrame, rcx),
+    r8 = const offset_of!(TrapFrame, r8),
+    r9 = const offset_of!(TrapFrame, r9),
+    rax = const offset_of!(TrapFrame, rax),
+    rbx = const offset_of!(TrapFrame, rbx),
+    rbp = const offset_of!(TrapFrame, rbp),
+    r10 = const offset_of!(TrapFrame, r10),
+    r12 = const offset_of!(TrapFrame, r12),
+    r13 = const offset_of!(TrapFrame, r13),
+    r14 = const offset_of!(TrapFrame, r14),
+    r15 = const offset_of!(TrapFrame, r15),
+    err = const offset_of!(TrapFrame, err),
+    rip = const offset_of!(TrapFrame, rip),
+    rflags = const offset_of!(TrapFrame, rflags),
+    rsp = const offset_of!(TrapFrame, rsp),
     handler = sym syscall_handler
 );
 
@@ -10,6 +10,7 @@ pub trait HypervisorExt: Hypervisor {}
 pub trait CpuStates {
     type Err: Error + Send + Sync + 'static;
 
+    fn get_pstate(&mut self) -> Result<Pstate, Self::Err>;
     fn set_pstate(&mut self, v: Pstate);
     fn set_sctlr(&mut self, v: Sctlr);
     fn set_mair_el1(&mut self, attrs: u64);
@@ -19,15 +20,41 @@ pub trait CpuStates {
     /// If `baddr` has non-zero on bit 0 or 48:64.
     fn set_ttbr0_el1(&mut self, baddr: usize);
 
+    fn get_ttbr1_el1(&mut self) -> Result<usize, Self::Err>;
+
     /// # Panics
     /// If `baddr` has non-zero on bit 0 or 48:64.
     fn set_ttbr1_el1(&mut self, baddr: usize);
 
+    fn get_far_el1(&mut self) -> Result<usize, Self::Err>;
+    fn get_sp_el1(&mut self) -> Result<usize, Self::Err>;
     fn set_sp_el1(&mut self, v: usize);
+    fn get_pc(&mut self) -> Result<usize, Self::Err>;
     fn set_pc(&mut self, v: usize);
     fn set_x0(&mut self, v: usize);
     fn set_x1(&mut self, v: usize);
     fn set_x2(&mut self, v: usize);
+
+    /// # Panics
+    /// If `n` is larger than 30.
+    fn get_x(&mut self, n: usize) -> Result<usize, Self::Err>;
+
+    /// # Panics
+    /// If `n` is larger than 30.
+    fn set_x(&mut self, n: usize, v: usize);
+
+    /// # Panics
+    /// If `n` is larger than 31.
+    fn get_v(&mut self, n: usize) -> Result<u128, Self::Err>;
+
+    /// # Panics
+    /// If `n` is larger than 31.
+    fn set_v(&mut self, n: usize, v: u128);
+
+    fn get_fpsr(&mut self) -> Result<u32, Self::Err>;
+    fn set_fpsr(&mut self, v: u32);
+    fn get_fpcr(&mut self) -> Result<u32, Self::Err>;
+    fn set_fpcr(&mut self, v: u32);
 }
 
 /// Features available on a PE.
@@ -41,6 +68,20 @@ pub struct CpuFeats {
     pub mmfr2: Mmfr2,
 }
 
+/// Hardware debugging states of a PE.
+#[derive(Debug, Clone, Default)]
+pub struct HwDebug {
+    pub step: bool,
+    /// Values of `DBGBVR<n>_EL1`.
+    pub bvr: [usize; 16],
+    /// Values of `DBGBCR<n>_EL1`.
+    pub bcr: [u64; 16],
+    /// Values of `DBGWVR<n>_EL1`.
+    pub wvr: [usize; 16],
+    /// Values of `DBGWCR<n>_EL1`.
+    pub wcr: [u64; 16],
+}
+
 /// Represents a value of `PSTATE`.
 ///
 /// This has the same structure as `SPSR_EL1` when exception taken from AArch64 state.
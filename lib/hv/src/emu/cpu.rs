@@ -0,0 +1,485 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+use super::Emu;
+use super::exec::{Fault, IF};
+use super::states::{EmuStates, Regs};
+use crate::{Cpu, CpuDebug, CpuExit, CpuIo, CpuKicker, CpuRun, DebugEvent, HwDebug, IoBuf};
+use std::convert::Infallible;
+use std::num::NonZero;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, MutexGuard};
+use thiserror::Error;
+
+/// Implementation of [`Cpu`] for the emulator.
+pub struct EmuCpu<'a> {
+    id: usize,
+    pub(super) emu: &'a Emu,
+    pub(super) regs: MutexGuard<'a, Regs>,
+    pub(super) exit: Option<ExitReason>,
+    pub(super) mmio: Mmio,
+    debug: HwDebug,
+    watch: Option<DebugEvent>,
+    skip_break: Option<u64>,
+    kicked: Arc<AtomicBool>,
+    window: bool,
+}
+
+impl<'a> EmuCpu<'a> {
+    pub(super) fn new(id: usize, emu: &'a Emu, regs: MutexGuard<'a, Regs>) -> Self {
+        Self {
+            id,
+            emu,
+            regs,
+            exit: None,
+            mmio: Mmio::default(),
+            debug: HwDebug::default(),
+            watch: None,
+            skip_break: None,
+            kicked: Arc::default(),
+            window: false,
+        }
+    }
+
+    /// Record a watchpoint hit if the data access match any enabled DRn.
+    pub(super) fn watch(&mut self, addr: u64, len: usize, write: bool) {
+        let dr7 = self.debug.dr7;
+        let end = addr.wrapping_add(len as u64);
+
+        for (i, &base) in self.debug.addrs.iter().enumerate() {
+            if dr7 & (0b11 << (i * 2)) == 0 {
+                continue;
+            }
+
+            let ev = match (dr7 >> (16 + i * 4)) & 0b11 {
+                0b01 if write => DebugEvent::WriteWatch(base),
+                0b11 => DebugEvent::AccessWatch(base),
+                _ => continue,
+            };
+
+            let size = match (dr7 >> (18 + i * 4)) & 0b11 {
+                0b00 => 1,
+                0b01 => 2,
+                0b10 => 8,
+                _ => 4,
+            };
+
+            if addr < base as u64 + size && (base as u64) < end {
+                self.watch.get_or_insert(ev);
+            }
+        }
+    }
+
+    /// Returns `true` if any enabled DRn is an instruction breakpoint at RIP.
+    fn hw_break(&mut self) -> bool {
+        let dr7 = self.debug.dr7;
+        let rip = self.regs.rip;
+
+        // Don't break on the same instruction again when resuming from it.
+        if self.skip_break.take() == Some(rip) {
+            return false;
+        }
+
+        for (i, &addr) in self.debug.addrs.iter().enumerate() {
+            let enabled = dr7 & (0b11 << (i * 2)) != 0;
+
+            if enabled && (dr7 >> (16 + i * 4)) & 0b11 == 0 && addr as u64 == rip {
+                self.skip_break = Some(rip);
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Walks the page tables pointed by CR3. The protection is not checked if `access` is [`None`].
+    ///
+    /// See Page Translation and Protection section on AMD64 Architecture Programmer's Manual Volume
+    /// 2 for how paging work in long-mode.
+    pub(super) fn walk(&self, vaddr: u64, access: Option<Access>) -> Result<u64, TranslateError> {
+        // Check if paging enabled.
+        if self.regs.cr0 & 0x80000000 == 0 {
+            return Ok(vaddr);
+        }
+
+        let mut table = self.regs.cr3 & 0x000FFFFFFFFFF000;
+        let mut user = true;
+        let mut writable = true;
+        let mut nx = false;
+
+        for level in (0..4).rev() {
+            let shift = 12 + level * 9;
+            let index = (vaddr >> shift) & 0x1FF;
+            let addr = table + index * 8;
+            let entry = self
+                .emu
+                .ram
+                .lock(addr as usize, NonZero::new(8).unwrap())
+                .map(|m| unsafe { m.as_ptr().cast::<u64>().read_volatile() })
+                .ok_or(TranslateError::InvalidTable(addr as usize))?;
+
+            // Check Present (P) Bit.
+            if entry & 1 == 0 {
+                return Err(TranslateError::NotPresent(vaddr as usize));
+            }
+
+            // The protection is the most restrictive one from all levels.
+            writable &= entry & 0x2 != 0;
+            user &= entry & 0x4 != 0;
+            nx |= entry & (1 << 63) != 0;
+
+            // Check Page Size (PS) Bit on PDPE and PDE.
+            let base = entry & 0x000FFFFFFFFFF000;
+
+            if level == 0 || (level < 3 && entry & 0x80 != 0) {
+                let mask = (1u64 << shift) - 1;
+
+                if access.is_some_and(|a| !self.check_access(a, user, writable, nx)) {
+                    return Err(TranslateError::Protection(vaddr as usize));
+                }
+
+                return Ok((base & !mask) | (vaddr & mask));
+            }
+
+            table = base;
+        }
+
+        unreachable!()
+    }
+
+    /// Returns `true` if `access` is allowed on the page with the specified protection.
+    fn check_access(&self, access: Access, user: bool, writable: bool, nx: bool) -> bool {
+        // User-mode access require U/S bit on all levels.
+        if access.user && !user {
+            return false;
+        }
+
+        // Supervisor-mode write to read-only page is allowed if CR0.WP is clear.
+        if access.write && !writable && (access.user || self.regs.cr0 & 0x10000 != 0) {
+            return false;
+        }
+
+        // NX bit is only effective if EFER.NXE is set.
+        !(access.fetch && nx && self.regs.efer & 0x800 != 0)
+    }
+}
+
+impl<'a> Cpu for EmuCpu<'a> {
+    type States<'b>
+        = EmuStates<'b>
+    where
+        Self: 'b;
+    type GetStatesErr = Infallible;
+    type Exit<'b>
+        = EmuExit<'b, 'a>
+    where
+        Self: 'b;
+    type TranslateErr = TranslateError;
+    type SnapshotErr = SnapshotError;
+    type DebugErr = Infallible;
+    type InterruptErr = RunError;
+    type Kicker = EmuKicker;
+
+    fn id(&self) -> usize {
+        self.id
+    }
+
+    fn states(&mut self) -> Result<Self::States<'_>, Self::GetStatesErr> {
+        Ok(EmuStates::new(&mut self.regs))
+    }
+
+    fn translate(&self, vaddr: usize) -> Result<usize, Self::TranslateErr> {
+        self.walk(vaddr as u64, None).map(|v| v as usize)
+    }
+
+    fn snapshot(&mut self) -> Result<Vec<u8>, Self::SnapshotErr> {
+        Ok(self.regs.save())
+    }
+
+    fn restore(&mut self, data: &[u8]) -> Result<(), Self::SnapshotErr> {
+        *self.regs = Regs::load(data).ok_or(SnapshotError::InvalidData)?;
+
+        Ok(())
+    }
+
+    fn set_debug(&mut self, v: &HwDebug) -> Result<(), Self::DebugErr> {
+        if self.emu.debug {
+            self.debug = v.clone();
+        }
+
+        Ok(())
+    }
+
+    fn kicker(&self) -> Self::Kicker {
+        EmuKicker(self.kicked.clone())
+    }
+
+    fn interruptible(&self) -> bool {
+        self.regs.rflags & IF != 0
+    }
+
+    fn inject(&mut self, vector: u8) -> Result<(), Self::InterruptErr> {
+        self.deliver(vector, None)
+    }
+
+    fn set_interrupt_window(&mut self, v: bool) {
+        self.window = v;
+    }
+}
+
+impl CpuRun for EmuCpu<'_> {
+    type RunErr = RunError;
+
+    fn run(&mut self) -> Result<Self::Exit<'_>, Self::RunErr> {
+        self.exit = None;
+
+        loop {
+            if self.kicked.swap(false, Ordering::Relaxed) || (self.window && self.interruptible()) {
+                self.exit = Some(ExitReason::Interrupt);
+                break Ok(EmuExit(self));
+            }
+
+            if self.hw_break() {
+                self.exit = Some(ExitReason::Debug(DebugEvent::HwBreak));
+                break Ok(EmuExit(self));
+            }
+
+            self.watch = None;
+
+            match self.step() {
+                Ok(()) if self.exit.is_none() => {
+                    // Data breakpoints are trap so we report it after the instruction completed.
+                    if let Some(ev) = self.watch.take() {
+                        self.exit = Some(ExitReason::Debug(ev));
+                    } else if self.debug.step {
+                        self.exit = Some(ExitReason::Debug(DebugEvent::Step));
+                    }
+                }
+                Ok(()) | Err(Fault::Stop) => (),
+                Err(Fault::Exception(v, e)) => self.deliver(v, e)?,
+                Err(Fault::Error(e)) => return Err(e),
+            }
+
+            if self.exit.is_some() {
+                break Ok(EmuExit(self));
+            }
+        }
+    }
+}
+
+/// Implementation of [`Cpu::Exit`] for the emulator.
+pub struct EmuExit<'a, 'b>(&'a mut EmuCpu<'b>);
+
+impl<'a, 'b> CpuExit for EmuExit<'a, 'b> {
+    type Cpu = EmuCpu<'b>;
+    type Io = EmuIo<'a, 'b>;
+    type Debug = EmuDebug<'a, 'b>;
+
+    fn cpu(&mut self) -> &mut Self::Cpu {
+        self.0
+    }
+
+    fn into_hlt(self) -> Result<(), Self> {
+        match self.0.exit {
+            Some(ExitReason::Hlt) => Ok(()),
+            _ => Err(self),
+        }
+    }
+
+    fn into_io(self) -> Result<Self::Io, Self> {
+        match self.0.exit {
+            Some(ExitReason::Io) => Ok(EmuIo(self.0)),
+            _ => Err(self),
+        }
+    }
+
+    fn into_debug(self) -> Result<Self::Debug, Self> {
+        match self.0.exit {
+            Some(ExitReason::Debug(_)) => Ok(EmuDebug(self.0)),
+            _ => Err(self),
+        }
+    }
+
+    fn into_interrupt(self) -> Result<(), Self> {
+        match self.0.exit {
+            Some(ExitReason::Interrupt) => Ok(()),
+            _ => Err(self),
+        }
+    }
+}
+
+/// Implementation of [`CpuIo`] for the emulator.
+pub struct EmuIo<'a, 'b>(&'a mut EmuCpu<'b>);
+
+impl<'b> CpuIo for EmuIo<'_, 'b> {
+    type Cpu = EmuCpu<'b>;
+
+    fn addr(&self) -> usize {
+        self.0.mmio.addr
+    }
+
+    fn buffer(&mut self) -> IoBuf<'_> {
+        let io = &mut self.0.mmio;
+        let buf = &mut io.data[..io.len];
+
+        if io.write {
+            IoBuf::Write(buf)
+        } else {
+            // The instruction will be re-executed on the next run with this data.
+            io.ready = true;
+            IoBuf::Read(buf)
+        }
+    }
+
+    fn cpu(&mut self) -> &mut Self::Cpu {
+        self.0
+    }
+}
+
+/// Implementation of [`CpuDebug`] for the emulator.
+pub struct EmuDebug<'a, 'b>(&'a mut EmuCpu<'b>);
+
+impl<'b> CpuDebug for EmuDebug<'_, 'b> {
+    type Cpu = EmuCpu<'b>;
+
+    fn reason(&mut self) -> DebugEvent {
+        match self.0.exit {
+            Some(ExitReason::Debug(v)) => v,
+            _ => unreachable!(),
+        }
+    }
+
+    fn cpu(&mut self) -> &mut Self::Cpu {
+        self.0
+    }
+}
+
+/// Implementation of [`Cpu::Kicker`] for the emulator.
+pub struct EmuKicker(Arc<AtomicBool>);
+
+impl CpuKicker for EmuKicker {
+    fn kick(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Reason of the latest exit.
+#[derive(Clone, Copy)]
+pub(super) enum ExitReason {
+    Hlt,
+    Io,
+    Debug(DebugEvent),
+    Interrupt,
+}
+
+/// Type of memory access to check with the page protection.
+#[derive(Clone, Copy)]
+pub(super) struct Access {
+    pub user: bool,
+    pub write: bool,
+    pub fetch: bool,
+}
+
+/// Pending memory-mapped I/O.
+#[derive(Default)]
+pub(super) struct Mmio {
+    pub addr: usize,
+    pub data: [u8; 16],
+    pub len: usize,
+    pub write: bool,
+    pub ready: bool,
+}
+
+/// Implementation of [`Cpu::TranslateErr`] for the emulator.
+#[derive(Debug, Error)]
+pub enum TranslateError {
+    #[error("page for {0:#x} is not present")]
+    NotPresent(usize),
+
+    #[error("access to {0:#x} violate the page protection")]
+    Protection(usize),
+
+    #[error("page table at {0:#x} is not allocated")]
+    InvalidTable(usize),
+}
+
+/// Implementation of [`Cpu::SnapshotErr`] for the emulator.
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("invalid snapshot data")]
+    InvalidData,
+}
+
+/// Implementation of [`CpuRun::RunErr`] for the emulator.
+#[derive(Debug, Error)]
+pub enum RunError {
+    #[error("unknown instruction {1:02x?} at {0:#x}")]
+    UnknownInstruction(usize, Vec<u8>),
+
+    #[error("physical address {0:#x} is not allocated")]
+    NotAllocated(usize),
+
+    #[error("attempt to fetch an instruction from memory-mapped I/O at {0:#x}")]
+    FetchMmio(usize),
+
+    #[error("memory-mapped I/O at {0:#x} is larger than supported")]
+    MmioTooLarge(usize),
+
+    #[error("multiple memory-mapped I/O on a single instruction at {0:#x}")]
+    MultipleMmio(usize),
+
+    #[error("unhandled exception #{0} at {1:#x}")]
+    UnhandledException(u8, usize),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Hypervisor;
+
+    #[test]
+    fn walk() {
+        let emu = Emu::new_test();
+        let mut cpu = emu.create_cpu(0).unwrap();
+
+        // Map 0x5000 as supervisor, 0x6000 as user read-only and 0x7000 as user no-execute.
+        emu.map(0x5000, 0x20000 | 0x3);
+        emu.map(0x6000, 0x21000 | 0x5);
+        emu.map(0x7000, 0x22000 | 0x7 | 1 << 63);
+
+        cpu.regs.cr0 |= 0x80000000;
+        cpu.regs.cr3 = Emu::TEST_CR3;
+
+        let check = |cpu: &EmuCpu, vaddr, user, write, fetch| {
+            let access = Access { user, write, fetch };
+
+            cpu.walk(vaddr, Some(access))
+        };
+
+        assert_eq!(cpu.walk(0x5123, None).unwrap(), 0x20123);
+        assert!(check(&cpu, 0x5000, false, true, false).is_ok());
+        assert!(matches!(
+            check(&cpu, 0x5000, true, false, false),
+            Err(TranslateError::Protection(0x5000))
+        ));
+
+        // Supervisor write to read-only page is allowed only if CR0.WP is clear.
+        assert!(check(&cpu, 0x6000, true, false, false).is_ok());
+        assert!(check(&cpu, 0x6000, true, true, false).is_err());
+        assert!(check(&cpu, 0x6000, false, true, false).is_ok());
+
+        cpu.regs.cr0 |= 0x10000;
+
+        assert!(check(&cpu, 0x6000, false, true, false).is_err());
+
+        // NX is only effective if EFER.NXE is set.
+        assert!(check(&cpu, 0x7000, true, false, true).is_ok());
+
+        cpu.regs.efer |= 0x800;
+
+        assert!(check(&cpu, 0x7000, true, false, true).is_err());
+        assert!(check(&cpu, 0x7000, true, true, false).is_ok());
+        assert!(matches!(
+            cpu.walk(0x8000, None),
+            Err(TranslateError::NotPresent(0x8000))
+        ));
+    }
+}
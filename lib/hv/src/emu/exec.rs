@@ -0,0 +1,2469 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+use super::cpu::{Access, EmuCpu, ExitReason, Mmio, RunError, TranslateError};
+use crate::DebugEvent;
+use std::num::NonZero;
+
+const CF: u64 = 1 << 0;
+const PF: u64 = 1 << 2;
+const AF: u64 = 1 << 4;
+const ZF: u64 = 1 << 6;
+const SF: u64 = 1 << 7;
+const TF: u64 = 1 << 8;
+pub(super) const IF: u64 = 1 << 9;
+const DF: u64 = 1 << 10;
+const OF: u64 = 1 << 11;
+const NT: u64 = 1 << 14;
+const RF: u64 = 1 << 16;
+
+/// Bits in RFLAGS that can be modified by POPF.
+const RFLAGS_WRITABLE: u64 = 0x3F7FD5;
+
+const RAX: usize = 0;
+const RCX: usize = 1;
+const RDX: usize = 2;
+const RSP: usize = 4;
+const RBP: usize = 5;
+const RSI: usize = 6;
+const RDI: usize = 7;
+
+const DE: u8 = 0; // Divide-by-zero.
+const BP: u8 = 3; // Breakpoint.
+const UD: u8 = 6; // Invalid opcode.
+const GP: u8 = 13; // General protection.
+const PF_VEC: u8 = 14; // Page fault.
+
+impl EmuCpu<'_> {
+    /// Execute a single instruction.
+    pub(super) fn step(&mut self) -> Result<(), Fault> {
+        // Fetch.
+        let rip = self.regs.rip;
+        let mut buf = [0u8; 15];
+        let (len, fault) = self.fetch(rip, &mut buf);
+        let mut d = Decoder {
+            buf: &buf[..len],
+            pos: 0,
+            fault,
+        };
+
+        // Parse prefixes.
+        let mut p = Prefix::default();
+
+        loop {
+            match d.peek()? {
+                0x66 => p.opsize = true,
+                0x67 => p.addr32 = true,
+                0xF0 => p.lock = true,
+                0xF2 | 0xF3 => p.rep = d.peek()?,
+                0x26 | 0x2E | 0x36 | 0x3E => (),
+                0x64 => p.seg = Some(SegOverride::Fs),
+                0x65 => p.seg = Some(SegOverride::Gs),
+                _ => break,
+            }
+
+            d.pos += 1;
+        }
+
+        if let 0x40..=0x4F = d.peek()? {
+            p.rex = d.u8()?;
+        }
+
+        // Execute.
+        let op = d.u8()?;
+        let r = if op == 0x0F {
+            let op = d.u8()?;
+            self.exec_0f(op, &mut d, &p)
+        } else {
+            self.exec(op, &mut d, &p)
+        };
+
+        match r {
+            Ok(Some(next)) => {
+                self.regs.rip = next;
+                Ok(())
+            }
+            Ok(None) => Ok(()),
+            Err(Fault::Error(RunError::UnknownInstruction(_, _))) => {
+                let bytes = buf[..d.pos.max(1)].to_vec();
+
+                Err(Fault::Error(RunError::UnknownInstruction(
+                    rip as usize,
+                    bytes,
+                )))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Deliver an exception or interrupt through IDT.
+    pub(super) fn deliver(&mut self, vector: u8, code: Option<u64>) -> Result<(), RunError> {
+        let rip = self.regs.rip;
+        let fail = || RunError::UnhandledException(vector, rip as usize);
+
+        // Load gate descriptor.
+        let (base, limit) = self.regs.idtr;
+        let off = u64::from(vector) * 16;
+
+        if off + 15 > limit.into() {
+            return Err(fail());
+        }
+
+        let mut gate = [0u8; 16];
+
+        self.read_sys(base + off, &mut gate).map_err(|_| fail())?;
+
+        if gate[5] & 0x80 == 0 {
+            return Err(fail());
+        }
+
+        let target = u64::from(u16::from_le_bytes([gate[0], gate[1]]))
+            | u64::from(u16::from_le_bytes([gate[6], gate[7]])) << 16
+            | u64::from(u32::from_le_bytes(gate[8..12].try_into().unwrap())) << 32;
+        let selector = u16::from_le_bytes([gate[2], gate[3]]);
+        let ist = gate[4] & 7;
+        let ty = gate[5] & 0xF;
+
+        // Get privilege level of the handler.
+        let cpl = self.cpl();
+        let dpl = (self.descriptor(selector).map_err(|_| fail())?[5] >> 5) & 3;
+
+        // Get the new stack. The stack is switched to RSPn on the TSS when the privilege level is
+        // changed.
+        let rsp = if ist != 0 {
+            self.read_tss(0x24 + u64::from(ist - 1) * 8)
+                .map_err(|_| fail())?
+        } else if dpl < cpl {
+            self.read_tss(0x04 + u64::from(dpl) * 8)
+                .map_err(|_| fail())?
+        } else {
+            self.regs.gpr[RSP]
+        };
+
+        // Push interrupt stack frame.
+        let frame = [
+            code,
+            Some(rip),
+            Some(self.regs.cs.selector.into()),
+            Some(self.regs.rflags),
+            Some(self.regs.gpr[RSP]),
+            Some(self.regs.ss.selector.into()),
+        ];
+        let mut rsp = rsp & !0xF;
+
+        for v in frame.into_iter().rev().flatten() {
+            rsp = rsp.wrapping_sub(8);
+            self.write_mem(rsp, &v.to_le_bytes(), dpl == 3)
+                .map_err(|_| fail())?;
+        }
+
+        // Jump to the handler. SS is set to null selector on privilege level change.
+        self.regs.gpr[RSP] = rsp;
+        self.regs.cs.selector = (selector & !3) | u16::from(dpl);
+
+        if dpl < cpl {
+            self.regs.ss.selector = dpl.into();
+        }
+
+        self.regs.rflags &= !(TF | NT | RF);
+
+        if ty == 0xE {
+            self.regs.rflags &= !IF;
+        }
+
+        self.regs.rip = target;
+
+        Ok(())
+    }
+
+    fn exec(&mut self, op: u8, d: &mut Decoder, p: &Prefix) -> Result<Option<u64>, Fault> {
+        let osz = p.osz();
+
+        match op {
+            // ALU r/m, r and r, r/m.
+            0x00..=0x3F if op & 7 < 4 => {
+                let alu = op >> 3;
+                let size = if op & 1 == 0 { 1 } else { osz };
+                let m = d.modrm(p)?;
+                let next = d.next(self);
+                let rm = self.operand(&m, next, p);
+                let reg = Operand::Reg(m.reg);
+
+                let (dst, src) = if op & 2 == 0 { (rm, reg) } else { (reg, rm) };
+                let a = self.get(dst, size, p)?;
+                let b = self.get(src, size, p)?;
+
+                if let Some(v) = self.alu(alu, a, b, size) {
+                    self.set_locked(dst, size, v, p)?;
+                }
+
+                Ok(Some(next))
+            }
+            // ALU AL/rAX, imm.
+            0x00..=0x3F if op & 7 < 6 => {
+                let alu = op >> 3;
+                let size = if op & 1 == 0 { 1 } else { osz };
+                let b = d.imm(size.min(4))?;
+                let b = sx(b, size.min(4)) as u64 & mask(size);
+                let a = self.reg(0, size, p);
+
+                if let Some(v) = self.alu(alu, a, b, size) {
+                    self.set_reg(0, size, v, p);
+                }
+
+                Ok(Some(d.next(self)))
+            }
+            // PUSH r64.
+            0x50..=0x57 => {
+                let r = (op & 7) as usize | p.rex_b();
+                let v = self.regs.gpr[r];
+
+                self.push(v)?;
+
+                Ok(Some(d.next(self)))
+            }
+            // POP r64.
+            0x58..=0x5F => {
+                let r = (op & 7) as usize | p.rex_b();
+                let v = self.pop()?;
+
+                self.regs.gpr[r] = v;
+
+                Ok(Some(d.next(self)))
+            }
+            // MOVSXD r, r/m32.
+            0x63 => {
+                let m = d.modrm(p)?;
+                let next = d.next(self);
+                let src = self.operand(&m, next, p);
+                let v = sx(self.get(src, 4, p)?, 4) as u64;
+
+                self.set_reg(m.reg, osz, v & mask(osz), p);
+
+                Ok(Some(next))
+            }
+            // PUSH imm.
+            0x68 | 0x6A => {
+                let v = match op {
+                    0x68 => sx(d.imm(4)?, 4),
+                    _ => sx(d.imm(1)?, 1),
+                };
+
+                self.push(v as u64)?;
+
+                Ok(Some(d.next(self)))
+            }
+            // IMUL r, r/m, imm.
+            0x69 | 0x6B => {
+                let m = d.modrm(p)?;
+                let imm = match op {
+                    0x69 => sx(d.imm(osz.min(4))?, osz.min(4)),
+                    _ => sx(d.imm(1)?, 1),
+                };
+                let next = d.next(self);
+                let src = self.operand(&m, next, p);
+                let a = sx(self.get(src, osz, p)?, osz);
+                let v = self.imul(a, imm, osz);
+
+                self.set_reg(m.reg, osz, v, p);
+
+                Ok(Some(next))
+            }
+            // Jcc rel8.
+            0x70..=0x7F => {
+                let rel = sx(d.imm(1)?, 1);
+                let next = d.next(self);
+
+                Ok(Some(if self.cond(op & 0xF) {
+                    next.wrapping_add_signed(rel)
+                } else {
+                    next
+                }))
+            }
+            // ALU r/m, imm.
+            0x80 | 0x81 | 0x83 => {
+                let size = if op == 0x80 { 1 } else { osz };
+                let m = d.modrm(p)?;
+                let b = match op {
+                    0x81 => sx(d.imm(size.min(4))?, size.min(4)),
+                    _ => sx(d.imm(1)?, 1),
+                } as u64
+                    & mask(size);
+                let next = d.next(self);
+                let dst = self.operand(&m, next, p);
+                let a = self.get(dst, size, p)?;
+
+                if let Some(v) = self.alu(m.reg as u8 & 7, a, b, size) {
+                    self.set_locked(dst, size, v, p)?;
+                }
+
+                Ok(Some(next))
+            }
+            // TEST r/m, r.
+            0x84 | 0x85 => {
+                let size = if op == 0x84 { 1 } else { osz };
+                let m = d.modrm(p)?;
+                let next = d.next(self);
+                let src = self.operand(&m, next, p);
+                let a = self.get(src, size, p)?;
+                let b = self.reg(m.reg, size, p);
+
+                self.logic_flags(a & b, size);
+
+                Ok(Some(next))
+            }
+            // XCHG r/m, r.
+            0x86 | 0x87 => {
+                let size = if op == 0x86 { 1 } else { osz };
+                let m = d.modrm(p)?;
+                let next = d.next(self);
+                let dst = self.operand(&m, next, p);
+                let emu = self.emu;
+                let lock = matches!(dst, Operand::Mem(_)).then(|| emu.atomic.lock().unwrap());
+                let a = self.get(dst, size, p)?;
+                let b = self.reg(m.reg, size, p);
+
+                self.set(dst, size, b, p)?;
+                self.set_reg(m.reg, size, a, p);
+
+                drop(lock);
+
+                Ok(Some(next))
+            }
+            // MOV r/m, r.
+            0x88 | 0x89 => {
+                let size = if op == 0x88 { 1 } else { osz };
+                let m = d.modrm(p)?;
+                let next = d.next(self);
+                let dst = self.operand(&m, next, p);
+                let v = self.reg(m.reg, size, p);
+
+                self.set(dst, size, v, p)?;
+
+                Ok(Some(next))
+            }
+            // MOV r, r/m.
+            0x8A | 0x8B => {
+                let size = if op == 0x8A { 1 } else { osz };
+                let m = d.modrm(p)?;
+                let next = d.next(self);
+                let src = self.operand(&m, next, p);
+                let v = self.get(src, size, p)?;
+
+                self.set_reg(m.reg, size, v, p);
+
+                Ok(Some(next))
+            }
+            // MOV r/m, Sreg.
+            0x8C => {
+                let m = d.modrm(p)?;
+                let next = d.next(self);
+                let dst = self.operand(&m, next, p);
+                let v = match m.reg & 7 {
+                    0 => self.regs.es.selector,
+                    1 => self.regs.cs.selector,
+                    2 => self.regs.ss.selector,
+                    3 => self.regs.ds.selector,
+                    4 => self.regs.fs.selector,
+                    5 => self.regs.gs.selector,
+                    _ => return Err(Fault::Exception(UD, None)),
+                };
+                let size = match dst {
+                    Operand::Reg(_) => osz,
+                    Operand::Mem(_) => 2,
+                };
+
+                self.set(dst, size, v.into(), p)?;
+
+                Ok(Some(next))
+            }
+            // LEA.
+            0x8D => {
+                let m = d.modrm(p)?;
+                let next = d.next(self);
+                let addr = match m.rm {
+                    Rm::Mem { .. } => self.ea(&m, next, p, false),
+                    Rm::Reg(_) => return Err(Fault::Exception(UD, None)),
+                };
+
+                self.set_reg(m.reg, osz, addr & mask(osz), p);
+
+                Ok(Some(next))
+            }
+            // MOV Sreg, r/m.
+            0x8E => {
+                let m = d.modrm(p)?;
+                let next = d.next(self);
+                let src = self.operand(&m, next, p);
+                let v = self.get(src, 2, p)? as u16;
+
+                self.load_segment(m.reg & 7, v)?;
+
+                Ok(Some(next))
+            }
+            // POP r/m.
+            0x8F => {
+                let m = d.modrm(p)?;
+                let next = d.next(self);
+                let rsp = self.regs.gpr[RSP];
+                let v = self.read_u64(rsp)?;
+
+                self.regs.gpr[RSP] = rsp.wrapping_add(8);
+
+                let dst = self.operand(&m, next, p);
+
+                if let Err(e) = self.set(dst, 8, v, p) {
+                    self.regs.gpr[RSP] = rsp;
+                    return Err(e);
+                }
+
+                Ok(Some(next))
+            }
+            // NOP and PAUSE.
+            0x90 if p.rex & 1 == 0 => Ok(Some(d.next(self))),
+            // XCHG r, rAX.
+            0x90..=0x97 => {
+                let r = (op & 7) as usize | p.rex_b();
+                let a = self.reg(RAX, osz, p);
+                let b = self.reg(r, osz, p);
+
+                self.set_reg(RAX, osz, b, p);
+                self.set_reg(r, osz, a, p);
+
+                Ok(Some(d.next(self)))
+            }
+            // CBW/CWDE/CDQE.
+            0x98 => {
+                let half = osz / 2;
+                let v = sx(self.reg(RAX, half, p), half) as u64;
+
+                self.set_reg(RAX, osz, v & mask(osz), p);
+
+                Ok(Some(d.next(self)))
+            }
+            // CWD/CDQ/CQO.
+            0x99 => {
+                let v = if self.reg(RAX, osz, p) & sign(osz) != 0 {
+                    mask(osz)
+                } else {
+                    0
+                };
+
+                self.set_reg(RDX, osz, v, p);
+
+                Ok(Some(d.next(self)))
+            }
+            // PUSHF.
+            0x9C => {
+                let v = self.regs.rflags & !(RF | (1 << 16));
+
+                self.push(v)?;
+
+                Ok(Some(d.next(self)))
+            }
+            // POPF.
+            0x9D => {
+                let v = self.pop()?;
+
+                self.regs.rflags = (self.regs.rflags & !RFLAGS_WRITABLE) | (v & RFLAGS_WRITABLE);
+                self.regs.rflags |= 2;
+
+                Ok(Some(d.next(self)))
+            }
+            // SAHF.
+            0x9E => {
+                let ah = (self.regs.gpr[RAX] >> 8) & 0xD5;
+
+                self.regs.rflags = (self.regs.rflags & !0xD5) | ah;
+
+                Ok(Some(d.next(self)))
+            }
+            // LAHF.
+            0x9F => {
+                let v = (self.regs.rflags & 0xD5) | 2;
+
+                self.regs.gpr[RAX] = (self.regs.gpr[RAX] & !0xFF00) | (v << 8);
+
+                Ok(Some(d.next(self)))
+            }
+            // TEST AL/rAX, imm.
+            0xA8 | 0xA9 => {
+                let size = if op == 0xA8 { 1 } else { osz };
+                let b = sx(d.imm(size.min(4))?, size.min(4)) as u64 & mask(size);
+                let a = self.reg(RAX, size, p);
+
+                self.logic_flags(a & b, size);
+
+                Ok(Some(d.next(self)))
+            }
+            // String instructions.
+            0xA4..=0xA7 | 0xAA..=0xAF => {
+                let size = if op & 1 == 0 { 1 } else { osz };
+                let next = d.next(self);
+
+                self.string(op & !1, size, p, next)
+            }
+            // MOV r8, imm8.
+            0xB0..=0xB7 => {
+                let r = (op & 7) as usize | p.rex_b();
+                let v = d.imm(1)?;
+
+                self.set_reg(r, 1, v, p);
+
+                Ok(Some(d.next(self)))
+            }
+            // MOV r, imm.
+            0xB8..=0xBF => {
+                let r = (op & 7) as usize | p.rex_b();
+                let v = d.imm(osz)?;
+
+                self.set_reg(r, osz, v, p);
+
+                Ok(Some(d.next(self)))
+            }
+            // Shift r/m, imm8.
+            0xC0 | 0xC1 => {
+                let size = if op == 0xC0 { 1 } else { osz };
+                let m = d.modrm(p)?;
+                let count = d.imm(1)?;
+                let next = d.next(self);
+                let dst = self.operand(&m, next, p);
+
+                self.shift(m.reg as u8 & 7, dst, size, count, p)?;
+
+                Ok(Some(next))
+            }
+            // RET imm16.
+            0xC2 => {
+                let n = d.imm(2)?;
+                let target = self.pop()?;
+
+                self.regs.gpr[RSP] = self.regs.gpr[RSP].wrapping_add(n);
+
+                Ok(Some(target))
+            }
+            // RET.
+            0xC3 => Ok(Some(self.pop()?)),
+            // MOV r/m, imm.
+            0xC6 | 0xC7 => {
+                let size = if op == 0xC6 { 1 } else { osz };
+                let m = d.modrm(p)?;
+                let v = sx(d.imm(size.min(4))?, size.min(4)) as u64 & mask(size);
+                let next = d.next(self);
+                let dst = self.operand(&m, next, p);
+
+                self.set(dst, size, v, p)?;
+
+                Ok(Some(next))
+            }
+            // LEAVE.
+            0xC9 => {
+                let rbp = self.regs.gpr[RBP];
+                let v = self.read_u64(rbp)?;
+
+                self.regs.gpr[RSP] = rbp.wrapping_add(8);
+                self.regs.gpr[RBP] = v;
+
+                Ok(Some(d.next(self)))
+            }
+            // RETF.
+            0xCB if p.rex_w() => {
+                let rsp = self.regs.gpr[RSP];
+                let rip = self.read_u64(rsp)?;
+                let cs = self.read_u64(rsp.wrapping_add(8))?;
+
+                self.regs.gpr[RSP] = rsp.wrapping_add(16);
+                self.regs.cs.selector = cs as u16;
+
+                Ok(Some(rip))
+            }
+            // INT3.
+            0xCC => {
+                if self.emu.debug {
+                    self.exit = Some(ExitReason::Debug(DebugEvent::SwBreak));
+                    Ok(None)
+                } else {
+                    self.regs.rip = d.next(self);
+                    Err(Fault::Exception(BP, None))
+                }
+            }
+            // IRETQ.
+            0xCF if p.rex_w() => {
+                let rsp = self.regs.gpr[RSP];
+                let mut frame = [0u64; 5];
+
+                for (i, v) in frame.iter_mut().enumerate() {
+                    *v = self.read_u64(rsp.wrapping_add(i as u64 * 8))?;
+                }
+
+                self.regs.cs.selector = frame[1] as u16;
+                self.regs.rflags = (frame[2] & RFLAGS_WRITABLE) | 2;
+                self.regs.gpr[RSP] = frame[3];
+                self.regs.ss.selector = frame[4] as u16;
+
+                Ok(Some(frame[0]))
+            }
+            // Shift r/m, 1 and r/m, CL.
+            0xD0..=0xD3 => {
+                let size = if op & 1 == 0 { 1 } else { osz };
+                let m = d.modrm(p)?;
+                let next = d.next(self);
+                let dst = self.operand(&m, next, p);
+                let count = if op < 0xD2 {
+                    1
+                } else {
+                    self.regs.gpr[RCX] & 0xFF
+                };
+
+                self.shift(m.reg as u8 & 7, dst, size, count, p)?;
+
+                Ok(Some(next))
+            }
+            // CALL rel32.
+            0xE8 => {
+                let rel = sx(d.imm(4)?, 4);
+                let next = d.next(self);
+
+                self.push(next)?;
+
+                Ok(Some(next.wrapping_add_signed(rel)))
+            }
+            // JMP rel32.
+            0xE9 => {
+                let rel = sx(d.imm(4)?, 4);
+
+                Ok(Some(d.next(self).wrapping_add_signed(rel)))
+            }
+            // JMP rel8.
+            0xEB => {
+                let rel = sx(d.imm(1)?, 1);
+
+                Ok(Some(d.next(self).wrapping_add_signed(rel)))
+            }
+            // HLT.
+            0xF4 => {
+                self.exit = Some(ExitReason::Hlt);
+
+                Ok(Some(d.next(self)))
+            }
+            // CMC.
+            0xF5 => {
+                self.regs.rflags ^= CF;
+
+                Ok(Some(d.next(self)))
+            }
+            // Group 3.
+            0xF6 | 0xF7 => {
+                let size = if op == 0xF6 { 1 } else { osz };
+                let m = d.modrm(p)?;
+                let imm = match m.reg & 7 {
+                    0 | 1 => Some(sx(d.imm(size.min(4))?, size.min(4)) as u64 & mask(size)),
+                    _ => None,
+                };
+                let next = d.next(self);
+                let dst = self.operand(&m, next, p);
+
+                self.group3(m.reg as u8 & 7, dst, size, imm, p)?;
+
+                Ok(Some(next))
+            }
+            // CLC, STC, CLI, STI, CLD and STD.
+            0xF8..=0xFD => {
+                let (flag, set) = match op {
+                    0xF8 => (CF, false),
+                    0xF9 => (CF, true),
+                    0xFA => (IF, false),
+                    0xFB => (IF, true),
+                    0xFC => (DF, false),
+                    _ => (DF, true),
+                };
+
+                if set {
+                    self.regs.rflags |= flag;
+                } else {
+                    self.regs.rflags &= !flag;
+                }
+
+                Ok(Some(d.next(self)))
+            }
+            // Group 4 and 5.
+            0xFE | 0xFF => {
+                let size = if op == 0xFE { 1 } else { osz };
+                let m = d.modrm(p)?;
+                let next = d.next(self);
+                let dst = self.operand(&m, next, p);
+
+                match (op, m.reg & 7) {
+                    // INC and DEC.
+                    (_, n @ (0 | 1)) => {
+                        let a = self.get(dst, size, p)?;
+                        let cf = self.regs.rflags & CF;
+                        let v = if n == 0 {
+                            self.alu(0, a, 1, size).unwrap()
+                        } else {
+                            self.alu(5, a, 1, size).unwrap()
+                        };
+
+                        self.regs.rflags = (self.regs.rflags & !CF) | cf;
+                        self.set_locked(dst, size, v, p)?;
+
+                        Ok(Some(next))
+                    }
+                    // CALL r/m64.
+                    (0xFF, 2) => {
+                        let target = self.get(dst, 8, p)?;
+
+                        self.push(next)?;
+
+                        Ok(Some(target))
+                    }
+                    // JMP r/m64.
+                    (0xFF, 4) => Ok(Some(self.get(dst, 8, p)?)),
+                    // PUSH r/m64.
+                    (0xFF, 6) => {
+                        let v = self.get(dst, 8, p)?;
+
+                        self.push(v)?;
+
+                        Ok(Some(next))
+                    }
+                    _ => Err(unknown()),
+                }
+            }
+            _ => Err(unknown()),
+        }
+    }
+
+    fn exec_0f(&mut self, op: u8, d: &mut Decoder, p: &Prefix) -> Result<Option<u64>, Fault> {
+        let osz = p.osz();
+
+        match op {
+            // LTR.
+            0x00 => {
+                let m = d.modrm(p)?;
+                let next = d.next(self);
+                let src = self.operand(&m, next, p);
+
+                match m.reg & 7 {
+                    3 => self.regs.tr = self.get(src, 2, p)? as u16,
+                    _ => return Err(unknown()),
+                }
+
+                Ok(Some(next))
+            }
+            0x01 => {
+                let m = d.modrm(p)?;
+                let next = d.next(self);
+
+                match (m.reg & 7, m.rm) {
+                    // LGDT and LIDT.
+                    (n @ (2 | 3), Rm::Mem { .. }) => {
+                        let addr = self.ea(&m, next, p, true);
+                        let mut buf = [0u8; 10];
+
+                        self.read_virt(addr, &mut buf)?;
+
+                        let limit = u16::from_le_bytes([buf[0], buf[1]]);
+                        let base = u64::from_le_bytes(buf[2..].try_into().unwrap());
+
+                        if n == 2 {
+                            self.regs.gdtr = (base, limit);
+                        } else {
+                            self.regs.idtr = (base, limit);
+                        }
+                    }
+                    // SWAPGS.
+                    (7, Rm::Reg(0)) => {
+                        let r = &mut *self.regs;
+
+                        std::mem::swap(&mut r.gs_base, &mut r.kernel_gs_base);
+                    }
+                    _ => return Err(unknown()),
+                }
+
+                Ok(Some(next))
+            }
+            // SYSCALL.
+            0x05 => {
+                let next = d.next(self);
+                let sel = (self.regs.star >> 32) as u16;
+
+                self.regs.gpr[RCX] = next;
+                self.regs.gpr[11] = self.regs.rflags;
+                self.regs.rflags &= !self.regs.sfmask;
+                self.regs.rflags |= 2;
+                self.regs.cs.selector = sel & !3;
+                self.regs.ss.selector = (sel & !3) + 8;
+
+                Ok(Some(self.regs.lstar))
+            }
+            // SYSRET.
+            0x07 => {
+                let sel = (self.regs.star >> 48) as u16;
+                let target = self.regs.gpr[RCX];
+
+                self.regs.rflags = (self.regs.gpr[11] & RFLAGS_WRITABLE) | 2;
+                self.regs.cs.selector = if p.rex_w() { sel + 16 } else { sel } | 3;
+                self.regs.ss.selector = (sel + 8) | 3;
+
+                Ok(Some(target))
+            }
+            // UD2.
+            0x0B => Err(Fault::Exception(UD, None)),
+            // PREFETCH and NOP r/m.
+            0x0D | 0x18..=0x1F => {
+                d.modrm(p)?;
+
+                Ok(Some(d.next(self)))
+            }
+            // MOV r64, CRn.
+            0x20 => {
+                let m = d.modrm(p)?;
+                let v = match m.reg {
+                    0 => self.regs.cr0,
+                    2 => self.regs.cr2,
+                    3 => self.regs.cr3,
+                    4 => self.regs.cr4,
+                    8 => 0,
+                    _ => return Err(Fault::Exception(UD, None)),
+                };
+
+                if let Rm::Reg(r) = m.rm {
+                    self.regs.gpr[r] = v;
+                }
+
+                Ok(Some(d.next(self)))
+            }
+            // MOV CRn, r64.
+            0x22 => {
+                let m = d.modrm(p)?;
+                let v = match m.rm {
+                    Rm::Reg(r) => self.regs.gpr[r],
+                    Rm::Mem { .. } => return Err(Fault::Exception(UD, None)),
+                };
+
+                match m.reg {
+                    0 => self.regs.cr0 = v,
+                    2 => self.regs.cr2 = v,
+                    3 => self.regs.cr3 = v,
+                    4 => self.regs.cr4 = v,
+                    8 => (),
+                    _ => return Err(Fault::Exception(UD, None)),
+                }
+
+                Ok(Some(d.next(self)))
+            }
+            // WRMSR.
+            0x30 => {
+                let v = (self.regs.gpr[RDX] << 32) | (self.regs.gpr[RAX] & 0xFFFFFFFF);
+                let msr = match self.regs.gpr[RCX] as u32 {
+                    0xC0000080 => &mut self.regs.efer,
+                    0xC0000081 => &mut self.regs.star,
+                    0xC0000082 => &mut self.regs.lstar,
+                    0xC0000083 => &mut self.regs.cstar,
+                    0xC0000084 => &mut self.regs.sfmask,
+                    0xC0000100 => &mut self.regs.fs_base,
+                    0xC0000101 => &mut self.regs.gs_base,
+                    0xC0000102 => &mut self.regs.kernel_gs_base,
+                    _ => return Err(Fault::Exception(GP, Some(0))),
+                };
+
+                *msr = v;
+
+                Ok(Some(d.next(self)))
+            }
+            // RDTSC.
+            0x31 => {
+                let v = self.tsc();
+
+                self.regs.gpr[RAX] = v & 0xFFFFFFFF;
+                self.regs.gpr[RDX] = v >> 32;
+
+                Ok(Some(d.next(self)))
+            }
+            // RDMSR.
+            0x32 => {
+                let v = match self.regs.gpr[RCX] as u32 {
+                    0x10 => self.tsc(),
+                    0xC0000080 => self.regs.efer,
+                    0xC0000081 => self.regs.star,
+                    0xC0000082 => self.regs.lstar,
+                    0xC0000083 => self.regs.cstar,
+                    0xC0000084 => self.regs.sfmask,
+                    0xC0000100 => self.regs.fs_base,
+                    0xC0000101 => self.regs.gs_base,
+                    0xC0000102 => self.regs.kernel_gs_base,
+                    _ => return Err(Fault::Exception(GP, Some(0))),
+                };
+
+                self.regs.gpr[RAX] = v & 0xFFFFFFFF;
+                self.regs.gpr[RDX] = v >> 32;
+
+                Ok(Some(d.next(self)))
+            }
+            // CMOVcc.
+            0x40..=0x4F => {
+                let m = d.modrm(p)?;
+                let next = d.next(self);
+                let src = self.operand(&m, next, p);
+                let v = self.get(src, osz, p)?;
+
+                if self.cond(op & 0xF) {
+                    self.set_reg(m.reg, osz, v, p);
+                } else if osz == 4 {
+                    self.regs.gpr[m.reg] &= 0xFFFFFFFF;
+                }
+
+                Ok(Some(next))
+            }
+            // Jcc rel32.
+            0x80..=0x8F => {
+                let rel = sx(d.imm(4)?, 4);
+                let next = d.next(self);
+
+                Ok(Some(if self.cond(op & 0xF) {
+                    next.wrapping_add_signed(rel)
+                } else {
+                    next
+                }))
+            }
+            // SETcc.
+            0x90..=0x9F => {
+                let m = d.modrm(p)?;
+                let next = d.next(self);
+                let dst = self.operand(&m, next, p);
+                let v = self.cond(op & 0xF).into();
+
+                self.set(dst, 1, v, p)?;
+
+                Ok(Some(next))
+            }
+            // CPUID.
+            0xA2 => {
+                let id = self.regs.gpr[RAX] as u32;
+                let (a, b, c, d2) = self
+                    .emu
+                    .feats
+                    .iter()
+                    .find(|f| f.id == id)
+                    .map(|f| (f.eax, f.ebx, f.ecx, f.edx))
+                    .unwrap_or_default();
+
+                self.regs.gpr[RAX] = a.into();
+                self.regs.gpr[3] = b.into();
+                self.regs.gpr[RCX] = c.into();
+                self.regs.gpr[RDX] = d2.into();
+
+                Ok(Some(d.next(self)))
+            }
+            // BT, BTS, BTR and BTC with register.
+            0xA3 | 0xAB | 0xB3 | 0xBB => {
+                let m = d.modrm(p)?;
+                let next = d.next(self);
+                let off = sx(self.reg(m.reg, osz, p), osz);
+                let bits = (osz * 8) as i64;
+                let dst = match self.operand(&m, next, p) {
+                    Operand::Mem(addr) => {
+                        Operand::Mem(addr.wrapping_add_signed(off.div_euclid(bits) * osz as i64))
+                    }
+                    v => v,
+                };
+                let bit = off.rem_euclid(bits) as u32;
+
+                self.bit_test((op >> 3) & 3, dst, osz, bit, p)?;
+
+                Ok(Some(next))
+            }
+            // SHLD and SHRD.
+            0xA4 | 0xA5 | 0xAC | 0xAD => {
+                let m = d.modrm(p)?;
+                let count = if op & 1 == 0 {
+                    d.imm(1)?
+                } else {
+                    self.regs.gpr[RCX] & 0xFF
+                };
+                let next = d.next(self);
+                let dst = self.operand(&m, next, p);
+                let bits = (osz * 8) as u32;
+                let count = (count & if osz == 8 { 0x3F } else { 0x1F }) as u32;
+
+                if count != 0 && count < bits {
+                    let a = self.get(dst, osz, p)?;
+                    let b = self.reg(m.reg, osz, p);
+                    let (v, cf) = if op < 0xA8 {
+                        let v = (a << count) | (b >> (bits - count));
+                        (v, (a >> (bits - count)) & 1)
+                    } else {
+                        let v = (a >> count) | (b << (bits - count));
+                        (v, (a >> (count - 1)) & 1)
+                    };
+                    let v = v & mask(osz);
+
+                    self.set(dst, osz, v, p)?;
+                    self.szp_flags(v, osz);
+                    self.set_flag(CF, cf != 0);
+                    self.set_flag(OF, (a ^ v) & sign(osz) != 0);
+                }
+
+                Ok(Some(next))
+            }
+            // LFENCE, MFENCE and SFENCE.
+            0xAE => {
+                let m = d.modrm(p)?;
+
+                match m.rm {
+                    Rm::Reg(_) if m.reg & 7 >= 5 => Ok(Some(d.next(self))),
+                    _ => Err(unknown()),
+                }
+            }
+            // IMUL r, r/m.
+            0xAF => {
+                let m = d.modrm(p)?;
+                let next = d.next(self);
+                let src = self.operand(&m, next, p);
+                let a = sx(self.reg(m.reg, osz, p), osz);
+                let b = sx(self.get(src, osz, p)?, osz);
+                let v = self.imul(a, b, osz);
+
+                self.set_reg(m.reg, osz, v, p);
+
+                Ok(Some(next))
+            }
+            // CMPXCHG.
+            0xB0 | 0xB1 => {
+                let size = if op == 0xB0 { 1 } else { osz };
+                let m = d.modrm(p)?;
+                let next = d.next(self);
+                let dst = self.operand(&m, next, p);
+                let emu = self.emu;
+                let lock = p.lock.then(|| emu.atomic.lock().unwrap());
+                let cur = self.get(dst, size, p)?;
+                let expected = self.reg(RAX, size, p);
+
+                self.alu(7, expected, cur, size);
+
+                if cur == expected {
+                    let v = self.reg(m.reg, size, p);
+                    self.set(dst, size, v, p)?;
+                } else {
+                    // The destination is always written on the real CPU.
+                    if matches!(dst, Operand::Mem(_)) {
+                        self.set(dst, size, cur, p)?;
+                    }
+
+                    self.set_reg(RAX, size, cur, p);
+                }
+
+                drop(lock);
+
+                Ok(Some(next))
+            }
+            // MOVZX and MOVSX.
+            0xB6 | 0xB7 | 0xBE | 0xBF => {
+                let size = if op & 1 == 0 { 1 } else { 2 };
+                let m = d.modrm(p)?;
+                let next = d.next(self);
+                let src = self.operand(&m, next, p);
+                let v = self.get(src, size, p)?;
+                let v = if op < 0xB8 { v } else { sx(v, size) as u64 };
+
+                self.set_reg(m.reg, osz, v & mask(osz), p);
+
+                Ok(Some(next))
+            }
+            // BT, BTS, BTR and BTC with immediate.
+            0xBA => {
+                let m = d.modrm(p)?;
+                let bit = d.imm(1)? as u32 % (osz as u32 * 8);
+                let next = d.next(self);
+                let dst = self.operand(&m, next, p);
+
+                match m.reg & 7 {
+                    n @ 4..=7 => self.bit_test(n as u8 - 4, dst, osz, bit, p)?,
+                    _ => return Err(unknown()),
+                }
+
+                Ok(Some(next))
+            }
+            // BSF/TZCNT and BSR/LZCNT. We does not report BMI1 and ABM so these always BSF and BSR.
+            0xBC | 0xBD => {
+                let m = d.modrm(p)?;
+                let next = d.next(self);
+                let src = self.operand(&m, next, p);
+                let v = self.get(src, osz, p)?;
+
+                if v == 0 {
+                    self.set_flag(ZF, true);
+                } else {
+                    let n = if op == 0xBC {
+                        v.trailing_zeros()
+                    } else {
+                        63 - v.leading_zeros()
+                    };
+
+                    self.set_flag(ZF, false);
+                    self.set_reg(m.reg, osz, n.into(), p);
+                }
+
+                Ok(Some(next))
+            }
+            // XADD.
+            0xC0 | 0xC1 => {
+                let size = if op == 0xC0 { 1 } else { osz };
+                let m = d.modrm(p)?;
+                let next = d.next(self);
+                let dst = self.operand(&m, next, p);
+                let emu = self.emu;
+                let lock = p.lock.then(|| emu.atomic.lock().unwrap());
+                let a = self.get(dst, size, p)?;
+                let b = self.reg(m.reg, size, p);
+                let v = self.alu(0, a, b, size).unwrap();
+
+                self.set(dst, size, v, p)?;
+                self.set_reg(m.reg, size, a, p);
+
+                drop(lock);
+
+                Ok(Some(next))
+            }
+            // BSWAP.
+            0xC8..=0xCF => {
+                let r = (op & 7) as usize | p.rex_b();
+                let v = self.regs.gpr[r];
+
+                self.regs.gpr[r] = match osz {
+                    8 => v.swap_bytes(),
+                    _ => u64::from((v as u32).swap_bytes()),
+                };
+
+                Ok(Some(d.next(self)))
+            }
+            _ => Err(unknown()),
+        }
+    }
+
+    fn string(&mut self, op: u8, size: usize, p: &Prefix, next: u64) -> Result<Option<u64>, Fault> {
+        let step = if self.regs.rflags & DF == 0 {
+            size as i64
+        } else {
+            -(size as i64)
+        };
+        let rep = p.rep != 0;
+
+        loop {
+            if rep && self.regs.gpr[RCX] == 0 {
+                return Ok(Some(next));
+            }
+
+            let si = self.regs.gpr[RSI];
+            let di = self.regs.gpr[RDI];
+            let mut stop = false;
+
+            match op {
+                // MOVS.
+                0xA4 => {
+                    let v = self.get(Operand::Mem(self.seg(si, p)), size, &Prefix::default())?;
+
+                    self.set(Operand::Mem(di), size, v, &Prefix::default())?;
+                    self.regs.gpr[RSI] = si.wrapping_add_signed(step);
+                    self.regs.gpr[RDI] = di.wrapping_add_signed(step);
+                }
+                // CMPS.
+                0xA6 => {
+                    let a = self.get(Operand::Mem(self.seg(si, p)), size, &Prefix::default())?;
+                    let b = self.get(Operand::Mem(di), size, &Prefix::default())?;
+
+                    self.alu(7, a, b, size);
+                    self.regs.gpr[RSI] = si.wrapping_add_signed(step);
+                    self.regs.gpr[RDI] = di.wrapping_add_signed(step);
+                    stop = true;
+                }
+                // STOS.
+                0xAA => {
+                    let v = self.reg(RAX, size, p);
+
+                    self.set(Operand::Mem(di), size, v, &Prefix::default())?;
+                    self.regs.gpr[RDI] = di.wrapping_add_signed(step);
+                }
+                // LODS.
+                0xAC => {
+                    let v = self.get(Operand::Mem(self.seg(si, p)), size, &Prefix::default())?;
+
+                    self.set_reg(RAX, size, v, p);
+                    self.regs.gpr[RSI] = si.wrapping_add_signed(step);
+                }
+                // SCAS.
+                0xAE => {
+                    let a = self.reg(RAX, size, p);
+                    let b = self.get(Operand::Mem(di), size, &Prefix::default())?;
+
+                    self.alu(7, a, b, size);
+                    self.regs.gpr[RDI] = di.wrapping_add_signed(step);
+                    stop = true;
+                }
+                _ => return Err(unknown()),
+            }
+
+            if !rep {
+                return Ok(Some(next));
+            }
+
+            self.regs.gpr[RCX] = self.regs.gpr[RCX].wrapping_sub(1);
+
+            // Check REPE/REPNE condition.
+            if stop {
+                let zf = self.regs.rflags & ZF != 0;
+
+                if (p.rep == 0xF3 && !zf) || (p.rep == 0xF2 && zf) {
+                    return Ok(Some(next));
+                }
+            }
+
+            // Give the VMM a chance to handle memory-mapped I/O.
+            if self.exit.is_some() {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn group3(
+        &mut self,
+        op: u8,
+        dst: Operand,
+        size: usize,
+        imm: Option<u64>,
+        p: &Prefix,
+    ) -> Result<(), Fault> {
+        let a = self.get(dst, size, p)?;
+        let m = mask(size);
+        let bits = size * 8;
+
+        match op {
+            // TEST.
+            0 | 1 => self.logic_flags(a & imm.unwrap(), size),
+            // NOT.
+            2 => self.set_locked(dst, size, !a & m, p)?,
+            // NEG.
+            3 => {
+                let v = self.alu(5, 0, a, size).unwrap();
+
+                self.set_flag(CF, a != 0);
+                self.set_locked(dst, size, v, p)?;
+            }
+            // MUL.
+            4 => {
+                let v = u128::from(self.reg(RAX, size, p)) * u128::from(a);
+                let hi = (v >> bits) as u64 & m;
+
+                self.set_mul_result(size, v as u64 & m, hi, p);
+                self.set_flag(CF, hi != 0);
+                self.set_flag(OF, hi != 0);
+            }
+            // IMUL.
+            5 => {
+                let v = i128::from(sx(self.reg(RAX, size, p), size)) * i128::from(sx(a, size));
+                let lo = v as u64 & m;
+                let hi = (v >> bits) as u64 & m;
+                let overflow = i128::from(sx(lo, size)) != v;
+
+                self.set_mul_result(size, lo, hi, p);
+                self.set_flag(CF, overflow);
+                self.set_flag(OF, overflow);
+            }
+            // DIV.
+            6 => {
+                if a == 0 {
+                    return Err(Fault::Exception(DE, None));
+                }
+
+                let (hi, lo) = self.dividend(size, p);
+                let n = (u128::from(hi) << bits) | u128::from(lo);
+                let q = n / u128::from(a);
+                let r = n % u128::from(a);
+
+                if q > u128::from(m) {
+                    return Err(Fault::Exception(DE, None));
+                }
+
+                self.set_div_result(size, q as u64, r as u64, p);
+            }
+            // IDIV.
+            7 => {
+                if a == 0 {
+                    return Err(Fault::Exception(DE, None));
+                }
+
+                let (hi, lo) = self.dividend(size, p);
+                let n = ((u128::from(hi) << bits) | u128::from(lo)) << (128 - bits * 2);
+                let n = (n as i128) >> (128 - bits * 2);
+                let b = i128::from(sx(a, size));
+                let q = n.wrapping_div(b);
+                let r = n.wrapping_rem(b);
+                let min = -(1i128 << (bits - 1));
+                let max = (1i128 << (bits - 1)) - 1;
+
+                if q < min || q > max {
+                    return Err(Fault::Exception(DE, None));
+                }
+
+                self.set_div_result(size, q as u64 & m, r as u64 & m, p);
+            }
+            _ => unreachable!(),
+        }
+
+        Ok(())
+    }
+
+    fn dividend(&self, size: usize, p: &Prefix) -> (u64, u64) {
+        match size {
+            1 => {
+                let ax = self.regs.gpr[RAX] & 0xFFFF;
+                (ax >> 8, ax & 0xFF)
+            }
+            _ => (self.reg(RDX, size, p), self.reg(RAX, size, p)),
+        }
+    }
+
+    fn set_mul_result(&mut self, size: usize, lo: u64, hi: u64, p: &Prefix) {
+        match size {
+            1 => self.set_reg(RAX, 2, (hi << 8) | lo, p),
+            _ => {
+                self.set_reg(RAX, size, lo, p);
+                self.set_reg(RDX, size, hi, p);
+            }
+        }
+    }
+
+    fn set_div_result(&mut self, size: usize, q: u64, r: u64, p: &Prefix) {
+        match size {
+            1 => self.set_reg(RAX, 2, (r << 8) | q, p),
+            _ => {
+                self.set_reg(RAX, size, q, p);
+                self.set_reg(RDX, size, r, p);
+            }
+        }
+    }
+
+    fn imul(&mut self, a: i64, b: i64, size: usize) -> u64 {
+        let v = i128::from(a) * i128::from(b);
+        let lo = v as u64 & mask(size);
+        let overflow = i128::from(sx(lo, size)) != v;
+
+        self.set_flag(CF, overflow);
+        self.set_flag(OF, overflow);
+
+        lo
+    }
+
+    fn bit_test(
+        &mut self,
+        op: u8,
+        dst: Operand,
+        size: usize,
+        bit: u32,
+        p: &Prefix,
+    ) -> Result<(), Fault> {
+        let v = self.get(dst, size, p)?;
+        let b = 1u64 << bit;
+
+        self.set_flag(CF, v & b != 0);
+
+        match op {
+            0 => (),
+            1 => self.set_locked(dst, size, v | b, p)?,
+            2 => self.set_locked(dst, size, v & !b, p)?,
+            _ => self.set_locked(dst, size, v ^ b, p)?,
+        }
+
+        Ok(())
+    }
+
+    fn shift(
+        &mut self,
+        op: u8,
+        dst: Operand,
+        size: usize,
+        count: u64,
+        p: &Prefix,
+    ) -> Result<(), Fault> {
+        let bits = (size * 8) as u32;
+        let count = (count & if size == 8 { 0x3F } else { 0x1F }) as u32;
+
+        if count == 0 {
+            return Ok(());
+        }
+
+        let a = self.get(dst, size, p)?;
+        let m = mask(size);
+        let msb = |v: u64| v & sign(size) != 0;
+        let v = match op {
+            // ROL.
+            0 => {
+                let c = count % bits;
+                let v = if c == 0 {
+                    a
+                } else {
+                    ((a << c) | (a >> (bits - c))) & m
+                };
+
+                self.set_flag(CF, v & 1 != 0);
+                self.set_flag(OF, msb(v) ^ (v & 1 != 0));
+                v
+            }
+            // ROR.
+            1 => {
+                let c = count % bits;
+                let v = if c == 0 {
+                    a
+                } else {
+                    ((a >> c) | (a << (bits - c))) & m
+                };
+
+                self.set_flag(CF, msb(v));
+                self.set_flag(OF, msb(v) ^ msb(v << 1));
+                v
+            }
+            // RCL and RCR.
+            2 | 3 => {
+                let mut v = a;
+                let mut cf = self.regs.rflags & CF != 0;
+
+                for _ in 0..(count % (bits + 1)) {
+                    if op == 2 {
+                        let out = msb(v);
+                        v = ((v << 1) | u64::from(cf)) & m;
+                        cf = out;
+                    } else {
+                        let out = v & 1 != 0;
+                        v = (v >> 1) | if cf { sign(size) } else { 0 };
+                        cf = out;
+                    }
+                }
+
+                self.set_flag(CF, cf);
+
+                if op == 2 {
+                    self.set_flag(OF, msb(v) ^ cf);
+                } else {
+                    self.set_flag(OF, msb(v) ^ msb(v << 1));
+                }
+
+                v
+            }
+            // SHL and SAL.
+            4 | 6 => {
+                let v = if count < 64 { (a << count) & m } else { 0 };
+                let cf = count <= bits && (a >> (bits - count)) & 1 != 0;
+
+                self.szp_flags(v, size);
+                self.set_flag(CF, cf);
+                self.set_flag(OF, msb(v) ^ cf);
+                v
+            }
+            // SHR.
+            5 => {
+                let v = if count < 64 { a >> count } else { 0 };
+
+                self.szp_flags(v, size);
+                self.set_flag(CF, (a >> (count - 1)) & 1 != 0);
+                self.set_flag(OF, msb(a));
+                v
+            }
+            // SAR.
+            _ => {
+                let s = sx(a, size);
+                let v = (s >> count.min(63)) as u64 & m;
+
+                self.szp_flags(v, size);
+                self.set_flag(CF, (s >> (count - 1).min(63)) & 1 != 0);
+                self.set_flag(OF, false);
+                v
+            }
+        };
+
+        self.set_locked(dst, size, v, p)
+    }
+
+    /// Returns [`None`] if the operation does not produce a result (e.g. CMP).
+    fn alu(&mut self, op: u8, a: u64, b: u64, size: usize) -> Option<u64> {
+        let m = mask(size);
+        let s = sign(size);
+        let carry = u64::from(self.regs.rflags & CF != 0);
+        let (v, write) = match op {
+            // ADD and ADC.
+            0 | 2 => {
+                let c = if op == 2 { carry } else { 0 };
+                let v = (u128::from(a) + u128::from(b) + u128::from(c)) as u64 & m;
+                let cf = u128::from(a) + u128::from(b) + u128::from(c) > u128::from(m);
+
+                self.szp_flags(v, size);
+                self.set_flag(CF, cf);
+                self.set_flag(OF, (a ^ v) & (b ^ v) & s != 0);
+                self.set_flag(AF, (a ^ b ^ v) & 0x10 != 0);
+                (v, true)
+            }
+            // SUB, SBB and CMP.
+            3 | 5 | 7 => {
+                let c = if op == 3 { carry } else { 0 };
+                let v = a.wrapping_sub(b).wrapping_sub(c) & m;
+                let cf = u128::from(a) < u128::from(b) + u128::from(c);
+
+                self.szp_flags(v, size);
+                self.set_flag(CF, cf);
+                self.set_flag(OF, (a ^ b) & (a ^ v) & s != 0);
+                self.set_flag(AF, (a ^ b ^ v) & 0x10 != 0);
+                (v, op != 7)
+            }
+            // OR.
+            1 => {
+                let v = (a | b) & m;
+
+                self.logic_flags(v, size);
+                (v, true)
+            }
+            // AND.
+            4 => {
+                let v = a & b & m;
+
+                self.logic_flags(v, size);
+                (v, true)
+            }
+            // XOR.
+            _ => {
+                let v = (a ^ b) & m;
+
+                self.logic_flags(v, size);
+                (v, true)
+            }
+        };
+
+        write.then_some(v)
+    }
+
+    fn logic_flags(&mut self, v: u64, size: usize) {
+        self.szp_flags(v, size);
+        self.set_flag(CF, false);
+        self.set_flag(OF, false);
+        self.set_flag(AF, false);
+    }
+
+    fn szp_flags(&mut self, v: u64, size: usize) {
+        let v = v & mask(size);
+
+        self.set_flag(ZF, v == 0);
+        self.set_flag(SF, v & sign(size) != 0);
+        self.set_flag(PF, (v as u8).count_ones().is_multiple_of(2));
+    }
+
+    fn set_flag(&mut self, flag: u64, v: bool) {
+        if v {
+            self.regs.rflags |= flag;
+        } else {
+            self.regs.rflags &= !flag;
+        }
+    }
+
+    fn cond(&self, cc: u8) -> bool {
+        let f = self.regs.rflags;
+        let cf = f & CF != 0;
+        let zf = f & ZF != 0;
+        let sf = f & SF != 0;
+        let of = f & OF != 0;
+        let pf = f & PF != 0;
+        let r = match cc >> 1 {
+            0 => of,
+            1 => cf,
+            2 => zf,
+            3 => cf || zf,
+            4 => sf,
+            5 => pf,
+            6 => sf != of,
+            _ => zf || sf != of,
+        };
+
+        r ^ (cc & 1 != 0)
+    }
+
+    fn load_segment(&mut self, reg: usize, sel: u16) -> Result<(), Fault> {
+        // Only FS and GS has a base address on 64-bit mode.
+        let base = match reg {
+            4 | 5 if sel & !3 != 0 => self.descriptor_base(sel)? & 0xFFFFFFFF,
+            _ => 0,
+        };
+
+        match reg {
+            0 => self.regs.es.selector = sel,
+            2 => self.regs.ss.selector = sel,
+            3 => self.regs.ds.selector = sel,
+            4 => {
+                self.regs.fs.selector = sel;
+                self.regs.fs_base = base;
+            }
+            5 => {
+                self.regs.gs.selector = sel;
+                self.regs.gs_base = base;
+            }
+            _ => return Err(Fault::Exception(UD, None)),
+        }
+
+        Ok(())
+    }
+
+    /// Returns base address of the descriptor referenced by `sel`. This also handle 16-bytes
+    /// system descriptor.
+    fn descriptor_base(&mut self, sel: u16) -> Result<u64, Fault> {
+        let d = self.descriptor(sel)?;
+        let mut v = u64::from(u16::from_le_bytes([d[2], d[3]]))
+            | u64::from(d[4]) << 16
+            | u64::from(d[7]) << 24;
+
+        // Check if system descriptor.
+        if d[5] & 0x10 == 0 {
+            v |= u64::from(u32::from_le_bytes(d[8..12].try_into().unwrap())) << 32;
+        }
+
+        Ok(v)
+    }
+
+    /// Read the descriptor referenced by `sel` from GDT. The upper 8 bytes will be zero if it is
+    /// outside the limit.
+    fn descriptor(&mut self, sel: u16) -> Result<[u8; 16], Fault> {
+        let (base, limit) = self.regs.gdtr;
+        let off = u64::from(sel & !7);
+
+        if off + 7 > limit.into() {
+            return Err(Fault::Exception(GP, Some(off)));
+        }
+
+        let mut d = [0u8; 16];
+        let len = if off + 15 <= limit.into() { 16 } else { 8 };
+
+        self.read_sys(base + off, &mut d[..len])?;
+
+        Ok(d)
+    }
+
+    /// Read 64-bit value at `off` from the current TSS.
+    fn read_tss(&mut self, off: u64) -> Result<u64, Fault> {
+        let tss = self.descriptor_base(self.regs.tr)?;
+        let mut buf = [0u8; 8];
+
+        self.read_sys(tss + off, &mut buf)?;
+
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    /// Returns the current privilege level.
+    fn cpl(&self) -> u8 {
+        (self.regs.cs.selector & 3) as u8
+    }
+
+    fn tsc(&self) -> u64 {
+        // Assume 1 GHz.
+        self.emu.started.elapsed().as_nanos() as u64
+    }
+
+    fn push(&mut self, v: u64) -> Result<(), Fault> {
+        let rsp = self.regs.gpr[RSP].wrapping_sub(8);
+
+        self.write_virt(rsp, &v.to_le_bytes())?;
+        self.regs.gpr[RSP] = rsp;
+
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<u64, Fault> {
+        let rsp = self.regs.gpr[RSP];
+        let v = self.read_u64(rsp)?;
+
+        self.regs.gpr[RSP] = rsp.wrapping_add(8);
+
+        Ok(v)
+    }
+
+    fn read_u64(&mut self, addr: u64) -> Result<u64, Fault> {
+        let mut buf = [0u8; 8];
+
+        self.read_virt(addr, &mut buf)?;
+
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn operand(&self, m: &ModRm, next: u64, p: &Prefix) -> Operand {
+        match m.rm {
+            Rm::Reg(r) => Operand::Reg(r),
+            Rm::Mem { .. } => Operand::Mem(self.ea(m, next, p, true)),
+        }
+    }
+
+    /// Compute effective address for `m`. Specify `true` for `seg` to apply segment override.
+    fn ea(&self, m: &ModRm, next: u64, p: &Prefix, seg: bool) -> u64 {
+        let Rm::Mem {
+            base,
+            index,
+            scale,
+            disp,
+        } = m.rm
+        else {
+            unreachable!();
+        };
+
+        let mut addr = match base {
+            Base::Reg(r) => self.regs.gpr[r],
+            Base::Rip => next,
+            Base::None => 0,
+        };
+
+        if let Some(i) = index {
+            addr = addr.wrapping_add(self.regs.gpr[i] << scale);
+        }
+
+        addr = addr.wrapping_add_signed(disp);
+
+        if p.addr32 {
+            addr &= 0xFFFFFFFF;
+        }
+
+        if seg { self.seg(addr, p) } else { addr }
+    }
+
+    fn seg(&self, addr: u64, p: &Prefix) -> u64 {
+        match p.seg {
+            Some(SegOverride::Fs) => addr.wrapping_add(self.regs.fs_base),
+            Some(SegOverride::Gs) => addr.wrapping_add(self.regs.gs_base),
+            None => addr,
+        }
+    }
+
+    fn get(&mut self, op: Operand, size: usize, p: &Prefix) -> Result<u64, Fault> {
+        match op {
+            Operand::Reg(r) => Ok(self.reg(r, size, p)),
+            Operand::Mem(addr) => {
+                let mut buf = [0u8; 8];
+
+                self.read_virt(addr, &mut buf[..size])?;
+
+                Ok(u64::from_le_bytes(buf))
+            }
+        }
+    }
+
+    fn set(&mut self, op: Operand, size: usize, v: u64, p: &Prefix) -> Result<(), Fault> {
+        match op {
+            Operand::Reg(r) => {
+                self.set_reg(r, size, v, p);
+                Ok(())
+            }
+            Operand::Mem(addr) => self.write_virt(addr, &v.to_le_bytes()[..size]),
+        }
+    }
+
+    /// Same as [`Self::set()`] but honor LOCK prefix.
+    ///
+    /// We don't hold the lock while reading the old value since the only instructions that
+    /// requires it are the one that implemented separately (e.g. CMPXCHG).
+    fn set_locked(&mut self, op: Operand, size: usize, v: u64, p: &Prefix) -> Result<(), Fault> {
+        let emu = self.emu;
+        let lock = p.lock.then(|| emu.atomic.lock().unwrap());
+        let r = self.set(op, size, v, p);
+
+        drop(lock);
+
+        r
+    }
+
+    fn reg(&self, r: usize, size: usize, p: &Prefix) -> u64 {
+        if size == 1 && p.rex == 0 && (4..8).contains(&r) {
+            (self.regs.gpr[r - 4] >> 8) & 0xFF
+        } else {
+            self.regs.gpr[r] & mask(size)
+        }
+    }
+
+    fn set_reg(&mut self, r: usize, size: usize, v: u64, p: &Prefix) {
+        match size {
+            1 if p.rex == 0 && (4..8).contains(&r) => {
+                let dst = &mut self.regs.gpr[r - 4];
+                *dst = (*dst & !0xFF00) | ((v & 0xFF) << 8);
+            }
+            1 | 2 => {
+                let m = mask(size);
+                let dst = &mut self.regs.gpr[r];
+                *dst = (*dst & !m) | (v & m);
+            }
+            // Writing 32-bit register always zero the upper bits.
+            _ => self.regs.gpr[r] = v & mask(size),
+        }
+    }
+
+    /// Returns the number of bytes fetched and the fault that stop the fetching, if any.
+    fn fetch(&mut self, rip: u64, buf: &mut [u8; 15]) -> (usize, Option<Fault>) {
+        let mut len = 0;
+
+        while len < buf.len() {
+            // Fetch up to the end of page.
+            let addr = rip.wrapping_add(len as u64);
+            let end = ((addr | 0xFFF) + 1 - addr) as usize;
+            let n = end.min(buf.len() - len);
+            let access = Access {
+                user: self.cpl() == 3,
+                write: false,
+                fetch: true,
+            };
+            let paddr = match self.walk(addr, Some(access)) {
+                Ok(v) => v as usize,
+                Err(e) => return (len, Some(self.page_fault(addr, e, access))),
+            };
+
+            if paddr >= self.emu.ram.len().get() {
+                return (len, Some(Fault::Error(RunError::FetchMmio(paddr))));
+            }
+
+            match self.emu.ram.lock(paddr, NonZero::new(n).unwrap()) {
+                Some(m) => unsafe { m.as_ptr().copy_to(buf[len..].as_mut_ptr(), n) },
+                None => return (len, Some(Fault::Error(RunError::NotAllocated(paddr)))),
+            }
+
+            len += n;
+        }
+
+        (len, None)
+    }
+
+    fn read_virt(&mut self, addr: u64, buf: &mut [u8]) -> Result<(), Fault> {
+        self.read_mem(addr, buf, self.cpl() == 3)
+    }
+
+    /// Read system data structure (e.g. GDT), which always accessed as supervisor.
+    fn read_sys(&mut self, addr: u64, buf: &mut [u8]) -> Result<(), Fault> {
+        self.read_mem(addr, buf, false)
+    }
+
+    fn read_mem(&mut self, addr: u64, buf: &mut [u8], user: bool) -> Result<(), Fault> {
+        let access = Access {
+            user,
+            write: false,
+            fetch: false,
+        };
+        let mut off = 0;
+
+        self.watch(addr, buf.len(), false);
+
+        while off < buf.len() {
+            let vaddr = addr.wrapping_add(off as u64);
+            let n = (((vaddr | 0xFFF) + 1 - vaddr) as usize).min(buf.len() - off);
+            let paddr = self
+                .walk(vaddr, Some(access))
+                .map_err(|e| self.page_fault(vaddr, e, access))?;
+
+            self.read_phys(paddr as usize, &mut buf[off..(off + n)])?;
+
+            off += n;
+        }
+
+        Ok(())
+    }
+
+    fn write_virt(&mut self, addr: u64, buf: &[u8]) -> Result<(), Fault> {
+        self.write_mem(addr, buf, self.cpl() == 3)
+    }
+
+    fn write_mem(&mut self, addr: u64, buf: &[u8], user: bool) -> Result<(), Fault> {
+        // Translate the whole range first so we don't end up writing only some part of it.
+        let access = Access {
+            user,
+            write: true,
+            fetch: false,
+        };
+        let mut chunks = [(0usize, 0usize, 0usize); 2];
+        let mut count = 0;
+        let mut off = 0;
+
+        self.watch(addr, buf.len(), true);
+
+        while off < buf.len() {
+            let vaddr = addr.wrapping_add(off as u64);
+            let n = (((vaddr | 0xFFF) + 1 - vaddr) as usize).min(buf.len() - off);
+            let paddr = self
+                .walk(vaddr, Some(access))
+                .map_err(|e| self.page_fault(vaddr, e, access))?;
+
+            chunks[count] = (paddr as usize, off, n);
+            count += 1;
+            off += n;
+        }
+
+        for &(paddr, off, n) in &chunks[..count] {
+            self.write_phys(paddr, &buf[off..(off + n)])?;
+        }
+
+        Ok(())
+    }
+
+    fn read_phys(&mut self, addr: usize, buf: &mut [u8]) -> Result<(), Fault> {
+        let len = NonZero::new(buf.len()).unwrap();
+
+        if let Some(m) = self.emu.ram.lock(addr, len) {
+            unsafe { m.as_ptr().copy_to(buf.as_mut_ptr(), buf.len()) };
+            return Ok(());
+        }
+
+        // Let the VMM handle the access outside RAM or to the RAM that has not been allocated.
+        let io = &mut self.mmio;
+
+        if io.ready && !io.write && io.addr == addr && io.len == buf.len() {
+            buf.copy_from_slice(&io.data[..io.len]);
+            io.ready = false;
+            return Ok(());
+        }
+
+        self.start_mmio(addr, len, false)?;
+
+        Err(Fault::Stop)
+    }
+
+    fn write_phys(&mut self, addr: usize, buf: &[u8]) -> Result<(), Fault> {
+        let len = NonZero::new(buf.len()).unwrap();
+
+        if let Some(mut m) = self.emu.ram.lock(addr, len) {
+            unsafe { m.as_mut_ptr().copy_from(buf.as_ptr(), buf.len()) };
+            return Ok(());
+        }
+
+        // Same as read_phys().
+        self.start_mmio(addr, len, true)?;
+        self.mmio.data[..buf.len()].copy_from_slice(buf);
+
+        Ok(())
+    }
+
+    fn start_mmio(&mut self, addr: usize, len: NonZero<usize>, write: bool) -> Result<(), Fault> {
+        if len.get() > self.mmio.data.len() {
+            return Err(Fault::Error(RunError::MmioTooLarge(addr)));
+        } else if self.exit.is_some() {
+            return Err(Fault::Error(RunError::MultipleMmio(addr)));
+        }
+
+        self.mmio = Mmio {
+            addr,
+            data: [0; 16],
+            len: len.get(),
+            write,
+            ready: false,
+        };
+
+        self.exit = Some(ExitReason::Io);
+
+        Ok(())
+    }
+
+    fn page_fault(&mut self, addr: u64, e: TranslateError, access: Access) -> Fault {
+        let mut code = match e {
+            TranslateError::NotPresent(_) => 0,
+            TranslateError::Protection(_) => 0x1,
+            TranslateError::InvalidTable(_) => {
+                return Fault::Error(RunError::NotAllocated(addr as usize));
+            }
+        };
+
+        if access.write {
+            code |= 0x2;
+        }
+
+        if access.user {
+            code |= 0x4;
+        }
+
+        if access.fetch {
+            code |= 0x10;
+        }
+
+        self.regs.cr2 = addr;
+
+        Fault::Exception(PF_VEC, Some(code))
+    }
+}
+
+/// Reason for an instruction to stop.
+#[derive(Debug)]
+pub enum Fault {
+    /// The instruction need to be re-executed after VMM handled the exit.
+    Stop,
+    Exception(u8, Option<u64>),
+    Error(RunError),
+}
+
+impl From<RunError> for Fault {
+    fn from(value: RunError) -> Self {
+        Self::Error(value)
+    }
+}
+
+/// Instruction prefixes.
+#[derive(Default)]
+struct Prefix {
+    opsize: bool,
+    addr32: bool,
+    lock: bool,
+    rep: u8,
+    seg: Option<SegOverride>,
+    rex: u8,
+}
+
+impl Prefix {
+    fn rex_w(&self) -> bool {
+        self.rex & 8 != 0
+    }
+
+    fn rex_b(&self) -> usize {
+        usize::from(self.rex & 1) << 3
+    }
+
+    /// Returns operand size in bytes.
+    fn osz(&self) -> usize {
+        if self.rex_w() {
+            8
+        } else if self.opsize {
+            2
+        } else {
+            4
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum SegOverride {
+    Fs,
+    Gs,
+}
+
+/// Cursor over the fetched instruction bytes.
+struct Decoder<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    fault: Option<Fault>,
+}
+
+impl Decoder<'_> {
+    fn peek(&mut self) -> Result<u8, Fault> {
+        match self.buf.get(self.pos) {
+            Some(&v) => Ok(v),
+            None => Err(self.fault.take().unwrap_or(Fault::Exception(GP, Some(0)))),
+        }
+    }
+
+    fn u8(&mut self) -> Result<u8, Fault> {
+        let v = self.peek()?;
+
+        self.pos += 1;
+
+        Ok(v)
+    }
+
+    /// Read an immediate value with `size` bytes, zero-extended.
+    fn imm(&mut self, size: usize) -> Result<u64, Fault> {
+        let mut v = 0;
+
+        for i in 0..size {
+            v |= u64::from(self.u8()?) << (i * 8);
+        }
+
+        Ok(v)
+    }
+
+    fn modrm(&mut self, p: &Prefix) -> Result<ModRm, Fault> {
+        let b = self.u8()?;
+        let md = b >> 6;
+        let reg = usize::from((b >> 3) & 7) | (usize::from(p.rex & 4) << 1);
+        let rm = b & 7;
+
+        if md == 3 {
+            return Ok(ModRm {
+                reg,
+                rm: Rm::Reg(usize::from(rm) | p.rex_b()),
+            });
+        }
+
+        // Parse SIB.
+        let (base, index, scale) = if rm == 4 {
+            let sib = self.u8()?;
+            let index = usize::from((sib >> 3) & 7) | (usize::from(p.rex & 2) << 2);
+            let base = sib & 7;
+            let base = if base == 5 && md == 0 {
+                Base::None
+            } else {
+                Base::Reg(usize::from(base) | p.rex_b())
+            };
+
+            (base, (index != 4).then_some(index), sib >> 6)
+        } else if rm == 5 && md == 0 {
+            (Base::Rip, None, 0)
+        } else {
+            (Base::Reg(usize::from(rm) | p.rex_b()), None, 0)
+        };
+
+        // Parse displacement.
+        let disp = match (md, base) {
+            (0, Base::Rip | Base::None) => sx(self.imm(4)?, 4),
+            (0, _) => 0,
+            (1, _) => sx(self.imm(1)?, 1),
+            _ => sx(self.imm(4)?, 4),
+        };
+
+        Ok(ModRm {
+            reg,
+            rm: Rm::Mem {
+                base,
+                index,
+                scale,
+                disp,
+            },
+        })
+    }
+
+    /// Returns address of the next instruction. This must be called after the whole instruction
+    /// has been decoded.
+    fn next(&self, cpu: &EmuCpu) -> u64 {
+        cpu.regs.rip.wrapping_add(self.pos as u64)
+    }
+}
+
+/// Decoded ModRM.
+struct ModRm {
+    reg: usize,
+    rm: Rm,
+}
+
+#[derive(Clone, Copy)]
+enum Rm {
+    Reg(usize),
+    Mem {
+        base: Base,
+        index: Option<usize>,
+        scale: u8,
+        disp: i64,
+    },
+}
+
+#[derive(Clone, Copy)]
+enum Base {
+    Reg(usize),
+    Rip,
+    None,
+}
+
+#[derive(Clone, Copy)]
+enum Operand {
+    Reg(usize),
+    Mem(u64),
+}
+
+fn unknown() -> Fault {
+    Fault::Error(RunError::UnknownInstruction(0, Vec::new()))
+}
+
+fn mask(size: usize) -> u64 {
+    match size {
+        8 => u64::MAX,
+        n => (1 << (n * 8)) - 1,
+    }
+}
+
+fn sign(size: usize) -> u64 {
+    1 << (size * 8 - 1)
+}
+
+/// Sign-extend `v` with `size` bytes.
+fn sx(v: u64, size: usize) -> i64 {
+    let shift = 64 - size * 8;
+
+    ((v << shift) as i64) >> shift
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Emu;
+    use super::*;
+    use crate::Hypervisor;
+
+    #[test]
+    fn alu() {
+        let emu = Emu::new_test();
+        let mut cpu = emu.create_cpu(0).unwrap();
+
+        // mov eax, 5; add eax, 3; mov ecx, eax; sub eax, 8
+        exec(
+            &mut cpu,
+            &[0xB8, 5, 0, 0, 0, 0x83, 0xC0, 3, 0x89, 0xC1, 0x83, 0xE8, 8],
+        )
+        .unwrap();
+
+        assert_eq!(cpu.regs.gpr[RCX], 8);
+        assert_eq!(cpu.regs.gpr[RAX], 0);
+        assert_ne!(cpu.regs.rflags & ZF, 0);
+        assert_eq!(cpu.regs.rflags & CF, 0);
+
+        // cmp eax, 1
+        exec(&mut cpu, &[0x83, 0xF8, 1]).unwrap();
+
+        assert_eq!(cpu.regs.rflags & ZF, 0);
+        assert_ne!(cpu.regs.rflags & CF, 0);
+        assert_ne!(cpu.regs.rflags & SF, 0);
+    }
+
+    #[test]
+    fn mov() {
+        let emu = Emu::new_test();
+        let mut cpu = emu.create_cpu(0).unwrap();
+        let mut buf = [0u8; 8];
+
+        cpu.regs.gpr[RAX] = 0x1122334455667788;
+        cpu.regs.gpr[RCX] = u64::MAX;
+        cpu.regs.gpr[3] = 0x2000;
+
+        // mov [rbx], rax; mov ecx, [rbx]; movzx rdx, byte [rbx]
+        exec(
+            &mut cpu,
+            &[0x48, 0x89, 0x03, 0x8B, 0x0B, 0x48, 0x0F, 0xB6, 0x13],
+        )
+        .unwrap();
+        emu.read(0x2000, &mut buf);
+
+        assert_eq!(u64::from_le_bytes(buf), 0x1122334455667788);
+        assert_eq!(cpu.regs.gpr[RCX], 0x55667788);
+        assert_eq!(cpu.regs.gpr[RDX], 0x88);
+    }
+
+    #[test]
+    fn stack() {
+        let emu = Emu::new_test();
+        let mut cpu = emu.create_cpu(0).unwrap();
+
+        cpu.regs.gpr[RAX] = 0x42;
+        cpu.regs.gpr[RSP] = 0x8000;
+
+        // push rax; pop rcx; call $+5; pop rdx
+        exec(&mut cpu, &[0x50, 0x59, 0xE8, 0, 0, 0, 0, 0x5A]).unwrap();
+
+        assert_eq!(cpu.regs.gpr[RCX], 0x42);
+        assert_eq!(cpu.regs.gpr[RDX], 0x1007);
+        assert_eq!(cpu.regs.gpr[RSP], 0x8000);
+    }
+
+    #[test]
+    fn jump() {
+        let emu = Emu::new_test();
+        let mut cpu = emu.create_cpu(0).unwrap();
+
+        // xor eax, eax; jz $+4; mov al, 1; jnz $+4; mov al, 2
+        exec(&mut cpu, &[0x31, 0xC0, 0x74, 2, 0xB0, 1, 0x75, 2, 0xB0, 2]).unwrap();
+
+        assert_eq!(cpu.regs.gpr[RAX], 2);
+    }
+
+    #[test]
+    fn string() {
+        let emu = Emu::new_test();
+        let mut cpu = emu.create_cpu(0).unwrap();
+        let mut buf = [0u8; 17];
+
+        cpu.regs.gpr[RAX] = 0xAB;
+        cpu.regs.gpr[RCX] = 16;
+        cpu.regs.gpr[RDI] = 0x3000;
+
+        // rep stosb
+        exec(&mut cpu, &[0xF3, 0xAA]).unwrap();
+        emu.read(0x3000, &mut buf);
+
+        assert_eq!(buf[..16], [0xAB; 16]);
+        assert_eq!(buf[16], 0);
+        assert_eq!(cpu.regs.gpr[RCX], 0);
+        assert_eq!(cpu.regs.gpr[RDI], 0x3010);
+    }
+
+    #[test]
+    fn mul_div() {
+        let emu = Emu::new_test();
+        let mut cpu = emu.create_cpu(0).unwrap();
+
+        cpu.regs.gpr[RAX] = 7;
+        cpu.regs.gpr[RCX] = 6;
+
+        // mul ecx
+        exec(&mut cpu, &[0xF7, 0xE1]).unwrap();
+
+        assert_eq!(cpu.regs.gpr[RAX], 42);
+        assert_eq!(cpu.regs.gpr[RDX], 0);
+
+        // div ecx
+        cpu.regs.gpr[RCX] = 0;
+
+        assert!(matches!(
+            exec(&mut cpu, &[0xF7, 0xF1]),
+            Err(Fault::Exception(DE, None))
+        ));
+    }
+
+    #[test]
+    fn shift() {
+        let emu = Emu::new_test();
+        let mut cpu = emu.create_cpu(0).unwrap();
+
+        cpu.regs.gpr[RAX] = 1;
+
+        // shl eax, 4
+        exec(&mut cpu, &[0xC1, 0xE0, 4]).unwrap();
+
+        assert_eq!(cpu.regs.gpr[RAX], 16);
+    }
+
+    #[test]
+    fn msr() {
+        let emu = Emu::new_test();
+        let mut cpu = emu.create_cpu(0).unwrap();
+
+        cpu.regs.gpr[RAX] = 0x2345;
+        cpu.regs.gpr[RCX] = 0xC0000082;
+        cpu.regs.gpr[RDX] = 1;
+
+        // wrmsr
+        exec(&mut cpu, &[0x0F, 0x30]).unwrap();
+
+        assert_eq!(cpu.regs.lstar, 0x100002345);
+
+        // rdmsr
+        cpu.regs.gpr[RAX] = 0;
+        cpu.regs.gpr[RDX] = 0;
+
+        exec(&mut cpu, &[0x0F, 0x32]).unwrap();
+
+        assert_eq!(cpu.regs.gpr[RAX], 0x2345);
+        assert_eq!(cpu.regs.gpr[RDX], 1);
+
+        // Unknown MSR.
+        cpu.regs.gpr[RCX] = 0x1234;
+
+        assert!(matches!(
+            exec(&mut cpu, &[0x0F, 0x32]),
+            Err(Fault::Exception(GP, Some(0)))
+        ));
+    }
+
+    #[test]
+    fn invalid() {
+        let emu = Emu::new_test();
+        let mut cpu = emu.create_cpu(0).unwrap();
+
+        // ud2
+        assert!(matches!(
+            exec(&mut cpu, &[0x0F, 0x0B]),
+            Err(Fault::Exception(UD, None))
+        ));
+    }
+
+    #[test]
+    fn page_fault() {
+        let emu = Emu::new_test();
+        let mut cpu = emu.create_cpu(0).unwrap();
+
+        emu.map(0x1000, 0x1000 | 0x5);
+        emu.map(0x6000, 0x6000 | 0x5);
+
+        cpu.regs.cr0 |= 0x80000000;
+        cpu.regs.cr3 = Emu::TEST_CR3;
+        cpu.regs.cs.selector = 0x23;
+        cpu.regs.gpr[3] = 0x6000;
+
+        // mov [rbx], eax
+        assert!(matches!(
+            exec(&mut cpu, &[0x89, 0x03]),
+            Err(Fault::Exception(PF_VEC, Some(0x7)))
+        ));
+        assert_eq!(cpu.regs.cr2, 0x6000);
+
+        cpu.regs.gpr[3] = 0x8000;
+
+        assert!(matches!(
+            exec(&mut cpu, &[0x89, 0x03]),
+            Err(Fault::Exception(PF_VEC, Some(0x6)))
+        ));
+        assert_eq!(cpu.regs.cr2, 0x8000);
+    }
+
+    #[test]
+    fn deliver_from_user() {
+        let emu = Emu::new_test();
+        let mut cpu = emu.create_cpu(0).unwrap();
+        let mut tss = [0u8; 16];
+
+        // Setup GDT with kernel code, user data, user code and TSS.
+        tss[..8].copy_from_slice(&0x0000890310000067u64.to_le_bytes());
+
+        emu.write(0x30008, &0x00209A0000000000u64.to_le_bytes());
+        emu.write(0x30018, &0x0000F20000000000u64.to_le_bytes());
+        emu.write(0x30020, &0x0020FA0000000000u64.to_le_bytes());
+        emu.write(0x30028, &tss);
+
+        // Set RSP0 on TSS and #UD handler on IDT.
+        emu.write(0x31004, &0x9000u64.to_le_bytes());
+        emu.write(
+            0x32000 + usize::from(UD) * 16,
+            &0x8E0000085000u64.to_le_bytes(),
+        );
+
+        cpu.regs.gdtr = (0x30000, 0x37);
+        cpu.regs.idtr = (0x32000, 0xFFF);
+        cpu.regs.tr = 0x28;
+        cpu.regs.cs.selector = 0x23;
+        cpu.regs.ss.selector = 0x1B;
+        cpu.regs.rflags = 0x202;
+        cpu.regs.gpr[RSP] = 0x7000;
+
+        // ud2
+        assert!(matches!(
+            exec(&mut cpu, &[0x0F, 0x0B]),
+            Err(Fault::Exception(UD, None))
+        ));
+
+        cpu.deliver(UD, None).unwrap();
+
+        assert_eq!(cpu.regs.rip, 0x5000);
+        assert_eq!(cpu.regs.cs.selector, 0x08);
+        assert_eq!(cpu.regs.ss.selector, 0);
+        assert_eq!(cpu.regs.gpr[RSP], 0x9000 - 40);
+        assert_eq!(cpu.regs.rflags & IF, 0);
+
+        let mut frame = [0u8; 40];
+
+        emu.read(0x9000 - 40, &mut frame);
+
+        let frame: Vec<u64> = frame
+            .chunks(8)
+            .map(|v| u64::from_le_bytes(v.try_into().unwrap()))
+            .collect();
+
+        assert_eq!(frame, [0x1000, 0x23, 0x202, 0x7000, 0x1B]);
+
+        // iretq
+        emu.write(0x5000, &[0x48, 0xCF]);
+        cpu.step().unwrap();
+
+        assert_eq!(cpu.regs.rip, 0x1000);
+        assert_eq!(cpu.regs.cs.selector, 0x23);
+        assert_eq!(cpu.regs.ss.selector, 0x1B);
+        assert_eq!(cpu.regs.gpr[RSP], 0x7000);
+        assert_ne!(cpu.regs.rflags & IF, 0);
+    }
+
+    /// Execute `code` at 0x1000 until RIP reach the end of it.
+    fn exec(cpu: &mut EmuCpu, code: &[u8]) -> Result<(), Fault> {
+        let end = 0x1000 + code.len() as u64;
+
+        cpu.emu.write(0x1000, code);
+        cpu.regs.rip = 0x1000;
+
+        while cpu.regs.rip != end {
+            cpu.step()?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+use self::cpu::EmuCpu;
+use self::states::Regs;
+use crate::{CpuFeats, FeatLeaf, HvError, Hypervisor, HypervisorExt, Ram};
+use std::num::NonZero;
+use std::sync::Mutex;
+use std::time::Instant;
+use thiserror::Error;
+
+mod cpu;
+mod exec;
+mod states;
+
+/// Create a [`Hypervisor`] that interpret the guest code in software instead of using the
+/// hypervisor provided by the OS.
+///
+/// This is a lot slower than the native one but it does not require any support from the host,
+/// which make it possible to run the VMM on a machine without hardware virtualization. Only x86-64
+/// long mode is supported.
+///
+/// The arguments have the same meaning as [`crate::new()`].
+///
+/// # Panics
+/// If `page_size` is not power of two.
+pub fn new_emulator(
+    cpu: usize,
+    ram_size: NonZero<usize>,
+    page_size: NonZero<usize>,
+    debug: bool,
+) -> Result<impl HypervisorExt, HvError> {
+    let ram = Ram::new(page_size, ram_size, ())?;
+    let cpus = (0..cpu).map(|_| Mutex::default()).collect();
+
+    Ok(Emu {
+        feats: default_feats(),
+        cpus,
+        ram,
+        atomic: Mutex::new(()),
+        debug,
+        started: Instant::now(),
+    })
+}
+
+/// Returns CPUID leaves of the emulated CPU.
+///
+/// We only report the features that we actually emulate so the kernel will not try to use
+/// something we don't have.
+fn default_feats() -> CpuFeats {
+    // Use the same vendor as the host so the kernel see a real name.
+    let r = std::arch::x86_64::__cpuid(0);
+    let basic = |id, eax, ebx, ecx, edx| FeatLeaf {
+        id,
+        eax,
+        ebx,
+        ecx,
+        edx,
+    };
+
+    vec![
+        basic(0, 1, r.ebx, r.ecx, r.edx),
+        // Family 15h, FPU, TSC, MSR, PAE, CX8, CMOV and CLFLUSH.
+        basic(1, 0x00610F00, 0x800, 0, 0x0008_8171),
+        basic(0x80000000, 0x80000001, 0, 0, 0),
+        // SYSCALL/SYSRET, NX and long mode.
+        basic(0x80000001, 0, 0, 0, 0x2010_0800),
+    ]
+}
+
+/// Implementation of [`Hypervisor`] using a software interpreter.
+struct Emu {
+    feats: CpuFeats,
+    cpus: Vec<Mutex<Regs>>,
+    ram: Ram,
+    atomic: Mutex<()>,
+    debug: bool,
+    started: Instant,
+}
+
+impl Hypervisor for Emu {
+    type Cpu<'a> = EmuCpu<'a>;
+    type CpuErr = EmuCpuError;
+
+    fn cpu_features(&self) -> &CpuFeats {
+        &self.feats
+    }
+
+    fn ram(&self) -> &Ram {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut Ram {
+        &mut self.ram
+    }
+
+    fn create_cpu(&self, id: usize) -> Result<Self::Cpu<'_>, HvError> {
+        let cpu = self.cpus.get(id).ok_or(HvError::InvalidCpuId)?;
+        let regs = cpu.try_lock().map_err(|_| HvError::DuplicatedCpuId)?;
+
+        Ok(EmuCpu::new(id, self, regs))
+    }
+}
+
+impl HypervisorExt for Emu {
+    fn set_cpuid(&mut self, leaf: FeatLeaf) -> Result<(), HvError> {
+        match self.feats.iter_mut().find(|f| f.id == leaf.id) {
+            Some(f) => *f = leaf,
+            None => self.feats.push(leaf),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl Emu {
+    /// Value of CR3 for the page tables populated by [`Self::map()`].
+    const TEST_CR3: u64 = 0x10000;
+
+    /// Create an [`Emu`] with a single CPU and 1MB of RAM, which is all allocated.
+    fn new_test() -> Self {
+        let page = NonZero::new(0x1000).unwrap();
+        let ram = Ram::new(page, NonZero::new(0x100000).unwrap(), ()).unwrap();
+
+        ram.alloc(0, ram.len()).unwrap();
+
+        Self {
+            feats: default_feats(),
+            cpus: vec![Mutex::default()],
+            ram,
+            atomic: Mutex::new(()),
+            debug: false,
+            started: Instant::now(),
+        }
+    }
+
+    fn write(&self, addr: usize, data: &[u8]) {
+        let mut m = self
+            .ram
+            .lock(addr, NonZero::new(data.len()).unwrap())
+            .unwrap();
+
+        unsafe { m.as_mut_ptr().copy_from(data.as_ptr(), data.len()) };
+    }
+
+    fn read(&self, addr: usize, buf: &mut [u8]) {
+        let m = self
+            .ram
+            .lock(addr, NonZero::new(buf.len()).unwrap())
+            .unwrap();
+
+        unsafe { m.as_ptr().copy_to(buf.as_mut_ptr(), buf.len()) };
+    }
+
+    /// Set PTE for `vaddr` to `entry`. Only the first 2MB can be mapped.
+    fn map(&self, vaddr: usize, entry: u64) {
+        let pdpt = Self::TEST_CR3 + 0x1000;
+        let pd = pdpt + 0x1000;
+        let pt = pd + 0x1000;
+
+        self.write(Self::TEST_CR3 as usize, &(pdpt | 7).to_le_bytes());
+        self.write(pdpt as usize, &(pd | 7).to_le_bytes());
+        self.write(pd as usize, &(pt | 7).to_le_bytes());
+        self.write(pt as usize + (vaddr >> 12) * 8, &entry.to_le_bytes());
+    }
+}
+
+/// Implementation of [`Hypervisor::CpuErr`].
+#[derive(Debug, Error)]
+pub enum EmuCpuError {}
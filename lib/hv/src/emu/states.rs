@@ -0,0 +1,806 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+use crate::{CpuCommit, CpuStates};
+use std::convert::Infallible;
+use x86_64::{Efer, Rflags};
+
+/// Architectural states of an emulated CPU.
+#[derive(Clone)]
+pub struct Regs {
+    pub gpr: [u64; 16],
+    pub rip: u64,
+    pub rflags: u64,
+    pub cr0: u64,
+    pub cr2: u64,
+    pub cr3: u64,
+    pub cr4: u64,
+    pub efer: u64,
+    pub cs: Segment,
+    pub ds: Segment,
+    pub es: Segment,
+    pub fs: Segment,
+    pub gs: Segment,
+    pub ss: Segment,
+    pub tr: u16,
+    pub gdtr: (u64, u16),
+    pub idtr: (u64, u16),
+    pub fs_base: u64,
+    pub gs_base: u64,
+    pub kernel_gs_base: u64,
+    pub star: u64,
+    pub lstar: u64,
+    pub cstar: u64,
+    pub sfmask: u64,
+    pub fpr: [[u8; 10]; 8],
+    pub fcw: u16,
+    pub fsw: u16,
+    pub ftwx: u8,
+    pub last_ip: u64,
+    pub last_dp: u64,
+    pub last_opcode: u16,
+    pub xmm: [u128; 16],
+    pub mxcsr: u32,
+}
+
+impl Default for Regs {
+    fn default() -> Self {
+        Self {
+            gpr: [0; 16],
+            rip: 0xFFF0,
+            rflags: 0x2,
+            cr0: 0x60000010,
+            cr2: 0,
+            cr3: 0,
+            cr4: 0,
+            efer: 0,
+            cs: Segment::default(),
+            ds: Segment::default(),
+            es: Segment::default(),
+            fs: Segment::default(),
+            gs: Segment::default(),
+            ss: Segment::default(),
+            tr: 0,
+            gdtr: (0, 0xFFFF),
+            idtr: (0, 0xFFFF),
+            fs_base: 0,
+            gs_base: 0,
+            kernel_gs_base: 0,
+            star: 0,
+            lstar: 0,
+            cstar: 0,
+            sfmask: 0,
+            fpr: [[0; 10]; 8],
+            fcw: 0x37F,
+            fsw: 0,
+            ftwx: 0,
+            last_ip: 0,
+            last_dp: 0,
+            last_opcode: 0,
+            xmm: [0; 16],
+            mxcsr: 0x1F80,
+        }
+    }
+}
+
+impl Regs {
+    /// Serialize all registers for a snapshot.
+    pub fn save(&self) -> Vec<u8> {
+        let mut w = Vec::new();
+        let u64s = |w: &mut Vec<u8>, v: &[u64]| {
+            for v in v {
+                w.extend_from_slice(&v.to_le_bytes());
+            }
+        };
+
+        u64s(&mut w, &self.gpr);
+        u64s(
+            &mut w,
+            &[
+                self.rip,
+                self.rflags,
+                self.cr0,
+                self.cr2,
+                self.cr3,
+                self.cr4,
+                self.efer,
+            ],
+        );
+
+        for s in [self.cs, self.ds, self.es, self.fs, self.gs, self.ss] {
+            s.save(&mut w);
+        }
+
+        w.extend_from_slice(&self.tr.to_le_bytes());
+
+        for (base, limit) in [self.gdtr, self.idtr] {
+            w.extend_from_slice(&base.to_le_bytes());
+            w.extend_from_slice(&limit.to_le_bytes());
+        }
+
+        u64s(
+            &mut w,
+            &[
+                self.fs_base,
+                self.gs_base,
+                self.kernel_gs_base,
+                self.star,
+                self.lstar,
+                self.cstar,
+                self.sfmask,
+            ],
+        );
+
+        w.extend(self.fpr.as_flattened());
+        w.extend_from_slice(&self.fcw.to_le_bytes());
+        w.extend_from_slice(&self.fsw.to_le_bytes());
+        w.push(self.ftwx);
+        u64s(&mut w, &[self.last_ip, self.last_dp]);
+        w.extend_from_slice(&self.last_opcode.to_le_bytes());
+
+        for v in self.xmm {
+            w.extend_from_slice(&v.to_le_bytes());
+        }
+
+        w.extend_from_slice(&self.mxcsr.to_le_bytes());
+        w
+    }
+
+    /// Deserialize the data produced by [`Regs::save()`]. Returns [`None`] if `data` is not valid.
+    pub fn load(data: &[u8]) -> Option<Self> {
+        let mut r = Reader(data);
+        let mut gpr = [0; 16];
+
+        for v in &mut gpr {
+            *v = r.u64()?;
+        }
+
+        let regs = Self {
+            gpr,
+            rip: r.u64()?,
+            rflags: r.u64()?,
+            cr0: r.u64()?,
+            cr2: r.u64()?,
+            cr3: r.u64()?,
+            cr4: r.u64()?,
+            efer: r.u64()?,
+            cs: Segment::load(&mut r)?,
+            ds: Segment::load(&mut r)?,
+            es: Segment::load(&mut r)?,
+            fs: Segment::load(&mut r)?,
+            gs: Segment::load(&mut r)?,
+            ss: Segment::load(&mut r)?,
+            tr: r.u16()?,
+            gdtr: (r.u64()?, r.u16()?),
+            idtr: (r.u64()?, r.u16()?),
+            fs_base: r.u64()?,
+            gs_base: r.u64()?,
+            kernel_gs_base: r.u64()?,
+            star: r.u64()?,
+            lstar: r.u64()?,
+            cstar: r.u64()?,
+            sfmask: r.u64()?,
+            fpr: {
+                let mut v = [[0; 10]; 8];
+
+                for v in &mut v {
+                    *v = r.array()?;
+                }
+
+                v
+            },
+            fcw: r.u16()?,
+            fsw: r.u16()?,
+            ftwx: r.array::<1>()?[0],
+            last_ip: r.u64()?,
+            last_dp: r.u64()?,
+            last_opcode: r.u16()?,
+            xmm: {
+                let mut v = [0; 16];
+
+                for v in &mut v {
+                    *v = u128::from_le_bytes(r.array()?);
+                }
+
+                v
+            },
+            mxcsr: u32::from_le_bytes(r.array()?),
+        };
+
+        r.0.is_empty().then_some(regs)
+    }
+}
+
+/// Visible part of a segment register.
+#[derive(Clone, Copy, Default)]
+pub struct Segment {
+    pub selector: u16,
+    pub ty: u8,
+    pub dpl: u8,
+    pub present: bool,
+    pub l: bool,
+    pub db: bool,
+}
+
+impl Segment {
+    fn save(&self, w: &mut Vec<u8>) {
+        w.extend_from_slice(&self.selector.to_le_bytes());
+        w.extend_from_slice(&[
+            self.ty,
+            self.dpl,
+            self.present.into(),
+            self.l.into(),
+            self.db.into(),
+        ]);
+    }
+
+    fn load(r: &mut Reader) -> Option<Self> {
+        let selector = r.u16()?;
+        let [ty, dpl, present, l, db] = r.array()?;
+
+        Some(Self {
+            selector,
+            ty,
+            dpl,
+            present: present != 0,
+            l: l != 0,
+            db: db != 0,
+        })
+    }
+}
+
+/// Cursor to read the data produced by [`Regs::save()`].
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn array<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let (v, rest) = self.0.split_first_chunk()?;
+
+        self.0 = rest;
+
+        Some(*v)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.array().map(u16::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.array().map(u64::from_le_bytes)
+    }
+}
+
+/// Implementation of [`CpuStates`] for the emulator.
+///
+/// All modifications are done on a copy and only visible to the CPU after
+/// [`CpuCommit::commit()`].
+pub struct EmuStates<'a> {
+    dst: &'a mut Regs,
+    regs: Regs,
+}
+
+impl<'a> EmuStates<'a> {
+    pub fn new(dst: &'a mut Regs) -> Self {
+        let regs = dst.clone();
+
+        Self { dst, regs }
+    }
+}
+
+impl CpuStates for EmuStates<'_> {
+    type Err = Infallible;
+
+    fn get_rax(&mut self) -> Result<usize, Self::Err> {
+        Ok(self.regs.gpr[0] as usize)
+    }
+
+    fn set_rax(&mut self, v: usize) {
+        self.regs.gpr[0] = v as u64;
+    }
+
+    fn get_rbx(&mut self) -> Result<usize, Self::Err> {
+        Ok(self.regs.gpr[3] as usize)
+    }
+
+    fn set_rbx(&mut self, v: usize) {
+        self.regs.gpr[3] = v as u64;
+    }
+
+    fn get_rcx(&mut self) -> Result<usize, Self::Err> {
+        Ok(self.regs.gpr[1] as usize)
+    }
+
+    fn set_rcx(&mut self, v: usize) {
+        self.regs.gpr[1] = v as u64;
+    }
+
+    fn get_rdx(&mut self) -> Result<usize, Self::Err> {
+        Ok(self.regs.gpr[2] as usize)
+    }
+
+    fn set_rdx(&mut self, v: usize) {
+        self.regs.gpr[2] = v as u64;
+    }
+
+    fn get_rbp(&mut self) -> Result<usize, Self::Err> {
+        Ok(self.regs.gpr[5] as usize)
+    }
+
+    fn set_rbp(&mut self, v: usize) {
+        self.regs.gpr[5] = v as u64;
+    }
+
+    fn get_r8(&mut self) -> Result<usize, Self::Err> {
+        Ok(self.regs.gpr[8] as usize)
+    }
+
+    fn set_r8(&mut self, v: usize) {
+        self.regs.gpr[8] = v as u64;
+    }
+
+    fn get_r9(&mut self) -> Result<usize, Self::Err> {
+        Ok(self.regs.gpr[9] as usize)
+    }
+
+    fn set_r9(&mut self, v: usize) {
+        self.regs.gpr[9] = v as u64;
+    }
+
+    fn get_r10(&mut self) -> Result<usize, Self::Err> {
+        Ok(self.regs.gpr[10] as usize)
+    }
+
+    fn set_r10(&mut self, v: usize) {
+        self.regs.gpr[10] = v as u64;
+    }
+
+    fn get_r11(&mut self) -> Result<usize, Self::Err> {
+        Ok(self.regs.gpr[11] as usize)
+    }
+
+    fn set_r11(&mut self, v: usize) {
+        self.regs.gpr[11] = v as u64;
+    }
+
+    fn get_r12(&mut self) -> Result<usize, Self::Err> {
+        Ok(self.regs.gpr[12] as usize)
+    }
+
+    fn set_r12(&mut self, v: usize) {
+        self.regs.gpr[12] = v as u64;
+    }
+
+    fn get_r13(&mut self) -> Result<usize, Self::Err> {
+        Ok(self.regs.gpr[13] as usize)
+    }
+
+    fn set_r13(&mut self, v: usize) {
+        self.regs.gpr[13] = v as u64;
+    }
+
+    fn get_r14(&mut self) -> Result<usize, Self::Err> {
+        Ok(self.regs.gpr[14] as usize)
+    }
+
+    fn set_r14(&mut self, v: usize) {
+        self.regs.gpr[14] = v as u64;
+    }
+
+    fn get_r15(&mut self) -> Result<usize, Self::Err> {
+        Ok(self.regs.gpr[15] as usize)
+    }
+
+    fn set_r15(&mut self, v: usize) {
+        self.regs.gpr[15] = v as u64;
+    }
+
+    fn get_rdi(&mut self) -> Result<usize, Self::Err> {
+        Ok(self.regs.gpr[7] as usize)
+    }
+
+    fn set_rdi(&mut self, v: usize) {
+        self.regs.gpr[7] = v as u64;
+    }
+
+    fn get_rsi(&mut self) -> Result<usize, Self::Err> {
+        Ok(self.regs.gpr[6] as usize)
+    }
+
+    fn set_rsi(&mut self, v: usize) {
+        self.regs.gpr[6] = v as u64;
+    }
+
+    fn get_rsp(&mut self) -> Result<usize, Self::Err> {
+        Ok(self.regs.gpr[4] as usize)
+    }
+
+    fn set_rsp(&mut self, v: usize) {
+        self.regs.gpr[4] = v as u64;
+    }
+
+    fn get_rip(&mut self) -> Result<usize, Self::Err> {
+        Ok(self.regs.rip as usize)
+    }
+
+    fn set_rip(&mut self, v: usize) {
+        self.regs.rip = v as u64;
+    }
+
+    fn set_cr0(&mut self, v: usize) {
+        self.regs.cr0 = v as u64;
+    }
+
+    fn get_cr2(&mut self) -> Result<usize, Self::Err> {
+        Ok(self.regs.cr2 as usize)
+    }
+
+    fn get_cr3(&mut self) -> Result<usize, Self::Err> {
+        Ok(self.regs.cr3 as usize)
+    }
+
+    fn set_cr3(&mut self, v: usize) {
+        self.regs.cr3 = v as u64;
+    }
+
+    fn set_cr4(&mut self, v: usize) {
+        self.regs.cr4 = v as u64;
+    }
+
+    fn get_rflags(&mut self) -> Result<Rflags, Self::Err> {
+        Ok(self.regs.rflags.into())
+    }
+
+    fn set_rflags(&mut self, v: Rflags) {
+        self.regs.rflags = v.into_bits();
+    }
+
+    fn set_efer(&mut self, v: Efer) {
+        self.regs.efer = v.into_bits();
+    }
+
+    fn get_cs(&mut self) -> Result<u16, Self::Err> {
+        Ok(self.regs.cs.selector)
+    }
+
+    fn set_cs_selector(&mut self, v: u16) {
+        self.regs.cs.selector = v;
+    }
+
+    fn set_cs(&mut self, ty: u8, dpl: u8, p: bool, l: bool, d: bool) {
+        self.regs.cs.ty = ty;
+        self.regs.cs.dpl = dpl;
+        self.regs.cs.present = p;
+        self.regs.cs.l = l;
+        self.regs.cs.db = d;
+    }
+
+    fn get_ds(&mut self) -> Result<u16, Self::Err> {
+        Ok(self.regs.ds.selector)
+    }
+
+    fn set_ds_selector(&mut self, v: u16) {
+        self.regs.ds.selector = v;
+    }
+
+    fn set_ds(&mut self, p: bool) {
+        self.regs.ds.present = p;
+    }
+
+    fn get_es(&mut self) -> Result<u16, Self::Err> {
+        Ok(self.regs.es.selector)
+    }
+
+    fn set_es_selector(&mut self, v: u16) {
+        self.regs.es.selector = v;
+    }
+
+    fn set_es(&mut self, p: bool) {
+        self.regs.es.present = p;
+    }
+
+    fn get_fs(&mut self) -> Result<u16, Self::Err> {
+        Ok(self.regs.fs.selector)
+    }
+
+    fn set_fs_selector(&mut self, v: u16) {
+        self.regs.fs.selector = v;
+    }
+
+    fn set_fs(&mut self, p: bool) {
+        self.regs.fs.present = p;
+    }
+
+    fn get_gs(&mut self) -> Result<u16, Self::Err> {
+        Ok(self.regs.gs.selector)
+    }
+
+    fn set_gs_selector(&mut self, v: u16) {
+        self.regs.gs.selector = v;
+    }
+
+    fn set_gs(&mut self, p: bool) {
+        self.regs.gs.present = p;
+    }
+
+    fn get_ss(&mut self) -> Result<u16, Self::Err> {
+        Ok(self.regs.ss.selector)
+    }
+
+    fn set_ss_selector(&mut self, v: u16) {
+        self.regs.ss.selector = v;
+    }
+
+    fn set_ss(&mut self, p: bool) {
+        self.regs.ss.present = p;
+    }
+
+    fn get_st0(&mut self) -> Result<[u8; 10], Self::Err> {
+        Ok(self.regs.fpr[0])
+    }
+
+    fn set_st0(&mut self, v: [u8; 10]) {
+        self.regs.fpr[0] = v;
+    }
+
+    fn get_st1(&mut self) -> Result<[u8; 10], Self::Err> {
+        Ok(self.regs.fpr[1])
+    }
+
+    fn set_st1(&mut self, v: [u8; 10]) {
+        self.regs.fpr[1] = v;
+    }
+
+    fn get_st2(&mut self) -> Result<[u8; 10], Self::Err> {
+        Ok(self.regs.fpr[2])
+    }
+
+    fn set_st2(&mut self, v: [u8; 10]) {
+        self.regs.fpr[2] = v;
+    }
+
+    fn get_st3(&mut self) -> Result<[u8; 10], Self::Err> {
+        Ok(self.regs.fpr[3])
+    }
+
+    fn set_st3(&mut self, v: [u8; 10]) {
+        self.regs.fpr[3] = v;
+    }
+
+    fn get_st4(&mut self) -> Result<[u8; 10], Self::Err> {
+        Ok(self.regs.fpr[4])
+    }
+
+    fn set_st4(&mut self, v: [u8; 10]) {
+        self.regs.fpr[4] = v;
+    }
+
+    fn get_st5(&mut self) -> Result<[u8; 10], Self::Err> {
+        Ok(self.regs.fpr[5])
+    }
+
+    fn set_st5(&mut self, v: [u8; 10]) {
+        self.regs.fpr[5] = v;
+    }
+
+    fn get_st6(&mut self) -> Result<[u8; 10], Self::Err> {
+        Ok(self.regs.fpr[6])
+    }
+
+    fn set_st6(&mut self, v: [u8; 10]) {
+        self.regs.fpr[6] = v;
+    }
+
+    fn get_st7(&mut self) -> Result<[u8; 10], Self::Err> {
+        Ok(self.regs.fpr[7])
+    }
+
+    fn set_st7(&mut self, v: [u8; 10]) {
+        self.regs.fpr[7] = v;
+    }
+
+    fn get_fcw(&mut self) -> Result<u32, Self::Err> {
+        Ok(self.regs.fcw.into())
+    }
+
+    fn set_fcw(&mut self, v: u32) {
+        self.regs.fcw = v as u16;
+    }
+
+    fn get_fsw(&mut self) -> Result<u32, Self::Err> {
+        Ok(self.regs.fsw.into())
+    }
+
+    fn set_fsw(&mut self, v: u32) {
+        self.regs.fsw = v as u16;
+    }
+
+    fn get_ftwx(&mut self) -> Result<u32, Self::Err> {
+        Ok(self.regs.ftwx.into())
+    }
+
+    fn set_ftwx(&mut self, v: u32) {
+        self.regs.ftwx = v as u8;
+    }
+
+    fn get_fiseg(&mut self) -> Result<u32, Self::Err> {
+        Ok((self.regs.last_ip >> 32) as u32)
+    }
+
+    fn set_fiseg(&mut self, v: u32) {
+        self.regs.last_ip = (self.regs.last_ip & 0xFFFFFFFF) | u64::from(v) << 32;
+    }
+
+    fn get_fioff(&mut self) -> Result<u32, Self::Err> {
+        Ok((self.regs.last_ip & 0xFFFFFFFF) as u32)
+    }
+
+    fn set_fioff(&mut self, v: u32) {
+        self.regs.last_ip = (self.regs.last_ip & !0xFFFFFFFF) | u64::from(v);
+    }
+
+    fn get_foseg(&mut self) -> Result<u32, Self::Err> {
+        Ok((self.regs.last_dp >> 32) as u32)
+    }
+
+    fn set_foseg(&mut self, v: u32) {
+        self.regs.last_dp = (self.regs.last_dp & 0xFFFFFFFF) | u64::from(v) << 32;
+    }
+
+    fn get_fooff(&mut self) -> Result<u32, Self::Err> {
+        Ok((self.regs.last_dp & 0xFFFFFFFF) as u32)
+    }
+
+    fn set_fooff(&mut self, v: u32) {
+        self.regs.last_dp = (self.regs.last_dp & !0xFFFFFFFF) | u64::from(v);
+    }
+
+    fn get_fop(&mut self) -> Result<u32, Self::Err> {
+        Ok(self.regs.last_opcode.into())
+    }
+
+    fn set_fop(&mut self, v: u32) {
+        self.regs.last_opcode = v as u16;
+    }
+
+    fn get_xmm0(&mut self) -> Result<u128, Self::Err> {
+        Ok(self.regs.xmm[0])
+    }
+
+    fn set_xmm0(&mut self, v: u128) {
+        self.regs.xmm[0] = v;
+    }
+
+    fn get_xmm1(&mut self) -> Result<u128, Self::Err> {
+        Ok(self.regs.xmm[1])
+    }
+
+    fn set_xmm1(&mut self, v: u128) {
+        self.regs.xmm[1] = v;
+    }
+
+    fn get_xmm2(&mut self) -> Result<u128, Self::Err> {
+        Ok(self.regs.xmm[2])
+    }
+
+    fn set_xmm2(&mut self, v: u128) {
+        self.regs.xmm[2] = v;
+    }
+
+    fn get_xmm3(&mut self) -> Result<u128, Self::Err> {
+        Ok(self.regs.xmm[3])
+    }
+
+    fn set_xmm3(&mut self, v: u128) {
+        self.regs.xmm[3] = v;
+    }
+
+    fn get_xmm4(&mut self) -> Result<u128, Self::Err> {
+        Ok(self.regs.xmm[4])
+    }
+
+    fn set_xmm4(&mut self, v: u128) {
+        self.regs.xmm[4] = v;
+    }
+
+    fn get_xmm5(&mut self) -> Result<u128, Self::Err> {
+        Ok(self.regs.xmm[5])
+    }
+
+    fn set_xmm5(&mut self, v: u128) {
+        self.regs.xmm[5] = v;
+    }
+
+    fn get_xmm6(&mut self) -> Result<u128, Self::Err> {
+        Ok(self.regs.xmm[6])
+    }
+
+    fn set_xmm6(&mut self, v: u128) {
+        self.regs.xmm[6] = v;
+    }
+
+    fn get_xmm7(&mut self) -> Result<u128, Self::Err> {
+        Ok(self.regs.xmm[7])
+    }
+
+    fn set_xmm7(&mut self, v: u128) {
+        self.regs.xmm[7] = v;
+    }
+
+    fn get_xmm8(&mut self) -> Result<u128, Self::Err> {
+        Ok(self.regs.xmm[8])
+    }
+
+    fn set_xmm8(&mut self, v: u128) {
+        self.regs.xmm[8] = v;
+    }
+
+    fn get_xmm9(&mut self) -> Result<u128, Self::Err> {
+        Ok(self.regs.xmm[9])
+    }
+
+    fn set_xmm9(&mut self, v: u128) {
+        self.regs.xmm[9] = v;
+    }
+
+    fn get_xmm10(&mut self) -> Result<u128, Self::Err> {
+        Ok(self.regs.xmm[10])
+    }
+
+    fn set_xmm10(&mut self, v: u128) {
+        self.regs.xmm[10] = v;
+    }
+
+    fn get_xmm11(&mut self) -> Result<u128, Self::Err> {
+        Ok(self.regs.xmm[11])
+    }
+
+    fn set_xmm11(&mut self, v: u128) {
+        self.regs.xmm[11] = v;
+    }
+
+    fn get_xmm12(&mut self) -> Result<u128, Self::Err> {
+        Ok(self.regs.xmm[12])
+    }
+
+    fn set_xmm12(&mut self, v: u128) {
+        self.regs.xmm[12] = v;
+    }
+
+    fn get_xmm13(&mut self) -> Result<u128, Self::Err> {
+        Ok(self.regs.xmm[13])
+    }
+
+    fn set_xmm13(&mut self, v: u128) {
+        self.regs.xmm[13] = v;
+    }
+
+    fn get_xmm14(&mut self) -> Result<u128, Self::Err> {
+        Ok(self.regs.xmm[14])
+    }
+
+    fn set_xmm14(&mut self, v: u128) {
+        self.regs.xmm[14] = v;
+    }
+
+    fn get_xmm15(&mut self) -> Result<u128, Self::Err> {
+        Ok(self.regs.xmm[15])
+    }
+
+    fn set_xmm15(&mut self, v: u128) {
+        self.regs.xmm[15] = v;
+    }
+
+    fn get_mxcsr(&mut self) -> Result<u32, Self::Err> {
+        Ok(self.regs.mxcsr)
+    }
+
+    fn set_mxcsr(&mut self, v: u32) {
+        self.regs.mxcsr = v;
+    }
+}
+
+impl CpuCommit for EmuStates<'_> {
+    fn commit(self) -> Result<(), Self::Err> {
+        *self.dst = self.regs;
+
+        Ok(())
+    }
+}
@@ -1,5 +1,7 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 pub use self::arch::*;
+#[cfg(target_arch = "x86_64")]
+pub use self::emu::new_emulator;
 pub use self::os::{HvError, new};
 pub use self::ram::*;
 
@@ -8,6 +10,8 @@ use std::error::Error;
 #[cfg_attr(target_arch = "aarch64", path = "aarch64.rs")]
 #[cfg_attr(target_arch = "x86_64", path = "x86_64.rs")]
 mod arch;
+#[cfg(target_arch = "x86_64")]
+mod emu;
 #[cfg_attr(target_os = "linux", path = "linux/mod.rs")]
 #[cfg_attr(target_os = "macos", path = "macos/mod.rs")]
 #[cfg_attr(target_os = "windows", path = "windows/mod.rs")]
@@ -41,10 +45,54 @@ pub trait Cpu {
     where
         Self: 'a;
     type TranslateErr: Error + Send + Sync + 'static;
+    type SnapshotErr: Error + Send + Sync + 'static;
+    type DebugErr: Error + Send + Sync + 'static;
+    type InterruptErr: Error + Send + Sync + 'static;
+    type Kicker: CpuKicker;
 
     fn id(&self) -> usize;
     fn states(&mut self) -> Result<Self::States<'_>, Self::GetStatesErr>;
     fn translate(&self, vaddr: usize) -> Result<usize, Self::TranslateErr>;
+
+    /// Returns all states of this CPU in a hypervisor-specific format.
+    ///
+    /// The returned data can only be used with [`Cpu::restore()`] on the same hypervisor.
+    fn snapshot(&mut self) -> Result<Vec<u8>, Self::SnapshotErr>;
+
+    /// Restore the states returned from [`Cpu::snapshot()`].
+    fn restore(&mut self, data: &[u8]) -> Result<(), Self::SnapshotErr>;
+
+    /// Replace hardware breakpoints, watchpoints and single-step states of this CPU.
+    ///
+    /// This has no effect if the hypervisor was not created with debugging enabled.
+    fn set_debug(&mut self, v: &HwDebug) -> Result<(), Self::DebugErr>;
+
+    /// Returns a handle to force [`CpuRun::run()`] to return from the other thread.
+    fn kicker(&self) -> Self::Kicker;
+
+    /// Returns `true` if the CPU can accept an external interrupt right now.
+    ///
+    /// This will return `false` after [`Cpu::inject()`] until the next [`CpuRun::run()`] unless the
+    /// interrupt controller can hold multiple pending interrupts.
+    fn interruptible(&self) -> bool;
+
+    /// Deliver an external interrupt `vector` to this CPU.
+    ///
+    /// This should be called only when [`Cpu::interruptible()`] returns `true`.
+    fn inject(&mut self, vector: u8) -> Result<(), Self::InterruptErr>;
+
+    /// Enable or disable an exit when the CPU become interruptible.
+    ///
+    /// The exit will be reported with [`CpuExit::into_interrupt()`].
+    fn set_interrupt_window(&mut self, v: bool);
+}
+
+/// Provides a method to force a CPU to exit from the other thread.
+pub trait CpuKicker: Send + Sync + 'static {
+    /// Force the current or the next [`CpuRun::run()`] to return as soon as possible.
+    ///
+    /// The exit will be reported with [`CpuExit::into_interrupt()`].
+    fn kick(&self);
 }
 
 /// Provides a method to run the CPU.
@@ -70,6 +118,10 @@ pub trait CpuExit: Sized {
     fn into_hlt(self) -> Result<(), Self>;
     fn into_io(self) -> Result<Self::Io, Self>;
     fn into_debug(self) -> Result<Self::Debug, Self>;
+
+    /// Returns `Ok` if the CPU was kicked by [`CpuKicker::kick()`] or it become interruptible
+    /// after [`Cpu::set_interrupt_window()`] has been enabled.
+    fn into_interrupt(self) -> Result<(), Self>;
 }
 
 /// Contains information when a VM exited because of memory-mapped I/O.
@@ -97,6 +149,20 @@ pub trait CpuDebug {
 }
 
 /// The debug event that cause the VM to exit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DebugEvent {
     SwBreak,
+    /// The CPU completed a single instruction with single-step enabled.
+    Step,
+    HwBreak,
+    /// Contains address that was read.
+    ReadWatch(usize),
+    /// Contains address that was written.
+    WriteWatch(usize),
+    /// Contains address that was accessed. This is used when the hardware cannot tell if it was a
+    /// read or a write.
+    AccessWatch(usize),
+    /// The exception was not caused by the debugger (e.g. the guest enabled single-step by
+    /// itself). Contains the exception vector on x86 or the exception class on AArch64.
+    Guest(u8),
 }
@@ -1,6 +1,7 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
-use super::ffi::{ARM64_SYS_REG, KVM_SET_ONE_REG, KvmOneReg};
-use crate::{CpuStates, HypervisorExt, Pstate, Sctlr, Tcr};
+use super::ffi::{ARM64_SYS_REG, KVM_GET_ONE_REG, KVM_SET_ONE_REG, KvmGuestDebugArch, KvmOneReg};
+use super::run::KvmDebugExitArch;
+use crate::{CpuStates, DebugEvent, HwDebug, HypervisorExt, Pstate, Sctlr, Tcr};
 use libc::ioctl;
 use std::io::Error;
 use std::os::fd::{AsRawFd, OwnedFd};
@@ -8,6 +9,101 @@ use thiserror::Error;
 
 impl HypervisorExt for Kvm {}
 
+pub fn snapshot(cpu: &OwnedFd) -> Result<Vec<u8>, SnapshotError> {
+    let mut data = Vec::new();
+
+    // Registers narrower than 128 bits are zero-extended.
+    for id in snapshot_regs() {
+        let mut val = 0u128;
+        let reg = KvmOneReg { id, addr: &mut val };
+
+        if unsafe { ioctl(cpu.as_raw_fd(), KVM_GET_ONE_REG, &reg) } != 0 {
+            return Err(SnapshotError::GetReg(id, Error::last_os_error()));
+        }
+
+        data.extend_from_slice(&val.to_le_bytes());
+    }
+
+    Ok(data)
+}
+
+pub fn restore(cpu: &OwnedFd, data: &[u8]) -> Result<(), SnapshotError> {
+    let regs = snapshot_regs();
+
+    if data.len() != regs.len() * 16 {
+        return Err(SnapshotError::InvalidData);
+    }
+
+    for (id, val) in regs.into_iter().zip(data.chunks_exact(16)) {
+        let mut val = u128::from_le_bytes(val.try_into().unwrap());
+        let reg = KvmOneReg { id, addr: &mut val };
+
+        if unsafe { ioctl(cpu.as_raw_fd(), KVM_SET_ONE_REG, &reg) } != 0 {
+            return Err(SnapshotError::SetReg(id, Error::last_os_error()));
+        }
+    }
+
+    Ok(())
+}
+
+pub fn debug_regs(v: &HwDebug) -> KvmGuestDebugArch {
+    let addr = |v: usize| u64::try_from(v).unwrap();
+
+    KvmGuestDebugArch {
+        dbg_bcr: v.bcr,
+        dbg_bvr: v.bvr.map(addr),
+        dbg_wcr: v.wcr,
+        dbg_wvr: v.wvr.map(addr),
+    }
+}
+
+pub fn debug_event(exit: &KvmDebugExitArch, _: &HwDebug) -> DebugEvent {
+    // See ESR_EL2 on the Arm Architecture Reference Manual for A-profile architecture.
+    let iss = exit.hsr & 0x1FFFFFF;
+    let far = usize::try_from(exit.far).unwrap();
+
+    match exit.hsr >> 26 {
+        0x30 | 0x31 => DebugEvent::HwBreak,
+        0x32 | 0x33 => DebugEvent::Step,
+        0x34 | 0x35 if iss & 0x40 != 0 => DebugEvent::WriteWatch(far),
+        0x34 | 0x35 => DebugEvent::ReadWatch(far),
+        0x3C => DebugEvent::SwBreak,
+        ec => DebugEvent::Guest(ec.try_into().unwrap()),
+    }
+}
+
+/// Returns identifier of all registers to be included in a snapshot.
+fn snapshot_regs() -> Vec<u64> {
+    // X0 - X30, SP_EL0, PC, PSTATE, SP_EL1, ELR_EL1 and SPSR_EL1.
+    let mut regs: Vec<u64> = (0..37).map(|i| 0x6030000000100000 + i * 2).collect();
+
+    // V0 - V31.
+    regs.extend((0..32).map(|i| 0x6040000000100054 + i * 4));
+
+    // FPSR and FPCR.
+    regs.push(0x60200000001000D4);
+    regs.push(0x60200000001000D5);
+
+    // System registers.
+    regs.extend([
+        ARM64_SYS_REG(0b11, 0b000, 0b0001, 0b0000, 0b000), // SCTLR_EL1
+        ARM64_SYS_REG(0b11, 0b000, 0b0001, 0b0000, 0b010), // CPACR_EL1
+        ARM64_SYS_REG(0b11, 0b000, 0b0010, 0b0000, 0b000), // TTBR0_EL1
+        ARM64_SYS_REG(0b11, 0b000, 0b0010, 0b0000, 0b001), // TTBR1_EL1
+        ARM64_SYS_REG(0b11, 0b000, 0b0010, 0b0000, 0b010), // TCR_EL1
+        ARM64_SYS_REG(0b11, 0b000, 0b0101, 0b0010, 0b000), // ESR_EL1
+        ARM64_SYS_REG(0b11, 0b000, 0b0110, 0b0000, 0b000), // FAR_EL1
+        ARM64_SYS_REG(0b11, 0b000, 0b1010, 0b0010, 0b000), // MAIR_EL1
+        ARM64_SYS_REG(0b11, 0b000, 0b1100, 0b0000, 0b000), // VBAR_EL1
+        ARM64_SYS_REG(0b11, 0b000, 0b1101, 0b0000, 0b001), // CONTEXTIDR_EL1
+        ARM64_SYS_REG(0b11, 0b000, 0b1101, 0b0000, 0b100), // TPIDR_EL1
+        ARM64_SYS_REG(0b11, 0b011, 0b1101, 0b0000, 0b010), // TPIDR_EL0
+        ARM64_SYS_REG(0b11, 0b011, 0b1101, 0b0000, 0b011), // TPIDRRO_EL0
+    ]);
+
+    regs
+}
+
 /// Implementation of [`Cpu::States`] for KVM.
 pub struct KvmStates<'a> {
     cpu: &'a mut OwnedFd,
@@ -19,8 +115,10 @@ pub struct KvmStates<'a> {
     ttbr1: State<u64>,
     sp: State<u64>,
     pc: State<u64>,
-    x0: State<u64>,
-    x1: State<u64>,
+    x: [State<u64>; 31],
+    v: [State<u128>; 32],
+    fpsr: State<u32>,
+    fpcr: State<u32>,
 }
 
 impl<'a> KvmStates<'a> {
@@ -35,11 +133,26 @@ impl<'a> KvmStates<'a> {
             ttbr1: State::None,
             sp: State::None,
             pc: State::None,
-            x0: State::None,
-            x1: State::None,
+            x: [const { State::None }; 31],
+            v: [const { State::None }; 32],
+            fpsr: State::None,
+            fpcr: State::None,
         })
     }
 
+    fn get_reg<T: Default>(&self, reg: u64) -> Result<T, Error> {
+        let mut val = T::default();
+        let reg = KvmOneReg {
+            id: reg,
+            addr: &mut val,
+        };
+
+        match unsafe { ioctl(self.cpu.as_raw_fd(), KVM_GET_ONE_REG, &reg) } {
+            0 => Ok(val),
+            _ => Err(Error::last_os_error()),
+        }
+    }
+
     fn set_reg<T>(&mut self, reg: u64, mut val: T) -> Result<(), Error> {
         let reg = KvmOneReg {
             id: reg,
@@ -56,6 +169,17 @@ impl<'a> KvmStates<'a> {
 impl<'a> CpuStates for KvmStates<'a> {
     type Err = StatesError;
 
+    fn get_pstate(&mut self) -> Result<Pstate, Self::Err> {
+        let v = match self.pstate {
+            State::Dirty(v) => v,
+            State::None => self
+                .get_reg(0x6030000000100042)
+                .map_err(StatesError::GetPstateFailed)?,
+        };
+
+        Ok(Pstate::from_bits(v))
+    }
+
     fn set_pstate(&mut self, v: Pstate) {
         self.pstate = State::Dirty(v.into_bits());
     }
@@ -76,24 +200,123 @@ impl<'a> CpuStates for KvmStates<'a> {
         self.ttbr0 = State::Dirty(baddr.try_into().unwrap());
     }
 
+    fn get_ttbr1_el1(&mut self) -> Result<usize, Self::Err> {
+        let v: u64 = match self.ttbr1 {
+            State::Dirty(v) => v,
+            State::None => self
+                .get_reg(ARM64_SYS_REG(0b11, 0b000, 0b0010, 0b0000, 0b001))
+                .map_err(StatesError::GetTtbr1Failed)?,
+        };
+
+        Ok(v.try_into().unwrap())
+    }
+
     fn set_ttbr1_el1(&mut self, baddr: usize) {
         self.ttbr1 = State::Dirty(baddr.try_into().unwrap());
     }
 
+    fn get_far_el1(&mut self) -> Result<usize, Self::Err> {
+        let v: u64 = self
+            .get_reg(ARM64_SYS_REG(0b11, 0b000, 0b0110, 0b0000, 0b000))
+            .map_err(StatesError::GetFarFailed)?;
+
+        Ok(v.try_into().unwrap())
+    }
+
+    fn get_sp_el1(&mut self) -> Result<usize, Self::Err> {
+        let v: u64 = match self.sp {
+            State::Dirty(v) => v,
+            State::None => self
+                .get_reg(0x6030000000100044)
+                .map_err(StatesError::GetSpFailed)?,
+        };
+
+        Ok(v.try_into().unwrap())
+    }
+
     fn set_sp_el1(&mut self, v: usize) {
         self.sp = State::Dirty(v.try_into().unwrap());
     }
 
+    fn get_pc(&mut self) -> Result<usize, Self::Err> {
+        let v: u64 = match self.pc {
+            State::Dirty(v) => v,
+            State::None => self
+                .get_reg(0x6030000000100040)
+                .map_err(StatesError::GetPcFailed)?,
+        };
+
+        Ok(v.try_into().unwrap())
+    }
+
     fn set_pc(&mut self, v: usize) {
         self.pc = State::Dirty(v.try_into().unwrap());
     }
 
     fn set_x0(&mut self, v: usize) {
-        self.x0 = State::Dirty(v.try_into().unwrap());
+        self.set_x(0, v);
     }
 
     fn set_x1(&mut self, v: usize) {
-        self.x1 = State::Dirty(v.try_into().unwrap());
+        self.set_x(1, v);
+    }
+
+    fn set_x2(&mut self, v: usize) {
+        self.set_x(2, v);
+    }
+
+    fn get_x(&mut self, n: usize) -> Result<usize, Self::Err> {
+        let v: u64 = match self.x[n] {
+            State::Dirty(v) => v,
+            State::None => self
+                .get_reg(0x6030000000100000 + n as u64 * 2)
+                .map_err(|e| StatesError::GetXFailed(n, e))?,
+        };
+
+        Ok(v.try_into().unwrap())
+    }
+
+    fn set_x(&mut self, n: usize, v: usize) {
+        self.x[n] = State::Dirty(v.try_into().unwrap());
+    }
+
+    fn get_v(&mut self, n: usize) -> Result<u128, Self::Err> {
+        match self.v[n] {
+            State::Dirty(v) => Ok(v),
+            State::None => self
+                .get_reg(0x6040000000100054 + n as u64 * 4)
+                .map_err(|e| StatesError::GetVFailed(n, e)),
+        }
+    }
+
+    fn set_v(&mut self, n: usize, v: u128) {
+        self.v[n] = State::Dirty(v);
+    }
+
+    fn get_fpsr(&mut self) -> Result<u32, Self::Err> {
+        match self.fpsr {
+            State::Dirty(v) => Ok(v),
+            State::None => self
+                .get_reg(0x60200000001000D4)
+                .map_err(StatesError::GetFpsrFailed),
+        }
+    }
+
+    fn set_fpsr(&mut self, v: u32) {
+        self.fpsr = State::Dirty(v);
+    }
+
+    fn get_fpcr(&mut self) -> Result<u32, Self::Err> {
+        match self.fpcr {
+            State::Dirty(v) => Ok(v),
+            State::None => self
+                .get_reg(0x60200000001000D5)
+                .map_err(StatesError::GetFpcrFailed),
+        }
+    }
+
+    fn set_fpcr(&mut self, v: u32) {
+        self.fpcr = State::Dirty(v);
     }
 
     fn commit(mut self) -> Result<(), Self::Err> {
@@ -145,16 +368,32 @@ impl<'a> CpuStates for KvmStates<'a> {
                 .map_err(StatesError::SetPcFailed)?;
         }
 
-        // X0.
-        if let State::Dirty(v) = self.x0 {
-            self.set_reg(0x6030000000100000, v)
-                .map_err(StatesError::SetX0Failed)?;
+        // X0 - X30.
+        for n in 0..31 {
+            if let State::Dirty(v) = self.x[n] {
+                self.set_reg(0x6030000000100000 + n as u64 * 2, v)
+                    .map_err(|e| StatesError::SetXFailed(n, e))?;
+            }
+        }
+
+        // V0 - V31.
+        for n in 0..32 {
+            if let State::Dirty(v) = self.v[n] {
+                self.set_reg(0x6040000000100054 + n as u64 * 4, v)
+                    .map_err(|e| StatesError::SetVFailed(n, e))?;
+            }
         }
 
-        // X1.
-        if let State::Dirty(v) = self.x1 {
-            self.set_reg(0x6030000000100002, v)
-                .map_err(StatesError::SetX1Failed)?;
+        // FPSR.
+        if let State::Dirty(v) = self.fpsr {
+            self.set_reg(0x60200000001000D4, v)
+                .map_err(StatesError::SetFpsrFailed)?;
+        }
+
+        // FPCR.
+        if let State::Dirty(v) = self.fpcr {
+            self.set_reg(0x60200000001000D5, v)
+                .map_err(StatesError::SetFpcrFailed)?;
         }
 
         Ok(())
@@ -169,6 +408,33 @@ enum State<T> {
 /// Implementation of [`CpuStates::Err`].
 #[derive(Debug, Error)]
 pub enum StatesError {
+    #[error("couldn't get PSTATE")]
+    GetPstateFailed(#[source] Error),
+
+    #[error("couldn't get TTBR1_EL1")]
+    GetTtbr1Failed(#[source] Error),
+
+    #[error("couldn't get FAR_EL1")]
+    GetFarFailed(#[source] Error),
+
+    #[error("couldn't get SP_EL1")]
+    GetSpFailed(#[source] Error),
+
+    #[error("couldn't get PC")]
+    GetPcFailed(#[source] Error),
+
+    #[error("couldn't get X{0}")]
+    GetXFailed(usize, #[source] Error),
+
+    #[error("couldn't get V{0}")]
+    GetVFailed(usize, #[source] Error),
+
+    #[error("couldn't get FPSR")]
+    GetFpsrFailed(#[source] Error),
+
+    #[error("couldn't get FPCR")]
+    GetFpcrFailed(#[source] Error),
+
     #[error("couldn't set PSTATE")]
     SetPstateFailed(#[source] Error),
 
@@ -193,9 +459,28 @@ pub enum StatesError {
     #[error("couldn't set PC")]
     SetPcFailed(#[source] Error),
 
-    #[error("couldn't set X0")]
-    SetX0Failed(#[source] Error),
+    #[error("couldn't set X{0}")]
+    SetXFailed(usize, #[source] Error),
+
+    #[error("couldn't set V{0}")]
+    SetVFailed(usize, #[source] Error),
+
+    #[error("couldn't set FPSR")]
+    SetFpsrFailed(#[source] Error),
+
+    #[error("couldn't set FPCR")]
+    SetFpcrFailed(#[source] Error),
+}
+
+/// Implementation of [`Cpu::SnapshotErr`](crate::Cpu::SnapshotErr).
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("couldn't get register {0:#x}")]
+    GetReg(u64, #[source] Error),
+
+    #[error("invalid snapshot data")]
+    InvalidData,
 
-    #[error("couldn't set X1")]
-    SetX1Failed(#[source] Error),
+    #[error("couldn't set register {0:#x}")]
+    SetReg(u64, #[source] Error),
 }
@@ -1,37 +1,95 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
-use super::arch::{KvmStates, StatesError};
-use super::ffi::{KVM_EXIT_DEBUG, KVM_EXIT_HLT, KVM_EXIT_IO, KVM_RUN};
+use super::arch::{KvmStates, SnapshotError, StatesError};
+use super::ffi::{
+    KVM_EXIT_DEBUG, KVM_EXIT_HLT, KVM_EXIT_INTR, KVM_EXIT_IO, KVM_GUESTDBG_ENABLE,
+    KVM_GUESTDBG_SINGLESTEP, KVM_GUESTDBG_USE_SW_BP, KVM_RUN, KVM_SET_GUEST_DEBUG, KvmGuestDebug,
+};
 use super::run::KvmRun;
-use crate::{Cpu, CpuDebug, CpuExit, CpuIo, CpuRun, DebugEvent, IoBuf};
-use libc::{ioctl, munmap};
+use crate::{Cpu, CpuDebug, CpuExit, CpuIo, CpuKicker, CpuRun, DebugEvent, HwDebug, IoBuf};
+use libc::{SIGRTMIN, ioctl, munmap, pthread_kill, pthread_self, pthread_t, sigaction};
+use std::cell::Cell;
+use std::ffi::c_int;
+use std::io::{Error, ErrorKind};
+use std::mem::zeroed;
+#[cfg(target_arch = "aarch64")]
+use std::os::fd::BorrowedFd;
 use std::os::fd::{AsRawFd, OwnedFd};
-use std::sync::MutexGuard;
+use std::ptr::{null_mut, write_volatile};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Install a signal handler for [`KvmKicker`].
+pub fn install_kick_handler() -> Result<(), Error> {
+    let mut act: sigaction = unsafe { zeroed() };
+
+    // We don't set SA_RESTART so KVM_RUN will return EINTR.
+    act.sa_sigaction = kick_handler as *const () as usize;
+
+    if unsafe { sigaction(SIGRTMIN(), &act, null_mut()) } < 0 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+extern "C" fn kick_handler(_: c_int) {
+    // The signal may arrive before KVM_RUN so we need immediate_exit to prevent KVM_RUN from
+    // blocking.
+    let cx = ACTIVE.get();
+
+    if !cx.is_null() {
+        unsafe { write_volatile(&raw mut (*cx).immediate_exit, 1) };
+    }
+}
+
+thread_local! {
+    /// `kvm_run` of the CPU that driven by the current thread.
+    static ACTIVE: Cell<*mut KvmRun> = const { Cell::new(null_mut()) };
+}
 
 /// Implementation of [`Cpu`] for KVM.
 pub struct KvmCpu<'a> {
     id: usize,
     fd: MutexGuard<'a, OwnedFd>,
+    #[cfg(target_arch = "aarch64")]
+    vm: BorrowedFd<'a>,
     cx: (*mut KvmRun, usize),
+    debug: HwDebug,
+    thread: Arc<Mutex<Option<pthread_t>>>,
 }
 
 impl<'a> KvmCpu<'a> {
     /// # Safety
     /// - `cx` cannot be null and must be obtained from `mmap` on `fd`.
     /// - `len` must be the same value that used on `mmap`.
-    pub unsafe fn new(id: usize, fd: MutexGuard<'a, OwnedFd>, cx: *mut KvmRun, len: usize) -> Self {
+    pub unsafe fn new(
+        id: usize,
+        fd: MutexGuard<'a, OwnedFd>,
+        #[cfg(target_arch = "aarch64")] vm: BorrowedFd<'a>,
+        cx: *mut KvmRun,
+        len: usize,
+    ) -> Self {
         assert!(len >= size_of::<KvmRun>());
 
+        ACTIVE.set(cx);
+
         Self {
             id,
             fd,
+            #[cfg(target_arch = "aarch64")]
+            vm,
             cx: (cx, len),
+            debug: HwDebug::default(),
+            thread: Arc::new(Mutex::new(Some(unsafe { pthread_self() }))),
         }
     }
 }
 
 impl Drop for KvmCpu<'_> {
     fn drop(&mut self) {
-        use std::io::Error;
+        // Prevent the kicker from sending a signal to the thread after this.
+        *self.thread.lock().unwrap() = None;
+
+        ACTIVE.set(null_mut());
 
         if unsafe { munmap(self.cx.0.cast(), self.cx.1) } < 0 {
             panic!("failed to munmap kvm_run: {}", Error::last_os_error());
@@ -50,6 +108,10 @@ impl<'a> Cpu for KvmCpu<'a> {
     where
         Self: 'b;
     type TranslateErr = std::io::Error;
+    type SnapshotErr = SnapshotError;
+    type DebugErr = std::io::Error;
+    type InterruptErr = std::io::Error;
+    type Kicker = KvmKicker;
 
     fn id(&self) -> usize {
         self.id
@@ -82,16 +144,134 @@ impl<'a> Cpu for KvmCpu<'a> {
             _ => Err(std::io::Error::last_os_error()),
         }
     }
+
+    fn snapshot(&mut self) -> Result<Vec<u8>, Self::SnapshotErr> {
+        super::arch::snapshot(&self.fd)
+    }
+
+    fn restore(&mut self, data: &[u8]) -> Result<(), Self::SnapshotErr> {
+        super::arch::restore(&self.fd, data)
+    }
+
+    fn set_debug(&mut self, v: &HwDebug) -> Result<(), Self::DebugErr> {
+        #[cfg(target_arch = "aarch64")]
+        use super::ffi::KVM_GUESTDBG_USE_HW;
+        #[cfg(target_arch = "x86_64")]
+        use super::ffi::KVM_GUESTDBG_USE_HW_BP as KVM_GUESTDBG_USE_HW;
+
+        let mut control = KVM_GUESTDBG_ENABLE | KVM_GUESTDBG_USE_SW_BP | KVM_GUESTDBG_USE_HW;
+
+        if v.step {
+            control |= KVM_GUESTDBG_SINGLESTEP;
+        }
+
+        let arg = KvmGuestDebug {
+            control,
+            pad: 0,
+            arch: super::arch::debug_regs(v),
+        };
+
+        if unsafe { ioctl(self.fd.as_raw_fd(), KVM_SET_GUEST_DEBUG, &arg) } < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        // We need the addresses to report which watchpoint was hit.
+        self.debug = v.clone();
+
+        Ok(())
+    }
+
+    fn kicker(&self) -> Self::Kicker {
+        KvmKicker(self.thread.clone())
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn interruptible(&self) -> bool {
+        // The GIC will keep each SPI pending until the PE can take it so we can inject all of them
+        // without waiting for an exit.
+        true
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn interruptible(&self) -> bool {
+        let cx = unsafe { &*self.cx.0 };
+
+        cx.ready_for_interrupt_injection != 0 && cx.if_flag != 0
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn inject(&mut self, vector: u8) -> Result<(), Self::InterruptErr> {
+        use super::GIC_SPI_BASE;
+        use super::ffi::{KVM_ARM_IRQ_TYPE_SHIFT, KVM_ARM_IRQ_TYPE_SPI, KVM_IRQ_LINE, KvmIrqLevel};
+
+        // The SPI is edge-triggered so we need to lower the line after raised it. Which PE will
+        // receive the SPI is depend on how the kernel route it.
+        let irq =
+            (KVM_ARM_IRQ_TYPE_SPI << KVM_ARM_IRQ_TYPE_SHIFT) | (GIC_SPI_BASE + u32::from(vector));
+
+        for level in [1, 0] {
+            let arg = KvmIrqLevel { irq, level };
+
+            if unsafe { ioctl(self.vm.as_raw_fd(), KVM_IRQ_LINE, &arg) } < 0 {
+                return Err(Error::last_os_error());
+            }
+        }
+
+        Ok(())
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn inject(&mut self, vector: u8) -> Result<(), Self::InterruptErr> {
+        use super::ffi::{KVM_INTERRUPT, KvmInterrupt};
+
+        let arg = KvmInterrupt { irq: vector.into() };
+
+        if unsafe { ioctl(self.fd.as_raw_fd(), KVM_INTERRUPT, &arg) } < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        // KVM can hold only one interrupt until the next KVM_RUN. KVM will update this field when
+        // KVM_RUN return.
+        unsafe { (*self.cx.0).ready_for_interrupt_injection = 0 };
+
+        Ok(())
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn set_interrupt_window(&mut self, _: bool) {
+        // KVM does not support an interrupt window on AArch64. This will never be enabled since
+        // Cpu::interruptible() always returns true.
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn set_interrupt_window(&mut self, v: bool) {
+        unsafe { (*self.cx.0).request_interrupt_window = v.into() };
+    }
 }
 
 impl CpuRun for KvmCpu<'_> {
     type RunErr = std::io::Error;
 
     fn run(&mut self) -> Result<Self::Exit<'_>, Self::RunErr> {
-        if unsafe { ioctl(self.fd.as_raw_fd(), KVM_RUN, 0) } < 0 {
-            Err(std::io::Error::last_os_error())
-        } else {
-            Ok(KvmExit(self))
+        let r = match unsafe { ioctl(self.fd.as_raw_fd(), KVM_RUN, 0) } {
+            0 => Ok(()),
+            _ => Err(Error::last_os_error()),
+        };
+
+        // Clear the kick so it does not affect the next run.
+        let cx = self.cx.0;
+
+        unsafe { write_volatile(&raw mut (*cx).immediate_exit, 0) };
+
+        match r {
+            Ok(_) => Ok(KvmExit(self)),
+            Err(e) if e.kind() == ErrorKind::Interrupted => {
+                // KVM does not set exit_reason when it return because of immediate_exit.
+                unsafe { (*cx).exit_reason = KVM_EXIT_INTR };
+
+                Ok(KvmExit(self))
+            }
+            Err(e) => Err(e),
         }
     }
 }
@@ -132,6 +312,26 @@ impl<'a, 'b> CpuExit for KvmExit<'a, 'b> {
             Err(self)
         }
     }
+
+    fn into_interrupt(self) -> Result<(), Self> {
+        match unsafe { (*self.0.cx.0).exit_reason } {
+            KVM_EXIT_INTR => Ok(()),
+            #[cfg(target_arch = "x86_64")]
+            super::ffi::KVM_EXIT_IRQ_WINDOW_OPEN => Ok(()),
+            _ => Err(self),
+        }
+    }
+}
+
+/// Implementation of [`Cpu::Kicker`] for KVM.
+pub struct KvmKicker(Arc<Mutex<Option<pthread_t>>>);
+
+impl CpuKicker for KvmKicker {
+    fn kick(&self) {
+        if let Some(t) = *self.0.lock().unwrap() {
+            unsafe { pthread_kill(t, SIGRTMIN()) };
+        }
+    }
 }
 
 /// Implementation of [`CpuIo`] for KVM.
@@ -167,12 +367,9 @@ impl<'b> CpuDebug for KvmDebug<'_, 'b> {
     type Cpu = KvmCpu<'b>;
 
     fn reason(&mut self) -> DebugEvent {
-        let debug = unsafe { (*self.0.cx.0).exit.debug.arch };
+        let exit = unsafe { (*self.0.cx.0).exit.debug.arch };
 
-        match debug.exception {
-            3 => DebugEvent::SwBreak,
-            exception => todo!("unhandled exception {exception}"),
-        }
+        super::arch::debug_event(&exit, &self.0.debug)
     }
 
     fn cpu(&mut self) -> &mut Self::Cpu {
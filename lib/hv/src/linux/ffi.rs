@@ -8,6 +8,8 @@ pub const KVM_GET_VCPU_MMAP_SIZE: c_ulong = _IO(KVMIO, 0x04);
 #[cfg(target_arch = "x86_64")]
 pub const KVM_GET_SUPPORTED_CPUID: c_ulong = _IOC(_IOC_READ | _IOC_WRITE, KVMIO, 0x05, 8);
 pub const KVM_CREATE_VCPU: c_ulong = _IO(KVMIO, 0x41);
+#[cfg(target_arch = "aarch64")]
+pub const KVM_IRQ_LINE: c_ulong = _IOW::<KvmIrqLevel>(KVMIO, 0x61);
 pub const KVM_SET_USER_MEMORY_REGION: c_ulong = _IOW::<KvmUserspaceMemoryRegion>(KVMIO, 0x46);
 pub const KVM_RUN: c_ulong = _IO(KVMIO, 0x80);
 #[cfg(not(target_arch = "aarch64"))]
@@ -21,10 +23,34 @@ pub const KVM_SET_SREGS: c_ulong = _IOW::<KvmSregs>(KVMIO, 0x84);
 #[cfg(target_arch = "x86_64")]
 pub const KVM_TRANSLATE: c_ulong = _IOWR::<KvmTranslation>(KVMIO, 0x85);
 #[cfg(target_arch = "x86_64")]
+pub const KVM_INTERRUPT: c_ulong = _IOW::<KvmInterrupt>(KVMIO, 0x86);
+#[cfg(target_arch = "x86_64")]
+pub const KVM_GET_MSRS: c_ulong = _IOC(_IOC_READ | _IOC_WRITE, KVMIO, 0x88, 8);
+#[cfg(target_arch = "x86_64")]
+pub const KVM_SET_MSRS: c_ulong = _IOC(_IOC_WRITE, KVMIO, 0x89, 8);
+#[cfg(target_arch = "x86_64")]
 pub const KVM_GET_FPU: c_ulong = _IOR::<KvmFpu>(KVMIO, 0x8c);
 #[cfg(target_arch = "x86_64")]
+pub const KVM_SET_FPU: c_ulong = _IOW::<KvmFpu>(KVMIO, 0x8d);
+#[cfg(target_arch = "x86_64")]
+pub const KVM_GET_LAPIC: c_ulong = _IOR::<KvmLapicState>(KVMIO, 0x8e);
+#[cfg(target_arch = "x86_64")]
+pub const KVM_SET_LAPIC: c_ulong = _IOW::<KvmLapicState>(KVMIO, 0x8f);
+#[cfg(target_arch = "x86_64")]
 pub const KVM_SET_CPUID2: c_ulong = _IOC(_IOC_WRITE, KVMIO, 0x90, 8);
 pub const KVM_SET_GUEST_DEBUG: c_ulong = _IOW::<KvmGuestDebug>(KVMIO, 0x9b);
+#[cfg(target_arch = "x86_64")]
+pub const KVM_GET_VCPU_EVENTS: c_ulong = _IOR::<KvmVcpuEvents>(KVMIO, 0x9f);
+#[cfg(target_arch = "x86_64")]
+pub const KVM_SET_VCPU_EVENTS: c_ulong = _IOW::<KvmVcpuEvents>(KVMIO, 0xa0);
+#[cfg(target_arch = "x86_64")]
+pub const KVM_GET_XSAVE: c_ulong = _IOR::<KvmXsave>(KVMIO, 0xa4);
+#[cfg(target_arch = "x86_64")]
+pub const KVM_SET_XSAVE: c_ulong = _IOW::<KvmXsave>(KVMIO, 0xa5);
+#[cfg(target_arch = "x86_64")]
+pub const KVM_GET_XCRS: c_ulong = _IOR::<KvmXcrs>(KVMIO, 0xa6);
+#[cfg(target_arch = "x86_64")]
+pub const KVM_SET_XCRS: c_ulong = _IOW::<KvmXcrs>(KVMIO, 0xa7);
 #[cfg(target_arch = "aarch64")]
 pub const KVM_GET_ONE_REG: c_ulong = _IOW::<KvmOneReg<()>>(KVMIO, 0xab);
 #[cfg(target_arch = "aarch64")]
@@ -33,6 +59,10 @@ pub const KVM_SET_ONE_REG: c_ulong = _IOW::<KvmOneReg<()>>(KVMIO, 0xac);
 pub const KVM_ARM_VCPU_INIT: c_ulong = _IOW::<KvmVcpuInit>(KVMIO, 0xae);
 #[cfg(target_arch = "aarch64")]
 pub const KVM_ARM_PREFERRED_TARGET: c_ulong = _IOR::<KvmVcpuInit>(KVMIO, 0xaf);
+#[cfg(target_arch = "aarch64")]
+pub const KVM_CREATE_DEVICE: c_ulong = _IOWR::<KvmCreateDevice>(KVMIO, 0xe0);
+#[cfg(target_arch = "aarch64")]
+pub const KVM_SET_DEVICE_ATTR: c_ulong = _IOW::<KvmDeviceAttr>(KVMIO, 0xe1);
 
 pub const KVM_API_VERSION: c_int = 12;
 pub const KVM_NR_INTERRUPTS: usize = 256;
@@ -48,9 +78,39 @@ pub const KVM_CAP_ARM_VM_IPA_SIZE: c_int = 165;
 pub const KVM_EXIT_DEBUG: u32 = 4;
 pub const KVM_EXIT_HLT: u32 = 5;
 pub const KVM_EXIT_IO: u32 = 6;
+#[cfg(target_arch = "x86_64")]
+pub const KVM_EXIT_IRQ_WINDOW_OPEN: u32 = 7;
+pub const KVM_EXIT_INTR: u32 = 10;
 
 pub const KVM_GUESTDBG_ENABLE: u32 = 0x00000001;
+pub const KVM_GUESTDBG_SINGLESTEP: u32 = 0x00000002;
 pub const KVM_GUESTDBG_USE_SW_BP: u32 = 0x00010000;
+#[cfg(target_arch = "x86_64")]
+pub const KVM_GUESTDBG_USE_HW_BP: u32 = 0x00020000;
+#[cfg(target_arch = "aarch64")]
+pub const KVM_GUESTDBG_USE_HW: u32 = 0x00020000;
+
+#[cfg(target_arch = "aarch64")]
+pub const KVM_DEV_TYPE_ARM_VGIC_V3: u32 = 7;
+#[cfg(target_arch = "aarch64")]
+pub const KVM_DEV_ARM_VGIC_GRP_ADDR: u32 = 0;
+#[cfg(target_arch = "aarch64")]
+pub const KVM_DEV_ARM_VGIC_GRP_DIST_REGS: u32 = 1;
+#[cfg(target_arch = "aarch64")]
+pub const KVM_DEV_ARM_VGIC_GRP_NR_IRQS: u32 = 3;
+#[cfg(target_arch = "aarch64")]
+pub const KVM_DEV_ARM_VGIC_GRP_CTRL: u32 = 4;
+#[cfg(target_arch = "aarch64")]
+pub const KVM_DEV_ARM_VGIC_CTRL_INIT: u64 = 0;
+#[cfg(target_arch = "aarch64")]
+pub const KVM_VGIC_V3_ADDR_TYPE_DIST: u64 = 2;
+#[cfg(target_arch = "aarch64")]
+pub const KVM_VGIC_V3_ADDR_TYPE_REDIST: u64 = 3;
+
+#[cfg(target_arch = "aarch64")]
+pub const KVM_ARM_IRQ_TYPE_SHIFT: u32 = 24;
+#[cfg(target_arch = "aarch64")]
+pub const KVM_ARM_IRQ_TYPE_SPI: u32 = 1;
 
 #[cfg(target_arch = "x86_64")]
 pub const KVM_CPUID_FLAG_SIGNIFCANT_INDEX: u32 = 1 << 0;
@@ -192,6 +252,19 @@ pub struct KvmSregs {
     pub interrupt_bitmap: [u64; KVM_NR_INTERRUPTS.div_ceil(64)],
 }
 
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+pub struct KvmInterrupt {
+    pub irq: u32,
+}
+
+#[cfg(target_arch = "aarch64")]
+#[repr(C)]
+pub struct KvmIrqLevel {
+    pub irq: u32,
+    pub level: u32,
+}
+
 #[cfg(target_arch = "x86_64")]
 #[repr(C)]
 pub struct KvmTranslation {
@@ -245,6 +318,80 @@ pub struct KvmFpu {
     pub pad2: u32,
 }
 
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+pub struct KvmMsrs<const N: usize> {
+    pub nmsrs: u32,
+    pub pad: u32,
+    pub entries: [KvmMsrEntry; N],
+}
+
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct KvmMsrEntry {
+    pub index: u32,
+    pub reserved: u32,
+    pub data: u64,
+}
+
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+pub struct KvmLapicState {
+    pub regs: [u8; 1024],
+}
+
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+pub struct KvmVcpuEvents {
+    pub exception_injected: u8,
+    pub exception_nr: u8,
+    pub exception_has_error_code: u8,
+    pub exception_pending: u8,
+    pub exception_error_code: u32,
+    pub interrupt_injected: u8,
+    pub interrupt_nr: u8,
+    pub interrupt_soft: u8,
+    pub interrupt_shadow: u8,
+    pub nmi_injected: u8,
+    pub nmi_pending: u8,
+    pub nmi_masked: u8,
+    pub nmi_pad: u8,
+    pub sipi_vector: u32,
+    pub flags: u32,
+    pub smi_smm: u8,
+    pub smi_pending: u8,
+    pub smi_smm_inside_nmi: u8,
+    pub smi_latched_init: u8,
+    pub triple_fault_pending: u8,
+    pub reserved: [u8; 26],
+    pub exception_has_payload: u8,
+    pub exception_payload: u64,
+}
+
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+pub struct KvmXsave {
+    pub region: [u32; 1024],
+}
+
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+pub struct KvmXcrs {
+    pub nr_xcrs: u32,
+    pub flags: u32,
+    pub xcrs: [KvmXcr; 16],
+    pub padding: [u64; 16],
+}
+
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+pub struct KvmXcr {
+    pub xcr: u32,
+    pub reserved: u32,
+    pub value: u64,
+}
+
 #[repr(C)]
 pub struct KvmGuestDebug {
     pub control: u32,
@@ -258,6 +405,15 @@ pub struct KvmGuestDebugArch {
     pub debugreg: [u64; 8],
 }
 
+#[cfg(target_arch = "aarch64")]
+#[repr(C)]
+pub struct KvmGuestDebugArch {
+    pub dbg_bcr: [u64; 16],
+    pub dbg_bvr: [u64; 16],
+    pub dbg_wcr: [u64; 16],
+    pub dbg_wvr: [u64; 16],
+}
+
 #[cfg(target_arch = "aarch64")]
 #[repr(C)]
 pub struct KvmOneReg<'a, T> {
@@ -271,3 +427,20 @@ pub struct KvmVcpuInit {
     pub target: u32,
     pub features: [u32; 7],
 }
+
+#[cfg(target_arch = "aarch64")]
+#[repr(C)]
+pub struct KvmCreateDevice {
+    pub ty: u32,
+    pub fd: u32,
+    pub flags: u32,
+}
+
+#[cfg(target_arch = "aarch64")]
+#[repr(C)]
+pub struct KvmDeviceAttr {
+    pub flags: u32,
+    pub group: u32,
+    pub attr: u64,
+    pub addr: u64,
+}
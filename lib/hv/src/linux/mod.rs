@@ -41,6 +41,9 @@ pub fn new(
     // Create RAM.
     let ram = Ram::new(page_size, ram_size, ())?;
 
+    // Install signal handler to kick vCPU.
+    self::cpu::install_kick_handler().map_err(HvError::InstallKickHandlerFailed)?;
+
     // Open KVM device.
     let kvm = unsafe { open(c"/dev/kvm".as_ptr(), O_RDWR) };
 
@@ -216,6 +219,10 @@ pub fn new(
         }
     }
 
+    // Setup the GIC after all PE is initialized.
+    #[cfg(target_arch = "aarch64")]
+    let gic = create_gic(vm.as_fd(), ram.len())?;
+
     // Load PE features after all PE is initialized.
     #[cfg(target_arch = "aarch64")]
     let feats = {
@@ -267,6 +274,8 @@ pub fn new(
         feats,
         cpus,
         vcpu_mmap_size: vcpu_mmap_size.try_into().unwrap(),
+        #[cfg(target_arch = "aarch64")]
+        gic,
         vm,
         ram,
         kvm,
@@ -298,6 +307,83 @@ fn create_vm(kvm: BorrowedFd) -> Result<OwnedFd, HvError> {
     }
 }
 
+/// Create an in-kernel GICv3 with its distributor at [`GIC_DIST`] and the redistributors right
+/// after it.
+///
+/// All SPIs are edge-triggered so each injection will be delivered once.
+#[cfg(target_arch = "aarch64")]
+fn create_gic(vm: BorrowedFd, ram: NonZero<usize>) -> Result<OwnedFd, HvError> {
+    use self::ffi::{
+        KVM_CREATE_DEVICE, KVM_DEV_ARM_VGIC_CTRL_INIT, KVM_DEV_ARM_VGIC_GRP_ADDR,
+        KVM_DEV_ARM_VGIC_GRP_CTRL, KVM_DEV_ARM_VGIC_GRP_DIST_REGS, KVM_DEV_ARM_VGIC_GRP_NR_IRQS,
+        KVM_DEV_TYPE_ARM_VGIC_V3, KVM_SET_DEVICE_ATTR, KVM_VGIC_V3_ADDR_TYPE_DIST,
+        KVM_VGIC_V3_ADDR_TYPE_REDIST, KvmCreateDevice, KvmDeviceAttr,
+    };
+
+    // The GIC cannot overlap with the RAM.
+    if u64::try_from(ram.get()).unwrap() > GIC_DIST {
+        return Err(HvError::InvalidRamSize);
+    }
+
+    // Create the device.
+    let mut dev = KvmCreateDevice {
+        ty: KVM_DEV_TYPE_ARM_VGIC_V3,
+        fd: 0,
+        flags: 0,
+    };
+
+    if unsafe { ioctl(vm.as_raw_fd(), KVM_CREATE_DEVICE, &mut dev) } < 0 {
+        return Err(HvError::CreateGicFailed(Error::last_os_error()));
+    }
+
+    let gic = unsafe { OwnedFd::from_raw_fd(dev.fd.try_into().unwrap()) };
+    let set = |group, attr, addr: u64| {
+        let arg = KvmDeviceAttr {
+            flags: 0,
+            group,
+            attr,
+            addr,
+        };
+
+        match unsafe { ioctl(gic.as_raw_fd(), KVM_SET_DEVICE_ATTR, &arg) } {
+            0 => Ok(()),
+            _ => Err(HvError::SetupGicFailed(Error::last_os_error())),
+        }
+    };
+
+    // Set addresses. The distributor is 64 KiB and each redistributor is 128 KiB.
+    let dist = GIC_DIST;
+    let redist = GIC_DIST + 0x10000;
+
+    set(
+        KVM_DEV_ARM_VGIC_GRP_ADDR,
+        KVM_VGIC_V3_ADDR_TYPE_DIST,
+        &raw const dist as u64,
+    )?;
+    set(
+        KVM_DEV_ARM_VGIC_GRP_ADDR,
+        KVM_VGIC_V3_ADDR_TYPE_REDIST,
+        &raw const redist as u64,
+    )?;
+
+    // Reserve a SPI for each vector.
+    let irqs = GIC_SPI_BASE + 256;
+
+    set(KVM_DEV_ARM_VGIC_GRP_NR_IRQS, 0, &raw const irqs as u64)?;
+    set(KVM_DEV_ARM_VGIC_GRP_CTRL, KVM_DEV_ARM_VGIC_CTRL_INIT, 0)?;
+
+    // Make all SPIs edge-triggered with GICD_ICFGR<n>, which has 2 bits for each interrupt.
+    let edge = 0xAAAAAAAAu32;
+
+    for n in (GIC_SPI_BASE / 16)..(irqs / 16) {
+        let off = 0x0C00 + u64::from(n) * 4;
+
+        set(KVM_DEV_ARM_VGIC_GRP_DIST_REGS, off, &raw const edge as u64)?;
+    }
+
+    Ok(gic)
+}
+
 #[cfg(target_arch = "x86_64")]
 fn create_vm(kvm: BorrowedFd) -> Result<OwnedFd, HvError> {
     let vm = unsafe { ioctl(kvm.as_raw_fd(), KVM_CREATE_VM, 0) };
@@ -319,6 +405,14 @@ fn get_ext(kvm: BorrowedFd, id: c_int) -> Result<c_uint, Error> {
     }
 }
 
+/// Guest physical address of the GIC distributor.
+#[cfg(target_arch = "aarch64")]
+const GIC_DIST: u64 = 0xF00000000;
+
+/// INTID of the SPI for vector 0.
+#[cfg(target_arch = "aarch64")]
+const GIC_SPI_BASE: u32 = 32;
+
 /// Implementation of [`Hypervisor`] using KVM.
 ///
 /// Fields in this struct need to drop in a correct order (e.g. vm must be dropped before ram).
@@ -326,7 +420,10 @@ struct Kvm {
     feats: CpuFeats,
     cpus: Vec<Mutex<OwnedFd>>,
     vcpu_mmap_size: usize,
-    #[allow(dead_code)]
+    #[cfg(target_arch = "aarch64")]
+    #[allow(dead_code)] // gic need to be kept open.
+    gic: OwnedFd,
+    #[cfg_attr(target_arch = "x86_64", allow(dead_code))]
     vm: OwnedFd,
     ram: Ram,
     #[allow(dead_code)] // kvm are needed by vm.
@@ -419,7 +516,16 @@ impl Hypervisor for Kvm {
             return Err(HvError::GetKvmRun(Error::last_os_error()));
         }
 
-        Ok(unsafe { KvmCpu::new(id, cpu, cx.cast(), self.vcpu_mmap_size) })
+        Ok(unsafe {
+            KvmCpu::new(
+                id,
+                cpu,
+                #[cfg(target_arch = "aarch64")]
+                self.vm.as_fd(),
+                cx.cast(),
+                self.vcpu_mmap_size,
+            )
+        })
     }
 }
 
@@ -436,6 +542,9 @@ pub enum HvError {
     #[error("couldn't create a RAM")]
     CreateRamFailed(#[source] Error),
 
+    #[error("couldn't install a signal handler to kick vCPU")]
+    InstallKickHandlerFailed(#[source] Error),
+
     #[error("couldn't open /dev/kvm")]
     OpenKvmFailed(#[source] Error),
 
@@ -494,6 +603,14 @@ pub enum HvError {
     #[error("couldn't initialize vCPU #{0}")]
     InitCpuFailed(usize, #[source] Error),
 
+    #[cfg(target_arch = "aarch64")]
+    #[error("couldn't create a GIC")]
+    CreateGicFailed(#[source] Error),
+
+    #[cfg(target_arch = "aarch64")]
+    #[error("couldn't setup the GIC")]
+    SetupGicFailed(#[source] Error),
+
     #[cfg(target_arch = "x86_64")]
     #[error("couldn't set CPUID")]
     SetCpuFeats(#[source] Error),
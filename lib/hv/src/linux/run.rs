@@ -84,6 +84,7 @@ pub struct Debug {
     pub arch: KvmDebugExitArch,
 }
 
+#[cfg(target_arch = "x86_64")]
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct KvmDebugExitArch {
@@ -94,6 +95,15 @@ pub struct KvmDebugExitArch {
     pub dr7: u64,
 }
 
+#[cfg(target_arch = "aarch64")]
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct KvmDebugExitArch {
+    pub hsr: u32,
+    pub hsr_high: u32,
+    pub far: u64,
+}
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct Mmio {
@@ -1,11 +1,16 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 use super::ffi::{
-    KVM_GET_FPU, KVM_GET_REGS, KVM_GET_SREGS, KVM_SET_REGS, KVM_SET_SREGS, KvmFpu, KvmRegs,
-    KvmSregs,
+    KVM_GET_FPU, KVM_GET_LAPIC, KVM_GET_MSRS, KVM_GET_REGS, KVM_GET_SREGS, KVM_GET_VCPU_EVENTS,
+    KVM_GET_XCRS, KVM_GET_XSAVE, KVM_SET_FPU, KVM_SET_LAPIC, KVM_SET_MSRS, KVM_SET_REGS,
+    KVM_SET_SREGS, KVM_SET_VCPU_EVENTS, KVM_SET_XCRS, KVM_SET_XSAVE, KvmFpu, KvmGuestDebugArch,
+    KvmLapicState, KvmMsrEntry, KvmMsrs, KvmRegs, KvmSregs, KvmVcpuEvents, KvmXcrs, KvmXsave,
 };
+use super::run::KvmDebugExitArch;
 use super::{HvError, Kvm};
-use crate::{CpuCommit, CpuStates, FeatLeaf, HypervisorExt};
-use libc::ioctl;
+use crate::{CpuCommit, CpuStates, DebugEvent, FeatLeaf, HwDebug, HypervisorExt};
+use libc::{EINVAL, ioctl};
+use std::ffi::c_ulong;
+use std::io::Error;
 use std::mem::MaybeUninit;
 use std::os::fd::{AsRawFd, OwnedFd};
 use thiserror::Error;
@@ -22,6 +27,239 @@ impl HypervisorExt for Kvm {
     }
 }
 
+/// MSRs that are not covered by [`KvmSregs`] to be included in a snapshot.
+const SNAPSHOT_MSRS: [u32; 6] = [
+    0x00000010, // TSC
+    0xC0000081, // STAR
+    0xC0000082, // LSTAR
+    0xC0000083, // CSTAR
+    0xC0000084, // SFMASK
+    0xC0000102, // KERNEL_GS_BASE
+];
+
+/// Size of the data produced by [`snapshot()`] when the vCPU does not have LAPIC.
+const SNAPSHOT_LEN: usize = size_of::<KvmRegs>()
+    + size_of::<KvmSregs>()
+    + size_of::<KvmXcrs>()
+    + size_of::<KvmXsave>()
+    + size_of::<KvmVcpuEvents>()
+    + size_of::<u64>() * SNAPSHOT_MSRS.len()
+    + 1;
+
+pub fn snapshot(cpu: &OwnedFd) -> Result<Vec<u8>, SnapshotError> {
+    let mut data = Vec::with_capacity(SNAPSHOT_LEN + size_of::<KvmLapicState>());
+
+    // General purpose registers.
+    let gregs = unsafe { get::<KvmRegs>(cpu, KVM_GET_REGS).map_err(SnapshotError::GetGeneral)? };
+
+    data.extend_from_slice(unsafe { as_bytes(&gregs) });
+
+    // Special registers.
+    let sregs = unsafe { get::<KvmSregs>(cpu, KVM_GET_SREGS).map_err(SnapshotError::GetSpecial)? };
+
+    data.extend_from_slice(unsafe { as_bytes(&sregs) });
+
+    // Extended control registers (e.g. XCR0).
+    let xcrs = unsafe { get::<KvmXcrs>(cpu, KVM_GET_XCRS).map_err(SnapshotError::GetXcrs)? };
+
+    data.extend_from_slice(unsafe { as_bytes(&xcrs) });
+
+    // FPU, SSE and AVX registers.
+    let xsave = unsafe { get::<KvmXsave>(cpu, KVM_GET_XSAVE).map_err(SnapshotError::GetXsave)? };
+
+    data.extend_from_slice(unsafe { as_bytes(&xsave) });
+
+    // Pending exceptions and interrupts.
+    let events = unsafe {
+        get::<KvmVcpuEvents>(cpu, KVM_GET_VCPU_EVENTS).map_err(SnapshotError::GetEvents)?
+    };
+
+    data.extend_from_slice(unsafe { as_bytes(&events) });
+
+    // MSRs.
+    let mut msrs = KvmMsrs {
+        nmsrs: SNAPSHOT_MSRS.len().try_into().unwrap(),
+        pad: 0,
+        entries: SNAPSHOT_MSRS.map(|index| KvmMsrEntry {
+            index,
+            reserved: 0,
+            data: 0,
+        }),
+    };
+
+    match unsafe { ioctl(cpu.as_raw_fd(), KVM_GET_MSRS, &mut msrs) } {
+        v if v < 0 => return Err(SnapshotError::GetMsrs(Error::last_os_error())),
+        v if v as usize != SNAPSHOT_MSRS.len() => return Err(SnapshotError::IncompleteMsrs),
+        _ => (),
+    }
+
+    for e in msrs.entries {
+        data.extend_from_slice(&e.data.to_le_bytes());
+    }
+
+    // LAPIC. The vCPU only has it when the VM has an in-kernel irqchip.
+    match unsafe { get::<KvmLapicState>(cpu, KVM_GET_LAPIC) } {
+        Ok(v) => {
+            data.push(1);
+            data.extend_from_slice(&v.regs);
+        }
+        Err(e) if e.raw_os_error() == Some(EINVAL) => data.push(0),
+        Err(e) => return Err(SnapshotError::GetLapic(e)),
+    }
+
+    Ok(data)
+}
+
+pub fn restore(cpu: &OwnedFd, data: &[u8]) -> Result<(), SnapshotError> {
+    let (mut data, lapic) = data
+        .split_at_checked(SNAPSHOT_LEN - 1)
+        .ok_or(SnapshotError::InvalidData)?;
+    let lapic = match lapic {
+        [0] => None,
+        [1, v @ ..] if v.len() == size_of::<KvmLapicState>() => Some(KvmLapicState {
+            regs: v.try_into().unwrap(),
+        }),
+        _ => return Err(SnapshotError::InvalidData),
+    };
+
+    // General purpose registers.
+    let gregs = unsafe { read::<KvmRegs>(&mut data) };
+
+    unsafe { set(cpu, KVM_SET_REGS, &gregs).map_err(SnapshotError::SetGeneral)? };
+
+    // Special registers. This need to be restored before XCR0 since it depends on CR4.
+    let sregs = unsafe { read::<KvmSregs>(&mut data) };
+
+    unsafe { set(cpu, KVM_SET_SREGS, &sregs).map_err(SnapshotError::SetSpecial)? };
+
+    // Extended control registers.
+    let xcrs = unsafe { read::<KvmXcrs>(&mut data) };
+
+    unsafe { set(cpu, KVM_SET_XCRS, &xcrs).map_err(SnapshotError::SetXcrs)? };
+
+    // FPU, SSE and AVX registers.
+    let xsave = unsafe { read::<KvmXsave>(&mut data) };
+
+    unsafe { set(cpu, KVM_SET_XSAVE, &xsave).map_err(SnapshotError::SetXsave)? };
+
+    // MSRs.
+    let (events, data) = data.split_at(size_of::<KvmVcpuEvents>());
+    let mut values = data.chunks_exact(8);
+    let msrs = KvmMsrs {
+        nmsrs: SNAPSHOT_MSRS.len().try_into().unwrap(),
+        pad: 0,
+        entries: SNAPSHOT_MSRS.map(|index| KvmMsrEntry {
+            index,
+            reserved: 0,
+            data: u64::from_le_bytes(values.next().unwrap().try_into().unwrap()),
+        }),
+    };
+
+    match unsafe { ioctl(cpu.as_raw_fd(), KVM_SET_MSRS, &msrs) } {
+        v if v < 0 => return Err(SnapshotError::SetMsrs(Error::last_os_error())),
+        v if v as usize != SNAPSHOT_MSRS.len() => return Err(SnapshotError::IncompleteMsrs),
+        _ => (),
+    }
+
+    // Pending exceptions and interrupts. This need to be restored after the registers otherwise
+    // KVM may discard it.
+    let events = unsafe { read::<KvmVcpuEvents>(&mut &events[..]) };
+
+    unsafe { set(cpu, KVM_SET_VCPU_EVENTS, &events).map_err(SnapshotError::SetEvents)? };
+
+    // LAPIC.
+    if let Some(v) = lapic {
+        unsafe { set(cpu, KVM_SET_LAPIC, &v).map_err(SnapshotError::SetLapic)? };
+    }
+
+    Ok(())
+}
+
+pub fn debug_regs(v: &HwDebug) -> KvmGuestDebugArch {
+    let mut debugreg = [0; 8];
+
+    for (i, &addr) in v.addrs.iter().enumerate() {
+        debugreg[i] = addr.try_into().unwrap();
+    }
+
+    debugreg[7] = v.dr7.try_into().unwrap();
+
+    KvmGuestDebugArch { debugreg }
+}
+
+pub fn debug_event(exit: &KvmDebugExitArch, debug: &HwDebug) -> DebugEvent {
+    match exit.exception {
+        1 => (),
+        3 => return DebugEvent::SwBreak,
+        v => return DebugEvent::Guest(v.try_into().unwrap()),
+    }
+
+    // Check which DRn was triggered. The B0-B3 on DR6 may be set even if the breakpoint was not
+    // enabled so we need to check with DR7 too.
+    for (i, &addr) in debug.addrs.iter().enumerate() {
+        if exit.dr6 & (1 << i) == 0 || exit.dr7 & (0b11 << (i * 2)) == 0 {
+            continue;
+        }
+
+        return match (exit.dr7 >> (16 + i * 4)) & 0b11 {
+            0b00 => DebugEvent::HwBreak,
+            0b01 => DebugEvent::WriteWatch(addr),
+            0b11 => DebugEvent::AccessWatch(addr),
+            _ => continue, // We never enable I/O breakpoint.
+        };
+    }
+
+    // Check BS. The guest can also set TF by itself so we need to check if we are stepping.
+    if exit.dr6 & 0x4000 != 0 && debug.step {
+        DebugEvent::Step
+    } else {
+        DebugEvent::Guest(1)
+    }
+}
+
+/// # Safety
+/// `T` must not contains any padding.
+unsafe fn as_bytes<T>(v: &T) -> &[u8] {
+    unsafe { std::slice::from_raw_parts((v as *const T).cast(), size_of::<T>()) }
+}
+
+/// # Safety
+/// `req` must be a request to read `T` from the vCPU.
+unsafe fn get<T>(cpu: &OwnedFd, req: c_ulong) -> Result<T, Error> {
+    let mut v = MaybeUninit::<T>::uninit();
+
+    if unsafe { ioctl(cpu.as_raw_fd(), req, v.as_mut_ptr()) < 0 } {
+        Err(Error::last_os_error())
+    } else {
+        Ok(unsafe { v.assume_init() })
+    }
+}
+
+/// # Safety
+/// `req` must be a request to write `T` to the vCPU.
+unsafe fn set<T>(cpu: &OwnedFd, req: c_ulong, v: &T) -> Result<(), Error> {
+    if unsafe { ioctl(cpu.as_raw_fd(), req, v) < 0 } {
+        Err(Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Read `T` from the beginning of `data` and advance it.
+///
+/// # Safety
+/// Any bit pattern must be a valid `T`.
+///
+/// # Panics
+/// If `data` is smaller than `T`.
+unsafe fn read<T>(data: &mut &[u8]) -> T {
+    let (v, rest) = data.split_at(size_of::<T>());
+
+    *data = rest;
+
+    unsafe { v.as_ptr().cast::<T>().read_unaligned() }
+}
+
 /// Implementation of [`CpuStates`] for KVM.
 pub struct KvmStates<'a> {
     cpu: &'a mut OwnedFd,
@@ -30,12 +268,11 @@ pub struct KvmStates<'a> {
     sregs: KvmSregs,
     sdirty: bool,
     fregs: KvmFpu,
+    fdirty: bool,
 }
 
 impl<'a> KvmStates<'a> {
     pub fn from_cpu(cpu: &'a mut OwnedFd) -> Result<Self, StatesError> {
-        use std::io::Error;
-
         // Load general purpose registers.
         let mut gregs = MaybeUninit::uninit();
         let gregs = if unsafe { ioctl(cpu.as_raw_fd(), KVM_GET_REGS, gregs.as_mut_ptr()) < 0 } {
@@ -67,6 +304,7 @@ impl<'a> KvmStates<'a> {
             sregs,
             sdirty: false,
             fregs,
+            fdirty: false,
         })
     }
 }
@@ -78,14 +316,29 @@ impl CpuStates for KvmStates<'_> {
         Ok(self.gregs.rax.try_into().unwrap())
     }
 
+    fn set_rax(&mut self, v: usize) {
+        self.gregs.rax = v.try_into().unwrap();
+        self.gdirty = true;
+    }
+
     fn get_rbx(&mut self) -> Result<usize, Self::Err> {
         Ok(self.gregs.rbx.try_into().unwrap())
     }
 
+    fn set_rbx(&mut self, v: usize) {
+        self.gregs.rbx = v.try_into().unwrap();
+        self.gdirty = true;
+    }
+
     fn get_rcx(&mut self) -> Result<usize, Self::Err> {
         Ok(self.gregs.rcx.try_into().unwrap())
     }
 
+    fn set_rcx(&mut self, v: usize) {
+        self.gregs.rcx = v.try_into().unwrap();
+        self.gdirty = true;
+    }
+
     fn get_rdx(&mut self) -> Result<usize, Self::Err> {
         Ok(self.gregs.rdx.try_into().unwrap())
     }
@@ -99,38 +352,83 @@ impl CpuStates for KvmStates<'_> {
         Ok(self.gregs.rbp.try_into().unwrap())
     }
 
+    fn set_rbp(&mut self, v: usize) {
+        self.gregs.rbp = v.try_into().unwrap();
+        self.gdirty = true;
+    }
+
     fn get_r8(&mut self) -> Result<usize, Self::Err> {
         Ok(self.gregs.r8.try_into().unwrap())
     }
 
+    fn set_r8(&mut self, v: usize) {
+        self.gregs.r8 = v.try_into().unwrap();
+        self.gdirty = true;
+    }
+
     fn get_r9(&mut self) -> Result<usize, Self::Err> {
         Ok(self.gregs.r9.try_into().unwrap())
     }
 
+    fn set_r9(&mut self, v: usize) {
+        self.gregs.r9 = v.try_into().unwrap();
+        self.gdirty = true;
+    }
+
     fn get_r10(&mut self) -> Result<usize, Self::Err> {
         Ok(self.gregs.r10.try_into().unwrap())
     }
 
+    fn set_r10(&mut self, v: usize) {
+        self.gregs.r10 = v.try_into().unwrap();
+        self.gdirty = true;
+    }
+
     fn get_r11(&mut self) -> Result<usize, Self::Err> {
         Ok(self.gregs.r11.try_into().unwrap())
     }
 
+    fn set_r11(&mut self, v: usize) {
+        self.gregs.r11 = v.try_into().unwrap();
+        self.gdirty = true;
+    }
+
     fn get_r12(&mut self) -> Result<usize, Self::Err> {
         Ok(self.gregs.r12.try_into().unwrap())
     }
 
+    fn set_r12(&mut self, v: usize) {
+        self.gregs.r12 = v.try_into().unwrap();
+        self.gdirty = true;
+    }
+
     fn get_r13(&mut self) -> Result<usize, Self::Err> {
         Ok(self.gregs.r13.try_into().unwrap())
     }
 
+    fn set_r13(&mut self, v: usize) {
+        self.gregs.r13 = v.try_into().unwrap();
+        self.gdirty = true;
+    }
+
     fn get_r14(&mut self) -> Result<usize, Self::Err> {
         Ok(self.gregs.r14.try_into().unwrap())
     }
 
+    fn set_r14(&mut self, v: usize) {
+        self.gregs.r14 = v.try_into().unwrap();
+        self.gdirty = true;
+    }
+
     fn get_r15(&mut self) -> Result<usize, Self::Err> {
         Ok(self.gregs.r15.try_into().unwrap())
     }
 
+    fn set_r15(&mut self, v: usize) {
+        self.gregs.r15 = v.try_into().unwrap();
+        self.gdirty = true;
+    }
+
     fn get_rdi(&mut self) -> Result<usize, Self::Err> {
         Ok(self.gregs.rdi.try_into().unwrap())
     }
@@ -172,6 +470,14 @@ impl CpuStates for KvmStates<'_> {
         self.sdirty = true;
     }
 
+    fn get_cr2(&mut self) -> Result<usize, Self::Err> {
+        Ok(self.sregs.cr2.try_into().unwrap())
+    }
+
+    fn get_cr3(&mut self) -> Result<usize, Self::Err> {
+        Ok(self.sregs.cr3.try_into().unwrap())
+    }
+
     fn set_cr3(&mut self, v: usize) {
         self.sregs.cr3 = v.try_into().unwrap();
         self.sdirty = true;
@@ -200,6 +506,11 @@ impl CpuStates for KvmStates<'_> {
         Ok(self.sregs.cs.selector)
     }
 
+    fn set_cs_selector(&mut self, v: u16) {
+        self.sregs.cs.selector = v;
+        self.sdirty = true;
+    }
+
     fn set_cs(&mut self, ty: u8, dpl: u8, p: bool, l: bool, d: bool) {
         self.sregs.cs.ty = ty;
         self.sregs.cs.dpl = dpl;
@@ -213,6 +524,11 @@ impl CpuStates for KvmStates<'_> {
         Ok(self.sregs.ds.selector)
     }
 
+    fn set_ds_selector(&mut self, v: u16) {
+        self.sregs.ds.selector = v;
+        self.sdirty = true;
+    }
+
     fn set_ds(&mut self, p: bool) {
         self.sregs.ds.present = p.into();
         self.sdirty = true;
@@ -222,6 +538,11 @@ impl CpuStates for KvmStates<'_> {
         Ok(self.sregs.es.selector)
     }
 
+    fn set_es_selector(&mut self, v: u16) {
+        self.sregs.es.selector = v;
+        self.sdirty = true;
+    }
+
     fn set_es(&mut self, p: bool) {
         self.sregs.es.present = p.into();
         self.sdirty = true;
@@ -231,6 +552,11 @@ impl CpuStates for KvmStates<'_> {
         Ok(self.sregs.fs.selector)
     }
 
+    fn set_fs_selector(&mut self, v: u16) {
+        self.sregs.fs.selector = v;
+        self.sdirty = true;
+    }
+
     fn set_fs(&mut self, p: bool) {
         self.sregs.fs.present = p.into();
         self.sdirty = true;
@@ -240,6 +566,11 @@ impl CpuStates for KvmStates<'_> {
         Ok(self.sregs.gs.selector)
     }
 
+    fn set_gs_selector(&mut self, v: u16) {
+        self.sregs.gs.selector = v;
+        self.sdirty = true;
+    }
+
     fn set_gs(&mut self, p: bool) {
         self.sregs.gs.present = p.into();
         self.sdirty = true;
@@ -249,6 +580,11 @@ impl CpuStates for KvmStates<'_> {
         Ok(self.sregs.ss.selector)
     }
 
+    fn set_ss_selector(&mut self, v: u16) {
+        self.sregs.ss.selector = v;
+        self.sdirty = true;
+    }
+
     fn set_ss(&mut self, p: bool) {
         self.sregs.ss.present = p.into();
         self.sdirty = true;
@@ -258,139 +594,302 @@ impl CpuStates for KvmStates<'_> {
         Ok(self.fregs.fpr[0][..10].try_into().unwrap())
     }
 
+    fn set_st0(&mut self, v: [u8; 10]) {
+        self.fregs.fpr[0][..10].copy_from_slice(&v);
+        self.fdirty = true;
+    }
+
     fn get_st1(&mut self) -> Result<[u8; 10], Self::Err> {
         Ok(self.fregs.fpr[1][..10].try_into().unwrap())
     }
 
+    fn set_st1(&mut self, v: [u8; 10]) {
+        self.fregs.fpr[1][..10].copy_from_slice(&v);
+        self.fdirty = true;
+    }
+
     fn get_st2(&mut self) -> Result<[u8; 10], Self::Err> {
         Ok(self.fregs.fpr[2][..10].try_into().unwrap())
     }
 
+    fn set_st2(&mut self, v: [u8; 10]) {
+        self.fregs.fpr[2][..10].copy_from_slice(&v);
+        self.fdirty = true;
+    }
+
     fn get_st3(&mut self) -> Result<[u8; 10], Self::Err> {
         Ok(self.fregs.fpr[3][..10].try_into().unwrap())
     }
 
+    fn set_st3(&mut self, v: [u8; 10]) {
+        self.fregs.fpr[3][..10].copy_from_slice(&v);
+        self.fdirty = true;
+    }
+
     fn get_st4(&mut self) -> Result<[u8; 10], Self::Err> {
         Ok(self.fregs.fpr[4][..10].try_into().unwrap())
     }
 
+    fn set_st4(&mut self, v: [u8; 10]) {
+        self.fregs.fpr[4][..10].copy_from_slice(&v);
+        self.fdirty = true;
+    }
+
     fn get_st5(&mut self) -> Result<[u8; 10], Self::Err> {
         Ok(self.fregs.fpr[5][..10].try_into().unwrap())
     }
 
+    fn set_st5(&mut self, v: [u8; 10]) {
+        self.fregs.fpr[5][..10].copy_from_slice(&v);
+        self.fdirty = true;
+    }
+
     fn get_st6(&mut self) -> Result<[u8; 10], Self::Err> {
         Ok(self.fregs.fpr[6][..10].try_into().unwrap())
     }
 
+    fn set_st6(&mut self, v: [u8; 10]) {
+        self.fregs.fpr[6][..10].copy_from_slice(&v);
+        self.fdirty = true;
+    }
+
     fn get_st7(&mut self) -> Result<[u8; 10], Self::Err> {
         Ok(self.fregs.fpr[7][..10].try_into().unwrap())
     }
 
+    fn set_st7(&mut self, v: [u8; 10]) {
+        self.fregs.fpr[7][..10].copy_from_slice(&v);
+        self.fdirty = true;
+    }
+
     fn get_fcw(&mut self) -> Result<u32, Self::Err> {
         Ok(self.fregs.fcw.into())
     }
 
+    fn set_fcw(&mut self, v: u32) {
+        self.fregs.fcw = v as u16;
+        self.fdirty = true;
+    }
+
     fn get_fsw(&mut self) -> Result<u32, Self::Err> {
         Ok(self.fregs.fsw.into())
     }
 
+    fn set_fsw(&mut self, v: u32) {
+        self.fregs.fsw = v as u16;
+        self.fdirty = true;
+    }
+
     fn get_ftwx(&mut self) -> Result<u32, Self::Err> {
         Ok(self.fregs.ftwx.into())
     }
 
+    fn set_ftwx(&mut self, v: u32) {
+        self.fregs.ftwx = v as u8;
+        self.fdirty = true;
+    }
+
     fn get_fiseg(&mut self) -> Result<u32, Self::Err> {
         Ok((self.fregs.last_ip >> 32) as u32)
     }
 
+    fn set_fiseg(&mut self, v: u32) {
+        self.fregs.last_ip = (self.fregs.last_ip & 0xFFFFFFFF) | u64::from(v) << 32;
+        self.fdirty = true;
+    }
+
     fn get_fioff(&mut self) -> Result<u32, Self::Err> {
         Ok((self.fregs.last_ip & 0xFFFFFFFF) as u32)
     }
 
+    fn set_fioff(&mut self, v: u32) {
+        self.fregs.last_ip = (self.fregs.last_ip & !0xFFFFFFFF) | u64::from(v);
+        self.fdirty = true;
+    }
+
     fn get_foseg(&mut self) -> Result<u32, Self::Err> {
         Ok((self.fregs.last_dp >> 32) as u32)
     }
 
+    fn set_foseg(&mut self, v: u32) {
+        self.fregs.last_dp = (self.fregs.last_dp & 0xFFFFFFFF) | u64::from(v) << 32;
+        self.fdirty = true;
+    }
+
     fn get_fooff(&mut self) -> Result<u32, Self::Err> {
         Ok((self.fregs.last_dp & 0xFFFFFFFF) as u32)
     }
 
+    fn set_fooff(&mut self, v: u32) {
+        self.fregs.last_dp = (self.fregs.last_dp & !0xFFFFFFFF) | u64::from(v);
+        self.fdirty = true;
+    }
+
     fn get_fop(&mut self) -> Result<u32, Self::Err> {
         Ok(self.fregs.last_opcode.into())
     }
 
+    fn set_fop(&mut self, v: u32) {
+        self.fregs.last_opcode = v as u16;
+        self.fdirty = true;
+    }
+
     fn get_xmm0(&mut self) -> Result<u128, Self::Err> {
         Ok(u128::from_le_bytes(self.fregs.xmm[0]))
     }
 
+    fn set_xmm0(&mut self, v: u128) {
+        self.fregs.xmm[0] = v.to_le_bytes();
+        self.fdirty = true;
+    }
+
     fn get_xmm1(&mut self) -> Result<u128, Self::Err> {
         Ok(u128::from_le_bytes(self.fregs.xmm[1]))
     }
 
+    fn set_xmm1(&mut self, v: u128) {
+        self.fregs.xmm[1] = v.to_le_bytes();
+        self.fdirty = true;
+    }
+
     fn get_xmm2(&mut self) -> Result<u128, Self::Err> {
         Ok(u128::from_le_bytes(self.fregs.xmm[2]))
     }
 
+    fn set_xmm2(&mut self, v: u128) {
+        self.fregs.xmm[2] = v.to_le_bytes();
+        self.fdirty = true;
+    }
+
     fn get_xmm3(&mut self) -> Result<u128, Self::Err> {
         Ok(u128::from_le_bytes(self.fregs.xmm[3]))
     }
 
+    fn set_xmm3(&mut self, v: u128) {
+        self.fregs.xmm[3] = v.to_le_bytes();
+        self.fdirty = true;
+    }
+
     fn get_xmm4(&mut self) -> Result<u128, Self::Err> {
         Ok(u128::from_le_bytes(self.fregs.xmm[4]))
     }
 
+    fn set_xmm4(&mut self, v: u128) {
+        self.fregs.xmm[4] = v.to_le_bytes();
+        self.fdirty = true;
+    }
+
     fn get_xmm5(&mut self) -> Result<u128, Self::Err> {
         Ok(u128::from_le_bytes(self.fregs.xmm[5]))
     }
 
+    fn set_xmm5(&mut self, v: u128) {
+        self.fregs.xmm[5] = v.to_le_bytes();
+        self.fdirty = true;
+    }
+
     fn get_xmm6(&mut self) -> Result<u128, Self::Err> {
         Ok(u128::from_le_bytes(self.fregs.xmm[6]))
     }
 
+    fn set_xmm6(&mut self, v: u128) {
+        self.fregs.xmm[6] = v.to_le_bytes();
+        self.fdirty = true;
+    }
+
     fn get_xmm7(&mut self) -> Result<u128, Self::Err> {
         Ok(u128::from_le_bytes(self.fregs.xmm[7]))
     }
 
+    fn set_xmm7(&mut self, v: u128) {
+        self.fregs.xmm[7] = v.to_le_bytes();
+        self.fdirty = true;
+    }
+
     fn get_xmm8(&mut self) -> Result<u128, Self::Err> {
         Ok(u128::from_le_bytes(self.fregs.xmm[8]))
     }
 
+    fn set_xmm8(&mut self, v: u128) {
+        self.fregs.xmm[8] = v.to_le_bytes();
+        self.fdirty = true;
+    }
+
     fn get_xmm9(&mut self) -> Result<u128, Self::Err> {
         Ok(u128::from_le_bytes(self.fregs.xmm[9]))
     }
 
+    fn set_xmm9(&mut self, v: u128) {
+        self.fregs.xmm[9] = v.to_le_bytes();
+        self.fdirty = true;
+    }
+
     fn get_xmm10(&mut self) -> Result<u128, Self::Err> {
         Ok(u128::from_le_bytes(self.fregs.xmm[10]))
     }
 
+    fn set_xmm10(&mut self, v: u128) {
+        self.fregs.xmm[10] = v.to_le_bytes();
+        self.fdirty = true;
+    }
+
     fn get_xmm11(&mut self) -> Result<u128, Self::Err> {
         Ok(u128::from_le_bytes(self.fregs.xmm[11]))
     }
 
+    fn set_xmm11(&mut self, v: u128) {
+        self.fregs.xmm[11] = v.to_le_bytes();
+        self.fdirty = true;
+    }
+
     fn get_xmm12(&mut self) -> Result<u128, Self::Err> {
         Ok(u128::from_le_bytes(self.fregs.xmm[12]))
     }
 
+    fn set_xmm12(&mut self, v: u128) {
+        self.fregs.xmm[12] = v.to_le_bytes();
+        self.fdirty = true;
+    }
+
     fn get_xmm13(&mut self) -> Result<u128, Self::Err> {
         Ok(u128::from_le_bytes(self.fregs.xmm[13]))
     }
 
+    fn set_xmm13(&mut self, v: u128) {
+        self.fregs.xmm[13] = v.to_le_bytes();
+        self.fdirty = true;
+    }
+
     fn get_xmm14(&mut self) -> Result<u128, Self::Err> {
         Ok(u128::from_le_bytes(self.fregs.xmm[14]))
     }
 
+    fn set_xmm14(&mut self, v: u128) {
+        self.fregs.xmm[14] = v.to_le_bytes();
+        self.fdirty = true;
+    }
+
     fn get_xmm15(&mut self) -> Result<u128, Self::Err> {
         Ok(u128::from_le_bytes(self.fregs.xmm[15]))
     }
 
+    fn set_xmm15(&mut self, v: u128) {
+        self.fregs.xmm[15] = v.to_le_bytes();
+        self.fdirty = true;
+    }
+
     fn get_mxcsr(&mut self) -> Result<u32, Self::Err> {
         Ok(self.fregs.mxcsr)
     }
+
+    fn set_mxcsr(&mut self, v: u32) {
+        self.fregs.mxcsr = v;
+        self.fdirty = true;
+    }
 }
 
 impl CpuCommit for KvmStates<'_> {
     fn commit(self) -> Result<(), Self::Err> {
-        use std::io::Error;
-
         // Set general purpose registers.
         if unsafe { self.gdirty && ioctl(self.cpu.as_raw_fd(), KVM_SET_REGS, &self.gregs) < 0 } {
             return Err(StatesError::SetGeneral(Error::last_os_error()));
@@ -401,6 +900,11 @@ impl CpuCommit for KvmStates<'_> {
             return Err(StatesError::SetSpecial(Error::last_os_error()));
         }
 
+        // Set FPU registers.
+        if unsafe { self.fdirty && ioctl(self.cpu.as_raw_fd(), KVM_SET_FPU, &self.fregs) < 0 } {
+            return Err(StatesError::SetFp(Error::last_os_error()));
+        }
+
         Ok(())
     }
 }
@@ -422,4 +926,109 @@ pub enum StatesError {
 
     #[error("couldn't set special registers")]
     SetSpecial(#[source] std::io::Error),
+
+    #[error("couldn't set floating point registers")]
+    SetFp(#[source] std::io::Error),
+}
+
+/// Implementation of [`Cpu::SnapshotErr`](crate::Cpu::SnapshotErr).
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("couldn't get general purpose registers")]
+    GetGeneral(#[source] std::io::Error),
+
+    #[error("couldn't get special registers")]
+    GetSpecial(#[source] std::io::Error),
+
+    #[error("couldn't get extended control registers")]
+    GetXcrs(#[source] std::io::Error),
+
+    #[error("couldn't get XSAVE area")]
+    GetXsave(#[source] std::io::Error),
+
+    #[error("couldn't get pending events")]
+    GetEvents(#[source] std::io::Error),
+
+    #[error("couldn't get MSRs")]
+    GetMsrs(#[source] std::io::Error),
+
+    #[error("couldn't get LAPIC states")]
+    GetLapic(#[source] std::io::Error),
+
+    #[error("invalid snapshot data")]
+    InvalidData,
+
+    #[error("couldn't set general purpose registers")]
+    SetGeneral(#[source] std::io::Error),
+
+    #[error("couldn't set special registers")]
+    SetSpecial(#[source] std::io::Error),
+
+    #[error("couldn't set extended control registers")]
+    SetXcrs(#[source] std::io::Error),
+
+    #[error("couldn't set XSAVE area")]
+    SetXsave(#[source] std::io::Error),
+
+    #[error("couldn't set MSRs")]
+    SetMsrs(#[source] std::io::Error),
+
+    #[error("couldn't set pending events")]
+    SetEvents(#[source] std::io::Error),
+
+    #[error("couldn't set LAPIC states")]
+    SetLapic(#[source] std::io::Error),
+
+    #[error("some MSRs was not transferred")]
+    IncompleteMsrs,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cpu, CpuCommit, CpuStates, HvError, Hypervisor};
+    use std::num::NonZero;
+
+    #[test]
+    fn snapshot() {
+        let hv = match crate::new(
+            1,
+            NonZero::new(0x200000).unwrap(),
+            NonZero::new(0x1000).unwrap(),
+            false,
+        ) {
+            Ok(v) => v,
+            Err(HvError::OpenKvmFailed(_)) => return, // KVM is not available.
+            Err(e) => panic!("{e}"),
+        };
+        let mut cpu = hv.create_cpu(0).unwrap();
+        let mut states = cpu.states().unwrap();
+
+        states.set_rax(0x1234);
+        states.set_rsp(0x5678);
+        states.commit().unwrap();
+
+        let a = cpu.snapshot().unwrap();
+        let mut states = cpu.states().unwrap();
+
+        states.set_rax(0);
+        states.set_rsp(0);
+        states.commit().unwrap();
+
+        cpu.restore(&a).unwrap();
+
+        let b = cpu.snapshot().unwrap();
+        let tsc = SNAPSHOT_LEN - 1 - size_of::<u64>() * SNAPSHOT_MSRS.len();
+        let tsc = tsc..(tsc + 8);
+
+        assert_eq!(a.len(), b.len());
+        assert_eq!(a[..tsc.start], b[..tsc.start]);
+        assert_eq!(a[tsc.end..], b[tsc.end..]);
+        assert!(
+            u64::from_le_bytes(b[tsc.clone()].try_into().unwrap())
+                >= u64::from_le_bytes(a[tsc].try_into().unwrap())
+        );
+
+        assert!(cpu.restore(&a[..(a.len() - 1)]).is_err());
+    }
 }
@@ -1,18 +1,20 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 use crate::{
-    Cpu, CpuCommit, CpuDebug, CpuExit, CpuIo, CpuRun, CpuStates, DebugEvent, IoBuf, Pstate, Sctlr,
-    Tcr,
+    Cpu, CpuCommit, CpuDebug, CpuExit, CpuIo, CpuKicker, CpuRun, CpuStates, DebugEvent, HwDebug,
+    IoBuf, Pstate, Sctlr, Tcr,
 };
 use aarch64::Esr;
-use applevisor_sys::hv_exit_reason_t::HV_EXIT_REASON_EXCEPTION;
+use applevisor_sys::hv_exit_reason_t::{HV_EXIT_REASON_CANCELED, HV_EXIT_REASON_EXCEPTION};
+use applevisor_sys::hv_interrupt_type_t::HV_INTERRUPT_TYPE_IRQ;
 use applevisor_sys::hv_reg_t::{HV_REG_CPSR, HV_REG_PC, HV_REG_X0, HV_REG_X1};
 use applevisor_sys::hv_sys_reg_t::{
-    HV_SYS_REG_MAIR_EL1, HV_SYS_REG_SCTLR_EL1, HV_SYS_REG_SP_EL1, HV_SYS_REG_TCR_EL1,
-    HV_SYS_REG_TTBR0_EL1, HV_SYS_REG_TTBR1_EL1,
+    HV_SYS_REG_FAR_EL1, HV_SYS_REG_MAIR_EL1, HV_SYS_REG_SCTLR_EL1, HV_SYS_REG_SP_EL1,
+    HV_SYS_REG_TCR_EL1, HV_SYS_REG_TTBR0_EL1, HV_SYS_REG_TTBR1_EL1,
 };
 use applevisor_sys::{
-    hv_return_t, hv_vcpu_destroy, hv_vcpu_exit_t, hv_vcpu_run, hv_vcpu_set_reg,
-    hv_vcpu_set_sys_reg, hv_vcpu_t,
+    hv_reg_t, hv_return_t, hv_sys_reg_t, hv_vcpu_destroy, hv_vcpu_exit_t, hv_vcpu_get_reg,
+    hv_vcpu_get_sys_reg, hv_vcpu_run, hv_vcpu_set_pending_interrupt, hv_vcpu_set_reg,
+    hv_vcpu_set_sys_reg, hv_vcpu_t, hv_vcpus_exit,
 };
 use std::marker::PhantomData;
 use std::num::NonZero;
@@ -22,6 +24,8 @@ use thiserror::Error;
 pub struct HvfCpu<'a> {
     instance: hv_vcpu_t,
     exit: *const hv_vcpu_exit_t,
+    window: bool,
+    irq: bool,
     vm: PhantomData<&'a ()>,
 }
 
@@ -30,6 +34,8 @@ impl<'a> HvfCpu<'a> {
         Self {
             instance,
             exit,
+            window: false,
+            irq: false,
             vm: PhantomData,
         }
     }
@@ -56,6 +62,10 @@ impl<'a> Cpu for HvfCpu<'a> {
     where
         Self: 'b;
     type TranslateErr = std::io::Error;
+    type SnapshotErr = std::io::Error;
+    type DebugErr = std::io::Error;
+    type InterruptErr = InterruptError;
+    type Kicker = HvfKicker;
 
     fn id(&self) -> usize {
         todo!()
@@ -74,18 +84,81 @@ impl<'a> Cpu for HvfCpu<'a> {
             pc: State::None,
             x0: State::None,
             x1: State::None,
+            unsupported: None,
         })
     }
 
     fn translate(&self, vaddr: usize) -> Result<usize, std::io::Error> {
         todo!();
     }
+    fn snapshot(&mut self) -> Result<Vec<u8>, Self::SnapshotErr> {
+        todo!()
+    }
+
+    fn restore(&mut self, data: &[u8]) -> Result<(), Self::SnapshotErr> {
+        todo!()
+    }
+
+    fn set_debug(&mut self, v: &HwDebug) -> Result<(), Self::DebugErr> {
+        todo!()
+    }
+
+    fn kicker(&self) -> Self::Kicker {
+        HvfKicker(self.instance)
+    }
+
+    fn interruptible(&self) -> bool {
+        // Hypervisor Framework has only one IRQ line so we cannot inject another interrupt until
+        // the PE take the current one.
+        if self.irq {
+            return false;
+        }
+
+        // Hypervisor Framework use CPSR to represent PSTATE.
+        let mut v = 0;
+        let ret = unsafe { hv_vcpu_get_reg(self.instance, HV_REG_CPSR, &mut v) };
+
+        if ret != 0 {
+            panic!("hv_vcpu_get_reg() failed with {ret:#x}");
+        }
+
+        !Pstate::from_bits(v).i()
+    }
+
+    fn inject(&mut self, vector: u8) -> Result<(), Self::InterruptErr> {
+        // Hypervisor Framework clear the pending IRQ after hv_vcpu_run() but the PE will take it
+        // immediately since we only inject when PSTATE.I is cleared.
+        //
+        // TODO: Pass the vector to the kernel once we emulate a GIC.
+        let ret =
+            unsafe { hv_vcpu_set_pending_interrupt(self.instance, HV_INTERRUPT_TYPE_IRQ, true) };
+
+        if let Some(v) = NonZero::new(ret) {
+            return Err(InterruptError::SetPendingFailed(vector, v));
+        }
+
+        self.irq = true;
+
+        Ok(())
+    }
+
+    fn set_interrupt_window(&mut self, v: bool) {
+        self.window = v;
+    }
 }
 
 impl<'a> CpuRun for HvfCpu<'a> {
     type RunErr = RunError;
 
     fn run(&mut self) -> Result<Self::Exit<'_>, Self::RunErr> {
+        // Hypervisor Framework does not have an interrupt window so we exit immediately if the PE
+        // is already interruptible. Otherwise the next interrupt will be injected on the next exit.
+        if self.window && self.interruptible() {
+            HvfKicker(self.instance).kick();
+        }
+
+        self.irq = false;
+
         match NonZero::new(unsafe { hv_vcpu_run(self.instance) }) {
             Some(v) => Err(RunError::HypervisorFailed(v)),
             None => Ok(HvfExit(self)),
@@ -107,11 +180,43 @@ pub struct HvfStates<'a, 'b> {
 
     x0: State<u64>,
     x1: State<u64>,
+    unsupported: Option<&'static str>,
+}
+
+impl<'a, 'b> HvfStates<'a, 'b> {
+    fn get_reg(&self, reg: hv_reg_t) -> Result<u64, StatesError> {
+        let mut v = 0;
+        let ret = unsafe { hv_vcpu_get_reg(self.cpu.instance, reg, &mut v) };
+
+        match NonZero::new(ret) {
+            Some(e) => Err(StatesError::ReadRegisterFailed(e)),
+            None => Ok(v),
+        }
+    }
+
+    fn get_sys_reg(&self, reg: hv_sys_reg_t) -> Result<u64, StatesError> {
+        let mut v = 0;
+        let ret = unsafe { hv_vcpu_get_sys_reg(self.cpu.instance, reg, &mut v) };
+
+        match NonZero::new(ret) {
+            Some(e) => Err(StatesError::ReadRegisterFailed(e)),
+            None => Ok(v),
+        }
+    }
 }
 
 impl<'a, 'b> CpuStates for HvfStates<'a, 'b> {
     type Err = StatesError;
 
+    fn get_pstate(&mut self) -> Result<Pstate, Self::Err> {
+        let v = match self.pstate {
+            State::Dirty(v) | State::Clean(v) => v,
+            State::None => self.get_reg(HV_REG_CPSR)?,
+        };
+
+        Ok(Pstate::from_bits(v))
+    }
+
     fn set_pstate(&mut self, v: Pstate) {
         self.pstate = State::Dirty(v.into_bits());
     }
@@ -134,16 +239,47 @@ impl<'a, 'b> CpuStates for HvfStates<'a, 'b> {
         self.ttbr0_el1 = State::Dirty(baddr.try_into().unwrap());
     }
 
+    fn get_ttbr1_el1(&mut self) -> Result<usize, Self::Err> {
+        let v = match self.ttbr1_el1 {
+            State::Dirty(v) | State::Clean(v) => v,
+            State::None => self.get_sys_reg(HV_SYS_REG_TTBR1_EL1)?,
+        };
+
+        Ok(v.try_into().unwrap())
+    }
+
     fn set_ttbr1_el1(&mut self, baddr: usize) {
         assert_eq!(baddr & 0xFFFF000000000001, 0);
 
         self.ttbr1_el1 = State::Dirty(baddr.try_into().unwrap());
     }
 
+    fn get_far_el1(&mut self) -> Result<usize, Self::Err> {
+        Ok(self.get_sys_reg(HV_SYS_REG_FAR_EL1)?.try_into().unwrap())
+    }
+
+    fn get_sp_el1(&mut self) -> Result<usize, Self::Err> {
+        let v = match self.sp_el1 {
+            State::Dirty(v) | State::Clean(v) => v,
+            State::None => self.get_sys_reg(HV_SYS_REG_SP_EL1)?,
+        };
+
+        Ok(v.try_into().unwrap())
+    }
+
     fn set_sp_el1(&mut self, v: usize) {
         self.sp_el1 = State::Dirty(v.try_into().unwrap());
     }
 
+    fn get_pc(&mut self) -> Result<usize, Self::Err> {
+        let v = match self.pc {
+            State::Dirty(v) | State::Clean(v) => v,
+            State::None => self.get_reg(HV_REG_PC)?,
+        };
+
+        Ok(v.try_into().unwrap())
+    }
+
     fn set_pc(&mut self, v: usize) {
         self.pc = State::Dirty(v.try_into().unwrap());
     }
@@ -159,10 +295,46 @@ impl<'a, 'b> CpuStates for HvfStates<'a, 'b> {
     fn set_x2(&mut self, v: usize) {
         todo!();
     }
+
+    fn get_x(&mut self, _: usize) -> Result<usize, Self::Err> {
+        Err(StatesError::UnsupportedRegister("general registers"))
+    }
+
+    fn set_x(&mut self, _: usize, _: usize) {
+        self.unsupported.get_or_insert("general registers");
+    }
+
+    fn get_v(&mut self, _: usize) -> Result<u128, Self::Err> {
+        Err(StatesError::UnsupportedRegister("SIMD registers"))
+    }
+
+    fn set_v(&mut self, _: usize, _: u128) {
+        self.unsupported.get_or_insert("SIMD registers");
+    }
+
+    fn get_fpsr(&mut self) -> Result<u32, Self::Err> {
+        Err(StatesError::UnsupportedRegister("FPSR"))
+    }
+
+    fn set_fpsr(&mut self, _: u32) {
+        self.unsupported.get_or_insert("FPSR");
+    }
+
+    fn get_fpcr(&mut self) -> Result<u32, Self::Err> {
+        Err(StatesError::UnsupportedRegister("FPCR"))
+    }
+
+    fn set_fpcr(&mut self, _: u32) {
+        self.unsupported.get_or_insert("FPCR");
+    }
 }
 
 impl<'a, 'b> CpuCommit for HvfStates<'a, 'b> {
     fn commit(self) -> Result<(), Self::Err> {
+        if let Some(v) = self.unsupported {
+            return Err(StatesError::UnsupportedRegister(v));
+        }
+
         // Set PSTATE. Hypervisor Framework use CPSR to represent PSTATE.
         let cpu = self.cpu.instance;
         let set_reg = |reg, val| match NonZero::new(unsafe { hv_vcpu_set_reg(cpu, reg, val) }) {
@@ -260,6 +432,27 @@ impl<'a, 'b> CpuExit for HvfExit<'a, 'b> {
     fn into_debug(self) -> Result<Self::Debug, Self> {
         todo!()
     }
+
+    fn into_interrupt(self) -> Result<(), Self> {
+        if unsafe { (*self.0.exit).reason } == HV_EXIT_REASON_CANCELED {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+/// Implementation of [`Cpu::Kicker`] for Hypervisor Framework.
+pub struct HvfKicker(hv_vcpu_t);
+
+impl CpuKicker for HvfKicker {
+    fn kick(&self) {
+        let ret = unsafe { hv_vcpus_exit(&self.0, 1) };
+
+        if ret != 0 {
+            panic!("hv_vcpus_exit() failed with {ret:#x}");
+        }
+    }
 }
 
 /// Implementation of [`CpuIo`] for Hypervisor Framework.
@@ -303,6 +496,13 @@ pub enum RunError {
     HypervisorFailed(NonZero<hv_return_t>),
 }
 
+/// Implementation of [`Cpu::InterruptErr`].
+#[derive(Debug, Error)]
+pub enum InterruptError {
+    #[error("couldn't set pending interrupt {0:#x} ({1:#x})")]
+    SetPendingFailed(u8, NonZero<hv_return_t>),
+}
+
 /// Implementation of [`Cpu::GetStatesErr`] and [`CpuStates::Err`].
 #[derive(Debug, Error)]
 pub enum StatesError {
@@ -338,4 +538,7 @@ pub enum StatesError {
 
     #[error("couldn't set X1")]
     SetX1Failed(NonZero<hv_return_t>),
+
+    #[error("{0} is not supported")]
+    UnsupportedRegister(&'static str),
 }
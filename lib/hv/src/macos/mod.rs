@@ -191,6 +191,12 @@ pub enum HvError {
     #[error("couldn't create a RAM")]
     CreateRamFailed(#[source] std::io::Error),
 
+    #[error("invalid CPU identifier")]
+    InvalidCpuId,
+
+    #[error("CPU identifier currently in use")]
+    DuplicatedCpuId,
+
     #[error("couldn't create a VM ({0:#x})")]
     CreateVmFailed(NonZero<hv_return_t>),
 
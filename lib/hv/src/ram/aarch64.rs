@@ -6,14 +6,14 @@ use thiserror::Error;
 
 impl<'a, H: Hypervisor> RamBuilder<'a, H> {
     pub(super) fn build_4k_page_tables(
-        self,
+        &mut self,
         _: impl IntoIterator<Item = AllocInfo>,
     ) -> Result<usize, RamBuilderError> {
         todo!()
     }
 
     pub(super) fn build_16k_page_tables(
-        mut self,
+        &mut self,
         devices: impl IntoIterator<Item = AllocInfo>,
     ) -> Result<usize, RamBuilderError> {
         // Allocate page table level 0.
@@ -43,7 +43,23 @@ impl<'a, H: Hypervisor> RamBuilder<'a, H> {
         for info in std::mem::take(&mut self.allocated) {
             let len = info.len.get().next_multiple_of(0x4000);
 
-            self.setup_16k_page_tables(&mut cx, info.vaddr, info.paddr, len, info.attr)?;
+            self.setup_16k_page_tables(&mut cx, info.vaddr, info.paddr, len, info.attr, false)?;
+        }
+
+        // Setup page tables to map the whole RAM. We use 32MB blocks for this.
+        if let Some(info) = self.ram.take() {
+            let len = info.len.get().next_multiple_of(0x4000);
+            let large = len & !0x1FFFFFF;
+
+            self.setup_16k_page_tables(&mut cx, info.vaddr, info.paddr, large, info.attr, true)?;
+            self.setup_16k_page_tables(
+                &mut cx,
+                info.vaddr + large,
+                info.paddr + large,
+                len - large,
+                info.attr,
+                false,
+            )?;
         }
 
         // Setup page tables to map virtual devices.
@@ -52,12 +68,14 @@ impl<'a, H: Hypervisor> RamBuilder<'a, H> {
 
             assert!(dev.paddr >= self.hv.ram().len().get());
 
-            self.setup_16k_page_tables(&mut cx, dev.vaddr, dev.paddr, len, dev.attr)?;
+            self.setup_16k_page_tables(&mut cx, dev.vaddr, dev.paddr, len, dev.attr, false)?;
         }
 
         Ok(page_table)
     }
 
+    /// Set `large` to map the range with 32MB blocks instead of 16K pages, in which case `vaddr`,
+    /// `paddr` and `len` must be multiply by 32MB.
     fn setup_16k_page_tables(
         &mut self,
         cx: &mut Context16K<'a>,
@@ -65,12 +83,14 @@ impl<'a, H: Hypervisor> RamBuilder<'a, H> {
         paddr: usize,
         len: usize,
         attr: u8,
+        large: bool,
     ) -> Result<(), RamBuilderError> {
         let attr = usize::from(attr);
+        let page_size = if large { 0x2000000 } else { 0x4000 };
 
-        assert_eq!(vaddr % 0x4000, 0);
-        assert_eq!(paddr % 0x4000, 0);
-        assert_eq!(len % 0x4000, 0);
+        assert_eq!(vaddr % page_size, 0);
+        assert_eq!(paddr % page_size, 0);
+        assert_eq!(len % page_size, 0);
         assert_eq!(attr & 0b11111000, 0);
 
         fn set_table_descriptor(entry: &mut usize, addr: usize) {
@@ -81,7 +101,7 @@ impl<'a, H: Hypervisor> RamBuilder<'a, H> {
             *entry |= 1 << 10; // AF
         }
 
-        for off in (0..len).step_by(0x4000) {
+        for off in (0..len).step_by(page_size) {
             use std::collections::hash_map::Entry;
 
             // Get level 1 table.
@@ -121,8 +141,30 @@ impl<'a, H: Hypervisor> RamBuilder<'a, H> {
                 v => cx.l2t.get_mut(&(v & 0xFFFFFFFFC000)).unwrap(),
             };
 
-            // Get level 3 table.
+            // Set block descriptor for 32MB block.
             let l2o = (addr & 0xFFE000000) >> 25;
+
+            if large {
+                let paddr = paddr + off;
+                let mut desc = paddr;
+
+                assert_eq!(paddr & 0xFFFF000001FFFFFF, 0);
+
+                if l2t[l2o] != 0 {
+                    return Err(RamBuilderError::DuplicatedVirtualAddr(addr));
+                }
+
+                desc |= 0b01; // Valid descriptor + Block descriptor
+                desc |= attr << 2; // AttrIndx[2:0]
+                desc |= 0b00 << 6; // AP[2:1]
+                desc |= 0b11 << 8; // Inner Shareable
+                desc |= 1 << 10; // AF
+
+                l2t[l2o] = desc;
+                continue;
+            }
+
+            // Get level 3 table.
             let l3t = match l2t[l2o] {
                 0 => {
                     let (l3t, addr) = self
@@ -136,6 +178,7 @@ impl<'a, H: Hypervisor> RamBuilder<'a, H> {
                         Entry::Vacant(e) => e.insert(l3t),
                     }
                 }
+                v if v & 0b10 == 0 => return Err(RamBuilderError::DuplicatedVirtualAddr(addr)),
                 v => cx.l3t.get_mut(&(v & 0xFFFFFFFFC000)).unwrap(),
             };
 
@@ -248,4 +291,7 @@ pub enum RamBuilderError {
 
     #[error("duplicated virtual address {0:#x}")]
     DuplicatedVirtualAddr(usize),
+
+    #[error("virtual address {0:#x} is a guard")]
+    GuardedVirtualAddr(usize),
 }
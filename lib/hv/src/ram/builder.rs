@@ -17,6 +17,8 @@ pub struct RamBuilder<'a, H> {
     hv: &'a H,
     next: usize,
     allocated: Vec<AllocInfo>,
+    guards: Vec<(usize, NonZero<usize>)>,
+    ram: Option<AllocInfo>,
 }
 
 impl<'a, H: Hypervisor> RamBuilder<'a, H> {
@@ -35,6 +37,8 @@ impl<'a, H: Hypervisor> RamBuilder<'a, H> {
             hv,
             next: start_addr,
             allocated: Vec::new(),
+            guards: Vec::new(),
+            ram: None,
         }
     }
 
@@ -49,7 +53,8 @@ impl<'a, H: Hypervisor> RamBuilder<'a, H> {
     /// which always aligned to RAM block size. This imply the memory contained in the [`LockedMem`]
     /// also aligned to RAM block size.
     ///
-    /// Returns [`RamError::InvalidAddr`] if available space is not enough for `len`.
+    /// Returns [`RamError::InvalidAddr`] if available space is not enough for `len` or
+    /// [`RamError::Guarded`] if the virtual address range overlapped with a guard range.
     ///
     /// # Panics
     /// If `vaddr` is not multiply by VM page size.
@@ -72,6 +77,10 @@ impl<'a, H: Hypervisor> RamBuilder<'a, H> {
 
         assert_eq!(vaddr % self.hv.ram().vm_page_size(), 0);
 
+        if self.guarded(vaddr, len) {
+            return Err(RamError::Guarded);
+        }
+
         // Alloc.
         let len = len
             .get()
@@ -94,15 +103,77 @@ impl<'a, H: Hypervisor> RamBuilder<'a, H> {
         Ok((paddr, mem))
     }
 
+    /// Reserve a range of virtual address that will never be mapped by
+    /// [`RamBuilder::build_page_table()`]. Any access to this range will cause a page fault.
+    ///
+    /// Returns [`RamError::AlreadyAllocated`] if some part of the range already allocated or
+    /// [`RamError::Guarded`] if it is overlapped with the other guard range.
+    ///
+    /// # Panics
+    /// If `vaddr` or `len` is not multiply by VM page size.
+    pub fn alloc_guard(&mut self, vaddr: usize, len: NonZero<usize>) -> Result<(), RamError> {
+        let page_size = self.hv.ram().vm_page_size().get();
+
+        assert_eq!(vaddr % page_size, 0);
+        assert_eq!(len.get() % page_size, 0);
+
+        // Check if the range valid.
+        let end = vaddr.checked_add(len.get()).ok_or(RamError::InvalidAddr)?;
+
+        if self
+            .allocated
+            .iter()
+            .any(|i| vaddr < i.vaddr + i.len.get() && i.vaddr < end)
+        {
+            return Err(RamError::AlreadyAllocated);
+        } else if self.guarded(vaddr, len) {
+            return Err(RamError::Guarded);
+        }
+
+        self.guards.push((vaddr, len));
+
+        Ok(())
+    }
+
+    /// Map the whole RAM to `vaddr` when [`RamBuilder::build_page_table()`] is invoked. This
+    /// mapping will use the largest page the architecture supports whenever possible so it does
+    /// not require a lot of memory for the page tables.
+    ///
+    /// # Panics
+    /// If `vaddr` is not multiply by VM page size.
+    pub fn map_ram(&mut self, vaddr: usize, #[cfg(target_arch = "aarch64")] attr: u8) {
+        assert_eq!(vaddr % self.hv.ram().vm_page_size(), 0);
+
+        self.ram = Some(AllocInfo {
+            paddr: 0,
+            vaddr,
+            len: self.hv.ram().len(),
+            #[cfg(target_arch = "aarch64")]
+            attr,
+        });
+    }
+
+    /// The memory for the page tables will be allocated from [`RamBuilder::next_addr()`] so the
+    /// caller can use it after this function return to get the end of the memory that was used.
+    ///
     /// # Panics
     /// If any [`AllocInfo::paddr`] in `devices` within RAM address, [`AllocInfo::paddr`] or
     /// [`AllocInfo::vaddr`] is not multiply by VM page size or [`AllocInfo::len`] size cannot round
     /// to VM page size. The latter case only happen when the value is too large (e.g.
     /// 0xFFFFFFFFFFFFF000 for 4K page).
     pub fn build_page_table(
-        self,
+        &mut self,
         devices: impl IntoIterator<Item = AllocInfo>,
     ) -> Result<usize, RamBuilderError> {
+        // Make sure none of device mapped to the guard ranges.
+        let devices = devices.into_iter().collect::<Vec<_>>();
+
+        for dev in devices.iter().chain(&self.ram) {
+            if self.guarded(dev.vaddr, dev.len) {
+                return Err(RamBuilderError::GuardedVirtualAddr(dev.vaddr));
+            }
+        }
+
         match self.hv.ram().vm_page_size().get() {
             0x1000 => self.build_4k_page_tables(devices),
             #[cfg(target_arch = "aarch64")]
@@ -112,6 +183,14 @@ impl<'a, H: Hypervisor> RamBuilder<'a, H> {
             _ => todo!(),
         }
     }
+
+    fn guarded(&self, vaddr: usize, len: NonZero<usize>) -> bool {
+        let end = vaddr.saturating_add(len.get());
+
+        self.guards
+            .iter()
+            .any(|&(addr, len)| vaddr < addr + len.get() && addr < end)
+    }
 }
 
 /// Contains information for an allocation in a virtual address space.
@@ -86,6 +86,26 @@ impl Ram {
         self.host_page_size
     }
 
+    /// Returns all allocated ranges ordered by address. Adjacent blocks are merged into a single
+    /// range.
+    pub fn allocated(&self) -> Vec<(usize, NonZero<usize>)> {
+        let allocated = self.allocated.lock().unwrap();
+        let block_size = self.block_size().get();
+        let mut ranges = Vec::<(usize, usize)>::new();
+
+        for &addr in allocated.keys() {
+            match ranges.last_mut() {
+                Some((start, len)) if *start + *len == addr => *len += block_size,
+                _ => ranges.push((addr, block_size)),
+            }
+        }
+
+        ranges
+            .into_iter()
+            .map(|(addr, len)| (addr, NonZero::new(len).unwrap()))
+            .collect()
+    }
+
     /// # Panics
     /// If `addr` or `len` is not multiply by block size.
     pub fn alloc(&self, addr: usize, len: NonZero<usize>) -> Result<LockedMem<'_>, RamError> {
@@ -194,6 +214,22 @@ impl Ram {
         }
     }
 
+    /// Return the memory of an allocated range to the host without deallocating it. The content of
+    /// the range will be zeroes the next time it is accessed.
+    ///
+    /// Attempt to discard a range locked by the calling thread will result in a deadlock.
+    ///
+    /// # Panics
+    /// If `addr` or `len` is not multiply by block size.
+    pub fn discard(&self, addr: usize, len: NonZero<usize>) -> Result<(), RamError> {
+        assert_eq!(addr % self.block_size(), 0);
+        assert_eq!(len.get() % self.block_size(), 0);
+
+        let mut mem = self.lock(addr, len).ok_or(RamError::InvalidAddr)?;
+
+        unsafe { self::os::discard(mem.as_mut_ptr(), len.get()).map_err(RamError::Decommit) }
+    }
+
     /// Return [`None`] if some part of the requested range is not allocated.
     ///
     /// Attempt to lock a range that already locked by the calling thread will result in a deadlock.
@@ -274,6 +310,9 @@ pub enum RamError {
     #[error("already allocated")]
     AlreadyAllocated,
 
+    #[error("the range is reserved as a guard")]
+    Guarded,
+
     #[error("couldn't commit the memory")]
     Commit(#[source] std::io::Error),
 
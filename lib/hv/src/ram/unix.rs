@@ -1,6 +1,6 @@
 use libc::{
-    _SC_PAGE_SIZE, MAP_ANON, MAP_FAILED, MAP_FIXED, MAP_PRIVATE, PROT_NONE, PROT_READ, PROT_WRITE,
-    mmap, mprotect, munmap, sysconf,
+    _SC_PAGE_SIZE, MADV_DONTNEED, MAP_ANON, MAP_FAILED, MAP_FIXED, MAP_NORESERVE, MAP_PRIVATE,
+    PROT_NONE, PROT_READ, PROT_WRITE, madvise, mmap, mprotect, munmap, sysconf,
 };
 use std::io::Error;
 use std::num::NonZero;
@@ -39,7 +39,7 @@ pub unsafe fn free(addr: *const u8, len: NonZero<usize>) -> Result<(), Error> {
 pub unsafe fn commit(addr: *const u8, len: NonZero<usize>) -> Result<(), Error> {
     let addr = addr.cast_mut().cast();
     let prot = PROT_READ | PROT_WRITE;
-    let flags = MAP_PRIVATE | MAP_ANON | MAP_FIXED;
+    let flags = MAP_PRIVATE | MAP_ANON | MAP_FIXED | MAP_NORESERVE;
     let ptr = unsafe { mmap(addr, len.get(), prot, flags, -1, 0) };
 
     if ptr == MAP_FAILED {
@@ -50,9 +50,25 @@ pub unsafe fn commit(addr: *const u8, len: NonZero<usize>) -> Result<(), Error>
 }
 
 pub unsafe fn decommit(addr: *mut u8, len: usize) -> Result<(), Error> {
+    unsafe { discard(addr, len)? };
+
     if unsafe { mprotect(addr.cast(), len, PROT_NONE) < 0 } {
         Err(Error::last_os_error())
     } else {
         Ok(())
     }
 }
+
+pub unsafe fn discard(addr: *mut u8, len: usize) -> Result<(), Error> {
+    // Only Linux guarantee the pages will be zero-filled after MADV_DONTNEED.
+    #[cfg(not(target_os = "linux"))]
+    unsafe {
+        addr.write_bytes(0, len)
+    };
+
+    if unsafe { madvise(addr.cast(), len, MADV_DONTNEED) < 0 } {
+        Err(Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
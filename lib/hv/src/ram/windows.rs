@@ -3,7 +3,7 @@ use std::mem::zeroed;
 use std::num::NonZero;
 use std::ptr::null;
 use windows_sys::Win32::System::Memory::{
-    MEM_COMMIT, MEM_DECOMMIT, MEM_RELEASE, MEM_RESERVE, PAGE_NOACCESS, PAGE_READWRITE,
+    MEM_COMMIT, MEM_DECOMMIT, MEM_RELEASE, MEM_RESERVE, MEM_RESET, PAGE_NOACCESS, PAGE_READWRITE,
     VirtualAlloc, VirtualFree,
 };
 use windows_sys::Win32::System::SystemInformation::GetSystemInfo;
@@ -51,3 +51,14 @@ pub unsafe fn decommit(addr: *mut u8, len: usize) -> Result<(), Error> {
         Ok(())
     }
 }
+
+pub unsafe fn discard(addr: *mut u8, len: usize) -> Result<(), Error> {
+    // The content of the pages is undefined after MEM_RESET so we need to zero it first.
+    unsafe { addr.write_bytes(0, len) };
+
+    if unsafe { VirtualAlloc(addr.cast(), len, MEM_RESET, PAGE_READWRITE).is_null() } {
+        Err(Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
@@ -7,7 +7,7 @@ use thiserror::Error;
 
 impl<'a, H: Hypervisor> RamBuilder<'a, H> {
     pub(super) fn build_4k_page_tables(
-        mut self,
+        &mut self,
         devices: impl IntoIterator<Item = AllocInfo>,
     ) -> Result<usize, RamBuilderError> {
         // Allocate page-map level-4 table.
@@ -30,7 +30,22 @@ impl<'a, H: Hypervisor> RamBuilder<'a, H> {
         for info in std::mem::take(&mut self.allocated) {
             let len = info.len.get().next_multiple_of(page_size.get());
 
-            self.setup_4k_page_tables(&mut cx, info.vaddr, info.paddr, len)?;
+            self.setup_4k_page_tables(&mut cx, info.vaddr, info.paddr, len, false)?;
+        }
+
+        // Setup page tables to map the whole RAM. We use 2MB pages for this.
+        if let Some(info) = self.ram.take() {
+            let len = info.len.get().next_multiple_of(page_size.get());
+            let large = len & !0x1FFFFF;
+
+            self.setup_4k_page_tables(&mut cx, info.vaddr, info.paddr, large, true)?;
+            self.setup_4k_page_tables(
+                &mut cx,
+                info.vaddr + large,
+                info.paddr + large,
+                len - large,
+                false,
+            )?;
         }
 
         // Setup page tables to map virtual devices.
@@ -43,22 +58,27 @@ impl<'a, H: Hypervisor> RamBuilder<'a, H> {
 
             assert!(dev.paddr >= self.hv.ram().len().get());
 
-            self.setup_4k_page_tables(&mut cx, dev.vaddr, dev.paddr, len)?;
+            self.setup_4k_page_tables(&mut cx, dev.vaddr, dev.paddr, len, false)?;
         }
 
         Ok(page_table)
     }
 
+    /// Set `large` to map the range with 2MB pages instead of 4K pages, in which case `vaddr`,
+    /// `paddr` and `len` must be multiply by 2MB.
     fn setup_4k_page_tables(
         &mut self,
         cx: &mut Context4K<'a>,
         vaddr: usize,
         paddr: usize,
         len: usize,
+        large: bool,
     ) -> Result<(), RamBuilderError> {
-        assert_eq!(vaddr % 4096, 0);
-        assert_eq!(paddr % 4096, 0);
-        assert_eq!(len % 4096, 0);
+        let page_size = if large { 0x200000 } else { 4096 };
+
+        assert_eq!(vaddr % page_size, 0);
+        assert_eq!(paddr % page_size, 0);
+        assert_eq!(len % page_size, 0);
 
         fn set_page_entry(entry: &mut usize, addr: usize) {
             assert_eq!(addr & 0x7FF0000000000000, 0);
@@ -69,7 +89,7 @@ impl<'a, H: Hypervisor> RamBuilder<'a, H> {
             *entry |= 0b10; // Read/Write (R/W) Bit.
         }
 
-        for off in (0..len).step_by(4096) {
+        for off in (0..len).step_by(page_size) {
             use std::collections::hash_map::Entry;
 
             // Get page-directory pointer table.
@@ -109,8 +129,20 @@ impl<'a, H: Hypervisor> RamBuilder<'a, H> {
                 v => cx.pdt.get_mut(&(v & 0xFFFFFFFFFF000)).unwrap(),
             };
 
-            // Get page table.
+            // Set page-directory entry for 2MB page.
             let pdo = (addr & 0x3FE00000) >> 21;
+
+            if large {
+                if pdt[pdo] != 0 {
+                    return Err(RamBuilderError::DuplicatedVirtualAddr(addr));
+                }
+
+                set_page_entry(&mut pdt[pdo], paddr + off);
+                pdt[pdo] |= 1 << 7; // Page Size (PS) Bit.
+                continue;
+            }
+
+            // Get page table.
             let pt = match pdt[pdo] {
                 0 => {
                     let (pt, addr) = self
@@ -124,6 +156,7 @@ impl<'a, H: Hypervisor> RamBuilder<'a, H> {
                         Entry::Vacant(e) => e.insert(pt),
                     }
                 }
+                v if v & (1 << 7) != 0 => return Err(RamBuilderError::DuplicatedVirtualAddr(addr)),
                 v => cx.pt.get_mut(&(v & 0xFFFFFFFFFF000)).unwrap(),
             };
 
@@ -205,4 +238,7 @@ pub enum RamBuilderError {
 
     #[error("duplicated virtual address {0:#x}")]
     DuplicatedVirtualAddr(usize),
+
+    #[error("virtual address {0:#x} is a guard")]
+    GuardedVirtualAddr(usize),
 }
@@ -1,15 +1,18 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
-use crate::{Cpu, CpuCommit, CpuDebug, CpuExit, CpuIo, CpuRun, CpuStates, DebugEvent, IoBuf};
+use crate::{
+    Cpu, CpuCommit, CpuDebug, CpuExit, CpuIo, CpuKicker, CpuRun, CpuStates, DebugEvent, HwDebug,
+    IoBuf,
+};
 use std::marker::PhantomData;
 use std::mem::{MaybeUninit, size_of, zeroed};
 use thiserror::Error;
 use windows_sys::Win32::System::Hypervisor::{
     WHV_PARTITION_HANDLE, WHV_REGISTER_NAME, WHV_REGISTER_VALUE, WHV_RUN_VP_EXIT_CONTEXT,
-    WHvDeleteVirtualProcessor, WHvGetVirtualProcessorRegisters, WHvRunVirtualProcessor,
-    WHvRunVpExitReasonX64Halt, WHvSetVirtualProcessorRegisters, WHvX64RegisterCr0,
-    WHvX64RegisterCr3, WHvX64RegisterCr4, WHvX64RegisterCs, WHvX64RegisterDs, WHvX64RegisterEfer,
-    WHvX64RegisterEs, WHvX64RegisterFs, WHvX64RegisterGs, WHvX64RegisterRip, WHvX64RegisterRsp,
-    WHvX64RegisterSs,
+    WHvCancelRunVirtualProcessor, WHvDeleteVirtualProcessor, WHvGetVirtualProcessorRegisters,
+    WHvRunVirtualProcessor, WHvRunVpExitReasonCanceled, WHvRunVpExitReasonX64Halt,
+    WHvSetVirtualProcessorRegisters, WHvX64RegisterCr0, WHvX64RegisterCr3, WHvX64RegisterCr4,
+    WHvX64RegisterCs, WHvX64RegisterDs, WHvX64RegisterEfer, WHvX64RegisterEs, WHvX64RegisterFs,
+    WHvX64RegisterGs, WHvX64RegisterRip, WHvX64RegisterRsp, WHvX64RegisterSs,
 };
 use windows_sys::core::HRESULT;
 use x86_64::{Efer, Rflags};
@@ -55,6 +58,10 @@ impl<'a> Cpu for WhpCpu<'a> {
         Self: 'b;
 
     type TranslateErr = std::io::Error;
+    type SnapshotErr = std::io::Error;
+    type DebugErr = std::io::Error;
+    type InterruptErr = std::io::Error;
+    type Kicker = WhpKicker;
 
     fn id(&self) -> usize {
         todo!()
@@ -79,6 +86,7 @@ impl<'a> Cpu for WhpCpu<'a> {
                 cpu: self,
                 values,
                 dirty: false,
+                unsupported: None,
             })
         }
     }
@@ -86,6 +94,36 @@ impl<'a> Cpu for WhpCpu<'a> {
     fn translate(&self, vaddr: usize) -> Result<usize, std::io::Error> {
         todo!()
     }
+    fn snapshot(&mut self) -> Result<Vec<u8>, Self::SnapshotErr> {
+        todo!()
+    }
+
+    fn restore(&mut self, data: &[u8]) -> Result<(), Self::SnapshotErr> {
+        todo!()
+    }
+
+    fn set_debug(&mut self, v: &HwDebug) -> Result<(), Self::DebugErr> {
+        todo!()
+    }
+
+    fn kicker(&self) -> Self::Kicker {
+        WhpKicker {
+            part: self.part,
+            index: self.index,
+        }
+    }
+
+    fn interruptible(&self) -> bool {
+        todo!()
+    }
+
+    fn inject(&mut self, vector: u8) -> Result<(), Self::InterruptErr> {
+        todo!()
+    }
+
+    fn set_interrupt_window(&mut self, v: bool) {
+        todo!()
+    }
 }
 
 impl<'a> CpuRun for WhpCpu<'a> {
@@ -118,6 +156,7 @@ pub struct WhpStates<'a, 'b> {
     cpu: &'a mut WhpCpu<'b>,
     values: [WHV_REGISTER_VALUE; REGISTERS],
     dirty: bool,
+    unsupported: Option<&'static str>,
 }
 
 impl<'a, 'b> WhpStates<'a, 'b> {
@@ -144,14 +183,26 @@ impl<'a, 'b> CpuStates for WhpStates<'a, 'b> {
         todo!()
     }
 
+    fn set_rax(&mut self, _: usize) {
+        self.unsupported.get_or_insert("rax");
+    }
+
     fn get_rbx(&mut self) -> Result<usize, Self::Err> {
         todo!()
     }
 
+    fn set_rbx(&mut self, _: usize) {
+        self.unsupported.get_or_insert("rbx");
+    }
+
     fn get_rcx(&mut self) -> Result<usize, Self::Err> {
         todo!()
     }
 
+    fn set_rcx(&mut self, _: usize) {
+        self.unsupported.get_or_insert("rcx");
+    }
+
     fn get_rdx(&mut self) -> Result<usize, Self::Err> {
         todo!()
     }
@@ -164,38 +215,74 @@ impl<'a, 'b> CpuStates for WhpStates<'a, 'b> {
         todo!()
     }
 
+    fn set_rbp(&mut self, _: usize) {
+        self.unsupported.get_or_insert("rbp");
+    }
+
     fn get_r8(&mut self) -> Result<usize, Self::Err> {
         todo!()
     }
 
+    fn set_r8(&mut self, _: usize) {
+        self.unsupported.get_or_insert("r8");
+    }
+
     fn get_r9(&mut self) -> Result<usize, Self::Err> {
         todo!()
     }
 
+    fn set_r9(&mut self, _: usize) {
+        self.unsupported.get_or_insert("r9");
+    }
+
     fn get_r10(&mut self) -> Result<usize, Self::Err> {
         todo!()
     }
 
+    fn set_r10(&mut self, _: usize) {
+        self.unsupported.get_or_insert("r10");
+    }
+
     fn get_r11(&mut self) -> Result<usize, Self::Err> {
         todo!()
     }
 
+    fn set_r11(&mut self, _: usize) {
+        self.unsupported.get_or_insert("r11");
+    }
+
     fn get_r12(&mut self) -> Result<usize, Self::Err> {
         todo!()
     }
 
+    fn set_r12(&mut self, _: usize) {
+        self.unsupported.get_or_insert("r12");
+    }
+
     fn get_r13(&mut self) -> Result<usize, Self::Err> {
         todo!()
     }
 
+    fn set_r13(&mut self, _: usize) {
+        self.unsupported.get_or_insert("r13");
+    }
+
     fn get_r14(&mut self) -> Result<usize, Self::Err> {
         todo!()
     }
 
+    fn set_r14(&mut self, _: usize) {
+        self.unsupported.get_or_insert("r14");
+    }
+
     fn get_r15(&mut self) -> Result<usize, Self::Err> {
         todo!()
     }
 
+    fn set_r15(&mut self, _: usize) {
+        self.unsupported.get_or_insert("r15");
+    }
+
     fn get_rdi(&mut self) -> Result<usize, Self::Err> {
         todo!()
     }
@@ -235,6 +322,14 @@ impl<'a, 'b> CpuStates for WhpStates<'a, 'b> {
         self.dirty = true;
     }
 
+    fn get_cr2(&mut self) -> Result<usize, Self::Err> {
+        todo!()
+    }
+
+    fn get_cr3(&mut self) -> Result<usize, Self::Err> {
+        todo!()
+    }
+
     fn set_cr3(&mut self, v: usize) {
         self.values[3].Reg64 = v.try_into().unwrap();
         self.dirty = true;
@@ -262,6 +357,10 @@ impl<'a, 'b> CpuStates for WhpStates<'a, 'b> {
         todo!()
     }
 
+    fn set_cs_selector(&mut self, _: u16) {
+        self.unsupported.get_or_insert("cs");
+    }
+
     fn set_cs(&mut self, ty: u8, dpl: u8, p: bool, l: bool, d: bool) {
         // Rust binding does not provides a way to set bit fields so we need to do this manually.
         // See https://learn.microsoft.com/en-us/virtualization/api/hypervisor-platform/funcs/whvvirtualprocessordatatypes
@@ -288,6 +387,10 @@ impl<'a, 'b> CpuStates for WhpStates<'a, 'b> {
         todo!()
     }
 
+    fn set_ds_selector(&mut self, _: u16) {
+        self.unsupported.get_or_insert("ds");
+    }
+
     fn set_ds(&mut self, p: bool) {
         let v = unsafe { &mut self.values[7].Segment.Anonymous.Attributes };
         let p: u16 = p.into();
@@ -301,6 +404,10 @@ impl<'a, 'b> CpuStates for WhpStates<'a, 'b> {
         todo!()
     }
 
+    fn set_es_selector(&mut self, _: u16) {
+        self.unsupported.get_or_insert("es");
+    }
+
     fn set_es(&mut self, p: bool) {
         let v = unsafe { &mut self.values[8].Segment.Anonymous.Attributes };
         let p: u16 = p.into();
@@ -314,6 +421,10 @@ impl<'a, 'b> CpuStates for WhpStates<'a, 'b> {
         todo!()
     }
 
+    fn set_fs_selector(&mut self, _: u16) {
+        self.unsupported.get_or_insert("fs");
+    }
+
     fn set_fs(&mut self, p: bool) {
         let v = unsafe { &mut self.values[9].Segment.Anonymous.Attributes };
         let p: u16 = p.into();
@@ -327,6 +438,10 @@ impl<'a, 'b> CpuStates for WhpStates<'a, 'b> {
         todo!()
     }
 
+    fn set_gs_selector(&mut self, _: u16) {
+        self.unsupported.get_or_insert("gs");
+    }
+
     fn set_gs(&mut self, p: bool) {
         let v = unsafe { &mut self.values[10].Segment.Anonymous.Attributes };
         let p: u16 = p.into();
@@ -340,6 +455,10 @@ impl<'a, 'b> CpuStates for WhpStates<'a, 'b> {
         todo!()
     }
 
+    fn set_ss_selector(&mut self, _: u16) {
+        self.unsupported.get_or_insert("ss");
+    }
+
     fn set_ss(&mut self, p: bool) {
         let v = unsafe { &mut self.values[11].Segment.Anonymous.Attributes };
         let p: u16 = p.into();
@@ -353,137 +472,273 @@ impl<'a, 'b> CpuStates for WhpStates<'a, 'b> {
         todo!()
     }
 
+    fn set_st0(&mut self, _: [u8; 10]) {
+        self.unsupported.get_or_insert("st0");
+    }
+
     fn get_st1(&mut self) -> Result<[u8; 10], Self::Err> {
         todo!()
     }
 
+    fn set_st1(&mut self, _: [u8; 10]) {
+        self.unsupported.get_or_insert("st1");
+    }
+
     fn get_st2(&mut self) -> Result<[u8; 10], Self::Err> {
         todo!()
     }
 
+    fn set_st2(&mut self, _: [u8; 10]) {
+        self.unsupported.get_or_insert("st2");
+    }
+
     fn get_st3(&mut self) -> Result<[u8; 10], Self::Err> {
         todo!()
     }
 
+    fn set_st3(&mut self, _: [u8; 10]) {
+        self.unsupported.get_or_insert("st3");
+    }
+
     fn get_st4(&mut self) -> Result<[u8; 10], Self::Err> {
         todo!()
     }
 
+    fn set_st4(&mut self, _: [u8; 10]) {
+        self.unsupported.get_or_insert("st4");
+    }
+
     fn get_st5(&mut self) -> Result<[u8; 10], Self::Err> {
         todo!()
     }
 
+    fn set_st5(&mut self, _: [u8; 10]) {
+        self.unsupported.get_or_insert("st5");
+    }
+
     fn get_st6(&mut self) -> Result<[u8; 10], Self::Err> {
         todo!()
     }
 
+    fn set_st6(&mut self, _: [u8; 10]) {
+        self.unsupported.get_or_insert("st6");
+    }
+
     fn get_st7(&mut self) -> Result<[u8; 10], Self::Err> {
         todo!()
     }
 
+    fn set_st7(&mut self, _: [u8; 10]) {
+        self.unsupported.get_or_insert("st7");
+    }
+
     fn get_fcw(&mut self) -> Result<u32, Self::Err> {
         todo!()
     }
 
+    fn set_fcw(&mut self, _: u32) {
+        self.unsupported.get_or_insert("fcw");
+    }
+
     fn get_fsw(&mut self) -> Result<u32, Self::Err> {
         todo!()
     }
 
+    fn set_fsw(&mut self, _: u32) {
+        self.unsupported.get_or_insert("fsw");
+    }
+
     fn get_ftwx(&mut self) -> Result<u32, Self::Err> {
         todo!()
     }
 
+    fn set_ftwx(&mut self, _: u32) {
+        self.unsupported.get_or_insert("ftwx");
+    }
+
     fn get_fiseg(&mut self) -> Result<u32, Self::Err> {
         todo!()
     }
 
+    fn set_fiseg(&mut self, _: u32) {
+        self.unsupported.get_or_insert("fiseg");
+    }
+
     fn get_fioff(&mut self) -> Result<u32, Self::Err> {
         todo!()
     }
 
+    fn set_fioff(&mut self, _: u32) {
+        self.unsupported.get_or_insert("fioff");
+    }
+
     fn get_foseg(&mut self) -> Result<u32, Self::Err> {
         todo!()
     }
 
+    fn set_foseg(&mut self, _: u32) {
+        self.unsupported.get_or_insert("foseg");
+    }
+
     fn get_fooff(&mut self) -> Result<u32, Self::Err> {
         todo!()
     }
 
+    fn set_fooff(&mut self, _: u32) {
+        self.unsupported.get_or_insert("fooff");
+    }
+
     fn get_fop(&mut self) -> Result<u32, Self::Err> {
         todo!()
     }
 
+    fn set_fop(&mut self, _: u32) {
+        self.unsupported.get_or_insert("fop");
+    }
+
     fn get_xmm0(&mut self) -> Result<u128, Self::Err> {
         todo!()
     }
 
+    fn set_xmm0(&mut self, _: u128) {
+        self.unsupported.get_or_insert("xmm0");
+    }
+
     fn get_xmm1(&mut self) -> Result<u128, Self::Err> {
         todo!()
     }
 
+    fn set_xmm1(&mut self, _: u128) {
+        self.unsupported.get_or_insert("xmm1");
+    }
+
     fn get_xmm2(&mut self) -> Result<u128, Self::Err> {
         todo!()
     }
 
+    fn set_xmm2(&mut self, _: u128) {
+        self.unsupported.get_or_insert("xmm2");
+    }
+
     fn get_xmm3(&mut self) -> Result<u128, Self::Err> {
         todo!()
     }
 
+    fn set_xmm3(&mut self, _: u128) {
+        self.unsupported.get_or_insert("xmm3");
+    }
+
     fn get_xmm4(&mut self) -> Result<u128, Self::Err> {
         todo!()
     }
 
+    fn set_xmm4(&mut self, _: u128) {
+        self.unsupported.get_or_insert("xmm4");
+    }
+
     fn get_xmm5(&mut self) -> Result<u128, Self::Err> {
         todo!()
     }
 
+    fn set_xmm5(&mut self, _: u128) {
+        self.unsupported.get_or_insert("xmm5");
+    }
+
     fn get_xmm6(&mut self) -> Result<u128, Self::Err> {
         todo!()
     }
 
+    fn set_xmm6(&mut self, _: u128) {
+        self.unsupported.get_or_insert("xmm6");
+    }
+
     fn get_xmm7(&mut self) -> Result<u128, Self::Err> {
         todo!()
     }
 
+    fn set_xmm7(&mut self, _: u128) {
+        self.unsupported.get_or_insert("xmm7");
+    }
+
     fn get_xmm8(&mut self) -> Result<u128, Self::Err> {
         todo!()
     }
 
+    fn set_xmm8(&mut self, _: u128) {
+        self.unsupported.get_or_insert("xmm8");
+    }
+
     fn get_xmm9(&mut self) -> Result<u128, Self::Err> {
         todo!()
     }
 
+    fn set_xmm9(&mut self, _: u128) {
+        self.unsupported.get_or_insert("xmm9");
+    }
+
     fn get_xmm10(&mut self) -> Result<u128, Self::Err> {
         todo!()
     }
 
+    fn set_xmm10(&mut self, _: u128) {
+        self.unsupported.get_or_insert("xmm10");
+    }
+
     fn get_xmm11(&mut self) -> Result<u128, Self::Err> {
         todo!()
     }
 
+    fn set_xmm11(&mut self, _: u128) {
+        self.unsupported.get_or_insert("xmm11");
+    }
+
     fn get_xmm12(&mut self) -> Result<u128, Self::Err> {
         todo!()
     }
 
+    fn set_xmm12(&mut self, _: u128) {
+        self.unsupported.get_or_insert("xmm12");
+    }
+
     fn get_xmm13(&mut self) -> Result<u128, Self::Err> {
         todo!()
     }
 
+    fn set_xmm13(&mut self, _: u128) {
+        self.unsupported.get_or_insert("xmm13");
+    }
+
     fn get_xmm14(&mut self) -> Result<u128, Self::Err> {
         todo!()
     }
 
+    fn set_xmm14(&mut self, _: u128) {
+        self.unsupported.get_or_insert("xmm14");
+    }
+
     fn get_xmm15(&mut self) -> Result<u128, Self::Err> {
         todo!()
     }
 
+    fn set_xmm15(&mut self, _: u128) {
+        self.unsupported.get_or_insert("xmm15");
+    }
+
     fn get_mxcsr(&mut self) -> Result<u32, Self::Err> {
         todo!()
     }
+
+    fn set_mxcsr(&mut self, _: u32) {
+        self.unsupported.get_or_insert("mxcsr");
+    }
 }
 
 impl<'a, 'b> CpuCommit for WhpStates<'a, 'b> {
     fn commit(self) -> Result<(), Self::Err> {
+        if let Some(v) = self.unsupported {
+            return Err(StatesError::UnsupportedRegister(v));
+        }
+
         if !self.dirty {
             return Ok(());
         }
@@ -537,6 +792,30 @@ impl<'a, 'b> CpuExit for WhpExit<'a, 'b> {
     fn into_debug(self) -> Result<Self::Debug, Self> {
         todo!()
     }
+
+    fn into_interrupt(self) -> Result<(), Self> {
+        if self.cx.ExitReason == WHvRunVpExitReasonCanceled {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+/// Implementation of [`Cpu::Kicker`] for Windows Hypervisor Platform.
+pub struct WhpKicker {
+    part: WHV_PARTITION_HANDLE,
+    index: u32,
+}
+
+impl CpuKicker for WhpKicker {
+    fn kick(&self) {
+        let status = unsafe { WHvCancelRunVirtualProcessor(self.part, self.index, 0) };
+
+        if status < 0 {
+            panic!("WHvCancelRunVirtualProcessor() was failed with {status:#x}");
+        }
+    }
 }
 
 /// Implementation of [`CpuIo`] for Windows Hypervisor Platform.
@@ -585,6 +864,9 @@ pub enum StatesError {
 
     #[error("WHvSetVirtualProcessorRegisters was failed ({0:#x})")]
     SetVirtualProcessorRegistersFailed(HRESULT),
+
+    #[error("setting {0} is not supported")]
+    UnsupportedRegister(&'static str),
 }
 
 /// Implementation of [`Cpu::RunErr`].
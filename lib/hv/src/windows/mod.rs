@@ -100,6 +100,12 @@ pub enum HvError {
     #[error("couldn't create a RAM")]
     CreateRamFailed(#[source] std::io::Error),
 
+    #[error("invalid CPU identifier")]
+    InvalidCpuId,
+
+    #[error("CPU identifier currently in use")]
+    DuplicatedCpuId,
+
     #[error("couldn't create WHP partition object ({0:#x})")]
     CreatePartitionFailed(HRESULT),
 
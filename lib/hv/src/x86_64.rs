@@ -15,19 +15,31 @@ pub trait CpuStates {
     type Err: Error + Send + Sync + 'static;
 
     fn get_rax(&mut self) -> Result<usize, Self::Err>;
+    fn set_rax(&mut self, v: usize);
     fn get_rbx(&mut self) -> Result<usize, Self::Err>;
+    fn set_rbx(&mut self, v: usize);
     fn get_rcx(&mut self) -> Result<usize, Self::Err>;
+    fn set_rcx(&mut self, v: usize);
     fn get_rdx(&mut self) -> Result<usize, Self::Err>;
     fn set_rdx(&mut self, v: usize);
     fn get_rbp(&mut self) -> Result<usize, Self::Err>;
+    fn set_rbp(&mut self, v: usize);
     fn get_r8(&mut self) -> Result<usize, Self::Err>;
+    fn set_r8(&mut self, v: usize);
     fn get_r9(&mut self) -> Result<usize, Self::Err>;
+    fn set_r9(&mut self, v: usize);
     fn get_r10(&mut self) -> Result<usize, Self::Err>;
+    fn set_r10(&mut self, v: usize);
     fn get_r11(&mut self) -> Result<usize, Self::Err>;
+    fn set_r11(&mut self, v: usize);
     fn get_r12(&mut self) -> Result<usize, Self::Err>;
+    fn set_r12(&mut self, v: usize);
     fn get_r13(&mut self) -> Result<usize, Self::Err>;
+    fn set_r13(&mut self, v: usize);
     fn get_r14(&mut self) -> Result<usize, Self::Err>;
+    fn set_r14(&mut self, v: usize);
     fn get_r15(&mut self) -> Result<usize, Self::Err>;
+    fn set_r15(&mut self, v: usize);
     fn get_rdi(&mut self) -> Result<usize, Self::Err>;
     fn set_rdi(&mut self, v: usize);
     fn get_rsi(&mut self) -> Result<usize, Self::Err>;
@@ -38,60 +50,101 @@ pub trait CpuStates {
     fn set_rip(&mut self, v: usize);
 
     fn set_cr0(&mut self, v: usize);
+    fn get_cr2(&mut self) -> Result<usize, Self::Err>;
+    fn get_cr3(&mut self) -> Result<usize, Self::Err>;
     fn set_cr3(&mut self, v: usize);
     fn set_cr4(&mut self, v: usize);
     fn get_rflags(&mut self) -> Result<Rflags, Self::Err>;
     fn set_rflags(&mut self, v: Rflags);
     fn set_efer(&mut self, v: Efer);
     fn get_cs(&mut self) -> Result<u16, Self::Err>;
+    fn set_cs_selector(&mut self, v: u16);
     fn set_cs(&mut self, ty: u8, dpl: u8, p: bool, l: bool, d: bool);
     fn get_ds(&mut self) -> Result<u16, Self::Err>;
+    fn set_ds_selector(&mut self, v: u16);
     fn set_ds(&mut self, p: bool);
     fn get_es(&mut self) -> Result<u16, Self::Err>;
+    fn set_es_selector(&mut self, v: u16);
     fn set_es(&mut self, p: bool);
     fn get_fs(&mut self) -> Result<u16, Self::Err>;
+    fn set_fs_selector(&mut self, v: u16);
     fn set_fs(&mut self, p: bool);
     fn get_gs(&mut self) -> Result<u16, Self::Err>;
+    fn set_gs_selector(&mut self, v: u16);
     fn set_gs(&mut self, p: bool);
     fn get_ss(&mut self) -> Result<u16, Self::Err>;
+    fn set_ss_selector(&mut self, v: u16);
     fn set_ss(&mut self, p: bool);
 
     fn get_st0(&mut self) -> Result<[u8; 10], Self::Err>;
+    fn set_st0(&mut self, v: [u8; 10]);
     fn get_st1(&mut self) -> Result<[u8; 10], Self::Err>;
+    fn set_st1(&mut self, v: [u8; 10]);
     fn get_st2(&mut self) -> Result<[u8; 10], Self::Err>;
+    fn set_st2(&mut self, v: [u8; 10]);
     fn get_st3(&mut self) -> Result<[u8; 10], Self::Err>;
+    fn set_st3(&mut self, v: [u8; 10]);
     fn get_st4(&mut self) -> Result<[u8; 10], Self::Err>;
+    fn set_st4(&mut self, v: [u8; 10]);
     fn get_st5(&mut self) -> Result<[u8; 10], Self::Err>;
+    fn set_st5(&mut self, v: [u8; 10]);
     fn get_st6(&mut self) -> Result<[u8; 10], Self::Err>;
+    fn set_st6(&mut self, v: [u8; 10]);
     fn get_st7(&mut self) -> Result<[u8; 10], Self::Err>;
+    fn set_st7(&mut self, v: [u8; 10]);
 
     fn get_fcw(&mut self) -> Result<u32, Self::Err>;
+    fn set_fcw(&mut self, v: u32);
     fn get_fsw(&mut self) -> Result<u32, Self::Err>;
+    fn set_fsw(&mut self, v: u32);
     fn get_ftwx(&mut self) -> Result<u32, Self::Err>;
+    fn set_ftwx(&mut self, v: u32);
     fn get_fiseg(&mut self) -> Result<u32, Self::Err>;
+    fn set_fiseg(&mut self, v: u32);
     fn get_fioff(&mut self) -> Result<u32, Self::Err>;
+    fn set_fioff(&mut self, v: u32);
     fn get_foseg(&mut self) -> Result<u32, Self::Err>;
+    fn set_foseg(&mut self, v: u32);
     fn get_fooff(&mut self) -> Result<u32, Self::Err>;
+    fn set_fooff(&mut self, v: u32);
     fn get_fop(&mut self) -> Result<u32, Self::Err>;
+    fn set_fop(&mut self, v: u32);
 
     fn get_xmm0(&mut self) -> Result<u128, Self::Err>;
+    fn set_xmm0(&mut self, v: u128);
     fn get_xmm1(&mut self) -> Result<u128, Self::Err>;
+    fn set_xmm1(&mut self, v: u128);
     fn get_xmm2(&mut self) -> Result<u128, Self::Err>;
+    fn set_xmm2(&mut self, v: u128);
     fn get_xmm3(&mut self) -> Result<u128, Self::Err>;
+    fn set_xmm3(&mut self, v: u128);
     fn get_xmm4(&mut self) -> Result<u128, Self::Err>;
+    fn set_xmm4(&mut self, v: u128);
     fn get_xmm5(&mut self) -> Result<u128, Self::Err>;
+    fn set_xmm5(&mut self, v: u128);
     fn get_xmm6(&mut self) -> Result<u128, Self::Err>;
+    fn set_xmm6(&mut self, v: u128);
     fn get_xmm7(&mut self) -> Result<u128, Self::Err>;
+    fn set_xmm7(&mut self, v: u128);
     fn get_xmm8(&mut self) -> Result<u128, Self::Err>;
+    fn set_xmm8(&mut self, v: u128);
     fn get_xmm9(&mut self) -> Result<u128, Self::Err>;
+    fn set_xmm9(&mut self, v: u128);
     fn get_xmm10(&mut self) -> Result<u128, Self::Err>;
+    fn set_xmm10(&mut self, v: u128);
     fn get_xmm11(&mut self) -> Result<u128, Self::Err>;
+    fn set_xmm11(&mut self, v: u128);
     fn get_xmm12(&mut self) -> Result<u128, Self::Err>;
+    fn set_xmm12(&mut self, v: u128);
     fn get_xmm13(&mut self) -> Result<u128, Self::Err>;
+    fn set_xmm13(&mut self, v: u128);
     fn get_xmm14(&mut self) -> Result<u128, Self::Err>;
+    fn set_xmm14(&mut self, v: u128);
     fn get_xmm15(&mut self) -> Result<u128, Self::Err>;
+    fn set_xmm15(&mut self, v: u128);
 
     fn get_mxcsr(&mut self) -> Result<u32, Self::Err>;
+    fn set_mxcsr(&mut self, v: u32);
 }
 
 /// Contains values for single CPUID leaf.
@@ -103,3 +156,13 @@ pub struct FeatLeaf {
     pub ecx: u32,
     pub edx: u32,
 }
+
+/// Hardware debugging states of a CPU.
+#[derive(Debug, Clone, Default)]
+pub struct HwDebug {
+    pub step: bool,
+    /// Values of DR0-DR3.
+    pub addrs: [usize; 4],
+    /// Value of DR7.
+    pub dr7: usize,
+}
@@ -67,6 +67,24 @@ pub fn error(file: &str, line: u32, msg: impl Display) {
     print(ConsoleType::Error, msg)
 }
 
+/// Write a statistics report.
+///
+/// Unlike the logs, `msg` is written as-is without the location. The LF character will be
+/// automatically appended.
+pub fn stats(msg: impl Display) {
+    print(ConsoleType::Stats, msg)
+}
+
+/// Read the input from the host console into `buf` and returns the number of bytes read.
+///
+/// This does not block. Zero will be returned if no input is available. When running inside a VM
+/// `buf` must not cross a page boundary.
+pub fn read_input(buf: &mut [u8]) -> usize {
+    match boot_env() {
+        BootEnv::Vm(env) => self::vm::read_input(env, buf),
+    }
+}
+
 fn print(ty: ConsoleType, msg: impl Display) {
     match boot_env() {
         BootEnv::Vm(env) => self::vm::print(env, ty, msg),
@@ -1,11 +1,15 @@
-use config::{ConsoleMemory, ConsoleType, Vm};
+use config::{ConsoleMemory, ConsoleType, DeviceType, Vm};
 use core::cmp::min;
 use core::fmt::{Display, Write};
 use core::num::NonZero;
-use core::ptr::write_volatile;
+use core::ptr::{read_volatile, write_volatile};
 
 pub fn print(env: &Vm, ty: ConsoleType, msg: impl Display) {
-    let c = env.console as *mut ConsoleMemory;
+    // Don't use unwrap here since the panic handler also use this function.
+    let c = match env.device(DeviceType::Console) {
+        Some(v) => v as *mut ConsoleMemory,
+        None => return,
+    };
     let mut w = Writer {
         con: c,
         buf: [0; 1024],
@@ -18,6 +22,21 @@ pub fn print(env: &Vm, ty: ConsoleType, msg: impl Display) {
     unsafe { write_volatile(&raw mut (*c).commit, ty) };
 }
 
+pub fn read_input(env: &Vm, buf: &mut [u8]) -> usize {
+    let c = match env.device(DeviceType::Console) {
+        Some(v) => v as *mut ConsoleMemory,
+        None => return 0,
+    };
+    let len = match NonZero::new(buf.len()) {
+        Some(v) => v,
+        None => return 0,
+    };
+
+    unsafe { write_volatile(&raw mut (*c).input_len, len) };
+    unsafe { write_volatile(&raw mut (*c).input_addr, buf.as_mut_ptr() as _) };
+    unsafe { read_volatile(&raw const (*c).input_read) }
+}
+
 /// [Write] implementation to write the message to the VMM console.
 struct Writer {
     con: *mut ConsoleMemory,
@@ -0,0 +1,25 @@
+use crate::config::boot_env;
+use config::BootEnv;
+
+mod vm;
+
+/// Start a secondary CPU.
+///
+/// The CPU will execute `entry` with `arg` as the first argument in the same mode as the main CPU.
+/// This function does not wait for the CPU to start.
+///
+/// # Safety
+/// - `id` must be unique and not currently running.
+/// - `page_table` must be a valid page table that map the kernel.
+/// - `stack` must be the top of a stack that live forever.
+pub unsafe fn start_cpu(
+    id: usize,
+    page_table: usize,
+    entry: unsafe extern "C" fn(usize) -> !,
+    stack: *mut u8,
+    arg: usize,
+) {
+    match boot_env() {
+        BootEnv::Vm(env) => unsafe { self::vm::start_cpu(env, id, page_table, entry, stack, arg) },
+    }
+}
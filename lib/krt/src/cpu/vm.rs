@@ -0,0 +1,19 @@
+use config::{DeviceType, Vm, VmmMemory};
+use core::ptr::write_volatile;
+
+pub unsafe fn start_cpu(
+    env: &Vm,
+    id: usize,
+    page_table: usize,
+    entry: unsafe extern "C" fn(usize) -> !,
+    stack: *mut u8,
+    arg: usize,
+) {
+    let vmm = env.device(DeviceType::Vmm).unwrap() as *mut VmmMemory;
+
+    unsafe { write_volatile(&raw mut (*vmm).cpu_page_table, page_table) };
+    unsafe { write_volatile(&raw mut (*vmm).cpu_entry, entry as usize) };
+    unsafe { write_volatile(&raw mut (*vmm).cpu_stack, stack as usize) };
+    unsafe { write_volatile(&raw mut (*vmm).cpu_arg, arg) };
+    unsafe { write_volatile(&raw mut (*vmm).cpu_start, id) };
+}
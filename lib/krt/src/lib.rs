@@ -7,12 +7,16 @@
 
 pub use self::config::*;
 pub use self::console::*;
+pub use self::cpu::*;
+pub use self::ram::*;
 
 use core::panic::PanicInfo;
 
 mod config;
 mod console;
+mod cpu;
 mod panic;
+mod ram;
 
 /// Entry point of the kernel.
 ///
@@ -1,9 +1,15 @@
-use config::{KernelExit, Vm, VmmMemory};
+use config::{DeviceType, KernelExit, Vm, VmmMemory};
 use core::hint::unreachable_unchecked;
 use core::ptr::{addr_of_mut, write_volatile};
 
 pub fn panic(env: &Vm) -> ! {
-    let vmm = env.vmm as *mut VmmMemory;
+    // Don't use unwrap here since it will cause a recursive panic.
+    let vmm = match env.device(DeviceType::Vmm) {
+        Some(v) => v as *mut VmmMemory,
+        None => loop {
+            core::hint::spin_loop();
+        },
+    };
 
     unsafe { write_volatile(addr_of_mut!((*vmm).shutdown), KernelExit::Panic) };
     unsafe { unreachable_unchecked() };
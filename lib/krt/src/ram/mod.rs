@@ -0,0 +1,18 @@
+use crate::config::boot_env;
+use config::BootEnv;
+use core::num::NonZero;
+
+mod vm;
+
+/// Return a range of physical memory to the host.
+///
+/// The content of the range will be zeroes the next time it is accessed.
+///
+/// # Safety
+/// - `addr` and `len` must be multiply by both host page size and VM page size.
+/// - The range must not be in use by anything.
+pub unsafe fn release_ram(addr: usize, len: NonZero<usize>) {
+    match boot_env() {
+        BootEnv::Vm(env) => unsafe { self::vm::release_ram(env, addr, len) },
+    }
+}
@@ -0,0 +1,10 @@
+use config::{DeviceType, Vm, VmmMemory};
+use core::num::NonZero;
+use core::ptr::write_volatile;
+
+pub unsafe fn release_ram(env: &Vm, addr: usize, len: NonZero<usize>) {
+    let vmm = env.device(DeviceType::Vmm).unwrap() as *mut VmmMemory;
+
+    unsafe { write_volatile(&raw mut (*vmm).ram_addr, addr) };
+    unsafe { write_volatile(&raw mut (*vmm).ram_release, len.get()) };
+}